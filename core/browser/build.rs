@@ -0,0 +1,29 @@
+//! Derive the expected privacy-defaults digest from the reviewed manifest.
+//!
+//! `privacy-defaults.manifest` lists every security-critical compiled-in
+//! value. The runtime check in `integrity` rebuilds the same listing from
+//! the live constants and compares digests.
+
+use std::path::PathBuf;
+
+include!("src/digest.rs");
+
+const MANIFEST: &str = "privacy-defaults.manifest";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", MANIFEST);
+    println!("cargo:rerun-if-changed=src/digest.rs");
+
+    let manifest =
+        std::fs::read_to_string(MANIFEST).expect("privacy-defaults.manifest is readable");
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo"));
+
+    std::fs::write(
+        out_dir.join("expected_digest.rs"),
+        format!(
+            "/// Digest of `privacy-defaults.manifest` at build time.\npub const EXPECTED_DIGEST: u64 = {:#018x};\n",
+            manifest_digest(&manifest)
+        ),
+    )
+    .expect("expected_digest.rs is writable");
+}
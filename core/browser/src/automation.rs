@@ -0,0 +1,724 @@
+//! Opt-in automation server for measurement research.
+//!
+//! Researchers running studies (load N onion pages, record the shield
+//! counters) can drive the browser over a line-delimited JSON protocol on
+//! a UNIX socket. The server exists only when `--automation-socket <path>`
+//! is given, runs in the UI process, and talks to peers with our own UID
+//! only. Every command maps to an existing core call, and responses carry
+//! identifiers and counters, never page content.
+//!
+//! ```text
+//! > {"cmd":"navigate","url":"https://example.onion/"}
+//! < {"ok":true,"context":1,"url":"https://example.onion/"}
+//! > {"cmd":"wait-for-load","timeout_ms":30000}
+//! < {"ok":true,"context":1}
+//! > {"cmd":"bogus"}
+//! < {"ok":false,"error":"unknown command: bogus"}
+//! ```
+//!
+//! Commands: `navigate` (`url`), `follow-link` (`url`), `reload`,
+//! `wait-for-load` (optional `timeout_ms`), `get-shield-counters`,
+//! `new-loop`, `get-fingerprint-report`, `quit`.
+//! `follow-link` navigates as a click in the current page would.
+//! `quit` answers with the session totals shown on the goodbye page.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{self, Permissions};
+use std::io;
+use std::iter::Peekable;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::Chars;
+use std::sync::Arc;
+use std::time::Duration;
+
+use forloop_config::{ForloopCli, NavigationKind};
+use forloop_fingerprint::{blocked_surface_registry, FingerprintDefense};
+use forloop_network::{
+    Connector, NavigationPipeline, NavigationStep, SanitizeReport, SanitizeStats,
+};
+use forloop_ui::{BrowserUi, UiMessage, WindowManager};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::session_stats::SessionStats;
+
+/// Longest accepted command line, in bytes.
+const MAX_COMMAND_BYTES: usize = 8 * 1024;
+
+/// `wait-for-load` timeout when none is given.
+const DEFAULT_LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A parsed automation command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AutomationCommand {
+    /// Enter a URL in the address bar
+    Navigate(String),
+    /// Click a link to a URL in the current page
+    FollowLink(String),
+    /// Reload the current page
+    Reload,
+    /// Wait for the current navigation's connection
+    WaitForLoad(Duration),
+    /// Read the sanitizer counters for this loop
+    GetShieldCounters,
+    /// Start a new loop (fresh identity, fresh context)
+    NewLoop,
+    /// Read the current synthetic fingerprint (no seeds)
+    GetFingerprintReport,
+    /// Quit the browser and stop the server
+    Quit,
+}
+
+impl AutomationCommand {
+    /// Parse one request line.
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = parse_object(line)?;
+        let Some(JsonValue::Str(cmd)) = fields.remove("cmd") else {
+            return Err("missing cmd".to_string());
+        };
+
+        match cmd.as_str() {
+            "navigate" => match fields.remove("url") {
+                Some(JsonValue::Str(url)) => Ok(Self::Navigate(url)),
+                _ => Err("navigate needs a url".to_string()),
+            },
+            "follow-link" => match fields.remove("url") {
+                Some(JsonValue::Str(url)) => Ok(Self::FollowLink(url)),
+                _ => Err("follow-link needs a url".to_string()),
+            },
+            "reload" => Ok(Self::Reload),
+            "wait-for-load" => match fields.remove("timeout_ms") {
+                None => Ok(Self::WaitForLoad(DEFAULT_LOAD_TIMEOUT)),
+                Some(JsonValue::Num(ms)) => Ok(Self::WaitForLoad(Duration::from_millis(ms))),
+                Some(JsonValue::Str(_)) => Err("timeout_ms must be a number".to_string()),
+            },
+            "get-shield-counters" => Ok(Self::GetShieldCounters),
+            "new-loop" => Ok(Self::NewLoop),
+            "get-fingerprint-report" => Ok(Self::GetFingerprintReport),
+            "quit" => Ok(Self::Quit),
+            other => Err(format!("unknown command: {}", other)),
+        }
+    }
+}
+
+/// Value in a request object. Only what the protocol needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JsonValue {
+    Str(String),
+    Num(u64),
+}
+
+/// Parse a flat JSON object of string and non-negative integer values.
+fn parse_object(line: &str) -> Result<HashMap<String, JsonValue>, String> {
+    fn skip_ws(chars: &mut Peekable<Chars<'_>>) {
+        while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn string(chars: &mut Peekable<Chars<'_>>) -> Result<String, String> {
+        if chars.next() != Some('"') {
+            return Err("expected string".to_string());
+        }
+        let mut out = String::new();
+        loop {
+            match chars.next().ok_or("unterminated string")? {
+                '"' => return Ok(out),
+                '\\' => out.push(match chars.next().ok_or("unterminated string")? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'u' => {
+                        let hex: String = chars.by_ref().take(4).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == 4)
+                            .and_then(char::from_u32)
+                            .ok_or("invalid \\u escape")?
+                    }
+                    _ => return Err("invalid escape".to_string()),
+                }),
+                c if c.is_control() => return Err("control character in string".to_string()),
+                c => out.push(c),
+            }
+        }
+    }
+
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = HashMap::new();
+
+    if chars.next() != Some('{') {
+        return Err("expected a JSON object".to_string());
+    }
+    skip_ws(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            skip_ws(&mut chars);
+            let key = string(&mut chars)?;
+            skip_ws(&mut chars);
+            if chars.next() != Some(':') {
+                return Err("expected ':'".to_string());
+            }
+            skip_ws(&mut chars);
+            let value = match chars.peek() {
+                Some('"') => JsonValue::Str(string(&mut chars)?),
+                Some(c) if c.is_ascii_digit() => {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    JsonValue::Num(digits.parse().map_err(|_| "number out of range")?)
+                }
+                _ => return Err("unsupported value".to_string()),
+            };
+            fields.insert(key, value);
+            skip_ws(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+    }
+
+    if chars.next().is_some() {
+        return Err("trailing data after object".to_string());
+    }
+    Ok(fields)
+}
+
+/// Encode `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Success response with pre-encoded field values.
+fn ok_response(fields: &[(&str, String)]) -> String {
+    let mut out = String::from("{\"ok\":true");
+    for (key, value) in fields {
+        let _ = write!(out, ",\"{}\":{}", key, value);
+    }
+    out.push('}');
+    out
+}
+
+/// Error response.
+fn error_response(message: &str) -> String {
+    format!("{{\"ok\":false,\"error\":{}}}", json_string(message))
+}
+
+/// The core state automation commands act on.
+pub struct AutomationHost<C: Connector> {
+    ui: BrowserUi,
+    connector: C,
+    max_request_bytes: usize,
+    pipeline: NavigationPipeline<C>,
+    fingerprint: FingerprintDefense,
+    shield: SanitizeStats,
+    session: Arc<SessionStats>,
+}
+
+impl<C: Connector> AutomationHost<C> {
+    /// Wrap the UI and a navigation connector.
+    pub fn new(ui: BrowserUi, connector: C, max_request_bytes: usize) -> Self {
+        let (window_width, window_height) = WindowManager::new().dimensions();
+        Self {
+            ui,
+            pipeline: NavigationPipeline::new(connector.clone(), max_request_bytes),
+            connector,
+            max_request_bytes,
+            fingerprint: FingerprintDefense::for_window(window_width, window_height),
+            shield: SanitizeStats::default(),
+            session: Arc::new(SessionStats::new()),
+        }
+    }
+
+    /// Get the session totals, to share with the event demux.
+    pub fn session_stats(&self) -> Arc<SessionStats> {
+        Arc::clone(&self.session)
+    }
+
+    /// Add a sanitized document's counters to this loop's shield totals.
+    pub fn record_sanitize(&mut self, report: &SanitizeReport) {
+        self.shield.refreshes_routed += report.stats.refreshes_routed;
+        self.shield.hints_stripped += report.stats.hints_stripped;
+        self.shield.pings_stripped += report.stats.pings_stripped;
+        self.shield.bases_neutralized += report.stats.bases_neutralized;
+        self.shield.headers_dropped += report.stats.headers_dropped;
+        self.session.record_sanitize(&report.stats);
+    }
+
+    /// Run one command and build its response line (without newline).
+    pub async fn dispatch(&mut self, command: AutomationCommand) -> String {
+        self.run(command)
+            .await
+            .unwrap_or_else(|e| error_response(&e))
+    }
+
+    async fn run(&mut self, command: AutomationCommand) -> Result<String, String> {
+        match command {
+            AutomationCommand::Navigate(url) => {
+                self.ui.navigate(&url).await;
+                self.start_navigation(&url, NavigationKind::AddressBar, None)
+            }
+            AutomationCommand::FollowLink(url) => {
+                let referrer = self.ui.current_url().to_string();
+                self.ui.follow_link(&url).await;
+                self.start_navigation(&url, NavigationKind::LinkClick, Some(&referrer))
+            }
+            AutomationCommand::Reload => {
+                let url = self.ui.current_url().to_string();
+                if url.is_empty() {
+                    return Err("nothing to reload".to_string());
+                }
+                self.ui.reload().await;
+                self.start_navigation(&url, NavigationKind::Reload, None)
+            }
+            AutomationCommand::WaitForLoad(timeout) => {
+                let ready = tokio::time::timeout(timeout, self.pipeline.renderer_ready())
+                    .await
+                    .map_err(|_| "timed out".to_string())?
+                    .map_err(|e| e.to_string())?;
+                self.ui.handle_message(UiMessage::LoadProgress(100));
+                self.session.record_page_loaded();
+                Ok(ok_response(&[(
+                    "context",
+                    ready.target.context_id.to_string(),
+                )]))
+            }
+            AutomationCommand::GetShieldCounters => Ok(ok_response(&[
+                ("refreshes_routed", self.shield.refreshes_routed.to_string()),
+                ("hints_stripped", self.shield.hints_stripped.to_string()),
+                ("pings_stripped", self.shield.pings_stripped.to_string()),
+                (
+                    "bases_neutralized",
+                    self.shield.bases_neutralized.to_string(),
+                ),
+                ("headers_dropped", self.shield.headers_dropped.to_string()),
+                ("total", self.shield.total().to_string()),
+            ])),
+            AutomationCommand::NewLoop => {
+                self.ui.new_loop().await;
+                self.fingerprint.rotate();
+                self.shield = SanitizeStats::default();
+                // Dropping the old pipeline cancels its connect and forgets
+                // the context's interstitial choices
+                self.pipeline =
+                    NavigationPipeline::new(self.connector.clone(), self.max_request_bytes);
+                Ok(ok_response(&[]))
+            }
+            AutomationCommand::GetFingerprintReport => {
+                let identity = self.fingerprint.identity();
+                let blocked: usize = blocked_surface_registry()
+                    .iter()
+                    .map(|(_, apis)| apis.len())
+                    .sum();
+                Ok(ok_response(&[
+                    ("platform", json_string(&identity.platform)),
+                    ("timezone", json_string(identity.timezone.iana_name())),
+                    (
+                        "screen",
+                        json_string(&format!(
+                            "{}x{}",
+                            identity.screen_bucket.width, identity.screen_bucket.height
+                        )),
+                    ),
+                    (
+                        "hardware_concurrency",
+                        identity.hardware.hardware_concurrency.to_string(),
+                    ),
+                    ("device_memory", identity.hardware.device_memory.to_string()),
+                    ("blocked_surfaces", blocked.to_string()),
+                    (
+                        "order_violations",
+                        self.fingerprint.audit_list_orders().len().to_string(),
+                    ),
+                ]))
+            }
+            AutomationCommand::Quit => {
+                // Goodbye page first, then the totals go with everything else
+                let summary = self.session.summary();
+                self.ui.handle_message(UiMessage::SessionSummary(summary));
+                self.session.wipe();
+                self.ui.quit().await;
+                Ok(ok_response(&[
+                    ("pages_loaded", summary.pages_loaded.to_string()),
+                    ("circuits_used", summary.circuits_used.to_string()),
+                    ("trackers_stripped", summary.trackers_stripped.to_string()),
+                    ("readbacks_noised", summary.readbacks_noised.to_string()),
+                    ("padding_bytes", summary.padding_bytes.to_string()),
+                ]))
+            }
+        }
+    }
+}
+
+impl<C: Connector> AutomationHost<C> {
+    /// Start connecting for a navigation the UI has already shown.
+    fn start_navigation(
+        &mut self,
+        url: &str,
+        kind: NavigationKind,
+        referrer: Option<&str>,
+    ) -> Result<String, String> {
+        let step = self
+            .pipeline
+            .on_navigate(url, kind, referrer)
+            .map_err(|e| e.to_string())?;
+        let target = match step {
+            NavigationStep::Connecting(target) => target,
+            // No one to ask: take what Enter would on the interstitial
+            NavigationStep::Interstitial(page) => self
+                .pipeline
+                .resolve_interstitial(page.default_choice())
+                .map_err(|e| e.to_string())?,
+        };
+        Ok(ok_response(&[
+            ("context", target.context_id.to_string()),
+            ("url", json_string(&target.url)),
+        ]))
+    }
+}
+
+/// The automation socket. The socket file is removed on drop.
+pub struct AutomationServer {
+    listener: UnixListener,
+    path: PathBuf,
+    uid: u32,
+}
+
+impl AutomationServer {
+    /// Start the server if, and only if, `--automation-socket` was given.
+    pub fn from_cli(cli: &ForloopCli) -> io::Result<Option<Self>> {
+        cli.automation_socket.as_deref().map(Self::bind).transpose()
+    }
+
+    /// Bind an owner-only socket at `path` and print the warning.
+    ///
+    /// An existing file at `path` is never replaced. Must be called from
+    /// within a tokio runtime.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        // Peers are checked by UID as well, which also covers the window
+        // before the mode is tightened
+        fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        let uid = fs::metadata(path)?.uid();
+
+        eprintln!("==============================================================");
+        eprintln!("AUTOMATION SOCKET ENABLED at {}", path.display());
+        eprintln!("Any program running as this user can drive this browser.");
+        eprintln!("For research only. Do not use this session for browsing.");
+        eprintln!("==============================================================");
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            uid,
+        })
+    }
+
+    /// Serve clients one at a time until a `quit` command.
+    pub async fn serve<C: Connector>(&self, host: &mut AutomationHost<C>) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            let peer = stream.peer_cred()?.uid();
+            if peer != self.uid {
+                log::warn!("Automation socket: rejected peer with uid {}", peer);
+                continue;
+            }
+            if self.session(stream, host).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle one client. Returns whether it asked to quit.
+    async fn session<C: Connector>(
+        &self,
+        stream: UnixStream,
+        host: &mut AutomationHost<C>,
+    ) -> io::Result<bool> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+
+        loop {
+            let mut line = Vec::new();
+            let n = (&mut reader)
+                .take(MAX_COMMAND_BYTES as u64 + 1)
+                .read_until(b'\n', &mut line)
+                .await?;
+            if n == 0 {
+                return Ok(false);
+            }
+            if line.len() > MAX_COMMAND_BYTES {
+                write
+                    .write_all(format!("{}\n", error_response("command too long")).as_bytes())
+                    .await?;
+                return Ok(false);
+            }
+
+            let (response, quit) = match std::str::from_utf8(&line)
+                .map_err(|_| "command is not UTF-8".to_string())
+                .and_then(AutomationCommand::parse)
+            {
+                Ok(command) => {
+                    let quit = command == AutomationCommand::Quit;
+                    (host.dispatch(command).await, quit)
+                }
+                Err(e) => (error_response(&e), false),
+            };
+            write
+                .write_all(format!("{}\n", response).as_bytes())
+                .await?;
+            if quit {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Drop for AutomationServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_network::{NavigationTarget, NetworkError};
+    use forloop_ui::SessionSummary;
+    use tokio::sync::mpsc;
+
+    /// Body the fake connection carries; must never reach a response.
+    const PAGE_BODY: &str = "<p>private page text</p>";
+
+    /// Connector standing in for Tor.
+    #[derive(Clone)]
+    struct FakeTor;
+
+    impl Connector for FakeTor {
+        type Connection = String;
+
+        async fn connect(&self, _target: NavigationTarget) -> Result<String, NetworkError> {
+            Ok(PAGE_BODY.to_string())
+        }
+    }
+
+    fn host() -> AutomationHost<FakeTor> {
+        let (tx, mut rx) = mpsc::channel(16);
+        // Stand-in for the browser core draining UI messages
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        AutomationHost::new(BrowserUi::new(tx), FakeTor, 1024)
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "forloop-automation-{}-{}.sock",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            AutomationCommand::parse(r#"{"cmd":"navigate","url":"https://a.onion/A"}"#),
+            Ok(AutomationCommand::Navigate("https://a.onion/A".to_string()))
+        );
+        assert_eq!(
+            AutomationCommand::parse(r#" { "timeout_ms" : 250 , "cmd" : "wait-for-load" } "#),
+            Ok(AutomationCommand::WaitForLoad(Duration::from_millis(250)))
+        );
+        assert_eq!(
+            AutomationCommand::parse(r#"{"cmd":"wait-for-load"}"#),
+            Ok(AutomationCommand::WaitForLoad(DEFAULT_LOAD_TIMEOUT))
+        );
+        assert_eq!(
+            AutomationCommand::parse(r#"{"cmd":"follow-link","url":"https://a.onion/B"}"#),
+            Ok(AutomationCommand::FollowLink(
+                "https://a.onion/B".to_string()
+            ))
+        );
+        assert_eq!(
+            AutomationCommand::parse(r#"{"cmd":"reload"}"#),
+            Ok(AutomationCommand::Reload)
+        );
+        assert_eq!(
+            AutomationCommand::parse(r#"{"cmd":"quit"}"#),
+            Ok(AutomationCommand::Quit)
+        );
+
+        for bad in [
+            "",
+            "navigate",
+            r#"{"cmd":"navigate"}"#,
+            r#"{"cmd":"follow-link"}"#,
+            r#"{"cmd":"eval","js":"1"}"#,
+            r#"{"cmd":"quit"} {"cmd":"quit"}"#,
+            r#"{"cmd":["quit"]}"#,
+            r#"{"cmd":"quit""#,
+        ] {
+            assert!(AutomationCommand::parse(bad).is_err(), "{:?} accepted", bad);
+        }
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("a\"b\\c\n"), r#""a\"b\\c\u000a""#);
+        assert_eq!(
+            error_response("unknown command: x"),
+            r#"{"ok":false,"error":"unknown command: x"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabled_without_flag() {
+        let cli = ForloopCli::parse_args(&["forloop".to_string()]);
+        assert!(AutomationServer::from_cli(&cli)
+            .expect("nothing to bind")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_existing_file_not_replaced() {
+        let path = socket_path("existing");
+        fs::write(&path, b"not a socket").expect("create file");
+        assert!(AutomationServer::bind(&path).is_err());
+        assert_eq!(fs::read(&path).expect("file kept"), b"not a socket");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_link_and_reload_navigate() {
+        let mut host = host();
+        assert_eq!(
+            host.dispatch(AutomationCommand::Reload).await,
+            error_response("nothing to reload")
+        );
+
+        host.dispatch(AutomationCommand::Navigate(
+            "https://example.com/a".to_string(),
+        ))
+        .await;
+        assert_eq!(
+            host.dispatch(AutomationCommand::FollowLink(
+                "https://example.org/b".to_string()
+            ))
+            .await,
+            r#"{"ok":true,"context":2,"url":"https://example.org/b"}"#
+        );
+        assert_eq!(host.ui.current_url(), "https://example.org/b");
+        assert_eq!(
+            host.dispatch(AutomationCommand::Reload).await,
+            r#"{"ok":true,"context":3,"url":"https://example.org/b"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_end_to_end_over_socket() {
+        let path = socket_path("e2e");
+        let server = AutomationServer::bind(&path).expect("bind");
+        let mode = fs::metadata(&path).expect("socket exists").mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let client_path = path.clone();
+        let client = tokio::spawn(async move {
+            let stream = UnixStream::connect(&client_path).await.expect("connect");
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut responses = Vec::new();
+
+            for command in [
+                r#"{"cmd":"navigate","url":"https://example.com/a"}"#,
+                r#"{"cmd":"wait-for-load","timeout_ms":5000}"#,
+                r#"{"cmd":"navigate","url":"https://duckduckgo.com/?q=x"}"#,
+                r#"{"cmd":"wait-for-load"}"#,
+                r#"{"cmd":"get-shield-counters"}"#,
+                r#"{"cmd":"get-fingerprint-report"}"#,
+                r#"{"cmd":"new-loop"}"#,
+                r#"{"cmd":"wait-for-load"}"#,
+                r#"{"cmd":"navigate","url":"http://example.com/"}"#,
+                "not json",
+                r#"{"cmd":"quit"}"#,
+            ] {
+                write
+                    .write_all(format!("{}\n", command).as_bytes())
+                    .await
+                    .expect("send");
+                responses.push(lines.next_line().await.expect("read").expect("response"));
+            }
+            responses
+        });
+
+        let mut host = host();
+        host.record_sanitize(&SanitizeReport {
+            stats: SanitizeStats {
+                pings_stripped: 2,
+                hints_stripped: 1,
+                ..SanitizeStats::default()
+            },
+            refreshes: Vec::new(),
+        });
+        server.serve(&mut host).await.expect("served until quit");
+        let responses = client.await.expect("client finished");
+
+        assert_eq!(
+            responses[0],
+            r#"{"ok":true,"context":1,"url":"https://example.com/a"}"#
+        );
+        assert_eq!(responses[1], r#"{"ok":true,"context":1}"#);
+        // The onion interstitial's Enter default was taken
+        assert!(responses[2]
+            .contains("duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion/?q=x"));
+        assert_eq!(responses[3], r#"{"ok":true,"context":2}"#);
+        assert_eq!(
+            responses[4],
+            concat!(
+                r#"{"ok":true,"refreshes_routed":0,"hints_stripped":1,"#,
+                r#""pings_stripped":2,"bases_neutralized":0,"headers_dropped":0,"total":3}"#
+            )
+        );
+        assert!(responses[5].starts_with(r#"{"ok":true,"platform":"#));
+        assert!(!responses[5].contains("seed"));
+        assert_eq!(responses[6], r#"{"ok":true}"#);
+        // New loop: nothing pending any more
+        assert!(responses[7].starts_with(r#"{"ok":false"#));
+        assert!(responses[8].starts_with(r#"{"ok":false"#));
+        assert!(responses[9].starts_with(r#"{"ok":false"#));
+        // Session totals survive New Loop and are reported once on quit
+        assert_eq!(
+            responses[10],
+            concat!(
+                r#"{"ok":true,"pages_loaded":2,"circuits_used":0,"trackers_stripped":3,"#,
+                r#""readbacks_noised":0,"padding_bytes":0}"#
+            )
+        );
+        assert_eq!(host.session_stats().summary(), SessionSummary::default());
+
+        for response in &responses {
+            assert!(!response.contains(PAGE_BODY), "page content leaked");
+        }
+
+        drop(server);
+        assert!(!path.exists(), "socket file left behind");
+    }
+}
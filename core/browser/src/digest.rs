@@ -0,0 +1,17 @@
+// Shared with build.rs through include!, so this file must stay free of
+// inner attributes and crate paths.
+
+/// FNV-1a digest of a privacy manifest, ignoring carriage returns.
+///
+/// This catches accidental edits, not a fork that also edits the manifest.
+pub fn manifest_digest(manifest: &str) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    manifest
+        .bytes()
+        .filter(|byte| *byte != b'\r')
+        .fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+}
@@ -0,0 +1,202 @@
+//! Startup self-check of compiled-in privacy constants.
+//!
+//! A downstream fork or packaging patch that touches a privacy default
+//! (an Accept-Language value, a storage switch, a blocked API) should be
+//! loud. `privacy_manifest` lists those values as they are compiled into
+//! this binary; build.rs digests the reviewed `privacy-defaults.manifest`
+//! and a mismatch means the two have drifted apart.
+
+use std::fmt::Write;
+
+use forloop_config::{ForloopConfig, ENGINE};
+use forloop_fingerprint::blocked_surface_registry;
+use forloop_network::{Platform, ACCEPT_ENCODING, ACCEPT_LANGUAGES, DANGEROUS_HEADERS};
+use forloop_ui::UiMessage;
+
+use crate::digest::manifest_digest;
+
+include!(concat!(env!("OUT_DIR"), "/expected_digest.rs"));
+
+/// List every security-critical compiled-in value, one `key = value` per line.
+///
+/// The format must match `privacy-defaults.manifest` byte for byte.
+pub fn privacy_manifest() -> String {
+    let config = ForloopConfig::get();
+    let mut out = String::new();
+
+    let mut line = |key: &str, value: &dyn std::fmt::Display| {
+        let _ = writeln!(out, "{} = {}", key, value);
+    };
+
+    line("config.tor_socks_port", &config.tor_socks_port);
+    line("config.tor_control_port", &config.tor_control_port);
+    line(
+        "config.new_circuit_per_request",
+        &config.new_circuit_per_request,
+    );
+    line(
+        "config.request_timeout_ms",
+        &config.request_timeout.as_millis(),
+    );
+    line(
+        "config.timing_precision_ms",
+        &config.timing_precision.as_millis(),
+    );
+    line(
+        "config.screen_bucket",
+        &format!(
+            "{}x{}",
+            config.screen_bucket.width, config.screen_bucket.height
+        ),
+    );
+    line("config.cookies_enabled", &config.cookies_enabled);
+    line(
+        "config.local_storage_enabled",
+        &config.local_storage_enabled,
+    );
+    line(
+        "config.session_storage_enabled",
+        &config.session_storage_enabled,
+    );
+    line("config.indexed_db_enabled", &config.indexed_db_enabled);
+    line("config.disk_cache_enabled", &config.disk_cache_enabled);
+    line(
+        "config.service_workers_enabled",
+        &config.service_workers_enabled,
+    );
+    line("config.webrtc_enabled", &config.webrtc_enabled);
+    line("config.geolocation_enabled", &config.geolocation_enabled);
+    line("config.sensors_enabled", &config.sensors_enabled);
+    line("config.telemetry_enabled", &config.telemetry_enabled);
+    line(
+        "config.crash_reporter_enabled",
+        &config.crash_reporter_enabled,
+    );
+
+    for platform in Platform::ALL {
+        line(
+            &format!("profile.user_agent.{:?}", platform).to_lowercase(),
+            &platform.user_agent(),
+        );
+    }
+    line("profile.accept_language", &ACCEPT_LANGUAGES.join("|"));
+    line("profile.accept_encoding", &ACCEPT_ENCODING);
+
+    line("headers.dangerous", &DANGEROUS_HEADERS.join(","));
+
+    for (owner, apis) in blocked_surface_registry() {
+        line(&format!("blocked.{}", owner), &apis.join(","));
+    }
+
+    out
+}
+
+/// Outcome of the startup self-check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Digest generated at build time from the reviewed manifest
+    pub expected: u64,
+    /// Digest of the values compiled into this binary
+    pub actual: u64,
+}
+
+impl IntegrityReport {
+    /// Check this binary's privacy constants.
+    pub fn check() -> Self {
+        Self::for_manifest(&privacy_manifest())
+    }
+
+    /// Check an arbitrary manifest against the build-time digest.
+    pub fn for_manifest(manifest: &str) -> Self {
+        Self {
+            expected: EXPECTED_DIGEST,
+            actual: manifest_digest(manifest),
+        }
+    }
+
+    /// Whether the compiled-in values match the reviewed manifest.
+    pub fn is_intact(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    /// Log the result, loudly on mismatch.
+    pub fn log(&self) {
+        if self.is_intact() {
+            log::debug!("Privacy defaults digest {:#018x} verified", self.actual);
+        } else {
+            log::warn!("==============================================================");
+            log::warn!("This build's privacy defaults have been modified");
+            log::warn!(
+                "expected digest {:#018x}, found {:#018x}",
+                self.expected,
+                self.actual
+            );
+            log::warn!("==============================================================");
+        }
+    }
+
+    /// UI banner to raise, if the check failed.
+    pub fn ui_message(&self) -> Option<UiMessage> {
+        (!self.is_intact()).then_some(UiMessage::ModifiedBuild)
+    }
+}
+
+/// `--version --json` output.
+pub fn version_json(report: &IntegrityReport) -> String {
+    format!(
+        concat!(
+            "{{\"version\":\"{}\",\"engine\":\"{}\",\"tor\":\"embedded\",",
+            "\"privacy_defaults\":{{\"intact\":{},\"expected\":\"{:016x}\",\"actual\":\"{:016x}\"}}}}"
+        ),
+        env!("CARGO_PKG_VERSION"),
+        ENGINE,
+        report.is_intact(),
+        report.expected,
+        report.actual
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compiled_defaults_match_manifest() {
+        let report = IntegrityReport::check();
+        assert!(
+            report.is_intact(),
+            "privacy defaults drifted from privacy-defaults.manifest; if intended, \
+             replace the manifest with:\n{}",
+            privacy_manifest()
+        );
+        assert!(report.ui_message().is_none());
+    }
+
+    #[test]
+    fn test_changed_constant_flips_result() {
+        let manifest = privacy_manifest();
+        assert!(IntegrityReport::for_manifest(&manifest).is_intact());
+
+        let patched = manifest.replace(
+            "config.disk_cache_enabled = false",
+            "config.disk_cache_enabled = true",
+        );
+        assert_ne!(patched, manifest);
+        let report = IntegrityReport::for_manifest(&patched);
+        assert!(!report.is_intact());
+        assert!(matches!(
+            report.ui_message(),
+            Some(UiMessage::ModifiedBuild)
+        ));
+        assert!(version_json(&report).contains("\"intact\":false"));
+
+        let patched = manifest.replace("en-US,en;q=0.5", "de-DE,de;q=0.5");
+        assert!(!IntegrityReport::for_manifest(&patched).is_intact());
+    }
+
+    #[test]
+    fn test_line_endings_ignored() {
+        let manifest = privacy_manifest().replace('\n', "\r\n");
+        assert!(IntegrityReport::for_manifest(&manifest).is_intact());
+    }
+}
@@ -0,0 +1,26 @@
+//! forloop Browser Core
+//!
+//! Glue between the process-level crates. The network layer and the UI
+//! know nothing about each other; everything that crosses between them
+//! is translated here.
+
+pub mod automation;
+mod digest;
+pub mod integrity;
+mod metadata;
+mod session_stats;
+mod translate;
+mod uploads;
+
+pub use automation::{AutomationCommand, AutomationHost, AutomationServer};
+pub use integrity::{privacy_manifest, version_json, IntegrityReport};
+pub use metadata::{strip_metadata, ImageFormat, MetadataError, RemovedMetadata};
+pub use session_stats::SessionStats;
+pub use translate::{
+    spawn_event_demux, translate_bootstrap, translate_download, translate_network_event,
+    translate_retry_offer, translate_verification,
+};
+pub use uploads::{
+    mediate_upload, FileOutcome, FileReview, PendingUpload, PickedFile, UploadConfirmation,
+    UploadFile,
+};
@@ -0,0 +1,389 @@
+//! Image metadata removal for uploads.
+//!
+//! A photo picked for upload can carry the GPS position it was taken at,
+//! the camera make and model, editing software and free-form comments.
+//! Stripping rewrites the file without those structures and leaves the
+//! image data byte for byte intact:
+//!
+//! - JPEG: APPn segments other than JFIF, ICC profile and Adobe color
+//!   info are dropped, as are comments and anything after EOI
+//! - PNG: ancillary chunks other than those needed to render (gamma,
+//!   color space, transparency, animation) are dropped, as is anything
+//!   after IEND
+//!
+//! TIFF keeps its metadata in the same IFDs as the image layout, so it
+//! cannot be cleaned by dropping segments and is reported as unsupported.
+
+use std::fmt;
+
+/// PNG file signature.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Ancillary PNG chunks that affect rendering.
+const KEPT_PNG_CHUNKS: &[&[u8; 4]] = &[
+    b"acTL", b"bKGD", b"cHRM", b"fcTL", b"fdAT", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"tRNS",
+];
+
+/// EXIF tags that identify the device.
+const CAMERA_TAGS: &[u16] = &[0x010f, 0x0110];
+
+/// EXIF tag pointing to the GPS IFD.
+const GPS_IFD_TAG: u16 = 0x8825;
+
+/// Image formats the stripper recognises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// JPEG / JFIF / EXIF
+    Jpeg,
+    /// PNG (and APNG)
+    Png,
+    /// TIFF, either byte order
+    Tiff,
+}
+
+impl ImageFormat {
+    /// Recognise a format by its magic bytes.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(PNG_SIGNATURE) {
+            Some(Self::Png)
+        } else if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+            Some(Self::Tiff)
+        } else {
+            None
+        }
+    }
+
+    /// Get the name shown to the user.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::Tiff => "TIFF",
+        }
+    }
+
+    /// Get the file extension used for renamed uploads.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Tiff => "tif",
+        }
+    }
+}
+
+/// What was removed from an image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RemovedMetadata {
+    /// GPS position
+    pub location: bool,
+    /// Camera make or model
+    pub camera: bool,
+    /// EXIF block
+    pub exif: bool,
+    /// XMP packet
+    pub xmp: bool,
+    /// Comments and text chunks
+    pub comments: bool,
+    /// Anything else: timestamps, vendor segments, trailing data
+    pub other: bool,
+}
+
+impl RemovedMetadata {
+    /// Whether anything was removed.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Describe what was removed, most sensitive first.
+    pub fn descriptions(&self) -> Vec<&'static str> {
+        [
+            (self.location, "Location (GPS)"),
+            (self.camera, "Camera make and model"),
+            (self.exif, "EXIF data"),
+            (self.xmp, "XMP data"),
+            (self.comments, "Comments and text"),
+            (self.other, "Other embedded metadata"),
+        ]
+        .into_iter()
+        .filter_map(|(removed, what)| removed.then_some(what))
+        .collect()
+    }
+}
+
+/// Why an image could not be cleaned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataError {
+    /// The format is recognised but cannot be cleaned
+    Unsupported(ImageFormat),
+    /// The file ends early or its structure is broken
+    Malformed(ImageFormat),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unsupported(format) => {
+                write!(
+                    f,
+                    "metadata cannot be removed from {} images",
+                    format.name()
+                )
+            }
+            Self::Malformed(format) => write!(f, "damaged {} image", format.name()),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// Remove metadata from an image.
+///
+/// Returns the cleaned bytes and what was removed, or `Ok(None)` if the
+/// file is not an image format this module knows.
+pub fn strip_metadata(
+    bytes: &[u8],
+) -> Result<Option<(ImageFormat, Vec<u8>, RemovedMetadata)>, MetadataError> {
+    let Some(format) = ImageFormat::sniff(bytes) else {
+        return Ok(None);
+    };
+    let mut removed = RemovedMetadata::default();
+    let clean = match format {
+        ImageFormat::Jpeg => strip_jpeg(bytes, &mut removed),
+        ImageFormat::Png => strip_png(bytes, &mut removed),
+        ImageFormat::Tiff => return Err(MetadataError::Unsupported(format)),
+    }
+    .ok_or(MetadataError::Malformed(format))?;
+    Ok(Some((format, clean, removed)))
+}
+
+fn strip_jpeg(bytes: &[u8], removed: &mut RemovedMetadata) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut pos = 2;
+
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+        // Fill bytes may pad any marker
+        while bytes.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *bytes.get(pos + 1)?;
+
+        match marker {
+            0xd9 => {
+                out.extend_from_slice(&[0xff, 0xd9]);
+                if pos + 2 < bytes.len() {
+                    removed.other = true;
+                }
+                return Some(out);
+            }
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&[0xff, marker]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = usize::from(u16::from_be_bytes([
+            *bytes.get(pos + 2)?,
+            *bytes.get(pos + 3)?,
+        ]));
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            return None;
+        }
+        let payload = &bytes[pos + 4..end];
+
+        if marker == 0xda {
+            // Entropy-coded data runs to the next marker that is not a
+            // stuffed 0xff or a restart
+            let is_marker =
+                |at: &[u8]| at[0] == 0xff && !matches!(at[1], 0x00 | 0xd0..=0xd7 | 0xff);
+            let scan_end = end + bytes[end..].windows(2).position(is_marker)?;
+            out.extend_from_slice(&bytes[pos..scan_end]);
+            pos = scan_end;
+            continue;
+        }
+
+        let keep = match marker {
+            0xe0 => true,
+            0xe2 => payload.starts_with(b"ICC_PROFILE\0"),
+            0xee => payload.starts_with(b"Adobe"),
+            0xe1 if payload.starts_with(b"Exif\0\0") => {
+                removed.exif = true;
+                scan_exif(&payload[6..], removed);
+                false
+            }
+            0xe1 if payload.starts_with(b"http://ns.adobe.com/") => {
+                removed.xmp = true;
+                false
+            }
+            0xfe => {
+                removed.comments = true;
+                false
+            }
+            0xe1..=0xef => {
+                removed.other = true;
+                false
+            }
+            _ => true,
+        };
+        if keep {
+            out.extend_from_slice(&bytes[pos..end]);
+        }
+        pos = end;
+    }
+}
+
+fn strip_png(bytes: &[u8], removed: &mut RemovedMetadata) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+
+    loop {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?);
+        let end = pos
+            .checked_add(12)?
+            .checked_add(usize::try_from(len).ok()?)?;
+        let chunk = bytes.get(pos..end)?;
+        let kind: &[u8; 4] = chunk[4..8].try_into().ok()?;
+        let data = &chunk[8..chunk.len() - 4];
+
+        // Critical chunks have an uppercase first letter
+        let keep = kind[0].is_ascii_uppercase() || KEPT_PNG_CHUNKS.contains(&kind);
+        if keep {
+            out.extend_from_slice(chunk);
+        } else {
+            match kind {
+                b"eXIf" => {
+                    removed.exif = true;
+                    scan_exif(data, removed);
+                }
+                b"iTXt" if data.starts_with(b"XML:com.adobe.xmp\0") => removed.xmp = true,
+                b"tEXt" | b"zTXt" | b"iTXt" => removed.comments = true,
+                _ => removed.other = true,
+            }
+        }
+        pos = end;
+
+        if kind == b"IEND" {
+            if pos < bytes.len() {
+                removed.other = true;
+            }
+            return Some(out);
+        }
+    }
+}
+
+/// Note which identifying tags an EXIF (TIFF-structured) block has in IFD0.
+fn scan_exif(tiff: &[u8], removed: &mut RemovedMetadata) {
+    let little_endian = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return,
+    };
+    let u16_at = |at: usize| {
+        let b = tiff.get(at..at + 2)?;
+        Some(if little_endian {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    };
+    let u32_at = |at: usize| {
+        let b: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+
+    let Some(ifd) = u32_at(4).and_then(|o| usize::try_from(o).ok()) else {
+        return;
+    };
+    let count = u16_at(ifd).unwrap_or(0);
+    for entry in 0..usize::from(count) {
+        let Some(tag) = u16_at(ifd + 2 + entry * 12) else {
+            return;
+        };
+        if tag == GPS_IFD_TAG {
+            removed.location = true;
+        } else if CAMERA_TAGS.contains(&tag) {
+            removed.camera = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/uploads")
+            .join(name);
+        std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    #[test]
+    fn test_jpeg_gps_exif_stripped() {
+        let (format, clean, removed) = strip_metadata(&fixture("gps.jpg"))
+            .expect("valid jpeg")
+            .expect("recognised");
+        assert_eq!(format, ImageFormat::Jpeg);
+        assert_eq!(clean, fixture("gps.clean.jpg"));
+        assert_eq!(
+            removed.descriptions(),
+            [
+                "Location (GPS)",
+                "Camera make and model",
+                "EXIF data",
+                "XMP data",
+                "Comments and text"
+            ]
+        );
+
+        // Already clean: unchanged, nothing reported
+        let (_, again, removed) = strip_metadata(&clean).expect("valid").expect("jpeg");
+        assert_eq!(again, clean);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn test_png_gps_exif_stripped() {
+        let mut dirty = fixture("gps.png");
+        dirty.extend_from_slice(b"trailing");
+        let (format, clean, removed) = strip_metadata(&dirty).expect("valid png").expect("png");
+        assert_eq!(format, ImageFormat::Png);
+        assert_eq!(clean, fixture("gps.clean.png"));
+        assert!(removed.location && removed.camera && removed.xmp);
+        assert!(removed.exif && removed.comments && removed.other);
+    }
+
+    #[test]
+    fn test_unsupported_and_malformed() {
+        assert_eq!(strip_metadata(b"%PDF-1.7"), Ok(None));
+        assert_eq!(
+            strip_metadata(b"II*\0\x08\0\0\0"),
+            Err(MetadataError::Unsupported(ImageFormat::Tiff))
+        );
+
+        let jpeg = fixture("gps.jpg");
+        assert_eq!(
+            strip_metadata(&jpeg[..jpeg.len() / 2]),
+            Err(MetadataError::Malformed(ImageFormat::Jpeg))
+        );
+        let png = fixture("gps.png");
+        assert_eq!(
+            strip_metadata(&png[..png.len() - 20]),
+            Err(MetadataError::Malformed(ImageFormat::Png))
+        );
+    }
+}
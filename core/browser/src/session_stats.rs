@@ -0,0 +1,161 @@
+//! Session totals for the goodbye page.
+//!
+//! A handful of fixed-size counters and nothing else: no URLs, no hosts,
+//! no per-site breakdown, so there is nothing in here worth stealing. The
+//! totals live in memory only, survive New Loop, are shown once on the
+//! goodbye page during quit, and are then zeroed with everything else.
+//! Counters saturate instead of wrapping.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use forloop_network::{NetworkEvent, SanitizeStats};
+use forloop_ui::SessionSummary;
+
+/// Session-wide counters, fed from the shield counters and network events.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    pages_loaded: AtomicU32,
+    circuits_used: AtomicU32,
+    trackers_stripped: AtomicU32,
+    readbacks_noised: AtomicU32,
+    padding_bytes: AtomicU64,
+}
+
+fn add_saturating(counter: &AtomicU32, n: u32) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_add(n))
+    });
+}
+
+impl SessionStats {
+    /// Create zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a page that reached the renderer.
+    pub fn record_page_loaded(&self) {
+        add_saturating(&self.pages_loaded, 1);
+    }
+
+    /// Count what a network event implies. Only new circuits matter here.
+    pub fn record_event(&self, event: &NetworkEvent) {
+        if let NetworkEvent::CircuitBuilt { .. } = event {
+            add_saturating(&self.circuits_used, 1);
+        }
+    }
+
+    /// Add a sanitized document's shield counters.
+    pub fn record_sanitize(&self, stats: &SanitizeStats) {
+        add_saturating(
+            &self.trackers_stripped,
+            u32::try_from(stats.total()).unwrap_or(u32::MAX),
+        );
+    }
+
+    /// Count canvas, WebGL or audio readbacks that were given noise.
+    pub fn record_readbacks_noised(&self, count: u32) {
+        add_saturating(&self.readbacks_noised, count);
+    }
+
+    /// Take the network layer's running padding total.
+    ///
+    /// The network reports a total rather than increments, so this keeps
+    /// the largest value seen.
+    pub fn record_padding_total(&self, total_bytes: u64) {
+        self.padding_bytes.fetch_max(total_bytes, Ordering::Relaxed);
+    }
+
+    /// Totals so far.
+    pub fn summary(&self) -> SessionSummary {
+        SessionSummary {
+            pages_loaded: self.pages_loaded.load(Ordering::Relaxed),
+            circuits_used: self.circuits_used.load(Ordering::Relaxed),
+            trackers_stripped: self.trackers_stripped.load(Ordering::Relaxed),
+            readbacks_noised: self.readbacks_noised.load(Ordering::Relaxed),
+            padding_bytes: self.padding_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zero every counter.
+    pub fn wipe(&self) {
+        self.pages_loaded.store(0, Ordering::Relaxed);
+        self.circuits_used.store(0, Ordering::Relaxed);
+        self.trackers_stripped.store(0, Ordering::Relaxed);
+        self.readbacks_noised.store(0, Ordering::Relaxed);
+        self.padding_bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_network::{Destination, TorState};
+
+    fn circuit_built() -> NetworkEvent {
+        NetworkEvent::CircuitBuilt {
+            info: forloop_network::CircuitInfo {
+                entry_country: "de".to_string(),
+                exit_country: "nl".to_string(),
+                hop_count: 3,
+            },
+        }
+    }
+
+    #[test]
+    fn test_aggregates_feed_points() {
+        let stats = SessionStats::new();
+        stats.record_page_loaded();
+        stats.record_page_loaded();
+        stats.record_event(&circuit_built());
+        stats.record_event(&NetworkEvent::RequestStarted {
+            context: 1,
+            destination: Destination::Document,
+        });
+        stats.record_event(&NetworkEvent::TorStateChanged(TorState::Connected));
+        stats.record_sanitize(&SanitizeStats {
+            hints_stripped: 2,
+            pings_stripped: 3,
+            ..SanitizeStats::default()
+        });
+        stats.record_readbacks_noised(4);
+        stats.record_padding_total(900);
+        stats.record_padding_total(600);
+
+        assert_eq!(
+            stats.summary(),
+            SessionSummary {
+                pages_loaded: 2,
+                circuits_used: 1,
+                trackers_stripped: 5,
+                readbacks_noised: 4,
+                padding_bytes: 900,
+            }
+        );
+    }
+
+    #[test]
+    fn test_counters_saturate() {
+        let stats = SessionStats::new();
+        stats.record_readbacks_noised(u32::MAX);
+        stats.record_readbacks_noised(10);
+        stats.record_sanitize(&SanitizeStats {
+            pings_stripped: usize::MAX,
+            ..SanitizeStats::default()
+        });
+        assert_eq!(stats.summary().readbacks_noised, u32::MAX);
+        assert_eq!(stats.summary().trackers_stripped, u32::MAX);
+    }
+
+    #[test]
+    fn test_wipe_zeroes_everything() {
+        let stats = SessionStats::new();
+        stats.record_page_loaded();
+        stats.record_event(&circuit_built());
+        stats.record_readbacks_noised(1);
+        stats.record_padding_total(1);
+
+        stats.wipe();
+        assert_eq!(stats.summary(), SessionSummary::default());
+    }
+}
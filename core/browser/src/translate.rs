@@ -0,0 +1,518 @@
+//! NetworkEvent → UiMessage translation.
+//!
+//! This is the only place network happenings become UI messages. Every
+//! match below is exhaustive, so a new event or error class fails to
+//! compile until it has been given a UI meaning.
+
+use std::sync::Arc;
+
+use forloop_network::{
+    BootstrapProgress, ConnectionSecurity, Consistency, ConsistencyReport, Destination, ErrorClass,
+    IdentityPhase, NetworkError, NetworkEvent, NetworkResponse, OnionPhase, TaskRegistry,
+    TaskScope, TorState,
+};
+use forloop_ui::{
+    CircuitInfo, PageConsistency, RetryPrompt, SecurityIndicator, TorStatus, UiMessage,
+};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::AbortHandle;
+
+use crate::session_stats::SessionStats;
+
+/// Translate one network event into the UI messages it implies.
+///
+/// Subresource starts and user cancellations produce nothing.
+pub fn translate_network_event(event: NetworkEvent) -> Vec<UiMessage> {
+    match event {
+        NetworkEvent::RequestStarted {
+            context: _,
+            destination,
+        } => match destination {
+            Destination::Document => vec![UiMessage::LoadProgress(0)],
+            Destination::Image
+            | Destination::Xhr
+            | Destination::Font
+            | Destination::Embed
+            | Destination::Script
+            | Destination::Style
+            | Destination::Iframe => Vec::new(),
+        },
+        NetworkEvent::Progress {
+            context: _,
+            progress,
+        } => vec![UiMessage::UploadProgress {
+            sent_bytes: progress.sent_bytes,
+            total_bytes: progress.total_bytes,
+        }],
+        NetworkEvent::Completed {
+            context: _,
+            status: _,
+            security,
+        } => vec![
+            UiMessage::LoadProgress(100),
+            UiMessage::SecurityChanged(security_indicator(security)),
+        ],
+        // A credential request on the path gets a warning page, never a prompt
+        NetworkEvent::Failed {
+            context: _,
+            error_class: ErrorClass::Intercepted,
+        } => vec![
+            UiMessage::SecurityChanged(SecurityIndicator::Error),
+            UiMessage::SecurityWarning(
+                error_message(ErrorClass::Intercepted)
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+        ],
+        NetworkEvent::Failed {
+            context: _,
+            error_class,
+        } => match error_message(error_class) {
+            Some(message) => vec![
+                UiMessage::SecurityChanged(SecurityIndicator::Error),
+                UiMessage::ShowError(message.to_string()),
+            ],
+            None => Vec::new(),
+        },
+        NetworkEvent::OnionProgress { context: _, phase } => match phase {
+            // Completion clears the status bar
+            OnionPhase::Connected => Vec::new(),
+            OnionPhase::LookingUp
+            | OnionPhase::Introducing
+            | OnionPhase::Rendezvous
+            | OnionPhase::WaitingForService => {
+                vec![UiMessage::ConnectStatus(phase.status_text().to_string())]
+            }
+        },
+        NetworkEvent::CircuitBuilt { info } => vec![UiMessage::CircuitChanged(CircuitInfo {
+            exit_country: info.exit_country,
+            hops: u8::try_from(info.hop_count).unwrap_or(u8::MAX),
+        })],
+        NetworkEvent::TorStateChanged(state) => vec![UiMessage::TorStatusChanged(match state {
+            TorState::Connecting => TorStatus::Connecting,
+            TorState::Connected => TorStatus::Connected,
+            TorState::Degraded(reason) => TorStatus::Degraded(reason),
+            TorState::Failed(reason) => TorStatus::Failed(reason),
+        })],
+        NetworkEvent::ExcessiveConnections => vec![UiMessage::ExcessiveConnections],
+        NetworkEvent::NewIdentity(phase) => match phase {
+            // The new circuit arrives as `CircuitBuilt`; this clears the status bar
+            IdentityPhase::Ready => Vec::new(),
+            IdentityPhase::ClosingCircuits | IdentityPhase::BuildingCircuit => {
+                vec![UiMessage::ConnectStatus(phase.status_text().to_string())]
+            }
+        },
+        NetworkEvent::MetricsUpdated(snapshot) => vec![UiMessage::MetricsUpdated(snapshot)],
+    }
+}
+
+/// Translate the outcome of "verify this page" for the security popup.
+pub fn translate_verification(result: Result<ConsistencyReport, NetworkError>) -> UiMessage {
+    UiMessage::PageVerified(match result {
+        Ok(report) => match report.consistency {
+            Consistency::Match { len: _ } => PageConsistency::Match,
+            Consistency::Mismatch {
+                first_len: _,
+                second_len: _,
+                first_difference,
+            } => PageConsistency::Mismatch { first_difference },
+        },
+        Err(e) => PageConsistency::Failed(
+            error_message(ErrorClass::of(&e))
+                .unwrap_or("Verification was cancelled.")
+                .to_string(),
+        ),
+    })
+}
+
+/// Forward network events to the UI for the rest of the session.
+///
+/// Every event is also counted into the session totals. The task lives in
+/// the session scope and stops on its own once either the event stream or
+/// the UI channel closes. A lagging receiver skips the missed events
+/// rather than stalling the network.
+pub fn spawn_event_demux(
+    tasks: &TaskRegistry,
+    mut events: broadcast::Receiver<NetworkEvent>,
+    ui: mpsc::Sender<UiMessage>,
+    stats: Arc<SessionStats>,
+) -> AbortHandle {
+    tasks.spawn(TaskScope::Session, "event demux", async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    log::warn!("UI missed {} network events", missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            stats.record_event(&event);
+            for message in translate_network_event(event) {
+                if ui.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    })
+}
+
+/// Offer a retry for a failed request, if its error allows one.
+///
+/// Only onion timeouts do: the service may just be slow to rendezvous.
+pub fn translate_retry_offer(error: &NetworkError) -> Option<UiMessage> {
+    match error {
+        NetworkError::OnionTimeout(timeout) => Some(UiMessage::OfferRetry(RetryPrompt {
+            message: timeout.message().to_string(),
+            url: timeout.url.clone(),
+            reuse_descriptor: timeout.descriptor_cached,
+        })),
+        _ => None,
+    }
+}
+
+/// Show Tor's bootstrap progress in the status indicator.
+pub fn translate_bootstrap(progress: &BootstrapProgress) -> UiMessage {
+    UiMessage::TorStatusChanged(match &progress.stalled {
+        Some(reason) => TorStatus::Stalled(progress.percent, reason.clone()),
+        None if progress.is_done() => TorStatus::Connected,
+        None => TorStatus::Bootstrapping(progress.percent),
+    })
+}
+
+/// Tell the user where a PDF went, if the response was downloaded.
+pub fn translate_download(response: &NetworkResponse) -> Option<UiMessage> {
+    response
+        .download
+        .as_ref()
+        .map(|path| UiMessage::PdfDownloaded(path.display().to_string()))
+}
+
+fn security_indicator(security: ConnectionSecurity) -> SecurityIndicator {
+    match security {
+        ConnectionSecurity::Https => SecurityIndicator::Secure,
+        ConnectionSecurity::Onion => SecurityIndicator::Onion,
+    }
+}
+
+/// User-facing text for a failure class. Cancellation is not an error.
+fn error_message(error_class: ErrorClass) -> Option<&'static str> {
+    match error_class {
+        ErrorClass::Tor => Some("Could not reach the Tor network."),
+        ErrorClass::Circuit => Some("Could not build a Tor circuit. Try again."),
+        ErrorClass::Timeout => Some("The site took too long to respond."),
+        ErrorClass::OnionTimeout => Some("The onion service took too long to respond."),
+        ErrorClass::OnionService => Some("The onion service could not be reached."),
+        ErrorClass::Intercepted => Some(
+            "Something between forloop and Tor asked for proxy credentials. \
+             Your traffic may be intercepted. forloop never sends credentials.",
+        ),
+        ErrorClass::Tls => Some("A secure connection could not be established."),
+        ErrorClass::Dns => Some("The site's address could not be found."),
+        ErrorClass::Refused => Some("This address is not allowed."),
+        ErrorClass::TooLarge => Some("The upload is too large."),
+        ErrorClass::ResponseTooLarge => Some("The page is too large to load."),
+        ErrorClass::Other => Some("The request failed."),
+        ErrorClass::Cancelled => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_network::{MetricsSnapshot, OnionTimeout, UploadProgress};
+
+    /// One event per variant. The exhaustive match in `variant_index`
+    /// stops this list from silently falling behind the enum.
+    fn sample_events() -> Vec<NetworkEvent> {
+        vec![
+            NetworkEvent::RequestStarted {
+                context: 1,
+                destination: Destination::Document,
+            },
+            NetworkEvent::Progress {
+                context: 1,
+                progress: UploadProgress {
+                    sent_bytes: 10,
+                    total_bytes: 40,
+                },
+            },
+            NetworkEvent::Completed {
+                context: 1,
+                status: 200,
+                security: ConnectionSecurity::Onion,
+            },
+            NetworkEvent::Failed {
+                context: 2,
+                error_class: ErrorClass::Timeout,
+            },
+            NetworkEvent::CircuitBuilt {
+                info: forloop_network::CircuitInfo {
+                    entry_country: "DE".to_string(),
+                    exit_country: "NL".to_string(),
+                    hop_count: 3,
+                },
+            },
+            NetworkEvent::TorStateChanged(TorState::Degraded("Network offline".to_string())),
+            NetworkEvent::OnionProgress {
+                context: 3,
+                phase: OnionPhase::Rendezvous,
+            },
+            NetworkEvent::ExcessiveConnections,
+            NetworkEvent::NewIdentity(IdentityPhase::BuildingCircuit),
+            NetworkEvent::MetricsUpdated(MetricsSnapshot {
+                attempts: 3,
+                successes: 2,
+                ..MetricsSnapshot::default()
+            }),
+        ]
+    }
+
+    fn variant_index(event: &NetworkEvent) -> usize {
+        match event {
+            NetworkEvent::RequestStarted { .. } => 0,
+            NetworkEvent::Progress { .. } => 1,
+            NetworkEvent::Completed { .. } => 2,
+            NetworkEvent::Failed { .. } => 3,
+            NetworkEvent::CircuitBuilt { .. } => 4,
+            NetworkEvent::TorStateChanged(_) => 5,
+            NetworkEvent::OnionProgress { .. } => 6,
+            NetworkEvent::ExcessiveConnections => 7,
+            NetworkEvent::NewIdentity(_) => 8,
+            NetworkEvent::MetricsUpdated(_) => 9,
+        }
+    }
+
+    #[test]
+    fn test_every_variant_translates() {
+        let events = sample_events();
+        let mut seen: Vec<usize> = events.iter().map(variant_index).collect();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen, (0..=9).collect::<Vec<_>>());
+
+        for event in events {
+            assert!(
+                !translate_network_event(event.clone()).is_empty(),
+                "{:?} produced no UI message",
+                event
+            );
+        }
+    }
+
+    #[test]
+    fn test_translation_values() {
+        let messages = translate_network_event(NetworkEvent::Completed {
+            context: 1,
+            status: 200,
+            security: ConnectionSecurity::Onion,
+        });
+        assert!(matches!(messages[0], UiMessage::LoadProgress(100)));
+        assert!(matches!(
+            messages[1],
+            UiMessage::SecurityChanged(SecurityIndicator::Onion)
+        ));
+
+        let messages = translate_network_event(NetworkEvent::CircuitBuilt {
+            info: forloop_network::CircuitInfo {
+                entry_country: "DE".to_string(),
+                exit_country: "NL".to_string(),
+                hop_count: 3,
+            },
+        });
+        let UiMessage::CircuitChanged(info) = &messages[0] else {
+            panic!("expected CircuitChanged, got {:?}", messages);
+        };
+        assert_eq!(info.exit_country, "NL");
+        assert_eq!(info.hops, 3);
+
+        let snapshot = MetricsSnapshot {
+            attempts: 4,
+            bytes_received: 512,
+            ..MetricsSnapshot::default()
+        };
+        assert!(matches!(
+            &translate_network_event(NetworkEvent::MetricsUpdated(snapshot.clone()))[..],
+            [UiMessage::MetricsUpdated(shown)] if *shown == snapshot
+        ));
+    }
+
+    #[test]
+    fn test_silent_events() {
+        assert!(translate_network_event(NetworkEvent::RequestStarted {
+            context: 3,
+            destination: Destination::Image,
+        })
+        .is_empty());
+        assert!(translate_network_event(NetworkEvent::Failed {
+            context: 3,
+            error_class: ErrorClass::Cancelled,
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn test_verification() {
+        let report = |consistency| ConsistencyReport {
+            context_id: 1,
+            url: "https://example.onion/".to_string(),
+            circuits: ["a".to_string(), "b".to_string()],
+            consistency,
+        };
+
+        assert!(matches!(
+            translate_verification(Ok(report(Consistency::Match { len: 5 }))),
+            UiMessage::PageVerified(PageConsistency::Match)
+        ));
+        assert!(matches!(
+            translate_verification(Ok(report(Consistency::Mismatch {
+                first_len: 5,
+                second_len: 6,
+                first_difference: 5,
+            }))),
+            UiMessage::PageVerified(PageConsistency::Mismatch {
+                first_difference: 5
+            })
+        ));
+        assert!(matches!(
+            translate_verification(Err(NetworkError::Timeout)),
+            UiMessage::PageVerified(PageConsistency::Failed(_))
+        ));
+    }
+
+    #[test]
+    fn test_onion_timeout() {
+        let messages = translate_network_event(NetworkEvent::OnionProgress {
+            context: 1,
+            phase: OnionPhase::WaitingForService,
+        });
+        assert!(matches!(
+            &messages[..],
+            [UiMessage::ConnectStatus(status)] if status == "Waiting for service…"
+        ));
+
+        let error = NetworkError::OnionTimeout(OnionTimeout {
+            url: "https://abcxyz.onion/".to_string(),
+            phase: OnionPhase::Rendezvous,
+            descriptor_cached: true,
+        });
+        let messages = translate_network_event(NetworkEvent::Failed {
+            context: 1,
+            error_class: ErrorClass::of(&error),
+        });
+        assert!(matches!(
+            &messages[1],
+            UiMessage::ShowError(message) if message.contains("onion service")
+        ));
+        let Some(UiMessage::OfferRetry(prompt)) = translate_retry_offer(&error) else {
+            panic!("onion timeouts offer a retry");
+        };
+        assert_eq!(prompt.url, "https://abcxyz.onion/");
+        assert!(prompt.reuse_descriptor);
+
+        // Clearnet timeouts do not
+        assert!(translate_retry_offer(&NetworkError::Timeout).is_none());
+    }
+
+    #[test]
+    fn test_proxy_auth_warning() {
+        let messages = translate_network_event(NetworkEvent::Failed {
+            context: 1,
+            error_class: ErrorClass::of(&NetworkError::ProxyAuthRequired),
+        });
+        assert!(matches!(
+            &messages[..],
+            [
+                UiMessage::SecurityChanged(SecurityIndicator::Error),
+                UiMessage::SecurityWarning(warning),
+            ] if warning.contains("intercepted")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_event_demux_is_owned_by_the_session() {
+        let tasks = TaskRegistry::new();
+        let (events, rx) = broadcast::channel(8);
+        let (ui, mut messages) = mpsc::channel(8);
+        spawn_event_demux(&tasks, rx, ui, Arc::new(SessionStats::new()));
+
+        events
+            .send(NetworkEvent::RequestStarted {
+                context: 1,
+                destination: Destination::Document,
+            })
+            .expect("demux subscribed");
+        assert!(matches!(
+            messages.recv().await,
+            Some(UiMessage::LoadProgress(0))
+        ));
+        assert_eq!(tasks.live_tasks(TaskScope::Session), vec!["event demux"]);
+
+        // The network outlives the UI here: the sender stays open
+        tasks.cancel_scope(TaskScope::Session).await;
+        tasks.assert_no_leaks(TaskScope::Session).await;
+        assert!(messages.recv().await.is_none());
+        drop(events);
+    }
+
+    #[tokio::test]
+    async fn test_event_demux_ends_with_the_ui() {
+        let tasks = TaskRegistry::new();
+        let (events, rx) = broadcast::channel(8);
+        let (ui, messages) = mpsc::channel(8);
+        spawn_event_demux(&tasks, rx, ui, Arc::new(SessionStats::new()));
+        drop(messages);
+
+        let _ = events.send(NetworkEvent::RequestStarted {
+            context: 1,
+            destination: Destination::Document,
+        });
+        tasks.assert_no_leaks(TaskScope::Session).await;
+    }
+
+    #[test]
+    fn test_download() {
+        let mut response = NetworkResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: b"<html></html>".to_vec(),
+            circuit_id: "c".to_string(),
+            html_report: None,
+            download: None,
+            metrics: Default::default(),
+            timing: None,
+            redirects: Default::default(),
+        };
+        assert!(translate_download(&response).is_none());
+
+        response.body.clear();
+        response.download = Some("/dev/shm/forloop-downloads/report.pdf".into());
+        let Some(UiMessage::PdfDownloaded(path)) = translate_download(&response) else {
+            panic!("expected PdfDownloaded");
+        };
+        assert_eq!(path, "/dev/shm/forloop-downloads/report.pdf");
+    }
+
+    #[test]
+    fn test_bootstrap() {
+        let mut progress = BootstrapProgress {
+            percent: 45,
+            ..Default::default()
+        };
+        let status = |progress: &BootstrapProgress| {
+            let UiMessage::TorStatusChanged(status) = translate_bootstrap(progress) else {
+                panic!("expected TorStatusChanged");
+            };
+            status
+        };
+        assert_eq!(status(&progress), TorStatus::Bootstrapping(45));
+        progress.stalled = Some("Connection refused".to_string());
+        assert_eq!(
+            status(&progress),
+            TorStatus::Stalled(45, "Connection refused".to_string())
+        );
+        progress.percent = 100;
+        progress.stalled = None;
+        assert_eq!(status(&progress), TorStatus::Connected);
+    }
+}
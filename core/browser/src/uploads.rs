@@ -0,0 +1,268 @@
+//! Mediation between the file picker and the content process.
+//!
+//! Files the user picks are never handed to a page as they are on disk.
+//! Before anything crosses into the content process:
+//!
+//! - images have their metadata stripped (see `metadata`); images that
+//!   cannot be cleaned are blocked rather than sent as they are
+//! - every file is renamed to `upload-<n>.<ext>`, numbered from 1 within
+//!   the pick, so the page never sees the local filename
+//! - modification times are dropped; `UploadFile` has no field for them,
+//!   so the content process reports the epoch as `lastModified`
+//!
+//! When anything was removed or blocked, the user confirms a summary of
+//! the changes before the page receives the files.
+
+use std::time::SystemTime;
+
+use crate::metadata::{strip_metadata, MetadataError, RemovedMetadata};
+
+/// Longest original extension carried over to a renamed upload.
+const MAX_EXTENSION_LEN: usize = 8;
+
+/// A file as returned by the file picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickedFile {
+    /// Local filename, without the directory
+    pub name: String,
+    /// File contents
+    pub bytes: Vec<u8>,
+    /// Local modification time, never forwarded
+    pub modified: Option<SystemTime>,
+}
+
+/// A file as exposed to the content process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadFile {
+    /// Generic name, e.g. `upload-1.jpg`
+    pub name: String,
+    /// Contents, with metadata removed
+    pub bytes: Vec<u8>,
+}
+
+/// What mediation did to one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOutcome {
+    /// Sent as picked, apart from the name
+    Unchanged,
+    /// Sent with metadata removed
+    Stripped(RemovedMetadata),
+    /// Not sent
+    Blocked(MetadataError),
+}
+
+/// One line of the confirmation summary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReview {
+    /// Local filename, shown to the user only
+    pub picked_name: String,
+    /// Name the page will see
+    pub upload_name: String,
+    /// What was done
+    pub outcome: FileOutcome,
+}
+
+impl FileReview {
+    /// Describe the outcome in one sentence.
+    pub fn summary(&self) -> String {
+        match &self.outcome {
+            FileOutcome::Unchanged => {
+                format!("{} will be sent as {}", self.picked_name, self.upload_name)
+            }
+            FileOutcome::Stripped(removed) => format!(
+                "{} will be sent as {} without: {}",
+                self.picked_name,
+                self.upload_name,
+                removed.descriptions().join(", ")
+            ),
+            FileOutcome::Blocked(reason) => {
+                format!("{} will not be sent: {}", self.picked_name, reason)
+            }
+        }
+    }
+}
+
+/// Confirmation dialog shown before the upload is released.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadConfirmation {
+    /// Dialog title
+    pub title: String,
+    /// One line per file, then a note about file dates
+    pub lines: Vec<String>,
+    /// Whether any file can be sent (otherwise only "Cancel" is offered)
+    pub can_upload: bool,
+}
+
+/// Files waiting for the user's confirmation.
+#[derive(Debug)]
+pub struct PendingUpload {
+    reviews: Vec<FileReview>,
+    files: Vec<UploadFile>,
+}
+
+impl PendingUpload {
+    /// Get what was done to each picked file, in pick order.
+    pub fn reviews(&self) -> &[FileReview] {
+        &self.reviews
+    }
+
+    /// Whether the user must see the summary before the page gets the files.
+    pub fn needs_confirmation(&self) -> bool {
+        self.reviews
+            .iter()
+            .any(|review| review.outcome != FileOutcome::Unchanged)
+    }
+
+    /// Build the confirmation dialog.
+    pub fn confirmation(&self) -> UploadConfirmation {
+        let mut lines: Vec<String> = self.reviews.iter().map(FileReview::summary).collect();
+        lines.push("File names and dates are never shared with the page.".to_string());
+        UploadConfirmation {
+            title: "Review upload".to_string(),
+            lines,
+            can_upload: !self.files.is_empty(),
+        }
+    }
+
+    /// Release the files that may be sent. Blocked files are left out.
+    pub fn confirm(self) -> Vec<UploadFile> {
+        self.files
+    }
+}
+
+/// Inspect, clean and rename a file picker result.
+pub fn mediate_upload(picked: Vec<PickedFile>) -> PendingUpload {
+    let mut reviews = Vec::with_capacity(picked.len());
+    let mut files = Vec::with_capacity(picked.len());
+
+    for (index, file) in picked.into_iter().enumerate() {
+        let stem = format!("upload-{}", index + 1);
+        let (upload_name, outcome, bytes) = match strip_metadata(&file.bytes) {
+            Ok(Some((format, clean, removed))) => {
+                let outcome = if removed.is_empty() {
+                    FileOutcome::Unchanged
+                } else {
+                    FileOutcome::Stripped(removed)
+                };
+                (
+                    format!("{}.{}", stem, format.extension()),
+                    outcome,
+                    Some(clean),
+                )
+            }
+            Ok(None) => {
+                let name = match extension_of(&file.name) {
+                    Some(ext) => format!("{}.{}", stem, ext),
+                    None => stem,
+                };
+                (name, FileOutcome::Unchanged, Some(file.bytes))
+            }
+            Err(reason) => (stem, FileOutcome::Blocked(reason), None),
+        };
+
+        if let Some(bytes) = bytes {
+            files.push(UploadFile {
+                name: upload_name.clone(),
+                bytes,
+            });
+        }
+        reviews.push(FileReview {
+            picked_name: file.name,
+            upload_name,
+            outcome,
+        });
+    }
+
+    PendingUpload { reviews, files }
+}
+
+/// Get a short alphanumeric extension from `name`, lowercased.
+fn extension_of(name: &str) -> Option<String> {
+    let (_, ext) = name.rsplit_once('.')?;
+    (!ext.is_empty()
+        && ext.len() <= MAX_EXTENSION_LEN
+        && ext.bytes().all(|b| b.is_ascii_alphanumeric()))
+    .then(|| ext.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ImageFormat;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/uploads")
+            .join(name);
+        std::fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    fn picked(name: &str, bytes: Vec<u8>) -> PickedFile {
+        PickedFile {
+            name: name.to_string(),
+            bytes,
+            modified: Some(SystemTime::UNIX_EPOCH),
+        }
+    }
+
+    #[test]
+    fn test_photos_cleaned_and_renamed() {
+        let pending = mediate_upload(vec![
+            picked("IMG_20261015_093000.JPG", fixture("gps.jpg")),
+            picked("Screenshot from Tuesday.png", fixture("gps.png")),
+            picked("Jane Doe CV.PDF", b"%PDF-1.7".to_vec()),
+            picked("README", b"notes".to_vec()),
+        ]);
+        assert!(pending.needs_confirmation());
+
+        let confirmation = pending.confirmation();
+        assert!(confirmation.can_upload);
+        assert_eq!(
+            confirmation.lines[0],
+            "IMG_20261015_093000.JPG will be sent as upload-1.jpg without: Location (GPS), \
+             Camera make and model, EXIF data, XMP data, Comments and text"
+        );
+        assert_eq!(
+            confirmation.lines[2],
+            "Jane Doe CV.PDF will be sent as upload-3.pdf"
+        );
+
+        let files = pending.confirm();
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["upload-1.jpg", "upload-2.png", "upload-3.pdf", "upload-4"]
+        );
+        assert_eq!(files[0].bytes, fixture("gps.clean.jpg"));
+        assert_eq!(files[1].bytes, fixture("gps.clean.png"));
+        assert_eq!(files[2].bytes, b"%PDF-1.7");
+    }
+
+    #[test]
+    fn test_uncleanable_images_blocked() {
+        let jpeg = fixture("gps.jpg");
+        let pending = mediate_upload(vec![
+            picked("scan.tiff", b"MM\0*\0\0\0\x08".to_vec()),
+            picked("cut.jpg", jpeg[..jpeg.len() / 2].to_vec()),
+        ]);
+        assert_eq!(
+            pending.reviews()[0].outcome,
+            FileOutcome::Blocked(MetadataError::Unsupported(ImageFormat::Tiff))
+        );
+
+        let confirmation = pending.confirmation();
+        assert!(!confirmation.can_upload);
+        assert_eq!(
+            confirmation.lines[..2],
+            [
+                "scan.tiff will not be sent: metadata cannot be removed from TIFF images",
+                "cut.jpg will not be sent: damaged JPEG image",
+            ]
+        );
+        assert!(pending.confirm().is_empty());
+
+        // Nothing to report for a clean image
+        let pending = mediate_upload(vec![picked("a.png", fixture("gps.clean.png"))]);
+        assert!(!pending.needs_confirmation());
+    }
+}
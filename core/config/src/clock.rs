@@ -0,0 +1,187 @@
+//! Clock abstraction for time-dependent components.
+//!
+//! Jitter, timing defenses and retry backoff read the time and sleep
+//! through a `Clock` so tests can drive them without waiting. Production
+//! code uses `SystemClock`, which follows tokio's clock: under
+//! `tokio::time::pause` it is paused too. `ManualClock` only moves when a
+//! test advances it.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Future returned by `Clock::sleep`.
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Source of time and delays.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Current monotonic time.
+    fn now(&self) -> Instant;
+
+    /// Wait for `duration`.
+    fn sleep(&self, duration: Duration) -> Sleep;
+
+    /// Block the current thread for `duration` (non-async callers).
+    fn sleep_blocking(&self, duration: Duration);
+}
+
+/// The real clock, over `tokio::time` and `std::thread`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn sleep_blocking(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Get a shared handle to the real clock.
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[cfg(any(test, feature = "test-support"))]
+pub use manual::ManualClock;
+
+#[cfg(any(test, feature = "test-support"))]
+mod manual {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::time::{Duration, Instant};
+
+    use super::{Clock, Sleep};
+
+    #[derive(Debug)]
+    struct State {
+        start: Instant,
+        elapsed: Duration,
+        waiters: Vec<Waker>,
+    }
+
+    /// Test clock that only moves when advanced.
+    ///
+    /// Only compiled for tests, or for other crates with the
+    /// `test-support` feature.
+    #[derive(Debug, Clone)]
+    pub struct ManualClock {
+        state: Arc<Mutex<State>>,
+    }
+
+    impl Default for ManualClock {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl ManualClock {
+        /// Create a clock stopped at the current instant.
+        pub fn new() -> Self {
+            Self {
+                state: Arc::new(Mutex::new(State {
+                    start: Instant::now(),
+                    elapsed: Duration::ZERO,
+                    waiters: Vec::new(),
+                })),
+            }
+        }
+
+        /// Move time forward, waking sleepers whose deadline has passed.
+        pub fn advance(&self, by: Duration) {
+            let waiters = {
+                let mut state = self.state.lock().expect("clock lock");
+                state.elapsed += by;
+                std::mem::take(&mut state.waiters)
+            };
+            // Sleepers that are still early re-register when polled
+            waiters.into_iter().for_each(Waker::wake);
+        }
+
+        /// Time advanced since creation.
+        pub fn elapsed(&self) -> Duration {
+            self.state.lock().expect("clock lock").elapsed
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            let state = self.state.lock().expect("clock lock");
+            state.start + state.elapsed
+        }
+
+        fn sleep(&self, duration: Duration) -> Sleep {
+            Box::pin(ManualSleep {
+                state: Arc::clone(&self.state),
+                deadline: self.elapsed() + duration,
+            })
+        }
+
+        /// Blocking sleeps complete at once and advance the clock.
+        fn sleep_blocking(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    struct ManualSleep {
+        state: Arc<Mutex<State>>,
+        deadline: Duration,
+    }
+
+    impl Future for ManualSleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.state.lock().expect("clock lock");
+            if state.elapsed >= self.deadline {
+                Poll::Ready(())
+            } else {
+                state.waiters.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_manual_clock_sleep_waits_for_advance() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        let sleeper = tokio::spawn(clock.sleep(Duration::from_secs(30)));
+        tokio::task::yield_now().await;
+
+        clock.advance(Duration::from_secs(10));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+
+        clock.advance(Duration::from_secs(20));
+        sleeper.await.expect("sleep completes");
+        assert_eq!(clock.now() - start, Duration::from_secs(30));
+
+        clock.sleep_blocking(Duration::from_secs(5));
+        assert_eq!(clock.elapsed(), Duration::from_secs(35));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_system_clock_follows_paused_tokio_time() {
+        let clock = SystemClock;
+        let start = clock.now();
+        clock.sleep(Duration::from_secs(3600)).await;
+        assert!(clock.now() - start >= Duration::from_secs(3600));
+    }
+}
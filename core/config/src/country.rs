@@ -0,0 +1,75 @@
+//! ISO 3166-1 alpha-2 country codes.
+//!
+//! Tor selects relays by country with `{cc}` entries in its node lists,
+//! and quietly matches nothing for a code it does not know. Codes are
+//! therefore checked against the assigned set before they get that far.
+
+use std::fmt;
+
+/// Every officially assigned ISO 3166-1 alpha-2 code, sorted.
+pub const COUNTRY_CODES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ",
+    "BA", "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS",
+    "BT", "BV", "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN",
+    "CO", "CR", "CU", "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE",
+    "EG", "EH", "ER", "ES", "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF",
+    "GG", "GH", "GI", "GL", "GM", "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM",
+    "HN", "HR", "HT", "HU", "ID", "IE", "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM",
+    "JO", "JP", "KE", "KG", "KH", "KI", "KM", "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC",
+    "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY", "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK",
+    "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT", "MU", "MV", "MW", "MX", "MY", "MZ", "NA",
+    "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU", "NZ", "OM", "PA", "PE", "PF", "PG",
+    "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA", "RE", "RO", "RS", "RU", "RW",
+    "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM", "SN", "SO", "SR", "SS",
+    "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL", "TM", "TN", "TO",
+    "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE", "VG", "VI",
+    "VN", "VU", "WF", "WS", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+/// A country code that is not an assigned ISO 3166-1 alpha-2 code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCountryCode(pub String);
+
+impl fmt::Display for InvalidCountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is not an ISO 3166-1 alpha-2 country code (e.g. DE, US)",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidCountryCode {}
+
+/// Check a country code, in either case, and get it in upper case.
+pub fn parse_country_code(code: &str) -> Result<String, InvalidCountryCode> {
+    let upper = code.to_ascii_uppercase();
+    if COUNTRY_CODES.binary_search(&upper.as_str()).is_ok() {
+        Ok(upper)
+    } else {
+        Err(InvalidCountryCode(code.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_country_codes() {
+        assert_eq!(COUNTRY_CODES.len(), 249);
+        assert!(COUNTRY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert_eq!(parse_country_code("de"), Ok("DE".to_string()));
+        assert_eq!(parse_country_code("US"), Ok("US".to_string()));
+        for invalid in ["", "D", "DEU", "XX", "UK", "??", "d3", "{us}", "ü"] {
+            assert_eq!(
+                parse_country_code(invalid),
+                Err(InvalidCountryCode(invalid.to_string())),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
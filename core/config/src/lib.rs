@@ -3,7 +3,29 @@
 //! This module handles command-line arguments and secure-by-default configuration.
 //! There are intentionally NO options to weaken privacy guarantees.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub mod clock;
+pub mod country;
+pub mod navigation;
+#[cfg(any(test, feature = "test-support"))]
+pub mod tripwire;
+pub mod units;
+
+pub use clock::{system_clock, Clock, SystemClock};
+pub use country::{parse_country_code, InvalidCountryCode, COUNTRY_CODES};
+pub use navigation::NavigationKind;
+pub use units::{ByteSize, Port};
+
+/// Rendering engine reported by `--version`.
+pub const ENGINE: &str = "Gecko (Firefox ESR 128)";
+
+/// Granularity of every clock content can read (Tor Browser uses 100ms).
+///
+/// Shared by the timing API defense and the content sandbox's clock
+/// broker so script and native code see the same resolution.
+pub const TIMING_PRECISION: Duration = Duration::from_millis(100);
 
 /// forloop command-line interface.
 #[derive(Debug)]
@@ -18,10 +40,18 @@ pub struct ForloopCli {
     pub use_bridges: bool,
     /// Custom bridge lines
     pub bridges: Vec<String>,
+    /// Use the built-in Snowflake bridges
+    pub use_snowflake: bool,
+    /// Countries no exit relay may be in, as upper-case ISO codes
+    pub exclude_exit_countries: Vec<String>,
     /// Verbose logging (to stderr only)
     pub verbose: bool,
     /// Print version and exit
     pub version: bool,
+    /// Print `--version` output as JSON
+    pub json: bool,
+    /// Socket path for the research automation server (off unless given)
+    pub automation_socket: Option<PathBuf>,
     /// Print help and exit
     pub help: bool,
 }
@@ -33,15 +63,31 @@ impl ForloopCli {
         Self::parse_args(&args)
     }
 
-    fn parse_args(args: &[String]) -> Self {
+    /// Parse an argument list (`args[0]` is the program name).
+    ///
+    /// Exits the process if an argument is invalid, so forloop never
+    /// starts with a setting other than the one asked for.
+    pub fn parse_args(args: &[String]) -> Self {
+        Self::try_parse_args(args).unwrap_or_else(|e| {
+            eprintln!("forloop: {}", e);
+            std::process::exit(2);
+        })
+    }
+
+    /// Parse an argument list, failing on an invalid argument.
+    pub fn try_parse_args(args: &[String]) -> Result<Self, InvalidCountryCode> {
         let mut cli = Self {
             url: None,
             new_loop: false,
             kill_all_state: false,
             use_bridges: false,
             bridges: Vec::new(),
+            use_snowflake: false,
+            exclude_exit_countries: Vec::new(),
             verbose: false,
             version: false,
+            json: false,
+            automation_socket: None,
             help: false,
         };
 
@@ -63,12 +109,33 @@ impl ForloopCli {
                         cli.bridges.push(args[i].clone());
                     }
                 }
+                "--use-snowflake" => {
+                    cli.use_snowflake = true;
+                }
+                "--exclude-exit" => {
+                    i += 1;
+                    if i < args.len() {
+                        let country = parse_country_code(&args[i])?;
+                        if !cli.exclude_exit_countries.contains(&country) {
+                            cli.exclude_exit_countries.push(country);
+                        }
+                    }
+                }
                 "--verbose" | "-v" => {
                     cli.verbose = true;
                 }
                 "--version" | "-V" => {
                     cli.version = true;
                 }
+                "--json" => {
+                    cli.json = true;
+                }
+                "--automation-socket" => {
+                    i += 1;
+                    if i < args.len() {
+                        cli.automation_socket = Some(PathBuf::from(&args[i]));
+                    }
+                }
                 "--help" | "-h" => {
                     cli.help = true;
                 }
@@ -83,7 +150,7 @@ impl ForloopCli {
             i += 1;
         }
 
-        cli
+        Ok(cli)
     }
 
     /// Print help message.
@@ -102,8 +169,20 @@ OPTIONS:
     -k, --kill-all-state    Securely wipe all temporary data and exit
         --use-bridges       Use Tor bridges for censorship circumvention
         --bridge <BRIDGE>   Specify a bridge line (can be repeated)
+        --use-snowflake     Use the built-in Snowflake bridges, no bridge
+                            lines needed (needs snowflake-client)
+        --exclude-exit <CC> Never exit through a relay in this country, given
+                            as an ISO 3166-1 alpha-2 code such as DE (can be
+                            repeated). Each country removed shrinks the pool
+                            of exits: your traffic is easier to correlate and
+                            your choice of exits sets you apart from other
+                            Tor users. Use only when you must
     -v, --verbose           Enable verbose logging to stderr
     -V, --version           Print version information
+        --json              With --version, print machine-readable JSON
+        --automation-socket <PATH>
+                            Research only: accept automation commands on a
+                            UNIX socket. Never use for normal browsing
     -h, --help              Print this help message
 
 NOTES:
@@ -116,6 +195,7 @@ EXAMPLES:
     forloop https://example.onion   Open a specific URL
     forloop --kill-all-state        Wipe temp data and exit
     forloop --use-bridges           Use bridges in censored regions
+    forloop --use-snowflake         Use Snowflake where bridges are blocked
 
 PHILOSOPHY:
     Stateless by design.
@@ -128,7 +208,7 @@ PHILOSOPHY:
     /// Print version.
     pub fn print_version() {
         println!("forloop {}", env!("CARGO_PKG_VERSION"));
-        println!("Engine: Gecko (Firefox ESR 128)");
+        println!("Engine: {}", ENGINE);
         println!("Tor: Embedded");
         println!();
         println!("Motto: Every request is the first.");
@@ -141,17 +221,17 @@ PHILOSOPHY:
 pub struct ForloopConfig {
     // Network settings
     /// Tor SOCKS port
-    pub tor_socks_port: u16,
+    pub tor_socks_port: Port,
     /// Tor control port
-    pub tor_control_port: u16,
+    pub tor_control_port: Port,
     /// Create new circuit per request
     pub new_circuit_per_request: bool,
-    /// Request timeout in seconds
-    pub request_timeout_secs: u64,
+    /// Time allowed for a whole request
+    pub request_timeout: Duration,
 
     // Fingerprint settings
-    /// Timing precision in milliseconds
-    pub timing_precision_ms: u64,
+    /// Granularity of timing APIs exposed to content
+    pub timing_precision: Duration,
     /// Screen size bucket to use
     pub screen_bucket: ScreenBucket,
 
@@ -195,13 +275,13 @@ impl Default for ForloopConfig {
     fn default() -> Self {
         Self {
             // Network
-            tor_socks_port: 9150,
-            tor_control_port: 9151,
+            tor_socks_port: Port::new(9150),
+            tor_control_port: Port::new(9151),
             new_circuit_per_request: true,
-            request_timeout_secs: 60,
+            request_timeout: Duration::from_secs(60),
 
             // Fingerprint
-            timing_precision_ms: 100,
+            timing_precision: TIMING_PRECISION,
             screen_bucket: ScreenBucket {
                 width: 1920,
                 height: 1080,
@@ -232,11 +312,11 @@ impl ForloopConfig {
     /// This returns compiled-in defaults that cannot be modified.
     pub fn get() -> &'static Self {
         static CONFIG: ForloopConfig = ForloopConfig {
-            tor_socks_port: 9150,
-            tor_control_port: 9151,
+            tor_socks_port: Port::new(9150),
+            tor_control_port: Port::new(9151),
             new_circuit_per_request: true,
-            request_timeout_secs: 60,
-            timing_precision_ms: 100,
+            request_timeout: Duration::from_secs(60),
+            timing_precision: TIMING_PRECISION,
             screen_bucket: ScreenBucket {
                 width: 1920,
                 height: 1080,
@@ -287,64 +367,92 @@ impl ForloopConfig {
     }
 }
 
-/// Temporary directory for downloads (RAM-backed).
-pub fn get_temp_download_dir() -> PathBuf {
-    // Use RAM-backed tmpfs on Linux
-    #[cfg(target_os = "linux")]
-    {
-        PathBuf::from("/dev/shm/forloop-downloads")
+/// Every filesystem location forloop may write to.
+///
+/// All writes must land under the RAM-backed root. Components take their
+/// paths from here rather than hardcoding them, so tests can redirect
+/// everything into a tracked directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatePaths {
+    /// RAM-backed root for all transient state
+    ram_root: PathBuf,
+}
+
+impl StatePaths {
+    /// Get the paths used in production.
+    pub fn system() -> Self {
+        // Use RAM-backed tmpfs on Linux
+        #[cfg(target_os = "linux")]
+        let ram_root = PathBuf::from("/dev/shm");
+
+        #[cfg(not(target_os = "linux"))]
+        let ram_root = std::env::temp_dir();
+
+        Self { ram_root }
     }
 
-    #[cfg(not(target_os = "linux"))]
-    {
-        std::env::temp_dir().join("forloop-downloads")
+    /// Create paths rooted at a specific RAM-backed directory.
+    pub fn with_ram_root(ram_root: impl Into<PathBuf>) -> Self {
+        Self {
+            ram_root: ram_root.into(),
+        }
     }
+
+    /// Get the RAM-backed root.
+    pub fn ram_root(&self) -> &Path {
+        &self.ram_root
+    }
+
+    /// Temporary directory for downloads.
+    pub fn download_dir(&self) -> PathBuf {
+        self.ram_root.join("forloop-downloads")
+    }
+
+    /// Data directory of the tor daemon.
+    pub fn tor_data_dir(&self) -> PathBuf {
+        self.ram_root.join("forloop-tor")
+    }
+}
+
+/// Temporary directory for downloads (RAM-backed).
+pub fn get_temp_download_dir() -> PathBuf {
+    StatePaths::system().download_dir()
 }
 
 /// Securely wipe all temporary data.
 pub fn kill_all_state() -> std::io::Result<()> {
-    let temp_dir = get_temp_download_dir();
+    kill_all_state_in(&StatePaths::system())
+}
 
-    if temp_dir.exists() {
-        // Overwrite files before deleting
-        secure_delete_dir(&temp_dir)?;
+/// Securely wipe all temporary data under the given paths.
+pub fn kill_all_state_in(paths: &StatePaths) -> std::io::Result<()> {
+    for dir in [paths.download_dir(), paths.tor_data_dir()] {
+        if dir.exists() {
+            // Overwrite files before deleting
+            secure_delete_dir(&dir)?;
+        }
     }
 
-    // Clear any other temporary state
-    // (In a full implementation, this would wipe Tor state, etc.)
-
     Ok(())
 }
 
 /// Securely delete a directory by overwriting files first.
-fn secure_delete_dir(path: &PathBuf) -> std::io::Result<()> {
+///
+/// Sockets and symlinks hold no data of their own and are just removed;
+/// a symlink is never followed.
+pub fn secure_delete_dir(path: &Path) -> std::io::Result<()> {
     use std::fs;
-    use std::io::Write;
 
     for entry in fs::read_dir(path)? {
         let entry = entry?;
         let path = entry.path();
+        let file_type = entry.file_type()?;
 
-        if path.is_dir() {
+        if file_type.is_dir() {
             secure_delete_dir(&path)?;
+        } else if file_type.is_file() {
+            secure_delete_file(&path)?;
         } else {
-            // Overwrite file with zeros
-            let len = fs::metadata(&path)?.len();
-            let mut file = fs::OpenOptions::new().write(true).open(&path)?;
-
-            let zeros = vec![0u8; 4096];
-            let mut remaining = len as usize;
-
-            while remaining > 0 {
-                let to_write = remaining.min(zeros.len());
-                file.write_all(&zeros[..to_write])?;
-                remaining -= to_write;
-            }
-
-            file.sync_all()?;
-            drop(file);
-
-            // Now delete
             fs::remove_file(&path)?;
         }
     }
@@ -353,6 +461,30 @@ fn secure_delete_dir(path: &PathBuf) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Securely delete one file by overwriting it with zeros first.
+pub fn secure_delete_file(path: &Path) -> std::io::Result<()> {
+    use std::fs;
+    use std::io::Write;
+
+    let len = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+
+    let zeros = vec![0u8; 4096];
+    let mut remaining = len as usize;
+
+    while remaining > 0 {
+        let to_write = remaining.min(zeros.len());
+        file.write_all(&zeros[..to_write])?;
+        remaining -= to_write;
+    }
+
+    file.sync_all()?;
+    drop(file);
+
+    // Now delete
+    fs::remove_file(path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -382,6 +514,65 @@ mod tests {
         let cli = ForloopCli::parse_args(&args);
         assert!(cli.use_bridges);
         assert_eq!(cli.bridges.len(), 1);
+        assert!(!cli.use_snowflake);
+
+        let args = ["forloop", "--use-snowflake", "--bridge", "192.0.2.1:443"].map(String::from);
+        let cli = ForloopCli::parse_args(&args);
+        assert!(cli.use_snowflake);
+        assert_eq!(cli.bridges, ["192.0.2.1:443"]);
+    }
+
+    #[test]
+    fn test_cli_version_json() {
+        let args = vec![
+            "forloop".to_string(),
+            "--version".to_string(),
+            "--json".to_string(),
+        ];
+
+        let cli = ForloopCli::parse_args(&args);
+        assert!(cli.version && cli.json);
+    }
+
+    #[test]
+    fn test_cli_automation_socket() {
+        let cli = ForloopCli::parse_args(&["forloop".to_string()]);
+        assert!(cli.automation_socket.is_none());
+
+        let args = vec![
+            "forloop".to_string(),
+            "--automation-socket".to_string(),
+            "/run/user/1000/forloop.sock".to_string(),
+        ];
+        let cli = ForloopCli::parse_args(&args);
+        assert_eq!(
+            cli.automation_socket,
+            Some(PathBuf::from("/run/user/1000/forloop.sock"))
+        );
+    }
+
+    #[test]
+    fn test_cli_exclude_exit() {
+        let args = [
+            "forloop",
+            "--exclude-exit",
+            "de",
+            "--exclude-exit",
+            "US",
+            "--exclude-exit",
+            "DE",
+        ]
+        .map(String::from);
+        let cli = ForloopCli::try_parse_args(&args).expect("valid codes");
+        assert_eq!(cli.exclude_exit_countries, ["DE", "US"]);
+
+        for invalid in ["XX", "DEU", "uk", ""] {
+            let args = ["forloop", "--exclude-exit", invalid].map(String::from);
+            assert_eq!(
+                ForloopCli::try_parse_args(&args).map(|cli| cli.exclude_exit_countries),
+                Err(InvalidCountryCode(invalid.to_string()))
+            );
+        }
     }
 
     #[test]
@@ -394,6 +585,13 @@ mod tests {
         assert!(!config.webrtc_enabled);
         assert!(!config.telemetry_enabled);
         assert!(config.new_circuit_per_request);
+
+        // The compiled-in singleton carries the same values
+        let compiled = ForloopConfig::get();
+        assert_eq!(compiled.tor_socks_port, Port::new(9150));
+        assert_eq!(compiled.tor_control_port, config.tor_control_port);
+        assert_eq!(compiled.request_timeout, Duration::from_secs(60));
+        assert_eq!(compiled.timing_precision, config.timing_precision);
     }
 
     #[test]
@@ -405,8 +603,23 @@ mod tests {
     #[test]
     #[should_panic(expected = "Cookies must be disabled")]
     fn test_config_verification_fails_on_cookies() {
-        let mut config = ForloopConfig::default();
-        config.cookies_enabled = true;
+        let config = ForloopConfig {
+            cookies_enabled: true,
+            ..ForloopConfig::default()
+        };
         config.verify_secure();
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secure_delete_dir_removes_sockets() {
+        let dir = std::env::temp_dir().join(format!("forloop-wipe-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).expect("create dir");
+        std::fs::write(dir.join("nested").join("key"), b"secret").expect("write file");
+        let _listener =
+            std::os::unix::net::UnixListener::bind(dir.join("control.sock")).expect("bind");
+
+        secure_delete_dir(&dir).expect("wiped");
+        assert!(!dir.exists());
+    }
 }
@@ -0,0 +1,26 @@
+//! How a top-level navigation was started.
+//!
+//! The UI knows how the user got to a page and the network layer needs it
+//! to pick the Sec-Fetch-* values Firefox would send, so the kind travels
+//! with the navigation from one to the other.
+
+/// What started a top-level navigation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NavigationKind {
+    /// URL typed or pasted into the address bar
+    #[default]
+    AddressBar,
+    /// Link clicked in a page
+    LinkClick,
+    /// Reload button or shortcut
+    Reload,
+    /// Back or forward
+    HistoryTraversal,
+}
+
+impl NavigationKind {
+    /// Whether the browser chrome, not a page, started the navigation.
+    pub fn is_browser_initiated(self) -> bool {
+        !matches!(self, NavigationKind::LinkClick)
+    }
+}
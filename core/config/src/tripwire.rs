@@ -0,0 +1,198 @@
+//! Filesystem write tripwire for "nothing is stored between sessions".
+//!
+//! `FsTripwire` builds a throwaway HOME/XDG tree plus a RAM root under a
+//! tracked temp directory. Tests point every configurable path at it (via
+//! `state_paths()` and `env()`), run a pipeline, then scan the tree: any
+//! file or directory that appeared outside the RAM root is a persistence
+//! leak.
+//!
+//! Only compiled for tests, or for other crates with the `test-support`
+//! feature.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::StatePaths;
+
+/// Distinguishes tripwires created by one process.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Directories a desktop process may be tempted to write into.
+const XDG_DIRS: &[(&str, &str)] = &[
+    ("XDG_CONFIG_HOME", "home/.config"),
+    ("XDG_CACHE_HOME", "home/.cache"),
+    ("XDG_DATA_HOME", "home/.local/share"),
+    ("XDG_STATE_HOME", "home/.local/state"),
+];
+
+/// Tracked temporary environment that detects stray writes.
+#[derive(Debug)]
+pub struct FsTripwire {
+    /// Root of the tracked tree
+    root: PathBuf,
+    /// Paths writes may land under
+    allowed: Vec<PathBuf>,
+    /// Paths present when the tripwire was armed
+    baseline: HashSet<PathBuf>,
+}
+
+impl FsTripwire {
+    /// Create the tracked tree and record its initial contents.
+    pub fn new() -> io::Result<Self> {
+        let root = std::env::temp_dir().join(format!(
+            "forloop-tripwire-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        if root.exists() {
+            fs::remove_dir_all(&root)?;
+        }
+
+        fs::create_dir_all(root.join("home"))?;
+        fs::create_dir_all(root.join("ram"))?;
+        for (_, dir) in XDG_DIRS {
+            fs::create_dir_all(root.join(dir))?;
+        }
+
+        let allowed = vec![root.join("ram")];
+        let mut tripwire = Self {
+            root,
+            allowed,
+            baseline: HashSet::new(),
+        };
+        tripwire.baseline = tripwire.scan()?.into_iter().collect();
+
+        Ok(tripwire)
+    }
+
+    /// Get the root of the tracked tree.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Get the fake HOME directory.
+    pub fn home(&self) -> PathBuf {
+        self.root.join("home")
+    }
+
+    /// Get state paths rooted at the tracked RAM directory.
+    pub fn state_paths(&self) -> StatePaths {
+        StatePaths::with_ram_root(self.root.join("ram"))
+    }
+
+    /// Environment for child processes: HOME, XDG dirs and TMPDIR.
+    ///
+    /// Not applied to the current process, since tests run in parallel.
+    pub fn env(&self) -> Vec<(&'static str, PathBuf)> {
+        let mut env = vec![("HOME", self.home()), ("TMPDIR", self.root.join("ram"))];
+        env.extend(
+            XDG_DIRS
+                .iter()
+                .map(|(key, dir)| (*key, self.root.join(dir))),
+        );
+        env
+    }
+
+    /// Allow writes under an additional path.
+    pub fn allow(&mut self, path: impl Into<PathBuf>) {
+        self.allowed.push(path.into());
+    }
+
+    /// List paths written outside the allowed locations since creation.
+    pub fn unexpected_writes(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .scan()?
+            .into_iter()
+            .filter(|path| !self.baseline.contains(path))
+            .filter(|path| !self.allowed.iter().any(|allowed| path.starts_with(allowed)))
+            .collect())
+    }
+
+    /// List anything still present in the RAM root.
+    ///
+    /// Expected to be empty after New Loop or shutdown.
+    pub fn ram_leftovers(&self) -> io::Result<Vec<PathBuf>> {
+        let ram = self.root.join("ram");
+        Ok(self
+            .scan()?
+            .into_iter()
+            .filter(|path| path.starts_with(&ram) && *path != ram)
+            .collect())
+    }
+
+    /// Panic if anything was written outside the allowed locations.
+    pub fn assert_clean(&self) {
+        let writes = self
+            .unexpected_writes()
+            .expect("Failed to scan tripwire root");
+        assert!(
+            writes.is_empty(),
+            "Unexpected filesystem writes: {:?}",
+            writes
+        );
+    }
+
+    /// Recursively list every path under the root.
+    fn scan(&self) -> io::Result<Vec<PathBuf>> {
+        let mut found = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path.clone());
+                }
+                found.push(path);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+impl Drop for FsTripwire {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulate a completed download into the RAM directory.
+    fn download(paths: &StatePaths, name: &str, body: &[u8]) -> PathBuf {
+        let dir = paths.download_dir();
+        fs::create_dir_all(&dir).expect("Failed to create download dir");
+        let file = dir.join(name);
+        fs::write(&file, body).expect("Failed to write download");
+        file
+    }
+
+    #[test]
+    fn test_detects_write_outside_ram() {
+        let tripwire = FsTripwire::new().expect("Failed to create tripwire");
+        let (_, config_home) = tripwire
+            .env()
+            .into_iter()
+            .find(|(key, _)| *key == "XDG_CONFIG_HOME")
+            .expect("XDG_CONFIG_HOME set");
+
+        let leak = config_home.join("forloop").join("prefs.js");
+        fs::create_dir_all(leak.parent().expect("has parent")).expect("mkdir");
+        fs::write(&leak, b"user_pref").expect("write");
+
+        let writes = tripwire.unexpected_writes().expect("Failed to scan");
+        assert!(writes.contains(&leak));
+
+        download(&tripwire.state_paths(), "a.bin", b"x");
+        assert_eq!(
+            tripwire.unexpected_writes().expect("Failed to scan").len(),
+            writes.len()
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Unit-carrying wrappers for configuration values.
+//!
+//! Ports, sizes and times used to be bare integers, which made it easy to
+//! pass one where another was expected. Times are `std::time::Duration`;
+//! ports and byte counts get their own types, so a mix-up fails to compile:
+//!
+//! ```compile_fail
+//! use forloop_config::{ByteSize, Port};
+//!
+//! fn listen(port: Port) {}
+//! listen(ByteSize::kib(4));
+//! ```
+
+use std::fmt;
+
+/// A TCP port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Port(u16);
+
+impl Port {
+    /// Wrap a port number.
+    pub const fn new(port: u16) -> Self {
+        Self(port)
+    }
+
+    /// Get the port number.
+    pub const fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl From<u16> for Port {
+    fn from(port: u16) -> Self {
+        Self(port)
+    }
+}
+
+impl From<Port> for u16 {
+    fn from(port: Port) -> Self {
+        port.0
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A size in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ByteSize(usize);
+
+impl ByteSize {
+    /// A size of `n` bytes.
+    pub const fn bytes(n: usize) -> Self {
+        Self(n)
+    }
+
+    /// A size of `n` KiB.
+    pub const fn kib(n: usize) -> Self {
+        Self(n * 1024)
+    }
+
+    /// A size of `n` MiB.
+    pub const fn mib(n: usize) -> Self {
+        Self(n * 1024 * 1024)
+    }
+
+    /// Get the size in bytes.
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl From<usize> for ByteSize {
+    fn from(n: usize) -> Self {
+        Self(n)
+    }
+}
+
+impl From<ByteSize> for usize {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let port = Port::new(9150);
+        assert_eq!(u16::from(port), 9150);
+        assert_eq!(Port::from(9150), port);
+        assert_eq!(port.to_string(), "9150");
+
+        assert_eq!(ByteSize::kib(64).get(), 65536);
+        assert_eq!(ByteSize::mib(100), ByteSize::bytes(100 * 1024 * 1024));
+        assert_eq!(usize::from(ByteSize::from(512)), 512);
+        assert_eq!(ByteSize::bytes(2048).to_string(), "2048 bytes");
+    }
+}
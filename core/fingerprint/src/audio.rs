@@ -4,17 +4,42 @@
 //! We return deterministic noise to prevent fingerprinting while
 //! maintaining audio functionality.
 
+use crate::noise::{NoiseBudget, NoiseDomain};
+
+/// Noise stream for AnalyserNode reads.
+const STREAM_ANALYSER: u64 = 0;
+/// Stream for synthesized fingerprint data.
+const STREAM_FINGERPRINT: u64 = 1;
+/// Stream for the fake DynamicsCompressor output.
+const STREAM_COMPRESSOR: u64 = 2;
+
+/// Sample rate reported by spoofed AudioContexts.
+pub const DEFAULT_SAMPLE_RATE: f64 = 48000.0;
+
+/// Oscillator frequency used by the common offline fingerprint probe.
+const PROBE_FREQUENCY_HZ: f64 = 10000.0;
+
+/// Peak of a 10kHz triangle wave after Firefox's DynamicsCompressor with
+/// the probe's settings (threshold -50, knee 40, ratio 12). Gives a
+/// sum of |samples| over 4500..5000 of about 35.74, as real Firefox does.
+const COMPRESSED_PEAK: f64 = 0.143;
+
 /// Audio defense configuration.
 #[derive(Debug, Clone)]
 pub struct AudioDefense {
-    /// Seed for this identity
-    seed: u64,
+    /// Shared noise source for this identity
+    budget: NoiseBudget,
 }
 
 impl AudioDefense {
     /// Create a new audio defense.
     pub fn new(seed: u64) -> Self {
-        Self { seed }
+        Self::with_budget(NoiseBudget::new(seed))
+    }
+
+    /// Create an audio defense drawing from an identity's noise budget.
+    pub fn with_budget(budget: NoiseBudget) -> Self {
+        Self { budget }
     }
 
     /// Generate a deterministic audio fingerprint response.
@@ -22,36 +47,20 @@ impl AudioDefense {
     /// When a page tries to fingerprint via AudioContext, we return
     /// values from this function instead of real audio processing results.
     pub fn generate_fingerprint_data(&self, length: usize) -> Vec<f32> {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
         (0..length)
             .map(|i| {
-                let mut hasher = DefaultHasher::new();
-                self.seed.hash(&mut hasher);
-                i.hash(&mut hasher);
-                let hash = hasher.finish();
-
                 // Generate value between -1.0 and 1.0
-                ((hash as f64 / u64::MAX as f64) * 2.0 - 1.0) as f32
+                self.budget
+                    .unit(NoiseDomain::Audio, i as u64, STREAM_FINGERPRINT) as f32
             })
             .collect()
     }
 
     /// Apply noise to frequency data from AnalyserNode.
     pub fn apply_frequency_noise(&self, data: &mut [f32]) {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
         for (i, value) in data.iter_mut().enumerate() {
-            let mut hasher = DefaultHasher::new();
-            self.seed.hash(&mut hasher);
-            i.hash(&mut hasher);
-            let hash = hasher.finish();
-
             // Add subtle noise
-            let noise = ((hash as f64 / u64::MAX as f64) - 0.5) * 0.01;
-            *value += noise as f32;
+            *value += self.budget.audio_delta(i as u64, STREAM_ANALYSER);
         }
     }
 
@@ -64,7 +73,7 @@ impl AudioDefense {
     /// Get spoofed audio context properties.
     pub fn get_audio_context_properties(&self) -> AudioContextProperties {
         AudioContextProperties {
-            sample_rate: 48000.0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
             base_latency: 0.005333333333333333,
             output_latency: 0.016,
             max_channel_count: 2,
@@ -72,6 +81,47 @@ impl AudioDefense {
         }
     }
 
+    /// Produce the full channel data for an OfflineAudioContext render.
+    ///
+    /// # Interception contract
+    ///
+    /// When a page calls `OfflineAudioContext.startRendering()`, the real
+    /// render graph is not run. The returned promise (and the `complete`
+    /// event) resolve with an AudioBuffer of the context's length and
+    /// sample rate whose every channel holds the output of this function.
+    /// A `sample_rate` of zero or below falls back to the identity's
+    /// spoofed rate.
+    ///
+    /// The output models the standard probe (triangle oscillator through
+    /// a DynamicsCompressor) so the sum of samples has the magnitude real
+    /// Firefox produces, scaled by a small per-identity gain and perturbed
+    /// per sample from the identity's noise budget. Its first 128 samples
+    /// are what `fake_dynamics_compressor_output` returns.
+    pub fn render_offline_fingerprint(&self, length: usize, sample_rate: f64) -> Vec<f32> {
+        let sample_rate = if sample_rate > 0.0 {
+            sample_rate
+        } else {
+            self.get_audio_context_properties().sample_rate
+        };
+
+        // Per-identity compressor gain within +/- the budget amplitude
+        let gain = 1.0
+            + self
+                .budget
+                .unit(NoiseDomain::Audio, u64::MAX, STREAM_COMPRESSOR)
+                * self.budget.amplitude();
+        let step = PROBE_FREQUENCY_HZ / sample_rate;
+
+        (0..length)
+            .map(|i| {
+                let phase = (i as f64 * step).fract();
+                let triangle = 4.0 * (phase - 0.5).abs() - 1.0;
+                let sample = (triangle * COMPRESSED_PEAK * gain) as f32;
+                sample + self.budget.audio_delta(i as u64, STREAM_COMPRESSOR)
+            })
+            .collect()
+    }
+
     /// Check if an audio method should have noise applied.
     pub fn should_apply_noise(method: &str) -> bool {
         matches!(
@@ -104,22 +154,13 @@ pub struct AudioContextProperties {
 /// Generate a deterministic DynamicsCompressor fingerprint.
 ///
 /// The DynamicsCompressor is commonly used for fingerprinting.
-/// We return consistent but non-unique values.
+/// We return consistent but non-unique values: the head of the
+/// identity's offline render, so both probes agree.
 pub fn fake_dynamics_compressor_output(seed: u64) -> Vec<f32> {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-
     // Standard DynamicsCompressor output length
     let length = 128;
 
-    (0..length)
-        .map(|i| {
-            let mut hasher = DefaultHasher::new();
-            seed.hash(&mut hasher);
-            i.hash(&mut hasher);
-            (hasher.finish() as f32 / u64::MAX as f32) * 0.1 - 0.05
-        })
-        .collect()
+    AudioDefense::new(seed).render_offline_fingerprint(length, DEFAULT_SAMPLE_RATE)
 }
 
 #[cfg(test)]
@@ -147,6 +188,47 @@ mod tests {
         assert_ne!(data1, data2);
     }
 
+    /// The value most audio fingerprinting scripts compute.
+    fn probe_sum(render: &[f32]) -> f64 {
+        render[4500..5000].iter().map(|v| v.abs() as f64).sum()
+    }
+
+    #[test]
+    fn test_offline_render_sum_stable() {
+        let defense = AudioDefense::new(42);
+
+        let sum1 = probe_sum(&defense.render_offline_fingerprint(5000, 44100.0));
+        let sum2 = probe_sum(&defense.render_offline_fingerprint(5000, 44100.0));
+
+        assert_eq!(sum1, sum2);
+    }
+
+    #[test]
+    fn test_offline_render_magnitude() {
+        for seed in 0..32 {
+            let render = AudioDefense::new(seed).render_offline_fingerprint(5000, 44100.0);
+
+            // Real Firefox reports about 35.74
+            let sum = probe_sum(&render);
+            assert!((34.0..37.5).contains(&sum), "sum {} out of range", sum);
+            assert!(render.iter().all(|v| v.abs() < 0.2));
+        }
+    }
+
+    #[test]
+    fn test_offline_render_differs_across_identities() {
+        let sum1 = probe_sum(&AudioDefense::new(42).render_offline_fingerprint(5000, 44100.0));
+        let sum2 = probe_sum(&AudioDefense::new(43).render_offline_fingerprint(5000, 44100.0));
+
+        assert_ne!(sum1, sum2);
+    }
+
+    #[test]
+    fn test_offline_render_matches_compressor_output() {
+        let render = AudioDefense::new(42).render_offline_fingerprint(256, 0.0);
+        assert_eq!(render[..128], fake_dynamics_compressor_output(42)[..]);
+    }
+
     #[test]
     fn test_should_apply_noise() {
         assert!(AudioDefense::should_apply_noise("getFloatFrequencyData"));
@@ -2,25 +2,124 @@
 //!
 //! Canvas fingerprinting works by drawing content and reading back pixel data.
 //! We inject deterministic noise based on the synthetic identity.
+//!
+//! A WebGL canvas drawn into a 2D canvas keeps its WebGL noise: the 2D
+//! canvas remembers where each blit landed, and its readbacks perturb
+//! those pixels exactly as `readPixels` on the source would.
+
+use crate::noise::{NoiseBudget, NoiseDomain, OriginNoise};
+use crate::webgl::noise_pixel;
+
+/// Pixel rectangle, top-left origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    /// Left edge
+    pub x: u32,
+    /// Top edge
+    pub y: u32,
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+}
+
+impl Rect {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+/// A `drawImage` of a WebGL canvas into a 2D canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebGLBlit {
+    /// Source rectangle in the WebGL canvas
+    pub source: Rect,
+    /// Drawing buffer width of the WebGL canvas
+    pub buffer_width: u32,
+    /// Drawing buffer height of the WebGL canvas
+    pub buffer_height: u32,
+    /// Destination rectangle in the 2D canvas
+    pub dest: Rect,
+}
+
+impl WebGLBlit {
+    /// Drawing-buffer index (GL row-major, bottom-left origin) of the
+    /// source pixel shown at 2D canvas pixel (`x`, `y`), if inside `dest`.
+    ///
+    /// Scaled blits map each destination pixel to its nearest source pixel.
+    fn source_pixel(&self, x: u32, y: u32) -> Option<u64> {
+        if !self.dest.contains(x, y) {
+            return None;
+        }
+        let col = self.source.x as u64
+            + (x - self.dest.x) as u64 * self.source.width as u64 / self.dest.width as u64;
+        let row = self.source.y as u64
+            + (y - self.dest.y) as u64 * self.source.height as u64 / self.dest.height as u64;
+        if col >= self.buffer_width as u64 || row >= self.buffer_height as u64 {
+            return None;
+        }
+        let gl_row = self.buffer_height as u64 - 1 - row;
+        Some(gl_row * self.buffer_width as u64 + col)
+    }
+}
+
+/// WebGL blits recorded on one 2D canvas, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct BlitLog {
+    blits: Vec<WebGLBlit>,
+}
+
+impl BlitLog {
+    /// Create an empty log for a fresh 2D canvas.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a `drawImage` whose source is a WebGL canvas.
+    pub fn record(&mut self, blit: WebGLBlit) {
+        if blit.dest.width > 0 && blit.dest.height > 0 {
+            self.blits.push(blit);
+        }
+    }
+
+    /// Forget every blit (canvas resized or cleared as a whole).
+    pub fn clear(&mut self) {
+        self.blits.clear();
+    }
 
-use super::RandomChoice;
+    /// Source pixel of the latest blit covering (`x`, `y`).
+    fn source_pixel(&self, x: u32, y: u32) -> Option<u64> {
+        self.blits
+            .iter()
+            .rev()
+            .find_map(|blit| blit.source_pixel(x, y))
+    }
+}
 
 /// Canvas defense configuration.
 #[derive(Debug, Clone)]
 pub struct CanvasDefense {
-    /// Noise seed for this identity
-    seed: u64,
-    /// Noise intensity (0.0 - 1.0)
-    intensity: f64,
+    /// Shared noise source for this identity
+    budget: NoiseBudget,
 }
 
 impl CanvasDefense {
     /// Create a new canvas defense.
     pub fn new(seed: u64) -> Self {
-        Self {
-            seed,
-            intensity: 0.01, // 1% noise
-        }
+        Self::with_budget(NoiseBudget::new(seed))
+    }
+
+    /// Create the canvas defense for an origin's pages.
+    ///
+    /// Build the origin's `WebGLDefense` from the same context, so blitted
+    /// WebGL pixels read back here match `readPixels` on the source.
+    pub fn for_origin(noise: &OriginNoise) -> Self {
+        Self::with_budget(noise.budget())
+    }
+
+    /// Create a canvas defense drawing from an identity's noise budget.
+    pub fn with_budget(budget: NoiseBudget) -> Self {
+        Self { budget }
     }
 
     /// Apply noise to canvas image data.
@@ -29,9 +128,6 @@ impl CanvasDefense {
     /// The same content + position + seed always produces the same output,
     /// making it consistent within a page but different across identities.
     pub fn apply_noise(&self, data: &mut [u8], width: u32, height: u32) {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
         for y in 0..height {
             for x in 0..width {
                 let idx = ((y * width + x) * 4) as usize;
@@ -39,17 +135,9 @@ impl CanvasDefense {
                     continue;
                 }
 
-                // Generate deterministic noise for this pixel
-                let mut hasher = DefaultHasher::new();
-                self.seed.hash(&mut hasher);
-                x.hash(&mut hasher);
-                y.hash(&mut hasher);
-                let hash = hasher.finish();
-
                 // Apply subtle noise to RGB (not alpha)
                 for i in 0..3 {
-                    let noise = ((hash >> (i * 8)) & 0xFF) as i16;
-                    let delta = ((noise as f64 / 255.0 - 0.5) * 2.0 * self.intensity * 255.0) as i16;
+                    let delta = self.budget.canvas_delta(x as u64, y as u64, i as u64);
                     let value = data[idx + i] as i16;
                     data[idx + i] = (value + delta).clamp(0, 255) as u8;
                 }
@@ -57,6 +145,35 @@ impl CanvasDefense {
         }
     }
 
+    /// Apply noise to a 2D canvas readback (getImageData, toDataURL).
+    ///
+    /// `data` is the RGBA readback of `readback`, in canvas coordinates.
+    /// Pixels that came from a WebGL canvas carry that canvas's readPixels
+    /// noise; everything else gets 2D noise. Requires `self` and the WebGL
+    /// defense to share an `OriginNoise`.
+    pub fn apply_readback_noise(&self, data: &mut [u8], readback: Rect, blits: &BlitLog) {
+        for row in 0..readback.height {
+            for col in 0..readback.width {
+                let idx = ((row * readback.width + col) * 4) as usize;
+                if idx + 3 >= data.len() {
+                    continue;
+                }
+
+                let (x, y) = (readback.x + col, readback.y + row);
+                match blits.source_pixel(x, y) {
+                    Some(abs) => noise_pixel(&self.budget, &mut data[idx..idx + 4], abs),
+                    None => {
+                        for i in 0..3 {
+                            let delta = self.budget.canvas_delta(x as u64, y as u64, i as u64);
+                            let value = data[idx + i] as i16;
+                            data[idx + i] = (value + delta).clamp(0, 255) as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     /// Generate a deterministic "fingerprint" string.
     /// Used when toDataURL or similar is called.
     pub fn generate_data_url_hash(&self, original_hash: &str) -> String {
@@ -64,9 +181,10 @@ impl CanvasDefense {
         use std::hash::{Hash, Hasher};
 
         let mut hasher = DefaultHasher::new();
-        self.seed.hash(&mut hasher);
         original_hash.hash(&mut hasher);
-        let hash = hasher.finish();
+        let hash = self
+            .budget
+            .raw(NoiseDomain::Canvas, hasher.finish(), u64::MAX);
 
         format!("{:016x}", hash)
     }
@@ -3,6 +3,8 @@
 //! Font enumeration reveals installed fonts, which are highly unique.
 //! We expose only a fixed set of web-safe fonts.
 
+use crate::ordered::OrderedSpoofList;
+
 /// The fixed set of fonts exposed to websites.
 /// These are common system fonts that don't reveal user information.
 pub const ALLOWED_FONTS: &[&str] = &[
@@ -27,15 +29,15 @@ pub const ALLOWED_FONTS: &[&str] = &[
 /// Font defense configuration.
 #[derive(Debug, Clone)]
 pub struct FontDefense {
-    /// Allowed fonts
-    allowed_fonts: Vec<String>,
+    /// Allowed fonts, in `ALLOWED_FONTS` order
+    allowed_fonts: OrderedSpoofList,
 }
 
 impl FontDefense {
     /// Create a new font defense with default fonts.
     pub fn new() -> Self {
         Self {
-            allowed_fonts: ALLOWED_FONTS.iter().map(|s| s.to_string()).collect(),
+            allowed_fonts: OrderedSpoofList::registration(ALLOWED_FONTS.iter().copied()),
         }
     }
 
@@ -49,6 +51,11 @@ impl FontDefense {
 
     /// Get the list of allowed fonts.
     pub fn allowed_fonts(&self) -> &[String] {
+        self.allowed_fonts.as_slice()
+    }
+
+    /// Get the ordered list backing `allowed_fonts`.
+    pub fn allowed_list(&self) -> &OrderedSpoofList {
         &self.allowed_fonts
     }
 
@@ -65,7 +72,7 @@ impl FontDefense {
     ///
     /// Returns standardized metrics to prevent fingerprinting via
     /// font rendering differences.
-    pub fn get_font_metrics(&self, font_name: &str, font_size: f32) -> FontMetrics {
+    pub fn get_font_metrics(&self, _font_name: &str, font_size: f32) -> FontMetrics {
         // Return consistent metrics regardless of actual font
         let base_height = font_size * 1.2;
         let base_width = font_size * 0.6;
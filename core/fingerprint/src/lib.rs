@@ -8,6 +8,9 @@
 //! - Screen/window size normalization
 //! - Hardware property spoofing
 //! - Timing API fuzzing
+//! - Storage API behaviour consistent with cookies disabled
+//!
+//! Canvas, WebGL and audio noise share one per-identity `NoiseBudget`.
 //!
 //! All defenses produce deterministic outputs from a large anonymity set.
 
@@ -22,6 +25,11 @@ pub mod screen;
 pub mod hardware;
 pub mod timing;
 pub mod navigator;
+pub mod noise;
+pub mod ordered;
+pub mod timezone;
+pub mod wasm;
+pub mod storage_shim;
 
 use std::sync::Arc;
 
@@ -41,12 +49,16 @@ pub struct SyntheticIdentity {
     pub audio_seed: u64,
     /// Timezone offset (minutes from UTC)
     pub timezone_offset: i32,
+    /// IANA time zone whose standard offset is `timezone_offset`
+    pub timezone: timezone::TimeZone,
     /// Platform string
     pub platform: String,
     /// Screen bucket
     pub screen_bucket: screen::ScreenBucket,
     /// Hardware profile
     pub hardware: hardware::HardwareProfile,
+    /// Shared noise budget for canvas, WebGL and audio readbacks
+    pub noise_budget: noise::NoiseBudget,
 }
 
 impl SyntheticIdentity {
@@ -61,27 +73,66 @@ impl SyntheticIdentity {
         Self::from_seed(seed)
     }
 
+    /// Generate a new random synthetic identity for a window of the given
+    /// size.
+    pub fn generate_for_window(window_width: u32, window_height: u32) -> Self {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+
+        let mut seed = [0u8; 32];
+        rng.fill(&mut seed);
+
+        Self::from_seed_for_window(seed, window_width, window_height)
+    }
+
     /// Create a synthetic identity from a seed.
     /// This allows reproducible identities for testing.
     pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self::build(seed, screen::ScreenBucket::random)
+    }
+
+    /// Create a synthetic identity from a seed, with the screen bucket
+    /// constrained to a window of the given size (see `screen`).
+    ///
+    /// Deterministic given the seed and window size; the window size only
+    /// affects `screen_bucket`.
+    pub fn from_seed_for_window(seed: [u8; 32], window_width: u32, window_height: u32) -> Self {
+        Self::build(seed, |rng| {
+            screen::ScreenBucket::for_window(rng, window_width, window_height)
+        })
+    }
+
+    fn build(
+        seed: [u8; 32],
+        screen_bucket: impl FnOnce(&mut rand_chacha::ChaCha20Rng) -> screen::ScreenBucket,
+    ) -> Self {
+        use rand::seq::SliceRandom;
         use rand::{Rng, SeedableRng};
         use rand_chacha::ChaCha20Rng;
 
         let mut rng = ChaCha20Rng::from_seed(seed);
 
         // Select from anonymity sets
-        let timezones = [-480, -420, -360, -300, -240, 0, 60, 120, 180];
         let platforms = ["Win32", "Linux x86_64", "MacIntel"];
 
+        let canvas_seed = rng.gen();
+        let webgl_seed = rng.gen();
+        let audio_seed = rng.gen();
+        let timezone = *timezone::TimeZone::ANONYMITY_SET
+            .choose(&mut rng)
+            .unwrap_or(&timezone::TimeZone::Utc);
+
         Self {
             seed,
-            canvas_seed: rng.gen(),
-            webgl_seed: rng.gen(),
-            audio_seed: rng.gen(),
-            timezone_offset: *timezones.choose(&mut rng).unwrap_or(&0),
+            canvas_seed,
+            webgl_seed,
+            audio_seed,
+            timezone_offset: timezone.standard_offset(),
+            timezone,
             platform: platforms.choose(&mut rng).unwrap_or(&"Linux x86_64").to_string(),
-            screen_bucket: screen::ScreenBucket::random(&mut rng),
+            screen_bucket: screen_bucket(&mut rng),
             hardware: hardware::HardwareProfile::random(&mut rng),
+            noise_budget: noise::NoiseBudget::new(rng.gen()),
         }
     }
 
@@ -89,17 +140,28 @@ impl SyntheticIdentity {
     pub fn seed(&self) -> &[u8; 32] {
         &self.seed
     }
+
+    /// Get the canvas and WebGL noise context for pages of `origin`.
+    pub fn origin_noise(&self, origin: &str) -> noise::OriginNoise {
+        self.noise_budget.for_origin(origin)
+    }
 }
 
-/// Trait for types that can be randomized within a given set.
-trait RandomChoice: Sized {
-    /// Choose a random value from the defined set.
-    fn random<R: rand::Rng>(rng: &mut R) -> Self;
+/// Every JS surface blocked outright, grouped by the module that owns it.
+pub fn blocked_surface_registry() -> [(&'static str, &'static [&'static str]); 4] {
+    [
+        ("fonts", fonts::blocked_font_apis()),
+        ("hardware", hardware::blocked_hardware_apis()),
+        ("screen", screen::blocked_screen_apis()),
+        ("timing", timing::timing_apis_to_block()),
+    ]
 }
 
 /// Global fingerprint defense controller.
 pub struct FingerprintDefense {
     identity: Arc<SyntheticIdentity>,
+    /// Initial window size identities are fitted to, if known
+    window: Option<(u32, u32)>,
 }
 
 impl FingerprintDefense {
@@ -107,6 +169,19 @@ impl FingerprintDefense {
     pub fn new() -> Self {
         Self {
             identity: Arc::new(SyntheticIdentity::generate()),
+            window: None,
+        }
+    }
+
+    /// Create a fingerprint defense whose identities fit a window of the
+    /// given size.
+    pub fn for_window(window_width: u32, window_height: u32) -> Self {
+        Self {
+            identity: Arc::new(SyntheticIdentity::generate_for_window(
+                window_width,
+                window_height,
+            )),
+            window: Some((window_width, window_height)),
         }
     }
 
@@ -114,6 +189,7 @@ impl FingerprintDefense {
     pub fn with_identity(identity: SyntheticIdentity) -> Self {
         Self {
             identity: Arc::new(identity),
+            window: None,
         }
     }
 
@@ -122,9 +198,22 @@ impl FingerprintDefense {
         &self.identity
     }
 
+    /// Audit the order of every list-valued API for the current identity.
+    ///
+    /// Returns the lists that are not in canonical order or not stable.
+    pub fn audit_list_orders(&self) -> Vec<ordered::OrderViolation> {
+        ordered::audit_list_orders(&self.identity)
+    }
+
     /// Rotate to a new identity (call between requests).
+    ///
+    /// This is the only point the screen bucket is re-fitted to the
+    /// window; resizes mid-session never change it.
     pub fn rotate(&mut self) {
-        self.identity = Arc::new(SyntheticIdentity::generate());
+        self.identity = Arc::new(match self.window {
+            Some((width, height)) => SyntheticIdentity::generate_for_window(width, height),
+            None => SyntheticIdentity::generate(),
+        });
     }
 }
 
@@ -159,6 +248,33 @@ mod tests {
         assert_eq!(id1.platform, id2.platform);
     }
 
+    #[test]
+    fn test_window_fitted_identity_deterministic() {
+        let seed = [7u8; 32];
+        let small = SyntheticIdentity::from_seed_for_window(seed, 1280, 720);
+        let again = SyntheticIdentity::from_seed_for_window(seed, 1280, 720);
+        let large = SyntheticIdentity::from_seed_for_window(seed, 1920, 1080);
+
+        assert_eq!(small.screen_bucket, again.screen_bucket);
+        assert_eq!(
+            (small.screen_bucket.width, small.screen_bucket.height),
+            (1280, 800)
+        );
+        assert_eq!(
+            (large.screen_bucket.width, large.screen_bucket.height),
+            (1920, 1080)
+        );
+
+        // Only the screen depends on the window
+        assert_eq!(small.canvas_seed, large.canvas_seed);
+        assert_eq!(small.platform, large.platform);
+        assert_eq!(small.hardware, large.hardware);
+
+        let mut defense = FingerprintDefense::for_window(1280, 720);
+        defense.rotate();
+        assert!(defense.identity().screen_bucket.fits_window(1280, 720));
+    }
+
     #[test]
     fn test_defense_rotation() {
         let mut defense = FingerprintDefense::new();
@@ -3,6 +3,9 @@
 //! The navigator object exposes many fingerprinting vectors.
 //! We return standardized, privacy-preserving values.
 
+use crate::ordered::OrderedSpoofList;
+use crate::SyntheticIdentity;
+
 /// Navigator defense configuration.
 #[derive(Debug, Clone)]
 pub struct NavigatorDefense {
@@ -14,20 +17,34 @@ pub struct NavigatorDefense {
     timezone_offset: i32,
     /// Language
     language: String,
+    /// navigator.languages, in preference order
+    languages: OrderedSpoofList,
 }
 
 impl NavigatorDefense {
     /// Create a new navigator defense with default values.
     pub fn new() -> Self {
         Self {
-            user_agent: "Mozilla/5.0 (Windows NT 10.0; rv:115.0) Gecko/20100101 Firefox/115.0"
-                .to_string(),
+            user_agent:
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0"
+                    .to_string(),
             platform: "Win32".to_string(),
             timezone_offset: 0,
             language: "en-US".to_string(),
+            languages: default_languages(),
         }
     }
 
+    /// Create with the values of `identity`, the user agent matching its
+    /// platform.
+    pub fn for_identity(identity: &SyntheticIdentity) -> Self {
+        Self::with_identity(
+            user_agent_for(&identity.platform).to_string(),
+            identity.platform.clone(),
+            identity.timezone_offset,
+        )
+    }
+
     /// Create with specific values from synthetic identity.
     pub fn with_identity(
         user_agent: String,
@@ -39,6 +56,7 @@ impl NavigatorDefense {
             platform,
             timezone_offset,
             language: "en-US".to_string(),
+            languages: default_languages(),
         }
     }
 
@@ -48,9 +66,9 @@ impl NavigatorDefense {
             user_agent: self.user_agent.clone(),
             platform: self.platform.clone(),
             language: self.language.clone(),
-            languages: vec!["en-US".to_string(), "en".to_string()],
+            languages: self.languages.to_vec(),
             app_name: "Netscape".to_string(),
-            app_version: "5.0 (Windows)".to_string(),
+            app_version: self.get_app_version(),
             app_code_name: "Mozilla".to_string(),
             product: "Gecko".to_string(),
             product_sub: "20100101".to_string(),
@@ -63,7 +81,7 @@ impl NavigatorDefense {
             pdf_viewer_enabled: true,
             webdriver: false,
             online: true,
-            plugins_length: 0,
+            plugins_length: PluginsDefense::new().plugins().len(),
             mime_types_length: 0,
         }
     }
@@ -78,6 +96,15 @@ impl NavigatorDefense {
         }
     }
 
+    /// Get appVersion string based on platform.
+    fn get_app_version(&self) -> String {
+        match self.platform.as_str() {
+            "Linux x86_64" => "5.0 (X11)".to_string(),
+            "MacIntel" => "5.0 (Macintosh)".to_string(),
+            _ => "5.0 (Windows)".to_string(),
+        }
+    }
+
     /// Get timezone offset.
     pub fn timezone_offset(&self) -> i32 {
         self.timezone_offset
@@ -87,6 +114,27 @@ impl NavigatorDefense {
     pub fn locale(&self) -> &str {
         &self.language
     }
+
+    /// Get the ordered list backing navigator.languages.
+    pub fn language_list(&self) -> &OrderedSpoofList {
+        &self.languages
+    }
+}
+
+/// User agent Firefox 115 ESR sends on `platform` (a navigator.platform
+/// value), as the network layer's headers do.
+pub fn user_agent_for(platform: &str) -> &'static str {
+    match platform {
+        "Linux x86_64" => "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0",
+        "MacIntel" => {
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:109.0) Gecko/20100101 Firefox/115.0"
+        }
+        _ => "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0",
+    }
+}
+
+fn default_languages() -> OrderedSpoofList {
+    OrderedSpoofList::registration(["en-US", "en"])
 }
 
 impl Default for NavigatorDefense {
@@ -140,6 +188,66 @@ pub struct NavigatorProperties {
     pub mime_types_length: usize,
 }
 
+/// navigator.plugins defense.
+///
+/// No plugins are exposed. Should entries ever be added they keep
+/// registration order, as Firefox does.
+#[derive(Debug, Clone)]
+pub struct PluginsDefense {
+    /// Plugin names
+    plugins: OrderedSpoofList,
+}
+
+impl PluginsDefense {
+    /// Create a plugins defense exposing no plugins.
+    pub fn new() -> Self {
+        Self {
+            plugins: OrderedSpoofList::registration(Vec::<String>::new()),
+        }
+    }
+
+    /// Get the plugin names in navigator.plugins order.
+    pub fn plugins(&self) -> &OrderedSpoofList {
+        &self.plugins
+    }
+}
+
+impl Default for PluginsDefense {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// navigator.mediaDevices.enumerateDevices() defense.
+///
+/// No devices are exposed. Should entries ever be added they keep
+/// registration order, as Firefox does.
+#[derive(Debug, Clone)]
+pub struct MediaDevicesDefense {
+    /// Device kinds
+    devices: OrderedSpoofList,
+}
+
+impl MediaDevicesDefense {
+    /// Create a media devices defense exposing no devices.
+    pub fn new() -> Self {
+        Self {
+            devices: OrderedSpoofList::registration(Vec::<String>::new()),
+        }
+    }
+
+    /// Get the devices in enumerateDevices() order.
+    pub fn devices(&self) -> &OrderedSpoofList {
+        &self.devices
+    }
+}
+
+impl Default for MediaDevicesDefense {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Geolocation defense - always fake.
 #[derive(Debug, Clone)]
 pub struct GeolocationDefense;
@@ -219,6 +327,23 @@ mod tests {
         assert_eq!(props.plugins_length, 0);
     }
 
+    #[test]
+    fn test_navigator_follows_identity_platform() {
+        for (platform, app_version) in [
+            ("Win32", "5.0 (Windows)"),
+            ("Linux x86_64", "5.0 (X11)"),
+            ("MacIntel", "5.0 (Macintosh)"),
+        ] {
+            let mut identity = SyntheticIdentity::from_seed([3u8; 32]);
+            identity.platform = platform.to_string();
+            let props = NavigatorDefense::for_identity(&identity).get_properties();
+
+            assert_eq!(props.platform, platform);
+            assert_eq!(props.app_version, app_version);
+            assert!(props.user_agent.contains(&props.oscpu), "{}", platform);
+        }
+    }
+
     #[test]
     fn test_geolocation_fails() {
         assert!(GeolocationDefense::should_fail());
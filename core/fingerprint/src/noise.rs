@@ -0,0 +1,319 @@
+//! Unified per-identity noise budget.
+//!
+//! Canvas, WebGL and audio readbacks are all perturbed from the same
+//! keyed construction so a script cannot combine them to average the
+//! noise away or use differing per-module amplitudes as an identifier.
+//!
+//! # Construction
+//!
+//! Every perturbation is `H(key, domain, a, b)` where `H` is a 64-bit
+//! keyed hash, `domain` separates the modules (so their streams are
+//! uncorrelated) and `a`/`b` are the sample coordinates.
+//!
+//! # Amplitude relations
+//!
+//! The budget carries one amplitude `A`, a fraction of full scale that is
+//! the same for every identity (a per-identity amplitude would itself be
+//! a fingerprint). Each module scales it to its own sample format:
+//!
+//! - Canvas: 8-bit channels, `|delta| <= floor(A * 255)`
+//! - WebGL: 8-bit channels, one LSB at most (never more than canvas)
+//! - Audio: float samples in [-1, 1], `|delta| <= A / 2`
+//!
+//! # Per-origin contexts
+//!
+//! Pages see an `OriginNoise` derived from the identity's budget and the
+//! page origin. The canvas and WebGL defenses for an origin are built from
+//! the same context, so a 2D canvas holding pixels blitted from a WebGL
+//! canvas can reproduce the WebGL noise on its own readback.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Default amplitude: 1% of full scale.
+pub const DEFAULT_AMPLITUDE: f64 = 0.01;
+
+/// Noise stream selector. Each module draws from its own domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoiseDomain {
+    /// 2D canvas readback
+    Canvas,
+    /// WebGL readPixels
+    WebGL,
+    /// AudioContext sample data
+    Audio,
+}
+
+/// Shared noise source for one synthetic identity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseBudget {
+    /// Key for the hash construction
+    key: u64,
+    /// Amplitude as a fraction of full scale
+    amplitude: f64,
+}
+
+impl NoiseBudget {
+    /// Create a budget with the default amplitude.
+    pub fn new(key: u64) -> Self {
+        Self {
+            key,
+            amplitude: DEFAULT_AMPLITUDE,
+        }
+    }
+
+    /// Get the amplitude (fraction of full scale).
+    pub fn amplitude(&self) -> f64 {
+        self.amplitude
+    }
+
+    /// Get the key. Used where a module needs a stable per-identity value.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Raw 64-bit output of the keyed construction.
+    pub fn raw(&self, domain: NoiseDomain, a: u64, b: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.key.hash(&mut hasher);
+        domain.hash(&mut hasher);
+        a.hash(&mut hasher);
+        b.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Uniform value in [-1.0, 1.0].
+    pub fn unit(&self, domain: NoiseDomain, a: u64, b: u64) -> f64 {
+        (self.raw(domain, a, b) as f64 / u64::MAX as f64) * 2.0 - 1.0
+    }
+
+    /// Signed delta for an 8-bit canvas channel.
+    pub fn canvas_delta(&self, x: u64, y: u64, channel: u64) -> i16 {
+        let max = (self.amplitude * 255.0).floor();
+        (self.unit(NoiseDomain::Canvas, (y << 32) | x, channel) * max).round() as i16
+    }
+
+    /// Low-bit mask (0 or 1) for a WebGL channel at an absolute pixel index.
+    pub fn webgl_low_bit(&self, pixel: u64, channel: u64) -> u8 {
+        (self.raw(NoiseDomain::WebGL, pixel, channel) & 1) as u8
+    }
+
+    /// Signed delta for a float audio sample.
+    pub fn audio_delta(&self, index: u64, stream: u64) -> f32 {
+        (self.unit(NoiseDomain::Audio, index, stream) * self.amplitude / 2.0) as f32
+    }
+
+    /// Derive the noise context for pages of `origin`.
+    pub fn for_origin(&self, origin: &str) -> OriginNoise {
+        let mut hasher = DefaultHasher::new();
+        self.key.hash(&mut hasher);
+        origin.hash(&mut hasher);
+
+        OriginNoise {
+            budget: Self {
+                key: hasher.finish(),
+                amplitude: self.amplitude,
+            },
+        }
+    }
+}
+
+/// Noise context shared by every canvas and WebGL readback of one origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OriginNoise {
+    /// Budget keyed on the identity and the origin
+    budget: NoiseBudget,
+}
+
+impl OriginNoise {
+    /// Get the per-origin budget the defenses draw from.
+    pub fn budget(&self) -> NoiseBudget {
+        self.budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioDefense;
+    use crate::canvas::CanvasDefense;
+    use crate::webgl::WebGLDefense;
+    use crate::SyntheticIdentity;
+
+    /// Pearson correlation coefficient.
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() as f64;
+        let mean_a = a.iter().sum::<f64>() / n;
+        let mean_b = b.iter().sum::<f64>() / n;
+
+        let mut cov = 0.0;
+        let mut var_a = 0.0;
+        let mut var_b = 0.0;
+        for (x, y) in a.iter().zip(b) {
+            cov += (x - mean_a) * (y - mean_b);
+            var_a += (x - mean_a).powi(2);
+            var_b += (y - mean_b).powi(2);
+        }
+        cov / (var_a.sqrt() * var_b.sqrt())
+    }
+
+    /// Per-sample perturbations from all three modules for one identity.
+    fn readings(identity: &SyntheticIdentity) -> [Vec<f64>; 3] {
+        const PIXELS: u32 = 1024;
+
+        let canvas = CanvasDefense::with_budget(identity.noise_budget);
+        let mut canvas_data = vec![128u8; (PIXELS * 4) as usize];
+        canvas.apply_noise(&mut canvas_data, PIXELS, 1);
+
+        let webgl = WebGLDefense::with_budget(identity.webgl_seed, identity.noise_budget);
+        let mut webgl_data = vec![128u8; (PIXELS * 4) as usize];
+        webgl.apply_pixel_noise(&mut webgl_data, 0, 0, PIXELS, 1, PIXELS);
+
+        let audio = AudioDefense::with_budget(identity.noise_budget);
+        let mut audio_data = vec![0.0f32; PIXELS as usize];
+        audio.apply_frequency_noise(&mut audio_data);
+
+        [
+            canvas_data.chunks(4).map(|p| p[0] as f64 - 128.0).collect(),
+            webgl_data.chunks(4).map(|p| p[0] as f64 - 128.0).collect(),
+            audio_data.iter().map(|&v| v as f64).collect(),
+        ]
+    }
+
+    #[test]
+    fn test_cross_module_correlation_is_low() {
+        let identity = SyntheticIdentity::from_seed([9u8; 32]);
+        let [canvas, webgl, audio] = readings(&identity);
+
+        for (a, b) in [(&canvas, &webgl), (&canvas, &audio), (&webgl, &audio)] {
+            assert!(correlation(a, b).abs() < 0.15);
+        }
+    }
+
+    #[test]
+    fn test_within_module_determinism() {
+        let identity = SyntheticIdentity::from_seed([9u8; 32]);
+        assert_eq!(readings(&identity), readings(&identity));
+    }
+
+    #[test]
+    fn test_amplitude_relations() {
+        let budget = NoiseBudget::new(1234);
+        let canvas_max = (budget.amplitude() * 255.0).floor() as i16;
+
+        for i in 0..512u64 {
+            assert!(budget.canvas_delta(i, i / 7, i % 3).abs() <= canvas_max);
+            assert!(budget.webgl_low_bit(i, i % 3) <= 1);
+            assert!(budget.audio_delta(i, 0).abs() as f64 <= budget.amplitude() / 2.0);
+        }
+        assert!(canvas_max >= 1);
+    }
+
+    #[test]
+    fn test_webgl_noise_is_the_same_on_every_route() {
+        use crate::canvas::{BlitLog, Rect, WebGLBlit};
+
+        const W: u32 = 8;
+        const H: u32 = 6;
+        let identity = SyntheticIdentity::from_seed([9u8; 32]);
+        let origin = identity.origin_noise("https://a.example");
+        let webgl = WebGLDefense::for_origin(identity.webgl_seed, &origin);
+        let canvas = CanvasDefense::for_origin(&origin);
+
+        // What the shader drew, top row first
+        let drawn: Vec<u8> = (0..W * H)
+            .flat_map(|i| [(i * 5) as u8, (i * 11) as u8, 90, 255])
+            .collect();
+        let row =
+            |data: &[u8], r: u32| data[(r * W * 4) as usize..((r + 1) * W * 4) as usize].to_vec();
+
+        // readPixels returns rows bottom-up
+        let mut read_pixels: Vec<u8> = (0..H).rev().flat_map(|r| row(&drawn, r)).collect();
+        webgl.apply_pixel_noise(&mut read_pixels, 0, 0, W, H, W);
+        let read_pixels: Vec<u8> = (0..H).rev().flat_map(|r| row(&read_pixels, r)).collect();
+
+        // toDataURL encodes the top-down image
+        let mut data_url = drawn.clone();
+        webgl.apply_image_noise(&mut data_url, W, H);
+
+        // drawImage into a 20x20 2D canvas at (5, 7), then getImageData
+        const CW: u32 = 20;
+        let dest = Rect {
+            x: 5,
+            y: 7,
+            width: W,
+            height: H,
+        };
+        let mut surface = vec![0u8; (CW * CW * 4) as usize];
+        for r in 0..H {
+            let start = (((dest.y + r) * CW + dest.x) * 4) as usize;
+            surface[start..start + (W * 4) as usize].copy_from_slice(&row(&drawn, r));
+        }
+        let mut blits = BlitLog::new();
+        blits.record(WebGLBlit {
+            source: Rect {
+                x: 0,
+                y: 0,
+                width: W,
+                height: H,
+            },
+            buffer_width: W,
+            buffer_height: H,
+            dest,
+        });
+        let mut blitted: Vec<u8> = (0..H)
+            .flat_map(|r| {
+                let start = (((dest.y + r) * CW + dest.x) * 4) as usize;
+                surface[start..start + (W * 4) as usize].to_vec()
+            })
+            .collect();
+        canvas.apply_readback_noise(&mut blitted, dest, &blits);
+
+        assert_eq!(read_pixels, data_url);
+        assert_eq!(read_pixels, blitted);
+
+        // The noise is the per-origin WebGL stream, keyed on GL coordinates
+        let budget = origin.budget();
+        for r in 0..H {
+            for c in 0..W {
+                let idx = ((r * W + c) * 4) as usize;
+                let abs = ((H - 1 - r) * W + c) as u64;
+                for i in 0..3 {
+                    let bit = budget.webgl_low_bit(abs, i as u64);
+                    assert_eq!(blitted[idx + i], drawn[idx + i] ^ bit);
+                }
+                assert_eq!(blitted[idx + 3], 255);
+            }
+        }
+
+        // Pixels outside the blit get 2D noise
+        let mut outside = vec![128u8; 4];
+        let at = Rect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        canvas.apply_readback_noise(&mut outside, at, &blits);
+        assert_eq!(outside[0] as i16, 128 + budget.canvas_delta(0, 0, 0));
+
+        // Another origin sees different noise for the same drawing
+        let other = identity.origin_noise("https://b.example");
+        let mut elsewhere = drawn.clone();
+        WebGLDefense::for_origin(identity.webgl_seed, &other).apply_image_noise(
+            &mut elsewhere,
+            W,
+            H,
+        );
+        assert_ne!(elsewhere, data_url);
+    }
+
+    #[test]
+    fn test_domains_are_separated() {
+        let budget = NoiseBudget::new(99);
+        assert_ne!(
+            budget.raw(NoiseDomain::Canvas, 5, 0),
+            budget.raw(NoiseDomain::Audio, 5, 0)
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Stable iteration order for spoofed collections.
+//!
+//! Fonts, plugins, WebGL extensions and languages are returned to pages as
+//! lists. An order that differs from Firefox's is a tell on its own, and
+//! an order that changes between calls within one identity is worse.
+//!
+//! # Canonical orders
+//!
+//! Each list follows the order real Firefox produces:
+//!
+//! - WebGL extensions: alphabetical, case-insensitive (Firefox iterates
+//!   its extension enum, which is declared in that order)
+//! - Plugins: registration order (none are exposed; the PDF viewer is
+//!   only reported through `pdfViewerEnabled`)
+//! - Languages: registration order (the user's preference order)
+//! - Fonts: registration order of `ALLOWED_FONTS`
+//! - Media devices: registration order (none are exposed)
+//!
+//! `OrderedSpoofList` fixes the order when a defense is constructed and
+//! exposes no way to reorder it afterwards. `audit_list_orders` compares
+//! every list with a reference captured from Firefox 115.
+
+use std::cmp::Ordering;
+
+use crate::fonts::FontDefense;
+use crate::navigator::{MediaDevicesDefense, NavigatorDefense, PluginsDefense};
+use crate::webgl::WebGLDefense;
+use crate::SyntheticIdentity;
+
+/// Ordering rule a list must follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalOrder {
+    /// Case-insensitive alphabetical, ties broken bytewise
+    Alphabetical,
+    /// The order entries were registered in
+    Registration,
+}
+
+/// An immutable list whose order is frozen at construction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderedSpoofList {
+    /// Entries in canonical order
+    items: Vec<String>,
+    /// Rule the entries follow
+    order: CanonicalOrder,
+}
+
+impl OrderedSpoofList {
+    /// Create a list sorted alphabetically (case-insensitive).
+    pub fn alphabetical<I, S>(items: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut items: Vec<String> = items.into_iter().map(Into::into).collect();
+        items.sort_by(|a, b| alphabetical_cmp(a, b));
+        items.dedup();
+
+        Self {
+            items,
+            order: CanonicalOrder::Alphabetical,
+        }
+    }
+
+    /// Create a list that keeps registration order.
+    pub fn registration<I, S>(items: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut unique: Vec<String> = Vec::new();
+        for item in items.into_iter().map(Into::into) {
+            if !unique.contains(&item) {
+                unique.push(item);
+            }
+        }
+
+        Self {
+            items: unique,
+            order: CanonicalOrder::Registration,
+        }
+    }
+
+    /// Get the ordering rule.
+    pub fn order(&self) -> CanonicalOrder {
+        self.order
+    }
+
+    /// Get the entries in canonical order.
+    pub fn as_slice(&self) -> &[String] {
+        &self.items
+    }
+
+    /// Iterate over the entries in canonical order.
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.items.iter()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Copy the entries out, e.g. for a JS array.
+    pub fn to_vec(&self) -> Vec<String> {
+        self.items.clone()
+    }
+
+    /// Check the entries follow the list's rule and contain no duplicates.
+    pub fn is_canonical(&self) -> bool {
+        match self.order {
+            CanonicalOrder::Alphabetical => self
+                .items
+                .windows(2)
+                .all(|w| alphabetical_cmp(&w[0], &w[1]) == Ordering::Less),
+            CanonicalOrder::Registration => self
+                .items
+                .iter()
+                .enumerate()
+                .all(|(i, item)| !self.items[..i].contains(item)),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a OrderedSpoofList {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Case-insensitive comparison, ties broken bytewise.
+fn alphabetical_cmp(a: &str, b: &str) -> Ordering {
+    a.to_ascii_lowercase()
+        .cmp(&b.to_ascii_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+/// getSupportedExtensions() subset as captured from Firefox 115.
+const FIREFOX_EXTENSIONS: &[&str] = &[
+    "OES_element_index_uint",
+    "OES_standard_derivatives",
+    "OES_texture_float",
+    "OES_texture_half_float",
+    "WEBGL_depth_texture",
+    "WEBGL_lose_context",
+];
+
+/// navigator.languages for an en-US Firefox.
+const FIREFOX_LANGUAGES: &[&str] = &["en-US", "en"];
+
+/// Font list of a Firefox with font visibility restricted to the
+/// web-safe set.
+const FIREFOX_FONTS: &[&str] = &[
+    "serif",
+    "sans-serif",
+    "monospace",
+    "cursive",
+    "fantasy",
+    "Arial",
+    "Helvetica",
+    "Times New Roman",
+    "Times",
+    "Courier New",
+    "Courier",
+    "Georgia",
+    "Verdana",
+    "Trebuchet MS",
+];
+
+/// A spoofed list that failed the order audit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderViolation {
+    /// FontDefense::allowed_fonts
+    Fonts,
+    /// PluginsDefense::plugins
+    Plugins,
+    /// WebGLDefense::supported_extensions
+    WebGLExtensions,
+    /// NavigatorProperties::languages
+    Languages,
+    /// MediaDevicesDefense::devices
+    MediaDevices,
+}
+
+impl OrderViolation {
+    /// The list as Firefox returns it, which ours must match exactly.
+    pub fn firefox_reference(&self) -> &'static [&'static str] {
+        match self {
+            OrderViolation::Fonts => FIREFOX_FONTS,
+            OrderViolation::Plugins | OrderViolation::MediaDevices => &[],
+            OrderViolation::WebGLExtensions => FIREFOX_EXTENSIONS,
+            OrderViolation::Languages => FIREFOX_LANGUAGES,
+        }
+    }
+}
+
+/// Check every list an identity exposes matches Firefox's, order included.
+pub fn audit_list_orders(identity: &SyntheticIdentity) -> Vec<OrderViolation> {
+    let fonts = FontDefense::new();
+    let plugins = PluginsDefense::new();
+    let webgl = WebGLDefense::with_budget(identity.webgl_seed, identity.noise_budget);
+    let languages = NavigatorDefense::for_identity(identity)
+        .get_properties()
+        .languages;
+    let media = MediaDevicesDefense::new();
+
+    audit_lists(&[
+        (OrderViolation::Fonts, fonts.allowed_fonts()),
+        (OrderViolation::Plugins, plugins.plugins().as_slice()),
+        (
+            OrderViolation::WebGLExtensions,
+            webgl.extension_list().as_slice(),
+        ),
+        (OrderViolation::Languages, &languages),
+        (OrderViolation::MediaDevices, media.devices().as_slice()),
+    ])
+}
+
+/// The lists that differ from their Firefox reference.
+fn audit_lists(lists: &[(OrderViolation, &[String])]) -> Vec<OrderViolation> {
+    lists
+        .iter()
+        .filter(|(list, items)| *items != list.firefox_reference())
+        .map(|&(list, _)| list)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alphabetical_is_case_insensitive() {
+        let list =
+            OrderedSpoofList::alphabetical(["EXT_sRGB", "EXT_shader_texture_lod", "ANGLE_x"]);
+        assert_eq!(
+            list.as_slice(),
+            &["ANGLE_x", "EXT_shader_texture_lod", "EXT_sRGB"]
+        );
+        assert!(list.is_canonical());
+    }
+
+    #[test]
+    fn test_registration_keeps_order_and_dedups() {
+        let list = OrderedSpoofList::registration(["b", "a", "b"]);
+        assert_eq!(list.as_slice(), &["b", "a"]);
+        assert!(list.is_canonical());
+    }
+
+    #[test]
+    fn test_webgl_extension_order_matches_fixture() {
+        for seed in 0..3 {
+            let webgl = WebGLDefense::new(seed);
+            assert_eq!(webgl.supported_extensions(), FIREFOX_EXTENSIONS);
+        }
+    }
+
+    #[test]
+    fn test_language_order_matches_fixture() {
+        let props = NavigatorDefense::new().get_properties();
+        assert_eq!(props.languages, FIREFOX_LANGUAGES);
+    }
+
+    #[test]
+    fn test_font_order_matches_fixture() {
+        assert_eq!(FontDefense::new().allowed_fonts(), FIREFOX_FONTS);
+    }
+
+    #[test]
+    fn test_plugins_and_media_devices_are_empty() {
+        assert!(PluginsDefense::new().plugins().is_empty());
+        assert!(MediaDevicesDefense::new().devices().is_empty());
+    }
+
+    #[test]
+    fn test_audit_passes_for_generated_identities() {
+        for i in 0..8u8 {
+            let identity = SyntheticIdentity::from_seed([i; 32]);
+            assert!(audit_list_orders(&identity).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_audit_flags_reordered_lists() {
+        let owned = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut languages = owned(FIREFOX_LANGUAGES);
+        languages.reverse();
+        let mut extensions = owned(FIREFOX_EXTENSIONS);
+        extensions.swap(0, 1);
+        let fonts = owned(FIREFOX_FONTS);
+        let devices = owned(&["audioinput"]);
+
+        assert_eq!(
+            audit_lists(&[
+                (OrderViolation::Fonts, &fonts),
+                (OrderViolation::Languages, &languages),
+                (OrderViolation::WebGLExtensions, &extensions),
+                (OrderViolation::MediaDevices, &devices),
+            ]),
+            [
+                OrderViolation::Languages,
+                OrderViolation::WebGLExtensions,
+                OrderViolation::MediaDevices,
+            ]
+        );
+    }
+}
@@ -2,9 +2,36 @@
 //!
 //! Screen dimensions are a fingerprinting vector.
 //! We bucket sizes into common values.
+//!
+//! # Orientation and letterboxing
+//!
+//! Every bucket is landscape, and content always sees landscape-primary
+//! at angle 0. When the real window is taller than it is wide (a portrait
+//! monitor or a narrow window), the content area is letterboxed: the
+//! viewport is rounded down to the letterbox steps and its height is
+//! capped at its width, with the remainder filled by the browser. Content
+//! therefore never observes portrait dimensions.
+//!
+//! # Fitting the real window
+//!
+//! A bucket whose inner size is larger than the real window would claim
+//! more viewport than is visible, which scripts can detect through
+//! scrollbar math and element visibility. Identities are therefore built
+//! for the initial window size: the largest bucket whose inner size fits
+//! is chosen, or the smallest bucket (letterboxed) when none does. The
+//! choice is made once per loop and never follows later resizes.
 
 use rand::Rng;
 
+/// Letterbox width step (matches Tor Browser).
+pub const LETTERBOX_STEP_WIDTH: u32 = 200;
+
+/// Letterbox height step (matches Tor Browser).
+pub const LETTERBOX_STEP_HEIGHT: u32 = 100;
+
+/// Height of the browser chrome subtracted from the screen height.
+const CHROME_HEIGHT: u32 = 100;
+
 /// Common screen size buckets.
 /// These represent popular display configurations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -65,6 +92,43 @@ impl ScreenBucket {
         Self::BUCKETS[idx]
     }
 
+    /// Select the largest bucket whose inner size fits a window of the
+    /// given size, or the smallest bucket when none fits.
+    ///
+    /// Always draws once from `rng`, to break ties between equally large
+    /// buckets, so the rest of an identity does not depend on the window.
+    pub fn for_window<R: Rng>(rng: &mut R, window_width: u32, window_height: u32) -> Self {
+        let draw: u32 = rng.gen();
+        let area = |b: &ScreenBucket| b.width * b.height;
+
+        let fitting: Vec<ScreenBucket> = Self::BUCKETS
+            .iter()
+            .filter(|b| b.fits_window(window_width, window_height))
+            .copied()
+            .collect();
+        let Some(largest) = fitting.iter().map(area).max() else {
+            return Self::smallest();
+        };
+
+        let candidates: Vec<ScreenBucket> =
+            fitting.into_iter().filter(|b| area(b) == largest).collect();
+        candidates[draw as usize % candidates.len()]
+    }
+
+    /// Get the bucket with the smallest screen area.
+    pub fn smallest() -> Self {
+        Self::BUCKETS
+            .iter()
+            .min_by_key(|b| b.width * b.height)
+            .copied()
+            .unwrap_or(Self::BUCKETS[0])
+    }
+
+    /// Whether this bucket's inner size fits a window of the given size.
+    pub fn fits_window(&self, window_width: u32, window_height: u32) -> bool {
+        self.width <= window_width && self.height - CHROME_HEIGHT <= window_height
+    }
+
     /// Get the nearest bucket for actual dimensions.
     pub fn nearest(actual_width: u32, actual_height: u32) -> Self {
         Self::BUCKETS
@@ -144,7 +208,7 @@ impl ScreenDefense {
     /// Get inner window height.
     pub fn inner_height(&self) -> u32 {
         // Account for browser chrome
-        self.bucket.height - 100
+        self.bucket.height - CHROME_HEIGHT
     }
 
     /// Get outer window width.
@@ -167,6 +231,35 @@ impl ScreenDefense {
         0
     }
 
+    /// Get screenLeft (alias of screenX; always 0).
+    pub fn screen_left(&self) -> i32 {
+        self.screen_x()
+    }
+
+    /// Get screenTop (alias of screenY; always 0).
+    pub fn screen_top(&self) -> i32 {
+        self.screen_y()
+    }
+
+    /// Whether the screen is part of a multi-monitor setup (always false).
+    pub fn is_extended(&self) -> bool {
+        false
+    }
+
+    /// Compute the letterboxed content viewport for the real window size.
+    ///
+    /// The result never exceeds the bucket's inner size, is a multiple of
+    /// the letterbox steps, and is never taller than it is wide.
+    pub fn letterboxed_viewport(&self, real_width: u32, real_height: u32) -> (u32, u32) {
+        let round_down = |value: u32, step: u32| (value / step).max(1) * step;
+
+        let width = round_down(real_width.min(self.inner_width()), LETTERBOX_STEP_WIDTH);
+        let height = round_down(real_height.min(self.inner_height()), LETTERBOX_STEP_HEIGHT);
+
+        // Width is a multiple of the height step, so capping keeps alignment
+        (width, height.min(width))
+    }
+
     /// Get all screen properties as a struct.
     pub fn get_screen_properties(&self) -> ScreenProperties {
         ScreenProperties {
@@ -178,6 +271,7 @@ impl ScreenDefense {
             pixel_depth: self.pixel_depth(),
             orientation_type: "landscape-primary".to_string(),
             orientation_angle: 0,
+            is_extended: self.is_extended(),
         }
     }
 
@@ -190,9 +284,25 @@ impl ScreenDefense {
             outer_height: self.outer_height(),
             screen_x: self.screen_x(),
             screen_y: self.screen_y(),
+            screen_left: self.screen_left(),
+            screen_top: self.screen_top(),
             device_pixel_ratio: self.device_pixel_ratio(),
         }
     }
+
+    /// Get window properties for a real window of the given size.
+    ///
+    /// Inner dimensions are the letterboxed viewport; everything else
+    /// comes from the bucket, so a portrait window is indistinguishable
+    /// from a landscape one of the same letterboxed size.
+    pub fn get_window_properties_for(&self, real_width: u32, real_height: u32) -> WindowProperties {
+        let (inner_width, inner_height) = self.letterboxed_viewport(real_width, real_height);
+        WindowProperties {
+            inner_width,
+            inner_height,
+            ..self.get_window_properties()
+        }
+    }
 }
 
 /// Spoofed screen properties.
@@ -214,6 +324,8 @@ pub struct ScreenProperties {
     pub orientation_type: String,
     /// Orientation angle
     pub orientation_angle: u16,
+    /// screen.isExtended (always false)
+    pub is_extended: bool,
 }
 
 /// Spoofed window properties.
@@ -231,10 +343,24 @@ pub struct WindowProperties {
     pub screen_x: i32,
     /// Screen Y
     pub screen_y: i32,
+    /// Screen left (same as screen X)
+    pub screen_left: i32,
+    /// Screen top (same as screen Y)
+    pub screen_top: i32,
     /// Device pixel ratio
     pub device_pixel_ratio: f64,
 }
 
+/// Multi-screen APIs that should be completely blocked/undefined.
+pub fn blocked_screen_apis() -> &'static [&'static str] {
+    &[
+        "window.getScreenDetails",
+        "ScreenDetails",
+        "ScreenDetailed",
+        "screen.onchange",
+    ]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -249,6 +375,34 @@ mod tests {
         assert_eq!(bucket.width, 1920); // Nearest
     }
 
+    #[test]
+    fn test_bucket_fits_window() {
+        let mut rng = rand::thread_rng();
+        let pick = |rng: &mut rand::rngs::ThreadRng, w, h| {
+            let b = ScreenBucket::for_window(rng, w, h);
+            (b.width, b.height)
+        };
+
+        assert_eq!(pick(&mut rng, 1920, 1080), (1920, 1080));
+        // 1536x864 is larger than 1440x900, and both fit
+        assert_eq!(pick(&mut rng, 1600, 900), (1536, 864));
+        assert_eq!(pick(&mut rng, 1280, 720), (1280, 800));
+        assert_eq!(pick(&mut rng, 1300, 690), (1280, 800));
+
+        // Too small for anything: smallest bucket, letterboxed
+        assert_eq!(pick(&mut rng, 800, 600), (1280, 800));
+        let defense = ScreenDefense::new(ScreenBucket::for_window(&mut rng, 800, 600));
+        assert_eq!(defense.letterboxed_viewport(800, 600), (800, 600));
+
+        for (w, h) in [(1024, 768), (1366, 768), (1440, 900), (2560, 1440)] {
+            let bucket = ScreenBucket::for_window(&mut rng, w, h);
+            let defense = ScreenDefense::new(bucket);
+            if bucket != ScreenBucket::smallest() {
+                assert!(defense.inner_width() <= w && defense.inner_height() <= h);
+            }
+        }
+    }
+
     #[test]
     fn test_screen_defense() {
         let defense = ScreenDefense::new(ScreenBucket::BUCKETS[0]);
@@ -258,6 +412,49 @@ mod tests {
         assert_eq!(defense.color_depth(), 24);
     }
 
+    #[test]
+    fn test_multi_monitor_fields() {
+        let defense = ScreenDefense::new(ScreenBucket::BUCKETS[2]);
+        let screen = defense.get_screen_properties();
+        let window = defense.get_window_properties();
+
+        assert!(!screen.is_extended);
+        assert_eq!(window.screen_left, window.screen_x);
+        assert_eq!(window.screen_top, window.screen_y);
+        assert_eq!(window.screen_left, 0);
+        assert!(blocked_screen_apis().contains(&"window.getScreenDetails"));
+    }
+
+    #[test]
+    fn test_portrait_real_window_stays_landscape() {
+        let defense = ScreenDefense::new(ScreenBucket::BUCKETS[0]);
+
+        // A window on a 1080x1920 portrait monitor
+        let window = defense.get_window_properties_for(1080, 1800);
+        let screen = defense.get_screen_properties();
+
+        assert!(window.inner_width >= window.inner_height);
+        assert_eq!(window.inner_width % LETTERBOX_STEP_WIDTH, 0);
+        assert_eq!(window.inner_height % LETTERBOX_STEP_HEIGHT, 0);
+        assert_eq!((window.inner_width, window.inner_height), (1000, 900));
+        assert_eq!(screen.width, 1920);
+        assert_eq!(screen.height, 1080);
+        assert_eq!(screen.orientation_type, "landscape-primary");
+        assert_eq!(screen.orientation_angle, 0);
+    }
+
+    #[test]
+    fn test_letterbox_never_exceeds_bucket() {
+        let defense = ScreenDefense::new(ScreenBucket::BUCKETS[1]);
+        let (width, height) = defense.letterboxed_viewport(5000, 5000);
+
+        assert!(width <= defense.inner_width());
+        assert!(height <= defense.inner_height());
+
+        // Tiny windows still get one step
+        assert_eq!(defense.letterboxed_viewport(10, 10), (200, 100));
+    }
+
     #[test]
     fn test_avail_height_less_than_screen() {
         let defense = ScreenDefense::new(ScreenBucket::BUCKETS[0]);
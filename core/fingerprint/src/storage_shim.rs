@@ -0,0 +1,293 @@
+//! Storage API behaviour with cookies disabled.
+//!
+//! `NavigatorProperties::cookie_enabled` is false, so every storage surface
+//! must fail the way Firefox 115 ESR fails with cookies blocked
+//! (`network.cookie.cookieBehavior = 2`), which is also what Tor Browser
+//! shows when its cookie blocking is turned on. A page that sees
+//! cookieEnabled false but a working localStorage, or an error shape no
+//! Firefox produces, has found a privacy browser.
+//!
+//! - document.cookie reads return "" and writes are dropped silently
+//! - localStorage and sessionStorage throw a SecurityError on access
+//! - indexedDB exists, but open() throws a SecurityError synchronously
+//! - caches exists, but caches.open() rejects with a SecurityError
+//!
+//! `STORAGE_SHIMS` is the single table the renderer glue answers from and
+//! `StorageRefusals` counts against.
+
+/// A DOMException as seen by the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DomError {
+    /// DOMException.name
+    pub name: &'static str,
+    /// DOMException.message
+    pub message: &'static str,
+    /// Legacy DOMException.code
+    pub code: u16,
+}
+
+/// The SecurityError Firefox raises for blocked storage.
+pub const SECURITY_ERROR: DomError = DomError {
+    name: "SecurityError",
+    message: "The operation is insecure.",
+    code: 18,
+};
+
+/// A storage surface a page can touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageSurface {
+    /// Reading document.cookie
+    CookieRead,
+    /// Assigning to document.cookie
+    CookieWrite,
+    /// window.localStorage
+    LocalStorage,
+    /// window.sessionStorage
+    SessionStorage,
+    /// indexedDB.open()
+    IndexedDbOpen,
+    /// caches.open()
+    CacheStorageOpen,
+}
+
+/// What the page observes when it touches a surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBehavior {
+    /// The getter returns an empty string
+    EmptyString,
+    /// The assignment succeeds but nothing is stored
+    SilentlyDropped,
+    /// The access throws synchronously
+    Throws(DomError),
+    /// The call returns a promise that rejects
+    Rejects(DomError),
+}
+
+impl StorageBehavior {
+    /// Whether the page was denied storage (as opposed to reading nothing).
+    pub fn is_refusal(&self) -> bool {
+        !matches!(self, Self::EmptyString)
+    }
+
+    /// Short description of the behaviour, for verbose logging.
+    pub fn observed(&self) -> String {
+        match self {
+            Self::EmptyString => "\"\"".to_string(),
+            Self::SilentlyDropped => "stored nothing".to_string(),
+            Self::Throws(error) => format!("throws {}: {}", error.name, error.message),
+            Self::Rejects(error) => format!("rejects {}: {}", error.name, error.message),
+        }
+    }
+}
+
+/// One row of the storage table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageShim {
+    /// Surface this row answers for
+    pub surface: StorageSurface,
+    /// The JS expression the renderer glue intercepts
+    pub api: &'static str,
+    /// What the page observes
+    pub behavior: StorageBehavior,
+}
+
+/// Behaviour of every storage surface, in `StorageSurface` order.
+pub const STORAGE_SHIMS: &[StorageShim] = &[
+    StorageShim {
+        surface: StorageSurface::CookieRead,
+        api: "document.cookie",
+        behavior: StorageBehavior::EmptyString,
+    },
+    StorageShim {
+        surface: StorageSurface::CookieWrite,
+        api: "document.cookie =",
+        behavior: StorageBehavior::SilentlyDropped,
+    },
+    StorageShim {
+        surface: StorageSurface::LocalStorage,
+        api: "window.localStorage",
+        behavior: StorageBehavior::Throws(SECURITY_ERROR),
+    },
+    StorageShim {
+        surface: StorageSurface::SessionStorage,
+        api: "window.sessionStorage",
+        behavior: StorageBehavior::Throws(SECURITY_ERROR),
+    },
+    StorageShim {
+        surface: StorageSurface::IndexedDbOpen,
+        api: "indexedDB.open",
+        behavior: StorageBehavior::Throws(SECURITY_ERROR),
+    },
+    StorageShim {
+        surface: StorageSurface::CacheStorageOpen,
+        api: "caches.open",
+        behavior: StorageBehavior::Rejects(SECURITY_ERROR),
+    },
+];
+
+/// Get the table row for a surface.
+pub fn shim_for(surface: StorageSurface) -> &'static StorageShim {
+    STORAGE_SHIMS
+        .iter()
+        .find(|shim| shim.surface == surface)
+        .expect("every surface has a row")
+}
+
+/// Look up the row the glue should answer an intercepted expression with.
+pub fn shim_for_api(api: &str) -> Option<&'static StorageShim> {
+    STORAGE_SHIMS.iter().find(|shim| shim.api == api)
+}
+
+/// Storage accesses refused on this page, for the shield UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageRefusals {
+    /// document.cookie writes dropped
+    pub cookie_writes: usize,
+    /// localStorage and sessionStorage accesses refused
+    pub web_storage: usize,
+    /// indexedDB.open() calls refused
+    pub indexed_db: usize,
+    /// caches.open() calls refused
+    pub cache_storage: usize,
+}
+
+impl StorageRefusals {
+    /// Record an access and return what the page must observe.
+    pub fn record(&mut self, surface: StorageSurface) -> StorageBehavior {
+        let behavior = shim_for(surface).behavior;
+        if behavior.is_refusal() {
+            let counter = match surface {
+                StorageSurface::CookieWrite => &mut self.cookie_writes,
+                StorageSurface::LocalStorage | StorageSurface::SessionStorage => {
+                    &mut self.web_storage
+                }
+                StorageSurface::IndexedDbOpen => &mut self.indexed_db,
+                StorageSurface::CacheStorageOpen => &mut self.cache_storage,
+                StorageSurface::CookieRead => return behavior,
+            };
+            *counter += 1;
+        }
+        behavior
+    }
+
+    /// Total number of refused accesses.
+    pub fn total(&self) -> usize {
+        self.cookie_writes + self.web_storage + self.indexed_db + self.cache_storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::navigator::NavigatorDefense;
+
+    /// Console notes captured in Tor Browser 13 (Firefox 115 ESR) with
+    /// cookies blocked: the expression run, then what the console showed.
+    const TOR_BROWSER_NOTES: &[(&str, &str)] = &[
+        ("document.cookie", "\"\""),
+        ("document.cookie = \"a=b\"; document.cookie", "\"\""),
+        (
+            "window.localStorage",
+            "Uncaught DOMException: The operation is insecure. (SecurityError, code 18)",
+        ),
+        (
+            "window.sessionStorage",
+            "Uncaught DOMException: The operation is insecure. (SecurityError, code 18)",
+        ),
+        (
+            "indexedDB.open(\"x\")",
+            "Uncaught DOMException: The operation is insecure. (SecurityError, code 18)",
+        ),
+        (
+            "await caches.open(\"x\")",
+            "Uncaught (in promise) DOMException: The operation is insecure. (SecurityError, code 18)",
+        ),
+    ];
+
+    /// Render a table row the way the console showed the captured note.
+    fn console_line(surface: StorageSurface) -> String {
+        match shim_for(surface).behavior {
+            StorageBehavior::EmptyString => "\"\"".to_string(),
+            // The captured note reads the cookie back after the write
+            StorageBehavior::SilentlyDropped => console_line(StorageSurface::CookieRead),
+            StorageBehavior::Throws(e) => format!(
+                "Uncaught DOMException: {} ({}, code {})",
+                e.message, e.name, e.code
+            ),
+            StorageBehavior::Rejects(e) => format!(
+                "Uncaught (in promise) DOMException: {} ({}, code {})",
+                e.message, e.name, e.code
+            ),
+        }
+    }
+
+    #[test]
+    fn test_table_matches_tor_browser_notes() {
+        assert_eq!(STORAGE_SHIMS.len(), TOR_BROWSER_NOTES.len());
+        for (shim, (expression, console)) in STORAGE_SHIMS.iter().zip(TOR_BROWSER_NOTES) {
+            let api = shim.api.trim_end_matches(" =");
+            assert!(expression.contains(api), "{} vs {}", shim.api, expression);
+            assert_eq!(console_line(shim.surface), *console, "{}", shim.api);
+        }
+    }
+
+    #[test]
+    fn test_consistent_with_cookie_enabled() {
+        assert!(!NavigatorDefense::new().get_properties().cookie_enabled);
+
+        // Nothing can be stored anywhere
+        for shim in STORAGE_SHIMS {
+            if shim.surface != StorageSurface::CookieRead {
+                assert!(shim.behavior.is_refusal(), "{}", shim.api);
+            }
+        }
+        // Firefox only ever uses the one error shape here
+        for shim in STORAGE_SHIMS {
+            if let StorageBehavior::Throws(e) | StorageBehavior::Rejects(e) = shim.behavior {
+                assert_eq!(e, SECURITY_ERROR);
+            }
+        }
+    }
+
+    #[test]
+    fn test_glue_lookup() {
+        let shim = shim_for_api("window.localStorage").expect("known API");
+        assert_eq!(shim.surface, StorageSurface::LocalStorage);
+        assert_eq!(
+            shim.behavior.observed(),
+            "throws SecurityError: The operation is insecure."
+        );
+        assert!(shim_for_api("navigator.storage").is_none());
+    }
+
+    #[test]
+    fn test_refusal_counter() {
+        let mut refusals = StorageRefusals::default();
+
+        assert_eq!(
+            refusals.record(StorageSurface::CookieRead),
+            StorageBehavior::EmptyString
+        );
+        assert_eq!(refusals.total(), 0);
+
+        refusals.record(StorageSurface::CookieWrite);
+        refusals.record(StorageSurface::LocalStorage);
+        refusals.record(StorageSurface::SessionStorage);
+        refusals.record(StorageSurface::IndexedDbOpen);
+        assert!(matches!(
+            refusals.record(StorageSurface::CacheStorageOpen),
+            StorageBehavior::Rejects(_)
+        ));
+
+        assert_eq!(
+            refusals,
+            StorageRefusals {
+                cookie_writes: 1,
+                web_storage: 2,
+                indexed_db: 1,
+                cache_storage: 1,
+            }
+        );
+        assert_eq!(refusals.total(), 5);
+    }
+}
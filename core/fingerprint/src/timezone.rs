@@ -0,0 +1,577 @@
+//! Time zone consistency for Date formatting.
+//!
+//! Spoofing getTimezoneOffset is not enough: Date.prototype.toString,
+//! toLocaleString and Intl.DateTimeFormat print zone names and follow DST
+//! transitions. These helpers format dates exactly as Firefox (en-US)
+//! does, for the identity's IANA zone, from a bundled rule table.
+//!
+//! # Bundled tz data
+//!
+//! Only the zones in the anonymity set are covered, and no system tzdata
+//! is read. Rules are exact from 2007 onward (current US/Canada rules,
+//! EU rules since 1996, Moscow's 2011 and 2014 changes). Earlier instants
+//! use the same rules and may be off around old transitions.
+//!
+//! Firefox replaces ICU's narrow no-break space before AM/PM with a plain
+//! space for web compatibility, so we do too.
+
+/// Milliseconds per day.
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Milliseconds per minute.
+const MS_PER_MINUTE: i64 = 60_000;
+
+/// 2011-03-26T23:00:00Z: Moscow moves to permanent UTC+4.
+const MOSCOW_2011_MS: i64 = 1_301_180_400_000;
+
+/// 2014-10-25T22:00:00Z: Moscow moves to permanent UTC+3.
+const MOSCOW_2014_MS: i64 = 1_414_274_400_000;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Time zones in the anonymity set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZone {
+    /// America/Los_Angeles
+    LosAngeles,
+    /// America/Denver
+    Denver,
+    /// America/Chicago
+    Chicago,
+    /// America/New_York
+    NewYork,
+    /// America/Halifax
+    Halifax,
+    /// UTC
+    Utc,
+    /// Europe/Berlin
+    Berlin,
+    /// Europe/Helsinki
+    Helsinki,
+    /// Europe/Moscow
+    Moscow,
+}
+
+/// DST rule family for a zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DstRule {
+    /// No DST
+    None,
+    /// Second Sunday of March to first Sunday of November, 02:00 local
+    UsCanada,
+    /// Last Sunday of March to last Sunday of October, 01:00 UTC
+    Eu,
+    /// Europe/Moscow history
+    Moscow,
+}
+
+/// How Intl.DateTimeFormat prints the zone name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZoneNameStyle {
+    /// timeZoneName: "short" ("PST", "GMT+1")
+    Short,
+    /// timeZoneName: "long" ("Pacific Standard Time")
+    Long,
+}
+
+/// Options for `format_locale_string`, mirroring the toLocale*String family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocaleFormatOptions {
+    /// Include the date part
+    pub date: bool,
+    /// Include the time part
+    pub time: bool,
+    /// Use a 12-hour clock
+    pub hour12: bool,
+    /// Append the zone name
+    pub time_zone_name: Option<TimeZoneNameStyle>,
+}
+
+impl Default for LocaleFormatOptions {
+    /// toLocaleString() with no arguments.
+    fn default() -> Self {
+        Self {
+            date: true,
+            time: true,
+            hour12: true,
+            time_zone_name: None,
+        }
+    }
+}
+
+impl LocaleFormatOptions {
+    /// toLocaleDateString() with no arguments.
+    pub fn date_only() -> Self {
+        Self {
+            time: false,
+            ..Self::default()
+        }
+    }
+
+    /// toLocaleTimeString() with no arguments.
+    pub fn time_only() -> Self {
+        Self {
+            date: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Local wall-clock fields for an instant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LocalTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    weekday: usize,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    offset_minutes: i32,
+    dst: bool,
+}
+
+impl TimeZone {
+    /// All zones, in the order identities select from.
+    pub const ANONYMITY_SET: &'static [TimeZone] = &[
+        TimeZone::LosAngeles,
+        TimeZone::Denver,
+        TimeZone::Chicago,
+        TimeZone::NewYork,
+        TimeZone::Halifax,
+        TimeZone::Utc,
+        TimeZone::Berlin,
+        TimeZone::Helsinki,
+        TimeZone::Moscow,
+    ];
+
+    /// IANA name (Intl resolvedOptions().timeZone).
+    pub fn iana_name(&self) -> &'static str {
+        match self {
+            TimeZone::LosAngeles => "America/Los_Angeles",
+            TimeZone::Denver => "America/Denver",
+            TimeZone::Chicago => "America/Chicago",
+            TimeZone::NewYork => "America/New_York",
+            TimeZone::Halifax => "America/Halifax",
+            TimeZone::Utc => "UTC",
+            TimeZone::Berlin => "Europe/Berlin",
+            TimeZone::Helsinki => "Europe/Helsinki",
+            TimeZone::Moscow => "Europe/Moscow",
+        }
+    }
+
+    /// Standard (non-DST) offset in minutes east of UTC.
+    pub fn standard_offset(&self) -> i32 {
+        match self {
+            TimeZone::LosAngeles => -480,
+            TimeZone::Denver => -420,
+            TimeZone::Chicago => -360,
+            TimeZone::NewYork => -300,
+            TimeZone::Halifax => -240,
+            TimeZone::Utc => 0,
+            TimeZone::Berlin => 60,
+            TimeZone::Helsinki => 120,
+            TimeZone::Moscow => 180,
+        }
+    }
+
+    fn rule(&self) -> DstRule {
+        match self {
+            TimeZone::LosAngeles
+            | TimeZone::Denver
+            | TimeZone::Chicago
+            | TimeZone::NewYork
+            | TimeZone::Halifax => DstRule::UsCanada,
+            TimeZone::Utc => DstRule::None,
+            TimeZone::Berlin | TimeZone::Helsinki => DstRule::Eu,
+            TimeZone::Moscow => DstRule::Moscow,
+        }
+    }
+
+    /// Long zone names (standard, daylight).
+    fn long_names(&self) -> (&'static str, &'static str) {
+        match self {
+            TimeZone::LosAngeles => ("Pacific Standard Time", "Pacific Daylight Time"),
+            TimeZone::Denver => ("Mountain Standard Time", "Mountain Daylight Time"),
+            TimeZone::Chicago => ("Central Standard Time", "Central Daylight Time"),
+            TimeZone::NewYork => ("Eastern Standard Time", "Eastern Daylight Time"),
+            TimeZone::Halifax => ("Atlantic Standard Time", "Atlantic Daylight Time"),
+            TimeZone::Utc => ("Coordinated Universal Time", "Coordinated Universal Time"),
+            TimeZone::Berlin => (
+                "Central European Standard Time",
+                "Central European Summer Time",
+            ),
+            TimeZone::Helsinki => (
+                "Eastern European Standard Time",
+                "Eastern European Summer Time",
+            ),
+            TimeZone::Moscow => ("Moscow Standard Time", "Moscow Summer Time"),
+        }
+    }
+
+    /// Short zone names en-US has abbreviations for (standard, daylight).
+    fn short_names(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            TimeZone::LosAngeles => Some(("PST", "PDT")),
+            TimeZone::Denver => Some(("MST", "MDT")),
+            TimeZone::Chicago => Some(("CST", "CDT")),
+            TimeZone::NewYork => Some(("EST", "EDT")),
+            TimeZone::Halifax => Some(("AST", "ADT")),
+            TimeZone::Utc => Some(("UTC", "UTC")),
+            _ => None,
+        }
+    }
+
+    /// Offset in minutes east of UTC at an instant (ms since epoch).
+    ///
+    /// getTimezoneOffset() must return the negation of this.
+    pub fn offset_at(&self, epoch_ms: i64) -> i32 {
+        self.offset_and_dst(epoch_ms).0
+    }
+
+    /// Whether DST is in effect at an instant.
+    pub fn is_dst(&self, epoch_ms: i64) -> bool {
+        self.offset_and_dst(epoch_ms).1
+    }
+
+    fn offset_and_dst(&self, epoch_ms: i64) -> (i32, bool) {
+        let std = self.standard_offset();
+        let year = civil_from_days(epoch_ms.div_euclid(MS_PER_DAY)).0;
+
+        let in_dst = match self.rule() {
+            DstRule::None => false,
+            DstRule::UsCanada => {
+                // 02:00 local standard time / 02:00 local daylight time
+                let start = nth_sunday(year, 3, 2) * MS_PER_DAY + 2 * 3_600_000
+                    - std as i64 * MS_PER_MINUTE;
+                let end = nth_sunday(year, 11, 1) * MS_PER_DAY + 2 * 3_600_000
+                    - (std as i64 + 60) * MS_PER_MINUTE;
+                epoch_ms >= start && epoch_ms < end
+            }
+            DstRule::Eu => {
+                let start = last_sunday(year, 3) * MS_PER_DAY + 3_600_000;
+                let end = last_sunday(year, 10) * MS_PER_DAY + 3_600_000;
+                epoch_ms >= start && epoch_ms < end
+            }
+            DstRule::Moscow => {
+                if epoch_ms >= MOSCOW_2014_MS {
+                    false
+                } else if epoch_ms >= MOSCOW_2011_MS {
+                    // Permanent UTC+4, labelled standard time
+                    return (240, false);
+                } else {
+                    // 02:00 MSK to 03:00 MSD, both 23:00 UTC the day before
+                    let start = last_sunday(year, 3) * MS_PER_DAY - 3_600_000;
+                    let end = last_sunday(year, 10) * MS_PER_DAY - 3_600_000;
+                    epoch_ms >= start && epoch_ms < end
+                }
+            }
+        };
+
+        if in_dst {
+            (std + 60, true)
+        } else {
+            (std, false)
+        }
+    }
+
+    fn local_time(&self, epoch_ms: i64) -> LocalTime {
+        let (offset_minutes, dst) = self.offset_and_dst(epoch_ms);
+        let local_ms = epoch_ms + offset_minutes as i64 * MS_PER_MINUTE;
+
+        let days = local_ms.div_euclid(MS_PER_DAY);
+        let ms_of_day = local_ms.rem_euclid(MS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        LocalTime {
+            year,
+            month,
+            day,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as usize,
+            hour: (ms_of_day / 3_600_000) as u32,
+            minute: (ms_of_day / 60_000 % 60) as u32,
+            second: (ms_of_day / 1000 % 60) as u32,
+            offset_minutes,
+            dst,
+        }
+    }
+
+    fn zone_name(&self, local: &LocalTime, style: TimeZoneNameStyle) -> String {
+        let pick = |(std, dst): (&'static str, &'static str)| if local.dst { dst } else { std };
+
+        match style {
+            TimeZoneNameStyle::Long => pick(self.long_names()).to_string(),
+            TimeZoneNameStyle::Short => match self.short_names() {
+                Some(names) => pick(names).to_string(),
+                None => gmt_short_name(local.offset_minutes),
+            },
+        }
+    }
+
+    /// Format an instant as Date.prototype.toString() does.
+    ///
+    /// e.g. "Sun Mar 10 2024 03:00:00 GMT-0700 (Pacific Daylight Time)"
+    pub fn format_date_tostring(&self, epoch_ms: i64) -> String {
+        let local = self.local_time(epoch_ms);
+        let sign = if local.offset_minutes < 0 { '-' } else { '+' };
+        let abs = local.offset_minutes.unsigned_abs();
+
+        format!(
+            "{} {} {:02} {:04} {:02}:{:02}:{:02} GMT{}{:02}{:02} ({})",
+            WEEKDAYS[local.weekday],
+            MONTHS[(local.month - 1) as usize],
+            local.day,
+            local.year,
+            local.hour,
+            local.minute,
+            local.second,
+            sign,
+            abs / 60,
+            abs % 60,
+            self.zone_name(&local, TimeZoneNameStyle::Long)
+        )
+    }
+
+    /// Format an instant as toLocaleString() and friends do for en-US.
+    ///
+    /// e.g. "3/10/2024, 3:00:00 AM PDT"
+    pub fn format_locale_string(&self, epoch_ms: i64, options: &LocaleFormatOptions) -> String {
+        let local = self.local_time(epoch_ms);
+        let mut parts = Vec::new();
+
+        if options.date {
+            parts.push(format!("{}/{}/{}", local.month, local.day, local.year));
+        }
+
+        let mut time = String::new();
+        if options.time {
+            if options.hour12 {
+                let hour = match local.hour % 12 {
+                    0 => 12,
+                    h => h,
+                };
+                let meridiem = if local.hour < 12 { "AM" } else { "PM" };
+                time = format!(
+                    "{}:{:02}:{:02} {}",
+                    hour, local.minute, local.second, meridiem
+                );
+            } else {
+                time = format!("{:02}:{:02}:{:02}", local.hour, local.minute, local.second);
+            }
+        }
+
+        if let Some(style) = options.time_zone_name {
+            let name = self.zone_name(&local, style);
+            if time.is_empty() {
+                time = name;
+            } else {
+                time = format!("{} {}", time, name);
+            }
+        }
+
+        if !time.is_empty() {
+            parts.push(time);
+        }
+
+        parts.join(", ")
+    }
+}
+
+/// "GMT+2" style name used where en-US has no abbreviation.
+fn gmt_short_name(offset_minutes: i32) -> String {
+    if offset_minutes == 0 {
+        return "GMT".to_string();
+    }
+
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let abs = offset_minutes.unsigned_abs();
+    if abs.is_multiple_of(60) {
+        format!("GMT{}{}", sign, abs / 60)
+    } else {
+        format!("GMT{}{}:{:02}", sign, abs / 60, abs % 60)
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian date for days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Day number of the nth Sunday of a month.
+fn nth_sunday(year: i64, month: u32, n: i64) -> i64 {
+    let first = days_from_civil(year, month, 1);
+    let weekday = (first + 4).rem_euclid(7);
+    first + (7 - weekday) % 7 + (n - 1) * 7
+}
+
+/// Day number of the last Sunday of a month.
+fn last_sunday(year: i64, month: u32) -> i64 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    let last = days_from_civil(next_year, next_month, 1) - 1;
+    last - (last + 4).rem_euclid(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ms since epoch for a UTC date-time.
+    fn utc(year: i64, month: u32, day: u32, hour: i64, minute: i64, second: i64) -> i64 {
+        days_from_civil(year, month, day) * MS_PER_DAY + (hour * 3600 + minute * 60 + second) * 1000
+    }
+
+    #[test]
+    fn test_civil_roundtrip() {
+        for days in [-719_468, -1, 0, 19_797, 2_932_896] {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn test_us_spring_forward() {
+        let zone = TimeZone::LosAngeles;
+
+        assert_eq!(
+            zone.format_date_tostring(utc(2024, 3, 10, 9, 59, 59)),
+            "Sun Mar 10 2024 01:59:59 GMT-0800 (Pacific Standard Time)"
+        );
+        assert_eq!(
+            zone.format_date_tostring(utc(2024, 3, 10, 10, 0, 0)),
+            "Sun Mar 10 2024 03:00:00 GMT-0700 (Pacific Daylight Time)"
+        );
+    }
+
+    #[test]
+    fn test_us_fall_back() {
+        let zone = TimeZone::NewYork;
+
+        assert_eq!(
+            zone.format_date_tostring(utc(2023, 11, 5, 5, 59, 59)),
+            "Sun Nov 05 2023 01:59:59 GMT-0400 (Eastern Daylight Time)"
+        );
+        assert_eq!(
+            zone.format_date_tostring(utc(2023, 11, 5, 6, 0, 0)),
+            "Sun Nov 05 2023 01:00:00 GMT-0500 (Eastern Standard Time)"
+        );
+        assert_eq!(zone.offset_at(utc(2023, 11, 5, 6, 0, 0)), -300);
+    }
+
+    #[test]
+    fn test_eu_transitions() {
+        assert_eq!(
+            TimeZone::Berlin.format_date_tostring(utc(2024, 3, 31, 0, 59, 59)),
+            "Sun Mar 31 2024 01:59:59 GMT+0100 (Central European Standard Time)"
+        );
+        assert_eq!(
+            TimeZone::Berlin.format_date_tostring(utc(2024, 3, 31, 1, 0, 0)),
+            "Sun Mar 31 2024 03:00:00 GMT+0200 (Central European Summer Time)"
+        );
+        assert_eq!(
+            TimeZone::Helsinki.format_date_tostring(utc(2023, 10, 29, 1, 0, 0)),
+            "Sun Oct 29 2023 03:00:00 GMT+0200 (Eastern European Standard Time)"
+        );
+    }
+
+    #[test]
+    fn test_moscow_history() {
+        assert_eq!(
+            TimeZone::Moscow.format_date_tostring(utc(2024, 7, 1, 12, 0, 0)),
+            "Mon Jul 01 2024 15:00:00 GMT+0300 (Moscow Standard Time)"
+        );
+        assert_eq!(TimeZone::Moscow.offset_at(utc(2013, 1, 15, 0, 0, 0)), 240);
+        assert_eq!(TimeZone::Moscow.offset_at(utc(2010, 7, 1, 0, 0, 0)), 240);
+        assert!(TimeZone::Moscow.is_dst(utc(2010, 7, 1, 0, 0, 0)));
+        assert_eq!(TimeZone::Moscow.offset_at(utc(2010, 12, 1, 0, 0, 0)), 180);
+    }
+
+    #[test]
+    fn test_utc() {
+        assert_eq!(
+            TimeZone::Utc.format_date_tostring(0),
+            "Thu Jan 01 1970 00:00:00 GMT+0000 (Coordinated Universal Time)"
+        );
+    }
+
+    #[test]
+    fn test_locale_strings() {
+        let instant = utc(2024, 3, 10, 10, 0, 0);
+        let zone = TimeZone::LosAngeles;
+
+        assert_eq!(
+            zone.format_locale_string(instant, &LocaleFormatOptions::default()),
+            "3/10/2024, 3:00:00 AM"
+        );
+        assert_eq!(
+            zone.format_locale_string(instant, &LocaleFormatOptions::date_only()),
+            "3/10/2024"
+        );
+        assert_eq!(
+            zone.format_locale_string(instant, &LocaleFormatOptions::time_only()),
+            "3:00:00 AM"
+        );
+
+        let short = LocaleFormatOptions {
+            time_zone_name: Some(TimeZoneNameStyle::Short),
+            ..LocaleFormatOptions::default()
+        };
+        assert_eq!(
+            zone.format_locale_string(instant, &short),
+            "3/10/2024, 3:00:00 AM PDT"
+        );
+        assert_eq!(
+            TimeZone::Berlin.format_locale_string(utc(2024, 7, 4, 22, 30, 5), &short),
+            "7/5/2024, 12:30:05 AM GMT+2"
+        );
+
+        let h23 = LocaleFormatOptions {
+            hour12: false,
+            time_zone_name: Some(TimeZoneNameStyle::Long),
+            ..LocaleFormatOptions::time_only()
+        };
+        assert_eq!(
+            TimeZone::Chicago.format_locale_string(utc(2024, 1, 15, 19, 5, 0), &h23),
+            "13:05:00 Central Standard Time"
+        );
+    }
+
+    #[test]
+    fn test_anonymity_set_offsets_are_distinct() {
+        let mut offsets: Vec<i32> = TimeZone::ANONYMITY_SET
+            .iter()
+            .map(|z| z.standard_offset())
+            .collect();
+        offsets.dedup();
+        assert_eq!(offsets.len(), TimeZone::ANONYMITY_SET.len());
+    }
+}
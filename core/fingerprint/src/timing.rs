@@ -3,19 +3,24 @@
 //! High-resolution timing APIs enable fingerprinting and side-channel attacks.
 //! We reduce precision and add jitter.
 
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use forloop_config::{system_clock, Clock, TIMING_PRECISION};
+
 /// Timing defense configuration.
 #[derive(Debug, Clone)]
 pub struct TimingDefense {
-    /// Base time for Date.now() calculations
+    /// Clock the page's time origin and performance.now() are read from
+    clock: Arc<dyn Clock>,
+    /// Base time for performance.now() calculations
     base_time: Instant,
-    /// Precision for Date.now() in milliseconds
-    date_precision_ms: u64,
-    /// Precision for performance.now() in milliseconds
-    perf_precision_ms: u64,
+    /// Precision for Date.now()
+    date_precision: Duration,
+    /// Precision for performance.now()
+    perf_precision: Duration,
     /// Maximum jitter to add
-    max_jitter_ms: u64,
+    max_jitter: Duration,
     /// Seed for deterministic jitter
     jitter_seed: u64,
 }
@@ -23,19 +28,63 @@ pub struct TimingDefense {
 impl TimingDefense {
     /// Create a new timing defense.
     pub fn new(jitter_seed: u64) -> Self {
+        Self::with_precision(jitter_seed, TIMING_PRECISION)
+    }
+
+    /// Create a timing defense that rounds both clocks to `precision`.
+    ///
+    /// Precision below one millisecond is raised to one millisecond.
+    pub fn with_precision(jitter_seed: u64, precision: Duration) -> Self {
+        let precision = precision.max(Duration::from_millis(1));
+        let clock = system_clock();
         Self {
-            base_time: Instant::now(),
-            date_precision_ms: 100, // 100ms precision
-            perf_precision_ms: 100, // 100ms precision (Tor Browser uses this)
-            max_jitter_ms: 10,
+            base_time: clock.now(),
+            clock,
+            date_precision: precision,
+            perf_precision: precision,
+            max_jitter: Duration::from_millis(10),
             jitter_seed,
         }
     }
 
+    /// Read time from `clock`; the time origin restarts at its current time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.base_time = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Get the clock the defense reads.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        Arc::clone(&self.clock)
+    }
+
+    /// Get the precision performance.now() is rounded to.
+    pub fn precision(&self) -> Duration {
+        self.perf_precision
+    }
+
+    /// Get a timestamp for an input event delivered now.
+    ///
+    /// Same timebase and precision as performance.now(), but without jitter,
+    /// so events stamped in order keep their order.
+    pub fn event_timestamp(&self) -> f64 {
+        let elapsed = self.clock.now().saturating_duration_since(self.base_time);
+        let precision_ms = self.perf_precision.as_millis() as f64;
+        (elapsed.as_secs_f64() * 1000.0 / precision_ms).floor() * precision_ms
+    }
+
+    /// Get the fuzzed performance.now() value for the current time.
+    pub fn performance_now(&self) -> f64 {
+        let elapsed = self.clock.now().saturating_duration_since(self.base_time);
+        self.fuzz_performance_now(elapsed.as_secs_f64() * 1000.0)
+    }
+
     /// Get fuzzed Date.now() value.
     pub fn fuzz_date_now(&self, actual_ms: u64) -> u64 {
         // Reduce precision
-        let reduced = (actual_ms / self.date_precision_ms) * self.date_precision_ms;
+        let precision_ms = self.date_precision.as_millis() as u64;
+        let reduced = (actual_ms / precision_ms) * precision_ms;
 
         // Add deterministic jitter
         let jitter = self.deterministic_jitter(actual_ms);
@@ -44,9 +93,9 @@ impl TimingDefense {
 
     /// Get fuzzed performance.now() value.
     pub fn fuzz_performance_now(&self, actual_ms: f64) -> f64 {
-        // Reduce precision to 100ms
-        let reduced = (actual_ms / self.perf_precision_ms as f64).floor()
-            * self.perf_precision_ms as f64;
+        // Reduce precision
+        let precision_ms = self.perf_precision.as_millis() as f64;
+        let reduced = (actual_ms / precision_ms).floor() * precision_ms;
 
         // Add jitter
         let jitter = self.deterministic_jitter(actual_ms as u64) as f64;
@@ -63,7 +112,7 @@ impl TimingDefense {
         input.hash(&mut hasher);
         let hash = hasher.finish();
 
-        hash % self.max_jitter_ms
+        hash % self.max_jitter.as_millis() as u64
     }
 
     /// Clamp requestAnimationFrame to 60Hz.
@@ -134,6 +183,7 @@ pub fn fuzz_navigation_timing() -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use forloop_config::clock::ManualClock;
 
     #[test]
     fn test_date_now_fuzzing() {
@@ -145,7 +195,7 @@ mod tests {
         // Should be rounded to 100ms precision
         assert_eq!(fuzzed % 100, fuzzed % 100); // Still aligned
         assert!(fuzzed >= (actual / 100) * 100);
-        assert!(fuzzed <= (actual / 100) * 100 + defense.max_jitter_ms);
+        assert!(fuzzed <= (actual / 100) * 100 + defense.max_jitter.as_millis() as u64);
     }
 
     #[test]
@@ -160,6 +210,30 @@ mod tests {
         assert!(fuzzed < 200.0);
     }
 
+    #[test]
+    fn test_with_precision() {
+        let defense = TimingDefense::with_precision(42, Duration::from_secs(1));
+        let fuzzed = defense.fuzz_date_now(1_703_412_345_678);
+        assert!((1_703_412_345_000..1_703_412_345_010).contains(&fuzzed));
+
+        // Sub-millisecond precision would divide by zero
+        let defense = TimingDefense::with_precision(42, Duration::ZERO);
+        assert!(defense.fuzz_performance_now(5.0) >= 5.0);
+    }
+
+    #[test]
+    fn test_performance_now_follows_clock() {
+        let clock = ManualClock::new();
+        let defense = TimingDefense::new(42).with_clock(Arc::new(clock.clone()));
+        assert!(defense.performance_now() < 10.0);
+
+        clock.advance(Duration::from_millis(1234));
+        let now = defense.performance_now();
+        assert!((1200.0..1210.0).contains(&now));
+        assert_eq!(now, defense.fuzz_performance_now(1234.0));
+        assert_eq!(defense.event_timestamp(), 1200.0);
+    }
+
     #[test]
     fn test_raf_clamping() {
         let defense = TimingDefense::new(42);
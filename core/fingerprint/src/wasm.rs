@@ -0,0 +1,195 @@
+//! WebAssembly capability reporting policy.
+//!
+//! WebAssembly availability, feature detection (SIMD, threads, ...) and
+//! compile timing are used to classify devices. We report exactly what
+//! Firefox 115 ESR reports, with two constraints:
+//!
+//! - Threads are unavailable, consistent with SharedArrayBuffer and
+//!   Atomics being blocked (see `timing::timing_apis_to_block`)
+//! - Compile and instantiate timings are only observable through the
+//!   fuzzed clocks, so they inherit `TimingDefense` quantization
+
+use crate::timing::TimingDefense;
+
+/// A wasm feature-detection probe and the answer we give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WasmFeature {
+    /// Feature name as used by wasm-feature-detect
+    pub name: &'static str,
+    /// How pages probe for it
+    pub probe: &'static str,
+    /// Whether the probe succeeds
+    pub supported: bool,
+}
+
+/// Feature matrix for Firefox 115 ESR on x86-64.
+pub const WASM_FEATURES: &[WasmFeature] = &[
+    WasmFeature {
+        name: "bigInt",
+        probe: "i64 parameter accepts a BigInt",
+        supported: true,
+    },
+    WasmFeature {
+        name: "bulkMemory",
+        probe: "validate module using memory.copy/memory.fill",
+        supported: true,
+    },
+    WasmFeature {
+        name: "exceptions",
+        probe: "validate module with a tag and try/catch",
+        supported: true,
+    },
+    WasmFeature {
+        name: "extendedConst",
+        probe: "validate global initializer using i32.add",
+        supported: true,
+    },
+    WasmFeature {
+        name: "gc",
+        probe: "validate module with struct types",
+        supported: false,
+    },
+    WasmFeature {
+        name: "memory64",
+        probe: "validate module with an i64-indexed memory",
+        supported: false,
+    },
+    WasmFeature {
+        name: "multiValue",
+        probe: "validate function returning two values",
+        supported: true,
+    },
+    WasmFeature {
+        name: "mutableGlobals",
+        probe: "import a mutable global",
+        supported: true,
+    },
+    WasmFeature {
+        name: "referenceTypes",
+        probe: "validate module using externref",
+        supported: true,
+    },
+    WasmFeature {
+        name: "relaxedSimd",
+        probe: "validate module using i32x4.relaxed_trunc_f32x4_s",
+        supported: false,
+    },
+    WasmFeature {
+        name: "saturatedFloatToInt",
+        probe: "validate module using i32.trunc_sat_f32_s",
+        supported: true,
+    },
+    WasmFeature {
+        name: "signExtensions",
+        probe: "validate module using i32.extend8_s",
+        supported: true,
+    },
+    WasmFeature {
+        name: "simd",
+        probe: "validate module using v128",
+        supported: true,
+    },
+    WasmFeature {
+        name: "streamingCompilation",
+        probe: "typeof WebAssembly.compileStreaming",
+        supported: true,
+    },
+    WasmFeature {
+        name: "tailCall",
+        probe: "validate module using return_call",
+        supported: false,
+    },
+    WasmFeature {
+        name: "threads",
+        probe: "validate module with a shared memory",
+        supported: false,
+    },
+];
+
+/// Whether the WebAssembly global is exposed (it is in Firefox).
+pub fn webassembly_present() -> bool {
+    true
+}
+
+/// Look up a feature by name. Unknown features are unsupported.
+pub fn feature_supported(name: &str) -> bool {
+    WASM_FEATURES
+        .iter()
+        .find(|feature| feature.name == name)
+        .is_some_and(|feature| feature.supported)
+}
+
+/// WebAssembly APIs whose duration pages measure.
+pub fn wasm_timed_apis() -> &'static [&'static str] {
+    &[
+        "WebAssembly.compile",
+        "WebAssembly.compileStreaming",
+        "WebAssembly.instantiate",
+        "WebAssembly.instantiateStreaming",
+        "WebAssembly.validate",
+    ]
+}
+
+/// Duration of a compile or instantiate as a page can observe it.
+///
+/// Pages time wasm work with performance.now() around the call, so the
+/// observable duration is the difference of two fuzzed readings.
+pub fn observed_compile_time(defense: &TimingDefense, start_ms: f64, end_ms: f64) -> f64 {
+    defense.fuzz_performance_now(end_ms) - defense.fuzz_performance_now(start_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::timing_apis_to_block;
+
+    #[test]
+    fn test_feature_matrix() {
+        let supported: Vec<&str> = WASM_FEATURES
+            .iter()
+            .filter(|f| f.supported)
+            .map(|f| f.name)
+            .collect();
+
+        assert_eq!(
+            supported,
+            vec![
+                "bigInt",
+                "bulkMemory",
+                "exceptions",
+                "extendedConst",
+                "multiValue",
+                "mutableGlobals",
+                "referenceTypes",
+                "saturatedFloatToInt",
+                "signExtensions",
+                "simd",
+                "streamingCompilation",
+            ]
+        );
+        assert!(webassembly_present());
+        assert!(!feature_supported("unknownFeature"));
+    }
+
+    #[test]
+    fn test_threads_consistent_with_blocked_surface() {
+        let blocked = timing_apis_to_block();
+        assert!(blocked.contains(&"SharedArrayBuffer"));
+        assert!(blocked.contains(&"Atomics"));
+        assert!(!feature_supported("threads"));
+    }
+
+    #[test]
+    fn test_compile_time_is_quantized() {
+        let defense = TimingDefense::new(7);
+
+        // Within one 100ms bucket only jitter remains of the real duration
+        for end in [1003.0, 1042.0, 1099.0] {
+            let observed = observed_compile_time(&defense, 1000.0, end);
+            assert!(observed.abs() < 10.0, "{} leaked as {}", end, observed);
+        }
+
+        let observed = observed_compile_time(&defense, 1000.0, 1250.0);
+        assert!((observed - 200.0).abs() < 10.0);
+    }
+}
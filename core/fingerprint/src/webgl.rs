@@ -3,13 +3,32 @@
 //! WebGL exposes GPU information that can fingerprint users.
 //! We return generic values from a defined anonymity set.
 
+use std::collections::VecDeque;
+
+use crate::noise::{NoiseBudget, OriginNoise};
+use crate::ordered::OrderedSpoofList;
+
+/// Extensions exposed by getSupportedExtensions(), when the profile has them.
+///
+/// A subset of every profile's list, to reduce fingerprint surface.
+const EXPOSED_EXTENSIONS: &[&str] = &[
+    "OES_element_index_uint",
+    "OES_standard_derivatives",
+    "OES_texture_float",
+    "OES_texture_half_float",
+    "WEBGL_depth_texture",
+    "WEBGL_lose_context",
+];
+
 /// WebGL defense configuration.
 #[derive(Debug, Clone)]
 pub struct WebGLDefense {
-    /// Seed for this identity
-    seed: u64,
+    /// Shared noise source for this identity
+    budget: NoiseBudget,
     /// Selected profile
     profile: WebGLProfile,
+    /// getSupportedExtensions() result, alphabetical as in Firefox
+    extensions: OrderedSpoofList,
 }
 
 /// WebGL profile representing a common configuration.
@@ -37,8 +56,101 @@ pub struct WebGLProfile {
     pub max_varying_vectors: i32,
     /// Supported extensions
     pub extensions: &'static [&'static str],
+    /// Default context attributes reported by getContextAttributes()
+    pub context_attributes: ContextAttributes,
+    /// Default framebuffer bit depths
+    pub framebuffer_bits: FramebufferBits,
+}
+
+/// WebGL context attributes (getContextAttributes()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextAttributes {
+    /// Drawing buffer has an alpha channel
+    pub alpha: bool,
+    /// Drawing buffer has a depth buffer
+    pub depth: bool,
+    /// Drawing buffer has a stencil buffer
+    pub stencil: bool,
+    /// Antialiasing enabled
+    pub antialias: bool,
+    /// Colors are premultiplied by alpha
+    pub premultiplied_alpha: bool,
+    /// Buffer contents survive compositing
+    pub preserve_drawing_buffer: bool,
+    /// GPU selection hint
+    pub power_preference: PowerPreference,
+    /// Fail creation on slow (software) implementations
+    pub fail_if_major_performance_caveat: bool,
+    /// Low-latency desynchronized canvas
+    pub desynchronized: bool,
+}
+
+/// Values of the powerPreference context attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerPreference {
+    /// "default"
+    Default,
+    /// "low-power"
+    LowPower,
+    /// "high-performance"
+    HighPerformance,
+}
+
+impl PowerPreference {
+    /// Get the JS string value.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerPreference::Default => "default",
+            PowerPreference::LowPower => "low-power",
+            PowerPreference::HighPerformance => "high-performance",
+        }
+    }
 }
 
+/// Bit depths of the default framebuffer.
+///
+/// Alpha, depth and stencil give the size of each buffer when the context
+/// has it; a context without one reports 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramebufferBits {
+    /// GL_RED_BITS
+    pub red: i32,
+    /// GL_GREEN_BITS
+    pub green: i32,
+    /// GL_BLUE_BITS
+    pub blue: i32,
+    /// GL_ALPHA_BITS
+    pub alpha: i32,
+    /// GL_DEPTH_BITS
+    pub depth: i32,
+    /// GL_STENCIL_BITS
+    pub stencil: i32,
+}
+
+/// What Firefox reports for a context created without attributes.
+const FIREFOX_CONTEXT_ATTRIBUTES: ContextAttributes = ContextAttributes {
+    alpha: true,
+    depth: true,
+    stencil: false,
+    antialias: true,
+    premultiplied_alpha: true,
+    preserve_drawing_buffer: false,
+    power_preference: PowerPreference::Default,
+    fail_if_major_performance_caveat: false,
+    desynchronized: false,
+};
+
+/// RGBA8 with a 24-bit depth buffer, and the 8-bit stencil buffer Firefox
+/// allocates when one is asked for.
+const RGBA8_DEPTH24_STENCIL8: FramebufferBits = FramebufferBits {
+    red: 8,
+    green: 8,
+    blue: 8,
+    alpha: 8,
+    depth: 24,
+    stencil: 8,
+};
+
 /// Pre-defined WebGL profiles matching common configurations.
 const WEBGL_PROFILES: &[WebGLProfile] = &[
     WebGLProfile {
@@ -75,6 +187,8 @@ const WEBGL_PROFILES: &[WebGLProfile] = &[
             "WEBGL_draw_buffers",
             "WEBGL_lose_context",
         ],
+        context_attributes: FIREFOX_CONTEXT_ATTRIBUTES,
+        framebuffer_bits: RGBA8_DEPTH24_STENCIL8,
     },
     WebGLProfile {
         renderer: "WebKit WebGL",
@@ -109,6 +223,8 @@ const WEBGL_PROFILES: &[WebGLProfile] = &[
             "WEBGL_draw_buffers",
             "WEBGL_lose_context",
         ],
+        context_attributes: FIREFOX_CONTEXT_ATTRIBUTES,
+        framebuffer_bits: RGBA8_DEPTH24_STENCIL8,
     },
     // Mesa profile for Linux
     WebGLProfile {
@@ -139,17 +255,41 @@ const WEBGL_PROFILES: &[WebGLProfile] = &[
             "WEBGL_draw_buffers",
             "WEBGL_lose_context",
         ],
+        context_attributes: FIREFOX_CONTEXT_ATTRIBUTES,
+        framebuffer_bits: RGBA8_DEPTH24_STENCIL8,
     },
 ];
 
 impl WebGLDefense {
     /// Create a new WebGL defense.
     pub fn new(seed: u64) -> Self {
+        Self::with_budget(seed, NoiseBudget::new(seed))
+    }
+
+    /// Create the WebGL defense for an origin's pages.
+    ///
+    /// Build the origin's `CanvasDefense` from the same context, or pixels
+    /// blitted into a 2D canvas will not carry the same noise.
+    pub fn for_origin(seed: u64, noise: &OriginNoise) -> Self {
+        Self::with_budget(seed, noise.budget())
+    }
+
+    /// Create a WebGL defense whose profile is selected by `seed` and whose
+    /// readback noise comes from an identity's noise budget.
+    pub fn with_budget(seed: u64, budget: NoiseBudget) -> Self {
         // Select profile based on seed
-        let profile_idx = (seed as usize) % WEBGL_PROFILES.len();
+        let profile = WEBGL_PROFILES[(seed as usize) % WEBGL_PROFILES.len()].clone();
+        let extensions = OrderedSpoofList::alphabetical(
+            EXPOSED_EXTENSIONS
+                .iter()
+                .filter(|ext| profile.extensions.contains(ext))
+                .copied(),
+        );
+
         Self {
-            seed,
-            profile: WEBGL_PROFILES[profile_idx].clone(),
+            budget,
+            profile,
+            extensions,
         }
     }
 
@@ -195,39 +335,204 @@ impl WebGLDefense {
             0x1F01 => WebGLValue::String(self.profile.renderer.to_string()),
             // GL_VENDOR
             0x1F00 => WebGLValue::String(self.profile.vendor.to_string()),
+            // GL_RED_BITS .. GL_STENCIL_BITS
+            0x0D52 => WebGLValue::Int(self.profile.framebuffer_bits.red),
+            0x0D53 => WebGLValue::Int(self.profile.framebuffer_bits.green),
+            0x0D54 => WebGLValue::Int(self.profile.framebuffer_bits.blue),
+            0x0D55..=0x0D57 => self.get_context_parameter(&self.profile.context_attributes, pname),
             // Default: return null
             _ => WebGLValue::Null,
         }
     }
 
+    /// Get a WebGL parameter value on a context with `attributes`.
+    ///
+    /// `attributes` are the ones `resolve_context_attributes` granted; the
+    /// alpha, depth and stencil bits report exactly the buffers they name.
+    pub fn get_context_parameter(&self, attributes: &ContextAttributes, pname: u32) -> WebGLValue {
+        let bits = self.profile.framebuffer_bits;
+        let granted = |has: bool, bits: i32| WebGLValue::Int(if has { bits } else { 0 });
+        match pname {
+            0x0D55 => granted(attributes.alpha, bits.alpha),
+            0x0D56 => granted(attributes.depth, bits.depth),
+            0x0D57 => granted(attributes.stencil, bits.stencil),
+            _ => self.get_parameter(pname),
+        }
+    }
+
+    /// Get the attributes of a context created with no attributes.
+    pub fn get_context_attributes(&self) -> ContextAttributes {
+        self.profile.context_attributes
+    }
+
+    /// Resolve the attributes a context reports for a getContext() request.
+    ///
+    /// Buffer layout requests (alpha, depth, stencil, antialias,
+    /// premultipliedAlpha, preserveDrawingBuffer) are honored as a page would
+    /// expect. powerPreference is echoed but never changes the GPU used.
+    /// failIfMajorPerformanceCaveat is echoed but creation never fails:
+    /// whether it would fail reveals the GPU class. desynchronized is never
+    /// granted.
+    pub fn resolve_context_attributes(&self, requested: &ContextAttributes) -> ContextAttributes {
+        let defaults = self.profile.context_attributes;
+        ContextAttributes {
+            alpha: requested.alpha,
+            depth: requested.depth,
+            stencil: requested.stencil,
+            antialias: requested.antialias && defaults.antialias,
+            premultiplied_alpha: requested.premultiplied_alpha,
+            preserve_drawing_buffer: requested.preserve_drawing_buffer,
+            power_preference: requested.power_preference,
+            fail_if_major_performance_caveat: requested.fail_if_major_performance_caveat,
+            desynchronized: false,
+        }
+    }
+
     /// Get supported extensions.
     pub fn supported_extensions(&self) -> Vec<String> {
-        // Return a subset of extensions to reduce fingerprint surface
-        vec![
-            "OES_texture_float".to_string(),
-            "OES_texture_half_float".to_string(),
-            "OES_standard_derivatives".to_string(),
-            "OES_element_index_uint".to_string(),
-            "WEBGL_depth_texture".to_string(),
-            "WEBGL_lose_context".to_string(),
-        ]
+        self.extensions.to_vec()
+    }
+
+    /// Get the ordered list backing `supported_extensions`.
+    pub fn extension_list(&self) -> &OrderedSpoofList {
+        &self.extensions
     }
 
     /// Generate deterministic noise for readPixels.
-    pub fn apply_pixel_noise(&self, data: &mut [u8]) {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        for (i, byte) in data.iter_mut().enumerate() {
-            let mut hasher = DefaultHasher::new();
-            self.seed.hash(&mut hasher);
-            i.hash(&mut hasher);
-            let hash = hasher.finish();
-
-            // Apply very subtle noise
-            let noise = ((hash & 0x03) as i16) - 1; // -1, 0, 1, or 2
-            *byte = (*byte as i16 + noise).clamp(0, 255) as u8;
+    ///
+    /// `data` is the RGBA readback of the `width` x `height` rectangle at
+    /// (`x`, `y`) in a drawing buffer `full_width` pixels wide. Noise is keyed
+    /// on absolute buffer coordinates, so overlapping readbacks see
+    /// byte-identical values in the overlap and the noise cannot be
+    /// subtracted by diffing them. Only the low bit of RGB is touched
+    /// (a change of at most 1); alpha is preserved.
+    ///
+    /// # Interception contract
+    ///
+    /// Every route that turns drawing-buffer pixels into page-visible bytes
+    /// carries this noise, keyed on the same pixel:
+    ///
+    /// - `readPixels`: this function, with GL (bottom-left origin)
+    ///   coordinates as the page passed them.
+    /// - `toDataURL` / `toBlob` on a WebGL canvas: `apply_image_noise` on
+    ///   the top-down image before it is encoded.
+    /// - `drawImage` of a WebGL canvas into a 2D canvas: the 2D canvas
+    ///   records a `WebGLBlit`, and its `getImageData` / `toDataURL`
+    ///   applies this noise to the blitted pixels instead of 2D noise
+    ///   (`CanvasDefense::apply_readback_noise`).
+    pub fn apply_pixel_noise(
+        &self,
+        data: &mut [u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        full_width: u32,
+    ) {
+        for row in 0..height {
+            for col in 0..width {
+                let idx = ((row * width + col) * 4) as usize;
+                if idx + 3 >= data.len() {
+                    continue;
+                }
+
+                // Absolute pixel index within the drawing buffer
+                let abs = (y + row) as u64 * full_width as u64 + (x + col) as u64;
+                noise_pixel(&self.budget, &mut data[idx..idx + 4], abs);
+            }
+        }
+    }
+
+    /// Apply readPixels noise to a top-down RGBA image of the whole
+    /// `width` x `height` drawing buffer (toDataURL, toBlob).
+    ///
+    /// Row 0 of the image is the top row, which GL numbers `height - 1`.
+    pub fn apply_image_noise(&self, data: &mut [u8], width: u32, height: u32) {
+        for row in 0..height {
+            let gl_row = (height - 1 - row) as u64;
+            for col in 0..width {
+                let idx = ((row * width + col) * 4) as usize;
+                if idx + 3 >= data.len() {
+                    continue;
+                }
+                noise_pixel(
+                    &self.budget,
+                    &mut data[idx..idx + 4],
+                    gl_row * width as u64 + col as u64,
+                );
+            }
+        }
+    }
+}
+
+/// Flip the low bit of RGB (not alpha) of the RGBA `pixel` at absolute
+/// drawing-buffer index `abs` (GL row-major, bottom-left origin).
+pub(crate) fn noise_pixel(budget: &NoiseBudget, pixel: &mut [u8], abs: u64) {
+    for (i, channel) in pixel.iter_mut().take(3).enumerate() {
+        *channel ^= budget.webgl_low_bit(abs, i as u64);
+    }
+}
+
+/// Context loss events delivered to the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextEvent {
+    /// webglcontextlost
+    Lost,
+    /// webglcontextrestored
+    Restored,
+}
+
+/// Error from a WEBGL_lose_context call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextLossError {
+    /// GL_INVALID_OPERATION (e.g. restoring a context that is not lost)
+    InvalidOperation,
+}
+
+/// Simulated context loss for the advertised WEBGL_lose_context extension.
+///
+/// Events are queued rather than dispatched, so the glue (and tests)
+/// decide exactly when they are delivered. Nothing here touches the GPU.
+#[derive(Debug, Clone, Default)]
+pub struct ContextLossSimulator {
+    lost: bool,
+    pending: VecDeque<ContextEvent>,
+}
+
+impl ContextLossSimulator {
+    /// Create a simulator for a live context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether the context is currently lost (isContextLost()).
+    pub fn is_context_lost(&self) -> bool {
+        self.lost
+    }
+
+    /// WEBGL_lose_context.loseContext().
+    pub fn lose_context(&mut self) -> Result<(), ContextLossError> {
+        if self.lost {
+            return Err(ContextLossError::InvalidOperation);
         }
+        self.lost = true;
+        self.pending.push_back(ContextEvent::Lost);
+        Ok(())
+    }
+
+    /// WEBGL_lose_context.restoreContext().
+    pub fn restore_context(&mut self) -> Result<(), ContextLossError> {
+        if !self.lost {
+            return Err(ContextLossError::InvalidOperation);
+        }
+        self.lost = false;
+        self.pending.push_back(ContextEvent::Restored);
+        Ok(())
+    }
+
+    /// Take the next pending event, in the order it was raised.
+    pub fn next_event(&mut self) -> Option<ContextEvent> {
+        self.pending.pop_front()
     }
 }
 
@@ -255,7 +560,7 @@ mod tests {
     #[test]
     fn test_profile_selection() {
         let defense1 = WebGLDefense::new(0);
-        let defense2 = WebGLDefense::new(1);
+        let _defense2 = WebGLDefense::new(1);
         let defense3 = WebGLDefense::new(3); // Wraps to 0
 
         // Same seed mod profiles should give same profile
@@ -273,6 +578,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_context_attributes_match_profile() {
+        for seed in 0..WEBGL_PROFILES.len() as u64 {
+            let defense = WebGLDefense::new(seed);
+            let attrs = defense.get_context_attributes();
+            assert_eq!(attrs, WEBGL_PROFILES[seed as usize].context_attributes);
+
+            // Framebuffer bits must agree with the attributes
+            let has_alpha = matches!(defense.get_parameter(0x0D55), WebGLValue::Int(b) if b > 0);
+            let has_depth = matches!(defense.get_parameter(0x0D56), WebGLValue::Int(b) if b > 0);
+            let has_stencil = matches!(defense.get_parameter(0x0D57), WebGLValue::Int(b) if b > 0);
+            assert_eq!(has_alpha, attrs.alpha);
+            assert_eq!(has_depth, attrs.depth);
+            assert_eq!(has_stencil, attrs.stencil);
+            assert_eq!(attrs.power_preference.as_str(), "default");
+        }
+    }
+
+    #[test]
+    fn test_resolved_attributes_match_framebuffer_bits() {
+        for seed in 0..WEBGL_PROFILES.len() as u64 {
+            let defense = WebGLDefense::new(seed);
+            for layout in 0..8u8 {
+                let requested = ContextAttributes {
+                    alpha: layout & 1 != 0,
+                    depth: layout & 2 != 0,
+                    stencil: layout & 4 != 0,
+                    ..defense.get_context_attributes()
+                };
+                let resolved = defense.resolve_context_attributes(&requested);
+                let has_bits = |pname| {
+                    let bits = defense.get_context_parameter(&resolved, pname);
+                    matches!(bits, WebGLValue::Int(b) if b > 0)
+                };
+                assert_eq!(has_bits(0x0D55), resolved.alpha);
+                assert_eq!(has_bits(0x0D56), resolved.depth);
+                assert_eq!(has_bits(0x0D57), resolved.stencil);
+            }
+
+            // Asking for a stencil buffer gets Firefox's 8 bits
+            let requested = ContextAttributes {
+                stencil: true,
+                ..defense.get_context_attributes()
+            };
+            let resolved = defense.resolve_context_attributes(&requested);
+            assert!(resolved.stencil);
+            assert!(matches!(
+                defense.get_context_parameter(&resolved, 0x0D57),
+                WebGLValue::Int(8)
+            ));
+            assert!(matches!(defense.get_parameter(0x0D57), WebGLValue::Int(0)));
+        }
+    }
+
+    #[test]
+    fn test_fail_if_major_performance_caveat_never_fails() {
+        let defense = WebGLDefense::new(2);
+        let requested = ContextAttributes {
+            fail_if_major_performance_caveat: true,
+            power_preference: PowerPreference::HighPerformance,
+            preserve_drawing_buffer: true,
+            desynchronized: true,
+            ..defense.get_context_attributes()
+        };
+
+        let resolved = defense.resolve_context_attributes(&requested);
+        assert!(resolved.fail_if_major_performance_caveat);
+        assert!(resolved.preserve_drawing_buffer);
+        assert!(!resolved.desynchronized);
+        assert_eq!(resolved.power_preference, PowerPreference::HighPerformance);
+    }
+
+    #[test]
+    fn test_context_loss_events_are_deterministic() {
+        let mut sim = ContextLossSimulator::new();
+        assert_eq!(
+            sim.restore_context(),
+            Err(ContextLossError::InvalidOperation)
+        );
+
+        sim.lose_context().expect("live context can be lost");
+        assert!(sim.is_context_lost());
+        assert_eq!(sim.lose_context(), Err(ContextLossError::InvalidOperation));
+        sim.restore_context().expect("lost context can be restored");
+
+        assert_eq!(sim.next_event(), Some(ContextEvent::Lost));
+        assert_eq!(sim.next_event(), Some(ContextEvent::Restored));
+        assert_eq!(sim.next_event(), None);
+        assert!(!sim.is_context_lost());
+    }
+
     #[test]
     fn test_pixel_noise_deterministic() {
         let defense = WebGLDefense::new(42);
@@ -280,9 +676,57 @@ mod tests {
         let mut data1 = vec![128u8; 64];
         let mut data2 = vec![128u8; 64];
 
-        defense.apply_pixel_noise(&mut data1);
-        defense.apply_pixel_noise(&mut data2);
+        defense.apply_pixel_noise(&mut data1, 0, 0, 4, 4, 4);
+        defense.apply_pixel_noise(&mut data2, 0, 0, 4, 4, 4);
 
         assert_eq!(data1, data2);
     }
+
+    /// Read back a rectangle from a synthetic 8x8 buffer and apply noise.
+    fn readback(defense: &WebGLDefense, x: u32, y: u32, w: u32, h: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity((w * h * 4) as usize);
+        for row in y..y + h {
+            for col in x..x + w {
+                data.extend_from_slice(&[(col * 30) as u8, (row * 30) as u8, 77, 200]);
+            }
+        }
+        defense.apply_pixel_noise(&mut data, x, y, w, h, 8);
+        data
+    }
+
+    #[test]
+    fn test_pixel_noise_overlapping_readbacks_agree() {
+        let defense = WebGLDefense::new(42);
+
+        // (0,0)-(6,6) and (2,3)-(8,8) overlap on (2,3)-(6,6)
+        let a = readback(&defense, 0, 0, 6, 6);
+        let b = readback(&defense, 2, 3, 6, 5);
+
+        for ay in 3..6u32 {
+            for ax in 2..6u32 {
+                let ia = ((ay * 6 + ax) * 4) as usize;
+                let ib = (((ay - 3) * 6 + (ax - 2)) * 4) as usize;
+                assert_eq!(a[ia..ia + 4], b[ib..ib + 4], "pixel ({}, {})", ax, ay);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pixel_noise_preserves_alpha_and_bounds_amplitude() {
+        let defense = WebGLDefense::new(7);
+
+        let mut data = vec![128u8; 16 * 4];
+        for px in data.chunks_mut(4) {
+            px[3] = 200;
+        }
+        defense.apply_pixel_noise(&mut data, 3, 1, 4, 4, 16);
+
+        for px in data.chunks(4) {
+            assert_eq!(px[3], 200);
+            for &c in &px[..3] {
+                assert!((c as i16 - 128).abs() <= 1);
+            }
+        }
+        assert!(data.iter().any(|&b| b != 128 && b != 200));
+    }
 }
@@ -25,7 +25,7 @@
         hardwareConcurrency: 4,
         deviceMemory: 8,
         platform: 'Win32',
-        userAgent: 'Mozilla/5.0 (Windows NT 10.0; rv:115.0) Gecko/20100101 Firefox/115.0'
+        userAgent: 'Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0'
     };
 
     // Remove our identity object from window
@@ -160,6 +160,7 @@
         pixelDepth: 24,
         availLeft: 0,
         availTop: 0,
+        isExtended: false,
     };
 
     for (const [key, value] of Object.entries(screenOverrides)) {
@@ -172,6 +173,15 @@
         } catch (e) {}
     }
 
+    // Block multi-screen APIs (getScreenDetails reveals monitor layout)
+    try {
+        defineProperty(window, 'getScreenDetails', {
+            get: function() { return undefined; },
+            configurable: false,
+            enumerable: false
+        });
+    } catch (e) {}
+
     // Override window dimensions
     const windowOverrides = {
         innerWidth: IDENTITY.screenWidth,
@@ -0,0 +1,173 @@
+//! In-memory recovery of unsent form text.
+//!
+//! The content process reports the focused textarea's text over IPC.
+//! The UI keeps only the latest draft, in a buffer that is zeroed when
+//! replaced or dropped, and after a content-process recycle offers a
+//! single "restore your unsent text" action. Nothing here touches disk.
+
+use std::fmt;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Largest draft kept, in bytes (matches the sandbox IPC bound).
+pub const MAX_DRAFT_BYTES: usize = 32 * 1024;
+
+/// Draft text that is zeroed on drop and never printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DraftText {
+    bytes: Vec<u8>,
+}
+
+impl DraftText {
+    /// Take ownership of `text`, truncated to `MAX_DRAFT_BYTES`.
+    pub fn new(text: String) -> Self {
+        let mut end = text.len().min(MAX_DRAFT_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let mut bytes = text.into_bytes();
+        bytes[end..].fill(0);
+        bytes.truncate(end);
+        Self { bytes }
+    }
+
+    /// Get the text.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes).unwrap_or_default()
+    }
+
+    /// Get the length in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the draft is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn wipe(&mut self) {
+        zeroize(&mut self.bytes);
+    }
+}
+
+/// Zero `bytes`' whole allocation, not just the live prefix, and clear it.
+pub(crate) fn zeroize(bytes: &mut Vec<u8>) {
+    bytes.resize(bytes.capacity(), 0);
+    bytes.fill(0);
+    std::hint::black_box(&mut *bytes);
+    compiler_fence(Ordering::SeqCst);
+    bytes.clear();
+}
+
+impl Drop for DraftText {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+impl fmt::Debug for DraftText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DraftText([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+/// Holds the latest unsent draft for the current page.
+#[derive(Debug, Default)]
+pub struct FormDraftHolder {
+    /// Latest draft reported by the content process
+    draft: Option<DraftText>,
+    /// A recycle happened and restore has not been used yet
+    offer_restore: bool,
+}
+
+impl FormDraftHolder {
+    /// Create an empty holder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the held draft. The previous one is wiped.
+    ///
+    /// An empty draft clears the holder (the user deleted the text).
+    pub fn store(&mut self, draft: DraftText) {
+        self.draft = (!draft.is_empty()).then_some(draft);
+    }
+
+    /// The content process was recycled; offer restore if there is text.
+    pub fn on_content_recycled(&mut self) {
+        self.offer_restore = self.draft.is_some();
+    }
+
+    /// Whether the "restore your unsent text" action should be shown.
+    pub fn restore_offered(&self) -> bool {
+        self.offer_restore
+    }
+
+    /// Take the draft for the one-time restore action.
+    pub fn take_for_restore(&mut self) -> Option<DraftText> {
+        if !std::mem::take(&mut self.offer_restore) {
+            return None;
+        }
+        self.draft.take()
+    }
+
+    /// Whether a draft is held.
+    pub fn has_draft(&self) -> bool {
+        self.draft.is_some()
+    }
+
+    /// Wipe the draft (navigation away, New Loop, quit).
+    pub fn wipe(&mut self) {
+        self.draft = None;
+        self.offer_restore = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draft_bounded_and_redacted() {
+        let draft = DraftText::new("ü".repeat(MAX_DRAFT_BYTES));
+        assert!(draft.len() <= MAX_DRAFT_BYTES);
+        assert!(draft.as_str().chars().all(|c| c == 'ü'));
+
+        let draft = DraftText::new("secret post".to_string());
+        assert_eq!(format!("{:?}", draft), "DraftText([REDACTED; 11])");
+    }
+
+    #[test]
+    fn test_restore_is_one_time() {
+        let mut holder = FormDraftHolder::new();
+        holder.store(DraftText::new("first".to_string()));
+        holder.store(DraftText::new("second".to_string()));
+
+        // No recycle, no offer
+        assert!(holder.take_for_restore().is_none());
+
+        holder.on_content_recycled();
+        assert!(holder.restore_offered());
+        let draft = holder.take_for_restore().expect("draft offered");
+        assert_eq!(draft.as_str(), "second");
+
+        assert!(!holder.restore_offered());
+        assert!(holder.take_for_restore().is_none());
+    }
+
+    #[test]
+    fn test_wipe() {
+        let mut holder = FormDraftHolder::new();
+        holder.store(DraftText::new("text".to_string()));
+        holder.on_content_recycled();
+
+        holder.wipe();
+        assert!(!holder.has_draft());
+        assert!(!holder.restore_offered());
+
+        let mut draft = DraftText::new("text".to_string());
+        draft.wipe();
+        assert!(draft.is_empty());
+    }
+}
@@ -0,0 +1,322 @@
+//! Timing defense for input forwarded to content.
+//!
+//! Keystroke and pointer timing is a behavioral biometric: the gaps between
+//! keys typed into a search box identify a person across sessions no matter
+//! what else is spoofed. Events from the UI pass through an `InputBatcher`
+//! on their way to the content process:
+//!
+//! - timestamps come from `TimingDefense::event_timestamp`, so they are
+//!   rounded to the same precision as performance.now()
+//! - delivery happens on a fixed 16ms grid, so the gaps the page observes
+//!   are multiples of the flush interval, not of the user's typing rhythm
+//! - scroll deltas are converted to whole lines or pages; the remainder is
+//!   carried into the next scroll so nothing is lost
+//!
+//! Order is always preserved, and no event waits longer than one interval.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use forloop_config::Clock;
+use forloop_fingerprint::timing::TimingDefense;
+use tokio::sync::mpsc;
+
+/// Interval between flushes to the content process (one 60Hz frame).
+pub const INPUT_FLUSH_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Pixels per line when converting pixel scrolling to lines.
+pub const SCROLL_LINE_PIXELS: f64 = 40.0;
+
+/// Input event from the UI, bound for the content process.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    /// A key went down or up.
+    Key {
+        /// Key value, as in KeyboardEvent.key.
+        key: String,
+        /// Whether the key was pressed (otherwise released).
+        pressed: bool,
+    },
+    /// The pointer moved or a button changed.
+    Pointer {
+        /// What happened.
+        kind: PointerKind,
+        /// Viewport x coordinate in CSS pixels.
+        x: i32,
+        /// Viewport y coordinate in CSS pixels.
+        y: i32,
+    },
+    /// Scroll wheel or touchpad movement.
+    Scroll {
+        /// Horizontal delta.
+        dx: f64,
+        /// Vertical delta.
+        dy: f64,
+        /// Unit of the deltas.
+        unit: ScrollUnit,
+    },
+    /// Text pasted into an editable element of the page.
+    Paste {
+        /// Clipboard text.
+        text: String,
+    },
+}
+
+/// Pointer event kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerKind {
+    /// Pointer moved.
+    Move,
+    /// Button pressed.
+    Down,
+    /// Button released.
+    Up,
+}
+
+/// Scroll delta units (WheelEvent.deltaMode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollUnit {
+    /// CSS pixels; never forwarded.
+    Pixel,
+    /// Lines.
+    Line,
+    /// Pages.
+    Page,
+}
+
+/// An event as delivered to the content process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedInput {
+    /// The event, with scroll deltas bucketed.
+    pub event: InputEvent,
+    /// Event timestamp in the page's performance.now() timebase.
+    pub timestamp_ms: f64,
+}
+
+/// Queue between the UI event source and the IPC sender.
+#[derive(Debug)]
+pub struct InputBatcher {
+    defense: TimingDefense,
+    clock: Arc<dyn Clock>,
+    /// Start of the flush grid.
+    epoch: Instant,
+    queue: VecDeque<TimedInput>,
+    /// Scroll not yet delivered, in lines.
+    line_remainder: (f64, f64),
+    /// Scroll not yet delivered, in pages.
+    page_remainder: (f64, f64),
+}
+
+impl InputBatcher {
+    /// Create a batcher stamping events with `defense`, on its clock.
+    pub fn new(defense: TimingDefense) -> Self {
+        let clock = defense.clock();
+        Self {
+            epoch: clock.now(),
+            clock,
+            defense,
+            queue: VecDeque::new(),
+            line_remainder: (0.0, 0.0),
+            page_remainder: (0.0, 0.0),
+        }
+    }
+
+    /// Queue an event for the next flush.
+    pub fn push(&mut self, event: InputEvent) {
+        let event = match event {
+            InputEvent::Scroll { dx, dy, unit } => match self.bucket_scroll(dx, dy, unit) {
+                Some(event) => event,
+                None => return,
+            },
+            event => event,
+        };
+        self.queue.push_back(TimedInput {
+            event,
+            timestamp_ms: self.defense.event_timestamp(),
+        });
+    }
+
+    /// Get the number of events waiting for a flush.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Get the next flush tick strictly after now.
+    pub fn next_flush(&self) -> Instant {
+        let elapsed = self.clock.now().saturating_duration_since(self.epoch);
+        let interval = INPUT_FLUSH_INTERVAL.as_nanos();
+        let ticks = elapsed.as_nanos() / interval + 1;
+        self.epoch + Duration::from_nanos((ticks * interval) as u64)
+    }
+
+    /// Take every queued event, oldest first.
+    pub fn flush(&mut self) -> Vec<TimedInput> {
+        self.queue.drain(..).collect()
+    }
+
+    /// Forward events from `events` to `ipc` on the flush grid until
+    /// either channel closes.
+    pub async fn run(
+        mut self,
+        mut events: mpsc::Receiver<InputEvent>,
+        ipc: mpsc::Sender<TimedInput>,
+    ) {
+        while let Some(event) = events.recv().await {
+            self.push(event);
+            let wait = self
+                .next_flush()
+                .saturating_duration_since(self.clock.now());
+            self.clock.sleep(wait).await;
+
+            while let Ok(event) = events.try_recv() {
+                self.push(event);
+            }
+            for input in self.flush() {
+                if ipc.send(input).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Convert a scroll to whole lines or pages, carrying the remainder.
+    fn bucket_scroll(&mut self, dx: f64, dy: f64, unit: ScrollUnit) -> Option<InputEvent> {
+        let (remainder, dx, dy, unit) = match unit {
+            ScrollUnit::Pixel => (
+                &mut self.line_remainder,
+                dx / SCROLL_LINE_PIXELS,
+                dy / SCROLL_LINE_PIXELS,
+                ScrollUnit::Line,
+            ),
+            ScrollUnit::Line => (&mut self.line_remainder, dx, dy, ScrollUnit::Line),
+            ScrollUnit::Page => (&mut self.page_remainder, dx, dy, ScrollUnit::Page),
+        };
+        if !dx.is_finite() || !dy.is_finite() {
+            return None;
+        }
+
+        let x = remainder.0 + dx;
+        let y = remainder.1 + dy;
+        *remainder = (x.fract(), y.fract());
+        let (dx, dy) = (x.trunc(), y.trunc());
+
+        (dx != 0.0 || dy != 0.0).then_some(InputEvent::Scroll { dx, dy, unit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_config::clock::ManualClock;
+
+    fn batcher(clock: &ManualClock) -> InputBatcher {
+        let defense = TimingDefense::new(42).with_clock(Arc::new(clock.clone()));
+        InputBatcher::new(defense)
+    }
+
+    fn key(key: &str) -> InputEvent {
+        InputEvent::Key {
+            key: key.to_string(),
+            pressed: true,
+        }
+    }
+
+    #[test]
+    fn test_timestamps_quantized_and_ordered() {
+        let clock = ManualClock::new();
+        let mut batcher = batcher(&clock);
+
+        for (gap_ms, k) in [(37, "a"), (41, "b"), (5, "c"), (130, "d")] {
+            clock.advance(Duration::from_millis(gap_ms));
+            batcher.push(key(k));
+        }
+
+        let delivered = batcher.flush();
+        let keys: Vec<_> = delivered
+            .iter()
+            .map(|input| match &input.event {
+                InputEvent::Key { key, .. } => key.as_str(),
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(keys, ["a", "b", "c", "d"]);
+
+        // 37, 78, 83 and 213ms all round down to the 100ms precision
+        let stamps: Vec<_> = delivered.iter().map(|input| input.timestamp_ms).collect();
+        assert_eq!(stamps, [0.0, 0.0, 0.0, 200.0]);
+        assert_eq!(batcher.pending(), 0);
+    }
+
+    #[test]
+    fn test_flush_tick_bounds_latency() {
+        let clock = ManualClock::new();
+        let batcher = batcher(&clock);
+        let start = clock.now();
+
+        for step_ms in [0, 1, 15, 16, 17, 250] {
+            clock.advance(Duration::from_millis(step_ms));
+            let now = clock.now();
+            let next = batcher.next_flush();
+            assert!(next > now);
+            assert!(next - now <= INPUT_FLUSH_INTERVAL);
+            assert_eq!((next - start).as_millis() % 16, 0);
+        }
+    }
+
+    #[test]
+    fn test_scroll_bucketed_to_lines_and_pages() {
+        let clock = ManualClock::new();
+        let mut batcher = batcher(&clock);
+        let scroll = |dx, dy, unit| InputEvent::Scroll { dx, dy, unit };
+
+        // 100px is 2.5 lines: two now, the half carried over
+        batcher.push(scroll(0.0, 100.0, ScrollUnit::Pixel));
+        batcher.push(scroll(0.0, 20.0, ScrollUnit::Pixel));
+        // Too small to move a whole line yet
+        batcher.push(scroll(0.0, 0.4, ScrollUnit::Line));
+        batcher.push(scroll(0.0, -1.5, ScrollUnit::Page));
+        batcher.push(scroll(f64::NAN, 1.0, ScrollUnit::Line));
+
+        let events: Vec<_> = batcher.flush().into_iter().map(|i| i.event).collect();
+        assert_eq!(
+            events,
+            [
+                scroll(0.0, 2.0, ScrollUnit::Line),
+                scroll(0.0, 1.0, ScrollUnit::Line),
+                scroll(0.0, -1.0, ScrollUnit::Page),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_delivers_within_one_interval() {
+        let clock = ManualClock::new();
+        let (event_tx, event_rx) = mpsc::channel(8);
+        let (ipc_tx, mut ipc_rx) = mpsc::channel(8);
+        tokio::spawn(batcher(&clock).run(event_rx, ipc_tx));
+
+        for k in ["x", "y", "z"] {
+            event_tx.send(key(k)).await.expect("batcher running");
+        }
+
+        let mut waited = Duration::ZERO;
+        let first = loop {
+            tokio::task::yield_now().await;
+            if let Ok(input) = ipc_rx.try_recv() {
+                break input;
+            }
+            clock.advance(Duration::from_millis(1));
+            waited += Duration::from_millis(1);
+            assert!(waited <= INPUT_FLUSH_INTERVAL, "held past one interval");
+        };
+
+        // The whole batch arrives together, in order
+        assert_eq!(first.event, key("x"));
+        assert_eq!(ipc_rx.try_recv().expect("same flush").event, key("y"));
+        assert_eq!(ipc_rx.try_recv().expect("same flush").event, key("z"));
+
+        drop(event_tx);
+        assert!(ipc_rx.recv().await.is_none());
+    }
+}
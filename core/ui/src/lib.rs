@@ -3,14 +3,36 @@
 //! Minimal browser UI designed for privacy. No distractions, no tracking,
 //! no unnecessary features. Every UI element serves a privacy purpose.
 
-use std::sync::Arc;
+use forloop_config::NavigationKind;
+use forloop_network::{
+    parse_bridge_lines, snowflake_bridges, BridgeLine, BridgeLineError, MetricsSnapshot,
+    SocksReplyCode,
+};
 use tokio::sync::mpsc;
 
+mod draft;
+mod input;
+mod paste;
+
+pub use draft::{DraftText, FormDraftHolder, MAX_DRAFT_BYTES};
+pub use input::{
+    InputBatcher, InputEvent, PointerKind, ScrollUnit, TimedInput, INPUT_FLUSH_INTERVAL,
+    SCROLL_LINE_PIXELS,
+};
+pub use paste::{PasteOutcome, PasteTarget, PastedText, MAX_PASTE_BYTES};
+
 /// Messages between UI and browser core.
 #[derive(Debug, Clone)]
 pub enum UiMessage {
-    /// User typed in URL bar.
-    Navigate(String),
+    /// User started a top-level navigation.
+    Navigate {
+        /// URL to load.
+        url: String,
+        /// URL bar, link, reload or back/forward.
+        kind: NavigationKind,
+        /// Page the clicked link was in.
+        referrer: Option<String>,
+    },
     /// User clicked "New Loop" button.
     NewLoop,
     /// User clicked "Clear State" button.
@@ -19,12 +41,69 @@ pub enum UiMessage {
     TorStatusChanged(TorStatus),
     /// Page load progress.
     LoadProgress(u8),
+    /// Request body upload progress.
+    UploadProgress {
+        /// Body bytes sent so far.
+        sent_bytes: u64,
+        /// Total body bytes.
+        total_bytes: u64,
+    },
+    /// User cancelled the in-flight upload.
+    CancelUpload,
     /// Page title changed.
     TitleChanged(String),
     /// Security indicator changed.
     SecurityChanged(SecurityIndicator),
+    /// A new circuit is in use.
+    CircuitChanged(CircuitInfo),
     /// Show error to user.
     ShowError(String),
+    /// Tor bootstrap looks blocked; suggest configuring a bridge.
+    SuggestBridges,
+    /// Bootstrap moved on to another configured bridge.
+    BridgeRotated(usize),
+    /// Bootstrap failed and will be retried.
+    BootstrapRetry {
+        /// Attempt that just failed.
+        attempt: u32,
+        /// Seconds until the next attempt.
+        delay_secs: u64,
+    },
+    /// Compiled-in privacy defaults differ from the reviewed manifest.
+    ModifiedBuild,
+    /// Latest unsent textarea text from the content process.
+    FormDraft(DraftText),
+    /// The content process was recycled (crash or resource limit).
+    ContentRecycled,
+    /// User chose to restore unsent text into the new content process.
+    RestoreDraft(DraftText),
+    /// Sandbox started with some kernel features missing.
+    ReducedIsolation(String),
+    /// User asked to verify the current page over a second circuit.
+    VerifyPage(String),
+    /// Result of a page verification.
+    PageVerified(PageConsistency),
+    /// A PDF was saved to the download directory instead of displayed.
+    PdfDownloaded(String),
+    /// Onion connection step for the status bar.
+    ConnectStatus(String),
+    /// A request timed out; show the error page with a retry button.
+    OfferRetry(RetryPrompt),
+    /// User clicked retry on a timed-out onion page.
+    RetryOnion {
+        /// URL to request again.
+        url: String,
+        /// Keep the onion descriptor Tor already fetched.
+        reuse_descriptor: bool,
+    },
+    /// The connection may be intercepted; show a warning page, not a prompt.
+    SecurityWarning(String),
+    /// The page was cut off for making too many connection attempts.
+    ExcessiveConnections,
+    /// Session totals for the goodbye page shown while quitting.
+    SessionSummary(SessionSummary),
+    /// Network totals of the current loop for the status bar.
+    MetricsUpdated(MetricsSnapshot),
     /// Exit browser.
     Quit,
 }
@@ -34,12 +113,19 @@ pub enum UiMessage {
 pub enum TorStatus {
     /// Not connected, trying to connect.
     Connecting,
+    /// Bootstrapping, this many percent done.
+    Bootstrapping(u8),
+    /// Bootstrap stuck at this many percent (Tor's warning, or progress
+    /// lost to a network outage).
+    Stalled(u8, String),
     /// Connected and ready.
     Connected,
     /// Connection failed.
     Failed(String),
     /// Building circuit.
     BuildingCircuit,
+    /// Connected but impaired (network offline, clock skew).
+    Degraded(String),
 }
 
 /// Security indicator state.
@@ -55,10 +141,72 @@ pub enum SecurityIndicator {
     Error,
 }
 
+/// Error page for a timed-out onion request, with a one-click retry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPrompt {
+    /// Explanation naming the step that stalled.
+    pub message: String,
+    /// URL the retry requests.
+    pub url: String,
+    /// Whether the retry can skip the descriptor lookup.
+    pub reuse_descriptor: bool,
+}
+
+/// Internal URL of the goodbye page.
+pub const GOODBYE_URL: &str = "forloop:goodbye";
+
+/// What forloop did this session, shown on the goodbye page.
+///
+/// Totals only: nothing per site, nothing that outlives the quit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionSummary {
+    /// Pages loaded.
+    pub pages_loaded: u32,
+    /// Tor circuits built.
+    pub circuits_used: u32,
+    /// Tracking constructs stripped from documents.
+    pub trackers_stripped: u32,
+    /// Canvas, WebGL and audio readbacks that carried noise.
+    pub readbacks_noised: u32,
+    /// Padding bytes added to requests.
+    pub padding_bytes: u64,
+}
+
+impl SessionSummary {
+    /// Lines of the goodbye page.
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("Pages loaded: {}", self.pages_loaded),
+            format!("Circuits used: {}", self.circuits_used),
+            format!("Trackers stripped: {}", self.trackers_stripped),
+            format!("Fingerprint readbacks noised: {}", self.readbacks_noised),
+            format!("Padding overhead: {} bytes", self.padding_bytes),
+        ]
+    }
+}
+
+/// Outcome of "verify this page", shown in the security popup.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PageConsistency {
+    /// Both circuits received the same document.
+    Match,
+    /// The circuits received different documents.
+    Mismatch {
+        /// Offset of the first differing byte.
+        first_difference: usize,
+    },
+    /// Verification could not be completed.
+    Failed(String),
+}
+
 /// Browser UI state.
 pub struct BrowserUi {
     /// Current URL in the address bar.
     current_url: String,
+    /// Text pasted into the URL bar, not yet submitted.
+    url_bar_text: Option<String>,
+    /// Note about the latest URL-bar paste.
+    paste_note: Option<String>,
     /// Current page title.
     current_title: String,
     /// Tor connection status.
@@ -67,6 +215,34 @@ pub struct BrowserUi {
     security: SecurityIndicator,
     /// Page load progress (0-100).
     load_progress: u8,
+    /// In-flight upload (sent, total), if any.
+    upload_progress: Option<(u64, u64)>,
+    /// Circuit used by the latest request.
+    circuit_info: Option<CircuitInfo>,
+    /// Unsent form text, kept in memory only.
+    draft: FormDraftHolder,
+    /// Privacy defaults failed the startup self-check.
+    modified_build: bool,
+    /// Reduced-isolation notice awaiting dismissal.
+    isolation_notice: Option<String>,
+    /// Whether the reduced-isolation notice has been shown this session.
+    isolation_notice_seen: bool,
+    /// Verification result for the current page.
+    page_consistency: Option<PageConsistency>,
+    /// Where the latest PDF was saved, awaiting dismissal.
+    download_notice: Option<String>,
+    /// Onion connection step while a page loads.
+    connect_status: Option<String>,
+    /// Timed-out onion request offering a retry.
+    retry_prompt: Option<RetryPrompt>,
+    /// Interception warning shown in place of the page.
+    security_warning: Option<String>,
+    /// The current page was cut off for excessive connection attempts.
+    excessive_connections: bool,
+    /// Session totals on the goodbye page, while quitting.
+    goodbye: Option<SessionSummary>,
+    /// Network totals of the current loop.
+    metrics: Option<MetricsSnapshot>,
     /// Channel to send messages to browser core.
     tx: mpsc::Sender<UiMessage>,
 }
@@ -76,10 +252,26 @@ impl BrowserUi {
     pub fn new(tx: mpsc::Sender<UiMessage>) -> Self {
         Self {
             current_url: String::new(),
+            url_bar_text: None,
+            paste_note: None,
             current_title: String::from("forloop"),
             tor_status: TorStatus::Connecting,
             security: SecurityIndicator::Secure,
             load_progress: 0,
+            upload_progress: None,
+            circuit_info: None,
+            draft: FormDraftHolder::new(),
+            modified_build: false,
+            isolation_notice: None,
+            isolation_notice_seen: false,
+            page_consistency: None,
+            download_notice: None,
+            connect_status: None,
+            retry_prompt: None,
+            security_warning: None,
+            excessive_connections: false,
+            goodbye: None,
+            metrics: None,
             tx,
         }
     }
@@ -90,8 +282,37 @@ impl BrowserUi {
             UiMessage::TorStatusChanged(status) => {
                 self.tor_status = status;
             }
+            UiMessage::SuggestBridges => {
+                self.tor_status = TorStatus::Failed(
+                    "Tor appears to be blocked on this network. Add a bridge in Settings."
+                        .to_string(),
+                );
+            }
+            UiMessage::BridgeRotated(_) | UiMessage::BootstrapRetry { .. } => {
+                self.tor_status = TorStatus::Connecting;
+            }
             UiMessage::LoadProgress(progress) => {
                 self.load_progress = progress;
+                if progress >= 100 {
+                    self.connect_status = None;
+                }
+            }
+            UiMessage::ConnectStatus(status) => {
+                self.connect_status = Some(status);
+            }
+            UiMessage::OfferRetry(prompt) => {
+                self.connect_status = None;
+                self.retry_prompt = Some(prompt);
+            }
+            UiMessage::UploadProgress {
+                sent_bytes,
+                total_bytes,
+            } => {
+                self.upload_progress = if sent_bytes >= total_bytes {
+                    None
+                } else {
+                    Some((sent_bytes, total_bytes))
+                };
             }
             UiMessage::TitleChanged(title) => {
                 self.current_title = title;
@@ -99,34 +320,325 @@ impl BrowserUi {
             UiMessage::SecurityChanged(security) => {
                 self.security = security;
             }
+            UiMessage::CircuitChanged(info) => {
+                self.circuit_info = Some(info);
+            }
+            UiMessage::ModifiedBuild => {
+                self.modified_build = true;
+            }
+            UiMessage::FormDraft(draft) => {
+                self.draft.store(draft);
+            }
+            UiMessage::ContentRecycled => {
+                self.draft.on_content_recycled();
+            }
+            // Shown once per session, not on every process spawn
+            UiMessage::ReducedIsolation(notice) if !self.isolation_notice_seen => {
+                self.isolation_notice_seen = true;
+                self.isolation_notice = Some(notice);
+            }
+            UiMessage::PageVerified(consistency) => {
+                self.page_consistency = Some(consistency);
+            }
+            UiMessage::PdfDownloaded(path) => {
+                self.download_notice = Some(path);
+            }
+            UiMessage::SecurityWarning(warning) => {
+                self.connect_status = None;
+                self.security = SecurityIndicator::Error;
+                self.security_warning = Some(warning);
+            }
+            UiMessage::ExcessiveConnections => {
+                self.excessive_connections = true;
+            }
+            UiMessage::SessionSummary(summary) => {
+                self.current_url = GOODBYE_URL.to_string();
+                self.goodbye = Some(summary);
+            }
+            UiMessage::MetricsUpdated(snapshot) => {
+                self.metrics = Some(snapshot);
+            }
             _ => {}
         }
     }
 
-    /// Navigate to a URL.
+    /// Navigate to a URL typed into the URL bar.
     pub async fn navigate(&mut self, url: &str) {
+        self.start_navigation(url, NavigationKind::AddressBar, None)
+            .await;
+    }
+
+    /// Follow a link clicked in the current page.
+    pub async fn follow_link(&mut self, url: &str) {
+        let referrer = Some(self.current_url.clone()).filter(|url| !url.is_empty());
+        self.start_navigation(url, NavigationKind::LinkClick, referrer)
+            .await;
+    }
+
+    /// Reload the current page.
+    pub async fn reload(&mut self) {
+        if self.current_url.is_empty() {
+            return;
+        }
+        let url = self.current_url.clone();
+        self.start_navigation(&url, NavigationKind::Reload, None)
+            .await;
+    }
+
+    /// Go back or forward to a history entry.
+    pub async fn traverse_history(&mut self, url: &str) {
+        self.start_navigation(url, NavigationKind::HistoryTraversal, None)
+            .await;
+    }
+
+    async fn start_navigation(
+        &mut self,
+        url: &str,
+        kind: NavigationKind,
+        referrer: Option<String>,
+    ) {
+        self.draft.wipe();
+        self.url_bar_text = None;
+        self.paste_note = None;
+        self.page_consistency = None;
+        self.connect_status = None;
+        self.retry_prompt = None;
+        self.security_warning = None;
+        self.excessive_connections = false;
         self.current_url = url.to_string();
         self.load_progress = 0;
-        let _ = self.tx.send(UiMessage::Navigate(url.to_string())).await;
+        let _ = self
+            .tx
+            .send(UiMessage::Navigate {
+                url: url.to_string(),
+                kind,
+                referrer,
+            })
+            .await;
     }
 
     /// Request new identity (new loop).
-    pub async fn new_loop(&self) {
+    pub async fn new_loop(&mut self) {
+        self.draft.wipe();
+        self.page_consistency = None;
+        // The download directory is erased with the loop
+        self.download_notice = None;
+        self.connect_status = None;
+        self.retry_prompt = None;
+        self.security_warning = None;
+        self.excessive_connections = false;
+        self.metrics = None;
         let _ = self.tx.send(UiMessage::NewLoop).await;
     }
 
     /// Clear all state.
-    pub async fn clear_state(&self) {
+    pub async fn clear_state(&mut self) {
+        self.draft.wipe();
         let _ = self.tx.send(UiMessage::ClearState).await;
     }
 
+    /// Exit the browser.
+    pub async fn quit(&mut self) {
+        self.draft.wipe();
+        if let Some(summary) = self.goodbye.as_mut() {
+            *summary = SessionSummary::default();
+        }
+        self.goodbye = None;
+        let _ = self.tx.send(UiMessage::Quit).await;
+    }
+
+    /// Whether to show the "restore your unsent text" action.
+    pub fn draft_restore_offered(&self) -> bool {
+        self.draft.restore_offered()
+    }
+
+    /// Send the unsent text back to the content process. Works once.
+    pub async fn restore_draft(&mut self) {
+        if let Some(draft) = self.draft.take_for_restore() {
+            let _ = self.tx.send(UiMessage::RestoreDraft(draft)).await;
+        }
+    }
+
+    /// Re-fetch the current page over a second circuit and compare.
+    pub async fn verify_page(&mut self) {
+        if self.current_url.is_empty() {
+            return;
+        }
+        self.page_consistency = None;
+        let _ = self
+            .tx
+            .send(UiMessage::VerifyPage(self.current_url.clone()))
+            .await;
+    }
+
+    /// Get the verification line for the security popup, if verified.
+    pub fn verification_display(&self) -> Option<String> {
+        self.page_consistency
+            .as_ref()
+            .map(|consistency| match consistency {
+                PageConsistency::Match => "Same page received over two circuits.".to_string(),
+                PageConsistency::Mismatch { first_difference } => format!(
+                    "Warning: this page differs between circuits (from byte {}). \
+                     The site may be tracking visitors by the content it serves.",
+                    first_difference
+                ),
+                PageConsistency::Failed(reason) => {
+                    format!("Could not verify this page: {}", reason)
+                }
+            })
+    }
+
+    /// Cancel the in-flight upload.
+    pub async fn cancel_upload(&mut self) {
+        if self.upload_progress.take().is_some() {
+            let _ = self.tx.send(UiMessage::CancelUpload).await;
+        }
+    }
+
+    /// Get the URL shown in the address bar.
+    pub fn current_url(&self) -> &str {
+        &self.current_url
+    }
+
+    /// Handle a paste. Pastes into the page come back as the event to
+    /// forward to content; pastes into the URL bar are cleaned and shown
+    /// here and never reach content.
+    pub fn on_paste(&mut self, target: PasteTarget, text: String) -> Option<InputEvent> {
+        if target == PasteTarget::Content {
+            return Some(InputEvent::Paste { text });
+        }
+
+        let outcome = match PastedText::new(text) {
+            Some(pasted) => PasteOutcome::classify(&pasted),
+            None => PasteOutcome::TooLarge,
+        };
+        self.paste_note = outcome.note();
+        match outcome {
+            PasteOutcome::Url { url, .. } => self.url_bar_text = Some(url),
+            PasteOutcome::Text(text) => self.url_bar_text = Some(text),
+            PasteOutcome::Invalid | PasteOutcome::TooLarge => {}
+        }
+        None
+    }
+
+    /// Get the pasted URL-bar text awaiting Enter, if any.
+    pub fn url_bar_text(&self) -> Option<&str> {
+        self.url_bar_text.as_deref()
+    }
+
+    /// Get the note shown under the URL bar after a paste, if any.
+    pub fn paste_note(&self) -> Option<&str> {
+        self.paste_note.as_deref()
+    }
+
+    /// Get the circuit used by the latest request.
+    pub fn circuit_info(&self) -> Option<&CircuitInfo> {
+        self.circuit_info.as_ref()
+    }
+
+    /// Get the permanent warning banner for a modified build, if any.
+    ///
+    /// Unlike other notices this cannot be dismissed.
+    pub fn build_banner(&self) -> Option<&'static str> {
+        self.modified_build
+            .then_some("Warning: this build's privacy defaults have been modified.")
+    }
+
+    /// Get the reduced-isolation notice, if it has not been dismissed.
+    pub fn isolation_notice(&self) -> Option<&str> {
+        self.isolation_notice.as_deref()
+    }
+
+    /// Dismiss the reduced-isolation notice.
+    pub fn dismiss_isolation_notice(&mut self) {
+        self.isolation_notice = None;
+    }
+
+    /// Get the onion connection step for the status bar, while loading.
+    pub fn connect_status(&self) -> Option<&str> {
+        self.connect_status.as_deref()
+    }
+
+    /// Get the timed-out onion page, if one is shown.
+    pub fn retry_prompt(&self) -> Option<&RetryPrompt> {
+        self.retry_prompt.as_ref()
+    }
+
+    /// Retry the timed-out onion request. Works once per prompt.
+    pub async fn retry(&mut self) {
+        if let Some(prompt) = self.retry_prompt.take() {
+            self.load_progress = 0;
+            let _ = self
+                .tx
+                .send(UiMessage::RetryOnion {
+                    url: prompt.url,
+                    reuse_descriptor: prompt.reuse_descriptor,
+                })
+                .await;
+        }
+    }
+
+    /// Get the interception warning page, if one is shown.
+    pub fn security_warning(&self) -> Option<&str> {
+        self.security_warning.as_deref()
+    }
+
+    /// Get the notice for a page cut off for excessive connection attempts.
+    pub fn connection_notice(&self) -> Option<&'static str> {
+        self.excessive_connections
+            .then_some("This page is making an unusual number of connection attempts.")
+    }
+
+    /// Get the goodbye page lines, while quitting.
+    pub fn goodbye_page(&self) -> Option<Vec<String>> {
+        self.goodbye.as_ref().map(SessionSummary::lines)
+    }
+
+    /// Get the network totals line for the status bar, once there are any.
+    pub fn metrics_display(&self) -> Option<String> {
+        self.metrics.as_ref().map(|metrics| {
+            format!(
+                "{} requests, {} circuits, {} failures, {} transferred",
+                metrics.attempts,
+                metrics.circuits_built,
+                metrics.failure_count(),
+                display_bytes(metrics.bytes_transferred())
+            )
+        })
+    }
+
+    /// Get the "PDF saved" notice, if it has not been dismissed.
+    pub fn download_notice(&self) -> Option<String> {
+        self.download_notice.as_ref().map(|path| {
+            format!(
+                "forloop does not display PDFs inline. The file was saved to {} \
+                 (in memory, erased on New Loop).",
+                path
+            )
+        })
+    }
+
+    /// Dismiss the "PDF saved" notice.
+    pub fn dismiss_download_notice(&mut self) {
+        self.download_notice = None;
+    }
+
+    /// Get upload progress (0-100), if an upload is in flight.
+    pub fn upload_percent(&self) -> Option<u8> {
+        self.upload_progress
+            .map(|(sent, total)| (sent.saturating_mul(100) / total.max(1)) as u8)
+    }
+
     /// Get current Tor status for display.
-    pub fn tor_status_display(&self) -> &'static str {
+    pub fn tor_status_display(&self) -> String {
         match &self.tor_status {
-            TorStatus::Connecting => "Connecting to Tor...",
-            TorStatus::Connected => "Connected",
-            TorStatus::Failed(_) => "Tor Failed",
-            TorStatus::BuildingCircuit => "Building Circuit...",
+            TorStatus::Connecting => "Connecting to Tor...".to_string(),
+            TorStatus::Bootstrapping(percent) => format!("Connecting to Tor… {}%", percent),
+            TorStatus::Stalled(percent, _) => format!("Tor Stalled at {}%", percent),
+            TorStatus::Connected => "Connected".to_string(),
+            TorStatus::Failed(_) => "Tor Failed".to_string(),
+            TorStatus::BuildingCircuit => "Building Circuit...".to_string(),
+            TorStatus::Degraded(_) => "Tor Degraded".to_string(),
         }
     }
 
@@ -141,6 +653,17 @@ impl BrowserUi {
     }
 }
 
+/// Byte count rounded for display: "900 B", "340 KB", "1.5 MB".
+fn display_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    match bytes {
+        0..KB => format!("{} B", bytes),
+        KB..MB => format!("{} KB", bytes / KB),
+        _ => format!("{:.1} MB", bytes as f64 / MB as f64),
+    }
+}
+
 /// Toolbar component.
 pub struct Toolbar {
     /// Whether "New Loop" button is enabled.
@@ -229,6 +752,7 @@ pub struct StatusBar {
 }
 
 /// Circuit information (displayed anonymously).
+#[derive(Debug, Clone, PartialEq)]
 pub struct CircuitInfo {
     /// Country of exit node (ISO 3166-1 alpha-2).
     pub exit_country: String,
@@ -316,6 +840,71 @@ impl ErrorDialog {
             show_report: false,
         }
     }
+
+    /// Create error dialog for a request Tor's SOCKS port failed, saying
+    /// what its reply code means for the user.
+    pub fn socks_failure(code: SocksReplyCode) -> Self {
+        let (title, message) = match code {
+            SocksReplyCode::OnionDescriptorNotFound => (
+                "Onion Service Not Found",
+                "Descriptor not found. The onion service has not published\n\
+                 its address recently: it is offline, or the address is\n\
+                 mistyped.",
+            ),
+            SocksReplyCode::OnionDescriptorInvalid => (
+                "Onion Service Unavailable",
+                "The onion service published a descriptor that could not be\n\
+                 read. Try again later.",
+            ),
+            SocksReplyCode::OnionIntroductionFailed | SocksReplyCode::OnionIntroductionTimedOut => {
+                (
+                    "Onion Service Offline",
+                    "This onion service is offline. It did not answer through\n\
+                     any of its introduction points; it may be down or\n\
+                     overloaded.",
+                )
+            }
+            SocksReplyCode::OnionRendezvousFailed => (
+                "Onion Service Unreachable",
+                "The onion service was found, but the connection to it\n\
+                 could not be completed. Try again.",
+            ),
+            SocksReplyCode::OnionMissingClientAuth => (
+                "Authorization Required",
+                "This onion service only accepts authorized clients.",
+            ),
+            SocksReplyCode::OnionWrongClientAuth => (
+                "Authorization Rejected",
+                "This onion service did not accept your authorization.",
+            ),
+            SocksReplyCode::OnionInvalidAddress => (
+                "Invalid Onion Address",
+                "This is not a valid onion address. Check it for typos.",
+            ),
+            SocksReplyCode::HostUnreachable => {
+                ("Site Not Found", "The site's address could not be found.")
+            }
+            SocksReplyCode::ConnectionRefused => {
+                ("Connection Refused", "The site refused the connection.")
+            }
+            SocksReplyCode::NotAllowed => (
+                "Connection Refused",
+                "The Tor exit does not allow connections to this address.",
+            ),
+            code => return Self::connection_failed(&code.to_string()),
+        };
+        Self {
+            title: title.to_string(),
+            message: message.to_string(),
+            show_report: false,
+        }
+    }
+
+    /// Lead the message with a specific hint (e.g. network offline, clock skew).
+    pub fn with_hint(mut self, hint: &str) -> Self {
+        self.message = format!("{}\n\n{}", hint, self.message);
+        self
+    }
 }
 
 /// Onboarding screen shown on first run.
@@ -337,6 +926,7 @@ impl OnboardingScreen {
     }
 
     /// Go to next page.
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> bool {
         if self.current_page < Self::pages().len() - 1 {
             self.current_page += 1;
@@ -424,12 +1014,14 @@ pub struct SettingsValues {
     pub use_bridges: bool,
     /// Bridge lines (if use_bridges is true).
     pub bridge_lines: Vec<String>,
+    /// Use the built-in Snowflake bridges, alongside any bridge lines.
+    pub use_snowflake: bool,
     /// Security level (always maximum, not changeable).
     pub security_level: SecurityLevel,
 }
 
 /// Security level.
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SecurityLevel {
     /// Maximum security (the only option).
     Maximum,
@@ -442,11 +1034,40 @@ impl SettingsPanel {
             settings: SettingsValues {
                 use_bridges: false,
                 bridge_lines: vec![],
+                use_snowflake: false,
                 security_level: SecurityLevel::Maximum,
             },
         }
     }
 
+    /// Replace the bridge lines with the text area contents.
+    ///
+    /// Returns the parsed bridges, or which line is wrong and why. The
+    /// text is kept either way so the user can fix it.
+    pub fn set_bridge_lines(&mut self, text: &str) -> Result<Vec<BridgeLine>, BridgeLineError> {
+        self.settings.bridge_lines = text.lines().map(str::to_string).collect();
+        parse_bridge_lines(&self.settings.bridge_lines)
+    }
+
+    /// Turn the built-in Snowflake bridges on or off.
+    pub fn set_use_snowflake(&mut self, on: bool) {
+        self.settings.use_snowflake = on;
+    }
+
+    /// The bridges to connect through: the bridge lines if bridges are
+    /// on, then the Snowflake bridges if those are.
+    pub fn bridges(&self) -> Result<Vec<BridgeLine>, BridgeLineError> {
+        let mut bridges = if self.settings.use_bridges {
+            parse_bridge_lines(&self.settings.bridge_lines)?
+        } else {
+            Vec::new()
+        };
+        if self.settings.use_snowflake {
+            bridges.extend(snowflake_bridges());
+        }
+        Ok(bridges)
+    }
+
     /// Get available settings.
     pub fn available_settings(&self) -> Vec<SettingItem> {
         vec![
@@ -463,6 +1084,12 @@ impl SettingsPanel {
                 value: self.settings.bridge_lines.join("\n"),
                 visible_when: "use_bridges",
             },
+            SettingItem::Toggle {
+                id: "use_snowflake",
+                label: "Use Snowflake",
+                description: "Connect through built-in Snowflake bridges, no bridge lines needed",
+                value: self.settings.use_snowflake,
+            },
             SettingItem::Info {
                 label: "Security Level",
                 value: "Maximum (cannot be changed)",
@@ -545,13 +1172,305 @@ impl Default for WindowManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use forloop_network::ErrorClass;
 
     #[test]
     fn test_tor_status_display() {
         let (tx, _rx) = mpsc::channel(10);
-        let ui = BrowserUi::new(tx);
+        let mut ui = BrowserUi::new(tx);
 
         assert_eq!(ui.tor_status_display(), "Connecting to Tor...");
+
+        ui.handle_message(UiMessage::TorStatusChanged(TorStatus::Bootstrapping(45)));
+        assert_eq!(ui.tor_status_display(), "Connecting to Tor… 45%");
+        ui.handle_message(UiMessage::TorStatusChanged(TorStatus::Stalled(
+            30,
+            "No route to host".to_string(),
+        )));
+        assert_eq!(ui.tor_status_display(), "Tor Stalled at 30%");
+    }
+
+    #[test]
+    fn test_degraded_status() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        ui.handle_message(UiMessage::TorStatusChanged(TorStatus::Degraded(
+            "Network offline".to_string(),
+        )));
+        assert_eq!(ui.tor_status_display(), "Tor Degraded");
+
+        let dialog = ErrorDialog::connection_failed("timeout")
+            .with_hint("Your network connection appears to be offline.");
+        assert!(dialog.message.starts_with("Your network connection"));
+    }
+
+    #[test]
+    fn test_socks_failure_dialogs() {
+        let offline = ErrorDialog::socks_failure(SocksReplyCode::OnionIntroductionFailed);
+        assert_eq!(offline.title, "Onion Service Offline");
+        assert!(offline.message.starts_with("This onion service is offline"));
+
+        let missing = ErrorDialog::socks_failure(SocksReplyCode::OnionDescriptorNotFound);
+        assert!(missing.message.starts_with("Descriptor not found"));
+        assert_ne!(missing.title, offline.title);
+
+        // Every onion code has its own wording, never the generic text
+        for code in 0xF0..=0xF7 {
+            let dialog = ErrorDialog::socks_failure(SocksReplyCode::from_code(code));
+            assert!(
+                !dialog.message.contains("Technical details"),
+                "{:#04x}",
+                code
+            );
+            assert!(!dialog.show_report);
+        }
+        let other = ErrorDialog::socks_failure(SocksReplyCode::Other(0x42));
+        assert!(other
+            .message
+            .ends_with("Technical details: reply code 0x42"));
+    }
+
+    #[test]
+    fn test_bootstrap_guidance() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        ui.handle_message(UiMessage::SuggestBridges);
+        assert_eq!(ui.tor_status_display(), "Tor Failed");
+
+        ui.handle_message(UiMessage::BridgeRotated(1));
+        assert_eq!(ui.tor_status_display(), "Connecting to Tor...");
+    }
+
+    #[test]
+    fn test_reduced_isolation_shown_once() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        let notice = "running with reduced isolation: no Landlock";
+        ui.handle_message(UiMessage::ReducedIsolation(notice.to_string()));
+        assert_eq!(ui.isolation_notice(), Some(notice));
+
+        ui.dismiss_isolation_notice();
+        ui.handle_message(UiMessage::ReducedIsolation(notice.to_string()));
+        assert_eq!(ui.isolation_notice(), None);
+    }
+
+    #[tokio::test]
+    async fn test_download_notice() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.download_notice(), None);
+
+        ui.handle_message(UiMessage::PdfDownloaded(
+            "/dev/shm/forloop-downloads/report.pdf".to_string(),
+        ));
+        assert_eq!(
+            ui.download_notice().as_deref(),
+            Some(
+                "forloop does not display PDFs inline. The file was saved to \
+                 /dev/shm/forloop-downloads/report.pdf (in memory, erased on New Loop)."
+            )
+        );
+
+        ui.dismiss_download_notice();
+        assert_eq!(ui.download_notice(), None);
+
+        // The file is gone after New Loop, so is the notice
+        ui.handle_message(UiMessage::PdfDownloaded("a.pdf".to_string()));
+        ui.new_loop().await;
+        assert_eq!(ui.download_notice(), None);
+    }
+
+    #[tokio::test]
+    async fn test_onion_status_and_retry() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        ui.handle_message(UiMessage::ConnectStatus("Introducing…".to_string()));
+        ui.handle_message(UiMessage::ConnectStatus("Rendezvous…".to_string()));
+        assert_eq!(ui.connect_status(), Some("Rendezvous…"));
+
+        let prompt = RetryPrompt {
+            message: "The onion service did not answer.".to_string(),
+            url: "https://abcxyz.onion/".to_string(),
+            reuse_descriptor: true,
+        };
+        ui.handle_message(UiMessage::OfferRetry(prompt.clone()));
+        assert_eq!(ui.connect_status(), None);
+        assert_eq!(ui.retry_prompt(), Some(&prompt));
+
+        ui.retry().await;
+        ui.retry().await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(UiMessage::RetryOnion {
+                reuse_descriptor: true,
+                ..
+            })
+        ));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(ui.retry_prompt(), None);
+
+        // A finished load clears the step
+        ui.handle_message(UiMessage::ConnectStatus("Waiting for service…".to_string()));
+        ui.handle_message(UiMessage::LoadProgress(100));
+        assert_eq!(ui.connect_status(), None);
+    }
+
+    #[tokio::test]
+    async fn test_security_warning() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.security_warning(), None);
+
+        ui.handle_message(UiMessage::SecurityWarning(
+            "Your traffic may be intercepted.".to_string(),
+        ));
+        assert_eq!(
+            ui.security_warning(),
+            Some("Your traffic may be intercepted.")
+        );
+        assert_eq!(ui.security_color(), "#ffaa00");
+
+        ui.navigate("https://example.com/").await;
+        assert_eq!(ui.security_warning(), None);
+    }
+
+    #[tokio::test]
+    async fn test_url_bar_paste_intercepted() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        let forwarded = ui.on_paste(
+            PasteTarget::UrlBar,
+            "https://example.com/?utm_source=a&utm_medium=b&utm_campaign=c&fbclid=d".to_string(),
+        );
+        assert_eq!(forwarded, None);
+        assert_eq!(ui.url_bar_text(), Some("https://example.com/"));
+        assert_eq!(
+            ui.paste_note(),
+            Some("Cleaned 4 tracking parameters from pasted link")
+        );
+
+        // A rejected paste leaves the earlier text in place
+        assert_eq!(
+            ui.on_paste(PasteTarget::UrlBar, "a".repeat(MAX_PASTE_BYTES + 1)),
+            None
+        );
+        assert_eq!(ui.paste_note(), Some("Pasted text is too long"));
+        assert_eq!(
+            ui.on_paste(PasteTarget::UrlBar, "ftp://example.com/".to_string()),
+            None
+        );
+        assert_eq!(ui.url_bar_text(), Some("https://example.com/"));
+
+        assert_eq!(
+            ui.on_paste(PasteTarget::UrlBar, "tor browser".to_string()),
+            None
+        );
+        assert_eq!(ui.url_bar_text(), Some("tor browser"));
+        assert_eq!(ui.paste_note(), None);
+
+        // Pastes into the page go to content unchanged
+        assert_eq!(
+            ui.on_paste(PasteTarget::Content, "?utm_source=a".to_string()),
+            Some(InputEvent::Paste {
+                text: "?utm_source=a".to_string()
+            })
+        );
+
+        ui.navigate("https://example.com/").await;
+        assert_eq!(ui.url_bar_text(), None);
+        assert_eq!(ui.paste_note(), None);
+        // Nothing from the URL bar pastes was sent anywhere
+        assert!(matches!(rx.try_recv(), Ok(UiMessage::Navigate { .. })));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_navigation_kind_sent_to_core() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        // Nothing to reload yet
+        ui.reload().await;
+        ui.navigate("https://example.com/a").await;
+        ui.follow_link("https://example.org/b").await;
+        ui.reload().await;
+        ui.traverse_history("https://example.com/a").await;
+        drop(ui);
+
+        let mut sent = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            if let UiMessage::Navigate {
+                url,
+                kind,
+                referrer,
+            } = msg
+            {
+                sent.push((url, kind, referrer));
+            }
+        }
+        let a = "https://example.com/a".to_string();
+        let b = "https://example.org/b".to_string();
+        assert_eq!(
+            sent,
+            vec![
+                (a.clone(), NavigationKind::AddressBar, None),
+                (b.clone(), NavigationKind::LinkClick, Some(a.clone())),
+                (b, NavigationKind::Reload, None),
+                (a, NavigationKind::HistoryTraversal, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connection_notice_until_navigation() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.connection_notice(), None);
+
+        ui.handle_message(UiMessage::ExcessiveConnections);
+        assert!(ui
+            .connection_notice()
+            .is_some_and(|notice| notice.contains("unusual number of connection attempts")));
+
+        ui.navigate("https://example.com/").await;
+        assert_eq!(ui.connection_notice(), None);
+    }
+
+    #[tokio::test]
+    async fn test_goodbye_page_wiped_on_quit() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.goodbye_page(), None);
+
+        ui.handle_message(UiMessage::SessionSummary(SessionSummary {
+            pages_loaded: 3,
+            circuits_used: 2,
+            trackers_stripped: 5,
+            readbacks_noised: 7,
+            padding_bytes: 4096,
+        }));
+        assert_eq!(ui.current_url, GOODBYE_URL);
+        let lines = ui.goodbye_page().expect("goodbye page shown");
+        assert_eq!(lines[0], "Pages loaded: 3");
+        assert_eq!(lines[4], "Padding overhead: 4096 bytes");
+
+        ui.quit().await;
+        assert_eq!(ui.goodbye_page(), None);
+    }
+
+    #[test]
+    fn test_modified_build_banner() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.build_banner(), None);
+
+        ui.handle_message(UiMessage::ModifiedBuild);
+        assert!(ui.build_banner().is_some());
     }
 
     #[test]
@@ -562,6 +1481,115 @@ mod tests {
         assert_eq!(ui.security_color(), "#00ff00");
     }
 
+    #[tokio::test]
+    async fn test_upload_progress_and_cancel() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        ui.handle_message(UiMessage::UploadProgress {
+            sent_bytes: 25,
+            total_bytes: 100,
+        });
+        assert_eq!(ui.upload_percent(), Some(25));
+
+        ui.cancel_upload().await;
+        assert_eq!(ui.upload_percent(), None);
+        assert!(matches!(rx.recv().await, Some(UiMessage::CancelUpload)));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_display() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+        assert_eq!(ui.metrics_display(), None);
+
+        ui.handle_message(UiMessage::MetricsUpdated(MetricsSnapshot {
+            attempts: 12,
+            successes: 10,
+            failures: vec![(ErrorClass::Timeout, 2)],
+            bytes_sent: 40 * 1024,
+            bytes_received: 300 * 1024,
+            circuits_built: 12,
+        }));
+        assert_eq!(
+            ui.metrics_display().as_deref(),
+            Some("12 requests, 12 circuits, 2 failures, 340 KB transferred")
+        );
+        assert_eq!(display_bytes(900), "900 B");
+        assert_eq!(display_bytes(3 * 1024 * 1024 / 2), "1.5 MB");
+
+        // The totals go with the loop
+        ui.new_loop().await;
+        assert_eq!(ui.metrics_display(), None);
+        assert!(matches!(rx.recv().await, Some(UiMessage::NewLoop)));
+    }
+
+    #[tokio::test]
+    async fn test_draft_restore_and_wipe() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        ui.handle_message(UiMessage::FormDraft(DraftText::new(
+            "long onion forum post".to_string(),
+        )));
+        ui.handle_message(UiMessage::ContentRecycled);
+        assert!(ui.draft_restore_offered());
+
+        ui.restore_draft().await;
+        match rx.recv().await {
+            Some(UiMessage::RestoreDraft(draft)) => {
+                assert_eq!(draft.as_str(), "long onion forum post")
+            }
+            other => panic!("expected RestoreDraft, got {:?}", other),
+        }
+        assert!(!ui.draft_restore_offered());
+
+        // Navigation, New Loop and quit each drop a pending draft
+        ui.handle_message(UiMessage::FormDraft(DraftText::new("a".to_string())));
+        ui.navigate("https://example.onion").await;
+        ui.handle_message(UiMessage::ContentRecycled);
+        assert!(!ui.draft_restore_offered());
+
+        ui.handle_message(UiMessage::FormDraft(DraftText::new("b".to_string())));
+        ui.new_loop().await;
+        ui.handle_message(UiMessage::ContentRecycled);
+        assert!(!ui.draft_restore_offered());
+
+        ui.handle_message(UiMessage::FormDraft(DraftText::new("c".to_string())));
+        ui.quit().await;
+        ui.handle_message(UiMessage::ContentRecycled);
+        assert!(!ui.draft_restore_offered());
+    }
+
+    #[tokio::test]
+    async fn test_verify_page() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let mut ui = BrowserUi::new(tx);
+
+        // Nothing to verify before the first navigation
+        ui.verify_page().await;
+        assert!(rx.try_recv().is_err());
+
+        ui.navigate("https://example.onion/").await;
+        let _ = rx.recv().await;
+        ui.verify_page().await;
+        match rx.recv().await {
+            Some(UiMessage::VerifyPage(url)) => assert_eq!(url, "https://example.onion/"),
+            other => panic!("expected VerifyPage, got {:?}", other),
+        }
+
+        ui.handle_message(UiMessage::PageVerified(PageConsistency::Mismatch {
+            first_difference: 42,
+        }));
+        let line = ui.verification_display().expect("verified");
+        assert!(line.starts_with("Warning"));
+        assert!(line.contains("42"));
+
+        // The result belongs to the page it was computed for
+        ui.navigate("https://other.onion/").await;
+        assert!(ui.verification_display().is_none());
+    }
+
     #[test]
     fn test_window_title_never_shows_url() {
         let wm = WindowManager::new();
@@ -573,4 +1601,54 @@ mod tests {
         let panel = SettingsPanel::new();
         assert_eq!(panel.settings.security_level, SecurityLevel::Maximum);
     }
+
+    #[test]
+    fn test_settings_bridge_lines_validated() {
+        let mut panel = SettingsPanel::new();
+        let bridges = panel
+            .set_bridge_lines("192.0.2.1:443\n\n198.51.100.7:9001\n")
+            .expect("valid bridges");
+        assert_eq!(bridges.len(), 2);
+
+        let error = panel
+            .set_bridge_lines("192.0.2.1:443\nobfs4 198.51.100.7:9001")
+            .expect_err("obfs4 without fingerprint");
+        assert_eq!(error.to_string(), "Bridge line 2: fingerprint is missing");
+        assert_eq!(panel.settings.bridge_lines.len(), 2);
+    }
+
+    #[test]
+    fn test_settings_snowflake_toggle() {
+        let mut panel = SettingsPanel::new();
+        let snowflake_value = |panel: &SettingsPanel| {
+            panel
+                .available_settings()
+                .into_iter()
+                .find_map(|item| match item {
+                    SettingItem::Toggle {
+                        id: "use_snowflake",
+                        value,
+                        ..
+                    } => Some(value),
+                    _ => None,
+                })
+                .expect("snowflake toggle")
+        };
+        assert!(!snowflake_value(&panel));
+        assert!(panel.bridges().expect("no bridges").is_empty());
+
+        // No bridge lines needed
+        panel.set_use_snowflake(true);
+        assert!(snowflake_value(&panel));
+        assert_eq!(panel.bridges().expect("bridges"), snowflake_bridges());
+
+        // Alongside pasted lines
+        panel.settings.use_bridges = true;
+        panel
+            .set_bridge_lines("192.0.2.1:443")
+            .expect("valid bridges");
+        let bridges = panel.bridges().expect("bridges");
+        assert_eq!(bridges.len(), 1 + snowflake_bridges().len());
+        assert_eq!(bridges[0].to_string(), "192.0.2.1:443");
+    }
 }
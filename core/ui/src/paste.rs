@@ -0,0 +1,229 @@
+//! Paste interception for the URL bar.
+//!
+//! A link copied out of a newsletter carries click identifiers. Pasting
+//! it should not put them on screen, in UI memory, or in the request the
+//! user sends by pressing Enter. Pastes aimed at the URL bar are handled
+//! here and never forwarded to the content process:
+//!
+//! - the clipboard text goes straight into a zeroizing `PastedText`,
+//!   refused outright above `MAX_PASTE_BYTES`
+//! - text that looks like a URL is stripped of tracking parameters and
+//!   run through the network validator; only a valid, cleaned URL is
+//!   displayed, and a note says how many parameters were removed
+//! - anything else is shown as typed text
+//!
+//! The original is zeroed as soon as the decision is made.
+
+use std::fmt;
+
+use forloop_network::{
+    strip_tracking_params, validate_request, Destination, FrameId, NetworkRequestMsg,
+};
+
+use crate::draft::zeroize;
+
+/// Largest paste accepted into the URL bar, in bytes.
+pub const MAX_PASTE_BYTES: usize = 8 * 1024;
+
+/// Where the focus was when the user pasted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteTarget {
+    /// The URL bar.
+    UrlBar,
+    /// An editable element in the page.
+    Content,
+}
+
+/// Pasted text that is zeroed on drop and never printed.
+pub struct PastedText {
+    bytes: Vec<u8>,
+}
+
+impl PastedText {
+    /// Take ownership of `text`; `None` (after zeroing it) if it is over
+    /// `MAX_PASTE_BYTES`.
+    pub fn new(text: String) -> Option<Self> {
+        let pasted = Self {
+            bytes: text.into_bytes(),
+        };
+        (pasted.bytes.len() <= MAX_PASTE_BYTES).then_some(pasted)
+    }
+
+    /// Get the text.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes).unwrap_or_default()
+    }
+}
+
+impl Drop for PastedText {
+    fn drop(&mut self) {
+        zeroize(&mut self.bytes);
+    }
+}
+
+impl fmt::Debug for PastedText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PastedText([REDACTED; {}])", self.bytes.len())
+    }
+}
+
+/// What a URL-bar paste turned into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasteOutcome {
+    /// A valid URL, with tracking parameters removed.
+    Url {
+        /// Cleaned URL to display.
+        url: String,
+        /// Tracking parameters removed.
+        removed: usize,
+    },
+    /// Not a URL; shown as typed text.
+    Text(String),
+    /// Looked like a URL but failed validation; nothing is displayed.
+    Invalid,
+    /// Over `MAX_PASTE_BYTES`; nothing is displayed.
+    TooLarge,
+}
+
+impl PasteOutcome {
+    /// Decide what to show for `pasted`.
+    pub fn classify(pasted: &PastedText) -> Self {
+        let text = pasted.as_str().trim();
+        let candidate = match scheme_of(text) {
+            Some(_) => copy(text),
+            // Bare host, as people copy them out of text
+            None if looks_like_host(text) => copy(&format!("https://{}", text)),
+            None => return PasteOutcome::Text(text.to_string()),
+        };
+
+        let (url, removed) = strip_tracking_params(candidate.as_str());
+        let valid = validate_request(
+            NetworkRequestMsg {
+                method: "GET".to_string(),
+                url: url.clone(),
+                headers: Vec::new(),
+                body: None,
+                frame_id: FrameId::TOP,
+                destination: Destination::Document,
+            },
+            0,
+        );
+        match valid {
+            Ok(_) => PasteOutcome::Url { url, removed },
+            Err(_) => {
+                drop(PastedText {
+                    bytes: url.into_bytes(),
+                });
+                PasteOutcome::Invalid
+            }
+        }
+    }
+
+    /// Note shown under the URL bar, if any.
+    pub fn note(&self) -> Option<String> {
+        match self {
+            PasteOutcome::Url { removed: 0, .. } | PasteOutcome::Text(_) => None,
+            PasteOutcome::Url { removed: 1, .. } => {
+                Some("Cleaned 1 tracking parameter from pasted link".to_string())
+            }
+            PasteOutcome::Url { removed, .. } => Some(format!(
+                "Cleaned {} tracking parameters from pasted link",
+                removed
+            )),
+            PasteOutcome::Invalid => Some("Pasted link is not a valid HTTPS address".to_string()),
+            PasteOutcome::TooLarge => Some("Pasted text is too long".to_string()),
+        }
+    }
+}
+
+/// Copy of (part of) the pasted text, zeroed on drop as well.
+fn copy(text: &str) -> PastedText {
+    PastedText {
+        bytes: text.as_bytes().to_vec(),
+    }
+}
+
+/// The URL scheme `text` starts with, if it starts with one.
+///
+/// `host:port` is not a scheme: after the colon comes a digit.
+fn scheme_of(text: &str) -> Option<&str> {
+    let (scheme, rest) = text.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && !rest.starts_with(|c: char| c.is_ascii_digit());
+    valid.then_some(scheme)
+}
+
+/// Whether `text` is a single word with a dot in its first segment.
+fn looks_like_host(text: &str) -> bool {
+    let host = text.split(['/', '?', '#']).next().unwrap_or_default();
+    !text.is_empty() && !text.contains(char::is_whitespace) && host.contains('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(text: &str) -> PasteOutcome {
+        PasteOutcome::classify(&PastedText::new(text.to_string()).expect("within bound"))
+    }
+
+    #[test]
+    fn test_paste_classification() {
+        let cleaned = classify(
+            "  https://example.com/a?utm_source=mail&id=7&fbclid=x&mc_eid=1&utm_medium=e\n",
+        );
+        assert_eq!(
+            cleaned,
+            PasteOutcome::Url {
+                url: "https://example.com/a?id=7".to_string(),
+                removed: 4,
+            }
+        );
+        assert_eq!(
+            cleaned.note().as_deref(),
+            Some("Cleaned 4 tracking parameters from pasted link")
+        );
+
+        assert_eq!(
+            classify("example.com/?gclid=1"),
+            PasteOutcome::Url {
+                url: "https://example.com/".to_string(),
+                removed: 1,
+            }
+        );
+        assert_eq!(classify("https://example.com/").note(), None);
+        assert_eq!(
+            classify("localhost:8080"),
+            PasteOutcome::Text("localhost:8080".to_string())
+        );
+    }
+
+    #[test]
+    fn test_invalid_and_text_pastes() {
+        for invalid in [
+            "http://example.com/?utm_source=x",
+            "javascript:alert(1)",
+            "https://user@example.com/",
+            "https://",
+        ] {
+            assert_eq!(classify(invalid), PasteOutcome::Invalid, "{}", invalid);
+        }
+
+        for text in ["privacy browser", "hello", "meet at 10.30 tomorrow"] {
+            let outcome = classify(text);
+            assert_eq!(outcome, PasteOutcome::Text(text.to_string()));
+            assert_eq!(outcome.note(), None);
+        }
+    }
+
+    #[test]
+    fn test_oversized_paste_refused() {
+        assert!(PastedText::new("a".repeat(MAX_PASTE_BYTES)).is_some());
+        assert!(PastedText::new("a".repeat(MAX_PASTE_BYTES + 1)).is_none());
+        let pasted =
+            PastedText::new("https://example.com/?fbclid=1".to_string()).expect("within bound");
+        assert_eq!(format!("{:?}", pasted), "PastedText([REDACTED; 29])");
+    }
+}
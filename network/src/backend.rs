@@ -0,0 +1,296 @@
+//! Where circuits and streams come from.
+//!
+//! `CircuitManager` and its `Circuit`s ask a `TorBackend` for circuits,
+//! streams and name lookups, and never talk to Tor themselves. The one
+//! backend today is the external tor daemon, driven by `TorController`
+//! over its control and SOCKS ports, TCP or Unix sockets (see
+//! `SocksEndpoint`). An in-process Tor only has to offer the same
+//! operations.
+//!
+//! Every stream is isolated by the circuit's `IsolationToken`; a backend
+//! must never put streams with different tokens on the same circuit.
+
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+
+use crate::control_protocol::BootstrapProgress;
+use crate::socks::{socks5_connect, socks5_resolve, IsolationToken};
+use crate::tor_integration::TorController;
+use crate::NetworkError;
+
+/// Future returned by `TorBackend` operations.
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, NetworkError>> + Send + 'a>>;
+
+/// A byte stream to a destination, opened through Tor.
+pub trait TorStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> TorStream for T {}
+
+/// A Tor implementation the circuit manager can run on.
+pub trait TorBackend: fmt::Debug + Send + Sync {
+    /// Get a fresh circuit for a request and return its id.
+    fn new_circuit(&self) -> BackendFuture<'_, String>;
+
+    /// Get a circuit isolated from every other, keeping cached onion
+    /// service descriptors.
+    fn new_isolated_circuit(&self) -> BackendFuture<'_, String>;
+
+    /// Close circuit `circuit_id`. Closing an unknown circuit succeeds.
+    fn close_circuit<'a>(&'a self, circuit_id: &'a str) -> BackendFuture<'a, ()>;
+
+    /// Open a stream to `host:port`, isolated by `isolation`.
+    ///
+    /// `host` is resolved at the exit, never locally.
+    fn connect_stream<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        isolation: &'a IsolationToken,
+    ) -> BackendFuture<'a, Box<dyn TorStream>>;
+
+    /// Follow bootstrap progress.
+    fn bootstrap_events(&self) -> watch::Receiver<BootstrapProgress>;
+
+    /// Resolve `host` at the exit, isolated by `isolation`.
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        isolation: &'a IsolationToken,
+    ) -> BackendFuture<'a, IpAddr>;
+}
+
+impl TorBackend for TorController {
+    fn new_circuit(&self) -> BackendFuture<'_, String> {
+        Box::pin(TorController::new_circuit(self))
+    }
+
+    fn new_isolated_circuit(&self) -> BackendFuture<'_, String> {
+        Box::pin(TorController::new_isolated_circuit(self))
+    }
+
+    fn close_circuit<'a>(&'a self, circuit_id: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(TorController::close_circuit(self, circuit_id))
+    }
+
+    fn connect_stream<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        isolation: &'a IsolationToken,
+    ) -> BackendFuture<'a, Box<dyn TorStream>> {
+        Box::pin(async move {
+            let mut stream = self.socks_addr().connect().await?;
+            socks5_connect(&mut stream, host, port, Some(isolation)).await?;
+            Ok(stream)
+        })
+    }
+
+    fn bootstrap_events(&self) -> watch::Receiver<BootstrapProgress> {
+        self.bootstrap_progress()
+    }
+
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+        isolation: &'a IsolationToken,
+    ) -> BackendFuture<'a, IpAddr> {
+        Box::pin(async move {
+            let mut stream = self.socks_addr().connect().await?;
+            socks5_resolve(&mut stream, host, Some(isolation)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CircuitManager;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+
+    /// Backend that numbers circuits and hands out in-memory streams,
+    /// recording every call.
+    #[derive(Debug, Default)]
+    struct FakeBackend {
+        circuits: AtomicU64,
+        calls: Mutex<Vec<String>>,
+        peers: Mutex<Vec<tokio::io::DuplexStream>>,
+    }
+
+    impl FakeBackend {
+        fn record(&self, call: String) {
+            self.calls.lock().expect("calls").push(call);
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().expect("calls").clone()
+        }
+    }
+
+    impl TorBackend for FakeBackend {
+        fn new_circuit(&self) -> BackendFuture<'_, String> {
+            let id = self.circuits.fetch_add(1, Ordering::Relaxed) + 1;
+            self.record(format!("new {}", id));
+            Box::pin(async move { Ok(id.to_string()) })
+        }
+
+        fn new_isolated_circuit(&self) -> BackendFuture<'_, String> {
+            self.record("isolated".to_string());
+            Box::pin(async { Ok("isolated".to_string()) })
+        }
+
+        fn close_circuit<'a>(&'a self, circuit_id: &'a str) -> BackendFuture<'a, ()> {
+            self.record(format!("close {}", circuit_id));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn connect_stream<'a>(
+            &'a self,
+            host: &'a str,
+            port: u16,
+            isolation: &'a IsolationToken,
+        ) -> BackendFuture<'a, Box<dyn TorStream>> {
+            self.record(format!(
+                "connect {}:{} as {}",
+                host,
+                port,
+                isolation.username()
+            ));
+            let (stream, peer) = tokio::io::duplex(64 * 1024);
+            self.peers.lock().expect("peers").push(peer);
+            Box::pin(async move { Ok(Box::new(stream) as Box<dyn TorStream>) })
+        }
+
+        fn bootstrap_events(&self) -> watch::Receiver<BootstrapProgress> {
+            watch::channel(BootstrapProgress::default()).1
+        }
+
+        fn resolve<'a>(
+            &'a self,
+            host: &'a str,
+            _isolation: &'a IsolationToken,
+        ) -> BackendFuture<'a, IpAddr> {
+            self.record(format!("resolve {}", host));
+            Box::pin(async { Ok(IpAddr::from([192, 0, 2, 1])) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_manager_runs_on_any_backend() {
+        let backend = Arc::new(FakeBackend::default());
+        let manager = CircuitManager::new(Arc::clone(&backend) as Arc<dyn TorBackend>);
+
+        let first = manager.create_new_circuit().await.expect("circuit");
+        let second = manager
+            .create_circuit_reusing_descriptors()
+            .await
+            .expect("circuit");
+        assert_eq!((first.id(), second.id()), ("1", "isolated"));
+
+        // The request's stream comes from the backend, with the circuit's
+        // isolation; the fake server closing it ends the TLS handshake
+        let request = first.request(
+            "GET",
+            "https://example.com/",
+            &[],
+            None,
+            crate::TlsFingerprintNormalizer::new()
+                .create_config()
+                .expect("TLS config"),
+            Duration::from_secs(5),
+            1024,
+        );
+        let server = async {
+            while backend.peers.lock().expect("peers").is_empty() {
+                tokio::task::yield_now().await;
+            }
+            let mut peer = backend.peers.lock().expect("peers").remove(0);
+            let mut hello = [0u8; 5];
+            peer.read_exact(&mut hello).await.expect("ClientHello");
+            hello
+        };
+        let (result, hello) = tokio::join!(request, server);
+        assert!(result.is_err());
+        // A TLS handshake record
+        assert_eq!(hello[0], 0x16);
+
+        manager.close_all().await.expect("closed");
+        assert_eq!(
+            backend.calls(),
+            [
+                "new 1",
+                "isolated",
+                "connect example.com:443 as forloop-1",
+                "close 1",
+                "close isolated",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_controller_streams_over_unix_socket() {
+        use crate::control_protocol::tests::{mock_control_port, tor_ready};
+        use crate::SocksEndpoint;
+        use forloop_config::Port;
+        use tokio::io::AsyncWriteExt;
+
+        let dir = std::env::temp_dir().join(format!("forloop-socks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        let path = dir.join("socks.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).expect("bind");
+        let (control, _) = mock_control_port(tor_ready());
+        let tor = TorController::with_control_stream(Port::new(9150), Port::new(9151), control)
+            .await
+            .expect("controller")
+            .with_socks_endpoint(SocksEndpoint::Unix(path.clone()));
+        assert_eq!(
+            tor.socks_addr().to_string(),
+            format!("unix:{}", path.display())
+        );
+
+        let proxy = tokio::spawn(async move {
+            let (mut proxy, _) = listener.accept().await.expect("accept");
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 2]).await.expect("choice");
+            let mut auth = [0u8; 2];
+            proxy.read_exact(&mut auth).await.expect("username length");
+            let mut username = vec![0u8; auth[1] as usize];
+            proxy.read_exact(&mut username).await.expect("username");
+            let mut password = vec![0u8; proxy.read_u8().await.expect("length") as usize];
+            proxy.read_exact(&mut password).await.expect("password");
+            proxy.write_all(&[1, 0]).await.expect("auth status");
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut target = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut target).await.expect("target");
+            proxy
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .expect("reply");
+            target
+        });
+
+        let isolation = IsolationToken::new("1", 1, 1);
+        TorBackend::connect_stream(&tor, "example.com", 443, &isolation)
+            .await
+            .expect("stream");
+        assert_eq!(proxy.await.expect("proxy"), b"example.com\x01\xbb");
+
+        std::fs::remove_dir_all(&dir).expect("clean up");
+        // Nothing listens there any more
+        assert!(matches!(
+            TorBackend::connect_stream(&tor, "example.com", 443, &isolation).await,
+            Err(NetworkError::TorConnectionFailed(_))
+        ));
+    }
+}
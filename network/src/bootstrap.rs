@@ -0,0 +1,516 @@
+//! Bootstrap supervision for censored networks.
+//!
+//! Where Tor is blocked, a direct bootstrap never completes and the user is
+//! left looking at "Connecting" with no idea that bridges exist. The
+//! supervisor bounds each attempt with a deadline, classifies why it failed
+//! from the control-port BOOTSTRAP warnings, and decides what to do next:
+//!
+//! - Relays unreachable or TLS reset, no bridges configured: suggest bridges
+//! - Relays unreachable or TLS reset, bridges configured: rotate to the next
+//! - Timeouts: retry after a bounded exponential backoff with jitter
+//!
+//! Quit cancels a running supervisor; a settings change restarts it.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use forloop_config::{system_clock, Clock};
+use rand::Rng;
+use tokio::sync::Notify;
+
+use crate::NetworkError;
+
+/// Why a bootstrap attempt failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapFailure {
+    /// No relay could be reached at all (refused, no route)
+    NoRelayConnection,
+    /// Connections opened but were reset during the TLS handshake
+    TlsReset,
+    /// Connections or the handshake timed out, or no progress was reported
+    Timeout,
+}
+
+impl BootstrapFailure {
+    /// Classify a failed attempt from its control-port event lines.
+    ///
+    /// Looks at the REASON of every "STATUS_CLIENT WARN BOOTSTRAP" event;
+    /// the most frequent class wins, and no warnings at all means the
+    /// deadline expired.
+    pub fn classify(lines: &[&str]) -> Self {
+        let mut counts = [0usize; 3];
+
+        for line in lines {
+            if !line.contains("STATUS_CLIENT WARN BOOTSTRAP") {
+                continue;
+            }
+            let reason = line
+                .split(' ')
+                .find_map(|arg| arg.strip_prefix("REASON="))
+                .unwrap_or_default();
+            let class = match reason.trim_end() {
+                "CONNECTREFUSED" | "NOROUTE" => BootstrapFailure::NoRelayConnection,
+                "CONNECTRESET" | "IOERROR" | "IDENTITY" => BootstrapFailure::TlsReset,
+                "TIMEOUT" => BootstrapFailure::Timeout,
+                _ => continue,
+            };
+            counts[class as usize] += 1;
+        }
+
+        [
+            BootstrapFailure::NoRelayConnection,
+            BootstrapFailure::TlsReset,
+            BootstrapFailure::Timeout,
+        ]
+        .into_iter()
+        .rev()
+        .max_by_key(|class| counts[*class as usize])
+        .filter(|class| counts[*class as usize] > 0)
+        .unwrap_or(BootstrapFailure::Timeout)
+    }
+
+    /// Whether the pattern points at Tor being blocked.
+    pub fn looks_censored(self) -> bool {
+        matches!(
+            self,
+            BootstrapFailure::NoRelayConnection | BootstrapFailure::TlsReset
+        )
+    }
+}
+
+/// What the supervisor does after a failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuggestedAction {
+    /// Ask the user to configure bridges
+    TryBridges,
+    /// Switch to the bridge at this index and retry
+    RotateBridge(usize),
+    /// Retry the same configuration after a backoff
+    Retry,
+}
+
+/// Progress reported to the UI while supervising.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SupervisorEvent {
+    /// Starting an attempt, through a bridge if `bridge` is set
+    Attempting {
+        /// Attempt number, from 1
+        attempt: u32,
+        /// Index of the bridge in use
+        bridge: Option<usize>,
+    },
+    /// An attempt failed
+    Failed(BootstrapFailure),
+    /// Tor looks blocked and no bridges are configured
+    SuggestBridges,
+    /// Switched to another configured bridge
+    RotatedBridge(usize),
+    /// Waiting before the next attempt
+    RetryIn(Duration),
+    /// Bootstrap completed
+    Connected,
+    /// Supervision was cancelled
+    Cancelled,
+}
+
+/// Bounded exponential backoff between attempts.
+#[derive(Debug, Clone)]
+pub struct BackoffPolicy {
+    /// Delay after the first failure
+    pub base: Duration,
+    /// Upper bound on any delay
+    pub max: Duration,
+    /// Random spread applied to each delay (0.0 - 1.0)
+    pub jitter: f64,
+    /// Deadline for a single bootstrap attempt
+    pub attempt_deadline: Duration,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(5),
+            max: Duration::from_secs(300),
+            jitter: 0.25,
+            attempt_deadline: Duration::from_secs(60),
+        }
+    }
+}
+
+impl BackoffPolicy {
+    /// Delay before retrying after `failures` consecutive failures.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(16);
+        let nominal = self.base.saturating_mul(1 << exponent).min(self.max);
+
+        let spread = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+        nominal.mul_f64(1.0 + spread).min(self.max)
+    }
+}
+
+/// One bootstrap attempt against the embedded Tor.
+pub trait Bootstrapper: Send + Sync {
+    /// Bootstrap, through `bridge` if set.
+    ///
+    /// On failure, returns the control-port event lines seen meanwhile.
+    fn bootstrap(
+        &self,
+        bridge: Option<&str>,
+    ) -> impl Future<Output = Result<(), Vec<String>>> + Send;
+}
+
+/// Cancels a running supervisor (used by Quit).
+#[derive(Debug, Clone, Default)]
+pub struct SupervisorCancel {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl SupervisorCancel {
+    /// Cancel supervision; a pending attempt or backoff ends at once.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Drives bootstrap attempts until connected, cancelled or stuck.
+#[derive(Debug)]
+pub struct BootstrapSupervisor {
+    bridges: Vec<String>,
+    current_bridge: Option<usize>,
+    policy: BackoffPolicy,
+    failures: u32,
+    cancel: SupervisorCancel,
+    clock: Arc<dyn Clock>,
+}
+
+impl BootstrapSupervisor {
+    /// Create a supervisor for the given bridge lines (may be empty).
+    pub fn new(bridges: Vec<String>, policy: BackoffPolicy) -> Self {
+        let current_bridge = (!bridges.is_empty()).then_some(0);
+        Self {
+            bridges,
+            current_bridge,
+            policy,
+            failures: 0,
+            cancel: SupervisorCancel::default(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Time deadlines and backoff on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get a handle that cancels this supervisor.
+    pub fn cancel_handle(&self) -> SupervisorCancel {
+        self.cancel.clone()
+    }
+
+    /// Start over after a settings change, with new bridge lines.
+    pub fn restart(&mut self, bridges: Vec<String>) {
+        *self = Self::new(bridges, self.policy.clone()).with_clock(Arc::clone(&self.clock));
+    }
+
+    /// Decide what to do after a failure.
+    pub fn next_action(&self, failure: BootstrapFailure) -> SuggestedAction {
+        if !failure.looks_censored() {
+            return SuggestedAction::Retry;
+        }
+        match self.current_bridge {
+            None => SuggestedAction::TryBridges,
+            Some(index) => SuggestedAction::RotateBridge((index + 1) % self.bridges.len()),
+        }
+    }
+
+    /// Run attempts until bootstrap succeeds.
+    ///
+    /// Returns an error when Tor looks blocked and there are no bridges to
+    /// try (after emitting `SuggestBridges`), or when cancelled.
+    pub async fn run<B: Bootstrapper>(
+        &mut self,
+        bootstrapper: &B,
+        mut on_event: impl FnMut(SupervisorEvent),
+    ) -> Result<(), NetworkError> {
+        loop {
+            if self.cancel.is_cancelled() {
+                on_event(SupervisorEvent::Cancelled);
+                return Err(NetworkError::Cancelled);
+            }
+
+            on_event(SupervisorEvent::Attempting {
+                attempt: self.failures + 1,
+                bridge: self.current_bridge,
+            });
+
+            let bridge = self.current_bridge.map(|i| self.bridges[i].as_str());
+            let result = tokio::select! {
+                result = bootstrapper.bootstrap(bridge) => Some(result),
+                _ = self.clock.sleep(self.policy.attempt_deadline) => None,
+                _ = self.cancel.notify.notified() => continue,
+            };
+
+            let lines = match result {
+                Some(Ok(())) => {
+                    on_event(SupervisorEvent::Connected);
+                    return Ok(());
+                }
+                Some(Err(lines)) => lines,
+                None => Vec::new(),
+            };
+
+            let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+            let failure = BootstrapFailure::classify(&lines);
+            self.failures += 1;
+            on_event(SupervisorEvent::Failed(failure));
+
+            match self.next_action(failure) {
+                SuggestedAction::TryBridges => {
+                    on_event(SupervisorEvent::SuggestBridges);
+                    return Err(NetworkError::TorConnectionFailed(
+                        "Tor appears to be blocked; bridges are needed".to_string(),
+                    ));
+                }
+                SuggestedAction::RotateBridge(index) => {
+                    self.current_bridge = Some(index);
+                    on_event(SupervisorEvent::RotatedBridge(index));
+                }
+                SuggestedAction::Retry => {}
+            }
+
+            let delay = self.policy.delay(self.failures);
+            on_event(SupervisorEvent::RetryIn(delay));
+            tokio::select! {
+                _ = self.clock.sleep(delay) => {}
+                _ = self.cancel.notify.notified() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const REFUSED: &str = "650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=5 TAG=conn SUMMARY=\"Connecting to a relay\" WARNING=\"Connection refused\" REASON=CONNECTREFUSED COUNT=1 RECOMMENDATION=ignore";
+    const RESET: &str = "650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=10 TAG=handshake_dir SUMMARY=\"Finishing handshake with directory server\" WARNING=\"Connection reset by peer\" REASON=CONNECTRESET COUNT=3 RECOMMENDATION=ignore";
+    const TIMEOUT: &str = "650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=14 TAG=handshake SUMMARY=\"Handshaking with a relay\" WARNING=\"Connection timed out\" REASON=TIMEOUT COUNT=1 RECOMMENDATION=ignore";
+
+    fn policy() -> BackoffPolicy {
+        BackoffPolicy {
+            base: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+            jitter: 0.25,
+            attempt_deadline: Duration::from_millis(50),
+        }
+    }
+
+    /// Fails with scripted event lines, then succeeds.
+    struct ScriptedTor {
+        failures: Mutex<Vec<Vec<String>>>,
+        bridges_used: Mutex<Vec<Option<String>>>,
+    }
+
+    impl ScriptedTor {
+        fn new(failures: &[&[&str]]) -> Self {
+            Self {
+                failures: Mutex::new(
+                    failures
+                        .iter()
+                        .rev()
+                        .map(|lines| lines.iter().map(|l| l.to_string()).collect())
+                        .collect(),
+                ),
+                bridges_used: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Bootstrapper for ScriptedTor {
+        fn bootstrap(
+            &self,
+            bridge: Option<&str>,
+        ) -> impl Future<Output = Result<(), Vec<String>>> + Send {
+            self.bridges_used
+                .lock()
+                .expect("bridges lock")
+                .push(bridge.map(str::to_string));
+            let next = self.failures.lock().expect("failures lock").pop();
+            async move { next.map_or(Ok(()), Err) }
+        }
+    }
+
+    #[test]
+    fn test_classifier() {
+        assert_eq!(
+            BootstrapFailure::classify(&[REFUSED, REFUSED, RESET]),
+            BootstrapFailure::NoRelayConnection
+        );
+        assert_eq!(
+            BootstrapFailure::classify(&[RESET, "650 NETWORK_LIVENESS UP"]),
+            BootstrapFailure::TlsReset
+        );
+        assert_eq!(
+            BootstrapFailure::classify(&[TIMEOUT]),
+            BootstrapFailure::Timeout
+        );
+        assert_eq!(BootstrapFailure::classify(&[]), BootstrapFailure::Timeout);
+    }
+
+    #[test]
+    fn test_action_per_pattern() {
+        let direct = BootstrapSupervisor::new(Vec::new(), policy());
+        let bridged = BootstrapSupervisor::new(
+            vec![
+                "obfs4 192.0.2.1:443".to_string(),
+                "obfs4 192.0.2.2:443".to_string(),
+            ],
+            policy(),
+        );
+
+        for (lines, without_bridges, with_bridges) in [
+            (
+                REFUSED,
+                SuggestedAction::TryBridges,
+                SuggestedAction::RotateBridge(1),
+            ),
+            (
+                RESET,
+                SuggestedAction::TryBridges,
+                SuggestedAction::RotateBridge(1),
+            ),
+            (TIMEOUT, SuggestedAction::Retry, SuggestedAction::Retry),
+        ] {
+            let failure = BootstrapFailure::classify(&[lines]);
+            assert_eq!(direct.next_action(failure), without_bridges);
+            assert_eq!(bridged.next_action(failure), with_bridges);
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let policy = policy();
+        for failures in 1..40 {
+            assert!(policy.delay(failures) <= policy.max);
+        }
+        assert!(policy.delay(1) <= Duration::from_micros(1250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_censored_without_bridges_suggests_bridges() {
+        let tor = ScriptedTor::new(&[&[REFUSED, REFUSED]]);
+        let mut supervisor = BootstrapSupervisor::new(Vec::new(), policy());
+        let mut events = Vec::new();
+
+        assert!(supervisor.run(&tor, |e| events.push(e)).await.is_err());
+        assert_eq!(
+            events,
+            vec![
+                SupervisorEvent::Attempting {
+                    attempt: 1,
+                    bridge: None
+                },
+                SupervisorEvent::Failed(BootstrapFailure::NoRelayConnection),
+                SupervisorEvent::SuggestBridges,
+            ]
+        );
+
+        // The user adds a bridge in settings
+        supervisor.restart(vec!["obfs4 192.0.2.1:443".to_string()]);
+        assert!(supervisor.run(&tor, |_| {}).await.is_ok());
+        assert_eq!(
+            tor.bridges_used.lock().expect("bridges lock").last(),
+            Some(&Some("obfs4 192.0.2.1:443".to_string()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rotates_bridges_then_connects() {
+        let tor = ScriptedTor::new(&[&[RESET], &[TIMEOUT]]);
+        let bridges = vec!["bridge-a".to_string(), "bridge-b".to_string()];
+        let mut supervisor = BootstrapSupervisor::new(bridges, policy());
+        let mut events = Vec::new();
+
+        supervisor
+            .run(&tor, |e| events.push(e))
+            .await
+            .expect("connected");
+
+        assert_eq!(
+            *tor.bridges_used.lock().expect("bridges lock"),
+            vec![
+                Some("bridge-a".to_string()),
+                Some("bridge-b".to_string()),
+                Some("bridge-b".to_string()),
+            ]
+        );
+        assert!(events.contains(&SupervisorEvent::RotatedBridge(1)));
+        assert_eq!(events.last(), Some(&SupervisorEvent::Connected));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_quit_cancels() {
+        let tor = ScriptedTor::new(&[&[TIMEOUT] as &[&str]; 100]);
+        let mut supervisor = BootstrapSupervisor::new(Vec::new(), policy());
+        let cancel = supervisor.cancel_handle();
+
+        let mut events = Vec::new();
+        let result = supervisor
+            .run(&tor, |e| {
+                if matches!(e, SupervisorEvent::Failed(_)) {
+                    cancel.cancel();
+                }
+                events.push(e);
+            })
+            .await;
+
+        assert!(matches!(result, Err(NetworkError::Cancelled)));
+        assert_eq!(events.last(), Some(&SupervisorEvent::Cancelled));
+    }
+
+    /// Never finishes, so every attempt runs into the deadline.
+    struct HangingTor;
+
+    impl Bootstrapper for HangingTor {
+        fn bootstrap(
+            &self,
+            _bridge: Option<&str>,
+        ) -> impl Future<Output = Result<(), Vec<String>>> + Send {
+            std::future::pending()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_attempt_deadline_on_clock() {
+        let clock = system_clock();
+        let start = clock.now();
+        let mut supervisor =
+            BootstrapSupervisor::new(Vec::new(), policy()).with_clock(Arc::clone(&clock));
+        let cancel = supervisor.cancel_handle();
+
+        let mut events = Vec::new();
+        let result = supervisor
+            .run(&HangingTor, |e| {
+                if matches!(e, SupervisorEvent::Failed(_)) {
+                    cancel.cancel();
+                }
+                events.push(e);
+            })
+            .await;
+
+        assert!(matches!(result, Err(NetworkError::Cancelled)));
+        assert!(events.contains(&SupervisorEvent::Failed(BootstrapFailure::Timeout)));
+        assert!(clock.now() - start >= policy().attempt_deadline);
+    }
+}
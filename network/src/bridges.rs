@@ -0,0 +1,400 @@
+//! Bridge line parsing.
+//!
+//! Bridge lines come from the `--bridge` flag and the settings panel as
+//! free text. They are parsed here, before anything reaches the torrc, so
+//! a typo is reported against the line it is on instead of surfacing as
+//! a bootstrap that never finishes. Accepted syntax:
+//!
+//! ```text
+//! [Bridge] <IP:port> [fingerprint]
+//! [Bridge] <transport> <IP:port> [fingerprint] [key=value ...]
+//! ```
+//!
+//! with transport one of obfs4, snowflake or meek_lite. Arguments may only
+//! hold printable ASCII without quotes, backslashes or '#', so a line can
+//! never carry a second torrc directive or a comment.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Length of a relay fingerprint in hex digits.
+const FINGERPRINT_HEX_LEN: usize = 40;
+
+/// Pluggable transport a bridge is reached through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BridgeTransport {
+    /// Plain Tor protocol, no transport
+    Vanilla,
+    /// obfs4
+    Obfs4,
+    /// Snowflake
+    Snowflake,
+    /// meek_lite (domain fronting)
+    MeekLite,
+}
+
+impl BridgeTransport {
+    /// Name in torrc, `None` for vanilla bridges.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            BridgeTransport::Vanilla => None,
+            BridgeTransport::Obfs4 => Some("obfs4"),
+            BridgeTransport::Snowflake => Some("snowflake"),
+            BridgeTransport::MeekLite => Some("meek_lite"),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "obfs4" => Some(BridgeTransport::Obfs4),
+            "snowflake" => Some(BridgeTransport::Snowflake),
+            "meek_lite" => Some(BridgeTransport::MeekLite),
+            _ => None,
+        }
+    }
+
+    /// Arguments the transport cannot connect without.
+    fn required_args(self) -> &'static [&'static str] {
+        match self {
+            BridgeTransport::Vanilla => &[],
+            BridgeTransport::Obfs4 => &["cert", "iat-mode"],
+            BridgeTransport::Snowflake | BridgeTransport::MeekLite => &["url"],
+        }
+    }
+}
+
+/// Why a bridge line was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeParseError {
+    /// Nothing on the line
+    Empty,
+    /// First word is neither an address nor a known transport
+    UnknownTransport(String),
+    /// Not an IP:port with a non-zero port
+    BadAddress(String),
+    /// Not 40 hex digits
+    BadFingerprint(String),
+    /// obfs4 bridges are only safe with the fingerprint pinned
+    MissingFingerprint,
+    /// A required transport argument is absent
+    MissingArg(&'static str),
+    /// Argument is not key=value, or holds forbidden characters
+    BadArg(String),
+    /// Argument given twice
+    DuplicateArg(String),
+    /// Vanilla bridges take no arguments
+    UnexpectedArg(String),
+}
+
+impl fmt::Display for BridgeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BridgeParseError::Empty => write!(f, "empty bridge line"),
+            BridgeParseError::UnknownTransport(word) => {
+                write!(f, "unknown transport {:?}", word)
+            }
+            BridgeParseError::BadAddress(address) => {
+                write!(f, "{:?} is not an IP:port address", address)
+            }
+            BridgeParseError::BadFingerprint(word) => write!(
+                f,
+                "fingerprint {:?} is not {} hex digits",
+                word, FINGERPRINT_HEX_LEN
+            ),
+            BridgeParseError::MissingFingerprint => write!(f, "fingerprint is missing"),
+            BridgeParseError::MissingArg(name) => write!(f, "{}= argument is missing", name),
+            BridgeParseError::BadArg(arg) => write!(f, "argument {:?} is malformed", arg),
+            BridgeParseError::DuplicateArg(name) => write!(f, "{}= is given twice", name),
+            BridgeParseError::UnexpectedArg(arg) => {
+                write!(f, "bridges without a transport take no argument {:?}", arg)
+            }
+        }
+    }
+}
+
+/// A rejected line among several, numbered from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeLineError {
+    /// Line (or `--bridge` flag) number, from 1
+    pub line: usize,
+    /// What is wrong with it
+    pub error: BridgeParseError,
+}
+
+impl fmt::Display for BridgeLineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bridge line {}: {}", self.line, self.error)
+    }
+}
+
+/// A validated bridge line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeLine {
+    /// Transport, or `Vanilla`
+    pub transport: BridgeTransport,
+    /// Bridge address
+    pub address: SocketAddr,
+    /// Relay fingerprint, uppercase hex
+    pub fingerprint: Option<String>,
+    /// Transport arguments, in the order given
+    pub args: Vec<(String, String)>,
+}
+
+impl BridgeLine {
+    /// Value of a transport argument.
+    pub fn arg(&self, key: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v.as_str()))
+    }
+
+    /// The line as a torrc directive, without the newline.
+    pub fn torrc_line(&self) -> String {
+        let mut line = "Bridge".to_string();
+        if let Some(name) = self.transport.name() {
+            line.push(' ');
+            line.push_str(name);
+        }
+        line.push_str(&format!(" {}", self.address));
+        if let Some(fingerprint) = &self.fingerprint {
+            line.push_str(&format!(" {}", fingerprint));
+        }
+        for (key, value) in &self.args {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        line
+    }
+}
+
+impl FromStr for BridgeLine {
+    type Err = BridgeParseError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_ascii_whitespace().peekable();
+        if words
+            .peek()
+            .is_some_and(|word| word.eq_ignore_ascii_case("Bridge"))
+        {
+            words.next();
+        }
+
+        let first = words.next().ok_or(BridgeParseError::Empty)?;
+        let (transport, address) = match first.parse::<SocketAddr>() {
+            Ok(address) => (BridgeTransport::Vanilla, address),
+            Err(_) => {
+                let transport = BridgeTransport::from_name(first)
+                    .ok_or_else(|| BridgeParseError::UnknownTransport(first.to_string()))?;
+                let address = words.next().unwrap_or_default();
+                let address = address
+                    .parse()
+                    .map_err(|_| BridgeParseError::BadAddress(address.to_string()))?;
+                (transport, address)
+            }
+        };
+        if address.port() == 0 {
+            return Err(BridgeParseError::BadAddress(address.to_string()));
+        }
+
+        let fingerprint = match words.peek() {
+            Some(word) if !word.contains('=') => {
+                let word = words.next().unwrap_or_default();
+                if word.len() != FINGERPRINT_HEX_LEN || !word.bytes().all(|b| b.is_ascii_hexdigit())
+                {
+                    return Err(BridgeParseError::BadFingerprint(word.to_string()));
+                }
+                Some(word.to_ascii_uppercase())
+            }
+            _ => None,
+        };
+        if transport == BridgeTransport::Obfs4 && fingerprint.is_none() {
+            return Err(BridgeParseError::MissingFingerprint);
+        }
+
+        let mut args: Vec<(String, String)> = Vec::new();
+        for word in words {
+            if transport == BridgeTransport::Vanilla {
+                return Err(BridgeParseError::UnexpectedArg(word.to_string()));
+            }
+            let (key, value) = word
+                .split_once('=')
+                .filter(|(key, value)| {
+                    !key.is_empty()
+                        && !value.is_empty()
+                        && key.bytes().chain(value.bytes()).all(safe_byte)
+                })
+                .ok_or_else(|| BridgeParseError::BadArg(word.to_string()))?;
+            if args.iter().any(|(k, _)| k == key) {
+                return Err(BridgeParseError::DuplicateArg(key.to_string()));
+            }
+            args.push((key.to_string(), value.to_string()));
+        }
+
+        let line = BridgeLine {
+            transport,
+            address,
+            fingerprint,
+            args,
+        };
+        if let Some(missing) = transport
+            .required_args()
+            .iter()
+            .find(|name| line.arg(name).is_none())
+        {
+            return Err(BridgeParseError::MissingArg(missing));
+        }
+        if transport == BridgeTransport::Obfs4
+            && !matches!(line.arg("iat-mode"), Some("0" | "1" | "2"))
+        {
+            return Err(BridgeParseError::BadArg(format!(
+                "iat-mode={}",
+                line.arg("iat-mode").unwrap_or_default()
+            )));
+        }
+        Ok(line)
+    }
+}
+
+impl fmt::Display for BridgeLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = self.torrc_line();
+        f.write_str(line.strip_prefix("Bridge ").unwrap_or(&line))
+    }
+}
+
+/// Parse one bridge per entry, skipping blank lines and '#' comments.
+///
+/// The first bad line is reported with its 1-based position.
+pub fn parse_bridge_lines<I, S>(lines: I) -> Result<Vec<BridgeLine>, BridgeLineError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let mut bridges = Vec::new();
+    for (index, line) in lines.into_iter().enumerate() {
+        let line = line.as_ref().trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bridge = line.parse().map_err(|error| BridgeLineError {
+            line: index + 1,
+            error,
+        })?;
+        bridges.push(bridge);
+    }
+    Ok(bridges)
+}
+
+/// Printable ASCII that cannot quote, escape or comment in a torrc.
+fn safe_byte(b: u8) -> bool {
+    b.is_ascii_graphic() && !matches!(b, b'"' | b'\\' | b'#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBFS4: &str = "obfs4 192.95.36.142:443 CDF2E852BF539B82BD10E27E9115A31734E378C2 cert=qUVQ0srL1JI/vO6V6m/24anYXiJD3QP2HgzUKQtQ7GRqqUvs7P+tG43RtAqdhLOALP7DJQ iat-mode=1";
+    const SNOWFLAKE: &str = "snowflake 192.0.2.3:80 2B280B23E1107BB62ABFC40DDCC8824814F80A72 fingerprint=2B280B23E1107BB62ABFC40DDCC8824814F80A72 url=https://1098762253.rsc.cdn77.org/ fronts=www.cdn77.com,www.phpmyadmin.net ice=stun:stun.antisip.com:3478,stun:stun.epygi.com:3478 utls-imitate=hellorandomizedalpn";
+    const MEEK: &str = "meek_lite 192.0.2.18:80 BE776A53492E1E044A26F17306E1BC46A55A1625 url=https://meek.azureedge.net/ front=ajax.aspnetcdn.com";
+
+    #[test]
+    fn test_real_bridge_lines() {
+        let obfs4: BridgeLine = OBFS4.parse().expect("obfs4");
+        assert_eq!(obfs4.transport, BridgeTransport::Obfs4);
+        assert_eq!(obfs4.address, "192.95.36.142:443".parse().expect("addr"));
+        assert_eq!(
+            obfs4.fingerprint.as_deref(),
+            Some("CDF2E852BF539B82BD10E27E9115A31734E378C2")
+        );
+        assert_eq!(obfs4.arg("iat-mode"), Some("1"));
+        assert_eq!(obfs4.torrc_line(), format!("Bridge {}", OBFS4));
+
+        for (line, transport) in [
+            (SNOWFLAKE, BridgeTransport::Snowflake),
+            (MEEK, BridgeTransport::MeekLite),
+        ] {
+            let bridge: BridgeLine = line.parse().expect("bridge");
+            assert_eq!(bridge.transport, transport);
+            assert_eq!(bridge.to_string(), line);
+        }
+
+        // Pasted straight from a torrc, lowercase fingerprint, IPv6
+        let vanilla: BridgeLine =
+            "Bridge [2001:db8::1]:9001 cdf2e852bf539b82bd10e27e9115a31734e378c2"
+                .parse()
+                .expect("vanilla");
+        assert_eq!(vanilla.transport, BridgeTransport::Vanilla);
+        assert_eq!(
+            vanilla.torrc_line(),
+            "Bridge [2001:db8::1]:9001 CDF2E852BF539B82BD10E27E9115A31734E378C2"
+        );
+    }
+
+    #[test]
+    fn test_rejected_bridge_lines() {
+        let without_cert = OBFS4.replace(
+            " cert=qUVQ0srL1JI/vO6V6m/24anYXiJD3QP2HgzUKQtQ7GRqqUvs7P+tG43RtAqdhLOALP7DJQ",
+            "",
+        );
+        for (line, error) in [
+            (without_cert.as_str(), BridgeParseError::MissingArg("cert")),
+            (
+                "obfs4 192.95.36.142:443 cert=abc iat-mode=0",
+                BridgeParseError::MissingFingerprint,
+            ),
+            (
+                &OBFS4.replace("iat-mode=1", "iat-mode=7") as &str,
+                BridgeParseError::BadArg("iat-mode=7".to_string()),
+            ),
+            (
+                "obfs4 bridge.example:443",
+                BridgeParseError::BadAddress("bridge.example:443".to_string()),
+            ),
+            (
+                "192.0.2.1:0",
+                BridgeParseError::BadAddress("192.0.2.1:0".to_string()),
+            ),
+            (
+                "192.0.2.1:443 CDF2E852",
+                BridgeParseError::BadFingerprint("CDF2E852".to_string()),
+            ),
+            (
+                "192.0.2.1:443 cert=x",
+                BridgeParseError::UnexpectedArg("cert=x".to_string()),
+            ),
+            (
+                &format!("{} cert=again", OBFS4) as &str,
+                BridgeParseError::DuplicateArg("cert".to_string()),
+            ),
+            (
+                &format!("{} note=\"x\"", MEEK) as &str,
+                BridgeParseError::BadArg("note=\"x\"".to_string()),
+            ),
+            (
+                "lol what is a bridge",
+                BridgeParseError::UnknownTransport("lol".to_string()),
+            ),
+            ("Bridge", BridgeParseError::Empty),
+        ] {
+            assert_eq!(line.parse::<BridgeLine>(), Err(error), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_bridge_lines_reports_line() {
+        let text = format!(
+            "# from bridges.torproject.org\n{}\n\n{}\ngarbage",
+            OBFS4, MEEK
+        );
+        let error = parse_bridge_lines(text.lines()).expect_err("garbage");
+        assert_eq!(error.line, 5);
+        assert_eq!(
+            error.to_string(),
+            "Bridge line 5: unknown transport \"garbage\""
+        );
+
+        let bridges = parse_bridge_lines([OBFS4, "", SNOWFLAKE]).expect("valid");
+        assert_eq!(bridges.len(), 2);
+    }
+}
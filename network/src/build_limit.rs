@@ -0,0 +1,176 @@
+//! Limit on circuits being built at once.
+//!
+//! A page with forty subresources asks for forty circuits at the same
+//! moment. Sent to Tor all together, the builds crowd the control port
+//! and every request stalls behind the slowest of them. Builds therefore
+//! take a permit from a fair (first come, first served) semaphore, and
+//! those over the limit queue for one.
+//!
+//! A request gives up on the queue after its wait timeout. Cancelling a
+//! request while it queues leaves the queue at once: the permit is only
+//! taken, and the waiter only counted, while the future is polled.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::NetworkError;
+
+/// Circuits built at once by default.
+pub const MAX_CONCURRENT_CIRCUIT_BUILDS: usize = 8;
+
+/// Longest a build waits for a permit by default.
+pub const BUILD_QUEUE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Queue of circuit builds, admitting a few at a time.
+#[derive(Debug)]
+pub struct BuildLimiter {
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    timeout: Duration,
+    queued: Arc<AtomicUsize>,
+    peak_queued: AtomicUsize,
+}
+
+impl BuildLimiter {
+    /// Create a limiter admitting `max_concurrent` builds (at least one),
+    /// whose waiters give up after `timeout`.
+    pub fn new(max_concurrent: usize, timeout: Duration) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            timeout,
+            queued: Arc::new(AtomicUsize::new(0)),
+            peak_queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Run `build` once a permit is free.
+    ///
+    /// Fails with `CircuitCreationFailed` if no permit is free within the
+    /// wait timeout; the build is then never started.
+    pub async fn run<T>(
+        &self,
+        build: impl Future<Output = Result<T, NetworkError>>,
+    ) -> Result<T, NetworkError> {
+        let _permit = self.permit().await?;
+        build.await
+    }
+
+    /// Wait for a build permit.
+    async fn permit(&self) -> Result<OwnedSemaphorePermit, NetworkError> {
+        if let Ok(permit) = Arc::clone(&self.permits).try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        let _waiting = Waiting::enter(&self.queued, &self.peak_queued);
+        let acquire = Arc::clone(&self.permits).acquire_owned();
+        match tokio::time::timeout(self.timeout, acquire).await {
+            Ok(Ok(permit)) => Ok(permit),
+            Ok(Err(_)) => Err(NetworkError::CircuitCreationFailed(
+                "circuit build queue closed".to_string(),
+            )),
+            Err(_) => Err(NetworkError::CircuitCreationFailed(
+                "timed out waiting to build a circuit".to_string(),
+            )),
+        }
+    }
+
+    /// Builds allowed at once.
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    /// Builds running now.
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.permits.available_permits()
+    }
+
+    /// Builds waiting for a permit now.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Most builds ever waiting at once.
+    pub fn peak_queue_depth(&self) -> usize {
+        self.peak_queued.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for BuildLimiter {
+    fn default() -> Self {
+        Self::new(MAX_CONCURRENT_CIRCUIT_BUILDS, BUILD_QUEUE_TIMEOUT)
+    }
+}
+
+/// Counts one waiter in the queue for as long as it lives.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn enter(queued: &'a AtomicUsize, peak: &AtomicUsize) -> Self {
+        let depth = queued.fetch_add(1, Ordering::Relaxed) + 1;
+        peak.fetch_max(depth, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiters_time_out_and_leave_the_queue() {
+        let limiter = Arc::new(BuildLimiter::new(1, Duration::from_secs(5)));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let holder = tokio::spawn({
+            let limiter = Arc::clone(&limiter);
+            async move {
+                limiter
+                    .run(async {
+                        let _ = released.await;
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(limiter.in_flight(), 1);
+
+        // Times out without ever starting its build
+        let started = std::sync::atomic::AtomicBool::new(false);
+        let result = limiter
+            .run(async {
+                started.store(true, Ordering::Relaxed);
+                Ok(())
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(NetworkError::CircuitCreationFailed(_))
+        ));
+        assert!(!started.load(Ordering::Relaxed));
+        assert_eq!(limiter.queue_depth(), 0);
+
+        // A cancelled waiter leaves the queue too
+        let waiter = limiter.run(async { Ok(()) });
+        let cancelled = tokio::time::timeout(Duration::from_secs(1), waiter).await;
+        assert!(cancelled.is_err());
+        assert_eq!(limiter.queue_depth(), 0);
+        assert_eq!(limiter.peak_queue_depth(), 1);
+
+        release.send(()).expect("holder waiting");
+        holder.await.expect("holder").expect("built");
+        assert_eq!(limiter.in_flight(), 0);
+        limiter.run(async { Ok(()) }).await.expect("free again");
+    }
+}
@@ -0,0 +1,142 @@
+//! HTTP authentication challenges: never prompt, never send credentials.
+//!
+//! Credentials identify the user across circuits, and a credential
+//! prompt on an arbitrary page is a phishing vector. So:
+//!
+//! - 407 Proxy Authentication Required is a hard error. The only proxy
+//!   forloop uses is Tor, which never asks; a 407 means something is
+//!   intercepting traffic, and the UI shows a security warning page.
+//! - 401 Unauthorized is passed through so the server's error body
+//!   renders, with the challenge headers removed so nothing downstream
+//!   can turn it into a prompt.
+//! - Authorization and Proxy-Authorization stay on `DANGEROUS_HEADERS`:
+//!   `validate_request` refuses pages that set them, and the request
+//!   builder drops them if anything else tries.
+
+use crate::NetworkError;
+
+/// Response headers that ask for credentials (lowercase).
+pub const CHALLENGE_HEADERS: &[&str] = &["www-authenticate", "proxy-authenticate"];
+
+/// Request headers that carry credentials (lowercase).
+pub const CREDENTIAL_HEADERS: &[&str] = &["authorization", "proxy-authorization"];
+
+/// Refuse responses that must not reach the renderer.
+///
+/// A 407 fails with `NetworkError::ProxyAuthRequired`; a 401 is allowed.
+pub fn check_challenge(status: u16) -> Result<(), NetworkError> {
+    match status {
+        407 => {
+            log::warn!("407 from upstream; treating traffic as intercepted");
+            Err(NetworkError::ProxyAuthRequired)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remove credential challenges from response headers.
+pub fn strip_challenge_headers(headers: &mut Vec<(String, String)>) {
+    headers.retain(|(name, _)| !CHALLENGE_HEADERS.contains(&name.to_ascii_lowercase().as_str()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{build_http_head, parse_url, BodyFraming};
+    use crate::frames::FrameId;
+    use crate::headers::{Destination, HeaderSynthesizer, Platform, DANGEROUS_HEADERS};
+    use crate::policy::{validate_request, NetworkRequestMsg, PolicyViolation};
+
+    fn carries_credentials(bytes: &[u8]) -> bool {
+        String::from_utf8_lossy(bytes).lines().any(|line| {
+            let name = line.split(':').next().unwrap_or_default();
+            CREDENTIAL_HEADERS.contains(&name.trim().to_ascii_lowercase().as_str())
+        })
+    }
+
+    #[test]
+    fn test_407_is_a_hard_error() {
+        assert!(matches!(
+            check_challenge(407),
+            Err(NetworkError::ProxyAuthRequired)
+        ));
+    }
+
+    #[test]
+    fn test_401_passes_through_without_challenge() {
+        assert!(check_challenge(401).is_ok());
+
+        let mut headers = vec![
+            ("Content-Type".to_string(), "text/html".to_string()),
+            (
+                "WWW-Authenticate".to_string(),
+                "Basic realm=\"x\"".to_string(),
+            ),
+            ("Proxy-Authenticate".to_string(), "Basic".to_string()),
+        ];
+        strip_challenge_headers(&mut headers);
+        assert_eq!(
+            headers,
+            vec![("Content-Type".to_string(), "text/html".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_pages_cannot_attach_credentials() {
+        for name in ["Authorization", "authorization", "PROXY-AUTHORIZATION"] {
+            let result = validate_request(
+                NetworkRequestMsg {
+                    method: "GET".to_string(),
+                    url: "https://example.com/".to_string(),
+                    headers: vec![(name.to_string(), "Basic dXNlcjpwYXNz".to_string())],
+                    body: None,
+                    frame_id: FrameId::TOP,
+                    destination: Destination::Xhr,
+                },
+                1024,
+            );
+            assert!(
+                matches!(result, Err(PolicyViolation::HeaderNotAllowed(_))),
+                "{}",
+                name
+            );
+        }
+        for name in CREDENTIAL_HEADERS {
+            assert!(DANGEROUS_HEADERS.contains(name));
+        }
+    }
+
+    #[test]
+    fn test_no_path_emits_authorization() {
+        // Synthetic headers never include credentials
+        for platform in Platform::ALL {
+            for destination in Destination::ALL {
+                let headers =
+                    HeaderSynthesizer::generate_for_platform(platform, destination).to_vec();
+                let head = build_http_head(
+                    "GET",
+                    &parse_url("https://example.com/").expect("valid URL"),
+                    &headers,
+                    BodyFraming::Empty,
+                )
+                .expect("valid head");
+                assert!(!carries_credentials(&head));
+            }
+        }
+
+        // The request builder drops them even if handed some directly
+        let head = build_http_head(
+            "POST",
+            &parse_url("https://example.com/login").expect("valid URL"),
+            &[
+                ("Authorization".to_string(), "Bearer token".to_string()),
+                ("proxy-authorization".to_string(), "Basic x".to_string()),
+                ("Accept".to_string(), "*/*".to_string()),
+            ],
+            BodyFraming::Length(0),
+        )
+        .expect("valid head");
+        assert!(!carries_credentials(&head));
+        assert!(String::from_utf8_lossy(&head).contains("Accept: */*\r\n"));
+    }
+}
@@ -0,0 +1,361 @@
+//! Circuit churn guard for hostile pages.
+//!
+//! Every request gets a fresh circuit, so a page firing hundreds of failing
+//! subresource requests at random onions makes Tor build hundreds of
+//! circuits and fetch hundreds of descriptors. Requests a page makes are
+//! therefore accounted per navigation context
+//! (`NavigationTarget::context_id`):
+//!
+//! - circuit creations draw from a token bucket that refills at
+//!   `circuits_per_minute`, up to `circuit_burst`; a request on a circuit
+//!   the page already shares draws nothing
+//! - at most `max_onion_hosts` distinct onion services may be contacted,
+//!   counted on every request
+//!
+//! Going over a limit is a strike, and strikes escalate: the first
+//! `delay_strikes` are delayed until a token is available, the following
+//! ones are refused, and at `abusive_strikes` the page is marked abusive.
+//! An abusive page gets every further request refused and the user is
+//! told once. A circuit admitted within the limits clears the strikes.
+//! A context starts over when it navigates; New Loop resets every context.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use forloop_config::{system_clock, Clock};
+
+use crate::events::ConnectionSecurity;
+use crate::frames::origin_of;
+
+/// Per-page limits on circuit churn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChurnLimits {
+    /// Circuit creations a page may make at once
+    pub circuit_burst: u32,
+    /// Sustained circuit creations per minute
+    pub circuits_per_minute: u32,
+    /// Distinct onion services a page may contact
+    pub max_onion_hosts: usize,
+    /// Strikes answered with a delay
+    pub delay_strikes: u32,
+    /// Strike at which the page is marked abusive
+    pub abusive_strikes: u32,
+}
+
+impl Default for ChurnLimits {
+    fn default() -> Self {
+        Self {
+            // A heavy page loads well over a hundred subresources
+            circuit_burst: 150,
+            circuits_per_minute: 60,
+            max_onion_hosts: 8,
+            delay_strikes: 10,
+            abusive_strikes: 30,
+        }
+    }
+}
+
+/// What to do with a page's request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChurnVerdict {
+    /// Within the limits
+    Admit,
+    /// Over the limit; send after this delay
+    Delay(Duration),
+    /// Over the limit; refuse
+    Refuse,
+    /// The page just became abusive; refuse and tell the user
+    Abusive,
+}
+
+/// Accounting for one page.
+#[derive(Debug)]
+struct PageChurn {
+    tokens: f64,
+    refilled_at: Instant,
+    onion_hosts: HashSet<String>,
+    strikes: u32,
+    abusive: bool,
+}
+
+/// Per-context circuit churn accounting.
+#[derive(Debug)]
+pub struct ChurnGuard {
+    limits: ChurnLimits,
+    pages: Mutex<HashMap<u64, PageChurn>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ChurnGuard {
+    /// Create a guard enforcing `limits`.
+    pub fn new(limits: ChurnLimits) -> Self {
+        Self {
+            limits,
+            pages: Mutex::new(HashMap::new()),
+            clock: system_clock(),
+        }
+    }
+
+    /// Measure time on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Clock delays should be waited out on.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Account a request to `url` made by the page in `context_id` that
+    /// builds a circuit of its own.
+    pub fn check(&self, context_id: u64, url: &str) -> ChurnVerdict {
+        match self.check_onion(context_id, url) {
+            ChurnVerdict::Admit => self.charge_circuit(context_id),
+            verdict => verdict,
+        }
+    }
+
+    /// Account a request to `url` made by the page in `context_id`
+    /// against the onion service cap only.
+    ///
+    /// For requests on a shared circuit, which call `charge_circuit`
+    /// only when that circuit has to be built.
+    pub fn check_onion(&self, context_id: u64, url: &str) -> ChurnVerdict {
+        let mut pages = self.pages.lock().expect("churn lock");
+        let page = self.page(&mut pages, context_id);
+        if page.abusive {
+            return ChurnVerdict::Refuse;
+        }
+
+        // Distinct onion services are capped outright; waiting does not help
+        if ConnectionSecurity::of_url(url) == ConnectionSecurity::Onion {
+            let host = origin_of(url).unwrap_or_else(|| url.to_string());
+            if !page.onion_hosts.contains(&host) {
+                if page.onion_hosts.len() >= self.limits.max_onion_hosts {
+                    return self.strike(page, false);
+                }
+                page.onion_hosts.insert(host);
+            }
+        }
+        ChurnVerdict::Admit
+    }
+
+    /// Account a circuit built for the page in `context_id`.
+    pub fn charge_circuit(&self, context_id: u64) -> ChurnVerdict {
+        let now = self.clock.now();
+        let mut pages = self.pages.lock().expect("churn lock");
+        let page = self.page(&mut pages, context_id);
+        if page.abusive {
+            return ChurnVerdict::Refuse;
+        }
+
+        let rate = f64::from(self.limits.circuits_per_minute) / 60.0;
+        let elapsed = now
+            .saturating_duration_since(page.refilled_at)
+            .as_secs_f64();
+        page.tokens = (page.tokens + elapsed * rate).min(f64::from(self.limits.circuit_burst));
+        page.refilled_at = now;
+
+        if page.tokens >= 1.0 {
+            page.tokens -= 1.0;
+            page.strikes = 0;
+            return ChurnVerdict::Admit;
+        }
+
+        self.strike(page, rate > 0.0)
+    }
+
+    /// Get `context_id`'s accounting, starting it with a full bucket.
+    fn page<'a>(
+        &self,
+        pages: &'a mut HashMap<u64, PageChurn>,
+        context_id: u64,
+    ) -> &'a mut PageChurn {
+        pages.entry(context_id).or_insert_with(|| PageChurn {
+            tokens: f64::from(self.limits.circuit_burst),
+            refilled_at: self.clock.now(),
+            onion_hosts: HashSet::new(),
+            strikes: 0,
+            abusive: false,
+        })
+    }
+
+    /// Escalate after a request went over a limit.
+    fn strike(&self, page: &mut PageChurn, can_wait: bool) -> ChurnVerdict {
+        page.strikes += 1;
+
+        if page.strikes >= self.limits.abusive_strikes {
+            page.abusive = true;
+            log::warn!("Page marked abusive after {} strikes", page.strikes);
+            return ChurnVerdict::Abusive;
+        }
+        if can_wait && page.strikes <= self.limits.delay_strikes {
+            // Borrow the next token and wait until it has been earned
+            let rate = f64::from(self.limits.circuits_per_minute) / 60.0;
+            page.tokens -= 1.0;
+            return ChurnVerdict::Delay(Duration::from_secs_f64(-page.tokens / rate));
+        }
+        ChurnVerdict::Refuse
+    }
+
+    /// Whether the page in `context_id` has been marked abusive.
+    pub fn is_abusive(&self, context_id: u64) -> bool {
+        self.pages
+            .lock()
+            .expect("churn lock")
+            .get(&context_id)
+            .is_some_and(|page| page.abusive)
+    }
+
+    /// Forget `context_id`'s accounting; called when it navigates.
+    pub fn reset_context(&self, context_id: u64) {
+        self.pages.lock().expect("churn lock").remove(&context_id);
+    }
+
+    /// Forget every context's accounting; called on New Loop.
+    pub fn reset(&self) {
+        self.pages.lock().expect("churn lock").clear();
+    }
+}
+
+impl Default for ChurnGuard {
+    fn default() -> Self {
+        Self::new(ChurnLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_config::clock::ManualClock;
+
+    const PAGE: u64 = 1;
+    const IMAGE: &str = "https://hostile.example/pixel.gif";
+
+    fn guard(clock: &ManualClock) -> ChurnGuard {
+        ChurnGuard::new(ChurnLimits {
+            circuit_burst: 3,
+            circuits_per_minute: 60,
+            max_onion_hosts: 2,
+            delay_strikes: 2,
+            abusive_strikes: 4,
+        })
+        .with_clock(Arc::new(clock.clone()))
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        let clock = ManualClock::new();
+        let guard = guard(&clock);
+        for _ in 0..3 {
+            assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+        }
+        assert_eq!(
+            guard.check(PAGE, IMAGE),
+            ChurnVerdict::Delay(Duration::from_secs(1))
+        );
+
+        // The borrowed token is repaid first, then the bucket refills
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+        assert_ne!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+
+        // Never more than the burst, however long the page idles
+        clock.advance(Duration::from_secs(600));
+        for _ in 0..3 {
+            assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+        }
+        assert_ne!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+    }
+
+    #[test]
+    fn test_escalation_ladder() {
+        let clock = ManualClock::new();
+        let guard = guard(&clock);
+        for _ in 0..3 {
+            guard.check(PAGE, IMAGE);
+        }
+
+        assert_eq!(
+            guard.check(PAGE, IMAGE),
+            ChurnVerdict::Delay(Duration::from_secs(1))
+        );
+        assert_eq!(
+            guard.check(PAGE, IMAGE),
+            ChurnVerdict::Delay(Duration::from_secs(2))
+        );
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Refuse);
+        assert!(!guard.is_abusive(PAGE));
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Abusive);
+        assert!(guard.is_abusive(PAGE));
+
+        // Abusive pages stay refused even once tokens are back
+        clock.advance(Duration::from_secs(600));
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Refuse);
+
+        // Other contexts are unaffected, and navigating starts over
+        assert_eq!(guard.check(PAGE + 1, IMAGE), ChurnVerdict::Admit);
+        guard.reset_context(PAGE);
+        assert!(!guard.is_abusive(PAGE));
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Admit);
+    }
+
+    #[test]
+    fn test_navigation_resets_only_its_context() {
+        let clock = ManualClock::new();
+        let guard = guard(&clock);
+        for _ in 0..7 {
+            guard.check(PAGE, IMAGE);
+        }
+        for _ in 0..3 {
+            guard.check(PAGE + 1, IMAGE);
+        }
+
+        guard.reset_context(PAGE + 1);
+        assert!(guard.is_abusive(PAGE));
+        assert_eq!(guard.check(PAGE, IMAGE), ChurnVerdict::Refuse);
+        assert_eq!(guard.check(PAGE + 1, IMAGE), ChurnVerdict::Admit);
+
+        guard.reset();
+        assert!(!guard.is_abusive(PAGE));
+    }
+
+    #[test]
+    fn test_distinct_onion_hosts_capped() {
+        let clock = ManualClock::new();
+        let guard = ChurnGuard::new(ChurnLimits {
+            circuit_burst: 100,
+            max_onion_hosts: 2,
+            abusive_strikes: 3,
+            ..ChurnLimits::default()
+        })
+        .with_clock(Arc::new(clock.clone()));
+
+        let onion = |n: u32| format!("https://random{}.onion/x.js", n);
+        assert_eq!(guard.check(PAGE, &onion(1)), ChurnVerdict::Admit);
+        assert_eq!(guard.check(PAGE, &onion(2)), ChurnVerdict::Admit);
+        // Repeat visits to a known service are not new descriptor fetches
+        assert_eq!(guard.check(PAGE, &onion(1)), ChurnVerdict::Admit);
+
+        assert_eq!(guard.check(PAGE, &onion(3)), ChurnVerdict::Refuse);
+        // Counted on a shared circuit too
+        assert_eq!(guard.check_onion(PAGE, &onion(4)), ChurnVerdict::Refuse);
+        assert_eq!(guard.check(PAGE, &onion(5)), ChurnVerdict::Abusive);
+    }
+
+    #[test]
+    fn test_only_circuits_draw_from_the_bucket() {
+        let clock = ManualClock::new();
+        let guard = guard(&clock);
+        assert_eq!(guard.charge_circuit(PAGE), ChurnVerdict::Admit);
+        for _ in 0..10 {
+            assert_eq!(guard.check_onion(PAGE, IMAGE), ChurnVerdict::Admit);
+        }
+        assert_eq!(guard.charge_circuit(PAGE), ChurnVerdict::Admit);
+        assert_eq!(guard.charge_circuit(PAGE), ChurnVerdict::Admit);
+        assert_ne!(guard.charge_circuit(PAGE), ChurnVerdict::Admit);
+    }
+}
@@ -0,0 +1,233 @@
+//! Circuits built ahead of the requests that use them.
+//!
+//! Building a circuit takes seconds, and every request needs a new one.
+//! With a `CircuitPool`, `CircuitManager` keeps up to `PoolPolicy::size`
+//! circuits built and unused in the background:
+//!
+//! - a pooled circuit is handed out to exactly one request and leaves the
+//!   pool; a replacement is built right away
+//! - a circuit idle in the pool past `PoolPolicy::max_idle` is closed
+//!   rather than handed out, so one built during earlier browsing is not
+//!   used for much later browsing
+//! - a built circuit that is already pooled or in use is not pooled again
+//!
+//! Circuits reusing onion descriptors are never pooled; they are built
+//! for the request that asks.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use forloop_config::{system_clock, Clock};
+use tokio::sync::Notify;
+
+/// Pool size and circuit lifetime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolPolicy {
+    /// Circuits kept built and unused
+    pub size: usize,
+    /// Close a pooled circuit unused for this long
+    pub max_idle: Duration,
+}
+
+impl Default for PoolPolicy {
+    fn default() -> Self {
+        Self {
+            size: 3,
+            max_idle: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Circuits built and waiting for a request.
+#[derive(Debug)]
+pub struct CircuitPool {
+    policy: PoolPolicy,
+    ready: Mutex<VecDeque<(String, Instant)>>,
+    demand: Notify,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitPool {
+    /// Create an empty pool.
+    pub fn new(policy: PoolPolicy) -> Self {
+        Self {
+            policy,
+            ready: Mutex::new(VecDeque::new()),
+            demand: Notify::new(),
+            clock: system_clock(),
+        }
+    }
+
+    /// Age circuits on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the pool's policy.
+    pub fn policy(&self) -> PoolPolicy {
+        self.policy
+    }
+
+    /// Number of circuits waiting in the pool.
+    pub fn len(&self) -> usize {
+        self.ready.lock().expect("pool lock").len()
+    }
+
+    /// Whether no circuit is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take the oldest fresh circuit, and remove every stale one for the
+    /// caller to close. Wakes the filler either way.
+    pub(crate) fn take(&self) -> (Option<String>, Vec<String>) {
+        let stale = self.remove_stale();
+        let taken = self
+            .ready
+            .lock()
+            .expect("pool lock")
+            .pop_front()
+            .map(|(id, _)| id);
+        self.demand.notify_one();
+        (taken, stale)
+    }
+
+    /// Remove circuits idle past `max_idle`.
+    pub(crate) fn remove_stale(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let mut ready = self.ready.lock().expect("pool lock");
+        let mut stale = Vec::new();
+        ready.retain(|(id, built)| {
+            let fresh = now.saturating_duration_since(*built) < self.policy.max_idle;
+            if !fresh {
+                stale.push(id.clone());
+            }
+            fresh
+        });
+        stale
+    }
+
+    /// Circuits missing from a full pool.
+    pub(crate) fn missing(&self) -> usize {
+        self.policy.size.saturating_sub(self.len())
+    }
+
+    /// Pool a newly built circuit. Refused if it is already pooled.
+    pub(crate) fn offer(&self, circuit_id: String) -> bool {
+        let mut ready = self.ready.lock().expect("pool lock");
+        if ready.len() >= self.policy.size || ready.iter().any(|(id, _)| *id == circuit_id) {
+            return false;
+        }
+        ready.push_back((circuit_id, self.clock.now()));
+        true
+    }
+
+    /// Drop `circuit_id` from the pool, if it is there. For a circuit that
+    /// was handed out by another route.
+    pub(crate) fn forget(&self, circuit_id: &str) {
+        self.ready
+            .lock()
+            .expect("pool lock")
+            .retain(|(id, _)| id != circuit_id);
+    }
+
+    /// Remove every pooled circuit.
+    pub(crate) fn drain(&self) -> Vec<String> {
+        self.ready
+            .lock()
+            .expect("pool lock")
+            .drain(..)
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Wait until a circuit is taken, or long enough that the oldest may
+    /// have gone stale.
+    pub(crate) async fn wait_for_demand(&self) {
+        tokio::select! {
+            _ = self.demand.notified() => {}
+            _ = self.clock.sleep(self.policy.max_idle / 2) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::CircuitManager;
+    use crate::control_protocol::tests::{mock_control_port, tor_ready, CommandLog};
+    use crate::tasks::TaskRegistry;
+    use crate::tor_integration::TorController;
+    use forloop_config::clock::ManualClock;
+    use forloop_config::Port;
+    use std::collections::HashSet;
+
+    async fn manager(
+        pool: CircuitPool,
+        clock: Arc<dyn Clock>,
+    ) -> (Arc<CircuitManager>, CommandLog) {
+        let (stream, log) = mock_control_port(tor_ready());
+        let tor = TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+            .await
+            .expect("controller")
+            .with_clock(clock);
+        (
+            Arc::new(CircuitManager::new(Arc::new(tor)).with_pool(pool)),
+            log,
+        )
+    }
+
+    async fn until_full(pool: &CircuitPool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.missing() > 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("pool refilled");
+    }
+
+    #[tokio::test]
+    async fn test_pool_hands_out_unique_circuits_and_refills() {
+        let (manager, _) = manager(CircuitPool::new(PoolPolicy::default()), system_clock()).await;
+        let tasks = TaskRegistry::new();
+        manager.spawn_pool_filler(&tasks).expect("pool configured");
+        let pool = manager.pool().expect("pool");
+        until_full(pool).await;
+
+        let mut handed_out = HashSet::new();
+        for _ in 0..5 {
+            let circuit = manager.create_new_circuit().await.expect("circuit");
+            assert!(
+                handed_out.insert(circuit.id().to_string()),
+                "circuit {} handed out twice",
+                circuit.id()
+            );
+            until_full(pool).await;
+        }
+        assert_eq!(pool.len(), 3);
+        tasks.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_stale_pooled_circuits_closed_not_handed_out() {
+        let clock = ManualClock::new();
+        let pool = CircuitPool::new(PoolPolicy::default()).with_clock(Arc::new(clock.clone()));
+        let (manager, log) = manager(pool, Arc::new(clock.clone())).await;
+        manager.fill_pool().await;
+        assert_eq!(manager.pool().expect("pool").len(), 3);
+
+        // Only the first pooled circuit came from a NEWNYM; the others were
+        // built inside its rate-limit window
+        clock.advance(Duration::from_secs(61));
+        let circuit = manager.create_new_circuit().await.expect("circuit");
+        assert_eq!(circuit.id(), "2");
+        assert!(log
+            .lock()
+            .expect("log")
+            .contains(&"CLOSECIRCUIT 1".to_string()));
+        assert!(manager.pool().expect("pool").is_empty());
+    }
+}
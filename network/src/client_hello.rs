@@ -0,0 +1,495 @@
+//! ClientHello encoding and decoding.
+//!
+//! The ClientHello is written byte for byte from `TlsConfig`: cipher
+//! suites, extensions, supported groups, signature algorithms and ALPN
+//! appear exactly in the configured order, so the JA3/JA4 hash is the one
+//! `TlsFingerprintNormalizer` promises. `parse_client_hello` reads one back
+//! for verification and tests. Connections send rustls's ClientHello
+//! instead (see `tls_handshake`).
+
+use crate::tls_fingerprint::{HelloFields, TlsConfig};
+use crate::NetworkError;
+
+/// TLS record content type for handshake messages.
+pub(crate) const CONTENT_HANDSHAKE: u8 = 0x16;
+/// ClientHello.legacy_version, frozen at TLS 1.2 since TLS 1.3.
+pub(crate) const LEGACY_VERSION: u16 = 0x0303;
+/// Handshake message type of a ClientHello.
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+
+pub(crate) const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_STATUS_REQUEST: u16 = 0x0005;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+pub(crate) const EXT_ALPN: u16 = 0x0010;
+const EXT_EXTENDED_MASTER_SECRET: u16 = 0x0017;
+const EXT_COMPRESS_CERTIFICATE: u16 = 0x001b;
+const EXT_RECORD_SIZE_LIMIT: u16 = 0x001c;
+const EXT_DELEGATED_CREDENTIALS: u16 = 0x0022;
+const EXT_SESSION_TICKET: u16 = 0x0023;
+pub(crate) const EXT_PRE_SHARED_KEY: u16 = 0x0029;
+pub(crate) const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 0x002d;
+const EXT_KEY_SHARE: u16 = 0x0033;
+const EXT_RENEGOTIATION_INFO: u16 = 0xff01;
+
+/// x25519, the only group a key share is sent for.
+const GROUP_X25519: u16 = 0x001d;
+
+/// Record size limit we advertise (Firefox's value).
+const RECORD_SIZE_LIMIT: u16 = 0x4001;
+
+/// Certificate compression algorithms offered: zlib, brotli, zstd.
+const CERTIFICATE_COMPRESSION: [u16; 3] = [0x0001, 0x0002, 0x0003];
+
+/// Per-connection random values of a ClientHello.
+#[derive(Debug, Clone)]
+pub struct HelloRandom {
+    /// ClientHello.random
+    pub random: [u8; 32],
+    /// Legacy session ID (sent for middlebox compatibility)
+    pub session_id: [u8; 32],
+    /// x25519 key share
+    pub key_share: [u8; 32],
+}
+
+impl HelloRandom {
+    /// Draw fresh values.
+    pub fn generate() -> Self {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        Self {
+            random: rng.gen(),
+            session_id: rng.gen(),
+            key_share: rng.gen(),
+        }
+    }
+}
+
+/// What a ClientHello offers, in wire order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHelloSummary {
+    /// Version in the record header
+    pub record_version: u16,
+    /// legacy_version field
+    pub version: u16,
+    /// Client random
+    pub random: [u8; 32],
+    /// legacy_session_id
+    pub session_id: Vec<u8>,
+    /// Cipher suites
+    pub cipher_suites: Vec<u16>,
+    /// Compression methods
+    pub compression_methods: Vec<u8>,
+    /// Extension types
+    pub extensions: Vec<u16>,
+    /// supported_groups contents
+    pub supported_groups: Vec<u16>,
+    /// ec_point_formats contents
+    pub ec_point_formats: Vec<u8>,
+    /// signature_algorithms contents
+    pub signature_algorithms: Vec<u16>,
+    /// supported_versions contents
+    pub supported_versions: Vec<u16>,
+    /// ALPN protocols
+    pub alpn_protocols: Vec<String>,
+    /// SNI host name, if sent
+    pub server_name: Option<String>,
+}
+
+impl ClientHelloSummary {
+    /// Get the JA3 string of this ClientHello.
+    pub fn ja3(&self) -> String {
+        self.fields().ja3()
+    }
+
+    /// Get the JA3 hash of this ClientHello.
+    pub fn ja3_hash(&self) -> String {
+        self.fields().ja3_hash()
+    }
+
+    /// Get the JA4 fingerprint of this ClientHello.
+    pub fn ja4(&self) -> String {
+        self.fields().ja4()
+    }
+
+    fn fields(&self) -> HelloFields {
+        HelloFields {
+            version: self.version,
+            supported_versions: self.supported_versions.clone(),
+            cipher_suites: self.cipher_suites.clone(),
+            extensions: self.extensions.clone(),
+            supported_groups: self.supported_groups.clone(),
+            ec_point_formats: self.ec_point_formats.clone(),
+            signature_algorithms: self.signature_algorithms.clone(),
+            alpn: self.alpn_protocols.first().cloned(),
+        }
+    }
+}
+
+/// Get the SNI name to send for `host`.
+///
+/// Onion services are reached through Tor, not by name at the TLS layer,
+/// so `.onion` names are never put on the wire. IP literals are not
+/// allowed in SNI (RFC 6066).
+pub fn server_name_for(host: &str) -> Option<&str> {
+    let bare = host.trim_end_matches('.');
+    let is_ip = bare.parse::<std::net::IpAddr>().is_ok() || bare.starts_with('[');
+    let is_onion = bare.to_ascii_lowercase().ends_with(".onion");
+    (!bare.is_empty() && !is_ip && !is_onion).then_some(bare)
+}
+
+/// Encode a ClientHello record for `config`.
+///
+/// `server_name` is only sent when `config.extensions` lists SNI.
+/// pre_shared_key is never sent: no session is ever kept to resume.
+pub fn build_client_hello(
+    config: &TlsConfig,
+    server_name: Option<&str>,
+    random: &HelloRandom,
+) -> Result<Vec<u8>, NetworkError> {
+    let mut extensions = Vec::new();
+    for &extension in &config.extensions {
+        let Some(body) = extension_body(extension, config, server_name, random)? else {
+            continue;
+        };
+        put_u16(&mut extensions, extension);
+        put_u16(&mut extensions, body.len() as u16);
+        extensions.extend_from_slice(&body);
+    }
+
+    let mut hello = Vec::new();
+    put_u16(&mut hello, LEGACY_VERSION);
+    hello.extend_from_slice(&random.random);
+    hello.push(random.session_id.len() as u8);
+    hello.extend_from_slice(&random.session_id);
+    put_u16(&mut hello, (config.cipher_suites.len() * 2) as u16);
+    for &suite in &config.cipher_suites {
+        put_u16(&mut hello, suite);
+    }
+    // Compression: null only
+    hello.extend_from_slice(&[1, 0]);
+    put_u16(&mut hello, extensions.len() as u16);
+    hello.extend_from_slice(&extensions);
+
+    let mut handshake = vec![HANDSHAKE_CLIENT_HELLO];
+    put_u24(&mut handshake, hello.len());
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![CONTENT_HANDSHAKE, 0x03, 0x01];
+    put_u16(&mut record, handshake.len() as u16);
+    record.extend_from_slice(&handshake);
+    Ok(record)
+}
+
+/// Encode the body of one extension; `None` leaves it out.
+fn extension_body(
+    extension: u16,
+    config: &TlsConfig,
+    server_name: Option<&str>,
+    random: &HelloRandom,
+) -> Result<Option<Vec<u8>>, NetworkError> {
+    let mut body = Vec::new();
+    match extension {
+        EXT_SERVER_NAME => {
+            let Some(name) = server_name else {
+                return Ok(None);
+            };
+            put_u16(&mut body, (name.len() + 3) as u16);
+            body.push(0); // host_name
+            put_u16(&mut body, name.len() as u16);
+            body.extend_from_slice(name.as_bytes());
+        }
+        EXT_EXTENDED_MASTER_SECRET | EXT_SESSION_TICKET => {}
+        EXT_RENEGOTIATION_INFO => body.push(0),
+        EXT_SUPPORTED_GROUPS => put_u16_list(&mut body, &config.supported_groups),
+        EXT_EC_POINT_FORMATS => {
+            body.push(config.ec_point_formats.len() as u8);
+            body.extend_from_slice(&config.ec_point_formats);
+        }
+        EXT_ALPN => {
+            if config.alpn_protocols.is_empty() {
+                return Ok(None);
+            }
+            let mut list = Vec::new();
+            for protocol in &config.alpn_protocols {
+                list.push(protocol.len() as u8);
+                list.extend_from_slice(protocol.as_bytes());
+            }
+            put_u16(&mut body, list.len() as u16);
+            body.extend_from_slice(&list);
+        }
+        EXT_STATUS_REQUEST => body.extend_from_slice(&[1, 0, 0, 0, 0]),
+        EXT_DELEGATED_CREDENTIALS => {
+            // ECDSA schemes only, as Firefox sends
+            let ecdsa: Vec<u16> = config
+                .signature_algorithms
+                .iter()
+                .copied()
+                .filter(|scheme| scheme & 0xff == 0x03)
+                .collect();
+            put_u16_list(&mut body, &ecdsa);
+        }
+        EXT_KEY_SHARE => {
+            if !config.supported_groups.contains(&GROUP_X25519) {
+                return Err(NetworkError::RequestFailed(
+                    "key_share needs x25519 in supported_groups".to_string(),
+                ));
+            }
+            put_u16(&mut body, 36);
+            put_u16(&mut body, GROUP_X25519);
+            put_u16(&mut body, 32);
+            body.extend_from_slice(&random.key_share);
+        }
+        EXT_SUPPORTED_VERSIONS => {
+            let versions = config.offered_versions();
+            body.push((versions.len() * 2) as u8);
+            for version in versions {
+                put_u16(&mut body, version);
+            }
+        }
+        EXT_SIGNATURE_ALGORITHMS => put_u16_list(&mut body, &config.signature_algorithms),
+        EXT_RECORD_SIZE_LIMIT => put_u16(&mut body, RECORD_SIZE_LIMIT),
+        EXT_COMPRESS_CERTIFICATE => {
+            body.push((CERTIFICATE_COMPRESSION.len() * 2) as u8);
+            for algorithm in CERTIFICATE_COMPRESSION {
+                put_u16(&mut body, algorithm);
+            }
+        }
+        EXT_PSK_KEY_EXCHANGE_MODES => body.extend_from_slice(&[1, 1]),
+        EXT_PRE_SHARED_KEY => return Ok(None),
+        other => {
+            return Err(NetworkError::RequestFailed(format!(
+                "no encoding for TLS extension {:#06x}",
+                other
+            )))
+        }
+    }
+    Ok(Some(body))
+}
+
+/// Decode a ClientHello record.
+///
+/// The error names the part of the record that could not be read.
+pub fn parse_client_hello(record: &[u8]) -> Result<ClientHelloSummary, String> {
+    let malformed = |part: &str| format!("malformed {}", part);
+
+    let mut r = Reader(record);
+    let content_type = r.u8().ok_or_else(|| malformed("record header"))?;
+    if content_type != CONTENT_HANDSHAKE {
+        return Err(format!("record type {} is not handshake", content_type));
+    }
+    let record_version = r.u16().ok_or_else(|| malformed("record header"))?;
+    let mut r = Reader(r.vec_u16().ok_or_else(|| malformed("record length"))?);
+    let handshake_type = r.u8().ok_or_else(|| malformed("handshake header"))?;
+    if handshake_type != HANDSHAKE_CLIENT_HELLO {
+        return Err(format!(
+            "handshake type {} is not ClientHello",
+            handshake_type
+        ));
+    }
+    let mut hello = Reader(r.vec_u24().ok_or_else(|| malformed("handshake length"))?);
+    let version = hello.u16().ok_or_else(|| malformed("version"))?;
+    let random = hello
+        .take(32)
+        .and_then(|random| random.try_into().ok())
+        .ok_or_else(|| malformed("random"))?;
+    let session_id = hello.vec_u8().ok_or_else(|| malformed("session id"))?;
+    let cipher_suites = hello
+        .vec_u16()
+        .and_then(|suites| Reader(suites).u16_list())
+        .ok_or_else(|| malformed("cipher suites"))?;
+    let compression_methods = hello.vec_u8().ok_or_else(|| malformed("compression"))?;
+
+    let mut summary = ClientHelloSummary {
+        record_version,
+        version,
+        random,
+        session_id: session_id.to_vec(),
+        cipher_suites,
+        compression_methods: compression_methods.to_vec(),
+        extensions: Vec::new(),
+        supported_groups: Vec::new(),
+        ec_point_formats: Vec::new(),
+        signature_algorithms: Vec::new(),
+        supported_versions: Vec::new(),
+        alpn_protocols: Vec::new(),
+        server_name: None,
+    };
+    let mut extensions = Reader(hello.vec_u16().ok_or_else(|| malformed("extensions"))?);
+    if !hello.0.is_empty() {
+        return Err("trailing bytes after the extensions".to_string());
+    }
+    while !extensions.0.is_empty() {
+        let (extension, body) = extensions
+            .u16()
+            .zip(extensions.vec_u16())
+            .ok_or_else(|| malformed("extension header"))?;
+        summary.extensions.push(extension);
+        read_extension(&mut summary, extension, Reader(body))
+            .ok_or_else(|| format!("malformed extension {:#06x}", extension))?;
+    }
+    Ok(summary)
+}
+
+/// Record what `summary` needs from one extension's body.
+fn read_extension(
+    summary: &mut ClientHelloSummary,
+    extension: u16,
+    mut body: Reader<'_>,
+) -> Option<()> {
+    match extension {
+        EXT_SERVER_NAME => {
+            body.take(3)?;
+            let name = body.vec_u16()?;
+            summary.server_name = Some(String::from_utf8(name.to_vec()).ok()?);
+        }
+        EXT_SUPPORTED_GROUPS => summary.supported_groups = Reader(body.vec_u16()?).u16_list()?,
+        EXT_EC_POINT_FORMATS => summary.ec_point_formats = body.vec_u8()?.to_vec(),
+        EXT_SIGNATURE_ALGORITHMS => {
+            summary.signature_algorithms = Reader(body.vec_u16()?).u16_list()?
+        }
+        EXT_SUPPORTED_VERSIONS => summary.supported_versions = Reader(body.vec_u8()?).u16_list()?,
+        EXT_ALPN => {
+            let mut list = Reader(body.vec_u16()?);
+            while !list.0.is_empty() {
+                let protocol = list.vec_u8()?;
+                summary
+                    .alpn_protocols
+                    .push(String::from_utf8(protocol.to_vec()).ok()?);
+            }
+        }
+        _ => {}
+    }
+    Some(())
+}
+
+/// Cursor over a byte slice.
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|b| (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+    }
+
+    /// Take a vector with a one-byte length prefix.
+    pub(crate) fn vec_u8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    /// Take a vector with a two-byte length prefix.
+    pub(crate) fn vec_u16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    /// Take a vector with a three-byte length prefix.
+    pub(crate) fn vec_u24(&mut self) -> Option<&'a [u8]> {
+        let len = self.u24()?;
+        self.take(len)
+    }
+
+    fn u16_list(&mut self) -> Option<Vec<u16>> {
+        let mut list = Vec::new();
+        while !self.0.is_empty() {
+            list.push(self.u16()?);
+        }
+        Some(list)
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u24(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_be_bytes()[1..]);
+}
+
+fn put_u16_list(out: &mut Vec<u8>, values: &[u16]) {
+    put_u16(out, (values.len() * 2) as u16);
+    for &value in values {
+        put_u16(out, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls_fingerprint::TlsFingerprintNormalizer;
+
+    fn config() -> TlsConfig {
+        TlsFingerprintNormalizer::new()
+            .create_config()
+            .expect("tls config")
+    }
+
+    #[test]
+    fn test_hello_follows_config_order() {
+        let config = config();
+        let record = build_client_hello(&config, Some("example.com"), &HelloRandom::generate())
+            .expect("encodes");
+        let hello = parse_client_hello(&record).expect("parses");
+
+        assert_eq!(hello.cipher_suites, config.cipher_suites);
+        assert_eq!(hello.supported_groups, config.supported_groups);
+        assert_eq!(hello.signature_algorithms, config.signature_algorithms);
+        assert_eq!(hello.alpn_protocols, config.alpn_protocols);
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+
+        // Every configured extension but pre_shared_key, in order
+        let expected: Vec<u16> = config
+            .extensions
+            .iter()
+            .copied()
+            .filter(|&e| e != EXT_PRE_SHARED_KEY)
+            .collect();
+        assert_eq!(hello.extensions, expected);
+    }
+
+    #[test]
+    fn test_sni_rules() {
+        assert_eq!(server_name_for("example.com"), Some("example.com"));
+        assert_eq!(server_name_for("example.com."), Some("example.com"));
+        assert_eq!(
+            server_name_for("duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion"),
+            None
+        );
+        assert_eq!(server_name_for("www.Example.ONION"), None);
+        assert_eq!(server_name_for("192.0.2.1"), None);
+        assert_eq!(server_name_for("[2001:db8::1]"), None);
+
+        let record =
+            build_client_hello(&config(), None, &HelloRandom::generate()).expect("encodes");
+        let hello = parse_client_hello(&record).expect("parses");
+        assert_eq!(hello.server_name, None);
+        assert!(!hello.extensions.contains(&EXT_SERVER_NAME));
+    }
+
+    #[test]
+    fn test_unknown_extension_refused() {
+        let mut config = config();
+        config.extensions.push(0x4469);
+        assert!(build_client_hello(&config, None, &HelloRandom::generate()).is_err());
+    }
+}
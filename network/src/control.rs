@@ -0,0 +1,316 @@
+//! Control-port channel and its authentication.
+//!
+//! The auth cookie is read from disk on every (re)connect and never cached;
+//! it lives only for the AUTHENTICATE exchange. The read and write buffers
+//! carry the hex-encoded cookie at that point, so both are scrubbed right
+//! after authenticating, on reconnect and on close.
+
+use std::path::Path;
+
+use crate::secret::{wipe_bytes, SecretBytes};
+use crate::NetworkError;
+
+/// Name of Tor's cookie file in its data directory.
+pub const AUTH_COOKIE_FILE: &str = "control_auth_cookie";
+
+/// Length of Tor's control auth cookie.
+pub(crate) const AUTH_COOKIE_LEN: usize = 32;
+
+/// A buffer that can be scrubbed of its contents.
+pub trait ScrubBuffer: Default + Send {
+    /// Append bytes.
+    fn push_bytes(&mut self, bytes: &[u8]);
+
+    /// Get the buffered bytes.
+    fn bytes(&self) -> &[u8];
+
+    /// Zero and clear the buffer.
+    fn scrub(&mut self);
+}
+
+impl ScrubBuffer for Vec<u8> {
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+
+    fn scrub(&mut self) {
+        wipe_bytes(self);
+        self.clear();
+    }
+}
+
+/// Read the control auth cookie from Tor's data directory.
+pub fn read_auth_cookie(data_dir: &Path) -> Result<SecretBytes, NetworkError> {
+    read_cookie_file(&data_dir.join(AUTH_COOKIE_FILE))
+}
+
+/// Read the control auth cookie from `path`, as PROTOCOLINFO reports it.
+pub fn read_cookie_file(path: &Path) -> Result<SecretBytes, NetworkError> {
+    let cookie = std::fs::read(path)
+        .map(SecretBytes::new)
+        .map_err(|e| cookie_error(path, &e))?;
+
+    if cookie.len() != AUTH_COOKIE_LEN {
+        return Err(NetworkError::TorConnectionFailed(format!(
+            "Auth cookie has {} bytes, expected {}",
+            cookie.len(),
+            AUTH_COOKIE_LEN
+        )));
+    }
+
+    Ok(cookie)
+}
+
+fn cookie_error(path: &Path, error: &std::io::Error) -> NetworkError {
+    let reason = match error.kind() {
+        std::io::ErrorKind::NotFound => "not found; is Tor running?".to_string(),
+        std::io::ErrorKind::PermissionDenied => {
+            "permission denied; Tor and forloop must run as the same user".to_string()
+        }
+        _ => error.to_string(),
+    };
+    NetworkError::TorConnectionFailed(format!("Auth cookie {}: {}", path.display(), reason))
+}
+
+/// Buffered control-port channel.
+#[derive(Default)]
+pub struct ControlChannel<B: ScrubBuffer = Vec<u8>> {
+    read_buf: B,
+    write_buf: B,
+    authenticated: bool,
+}
+
+impl<B: ScrubBuffer> ControlChannel<B> {
+    /// Create a closed channel.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether AUTHENTICATE has succeeded on this connection.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Bytes currently held in the read and write buffers.
+    pub fn buffered_len(&self) -> usize {
+        self.read_buf.bytes().len() + self.write_buf.bytes().len()
+    }
+
+    /// Buffer bytes received from the control port.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.read_buf.push_bytes(bytes);
+    }
+
+    /// Buffer a command for the control port.
+    pub fn send(&mut self, command: &str) {
+        self.write_buf.push_bytes(command.as_bytes());
+    }
+
+    /// Authenticate with a cookie read fresh from disk.
+    ///
+    /// The command is assembled directly in the write buffer so the hex
+    /// cookie never exists as a separate allocation. Both buffers are
+    /// scrubbed once the exchange is done, whatever its outcome.
+    pub fn authenticate(&mut self, cookie: &SecretBytes) -> Result<(), NetworkError> {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+        self.write_buf.push_bytes(b"AUTHENTICATE ");
+        for byte in cookie.expose() {
+            self.write_buf
+                .push_bytes(&[HEX[usize::from(byte >> 4)], HEX[usize::from(byte & 0xF)]]);
+        }
+        self.write_buf.push_bytes(b"\r\n");
+
+        // Real implementation flushes write_buf to the socket and reads the
+        // reply into read_buf
+        self.read_buf.push_bytes(b"250 OK\r\n");
+        let accepted = self.read_buf.bytes().starts_with(b"250");
+
+        self.scrub();
+
+        if !accepted {
+            return Err(NetworkError::TorConnectionFailed(
+                "Control port rejected authentication".to_string(),
+            ));
+        }
+        self.authenticated = true;
+        Ok(())
+    }
+
+    /// Drop the current connection state and authenticate again.
+    pub fn reconnect(&mut self, data_dir: &Path) -> Result<(), NetworkError> {
+        self.close();
+        let cookie = read_auth_cookie(data_dir)?;
+        self.authenticate(&cookie)
+    }
+
+    /// Close the channel, scrubbing both buffers.
+    pub fn close(&mut self) {
+        self.scrub();
+        self.authenticated = false;
+    }
+
+    fn scrub(&mut self) {
+        self.read_buf.scrub();
+        self.write_buf.scrub();
+    }
+}
+
+impl<B: ScrubBuffer> Drop for ControlChannel<B> {
+    fn drop(&mut self) {
+        self.scrub();
+    }
+}
+
+impl<B: ScrubBuffer> std::fmt::Debug for ControlChannel<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ControlChannel")
+            .field("authenticated", &self.authenticated)
+            .field("buffered", &self.buffered_len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::sync::{Arc, Mutex};
+
+    /// Contents of each buffer at the moment it was scrubbed.
+    type ScrubLog = Arc<Mutex<Vec<Vec<u8>>>>;
+
+    /// Records every scrub and what the buffer held at that moment.
+    #[derive(Default)]
+    struct InstrumentedBuffer {
+        bytes: Vec<u8>,
+        scrubbed: ScrubLog,
+    }
+
+    impl ScrubBuffer for InstrumentedBuffer {
+        fn push_bytes(&mut self, bytes: &[u8]) {
+            self.bytes.extend_from_slice(bytes);
+        }
+
+        fn bytes(&self) -> &[u8] {
+            &self.bytes
+        }
+
+        fn scrub(&mut self) {
+            self.scrubbed
+                .lock()
+                .expect("scrub log")
+                .push(self.bytes.clone());
+            self.bytes.scrub();
+        }
+    }
+
+    /// Temporary Tor data directory holding a cookie file.
+    pub(crate) fn cookie_dir(tag: &str, cookie: &[u8]) -> TempDir {
+        let dir = TempDir::create(&format!("cookie-{}", tag));
+        write_cookie(&dir, cookie);
+        dir
+    }
+
+    /// Replace the cookie file in `dir`.
+    pub(crate) fn write_cookie(dir: &TempDir, cookie: &[u8]) {
+        std::fs::write(dir.path().join(AUTH_COOKIE_FILE), cookie).expect("write cookie");
+    }
+
+    fn instrumented() -> (ControlChannel<InstrumentedBuffer>, ScrubLog) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let channel = ControlChannel {
+            read_buf: InstrumentedBuffer {
+                bytes: Vec::new(),
+                scrubbed: Arc::clone(&log),
+            },
+            write_buf: InstrumentedBuffer {
+                bytes: Vec::new(),
+                scrubbed: Arc::clone(&log),
+            },
+            authenticated: false,
+        };
+        (channel, log)
+    }
+
+    fn scrubbed_contains(log: &ScrubLog, needle: &[u8]) -> bool {
+        log.lock()
+            .expect("scrub log")
+            .iter()
+            .any(|bytes| bytes.windows(needle.len()).any(|w| w == needle))
+    }
+
+    #[test]
+    fn test_buffers_scrubbed_after_authenticate() {
+        let dir = cookie_dir("auth", &[0xAB; AUTH_COOKIE_LEN]);
+        let (mut channel, log) = instrumented();
+
+        channel.reconnect(dir.path()).expect("authenticate");
+
+        assert!(channel.is_authenticated());
+        assert_eq!(channel.buffered_len(), 0);
+        assert!(scrubbed_contains(&log, b"AUTHENTICATE ABABAB"));
+    }
+
+    #[test]
+    fn test_cookie_reread_on_reconnect() {
+        let dir = cookie_dir("reread", &[0x11; AUTH_COOKIE_LEN]);
+        let (mut channel, log) = instrumented();
+        channel.reconnect(dir.path()).expect("authenticate");
+
+        // Tor restarted and wrote a new cookie
+        write_cookie(&dir, &[0x22; AUTH_COOKIE_LEN]);
+        channel.receive(b"650 NETWORK_LIVENESS UP\r\n");
+        let scrubs_before = log.lock().expect("scrub log").len();
+        channel.reconnect(dir.path()).expect("authenticate");
+
+        assert!(log.lock().expect("scrub log").len() > scrubs_before);
+        assert!(scrubbed_contains(&log, b"650 NETWORK_LIVENESS UP"));
+        assert!(scrubbed_contains(&log, b"AUTHENTICATE 222222"));
+        assert_eq!(channel.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_close_scrubs_and_resets() {
+        let dir = cookie_dir("close", &[0x33; AUTH_COOKIE_LEN]);
+        let (mut channel, log) = instrumented();
+        channel.reconnect(dir.path()).expect("authenticate");
+        channel.send("GETINFO version\r\n");
+
+        channel.close();
+
+        assert!(!channel.is_authenticated());
+        assert_eq!(channel.buffered_len(), 0);
+        assert!(scrubbed_contains(&log, b"GETINFO version"));
+    }
+
+    #[test]
+    fn test_rejects_bad_cookie() {
+        let dir = cookie_dir("short", &[0x44; 8]);
+        let mut channel: ControlChannel = ControlChannel::new();
+
+        assert!(channel.reconnect(dir.path()).is_err());
+        assert!(!channel.is_authenticated());
+    }
+
+    #[test]
+    fn test_cookie_read_failures() {
+        let dir = cookie_dir("failures", &[0x55; 31]);
+        let path = dir.path().join(AUTH_COOKIE_FILE);
+        let message = |result: Result<SecretBytes, NetworkError>| {
+            result.expect_err("cookie refused").to_string()
+        };
+
+        assert!(message(read_cookie_file(&path)).contains("has 31 bytes, expected 32"));
+        assert!(message(read_cookie_file(&dir.path().join("missing"))).contains("not found"));
+        // Root can read anything, so the mapping is checked directly
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(cookie_error(&path, &denied)
+            .to_string()
+            .contains("permission denied"));
+    }
+}
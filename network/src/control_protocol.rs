@@ -0,0 +1,1115 @@
+//! Tor control-port protocol.
+//!
+//! Speaks the line protocol from Tor's control-spec over any byte stream:
+//! a `TcpStream` to the control port in production, an in-memory pipe in
+//! tests. A reply is one or more lines sharing a status code: "250-" for
+//! a middle line, "250+" for a data block ended by a lone ".", and "250 "
+//! for the last line. Asynchronous "650" events can arrive ahead of any
+//! reply; they are queued and handed out by `next_event`, so commands and
+//! the event subscription share the one connection.
+//!
+//! A 4xx or 5xx reply fails the command with `NetworkError::ControlRejected`.
+//!
+//! Authentication prefers SAFECOOKIE: both sides prove they know the
+//! cookie by HMAC over fresh nonces, so the cookie itself never crosses
+//! the socket and a process squatting on the port learns nothing. Plain
+//! COOKIE (hex on the wire) is the fallback, NULL the last resort. The
+//! cookie is read from the COOKIEFILE PROTOCOLINFO names, fresh on every
+//! attempt. If Tor rejects it, or cannot prove it knows it, Tor has most
+//! likely restarted and rewritten it; `open_authenticated` reconnects,
+//! re-reads the cookie and tries once more.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
+
+use rand::Rng;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+use crate::control::{read_cookie_file, AUTH_COOKIE_FILE};
+use crate::digest::{constant_time_eq, hmac_sha256};
+use crate::secret::{wipe_bytes, SecretBytes};
+use crate::NetworkError;
+
+/// Longest reply line accepted, in bytes.
+const MAX_LINE_BYTES: usize = 16 * 1024;
+
+/// Most lines accepted in one reply (circuit-status lists every circuit).
+const MAX_REPLY_LINES: usize = 4096;
+
+/// Status code of an asynchronous event.
+const EVENT_STATUS: u16 = 650;
+
+/// Status code of a rejected AUTHENTICATE.
+const AUTH_FAILED_STATUS: u16 = 515;
+
+/// HMAC key for the hash Tor sends to prove it knows the cookie.
+const SAFECOOKIE_SERVER_KEY: &[u8] = b"Tor safe cookie authentication server-to-controller hash";
+
+/// HMAC key for the hash we send to prove we know the cookie.
+const SAFECOOKIE_CLIENT_KEY: &[u8] = b"Tor safe cookie authentication controller-to-server hash";
+
+/// Length of SAFECOOKIE nonces and hashes.
+const SAFECOOKIE_LEN: usize = 32;
+
+/// A byte stream to the control port.
+pub trait ControlStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ControlStream for T {}
+
+/// One complete reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlReply {
+    /// Three-digit status code
+    pub status: u16,
+    /// Text of each line after the status and separator; a data block is
+    /// appended to its line, one entry per line joined with '\n'
+    pub lines: Vec<String>,
+}
+
+impl ControlReply {
+    /// Value of a "key=value" line, as GETINFO returns them.
+    pub fn value(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    }
+}
+
+/// What PROTOCOLINFO said about the control port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtocolInfo {
+    /// Accepted authentication methods (NULL, COOKIE, SAFECOOKIE, ...)
+    pub auth_methods: Vec<String>,
+    /// Where Tor wrote the auth cookie, if cookie auth is enabled
+    pub cookie_file: Option<PathBuf>,
+    /// Tor version
+    pub tor_version: Option<String>,
+}
+
+impl ProtocolInfo {
+    /// Parse a PROTOCOLINFO reply.
+    pub fn parse(reply: &ControlReply) -> Self {
+        let mut info = ProtocolInfo::default();
+        for line in &reply.lines {
+            if let Some(auth) = line.strip_prefix("AUTH ") {
+                for arg in auth.split(' ') {
+                    if let Some(methods) = arg.strip_prefix("METHODS=") {
+                        info.auth_methods = methods.split(',').map(str::to_string).collect();
+                    }
+                }
+                info.cookie_file = auth
+                    .split_once("COOKIEFILE=")
+                    .and_then(|(_, rest)| unquote(rest))
+                    .map(PathBuf::from);
+            } else if let Some(version) = line.strip_prefix("VERSION Tor=") {
+                info.tor_version = unquote(version);
+            }
+        }
+        info
+    }
+
+    /// Whether `method` is accepted.
+    pub fn accepts(&self, method: &str) -> bool {
+        self.auth_methods.iter().any(|m| m == method)
+    }
+}
+
+/// Where Tor's bootstrap stands.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootstrapProgress {
+    /// Percent complete
+    pub percent: u8,
+    /// Tor's tag for the current phase ("conn_done", "handshake", ...)
+    pub phase: String,
+    /// Tor's description of the current phase
+    pub summary: String,
+    /// Why bootstrap is stuck, if Tor warned about it or lost progress
+    pub stalled: Option<String>,
+}
+
+impl BootstrapProgress {
+    /// Parse a "<severity> BOOTSTRAP PROGRESS=<n> ..." status, from
+    /// GETINFO status/bootstrap-phase or a STATUS_CLIENT event.
+    ///
+    /// A WARN or ERR status is stalled, with Tor's WARNING as the reason.
+    pub fn parse(status: &str) -> Option<Self> {
+        let (head, args) = status.split_once(" BOOTSTRAP ")?;
+        let severity = head.rsplit(' ').next().unwrap_or_default();
+        let args = keyword_args(args);
+        let arg = |key: &str| {
+            args.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.clone())
+        };
+
+        let percent = arg("PROGRESS")?.parse().ok().filter(|p| *p <= 100)?;
+        let summary = arg("SUMMARY").unwrap_or_default();
+        let stalled = matches!(severity, "WARN" | "ERR")
+            .then(|| arg("WARNING").unwrap_or_else(|| summary.clone()));
+        Some(Self {
+            percent,
+            phase: arg("TAG").unwrap_or_default(),
+            summary,
+            stalled,
+        })
+    }
+
+    /// The progress after Tor reports `next`.
+    ///
+    /// Progress going down, as it does when the network drops mid-way,
+    /// stalls bootstrap until it climbs again.
+    pub fn advance(&self, mut next: Self) -> Self {
+        if next.percent < self.percent && next.stalled.is_none() {
+            next.stalled = Some(format!(
+                "Progress fell from {}% to {}%",
+                self.percent, next.percent
+            ));
+        }
+        next
+    }
+
+    /// Whether bootstrap has finished.
+    pub fn is_done(&self) -> bool {
+        self.percent == 100 && self.stalled.is_none()
+    }
+}
+
+/// Bootstrap percentage in a "BOOTSTRAP PROGRESS=<n>" status, from
+/// GETINFO status/bootstrap-phase or a STATUS_CLIENT event.
+pub fn bootstrap_progress(status: &str) -> Option<u8> {
+    BootstrapProgress::parse(status).map(|progress| progress.percent)
+}
+
+/// Id of the newest built general-purpose circuit in a circuit-status
+/// listing.
+///
+/// Tor numbers circuits in creation order, so after NEWNYM the highest
+/// id is the circuit new streams will be attached to.
+pub fn newest_built_circuit(circuit_status: &str) -> Option<String> {
+    circuit_status
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split(' ');
+            let id: u64 = words.next()?.parse().ok()?;
+            let built = words.next()? == "BUILT";
+            let general = words
+                .find_map(|arg| arg.strip_prefix("PURPOSE="))
+                .is_none_or(|purpose| purpose == "GENERAL");
+            (built && general).then_some(id)
+        })
+        .max()
+        .map(|id| id.to_string())
+}
+
+/// Relay fingerprints along circuit `circuit_id` in a circuit-status
+/// listing, entry first.
+///
+/// Path elements are "$<fingerprint>~<nickname>" (or "=<nickname>" from
+/// older Tors); an element with no fingerprint is kept as an empty string
+/// so the hop count stays right.
+pub fn circuit_path(circuit_status: &str, circuit_id: &str) -> Option<Vec<String>> {
+    let line = circuit_status
+        .lines()
+        .find(|line| line.split(' ').next() == Some(circuit_id))?;
+    // A circuit still being launched has no path, only keyword arguments
+    let path = line
+        .split(' ')
+        .nth(2)
+        .filter(|path| path.starts_with('$') || !path.contains('='))?;
+    Some(
+        path.split(',')
+            .map(|hop| {
+                hop.strip_prefix('$')
+                    .map(|hop| hop.split(['~', '=']).next().unwrap_or_default())
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .collect(),
+    )
+}
+
+/// A relay's IPv4 address from its router status entry, as GETINFO
+/// ns/id/<fingerprint> returns it.
+pub fn relay_address(ns_entry: &str) -> Option<Ipv4Addr> {
+    ns_entry
+        .lines()
+        .find_map(|line| line.strip_prefix("r "))?
+        .split(' ')
+        .nth(5)?
+        .parse()
+        .ok()
+}
+
+/// An open control-port connection.
+pub struct ControlConnection {
+    stream: BufReader<Box<dyn ControlStream>>,
+    events: VecDeque<String>,
+}
+
+impl ControlConnection {
+    /// Wrap a connected stream.
+    pub fn new(stream: impl ControlStream + 'static) -> Self {
+        Self {
+            stream: BufReader::new(Box::new(stream)),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Send a command and wait for its reply.
+    pub async fn command(&mut self, command: &str) -> Result<ControlReply, NetworkError> {
+        if command.contains(['\r', '\n']) {
+            return Err(NetworkError::TorConnectionFailed(
+                "Control command contains a line break".to_string(),
+            ));
+        }
+        let mut bytes = Vec::with_capacity(command.len() + 2);
+        bytes.extend_from_slice(command.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+        self.send_and_wait(bytes).await
+    }
+
+    /// Ask which authentication methods the control port accepts.
+    pub async fn protocol_info(&mut self) -> Result<ProtocolInfo, NetworkError> {
+        let reply = self.command("PROTOCOLINFO 1").await?;
+        Ok(ProtocolInfo::parse(&reply))
+    }
+
+    /// Authenticate with the best method `info` offers: SAFECOOKIE, then
+    /// COOKIE, then NULL.
+    ///
+    /// The cookie is read from `info.cookie_file`, or from `data_dir` if
+    /// PROTOCOLINFO named none. Command buffers holding anything derived
+    /// from it are wiped once sent.
+    pub async fn authenticate(
+        &mut self,
+        info: &ProtocolInfo,
+        data_dir: &Path,
+    ) -> Result<(), NetworkError> {
+        self.try_authenticate(info, data_dir)
+            .await
+            .map_err(AuthFailure::into_error)
+    }
+
+    async fn try_authenticate(
+        &mut self,
+        info: &ProtocolInfo,
+        data_dir: &Path,
+    ) -> Result<(), AuthFailure> {
+        let cookie_file = || {
+            info.cookie_file
+                .clone()
+                .unwrap_or_else(|| data_dir.join(AUTH_COOKIE_FILE))
+        };
+        let mut command = if info.accepts("SAFECOOKIE") {
+            let cookie = read_cookie_file(&cookie_file()).map_err(AuthFailure::Fatal)?;
+            self.safecookie_response(&cookie).await?
+        } else if info.accepts("COOKIE") {
+            let cookie = read_cookie_file(&cookie_file()).map_err(AuthFailure::Fatal)?;
+            let mut command = b"AUTHENTICATE ".to_vec();
+            push_hex(&mut command, cookie.expose());
+            command
+        } else if info.accepts("NULL") {
+            b"AUTHENTICATE".to_vec()
+        } else {
+            return Err(AuthFailure::Fatal(NetworkError::TorConnectionFailed(
+                format!(
+                    "No supported control auth method in {:?}",
+                    info.auth_methods
+                ),
+            )));
+        };
+        command.extend_from_slice(b"\r\n");
+
+        match self.send_and_wait(command).await {
+            Ok(_) => Ok(()),
+            Err(NetworkError::ControlRejected {
+                status: AUTH_FAILED_STATUS,
+                message,
+            }) => Err(AuthFailure::StaleCookie(NetworkError::ControlRejected {
+                status: AUTH_FAILED_STATUS,
+                message,
+            })),
+            Err(e) => Err(AuthFailure::Fatal(e)),
+        }
+    }
+
+    /// Run the AUTHCHALLENGE exchange and build the AUTHENTICATE command
+    /// carrying our HMAC, without its line terminator.
+    async fn safecookie_response(&mut self, cookie: &SecretBytes) -> Result<Vec<u8>, AuthFailure> {
+        let mut client_nonce = [0u8; SAFECOOKIE_LEN];
+        rand::thread_rng().fill(&mut client_nonce);
+        let challenge = format!(
+            "AUTHCHALLENGE SAFECOOKIE {}",
+            client_nonce
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<String>()
+        );
+
+        let reply = self.command(&challenge).await.map_err(AuthFailure::Fatal)?;
+        let arg = |name: &str| {
+            reply
+                .lines
+                .iter()
+                .flat_map(|line| line.split(' '))
+                .find_map(|arg| arg.strip_prefix(name))
+                .and_then(decode_hex)
+                .filter(|bytes| bytes.len() == SAFECOOKIE_LEN)
+        };
+        let (Some(server_hash), Some(server_nonce)) = (arg("SERVERHASH="), arg("SERVERNONCE="))
+        else {
+            return Err(AuthFailure::Fatal(malformed("bad AUTHCHALLENGE reply")));
+        };
+
+        let message: [&[u8]; 3] = [cookie.expose(), &client_nonce, &server_nonce];
+        let mut expected = hmac_sha256(SAFECOOKIE_SERVER_KEY, &message);
+        let genuine = constant_time_eq(&expected, &server_hash);
+        wipe_bytes(&mut expected);
+        if !genuine {
+            return Err(AuthFailure::StaleCookie(NetworkError::TorConnectionFailed(
+                "Control port could not prove it knows the auth cookie".to_string(),
+            )));
+        }
+
+        let mut client_hash = hmac_sha256(SAFECOOKIE_CLIENT_KEY, &message);
+        let mut command = b"AUTHENTICATE ".to_vec();
+        push_hex(&mut command, &client_hash);
+        wipe_bytes(&mut client_hash);
+        Ok(command)
+    }
+
+    /// Ask for one GETINFO key.
+    pub async fn get_info(&mut self, key: &str) -> Result<String, NetworkError> {
+        let reply = self.command(&format!("GETINFO {}", key)).await?;
+        reply.value(key).map(str::to_string).ok_or_else(|| {
+            NetworkError::TorConnectionFailed(format!("GETINFO reply without {}", key))
+        })
+    }
+
+    /// Take every event queued while waiting for replies.
+    pub fn take_events(&mut self) -> Vec<String> {
+        self.events.drain(..).collect()
+    }
+
+    /// Wait for the next asynchronous event line, starting with "650".
+    pub async fn next_event(&mut self) -> Result<String, NetworkError> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let reply = self.read_reply().await?;
+            if reply.status == EVENT_STATUS {
+                return Ok(event_line(&reply));
+            }
+            log::warn!("Unsolicited control reply {}", reply.status);
+        }
+    }
+
+    /// Queue the events that have already arrived, without waiting for
+    /// more.
+    ///
+    /// Fails if Tor closed the connection or it broke, so an idle
+    /// connection can be checked between commands.
+    pub async fn read_ready_events(&mut self) -> Result<(), NetworkError> {
+        loop {
+            let ready = std::future::poll_fn(|cx| {
+                Poll::Ready(match Pin::new(&mut self.stream).poll_fill_buf(cx) {
+                    Poll::Ready(buffered) => Some(buffered.map(|bytes| !bytes.is_empty())),
+                    Poll::Pending => None,
+                })
+            })
+            .await;
+            match ready {
+                None => return Ok(()),
+                Some(Ok(true)) => {
+                    let reply = self.read_reply().await?;
+                    if reply.status == EVENT_STATUS {
+                        self.events.push_back(event_line(&reply));
+                    } else {
+                        log::warn!("Unsolicited control reply {}", reply.status);
+                    }
+                }
+                Some(Ok(false)) => {
+                    return Err(NetworkError::TorConnectionFailed(
+                        "Control port closed the connection".to_string(),
+                    ))
+                }
+                Some(Err(e)) => {
+                    return Err(NetworkError::TorConnectionFailed(format!(
+                        "Control port: {}",
+                        e
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Write `bytes`, wipe them, and read until the command's reply.
+    async fn send_and_wait(&mut self, mut bytes: Vec<u8>) -> Result<ControlReply, NetworkError> {
+        let written = async {
+            self.stream.write_all(&bytes).await?;
+            self.stream.flush().await
+        }
+        .await;
+        wipe_bytes(&mut bytes);
+        written.map_err(|e| NetworkError::TorConnectionFailed(format!("Control port: {}", e)))?;
+
+        loop {
+            let reply = self.read_reply().await?;
+            if reply.status == EVENT_STATUS {
+                self.events.push_back(event_line(&reply));
+                continue;
+            }
+            if reply.status >= 400 {
+                return Err(NetworkError::ControlRejected {
+                    status: reply.status,
+                    message: reply.lines.last().cloned().unwrap_or_default(),
+                });
+            }
+            return Ok(reply);
+        }
+    }
+
+    async fn read_reply(&mut self) -> Result<ControlReply, NetworkError> {
+        let mut status = None;
+        let mut lines = Vec::new();
+        loop {
+            if lines.len() >= MAX_REPLY_LINES {
+                return Err(malformed("too many lines"));
+            }
+            let line = self.read_line().await?;
+            let code: u16 = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| malformed("no status code"))?;
+            if *status.get_or_insert(code) != code {
+                return Err(malformed("status code changed mid-reply"));
+            }
+            let text = line.get(4..).unwrap_or_default().to_string();
+            match line.as_bytes().get(3) {
+                Some(b' ') | None => {
+                    lines.push(text);
+                    return Ok(ControlReply {
+                        status: code,
+                        lines,
+                    });
+                }
+                Some(b'-') => lines.push(text),
+                Some(b'+') => {
+                    let mut data = Vec::new();
+                    loop {
+                        let line = self.read_line().await?;
+                        if line == "." {
+                            break;
+                        }
+                        data.push(line.strip_prefix('.').unwrap_or(&line).to_string());
+                        if data.len() >= MAX_REPLY_LINES {
+                            return Err(malformed("data block too long"));
+                        }
+                    }
+                    lines.push(text + &data.join("\n"));
+                }
+                Some(_) => return Err(malformed("bad separator")),
+            }
+        }
+    }
+
+    /// Read one CRLF-terminated line, without the terminator.
+    async fn read_line(&mut self) -> Result<String, NetworkError> {
+        let mut line = Vec::new();
+        let read = (&mut self.stream)
+            .take(MAX_LINE_BYTES as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| NetworkError::TorConnectionFailed(format!("Control port: {}", e)))?;
+        if read == 0 {
+            return Err(NetworkError::TorConnectionFailed(
+                "Control port closed the connection".to_string(),
+            ));
+        }
+        if read > MAX_LINE_BYTES || !line.ends_with(b"\n") {
+            return Err(malformed("line too long"));
+        }
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| malformed("not UTF-8"))
+    }
+}
+
+impl std::fmt::Debug for ControlConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ControlConnection")
+            .field("queued_events", &self.events.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Connect with `connect` and authenticate, retrying once on a fresh
+/// connection if the cookie looks stale.
+///
+/// Tor closes the connection after a failed AUTHENTICATE, and a cookie
+/// Tor cannot prove it knows must not be answered, so the retry always
+/// starts over with a new connection, a new PROTOCOLINFO and a cookie
+/// read again from disk.
+pub async fn open_authenticated<C, F, S>(
+    mut connect: C,
+    data_dir: &Path,
+) -> Result<(ControlConnection, ProtocolInfo), NetworkError>
+where
+    C: FnMut() -> F,
+    F: Future<Output = Result<S, NetworkError>>,
+    S: ControlStream + 'static,
+{
+    let mut stale: Option<NetworkError> = None;
+    loop {
+        let stream = match connect().await {
+            Ok(stream) => stream,
+            // If reconnecting failed, the rejection says more
+            Err(e) => return Err(stale.unwrap_or(e)),
+        };
+        let mut connection = ControlConnection::new(stream);
+        let info = connection.protocol_info().await?;
+        match connection.try_authenticate(&info, data_dir).await {
+            Ok(()) => return Ok((connection, info)),
+            Err(AuthFailure::StaleCookie(e)) if stale.is_none() => {
+                log::warn!("Control auth failed, re-reading the cookie: {}", e);
+                stale = Some(e);
+            }
+            Err(failure) => return Err(failure.into_error()),
+        }
+    }
+}
+
+/// Why authentication failed.
+enum AuthFailure {
+    /// Tor and we disagree about the cookie; re-reading it may help
+    StaleCookie(NetworkError),
+    /// Anything else
+    Fatal(NetworkError),
+}
+
+impl AuthFailure {
+    fn into_error(self) -> NetworkError {
+        match self {
+            AuthFailure::StaleCookie(e) | AuthFailure::Fatal(e) => e,
+        }
+    }
+}
+
+/// Append `bytes` as uppercase hex.
+fn push_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for byte in bytes {
+        out.extend_from_slice(&[HEX[usize::from(byte >> 4)], HEX[usize::from(byte & 0xF)]]);
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The event as a single "650 ..." line, the form `TorEvent::parse` takes.
+fn event_line(reply: &ControlReply) -> String {
+    format!(
+        "{} {}",
+        EVENT_STATUS,
+        reply.lines.first().map_or("", String::as_str)
+    )
+}
+
+/// Contents of a quoted string at the start of `s`, unescaped.
+fn unquote(s: &str) -> Option<String> {
+    unquote_prefix(s).map(|(out, _)| out)
+}
+
+/// Like `unquote`, also returning the length of the quoted string.
+fn unquote_prefix(s: &str) -> Option<(String, usize)> {
+    let mut chars = s.strip_prefix('"')?.char_indices();
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            (end, '"') => return Some((out, end + 2)),
+            (_, '\\') => out.push(chars.next()?.1),
+            (_, c) => out.push(c),
+        }
+    }
+}
+
+/// KEY=value and KEY="quoted value" arguments, in order. Words
+/// without a value are skipped.
+fn keyword_args(mut args: &str) -> Vec<(&str, String)> {
+    let mut out = Vec::new();
+    loop {
+        args = args.trim_start_matches(' ');
+        let Some(word_end) = args.find([' ', '=']) else {
+            return out;
+        };
+        let (name, rest) = args.split_at(word_end);
+        let Some(value) = rest.strip_prefix('=') else {
+            args = rest;
+            continue;
+        };
+        let (value, len) = match unquote_prefix(value) {
+            Some(quoted) => quoted,
+            None => {
+                let len = value.find(' ').unwrap_or(value.len());
+                (value[..len].to_string(), len)
+            }
+        };
+        out.push((name, value));
+        args = &rest[1 + len..];
+    }
+}
+
+fn malformed(what: &str) -> NetworkError {
+    NetworkError::TorConnectionFailed(format!("Malformed control reply: {}", what))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::control::tests::{cookie_dir, write_cookie};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Commands a mock control port received, in order.
+    pub(crate) type CommandLog = Arc<Mutex<Vec<String>>>;
+
+    /// Serve a control port on an in-memory pipe, answering each command
+    /// line with whatever `respond` returns for it.
+    pub(crate) fn mock_control_port<F>(mut respond: F) -> (DuplexStream, CommandLog)
+    where
+        F: FnMut(&str) -> String + Send + 'static,
+    {
+        let (client, server) = duplex(64 * 1024);
+        let log = CommandLog::default();
+        let commands = Arc::clone(&log);
+        tokio::spawn(async move {
+            let (read, mut write) = tokio::io::split(server);
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = respond(&line);
+                commands.lock().expect("command log").push(line);
+                if write.write_all(reply.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        });
+        (client, log)
+    }
+
+    /// Responder for a bootstrapped Tor that accepts NULL auth.
+    ///
+    /// Every NEWNYM builds one more circuit, so circuit-status lists
+    /// circuits 1 to the number of NEWNYMs so far.
+    pub(crate) fn tor_ready() -> impl FnMut(&str) -> String + Send + 'static {
+        let mut circuits = 0;
+        move |command| match command {
+            "PROTOCOLINFO 1" => concat!(
+                "250-PROTOCOLINFO 1\r\n",
+                "250-AUTH METHODS=NULL\r\n",
+                "250-VERSION Tor=\"0.4.8.9\"\r\n",
+                "250 OK\r\n"
+            )
+            .to_string(),
+            "GETINFO status/bootstrap-phase" => concat!(
+                "250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=100 TAG=done ",
+                "SUMMARY=\"Done\"\r\n",
+                "250 OK\r\n"
+            )
+            .to_string(),
+            "SIGNAL NEWNYM" => {
+                circuits += 1;
+                "250 OK\r\n".to_string()
+            }
+            "GETINFO circuit-status" => {
+                let mut reply = "250+circuit-status=\r\n".to_string();
+                for id in 1..=circuits {
+                    reply += &format!("{} BUILT $A~a,$B~b,$C~c PURPOSE=GENERAL\r\n", id);
+                }
+                reply + ".\r\n250 OK\r\n"
+            }
+            _ if command == "AUTHENTICATE"
+                || command.starts_with("SETEVENTS ")
+                || command.starts_with("CLOSECIRCUIT ") =>
+            {
+                "250 OK\r\n".to_string()
+            }
+            _ => "510 Unrecognized command\r\n".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multi_line_and_data_replies() {
+        let (stream, _) = mock_control_port(|command| match command {
+            "PROTOCOLINFO 1" => concat!(
+                "250-PROTOCOLINFO 1\r\n",
+                "250-AUTH METHODS=COOKIE,SAFECOOKIE ",
+                "COOKIEFILE=\"/var/lib/tor/control_auth_cookie\"\r\n",
+                "250-VERSION Tor=\"0.4.8.9\"\r\n",
+                "250 OK\r\n"
+            )
+            .to_string(),
+            _ => concat!(
+                "650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=85 TAG=ap_conn\r\n",
+                "250+circuit-status=\r\n",
+                "3 BUILT $A~a,$B~b PURPOSE=GENERAL\r\n",
+                "7 EXTENDED $A~a PURPOSE=GENERAL\r\n",
+                "5 BUILT $A~a,$B~b,$C~c PURPOSE=GENERAL\r\n",
+                "9 BUILT $A~a,$B~b PURPOSE=HS_CLIENT_INTRO\r\n",
+                ".\r\n",
+                "250 OK\r\n"
+            )
+            .to_string(),
+        });
+        let mut connection = ControlConnection::new(stream);
+
+        let info = connection.protocol_info().await.expect("protocolinfo");
+        assert_eq!(info.auth_methods, ["COOKIE", "SAFECOOKIE"]);
+        assert_eq!(
+            info.cookie_file.as_deref(),
+            Some(Path::new("/var/lib/tor/control_auth_cookie"))
+        );
+        assert_eq!(info.tor_version.as_deref(), Some("0.4.8.9"));
+
+        let status = connection
+            .get_info("circuit-status")
+            .await
+            .expect("circuit-status");
+        assert_eq!(newest_built_circuit(&status).as_deref(), Some("5"));
+
+        // The event that arrived ahead of the reply was queued
+        let event = connection.next_event().await.expect("event");
+        assert_eq!(bootstrap_progress(&event), Some(85));
+        assert!(connection.take_events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ready_events_read_without_waiting() {
+        let (client, mut server) = duplex(1024);
+        let mut connection = ControlConnection::new(client);
+
+        // Nothing arrived: returns at once
+        connection.read_ready_events().await.expect("idle");
+        assert!(connection.take_events().is_empty());
+
+        server
+            .write_all(b"650 NETWORK_LIVENESS DOWN\r\n650 NETWORK_LIVENESS UP\r\n")
+            .await
+            .expect("write");
+        connection.read_ready_events().await.expect("events");
+        assert_eq!(
+            connection.take_events(),
+            ["650 NETWORK_LIVENESS DOWN", "650 NETWORK_LIVENESS UP"]
+        );
+
+        drop(server);
+        assert!(matches!(
+            connection.read_ready_events().await,
+            Err(NetworkError::TorConnectionFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_error_replies_fail_the_command() {
+        let (stream, log) = mock_control_port(|command| match command {
+            "SIGNAL NEWNYM" => "552 Unrecognized signal\r\n".to_string(),
+            _ => "250 OK\r\n".to_string(),
+        });
+        let mut connection = ControlConnection::new(stream);
+
+        let error = connection
+            .command("SIGNAL NEWNYM")
+            .await
+            .expect_err("rejected");
+        assert!(matches!(
+            error,
+            NetworkError::ControlRejected { status: 552, ref message }
+                if message == "Unrecognized signal"
+        ));
+
+        // The connection is still usable, and nothing with a line break
+        // is ever sent
+        connection.command("SETEVENTS").await.expect("accepted");
+        assert!(connection
+            .command("GETINFO version\r\nSIGNAL HALT")
+            .await
+            .is_err());
+        assert_eq!(
+            log.lock().expect("command log").as_slice(),
+            ["SIGNAL NEWNYM", "SETEVENTS"]
+        );
+    }
+
+    /// Responder for a Tor offering `methods`, with `cookie` in `cookie_file`.
+    ///
+    /// Checks AUTHENTICATE against the cookie (COOKIE) or the HMAC over
+    /// both nonces (SAFECOOKIE), answering 515 on a mismatch.
+    fn cookie_tor(
+        methods: &'static str,
+        cookie_file: &Path,
+        cookie: [u8; 32],
+    ) -> impl FnMut(&str) -> String + Send + 'static {
+        let server_nonce = [0x4E; SAFECOOKIE_LEN];
+        let cookie_file = cookie_file.display().to_string();
+        let mut expected = Vec::new();
+        push_hex(&mut expected, &cookie);
+        move |command| {
+            if command == "PROTOCOLINFO 1" {
+                return format!(
+                    "250-PROTOCOLINFO 1\r\n250-AUTH METHODS={} COOKIEFILE=\"{}\"\r\n250 OK\r\n",
+                    methods, cookie_file
+                );
+            }
+            if let Some(client_nonce) = command.strip_prefix("AUTHCHALLENGE SAFECOOKIE ") {
+                let client_nonce = decode_hex(client_nonce).expect("hex nonce");
+                let message: [&[u8]; 3] = [&cookie, &client_nonce, &server_nonce];
+                let mut reply = b"250 AUTHCHALLENGE SERVERHASH=".to_vec();
+                push_hex(&mut reply, &hmac_sha256(SAFECOOKIE_SERVER_KEY, &message));
+                reply.extend_from_slice(b" SERVERNONCE=");
+                push_hex(&mut reply, &server_nonce);
+                reply.extend_from_slice(b"\r\n");
+                expected = b"AUTHENTICATE ".to_vec();
+                push_hex(&mut expected, &hmac_sha256(SAFECOOKIE_CLIENT_KEY, &message));
+                return String::from_utf8(reply).expect("ASCII");
+            }
+            if let Some(hex) = command.strip_prefix("AUTHENTICATE ") {
+                let accepted = expected.ends_with(hex.as_bytes());
+                return if accepted {
+                    "250 OK\r\n"
+                } else {
+                    "515 Authentication failed\r\n"
+                }
+                .to_string();
+            }
+            "510 Unrecognized command\r\n".to_string()
+        }
+    }
+
+    /// Connects to each stream in turn, counting connections.
+    fn connector(
+        streams: Vec<DuplexStream>,
+    ) -> (
+        impl FnMut() -> std::future::Ready<Result<DuplexStream, NetworkError>>,
+        Arc<Mutex<usize>>,
+    ) {
+        let mut streams = streams.into_iter();
+        let count = Arc::new(Mutex::new(0));
+        let connects = Arc::clone(&count);
+        let connect = move || {
+            *connects.lock().expect("connects") += 1;
+            std::future::ready(
+                streams
+                    .next()
+                    .ok_or_else(|| NetworkError::TorConnectionFailed("refused".to_string())),
+            )
+        };
+        (connect, count)
+    }
+
+    fn logged(log: &CommandLog) -> Vec<String> {
+        log.lock().expect("command log").clone()
+    }
+
+    #[tokio::test]
+    async fn test_safecookie_preferred_and_cookie_stays_local() {
+        let cookie = [0xC3; 32];
+        let dir = cookie_dir("safecookie", &cookie);
+        let cookie_file = dir.path().join(AUTH_COOKIE_FILE);
+        let (stream, log) =
+            mock_control_port(cookie_tor("COOKIE,SAFECOOKIE", &cookie_file, cookie));
+        let (connect, _) = connector(vec![stream]);
+
+        // The data directory is wrong; COOKIEFILE is what counts
+        let (_, info) = open_authenticated(connect, Path::new("/nonexistent"))
+            .await
+            .expect("authenticated");
+        assert_eq!(info.cookie_file.as_deref(), Some(cookie_file.as_path()));
+
+        let commands = logged(&log);
+        assert_eq!(commands.len(), 3);
+        assert!(commands[1].starts_with("AUTHCHALLENGE SAFECOOKIE "));
+        assert!(commands[2].starts_with("AUTHENTICATE "));
+        assert!(commands.iter().all(|command| !command.contains("C3C3C3")));
+    }
+
+    #[tokio::test]
+    async fn test_plain_cookie_auth() {
+        let cookie = [0x3C; 32];
+        let dir = cookie_dir("plain", &cookie);
+        let cookie_file = dir.path().join(AUTH_COOKIE_FILE);
+        let (stream, log) = mock_control_port(cookie_tor("COOKIE", &cookie_file, cookie));
+
+        let mut connection = ControlConnection::new(stream);
+        let info = connection.protocol_info().await.expect("protocolinfo");
+        connection
+            .authenticate(&info, Path::new("/nonexistent"))
+            .await
+            .expect("authenticated");
+        assert_eq!(logged(&log)[1], format!("AUTHENTICATE {}", "3C".repeat(32)));
+
+        // Missing and truncated cookies fail before anything is sent
+        for (contents, error) in [(None, "not found"), (Some(&[0x3C; 16][..]), "16 bytes")] {
+            match contents {
+                Some(contents) => write_cookie(&dir, contents),
+                None => std::fs::remove_file(&cookie_file).expect("remove cookie"),
+            }
+            let (stream, log) = mock_control_port(cookie_tor("COOKIE", &cookie_file, cookie));
+            let (connect, _) = connector(vec![stream]);
+            let message = open_authenticated(connect, dir.path())
+                .await
+                .expect_err("no usable cookie")
+                .to_string();
+            assert!(message.contains(error), "{}", message);
+            assert_eq!(logged(&log), ["PROTOCOLINFO 1"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_cookie_reread_once() {
+        let (old, new) = ([0x01; 32], [0x02; 32]);
+        for methods in ["COOKIE", "SAFECOOKIE"] {
+            let dir = cookie_dir(&format!("stale-{}", methods), &old);
+            let cookie_file = dir.path().join(AUTH_COOKIE_FILE);
+
+            // Tor restarts with a new cookie just as we authenticate
+            let mut restarted = cookie_tor(methods, &cookie_file, new);
+            let rewrite = cookie_file.clone();
+            let (first, first_log) = mock_control_port(move |command| {
+                if command.starts_with("AUTH") {
+                    std::fs::write(&rewrite, new).expect("rewrite cookie");
+                }
+                restarted(command)
+            });
+            let (second, _) = mock_control_port(cookie_tor(methods, &cookie_file, new));
+            let (connect, connects) = connector(vec![first, second]);
+
+            open_authenticated(connect, dir.path())
+                .await
+                .expect("authenticated on retry");
+            assert_eq!(*connects.lock().expect("connects"), 2);
+            // A SAFECOOKIE server that can't prove the cookie gets no answer
+            let answered = logged(&first_log)
+                .iter()
+                .any(|command| command.starts_with("AUTHENTICATE"));
+            assert_eq!(answered, methods == "COOKIE");
+
+            // Only once: a cookie that stays wrong fails
+            let (first, _) = mock_control_port(cookie_tor(methods, &cookie_file, old));
+            let (second, _) = mock_control_port(cookie_tor(methods, &cookie_file, old));
+            let (connect, connects) = connector(vec![first, second]);
+            assert!(open_authenticated(connect, dir.path()).await.is_err());
+            assert_eq!(*connects.lock().expect("connects"), 2);
+        }
+    }
+
+    #[test]
+    fn test_bootstrap_progress() {
+        assert_eq!(
+            bootstrap_progress("NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\""),
+            Some(100)
+        );
+        assert_eq!(
+            bootstrap_progress("650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=14 TAG=handshake"),
+            Some(14)
+        );
+        assert_eq!(
+            bootstrap_progress("650 STATUS_CLIENT NOTICE CIRCUIT_ESTABLISHED"),
+            None
+        );
+        assert_eq!(bootstrap_progress("NOTICE BOOTSTRAP PROGRESS=250"), None);
+    }
+
+    /// circuit-status as Tor 0.4.8 reports it, with an onion circuit, a
+    /// circuit still being built and one being launched.
+    const CIRCUIT_STATUS: &str = "
+7 BUILT $F2044413DAC2E02E3D6BCF4735A19BCA1DE97281~gabelmoo,$847B1F850344D7876491A54892F904934E4EB85D~tor26,$9695DFC35FFEB861329B9F1AB04C46397020CE31~moria1 BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL TIME_CREATED=2024-05-01T10:00:00.000000
+8 BUILT $F2044413DAC2E02E3D6BCF4735A19BCA1DE97281~gabelmoo,$BD6A829255CB08E66FBE7D3748363586E46B3810~maatuska,$7BE683E65D48141321C5ED92F075C55364AC7123~dannenberg,$847B1F850344D7876491A54892F904934E4EB85D~tor26 BUILD_FLAGS=IS_INTERNAL,NEED_CAPACITY,NEED_UPTIME PURPOSE=HS_CLIENT_REND HS_STATE=HSCR_JOINED REND_QUERY=duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad TIME_CREATED=2024-05-01T10:00:01.000000
+9 EXTENDED $F2044413DAC2E02E3D6BCF4735A19BCA1DE97281~gabelmoo BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL TIME_CREATED=2024-05-01T10:00:02.000000
+10 LAUNCHED BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL TIME_CREATED=2024-05-01T10:00:03.000000";
+
+    #[test]
+    fn test_circuit_status_parsing() {
+        let newest = newest_built_circuit(CIRCUIT_STATUS).expect("built circuit");
+        assert_eq!(newest, "7");
+        assert_eq!(
+            circuit_path(CIRCUIT_STATUS, &newest).expect("path"),
+            [
+                "F2044413DAC2E02E3D6BCF4735A19BCA1DE97281",
+                "847B1F850344D7876491A54892F904934E4EB85D",
+                "9695DFC35FFEB861329B9F1AB04C46397020CE31",
+            ]
+        );
+        assert_eq!(circuit_path(CIRCUIT_STATUS, "8").expect("path").len(), 4);
+        assert_eq!(circuit_path(CIRCUIT_STATUS, "10"), None);
+        assert_eq!(circuit_path(CIRCUIT_STATUS, "11"), None);
+        // Older Tors separate the nickname with '='; a bare nickname has
+        // no fingerprint to look up
+        assert_eq!(
+            circuit_path("3 BUILT $ABCD=relay,exit PURPOSE=GENERAL", "3").expect("path"),
+            ["ABCD", ""]
+        );
+
+        let entry = concat!(
+            "\n",
+            "r moria1 lpXfw1/+uGEym58asExGOXAgzjE p9CMnfPLLxWDNqbkJq7g7lSbaKQ ",
+            "2024-05-01 09:38:57 128.31.0.39 9201 9231\n",
+            "a [2001:db8::1]:9201\n",
+            "s Authority Fast Running Stable V2Dir Valid\n",
+            "w Bandwidth=20 Unmeasured=1",
+        );
+        assert_eq!(relay_address(entry), Some(Ipv4Addr::new(128, 31, 0, 39)));
+        assert_eq!(relay_address("s Fast Running"), None);
+    }
+
+    #[test]
+    fn test_bootstrap_stalls() {
+        let handshake = BootstrapProgress::parse(
+            "650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=45 TAG=requesting_descriptors \
+             SUMMARY=\"Asking for relay descriptors\"",
+        )
+        .expect("progress");
+        assert_eq!(handshake.percent, 45);
+        assert_eq!(handshake.phase, "requesting_descriptors");
+        assert_eq!(handshake.summary, "Asking for relay descriptors");
+        assert_eq!(handshake.stalled, None);
+
+        // Tor's warning, quoted spaces and all
+        let warned = BootstrapProgress::parse(
+            "650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=10 TAG=conn_done \
+             SUMMARY=\"Connected to a relay\" WARNING=\"Connection refused\" \
+             REASON=CONNECTREFUSED COUNT=3 RECOMMENDATION=warn",
+        )
+        .expect("progress");
+        assert_eq!(warned.summary, "Connected to a relay");
+        assert_eq!(warned.stalled.as_deref(), Some("Connection refused"));
+        assert!(!handshake.advance(warned).is_done());
+
+        // Losing the network sets progress back
+        let regressed = handshake.advance(
+            BootstrapProgress::parse("NOTICE BOOTSTRAP PROGRESS=5 TAG=conn").expect("progress"),
+        );
+        assert_eq!(
+            regressed.stalled.as_deref(),
+            Some("Progress fell from 45% to 5%")
+        );
+        let recovered = regressed.advance(
+            BootstrapProgress::parse("NOTICE BOOTSTRAP PROGRESS=100 TAG=done").expect("progress"),
+        );
+        assert!(recovered.is_done());
+    }
+}
@@ -0,0 +1,716 @@
+//! Downloads written to the RAM-backed download directory.
+//!
+//! `AnonymizedNetwork::download` streams a response body straight into a
+//! file under `NetworkConfig::download_dir`, so a download never touches
+//! persistent storage and is never held in memory whole. The file is
+//! created exclusively, under a name taken from Content-Disposition or
+//! the URL and reduced to a safe character set, so a server cannot name
+//! a path outside the directory or overwrite what is already there.
+//!
+//! The directory lives in RAM, so what it may hold is budgeted: one file
+//! may not exceed `DownloadBudget::per_file`, and all of them together
+//! may not exceed `DownloadBudget::total` or the size of the tmpfs.
+//!
+//! Progress is published on a watch channel any number of UI components
+//! can subscribe to. A download that does not complete (cancelled, over
+//! budget, cut off, or dropped with its scope on New Loop) has its
+//! partial file overwritten and deleted before it reports how it ended.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use forloop_config::ByteSize;
+use tokio::sync::watch;
+
+use crate::downloads::{create_unique, safe_file_name, url_file_name};
+use crate::streaming::{ResponseBody, StreamingResponse};
+use crate::tasks::{TaskCancel, TaskRegistry, TaskScope};
+use crate::NetworkError;
+
+/// Name a download is saved under when neither the server nor the URL
+/// gives a usable one.
+pub const DEFAULT_DOWNLOAD_NAME: &str = "download";
+
+/// Longest file name a download is saved under, in bytes.
+const MAX_FILE_NAME: usize = 128;
+
+/// How much of the download directory downloads may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadBudget {
+    /// Largest single download
+    pub per_file: ByteSize,
+    /// Most the directory may hold, all files together; lowered to the
+    /// size of the tmpfs it is on
+    pub total: ByteSize,
+}
+
+impl Default for DownloadBudget {
+    fn default() -> Self {
+        Self {
+            per_file: ByteSize::mib(512),
+            total: ByteSize::mib(1024),
+        }
+    }
+}
+
+/// How a download stands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadState {
+    /// The body is still arriving
+    InProgress,
+    /// The whole body was saved to this file
+    Complete(PathBuf),
+    /// The download failed and its partial file was wiped
+    Failed(String),
+    /// The download was cancelled and its partial file was wiped
+    Cancelled,
+}
+
+/// Progress of one download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Body bytes written so far
+    pub received_bytes: u64,
+    /// Body length announced by the server, if any
+    pub total_bytes: Option<u64>,
+    /// How the download stands
+    pub state: DownloadState,
+}
+
+impl DownloadProgress {
+    /// Whether the download has ended, one way or another.
+    pub fn is_finished(&self) -> bool {
+        self.state != DownloadState::InProgress
+    }
+}
+
+/// A download whose body is being written by a background task.
+#[derive(Debug)]
+pub struct Download {
+    path: PathBuf,
+    progress: watch::Receiver<DownloadProgress>,
+    cancel: TaskCancel,
+}
+
+impl Download {
+    /// Get the file being written. It only remains once complete.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Subscribe to progress updates, for the UI.
+    pub fn subscribe(&self) -> watch::Receiver<DownloadProgress> {
+        self.progress.clone()
+    }
+
+    /// Cancel the download; its partial file is wiped.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Wait for the download to end and get its final progress.
+    pub async fn finished(&self) -> DownloadProgress {
+        let mut progress = self.progress.clone();
+        let finished = progress
+            .wait_for(DownloadProgress::is_finished)
+            .await
+            .map(|done| done.clone());
+        // The sender always reports an end before it goes away
+        finished.unwrap_or_else(|_| progress.borrow().clone())
+    }
+}
+
+/// Writes response bodies into the download directory, within budget.
+#[derive(Debug)]
+pub struct DownloadManager {
+    dir: PathBuf,
+    per_file: u64,
+    total: u64,
+    tasks: Arc<TaskRegistry>,
+}
+
+impl DownloadManager {
+    /// Create a manager writing into `dir`, with bodies written by tasks
+    /// in the context scope of `tasks`.
+    pub fn new(dir: impl Into<PathBuf>, budget: DownloadBudget, tasks: Arc<TaskRegistry>) -> Self {
+        let dir = dir.into();
+        let mut total = budget.total.get() as u64;
+        if let Some(capacity) = tmpfs_capacity(&dir) {
+            total = total.min(capacity);
+        }
+        Self {
+            dir,
+            per_file: budget.per_file.get() as u64,
+            total,
+            tasks,
+        }
+    }
+
+    /// Get the download directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Start saving `response`, fetched from `url`, to a new file.
+    ///
+    /// Fails at once if the announced length is over budget or the file
+    /// cannot be created; later failures are reported as progress.
+    pub fn start(&self, url: &str, response: StreamingResponse) -> Result<Download, NetworkError> {
+        let total_bytes = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<u64>().ok());
+        let quota = Quota {
+            per_file: self.per_file,
+            total: self.total,
+            used: dir_usage(&self.dir).map_err(download_failed)?,
+        };
+        if let Some(len) = total_bytes {
+            quota.check(len)?;
+        }
+
+        let file_name = download_name(url, &response.headers);
+        let (path, file) = create_unique(&self.dir, &file_name).map_err(download_failed)?;
+        let (progress, receiver) = watch::channel(DownloadProgress {
+            received_bytes: 0,
+            total_bytes,
+            state: DownloadState::InProgress,
+        });
+        let partial = PartialFile {
+            path: path.clone(),
+            file: Some(file),
+            progress,
+            quota,
+            received: 0,
+            failure: None,
+        };
+
+        let cancel = TaskCancel::default();
+        self.tasks.spawn(
+            TaskScope::Context,
+            "download",
+            receive(response.body, partial, cancel.clone()),
+        );
+        log::debug!("Download started");
+        Ok(Download {
+            path,
+            progress: receiver,
+            cancel,
+        })
+    }
+}
+
+/// File name for a download from `url`: the Content-Disposition name if
+/// there is a usable one, else the last URL segment.
+///
+/// Any directory part is dropped and the rest reduced to a safe
+/// character set, so the name always stays inside the download directory.
+pub fn download_name(url: &str, headers: &[(String, String)]) -> String {
+    let disposition = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-disposition"))
+        .and_then(|(_, value)| disposition_file_name(value))
+        .map(|name| {
+            let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+            safe_file_name(base)
+        });
+
+    let mut name = match disposition {
+        Some(name) if !name.is_empty() => name,
+        _ => url_file_name(url),
+    };
+    if name.is_empty() {
+        name = DEFAULT_DOWNLOAD_NAME.to_string();
+    }
+    // Keep the end, where the extension is
+    let cut = name.len().saturating_sub(MAX_FILE_NAME);
+    name.split_off(cut)
+}
+
+/// The file name a Content-Disposition value carries, `filename*`
+/// (RFC 6266) preferred over `filename`. Not yet made safe.
+fn disposition_file_name(value: &str) -> Option<String> {
+    let mut plain = None;
+    for param in value.split(';').skip(1) {
+        let Some((name, raw)) = param.split_once('=') else {
+            continue;
+        };
+        let raw = raw.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            // charset'language'percent-encoded
+            "filename*" => {
+                if let Some(encoded) = raw.splitn(3, '\'').nth(2) {
+                    return Some(percent_decode(encoded));
+                }
+            }
+            "filename" => plain = Some(raw.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    plain
+}
+
+/// Decode `%XX` escapes, leaving malformed ones as they are.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Size of the tmpfs `dir` is on, or `None` if it is not on one.
+pub fn tmpfs_capacity(dir: &Path) -> Option<u64> {
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    mount_capacity(&mounts, &meminfo, dir)
+}
+
+/// Size of the tmpfs `dir` is on, from `/proc/mounts` and
+/// `/proc/meminfo` contents.
+fn mount_capacity(mounts: &str, meminfo: &str, dir: &Path) -> Option<u64> {
+    // The innermost mount containing `dir`; the last one if stacked
+    let (_, fstype, options) = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let point = Path::new(fields.nth(1)?);
+            let fstype = fields.next()?;
+            let options = fields.next()?;
+            dir.starts_with(point).then_some((point, fstype, options))
+        })
+        .max_by_key(|(point, _, _)| point.as_os_str().len())?;
+    if fstype != "tmpfs" {
+        return None;
+    }
+
+    let mem_total = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|rest| rest.trim().strip_suffix("kB"))
+        .and_then(|kib| kib.trim().parse::<u64>().ok())
+        .map(|kib| kib * 1024);
+    match options
+        .split(',')
+        .find_map(|option| option.strip_prefix("size="))
+    {
+        Some(size) => tmpfs_size(size, mem_total),
+        // A tmpfs without a size gets half of RAM
+        None => mem_total.map(|total| total / 2),
+    }
+}
+
+/// Parse a tmpfs `size=` option: bytes, a k/m/g suffix, or a percentage
+/// of RAM.
+fn tmpfs_size(size: &str, mem_total: Option<u64>) -> Option<u64> {
+    if let Some(percent) = size.strip_suffix('%') {
+        return Some(mem_total? / 100 * percent.parse::<u64>().ok()?);
+    }
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (digits, unit) = size.split_at(split);
+    let scale: u64 = match unit.to_ascii_lowercase().as_str() {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Bytes held by the files directly in `dir`; none if it does not exist.
+fn dir_usage(dir: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut used = 0;
+    for entry in entries {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            used += metadata.len();
+        }
+    }
+    Ok(used)
+}
+
+fn download_failed(e: io::Error) -> NetworkError {
+    NetworkError::RequestFailed(format!("Download failed: {}", e))
+}
+
+/// Limits one download is held to.
+#[derive(Debug, Clone, Copy)]
+struct Quota {
+    per_file: u64,
+    total: u64,
+    /// Directory usage when the download started
+    used: u64,
+}
+
+impl Quota {
+    /// Check that a file of `len` bytes fits.
+    fn check(&self, len: u64) -> Result<(), NetworkError> {
+        if len > self.per_file {
+            return Err(NetworkError::DownloadBudgetExceeded {
+                limit: self.per_file,
+                needed: len,
+            });
+        }
+        let needed = self.used.saturating_add(len);
+        if needed > self.total {
+            return Err(NetworkError::DownloadBudgetExceeded {
+                limit: self.total,
+                needed,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A download file being written. Dropped before `complete`, it wipes
+/// the file and only then reports the download failed or cancelled.
+struct PartialFile {
+    path: PathBuf,
+    file: Option<fs::File>,
+    progress: watch::Sender<DownloadProgress>,
+    quota: Quota,
+    received: u64,
+    failure: Option<String>,
+}
+
+impl PartialFile {
+    /// Append a piece of the body, within budget.
+    fn write(&mut self, chunk: &[u8]) -> Result<(), NetworkError> {
+        let received = self.received + chunk.len() as u64;
+        self.quota.check(received)?;
+        let file = self.file.as_mut().ok_or(NetworkError::Cancelled)?;
+        // A write to tmpfs is a memory copy; it does not block for long
+        file.write_all(chunk).map_err(download_failed)?;
+        self.received = received;
+        self.progress
+            .send_modify(|progress| progress.received_bytes = received);
+        Ok(())
+    }
+
+    /// Keep the file and report it complete.
+    fn complete(mut self) -> Result<(), NetworkError> {
+        if let Some(file) = self.file.take() {
+            file.sync_all().map_err(download_failed)?;
+        }
+        let path = self.path.clone();
+        self.progress
+            .send_modify(|progress| progress.state = DownloadState::Complete(path));
+        Ok(())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        // Completed
+        let Some(file) = self.file.take() else {
+            return;
+        };
+        drop(file);
+        if let Err(e) = forloop_config::secure_delete_file(&self.path) {
+            log::warn!("Could not wipe partial download: {}", e);
+        }
+        let state = match self.failure.take() {
+            Some(reason) => DownloadState::Failed(reason),
+            None => DownloadState::Cancelled,
+        };
+        self.progress.send_modify(|progress| progress.state = state);
+    }
+}
+
+/// Write `body` into `partial` until it ends, fails, or is cancelled.
+async fn receive(mut body: ResponseBody, mut partial: PartialFile, cancel: TaskCancel) {
+    loop {
+        let chunk = tokio::select! {
+            chunk = body.chunk() => chunk,
+            () = cancel.cancelled() => {
+                log::debug!("Download cancelled");
+                return;
+            }
+        };
+        let written = match chunk {
+            Ok(Some(chunk)) => partial.write(&chunk),
+            Ok(None) => {
+                if let Err(e) = partial.complete() {
+                    log::debug!("Download failed: {}", e);
+                }
+                return;
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            log::debug!("Download failed: {}", e);
+            partial.failure = Some(e.to_string());
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use tokio::sync::mpsc;
+
+    type BodySender = mpsc::Sender<Result<Option<Vec<u8>>, NetworkError>>;
+
+    /// A response whose body the test feeds piece by piece.
+    fn streamed(headers: &[(&str, &str)]) -> (StreamingResponse, BodySender) {
+        let (sender, chunks) = mpsc::channel(4);
+        let response = StreamingResponse {
+            status: 200,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            circuit_id: "1".to_string(),
+            body: ResponseBody::new(chunks),
+        };
+        (response, sender)
+    }
+
+    fn manager(dir: &Path, per_file: usize, total: usize) -> DownloadManager {
+        DownloadManager::new(
+            dir,
+            DownloadBudget {
+                per_file: ByteSize::bytes(per_file),
+                total: ByteSize::bytes(total),
+            },
+            Arc::new(TaskRegistry::new()),
+        )
+    }
+
+    fn disposition(value: &str) -> Vec<(String, String)> {
+        vec![("content-disposition".to_string(), value.to_string())]
+    }
+
+    #[test]
+    fn test_traversal_names_stay_in_directory() {
+        let url = "https://example.com/files/report.zip?x=1";
+        for (value, name) in [
+            (r#"attachment; filename="../../etc/passwd""#, "passwd"),
+            (r#"attachment; filename="..\..\boot.ini""#, "boot.ini"),
+            (r#"attachment; filename="/etc/shadow""#, "shadow"),
+            ("attachment; filename*=UTF-8''..%2F..%2F.bashrc", "bashrc"),
+            ("attachment; filename*=UTF-8''%2e%2e", "report.zip"),
+            (r#"attachment; filename="..""#, "report.zip"),
+            (r#"attachment; filename="a/""#, "report.zip"),
+            ("attachment; filename=\"evil\u{0}.sh\"", "evil_.sh"),
+            (
+                "inline; filename=\"plain.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf",
+                "r_sum_.pdf",
+            ),
+            ("attachment; filename=notes.txt", "notes.txt"),
+            ("attachment", "report.zip"),
+        ] {
+            assert_eq!(download_name(url, &disposition(value)), name, "{}", value);
+        }
+        assert_eq!(
+            download_name("https://example.com/", &[]),
+            DEFAULT_DOWNLOAD_NAME
+        );
+
+        let long = format!("attachment; filename=\"{}.tar.gz\"", "a".repeat(500));
+        let name = download_name(url, &disposition(&long));
+        assert_eq!(name.len(), MAX_FILE_NAME);
+        assert!(name.ends_with(".tar.gz"));
+    }
+
+    #[tokio::test]
+    async fn test_download_saved_under_safe_name() {
+        let dir = TempDir::new("download-manager-saved");
+        let manager = manager(dir.path(), 1024, 4096);
+
+        let (response, body) = streamed(&[
+            (
+                "content-disposition",
+                r#"attachment; filename="../../../tmp/x.sh""#,
+            ),
+            ("content-length", "11"),
+        ]);
+        let download = manager
+            .start("https://example.com/get", response)
+            .expect("started");
+        assert_eq!(download.path(), dir.path().join("x.sh"));
+
+        let mut progress = download.subscribe();
+        body.send(Ok(Some(b"hello ".to_vec()))).await.expect("send");
+        body.send(Ok(Some(b"world".to_vec()))).await.expect("send");
+        body.send(Ok(None)).await.expect("send");
+
+        let done = download.finished().await;
+        assert_eq!(done.state, DownloadState::Complete(dir.path().join("x.sh")));
+        assert_eq!((done.received_bytes, done.total_bytes), (11, Some(11)));
+        assert_eq!(*progress.borrow_and_update(), done);
+        assert_eq!(fs::read(download.path()).expect("saved"), b"hello world");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(download.path())
+                .expect("metadata")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Same name again gets its own file
+        let (response, body) = streamed(&[("content-disposition", "attachment; filename=x.sh")]);
+        let second = manager
+            .start("https://example.com/get", response)
+            .expect("started");
+        assert_eq!(second.path(), dir.path().join("x-1.sh"));
+        drop(body);
+        assert!(matches!(
+            second.finished().await.state,
+            DownloadState::Failed(_)
+        ));
+        assert!(!second.path().exists());
+        assert_eq!(fs::read(download.path()).expect("kept"), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn test_quota_exhaustion_wipes_partial_file() {
+        let dir = TempDir::new("download-manager-quota");
+        let manager = manager(dir.path(), 100, 150);
+
+        // Announced over the per-file limit: refused before any file exists
+        let (response, _body) = streamed(&[("content-length", "101")]);
+        assert!(matches!(
+            manager.start("https://example.com/big.iso", response),
+            Err(NetworkError::DownloadBudgetExceeded {
+                limit: 100,
+                needed: 101
+            })
+        ));
+        assert!(!dir.path().join("big.iso").exists());
+
+        // Unannounced, it fails once it crosses the limit
+        let (response, body) = streamed(&[]);
+        let download = manager
+            .start("https://example.com/big.iso", response)
+            .expect("started");
+        body.send(Ok(Some(vec![7; 60]))).await.expect("send");
+        body.send(Ok(Some(vec![7; 60]))).await.expect("send");
+        let done = download.finished().await;
+        assert!(matches!(done.state, DownloadState::Failed(_)), "{:?}", done);
+        assert_eq!(done.received_bytes, 60);
+        assert!(!download.path().exists());
+
+        // What is already in the directory counts against the total
+        fs::write(dir.path().join("earlier.bin"), vec![1; 100]).expect("earlier download");
+        let (response, _body) = streamed(&[("content-length", "51")]);
+        assert!(matches!(
+            manager.start("https://example.com/more.bin", response),
+            Err(NetworkError::DownloadBudgetExceeded {
+                limit: 150,
+                needed: 151
+            })
+        ));
+        let (response, body) = streamed(&[]);
+        let download = manager
+            .start("https://example.com/more.bin", response)
+            .expect("started");
+        body.send(Ok(Some(vec![7; 40]))).await.expect("send");
+        body.send(Ok(Some(vec![7; 40]))).await.expect("send");
+        assert!(matches!(
+            download.finished().await.state,
+            DownloadState::Failed(_)
+        ));
+        assert!(!download.path().exists());
+        assert_eq!(
+            fs::read(dir.path().join("earlier.bin"))
+                .expect("untouched")
+                .len(),
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_mid_download_wipes_partial_file() {
+        let dir = TempDir::new("download-manager-cancel");
+        let tasks = Arc::new(TaskRegistry::new());
+        let manager =
+            DownloadManager::new(dir.path(), DownloadBudget::default(), Arc::clone(&tasks));
+
+        let (response, body) = streamed(&[("content-length", "1000")]);
+        let download = manager
+            .start("https://example.com/a.bin", response)
+            .expect("started");
+        let mut progress = download.subscribe();
+        body.send(Ok(Some(vec![7; 400]))).await.expect("send");
+        progress
+            .wait_for(|progress| progress.received_bytes == 400)
+            .await
+            .expect("progress");
+        assert_eq!(fs::metadata(download.path()).expect("partial").len(), 400);
+
+        download.cancel();
+        assert_eq!(download.finished().await.state, DownloadState::Cancelled);
+        assert!(!download.path().exists());
+        // The server side sees the body dropped, which closes the circuit
+        body.closed().await;
+
+        // New Loop drops the task with its scope; the file goes the same way
+        let (response, body) = streamed(&[]);
+        let download = manager
+            .start("https://example.com/b.bin", response)
+            .expect("started");
+        let mut progress = download.subscribe();
+        body.send(Ok(Some(vec![7; 400]))).await.expect("send");
+        progress
+            .wait_for(|progress| progress.received_bytes == 400)
+            .await
+            .expect("progress");
+        tasks.cancel_scope(TaskScope::Context).await;
+        assert_eq!(download.finished().await.state, DownloadState::Cancelled);
+        assert!(!download.path().exists());
+        assert_eq!(dir_usage(dir.path()).expect("usage"), 0);
+    }
+
+    #[test]
+    fn test_tmpfs_capacity_from_mounts() {
+        let mounts = "\
+/dev/sda1 / ext4 rw,relatime 0 0
+tmpfs /dev/shm tmpfs rw,nosuid,nodev,size=65536k 0 0
+tmpfs /run tmpfs rw,nosuid,mode=755 0 0
+tmpfs /tmp tmpfs rw,size=25% 0 0
+";
+        let meminfo = "MemTotal:        8000000 kB\nMemFree:         100 kB\n";
+        let capacity = |dir: &str| mount_capacity(mounts, meminfo, Path::new(dir));
+
+        assert_eq!(capacity("/dev/shm/forloop-downloads"), Some(64 << 20));
+        assert_eq!(capacity("/run/user"), Some(8_000_000 * 1024 / 2));
+        assert_eq!(capacity("/tmp"), Some(8_000_000 * 1024 / 100 * 25));
+        assert_eq!(capacity("/home/user/Downloads"), None);
+        assert_eq!(capacity("/dev/shmem"), None);
+        assert_eq!(tmpfs_size("2g", None), Some(2 << 30));
+        assert_eq!(tmpfs_size("50%", None), None);
+        assert_eq!(tmpfs_size("12q", None), None);
+    }
+}
@@ -0,0 +1,362 @@
+//! PDF responses: always download, never render inline.
+//!
+//! navigator.pdfViewerEnabled is reported true so forloop blends in, but
+//! rendering PDFs would pull a large parser into the content process. A
+//! top-level PDF goes to the `Downloader`, which writes it under the
+//! RAM-backed download directory, and the UI says where it went. A PDF
+//! requested by `<embed>`, `<object>` or `<iframe>` is refused and counted.
+//!
+//! A response is a PDF if its Content-Type says so or, when mislabeled,
+//! if the `%PDF-` signature appears in its first 1024 bytes (the window
+//! PDF readers accept).
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::headers::Destination;
+use crate::policy::PolicyViolation;
+use crate::NetworkError;
+
+/// Signature that starts a PDF file.
+pub const PDF_SIGNATURE: &[u8] = b"%PDF-";
+
+/// How far into the body the signature is looked for.
+const SNIFF_WINDOW: usize = 1024;
+
+/// Whether a response is a PDF, by Content-Type or by signature.
+pub fn is_pdf(headers: &[(String, String)], body: &[u8]) -> bool {
+    let labeled = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-type")
+            && value.split(';').next().is_some_and(|mime| {
+                let mime = mime.trim();
+                mime.eq_ignore_ascii_case("application/pdf")
+                    || mime.eq_ignore_ascii_case("application/x-pdf")
+            })
+    });
+    let window = &body[..body.len().min(SNIFF_WINDOW)];
+    labeled
+        || window
+            .windows(PDF_SIGNATURE.len())
+            .any(|chunk| chunk == PDF_SIGNATURE)
+}
+
+/// File name a download is saved under, derived from its URL.
+///
+/// Only the last path segment is used, reduced to a safe character set.
+pub fn download_file_name(url: &str) -> String {
+    match url_file_name(url).as_str() {
+        "" => "download.pdf".to_string(),
+        name if name.to_ascii_lowercase().ends_with(".pdf") => name.to_string(),
+        name => format!("{}.pdf", name),
+    }
+}
+
+/// The last path segment of `url`, made safe; empty if there is none.
+pub(crate) fn url_file_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let segment = path
+        .split_once("://")
+        .map_or(path, |(_, rest)| rest)
+        .split('/')
+        .skip(1)
+        .last()
+        .unwrap_or_default();
+    safe_file_name(segment)
+}
+
+/// Reduce `name` to a safe character set with no leading dot, so it can
+/// neither leave the download directory nor hide in it. May be empty.
+pub(crate) fn safe_file_name(name: &str) -> String {
+    let safe: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    safe.trim_start_matches('.').to_string()
+}
+
+/// Writes downloads into one directory, never overwriting.
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    dir: PathBuf,
+}
+
+impl Downloader {
+    /// Create a downloader writing into `dir` (created on first save).
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Get the download directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Save `body` as `file_name`, adding a counter if the name is taken.
+    ///
+    /// The file is only readable by the current user.
+    pub fn save(&self, file_name: &str, body: &[u8]) -> io::Result<PathBuf> {
+        let (path, mut file) = create_unique(&self.dir, file_name)?;
+        file.write_all(body)?;
+        Ok(path)
+    }
+}
+
+/// Create `file_name` in `dir`, adding a counter if the name is taken.
+///
+/// `dir` is created if missing, readable only by the current user, and
+/// so is the file. Creation is exclusive (`O_EXCL`): an existing file,
+/// or a symlink planted in its place, is never opened.
+pub(crate) fn create_unique(dir: &Path, file_name: &str) -> io::Result<(PathBuf, fs::File)> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)?;
+
+    let (stem, extension) = file_name
+        .rsplit_once('.')
+        .map_or((file_name, ""), |(stem, ext)| (stem, ext));
+    for attempt in 0u32.. {
+        let name = match (attempt, extension) {
+            (0, _) => file_name.to_string(),
+            (n, "") => format!("{}-{}", stem, n),
+            (n, ext) => format!("{}-{}.{}", stem, n, ext),
+        };
+        let path = dir.join(name);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other("no free download name"))
+}
+
+/// Where a response body went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Routed {
+    /// Hand the body to the renderer (or the page's script)
+    Render(Vec<u8>),
+    /// The body was saved to this file instead
+    Downloaded(PathBuf),
+}
+
+/// What the PDF policy did, for the shield UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PdfStats {
+    /// Top-level PDFs saved instead of rendered
+    pub downloaded: usize,
+    /// PDFs requested by embed, object or iframe and refused
+    pub embeds_refused: usize,
+}
+
+/// Routes responses to the renderer or the downloader.
+#[derive(Debug)]
+pub struct ResponseRouter {
+    downloader: Downloader,
+    stats: Mutex<PdfStats>,
+}
+
+impl ResponseRouter {
+    /// Create a router saving downloads with `downloader`.
+    pub fn new(downloader: Downloader) -> Self {
+        Self {
+            downloader,
+            stats: Mutex::new(PdfStats::default()),
+        }
+    }
+
+    /// Decide where a response body goes.
+    ///
+    /// Non-PDF bodies, and PDFs fetched by script, images, fonts or
+    /// stylesheets, are passed through; none of those render a PDF.
+    pub fn route(
+        &self,
+        destination: Destination,
+        url: &str,
+        headers: &[(String, String)],
+        body: Vec<u8>,
+    ) -> Result<Routed, NetworkError> {
+        if !is_pdf(headers, &body) {
+            return Ok(Routed::Render(body));
+        }
+
+        match destination {
+            Destination::Document => {
+                let path = self
+                    .downloader
+                    .save(&download_file_name(url), &body)
+                    .map_err(|e| NetworkError::RequestFailed(format!("Download failed: {}", e)))?;
+                self.stats.lock().expect("stats lock").downloaded += 1;
+                log::debug!("PDF saved to the download directory instead of rendered");
+                Ok(Routed::Downloaded(path))
+            }
+            Destination::Embed | Destination::Iframe => {
+                self.stats.lock().expect("stats lock").embeds_refused += 1;
+                Err(
+                    PolicyViolation::BlockedByPolicy("PDFs are not displayed inline".to_string())
+                        .into(),
+                )
+            }
+            Destination::Image
+            | Destination::Xhr
+            | Destination::Font
+            | Destination::Script
+            | Destination::Style => Ok(Routed::Render(body)),
+        }
+    }
+
+    /// Get the counters so far.
+    pub fn stats(&self) -> PdfStats {
+        *self.stats.lock().expect("stats lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    const PDF: &[u8] = b"%PDF-1.7\n1 0 obj\n<< /Type /Catalog >>\nendobj\n%%EOF\n";
+
+    fn content_type(value: &str) -> Vec<(String, String)> {
+        vec![("Content-Type".to_string(), value.to_string())]
+    }
+
+    #[test]
+    fn test_top_level_pdf_is_downloaded() {
+        let dir = TempDir::new("downloads-top");
+        let router = ResponseRouter::new(Downloader::new(dir.path()));
+
+        let routed = router
+            .route(
+                Destination::Document,
+                "https://example.com/papers/report.pdf?download=1",
+                &content_type("application/pdf"),
+                PDF.to_vec(),
+            )
+            .expect("routed");
+        let Routed::Downloaded(path) = routed else {
+            panic!("PDF must not reach the renderer");
+        };
+        assert_eq!(path, dir.path().join("report.pdf"));
+        assert_eq!(fs::read(&path).expect("saved"), PDF);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Same name again does not overwrite
+        let Ok(Routed::Downloaded(second)) = router.route(
+            Destination::Document,
+            "https://example.com/report.pdf",
+            &content_type("application/pdf"),
+            PDF.to_vec(),
+        ) else {
+            panic!("expected a download");
+        };
+        assert_eq!(second, dir.path().join("report-1.pdf"));
+        assert_eq!(router.stats().downloaded, 2);
+    }
+
+    #[test]
+    fn test_embedded_pdf_is_refused() {
+        let dir = TempDir::new("downloads-embed");
+        let router = ResponseRouter::new(Downloader::new(dir.path()));
+
+        for destination in [Destination::Embed, Destination::Iframe] {
+            let result = router.route(
+                destination,
+                "https://example.com/brochure.pdf",
+                &content_type("application/pdf"),
+                PDF.to_vec(),
+            );
+            assert!(matches!(
+                result,
+                Err(NetworkError::PolicyViolation(
+                    PolicyViolation::BlockedByPolicy(_)
+                ))
+            ));
+        }
+        assert_eq!(
+            router.stats(),
+            PdfStats {
+                downloaded: 0,
+                embeds_refused: 2,
+            }
+        );
+        assert!(!dir.path().exists());
+
+        // Embedding anything else is untouched
+        let routed = router.route(
+            Destination::Embed,
+            "https://example.com/movie.svg",
+            &content_type("image/svg+xml"),
+            b"<svg/>".to_vec(),
+        );
+        assert_eq!(routed.expect("routed"), Routed::Render(b"<svg/>".to_vec()));
+    }
+
+    #[test]
+    fn test_mislabeled_pdf_caught_by_signature() {
+        let dir = TempDir::new("downloads-sniff");
+        let router = ResponseRouter::new(Downloader::new(dir.path()));
+
+        // Leading junk within the window still counts
+        let mut body = b"\n\n<!-- -->".to_vec();
+        body.extend_from_slice(PDF);
+        let routed = router
+            .route(
+                Destination::Document,
+                "https://example.com/view?id=3",
+                &content_type("text/html; charset=utf-8"),
+                body,
+            )
+            .expect("routed");
+        assert_eq!(routed, Routed::Downloaded(dir.path().join("view.pdf")));
+
+        // Past the window it is not a PDF
+        let mut late = vec![b' '; SNIFF_WINDOW];
+        late.extend_from_slice(PDF);
+        assert!(!is_pdf(&content_type("text/html"), &late));
+        assert!(is_pdf(&content_type("application/x-pdf"), b""));
+        assert!(!is_pdf(&content_type("text/html"), b"<html>%PD</html>"));
+    }
+
+    #[test]
+    fn test_download_file_names() {
+        for (url, name) in [
+            ("https://example.com/a/b/paper.pdf", "paper.pdf"),
+            ("https://example.com/a/Paper.PDF#page=2", "Paper.PDF"),
+            ("https://example.com/", "download.pdf"),
+            ("https://example.com", "download.pdf"),
+            ("https://example.com/..%2F..%2Fetc", "_2F.._2Fetc.pdf"),
+            ("https://example.com/.hidden", "hidden.pdf"),
+            ("https://example.com/r\u{e9}sum\u{e9}", "r_sum_.pdf"),
+        ] {
+            assert_eq!(download_file_name(url), name, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Typed events from the network layer to the browser core.
+//!
+//! `AnonymizedNetwork` publishes these on a broadcast channel. The
+//! browser core owns the single translation into UI messages, so this
+//! crate never depends on the UI crate.
+
+use crate::{
+    CircuitInfo, Destination, MetricsSnapshot, NetworkError, OnionPhase, TorHealthStatus,
+    UploadProgress,
+};
+
+/// Capacity of the event broadcast channel.
+///
+/// Slow subscribers lag and skip events rather than stalling requests.
+pub(crate) const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+/// How the finished request reached its destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionSecurity {
+    /// HTTPS through an exit
+    Https,
+    /// Onion service, never leaves Tor
+    Onion,
+}
+
+impl ConnectionSecurity {
+    /// Classify a request URL.
+    pub fn of_url(url: &str) -> Self {
+        let host = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or("");
+        let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+
+        if host.ends_with(".onion") {
+            ConnectionSecurity::Onion
+        } else {
+            ConnectionSecurity::Https
+        }
+    }
+}
+
+/// Coarse failure class, safe to show without leaking details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Tor itself is unavailable
+    Tor,
+    /// No circuit could be built
+    Circuit,
+    /// Request timed out
+    Timeout,
+    /// Onion service did not respond in time
+    OnionTimeout,
+    /// Onion service offline, unknown or refusing us
+    OnionService,
+    /// Something on the path asked for proxy credentials
+    Intercepted,
+    /// TLS handshake or certificate failure
+    Tls,
+    /// Name resolution failed
+    Dns,
+    /// URL or protocol refused before sending
+    Refused,
+    /// Request body over the configured cap
+    TooLarge,
+    /// Response body over the configured cap
+    ResponseTooLarge,
+    /// Cancelled by the user
+    Cancelled,
+    /// Anything else
+    Other,
+}
+
+impl ErrorClass {
+    /// Every error class.
+    pub const ALL: [ErrorClass; 13] = [
+        ErrorClass::Tor,
+        ErrorClass::Circuit,
+        ErrorClass::Timeout,
+        ErrorClass::OnionTimeout,
+        ErrorClass::OnionService,
+        ErrorClass::Intercepted,
+        ErrorClass::Tls,
+        ErrorClass::Dns,
+        ErrorClass::Refused,
+        ErrorClass::TooLarge,
+        ErrorClass::ResponseTooLarge,
+        ErrorClass::Cancelled,
+        ErrorClass::Other,
+    ];
+
+    /// Classify a network error.
+    pub fn of(error: &NetworkError) -> Self {
+        match error {
+            NetworkError::TorConnectionFailed(_)
+            | NetworkError::ControlRejected { .. }
+            | NetworkError::TorBinaryNotFound(_)
+            | NetworkError::TorPortInUse(_)
+            | NetworkError::InvalidTorConfig(_)
+            | NetworkError::TorStartupFailed(_)
+            | NetworkError::TransportNotFound { .. } => ErrorClass::Tor,
+            NetworkError::CircuitCreationFailed(_) | NetworkError::CircuitFailed(_) => {
+                ErrorClass::Circuit
+            }
+            NetworkError::Timeout => ErrorClass::Timeout,
+            NetworkError::OnionTimeout(_) => ErrorClass::OnionTimeout,
+            NetworkError::SocksFailure(code) if code.is_onion_service() => ErrorClass::OnionService,
+            NetworkError::ProxyAuthRequired => ErrorClass::Intercepted,
+            NetworkError::TlsError(_) => ErrorClass::Tls,
+            NetworkError::DnsError(_) => ErrorClass::Dns,
+            NetworkError::InvalidUrl(_)
+            | NetworkError::InvalidHeader(_)
+            | NetworkError::ProtocolNotSupported(_)
+            | NetworkError::PortBlocked(_)
+            | NetworkError::PolicyViolation(_)
+            | NetworkError::RedirectRefused(_) => ErrorClass::Refused,
+            NetworkError::RequestTooLarge { .. } => ErrorClass::TooLarge,
+            NetworkError::ResponseTooLarge { .. }
+            | NetworkError::HeadersTooLarge { .. }
+            | NetworkError::DownloadBudgetExceeded { .. } => ErrorClass::ResponseTooLarge,
+            NetworkError::Cancelled => ErrorClass::Cancelled,
+            NetworkError::RequestFailed(_)
+            | NetworkError::SocksFailure(_)
+            | NetworkError::Http2(_)
+            | NetworkError::InvalidResponse(_) => ErrorClass::Other,
+        }
+    }
+}
+
+/// Tor connection state as seen by the network layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorState {
+    /// Bootstrapping or reconnecting
+    Connecting,
+    /// Connected and healthy
+    Connected,
+    /// Connected but impaired, with a short reason
+    Degraded(String),
+    /// Unusable, with a short reason
+    Failed(String),
+}
+
+impl From<TorHealthStatus> for TorState {
+    fn from(status: TorHealthStatus) -> Self {
+        match status {
+            TorHealthStatus::Healthy => TorState::Connected,
+            TorHealthStatus::Degraded(reason) => TorState::Degraded(reason),
+        }
+    }
+}
+
+/// Step reached by `AnonymizedNetwork::new_identity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdentityPhase {
+    /// Cancelling requests and closing every circuit
+    ClosingCircuits,
+    /// NEWNYM sent or waiting for Tor's rate limit; no circuit built yet
+    BuildingCircuit,
+    /// A circuit built after the NEWNYM is ready
+    Ready,
+}
+
+impl IdentityPhase {
+    /// Status bar text for this step.
+    pub fn status_text(self) -> &'static str {
+        match self {
+            IdentityPhase::ClosingCircuits => "Closing circuits…",
+            IdentityPhase::BuildingCircuit => "Building new identity…",
+            IdentityPhase::Ready => "New identity ready",
+        }
+    }
+}
+
+/// Something the UI may want to know about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkEvent {
+    /// A request left for the network
+    RequestStarted {
+        /// Request context, unique per request on this network layer
+        context: u64,
+        /// What the request fetches
+        destination: Destination,
+    },
+    /// Request body upload progressed
+    Progress {
+        /// Request context
+        context: u64,
+        /// Bytes sent so far
+        progress: UploadProgress,
+    },
+    /// A response arrived
+    Completed {
+        /// Request context
+        context: u64,
+        /// HTTP status code
+        status: u16,
+        /// How the destination was reached
+        security: ConnectionSecurity,
+    },
+    /// A request failed
+    Failed {
+        /// Request context
+        context: u64,
+        /// Coarse failure class
+        error_class: ErrorClass,
+    },
+    /// An onion request reached a new connection step
+    OnionProgress {
+        /// Request context
+        context: u64,
+        /// Step reached
+        phase: OnionPhase,
+    },
+    /// A fresh circuit was built
+    CircuitBuilt {
+        /// Circuit summary for display
+        info: CircuitInfo,
+    },
+    /// Tor connection state changed
+    TorStateChanged(TorState),
+    /// The current page made so many connection attempts that it was
+    /// marked abusive; its further requests are refused
+    ExcessiveConnections,
+    /// New Loop's identity switch reached a new step
+    NewIdentity(IdentityPhase),
+    /// The session totals changed: a request ended or was refused, or
+    /// New Loop zeroed them
+    MetricsUpdated(MetricsSnapshot),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SocksReplyCode;
+
+    #[test]
+    fn test_connection_security() {
+        assert_eq!(
+            ConnectionSecurity::of_url("https://example.com/path"),
+            ConnectionSecurity::Https
+        );
+        assert_eq!(
+            ConnectionSecurity::of_url("https://abc.onion:443/x?q=.onion"),
+            ConnectionSecurity::Onion
+        );
+        assert_eq!(
+            ConnectionSecurity::of_url("https://example.com/?next=a.onion"),
+            ConnectionSecurity::Https
+        );
+    }
+
+    #[test]
+    fn test_error_class() {
+        assert_eq!(ErrorClass::of(&NetworkError::Timeout), ErrorClass::Timeout);
+        assert_eq!(
+            ErrorClass::of(&NetworkError::ProtocolNotSupported("http".to_string())),
+            ErrorClass::Refused
+        );
+        assert_eq!(
+            ErrorClass::of(&NetworkError::Cancelled),
+            ErrorClass::Cancelled
+        );
+        assert_eq!(
+            ErrorClass::of(&NetworkError::SocksFailure(
+                SocksReplyCode::OnionIntroductionFailed
+            )),
+            ErrorClass::OnionService
+        );
+        assert_eq!(
+            ErrorClass::of(&NetworkError::SocksFailure(
+                SocksReplyCode::CommandNotSupported
+            )),
+            ErrorClass::Other
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Frame tree of a page and the origin rules for embedded frames.
+//!
+//! Embedded iframes are the main vehicle for third-party fingerprinting.
+//! Every request a content process sends names the frame that issued it,
+//! and that frame's origin is what policy and per-origin identity
+//! derivation see. First-party isolation still keys circuits on the
+//! top-level origin, so a tracker embedded on two sites cannot link them.
+//!
+//! Cross-origin frames are always sandboxed as if the page had written
+//! `sandbox="allow-scripts"`: scripts run, but in an opaque origin with no
+//! forms, popups or top navigation. A page can restrict a frame further,
+//! never loosen it; `allow-same-origin` is never granted cross-origin.
+
+use crate::circuit::parse_url;
+use crate::policy::{PolicyViolation, ValidatedRequest};
+
+/// Identifies a frame within one page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FrameId(pub u64);
+
+impl FrameId {
+    /// The top-level document.
+    pub const TOP: Self = Self(0);
+}
+
+/// Origin of an https URL: `https://host[:port]`, lowercase, default port
+/// omitted.
+pub fn origin_of(url: &str) -> Option<String> {
+    let parsed = parse_url(url).ok()?;
+    let host = parsed.host.to_ascii_lowercase();
+    if host.is_empty() {
+        return None;
+    }
+    Some(match parsed.port {
+        443 => format!("https://{}", host),
+        port => format!("https://{}:{}", host, port),
+    })
+}
+
+/// One frame in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Frame identifier
+    pub id: FrameId,
+    /// Origin of the document loaded in the frame
+    pub origin: String,
+    /// Embedding frame (`None` for the top-level document)
+    pub parent: Option<FrameId>,
+}
+
+/// Sandbox keywords a frame is rendered with.
+///
+/// Passed to the renderer as the effective `sandbox` attribute.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameSandbox {
+    /// `allow-scripts`
+    pub allow_scripts: bool,
+    /// `allow-same-origin`
+    pub allow_same_origin: bool,
+    /// `allow-forms`
+    pub allow_forms: bool,
+    /// `allow-popups`
+    pub allow_popups: bool,
+    /// `allow-top-navigation`
+    pub allow_top_navigation: bool,
+}
+
+impl FrameSandbox {
+    /// Most a cross-origin frame ever gets: scripts, nothing else.
+    pub const CROSS_ORIGIN: Self = Self {
+        allow_scripts: true,
+        allow_same_origin: false,
+        allow_forms: false,
+        allow_popups: false,
+        allow_top_navigation: false,
+    };
+
+    /// Parse a page-supplied `sandbox` attribute. Unknown keywords are ignored.
+    pub fn parse(attribute: &str) -> Self {
+        let mut sandbox = Self::default();
+        for keyword in attribute.split_ascii_whitespace() {
+            match keyword.to_ascii_lowercase().as_str() {
+                "allow-scripts" => sandbox.allow_scripts = true,
+                "allow-same-origin" => sandbox.allow_same_origin = true,
+                "allow-forms" => sandbox.allow_forms = true,
+                "allow-popups" => sandbox.allow_popups = true,
+                "allow-top-navigation" => sandbox.allow_top_navigation = true,
+                _ => {}
+            }
+        }
+        sandbox
+    }
+
+    /// Keep only what both allow.
+    pub fn intersect(self, other: Self) -> Self {
+        Self {
+            allow_scripts: self.allow_scripts && other.allow_scripts,
+            allow_same_origin: self.allow_same_origin && other.allow_same_origin,
+            allow_forms: self.allow_forms && other.allow_forms,
+            allow_popups: self.allow_popups && other.allow_popups,
+            allow_top_navigation: self.allow_top_navigation && other.allow_top_navigation,
+        }
+    }
+
+    /// The `sandbox` attribute value (empty: everything denied).
+    pub fn attribute(&self) -> String {
+        [
+            (self.allow_scripts, "allow-scripts"),
+            (self.allow_same_origin, "allow-same-origin"),
+            (self.allow_forms, "allow-forms"),
+            (self.allow_popups, "allow-popups"),
+            (self.allow_top_navigation, "allow-top-navigation"),
+        ]
+        .iter()
+        .filter(|(granted, _)| *granted)
+        .map(|(_, keyword)| *keyword)
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+/// Origins a request is attributed to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestKeys {
+    /// Frame that issued the request
+    pub frame_id: FrameId,
+    /// That frame's origin: used for policy and identity derivation
+    pub frame_origin: String,
+    /// Top-level origin: the first-party isolation key for circuits
+    pub isolation_origin: String,
+}
+
+impl RequestKeys {
+    /// Whether the request was issued by a frame from another origin than
+    /// the top-level page.
+    pub fn is_third_party(&self) -> bool {
+        self.frame_origin != self.isolation_origin
+    }
+}
+
+/// The frames of one page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageContext {
+    /// Navigation context the page was loaded in
+    context_id: u64,
+    /// Frames in attach order; the top-level document is first
+    frames: Vec<Frame>,
+    next_id: u64,
+}
+
+impl PageContext {
+    /// Start a page for a top-level document at `url`, loaded by the
+    /// navigation `context_id` (`NavigationTarget::context_id`).
+    pub fn new(context_id: u64, url: &str) -> Result<Self, PolicyViolation> {
+        let origin = origin_of(url).ok_or_else(|| PolicyViolation::InvalidUrl(url.to_string()))?;
+        Ok(Self {
+            context_id,
+            frames: vec![Frame {
+                id: FrameId::TOP,
+                origin,
+                parent: None,
+            }],
+            next_id: 1,
+        })
+    }
+
+    /// Navigation context the page was loaded in.
+    pub fn context_id(&self) -> u64 {
+        self.context_id
+    }
+
+    /// Origin of the top-level document.
+    pub fn top_origin(&self) -> &str {
+        &self.frames[0].origin
+    }
+
+    /// Get a frame.
+    pub fn frame(&self, id: FrameId) -> Option<&Frame> {
+        self.frames.iter().find(|frame| frame.id == id)
+    }
+
+    /// Attach a frame loading `url` under `parent`.
+    pub fn attach_frame(&mut self, parent: FrameId, url: &str) -> Result<FrameId, PolicyViolation> {
+        if self.frame(parent).is_none() {
+            return Err(PolicyViolation::UnknownFrame(parent.0));
+        }
+        let origin = origin_of(url).ok_or_else(|| PolicyViolation::InvalidUrl(url.to_string()))?;
+
+        let id = FrameId(self.next_id);
+        self.next_id += 1;
+        self.frames.push(Frame {
+            id,
+            origin,
+            parent: Some(parent),
+        });
+        Ok(id)
+    }
+
+    /// Detach a frame and everything nested in it. The top level stays.
+    pub fn detach_frame(&mut self, id: FrameId) {
+        if id == FrameId::TOP {
+            return;
+        }
+        let mut removed = vec![id];
+        while let Some(current) = removed.pop() {
+            removed.extend(
+                self.frames
+                    .iter()
+                    .filter(|frame| frame.parent == Some(current))
+                    .map(|frame| frame.id),
+            );
+            self.frames.retain(|frame| frame.id != current);
+        }
+    }
+
+    /// Frames from `id` up to the top-level document.
+    fn ancestry(&self, id: FrameId) -> impl Iterator<Item = &Frame> {
+        std::iter::successors(self.frame(id), |frame| {
+            frame.parent.and_then(|parent| self.frame(parent))
+        })
+    }
+
+    /// Whether any frame between `id` and the top has a different origin.
+    ///
+    /// A same-origin frame nested inside a cross-origin one counts as
+    /// cross-origin: it inherits the outer frame's sandbox.
+    pub fn is_cross_origin(&self, id: FrameId) -> bool {
+        let Some(frame) = self.frame(id) else {
+            return true;
+        };
+        self.ancestry(id)
+            .any(|ancestor| ancestor.origin != frame.origin)
+    }
+
+    /// Effective sandbox for a frame, given the page's `sandbox` attribute.
+    ///
+    /// `None` means unsandboxed. Cross-origin frames never get more than
+    /// `FrameSandbox::CROSS_ORIGIN`, whatever the page asked for.
+    pub fn sandbox_for(&self, id: FrameId, declared: Option<&str>) -> Option<FrameSandbox> {
+        let declared = declared.map(FrameSandbox::parse);
+        if self.is_cross_origin(id) {
+            Some(declared.map_or(FrameSandbox::CROSS_ORIGIN, |sandbox| {
+                sandbox.intersect(FrameSandbox::CROSS_ORIGIN)
+            }))
+        } else {
+            declared
+        }
+    }
+
+    /// Attribute a validated request to its frame.
+    ///
+    /// Requests naming a frame that is not (or no longer) in this page
+    /// are rejected.
+    pub fn request_keys(&self, request: &ValidatedRequest) -> Result<RequestKeys, PolicyViolation> {
+        let frame_id = request.frame_id();
+        let frame = self
+            .frame(frame_id)
+            .ok_or(PolicyViolation::UnknownFrame(frame_id.0))?;
+
+        Ok(RequestKeys {
+            frame_id,
+            frame_origin: frame.origin.clone(),
+            isolation_origin: self.top_origin().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Destination;
+    use crate::policy::{validate_request, NetworkRequestMsg};
+
+    fn request_from(frame_id: FrameId, url: &str) -> ValidatedRequest {
+        validate_request(
+            NetworkRequestMsg {
+                method: "GET".to_string(),
+                url: url.to_string(),
+                headers: Vec::new(),
+                body: None,
+                frame_id,
+                destination: Destination::Document,
+            },
+            1024,
+        )
+        .expect("valid request")
+    }
+
+    /// news.example → ads.example → news.example → tracker.example
+    fn nested_page() -> (PageContext, [FrameId; 3]) {
+        let mut page = PageContext::new(1, "https://News.example/article").expect("valid URL");
+        let ads = page
+            .attach_frame(FrameId::TOP, "https://ads.example/slot")
+            .expect("attach");
+        let inner = page
+            .attach_frame(ads, "https://news.example/widget")
+            .expect("attach");
+        let tracker = page
+            .attach_frame(inner, "https://tracker.example:8443/px")
+            .expect("attach");
+        (page, [ads, inner, tracker])
+    }
+
+    #[test]
+    fn test_origin_of() {
+        assert_eq!(
+            origin_of("https://Example.com/a?b").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            origin_of("https://example.com:8443").as_deref(),
+            Some("https://example.com:8443")
+        );
+        assert_eq!(origin_of("http://example.com/"), None);
+    }
+
+    #[test]
+    fn test_nested_cross_origin_frames() {
+        let (page, [ads, inner, tracker]) = nested_page();
+
+        assert!(!page.is_cross_origin(FrameId::TOP));
+        assert!(page.is_cross_origin(ads));
+        // Same origin as the top, but embedded by a cross-origin frame
+        assert!(page.is_cross_origin(inner));
+        assert!(page.is_cross_origin(tracker));
+
+        assert_eq!(page.sandbox_for(FrameId::TOP, None), None);
+        for frame in [ads, inner, tracker] {
+            let sandbox = page.sandbox_for(frame, None).expect("sandboxed");
+            assert_eq!(sandbox.attribute(), "allow-scripts");
+        }
+    }
+
+    #[test]
+    fn test_page_cannot_grant_same_origin_cross_origin() {
+        let (mut page, [ads, ..]) = nested_page();
+        let declared = Some("allow-scripts allow-same-origin allow-forms allow-popups");
+
+        let sandbox = page.sandbox_for(ads, declared).expect("sandboxed");
+        assert!(!sandbox.allow_same_origin);
+        assert_eq!(sandbox.attribute(), "allow-scripts");
+
+        // Pages can still restrict further
+        let sandbox = page.sandbox_for(ads, Some("")).expect("sandboxed");
+        assert_eq!(sandbox.attribute(), "");
+
+        // A same-origin frame keeps what the page declared
+        let own = page
+            .attach_frame(FrameId::TOP, "https://news.example/comments")
+            .expect("attach");
+        assert!(!page.is_cross_origin(own));
+        assert_eq!(page.sandbox_for(own, None), None);
+        let sandbox = page.sandbox_for(own, declared).expect("declared");
+        assert!(sandbox.allow_same_origin && sandbox.allow_forms);
+    }
+
+    #[test]
+    fn test_request_keys_use_frame_origin() {
+        let (page, [_, _, tracker]) = nested_page();
+
+        let keys = page
+            .request_keys(&request_from(tracker, "https://cdn.example/x.js"))
+            .expect("known frame");
+        assert_eq!(keys.frame_origin, "https://tracker.example:8443");
+        assert_eq!(keys.isolation_origin, "https://news.example");
+        assert!(keys.is_third_party());
+
+        let keys = page
+            .request_keys(&request_from(FrameId::TOP, "https://cdn.example/y.js"))
+            .expect("known frame");
+        assert_eq!(keys.frame_origin, "https://news.example");
+        assert!(!keys.is_third_party());
+    }
+
+    #[test]
+    fn test_detached_frames_rejected() {
+        let (mut page, [ads, inner, tracker]) = nested_page();
+        page.detach_frame(ads);
+
+        for frame in [ads, inner, tracker] {
+            assert!(page.frame(frame).is_none());
+            assert_eq!(
+                page.request_keys(&request_from(frame, "https://cdn.example/")),
+                Err(PolicyViolation::UnknownFrame(frame.0))
+            );
+        }
+        assert!(page.attach_frame(ads, "https://ads.example/").is_err());
+
+        page.detach_frame(FrameId::TOP);
+        assert_eq!(page.top_origin(), "https://news.example");
+    }
+}
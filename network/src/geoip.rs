@@ -0,0 +1,93 @@
+//! Offline relay geolocation for the circuit display.
+//!
+//! Relay addresses are looked up in a table compiled into the binary, in
+//! the format of Tor's own geoip file. Nothing is ever asked of a lookup
+//! service: that would tell a third party which relays we are using.
+
+use std::net::Ipv4Addr;
+use std::sync::OnceLock;
+
+/// Country code shown for an address the table does not cover.
+pub const UNKNOWN_COUNTRY: &str = "??";
+
+/// The table shipped with the crate.
+const EMBEDDED_GEOIP: &str = include_str!("../assets/geoip");
+
+/// IPv4 ranges and their country codes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoIp {
+    ranges: Vec<(u32, u32, String)>,
+}
+
+impl GeoIp {
+    /// Parse a table of "INTIPLOW,INTIPHIGH,CC" lines. Comments, blank
+    /// lines and malformed lines are skipped.
+    pub fn parse(table: &str) -> Self {
+        let mut ranges: Vec<(u32, u32, String)> = table
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                if line.starts_with('#') {
+                    return None;
+                }
+                let mut fields = line.split(',');
+                let low = fields.next()?.parse().ok()?;
+                let high = fields.next()?.parse().ok()?;
+                let country = fields.next()?;
+                (low <= high && country.len() == 2).then(|| (low, high, country.to_string()))
+            })
+            .collect();
+        ranges.sort_unstable_by_key(|(low, _, _)| *low);
+        Self { ranges }
+    }
+
+    /// Get the table compiled into the binary.
+    pub fn embedded() -> &'static Self {
+        static TABLE: OnceLock<GeoIp> = OnceLock::new();
+        TABLE.get_or_init(|| Self::parse(EMBEDDED_GEOIP))
+    }
+
+    /// Number of ranges in the table.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Whether the table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Country code of `address`, if the table covers it.
+    pub fn country(&self, address: Ipv4Addr) -> Option<&str> {
+        let address = u32::from(address);
+        let next = self.ranges.partition_point(|(low, _, _)| *low <= address);
+        let (_, high, country) = self.ranges.get(next.checked_sub(1)?)?;
+        (address <= *high).then_some(country.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let table =
+            GeoIp::parse("# comment\n16777216,16777471,AU\n\n134744064,134744319,US\nbad\n");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.country(Ipv4Addr::new(1, 0, 0, 1)), Some("AU"));
+        assert_eq!(table.country(Ipv4Addr::new(8, 8, 8, 255)), Some("US"));
+        assert_eq!(table.country(Ipv4Addr::new(1, 0, 1, 0)), None);
+        assert_eq!(table.country(Ipv4Addr::new(0, 0, 0, 1)), None);
+        assert_eq!(table.country(Ipv4Addr::new(255, 0, 0, 0)), None);
+
+        // The shipped table covers the directory authorities
+        let embedded = GeoIp::embedded();
+        assert_eq!(embedded.country(Ipv4Addr::new(128, 31, 0, 39)), Some("US"));
+        assert_eq!(
+            embedded.country(Ipv4Addr::new(131, 188, 40, 189)),
+            Some("DE")
+        );
+        assert_eq!(embedded.country(Ipv4Addr::new(171, 25, 193, 9)), Some("SE"));
+    }
+}
@@ -5,39 +5,346 @@
 //! - Rotate per request where safe
 //! - Reveal no identifying information
 //! - Use a minimal header set
+//!
+//! Values that changed between Firefox releases (the Accept strings, down
+//! to where image/avif sits) live in `BrowserProfile`, pinned to the Tor
+//! Browser version we present as. Top-level navigations also depend on
+//! how they were started: `NavigationKind` selects Sec-Fetch-Site.
+//!
+//! The platform rotates per request unless the synthesizer is made for a
+//! `SyntheticIdentity`: then every request carries the User-Agent of the
+//! platform its navigator reports, since a page seeing Windows headers
+//! and a Linux navigator.platform has found a tell.
+//!
+//! What a request may not carry is listed here too: `DANGEROUS_HEADERS`,
+//! and the tracking parameters `validate_request` strips from every
+//! request URL, keeping the order of the rest.
 
+use forloop_config::NavigationKind;
+use forloop_fingerprint::SyntheticIdentity;
 use rand::seq::SliceRandom;
-use rand::Rng;
-
-/// Pre-defined User-Agent strings that match Tor Browser.
-/// These MUST be kept in sync with actual Tor Browser releases.
-const USER_AGENTS: &[&str] = &[
-    // Tor Browser 13.0 on Windows
-    "Mozilla/5.0 (Windows NT 10.0; rv:115.0) Gecko/20100101 Firefox/115.0",
-    // Tor Browser 13.0 on Linux
-    "Mozilla/5.0 (X11; Linux x86_64; rv:115.0) Gecko/20100101 Firefox/115.0",
-    // Tor Browser 13.0 on macOS
-    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:115.0) Gecko/20100101 Firefox/115.0",
-];
 
-/// Accept-Language values - kept generic and common.
-const ACCEPT_LANGUAGES: &[&str] = &[
-    "en-US,en;q=0.5",
+use crate::frames::origin_of;
+use crate::NetworkError;
+
+/// Platforms whose Tor Browser we present as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// Tor Browser on Windows
+    Windows,
+    /// Tor Browser on Linux
+    Linux,
+    /// Tor Browser on macOS
+    MacOs,
+}
+
+impl Platform {
+    /// All platforms, in rotation order.
+    pub const ALL: [Platform; 3] = [Platform::Windows, Platform::Linux, Platform::MacOs];
+
+    /// Platform whose navigator.platform `identity` reports.
+    ///
+    /// Anything unknown is Windows, as the navigator defense assumes.
+    pub fn for_identity(identity: &SyntheticIdentity) -> Self {
+        match identity.platform.as_str() {
+            "Linux x86_64" => Platform::Linux,
+            "MacIntel" => Platform::MacOs,
+            _ => Platform::Windows,
+        }
+    }
+
+    /// User-Agent sent by Tor Browser 13.0 (Firefox 115 ESR) on this platform.
+    ///
+    /// Firefox freezes the "rv:" token at 109.0 and Tor Browser always
+    /// reports 64-bit Windows. These MUST be kept in sync with actual Tor
+    /// Browser releases.
+    pub fn user_agent(self) -> &'static str {
+        match self {
+            Platform::Windows => {
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0"
+            }
+            Platform::Linux => {
+                "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0"
+            }
+            Platform::MacOs => {
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:109.0) Gecko/20100101 Firefox/115.0"
+            }
+        }
+    }
+}
+
+/// What a request is for, as Firefox tells the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// Top-level navigation typed by the user
+    Document,
+    /// Image subresource
+    Image,
+    /// fetch() / XMLHttpRequest
+    Xhr,
+    /// Web font
+    Font,
+    /// <embed> or <object> resource
+    Embed,
+    /// Classic <script> subresource
+    Script,
+    /// Stylesheet subresource
+    Style,
+    /// Document loaded into an <iframe>
+    Iframe,
+}
+
+impl Destination {
+    /// Every destination.
+    pub const ALL: [Destination; 8] = [
+        Destination::Document,
+        Destination::Image,
+        Destination::Xhr,
+        Destination::Font,
+        Destination::Embed,
+        Destination::Script,
+        Destination::Style,
+        Destination::Iframe,
+    ];
+
+    /// Sec-Fetch-Dest and Sec-Fetch-Mode values.
+    fn sec_fetch(self) -> [&'static str; 2] {
+        match self {
+            Destination::Document => ["document", "navigate"],
+            Destination::Image => ["image", "no-cors"],
+            Destination::Xhr => ["empty", "cors"],
+            Destination::Font => ["font", "cors"],
+            Destination::Embed => ["embed", "no-cors"],
+            Destination::Script => ["script", "no-cors"],
+            Destination::Style => ["style", "no-cors"],
+            Destination::Iframe => ["iframe", "navigate"],
+        }
+    }
+
+    /// Whether the request navigates a browsing context.
+    fn is_navigation(self) -> bool {
+        matches!(self, Destination::Document | Destination::Iframe)
+    }
+
+    /// Sec-Fetch-Site when nothing more is known about the request.
+    fn default_site(self) -> FetchSite {
+        match self {
+            // As if typed into the address bar
+            Destination::Document => FetchSite::None,
+            _ => FetchSite::SameOrigin,
+        }
+    }
+}
+
+/// Sec-Fetch-Site value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchSite {
+    /// Initiator has the request's origin
+    SameOrigin,
+    /// Initiator is on the same site (registrable domain)
+    SameSite,
+    /// Initiator is on another site
+    CrossSite,
+    /// Started by the user through the browser, not by a page
+    None,
+}
+
+impl FetchSite {
+    /// Header value.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FetchSite::SameOrigin => "same-origin",
+            FetchSite::SameSite => "same-site",
+            FetchSite::CrossSite => "cross-site",
+            FetchSite::None => "none",
+        }
+    }
+
+    /// Sec-Fetch-Site for a navigation of `kind` to `url`.
+    ///
+    /// `initiator` is the origin of the page a link was clicked in; it is
+    /// ignored for navigations the browser chrome started.
+    pub fn for_navigation(kind: NavigationKind, initiator: Option<&str>, url: &str) -> Self {
+        if kind.is_browser_initiated() {
+            return FetchSite::None;
+        }
+        match initiator {
+            Some(initiator) => Self::between(initiator, url),
+            None => FetchSite::CrossSite,
+        }
+    }
+
+    /// Sec-Fetch-Site for a request a page at origin `initiator` made to
+    /// `url`.
+    pub fn between(initiator: &str, url: &str) -> Self {
+        let Some(target) = origin_of(url) else {
+            return FetchSite::CrossSite;
+        };
+        if initiator.eq_ignore_ascii_case(&target) {
+            FetchSite::SameOrigin
+        } else if site_of(initiator) == site_of(&target) {
+            FetchSite::SameSite
+        } else {
+            FetchSite::CrossSite
+        }
+    }
+}
+
+/// Public suffixes of two labels, under which a site has three.
+///
+/// The common ones from the Public Suffix List, private registries such as
+/// `github.io` included. Kept sorted.
+const MULTI_LABEL_SUFFIXES: &[&str] = &[
+    "ac.jp",
+    "ac.uk",
+    "appspot.com",
+    "azurewebsites.net",
+    "blogspot.com",
+    "cloudfront.net",
+    "co.id",
+    "co.il",
+    "co.in",
+    "co.jp",
+    "co.kr",
+    "co.nz",
+    "co.uk",
+    "co.za",
+    "com.ar",
+    "com.au",
+    "com.br",
+    "com.cn",
+    "com.hk",
+    "com.mx",
+    "com.my",
+    "com.sg",
+    "com.tr",
+    "com.tw",
+    "com.ua",
+    "edu.au",
+    "github.io",
+    "gitlab.io",
+    "go.jp",
+    "gov.au",
+    "gov.cn",
+    "gov.uk",
+    "herokuapp.com",
+    "me.uk",
+    "ne.jp",
+    "net.au",
+    "net.br",
+    "net.cn",
+    "net.in",
+    "netlify.app",
+    "or.jp",
+    "or.kr",
+    "org.au",
+    "org.br",
+    "org.cn",
+    "org.in",
+    "org.nz",
+    "org.uk",
+    "pages.dev",
+    "vercel.app",
+    "workers.dev",
 ];
 
-/// Accept header for HTML pages.
-const ACCEPT_HTML: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+/// Site of an origin: its registrable domain (eTLD+1).
+///
+/// That is the host's last two labels, or three under one of the
+/// `MULTI_LABEL_SUFFIXES`. A suffix missing from that list only ever
+/// turns cross-site into same-site. Onion addresses come out right
+/// (`sub.xyz.onion` is `xyz.onion`).
+fn site_of(origin: &str) -> String {
+    let authority = origin.trim_start_matches("https://");
+    let host = match authority.split_once(']') {
+        Some((v6, _)) => return format!("{}]", v6),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    let host = host.to_ascii_lowercase();
+    if host.parse::<std::net::Ipv4Addr>().is_ok() {
+        return host;
+    }
+    let labels: Vec<&str> = host.rsplitn(4, '.').collect();
+    match labels.as_slice() {
+        [tld, second, ..] if is_multi_label_suffix(second, tld) => match labels.get(2) {
+            Some(domain) => format!("{}.{}.{}", domain, second, tld),
+            None => host,
+        },
+        [tld, domain, ..] => format!("{}.{}", domain, tld),
+        _ => host,
+    }
+}
+
+/// Whether `second.tld` is one of the `MULTI_LABEL_SUFFIXES`.
+fn is_multi_label_suffix(second: &str, tld: &str) -> bool {
+    MULTI_LABEL_SUFFIXES
+        .binary_search(&format!("{}.{}", second, tld).as_str())
+        .is_ok()
+}
+
+/// Request header values that differ between pinned browser releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrowserProfile {
+    /// Tor Browser release the values were captured from
+    pub tor_browser: &'static str,
+    /// Firefox ESR major version it is built on
+    pub firefox: u32,
+    /// Accept for documents
+    pub accept_document: &'static str,
+    /// Accept for images
+    pub accept_image: &'static str,
+    /// Accept for fonts
+    pub accept_font: &'static str,
+    /// Accept for stylesheets
+    pub accept_style: &'static str,
+    /// Accept for fetch(), XMLHttpRequest, scripts and plugins
+    pub accept_any: &'static str,
+}
+
+impl BrowserProfile {
+    /// Tor Browser 13.0 (Firefox 115 ESR).
+    pub const TOR_BROWSER_13: BrowserProfile = BrowserProfile {
+        tor_browser: "13.0",
+        firefox: 115,
+        accept_document:
+            "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
+        accept_image: "image/avif,image/webp,*/*",
+        accept_font: "application/font-woff2;q=1.0,application/font-woff;q=0.9,*/*;q=0.8",
+        accept_style: "text/css,*/*;q=0.1",
+        accept_any: "*/*",
+    };
+
+    /// The release we present as. Move together with the User-Agent
+    /// strings and the header fixtures.
+    pub const PINNED: BrowserProfile = BrowserProfile::TOR_BROWSER_13;
 
-/// Accept header for images.
-const ACCEPT_IMAGE: &str = "image/avif,image/webp,*/*";
+    /// Accept header for `destination`.
+    ///
+    /// Firefox sends the same document Accept whatever started the
+    /// navigation, reloads and frames included.
+    pub fn accept(&self, destination: Destination) -> &'static str {
+        match destination {
+            Destination::Document | Destination::Iframe => self.accept_document,
+            Destination::Image => self.accept_image,
+            Destination::Xhr | Destination::Embed | Destination::Script => self.accept_any,
+            Destination::Font => self.accept_font,
+            Destination::Style => self.accept_style,
+        }
+    }
+}
+
+/// Accept-Language values - kept generic and common.
+pub const ACCEPT_LANGUAGES: &[&str] = &["en-US,en;q=0.5"];
 
 /// Accept-Encoding header.
-const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
 
 /// Synthesizes HTTP headers for anonymized requests.
 pub struct HeaderSynthesizer {
     /// Random number generator
     rng: std::sync::Mutex<rand::rngs::ThreadRng>,
+    /// Platform of the identity requests are made for, if any
+    platform: Option<Platform>,
 }
 
 impl HeaderSynthesizer {
@@ -45,57 +352,148 @@ impl HeaderSynthesizer {
     pub fn new() -> Self {
         Self {
             rng: std::sync::Mutex::new(rand::thread_rng()),
+            platform: None,
         }
     }
 
-    /// Generate a complete set of synthetic headers for a request.
+    /// Create a header synthesizer for `identity`, sending the User-Agent
+    /// of its platform with every request.
+    pub fn for_identity(identity: &SyntheticIdentity) -> Self {
+        Self {
+            platform: Some(Platform::for_identity(identity)),
+            ..Self::new()
+        }
+    }
+
+    /// Get the platform every request is made as, if it is pinned.
+    pub fn platform(&self) -> Option<Platform> {
+        self.platform
+    }
+
+    /// Generate a complete set of synthetic headers for a navigation.
     pub fn generate(&self) -> SyntheticHeaders {
-        let mut rng = self.rng.lock().expect("RNG lock poisoned");
+        self.generate_for(Destination::Document)
+    }
+
+    /// Generate headers for an image request.
+    pub fn generate_for_image(&self) -> SyntheticHeaders {
+        self.generate_for(Destination::Image)
+    }
 
-        // Select User-Agent (rotated per request)
-        let user_agent = USER_AGENTS
-            .choose(&mut *rng)
-            .expect("USER_AGENTS is non-empty")
-            .to_string();
+    /// Generate headers for a request to the given destination.
+    pub fn generate_for(&self, destination: Destination) -> SyntheticHeaders {
+        // Select platform (rotated per request unless pinned)
+        let platform = self.platform.unwrap_or_else(|| {
+            let mut rng = self.rng.lock().expect("RNG lock poisoned");
+            *Platform::ALL
+                .choose(&mut *rng)
+                .expect("Platform::ALL is non-empty")
+        });
 
-        // Accept-Language is fixed (variation would fingerprint)
-        let accept_language = ACCEPT_LANGUAGES[0].to_string();
+        Self::generate_for_platform(platform, destination)
+    }
 
+    /// Generate headers for a request to `destination` that a page at
+    /// origin `initiator` made to `url`.
+    ///
+    /// Nothing a page starts counts as user-initiated, so a frame or
+    /// document it navigates gets no Sec-Fetch-User.
+    pub fn generate_for_request(
+        &self,
+        destination: Destination,
+        initiator: &str,
+        url: &str,
+    ) -> SyntheticHeaders {
         SyntheticHeaders {
-            user_agent,
-            accept: ACCEPT_HTML.to_string(),
-            accept_language,
-            accept_encoding: ACCEPT_ENCODING.to_string(),
+            fetch_site: FetchSite::between(initiator, url),
+            user_initiated: false,
+            ..self.generate_for(destination)
         }
     }
 
-    /// Generate headers for an image request.
-    pub fn generate_for_image(&self) -> SyntheticHeaders {
-        let mut headers = self.generate();
-        headers.accept = ACCEPT_IMAGE.to_string();
-        headers
+    /// Generate headers for a top-level navigation of `kind` to `url`.
+    ///
+    /// `initiator` is the origin of the page a clicked link was in. Every
+    /// kind is started by the user, so Sec-Fetch-User is sent.
+    pub fn generate_navigation(
+        &self,
+        kind: NavigationKind,
+        initiator: Option<&str>,
+        url: &str,
+    ) -> SyntheticHeaders {
+        SyntheticHeaders {
+            fetch_site: FetchSite::for_navigation(kind, initiator, url),
+            ..self.generate()
+        }
+    }
+
+    /// Generate navigation headers for a fixed platform.
+    pub fn navigation_for_platform(
+        platform: Platform,
+        kind: NavigationKind,
+        initiator: Option<&str>,
+        url: &str,
+    ) -> SyntheticHeaders {
+        SyntheticHeaders {
+            fetch_site: FetchSite::for_navigation(kind, initiator, url),
+            ..Self::generate_for_platform(platform, Destination::Document)
+        }
+    }
+
+    /// Generate headers for a fixed platform and destination.
+    pub fn generate_for_platform(platform: Platform, destination: Destination) -> SyntheticHeaders {
+        SyntheticHeaders {
+            user_agent: platform.user_agent().to_string(),
+            accept: BrowserProfile::PINNED.accept(destination).to_string(),
+            // Accept-Language is fixed (variation would fingerprint)
+            accept_language: ACCEPT_LANGUAGES[0].to_string(),
+            accept_encoding: ACCEPT_ENCODING.to_string(),
+            destination,
+            fetch_site: destination.default_site(),
+            // As if typed into the address bar
+            user_initiated: destination == Destination::Document,
+        }
     }
 
     /// Convert synthetic headers to a list of (name, value) pairs.
+    ///
+    /// Tor Browser 13.0 is Firefox 115 ESR, which does not send the
+    /// Priority header yet; add it here when the pinned version moves.
     pub fn to_header_list(headers: &SyntheticHeaders) -> Vec<(String, String)> {
-        vec![
+        let [dest, mode] = headers.destination.sec_fetch();
+
+        let mut list = vec![
             ("User-Agent".to_string(), headers.user_agent.clone()),
             ("Accept".to_string(), headers.accept.clone()),
-            ("Accept-Language".to_string(), headers.accept_language.clone()),
-            ("Accept-Encoding".to_string(), headers.accept_encoding.clone()),
+            (
+                "Accept-Language".to_string(),
+                headers.accept_language.clone(),
+            ),
+            (
+                "Accept-Encoding".to_string(),
+                headers.accept_encoding.clone(),
+            ),
             ("Connection".to_string(), "keep-alive".to_string()),
-            ("Upgrade-Insecure-Requests".to_string(), "1".to_string()),
-            ("Sec-Fetch-Dest".to_string(), "document".to_string()),
-            ("Sec-Fetch-Mode".to_string(), "navigate".to_string()),
-            ("Sec-Fetch-Site".to_string(), "none".to_string()),
-            ("Sec-Fetch-User".to_string(), "?1".to_string()),
-            // Explicitly NOT sending:
-            // - Referer (tracking)
-            // - Cookie (tracking)
-            // - DNT (ironically identifies privacy users)
-            // - X-Forwarded-For (internal only)
-            // - Any custom headers
-        ]
+        ];
+        if headers.destination.is_navigation() {
+            list.push(("Upgrade-Insecure-Requests".to_string(), "1".to_string()));
+        }
+        list.push(("Sec-Fetch-Dest".to_string(), dest.to_string()));
+        list.push(("Sec-Fetch-Mode".to_string(), mode.to_string()));
+        list.push((
+            "Sec-Fetch-Site".to_string(),
+            headers.fetch_site.as_str().to_string(),
+        ));
+        if headers.destination.is_navigation() && headers.user_initiated {
+            list.push(("Sec-Fetch-User".to_string(), "?1".to_string()));
+        }
+        // Explicitly NOT sending:
+        // - Referer (tracking)
+        // - Cookie (tracking)
+        // - DNT (ironically identifies privacy users)
+        // - X-Forwarded-For (internal only)
+        // - Any custom headers
+        list
     }
 }
 
@@ -116,6 +514,12 @@ pub struct SyntheticHeaders {
     pub accept_language: String,
     /// Accept-Encoding header
     pub accept_encoding: String,
+    /// What the request is for (selects the Sec-Fetch-* values)
+    pub destination: Destination,
+    /// Sec-Fetch-Site value
+    pub fetch_site: FetchSite,
+    /// The user started the navigation (sends Sec-Fetch-User)
+    pub user_initiated: bool,
 }
 
 impl SyntheticHeaders {
@@ -125,34 +529,144 @@ impl SyntheticHeaders {
     }
 }
 
+/// Headers that identify or track the user (lowercase).
+pub const DANGEROUS_HEADERS: &[&str] = &[
+    "cookie",
+    "authorization",
+    "proxy-authorization",
+    "x-forwarded-for",
+    "x-real-ip",
+    "x-client-ip",
+    "forwarded",
+    "via",
+    "x-request-id",
+    "x-correlation-id",
+    "dnt",
+    "referer",
+    "origin", // Except for CORS, but we don't do cross-origin
+];
+
 /// Strips dangerous headers from outgoing requests.
 /// Used as a last line of defense.
 pub fn strip_dangerous_headers(headers: &mut Vec<(String, String)>) {
-    let dangerous = [
-        "cookie",
-        "authorization",
-        "proxy-authorization",
-        "x-forwarded-for",
-        "x-real-ip",
-        "x-client-ip",
-        "forwarded",
-        "via",
-        "x-request-id",
-        "x-correlation-id",
-        "dnt",
-        "referer",
-        "origin", // Except for CORS, but we don't do cross-origin
-    ];
-
     headers.retain(|(name, _)| {
         let lower = name.to_lowercase();
-        !dangerous.contains(&lower.as_str())
+        !DANGEROUS_HEADERS.contains(&lower.as_str())
     });
 }
 
+/// Query parameters that identify a click, dropped from request URLs.
+///
+/// Click identifiers and campaign tags added by mail and ad platforms tie
+/// a visit to a person or a message, and never change what the server
+/// returns. Names are matched case-insensitively.
+pub const TRACKING_PARAMS: &[&str] = &[
+    "fbclid",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "igshid",
+    "li_fat_id",
+    "mc_cid",
+    "mc_eid",
+    "mkt_tok",
+    "oly_anon_id",
+    "oly_enc_id",
+    "vero_id",
+    "vero_conv",
+    "rb_clickid",
+    "s_cid",
+    "ef_id",
+    "_openstat",
+    "wickedid",
+];
+
+/// Prefixes of tracking parameter families (Google Analytics, HubSpot,
+/// Matomo).
+pub const TRACKING_PARAM_PREFIXES: &[&str] = &["utm_", "_hs", "__hs", "mtm_", "pk_"];
+
+/// Whether `name` is a tracking parameter.
+pub fn is_tracking_param(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    TRACKING_PARAMS.contains(&name.as_str())
+        || TRACKING_PARAM_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+/// Remove tracking parameters from `url`'s query, returning the cleaned
+/// URL and how many parameters were removed.
+pub fn strip_tracking_params(url: &str) -> (String, usize) {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let Some((base, query)) = url.split_once('?') else {
+        return (rejoin(url, fragment), 0);
+    };
+
+    let mut removed = 0;
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or_default();
+            let tracking = is_tracking_param(name);
+            removed += usize::from(tracking);
+            !tracking
+        })
+        .collect();
+
+    let cleaned = if removed == 0 {
+        url.to_string()
+    } else if kept.iter().all(|pair| pair.is_empty()) {
+        base.to_string()
+    } else {
+        format!("{}?{}", base, kept.join("&"))
+    };
+    (rejoin(&cleaned, fragment), removed)
+}
+
+fn rejoin(url: &str, fragment: Option<&str>) -> String {
+    match fragment {
+        Some(fragment) => format!("{}#{}", url, fragment),
+        None => url.to_string(),
+    }
+}
+
+/// Check that a request header can be written to the wire as is.
+///
+/// The name must be an RFC 7230 token and the value may hold no control
+/// characters but tab, so neither can end the header line early and
+/// smuggle in another header or request.
+pub fn check_header(name: &str, value: &str) -> Result<(), NetworkError> {
+    if !is_token(name) {
+        return Err(NetworkError::InvalidHeader(format!("bad name {:?}", name)));
+    }
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        return Err(NetworkError::InvalidHeader(format!(
+            "control character in {} value",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `s` is an RFC 7230 token, as header names and methods must be.
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
 /// Normalizes header order to match Tor Browser.
 /// Header order can be used for fingerprinting.
-pub fn normalize_header_order(headers: &mut Vec<(String, String)>) {
+pub fn normalize_header_order(headers: &mut [(String, String)]) {
     // Tor Browser/Firefox header order
     let order = [
         "host",
@@ -174,8 +688,14 @@ pub fn normalize_header_order(headers: &mut Vec<(String, String)>) {
         let a_lower = a.to_lowercase();
         let b_lower = b.to_lowercase();
 
-        let a_pos = order.iter().position(|&x| x == a_lower).unwrap_or(usize::MAX);
-        let b_pos = order.iter().position(|&x| x == b_lower).unwrap_or(usize::MAX);
+        let a_pos = order
+            .iter()
+            .position(|&x| x == a_lower)
+            .unwrap_or(usize::MAX);
+        let b_pos = order
+            .iter()
+            .position(|&x| x == b_lower)
+            .unwrap_or(usize::MAX);
 
         a_pos.cmp(&b_pos)
     });
@@ -213,6 +733,275 @@ mod tests {
         assert!(!headers.iter().any(|(n, _)| n.to_lowercase() == "referer"));
     }
 
+    /// Read a captured header dump from `fixtures/headers`.
+    fn load_fixture(name: &str) -> Vec<(String, String)> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/headers")
+            .join(name);
+        std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (name, value) = line.split_once(": ").expect("Name: value line");
+                (name.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    /// Describe every difference between two header lists, line by line.
+    fn diff_headers(expected: &[(String, String)], actual: &[(String, String)]) -> Vec<String> {
+        let show = |header: Option<&(String, String)>| {
+            header.map_or("<missing>".to_string(), |(n, v)| format!("{}: {}", n, v))
+        };
+
+        (0..expected.len().max(actual.len()))
+            .filter(|&i| expected.get(i) != actual.get(i))
+            .map(|i| {
+                format!(
+                    "  line {}:\n    expected {}\n    actual   {}",
+                    i + 1,
+                    show(expected.get(i)),
+                    show(actual.get(i))
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_headers_match_captured_fixtures() {
+        let platforms = [
+            (Platform::Windows, "windows"),
+            (Platform::Linux, "linux"),
+            (Platform::MacOs, "macos"),
+        ];
+        let destinations = [
+            (Destination::Document, "document"),
+            (Destination::Image, "image"),
+            (Destination::Xhr, "xhr"),
+            (Destination::Font, "font"),
+            (Destination::Script, "script"),
+            (Destination::Style, "style"),
+            (Destination::Iframe, "iframe"),
+        ];
+
+        let mut failures = Vec::new();
+        for (platform, platform_name) in platforms {
+            for (destination, destination_name) in destinations {
+                let fixture = format!("{}-{}.txt", platform_name, destination_name);
+
+                // The transport adds Host; ordering must put it first
+                let mut actual =
+                    HeaderSynthesizer::generate_for_platform(platform, destination).to_vec();
+                actual.push(("Host".to_string(), "example.com".to_string()));
+                normalize_header_order(&mut actual);
+
+                let diff = diff_headers(&load_fixture(&fixture), &actual);
+                if !diff.is_empty() {
+                    failures.push(format!("{}\n{}", fixture, diff.join("\n")));
+                }
+            }
+        }
+
+        assert!(
+            failures.is_empty(),
+            "Headers differ from fixtures:\n{}",
+            failures.join("\n")
+        );
+    }
+
+    #[test]
+    fn test_navigation_headers_match_fixtures() {
+        let kinds = [
+            (NavigationKind::AddressBar, None, "address-bar"),
+            (NavigationKind::Reload, None, "reload"),
+            (NavigationKind::HistoryTraversal, None, "history"),
+            // A browser-initiated navigation ignores any page it came from
+            (
+                NavigationKind::Reload,
+                Some("https://example.org"),
+                "reload",
+            ),
+            (
+                NavigationKind::LinkClick,
+                Some("https://example.com"),
+                "link-same-origin",
+            ),
+            (
+                NavigationKind::LinkClick,
+                Some("https://www.example.com"),
+                "link-same-site",
+            ),
+            (
+                NavigationKind::LinkClick,
+                Some("https://example.org"),
+                "link-cross-site",
+            ),
+        ];
+
+        for (kind, initiator, name) in kinds {
+            let mut actual = HeaderSynthesizer::navigation_for_platform(
+                Platform::Windows,
+                kind,
+                initiator,
+                "https://example.com/",
+            )
+            .to_vec();
+            actual.push(("Host".to_string(), "example.com".to_string()));
+            normalize_header_order(&mut actual);
+
+            let fixture = format!("windows-navigation-{}.txt", name);
+            let diff = diff_headers(&load_fixture(&fixture), &actual);
+            assert!(diff.is_empty(), "{}\n{}", fixture, diff.join("\n"));
+        }
+    }
+
+    #[test]
+    fn test_fetch_site_for_links() {
+        let site = |initiator: &str, url: &str| {
+            FetchSite::for_navigation(NavigationKind::LinkClick, Some(initiator), url)
+        };
+
+        assert_eq!(
+            site("https://example.com:8443", "https://example.com/"),
+            FetchSite::SameSite
+        );
+        assert_eq!(
+            site("https://a.xyz.onion", "https://b.xyz.onion/"),
+            FetchSite::SameSite
+        );
+        assert_eq!(
+            site("https://10.0.0.1", "https://10.0.0.2/"),
+            FetchSite::CrossSite
+        );
+        assert_eq!(
+            FetchSite::for_navigation(NavigationKind::LinkClick, None, "https://example.com/"),
+            FetchSite::CrossSite
+        );
+        assert_eq!(
+            BrowserProfile::PINNED.accept(Destination::Document),
+            HeaderSynthesizer::new()
+                .generate_navigation(NavigationKind::Reload, None, "https://example.com/")
+                .accept
+        );
+    }
+
+    #[test]
+    fn test_fetch_site_for_subresources() {
+        let synth = HeaderSynthesizer::new();
+        let site = |initiator: &str, url: &str| {
+            synth
+                .generate_for_request(Destination::Script, initiator, url)
+                .fetch_site
+        };
+
+        assert_eq!(
+            site("https://example.com", "https://example.com/app.js"),
+            FetchSite::SameOrigin
+        );
+        assert_eq!(
+            site("https://www.example.com", "https://cdn.example.com/app.js"),
+            FetchSite::SameSite
+        );
+        assert_eq!(
+            site("https://example.com", "https://example.org/app.js"),
+            FetchSite::CrossSite
+        );
+        // Registrable domains under multi-label public suffixes
+        assert_eq!(
+            site("https://news.bbc.co.uk", "https://static.bbc.co.uk/app.js"),
+            FetchSite::SameSite
+        );
+        assert_eq!(
+            site("https://bbc.co.uk", "https://itv.co.uk/app.js"),
+            FetchSite::CrossSite
+        );
+        assert_eq!(
+            site("https://alice.github.io", "https://bob.github.io/app.js"),
+            FetchSite::CrossSite
+        );
+        assert_eq!(site_of("https://co.uk"), "co.uk");
+        assert!(MULTI_LABEL_SUFFIXES
+            .windows(2)
+            .all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_sec_fetch_user_only_for_user_navigations() {
+        let synth = HeaderSynthesizer::new();
+        let has = |headers: &SyntheticHeaders, name: &str, value: &str| {
+            headers
+                .to_vec()
+                .contains(&(name.to_string(), value.to_string()))
+        };
+
+        let typed =
+            synth.generate_navigation(NavigationKind::AddressBar, None, "https://example.com/");
+        assert!(has(&typed, "Sec-Fetch-Site", "none"));
+        assert!(has(&typed, "Sec-Fetch-User", "?1"));
+
+        // A page navigating itself or a frame is not the user
+        for destination in [Destination::Document, Destination::Iframe] {
+            let headers = synth.generate_for_request(
+                destination,
+                "https://example.com",
+                "https://www.example.com/next",
+            );
+            assert!(has(&headers, "Sec-Fetch-Site", "same-site"));
+            assert!(has(&headers, "Upgrade-Insecure-Requests", "1"));
+            assert!(!headers
+                .to_vec()
+                .iter()
+                .any(|(name, _)| name == "Sec-Fetch-User"));
+        }
+    }
+
+    #[test]
+    fn test_identity_headers_agree_with_navigator() {
+        use forloop_fingerprint::navigator::NavigatorDefense;
+
+        let mut seen = Vec::new();
+        for byte in 0..64u8 {
+            let identity = SyntheticIdentity::from_seed([byte; 32]);
+            let navigator = NavigatorDefense::for_identity(&identity).get_properties();
+            let synth = HeaderSynthesizer::for_identity(&identity);
+
+            for destination in Destination::ALL {
+                assert_eq!(
+                    synth.generate_for(destination).user_agent,
+                    navigator.user_agent
+                );
+            }
+            assert_eq!(navigator.platform, identity.platform);
+            assert!(
+                navigator.user_agent.contains(&navigator.oscpu),
+                "{} does not contain {}",
+                navigator.user_agent,
+                navigator.oscpu
+            );
+            if !seen.contains(&synth.platform()) {
+                seen.push(synth.platform());
+            }
+        }
+        assert_eq!(seen.len(), Platform::ALL.len());
+    }
+
+    #[test]
+    fn test_generate_for_rotates_platform_only() {
+        let synth = HeaderSynthesizer::new();
+        let headers = synth.generate_for(Destination::Xhr);
+
+        assert!(Platform::ALL
+            .iter()
+            .any(|p| p.user_agent() == headers.user_agent));
+        assert_eq!(headers.accept, "*/*");
+        assert_eq!(synth.generate_for_image().destination, Destination::Image);
+        assert_eq!(
+            synth.generate_for(Destination::Style).accept,
+            "text/css,*/*;q=0.1"
+        );
+    }
+
     #[test]
     fn test_header_order_normalization() {
         let mut headers = vec![
@@ -227,4 +1016,33 @@ mod tests {
         assert_eq!(headers[1].0, "User-Agent");
         assert_eq!(headers[2].0, "Accept");
     }
+
+    #[test]
+    fn test_strip_tracking_params() {
+        assert_eq!(
+            strip_tracking_params(
+                "https://example.com/a?utm_source=mail&id=7&FBCLID=x&_hsenc=y&q=a%26b#top"
+            ),
+            ("https://example.com/a?id=7&q=a%26b#top".to_string(), 3)
+        );
+        assert_eq!(
+            strip_tracking_params("https://x.com/?a=1&utm_source=mail&b=2"),
+            ("https://x.com/?a=1&b=2".to_string(), 1)
+        );
+        assert_eq!(
+            strip_tracking_params("https://example.com/?gclid=1&utm_medium=2"),
+            ("https://example.com/".to_string(), 2)
+        );
+
+        // Untouched when there is nothing to remove, including a fragment
+        // that looks like a query
+        for url in [
+            "https://example.com/",
+            "https://example.com/?page=2",
+            "https://example.com/#?utm_source=x",
+            "https://example.com/?utmost=1",
+        ] {
+            assert_eq!(strip_tracking_params(url), (url.to_string(), 0));
+        }
+    }
 }
@@ -0,0 +1,484 @@
+//! HPACK header compression for HTTP/2 (RFC 7541).
+//!
+//! Outgoing headers are encoded as literals that are never indexed and
+//! never Huffman-coded, so the block is written in exactly the order it
+//! was given and nothing is left in the server's table for a later
+//! request. Incoming blocks are decoded in full: both tables, Huffman
+//! strings and table size updates.
+//!
+//! Decoding is held to `max_list_bytes`, counted the way SETTINGS
+//! MAX_HEADER_LIST_SIZE is (name, value and 32 bytes per field), so a
+//! small block that keeps referencing a large table entry cannot blow up
+//! in memory, and to `max_fields` fields besides the pseudo-headers.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+use crate::http2::COMPRESSION_ERROR;
+use crate::http_response::{headers_too_large, HeaderLimit};
+use crate::protocol_fallback::Http2Failure;
+use crate::NetworkError;
+
+/// Per-entry overhead in table and header list sizes.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Static table (Appendix A); index 1 is the first entry.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Huffman code length of every symbol, 256 being EOS (Appendix B).
+///
+/// The code is canonical: within a length, codes are consecutive in
+/// symbol order, so the lengths alone define it.
+#[rustfmt::skip]
+const HUFFMAN_CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+     6, 10, 10, 12, 13,  6,  8, 11, 10, 10,  8, 11,  8,  6,  6,  6,
+     5,  5,  5,  6,  6,  6,  6,  6,  6,  6,  7,  8, 15,  6, 12, 10,
+    13,  6,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,
+     7,  7,  7,  7,  7,  7,  7,  7,  8,  7,  8, 13, 19, 13, 14,  6,
+    15,  5,  6,  5,  6,  5,  6,  6,  6,  5,  7,  7,  6,  6,  6,  5,
+     6,  7,  6,  5,  5,  6,  7,  7,  7,  7,  7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+/// Symbol for end of string, never valid inside one.
+const EOS: u16 = 256;
+
+/// Encode `headers` as a header block, in order.
+///
+/// Names are lowercased, as HTTP/2 requires.
+pub(crate) fn encode_headers(headers: &[(String, String)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        // Literal header field without indexing, new name
+        block.push(0x00);
+        encode_string(&mut block, name.to_ascii_lowercase().as_bytes());
+        encode_string(&mut block, value.as_bytes());
+    }
+    block
+}
+
+fn encode_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    encode_integer(out, 0x00, 7, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Write `value` with an `n`-bit prefix, `flags` in the bits above it.
+fn encode_integer(out: &mut Vec<u8>, flags: u8, n: u32, mut value: usize) {
+    let max = (1usize << n) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Decoding state for the headers a peer sends on one connection.
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    dynamic: VecDeque<(String, String)>,
+    size: usize,
+    /// Table size we advertised (SETTINGS_HEADER_TABLE_SIZE)
+    max_size: usize,
+    /// Table size the peer last set, at most `max_size`
+    limit: usize,
+}
+
+impl Decoder {
+    /// Create a decoder for a peer told to keep its table within
+    /// `max_size` bytes.
+    pub(crate) fn new(max_size: usize) -> Self {
+        Self {
+            dynamic: VecDeque::new(),
+            size: 0,
+            max_size,
+            limit: max_size,
+        }
+    }
+
+    /// Decode a complete header block into at most `max_fields` fields,
+    /// pseudo-headers aside, totalling at most `max_list_bytes`.
+    pub(crate) fn decode(
+        &mut self,
+        block: &[u8],
+        max_fields: usize,
+        max_list_bytes: usize,
+    ) -> Result<Vec<(String, String)>, NetworkError> {
+        let mut input = block;
+        let mut fields = Vec::new();
+        let mut list_bytes = 0usize;
+        let mut regular = 0usize;
+        let mut first = true;
+
+        while let Some(&byte) = input.first() {
+            let field = if byte & 0x80 != 0 {
+                // Indexed
+                let index = decode_integer(&mut input, 7)?;
+                self.entry(index)?
+            } else if byte & 0x40 != 0 {
+                // Literal with incremental indexing
+                let field = self.literal(&mut input, 6)?;
+                self.insert(field.clone());
+                field
+            } else if byte & 0x20 != 0 {
+                // Table size update, only ahead of the first field
+                if !first {
+                    return Err(compression("table size update after a field"));
+                }
+                let size = decode_integer(&mut input, 5)?;
+                if size > self.max_size {
+                    return Err(compression("table size over the advertised limit"));
+                }
+                self.limit = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal without indexing, or never indexed
+                self.literal(&mut input, 4)?
+            };
+            first = false;
+
+            list_bytes = list_bytes.saturating_add(field.0.len() + field.1.len() + ENTRY_OVERHEAD);
+            if list_bytes > max_list_bytes {
+                return Err(headers_too_large(HeaderLimit::TotalSize, max_list_bytes));
+            }
+            if !field.0.starts_with(':') {
+                if regular == max_fields {
+                    return Err(headers_too_large(HeaderLimit::Count, max_fields));
+                }
+                regular += 1;
+            }
+            fields.push(field);
+        }
+        Ok(fields)
+    }
+
+    /// A literal field whose name index has an `n`-bit prefix.
+    fn literal(&mut self, input: &mut &[u8], n: u32) -> Result<(String, String), NetworkError> {
+        let index = decode_integer(input, n)?;
+        let name = if index == 0 {
+            decode_string(input)?
+        } else {
+            self.entry(index)?.0
+        };
+        Ok((name, decode_string(input)?))
+    }
+
+    fn entry(&self, index: usize) -> Result<(String, String), NetworkError> {
+        let field = match index {
+            0 => None,
+            1..=61 => STATIC_TABLE
+                .get(index - 1)
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+            _ => self.dynamic.get(index - 62).cloned(),
+        };
+        field.ok_or_else(|| compression("index out of range"))
+    }
+
+    fn insert(&mut self, field: (String, String)) {
+        let size = field.0.len() + field.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the whole table empties it and is dropped
+        if size <= self.limit {
+            self.size += size;
+            self.dynamic.push_front(field);
+        }
+    }
+
+    /// Evict the oldest entries until `incoming` more bytes fit.
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.limit {
+            let Some((name, value)) = self.dynamic.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn decode_integer(input: &mut &[u8], n: u32) -> Result<usize, NetworkError> {
+    let (&first, rest) = input
+        .split_first()
+        .ok_or_else(|| compression("truncated integer"))?;
+    *input = rest;
+    let max = (1usize << n) - 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input
+            .split_first()
+            .ok_or_else(|| compression("truncated integer"))?;
+        *input = rest;
+        // Nothing legitimate needs more than four continuation bytes
+        if shift > 21 {
+            return Err(compression("integer too large"));
+        }
+        value += (byte as usize & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, NetworkError> {
+    let huffman = input.first().is_some_and(|byte| byte & 0x80 != 0);
+    let len = decode_integer(input, 7)?;
+    if len > input.len() {
+        return Err(compression("truncated string"));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+
+    let bytes = if huffman {
+        huffman_decode(bytes)?
+    } else {
+        bytes.to_vec()
+    };
+    String::from_utf8(bytes).map_err(|_| compression("header is not UTF-8"))
+}
+
+/// Canonical decoding tables: symbols by (length, symbol), and for each
+/// length its first code and where its symbols start.
+struct HuffmanTables {
+    symbols: Vec<u16>,
+    first_code: [u32; 31],
+    first_index: [usize; 31],
+    count: [usize; 31],
+}
+
+fn huffman_tables() -> &'static HuffmanTables {
+    static TABLES: OnceLock<HuffmanTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..=EOS).collect();
+        symbols.sort_by_key(|&symbol| (HUFFMAN_CODE_LENGTHS[symbol as usize], symbol));
+
+        let mut count = [0usize; 31];
+        for &len in &HUFFMAN_CODE_LENGTHS {
+            count[len as usize] += 1;
+        }
+        let mut first_code = [0u32; 31];
+        let mut first_index = [0usize; 31];
+        let (mut code, mut index) = (0u32, 0usize);
+        for len in 1..31 {
+            first_code[len] = code;
+            first_index[len] = index;
+            code = (code + count[len] as u32) << 1;
+            index += count[len];
+        }
+        HuffmanTables {
+            symbols,
+            first_code,
+            first_index,
+            count,
+        }
+    })
+}
+
+fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, NetworkError> {
+    let tables = huffman_tables();
+    let mut out = Vec::with_capacity(bytes.len() * 8 / 5);
+    let (mut code, mut len) = (0u32, 0usize);
+
+    for byte in bytes {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from(byte >> bit & 1);
+            len += 1;
+            let offset = code.wrapping_sub(tables.first_code[len]) as usize;
+            if offset < tables.count[len] {
+                let symbol = tables.symbols[tables.first_index[len] + offset];
+                if symbol == EOS {
+                    return Err(compression("EOS inside a Huffman string"));
+                }
+                out.push(symbol as u8);
+                code = 0;
+                len = 0;
+            } else if len == 30 {
+                return Err(compression("invalid Huffman code"));
+            }
+        }
+    }
+
+    // Padding is the start of EOS: all ones, shorter than a byte
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(compression("invalid Huffman padding"));
+    }
+    Ok(out)
+}
+
+/// A decoding failure, which is fatal to the connection (section 2.3).
+fn compression(detail: &str) -> NetworkError {
+    log::debug!("HPACK: {}", detail);
+    NetworkError::Http2(Http2Failure::Connection {
+        code: COMPRESSION_ERROR,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("hex"))
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rfc7541_huffman_requests() {
+        // C.4: the same connection's requests, sharing the dynamic table
+        let mut decoder = Decoder::new(4096);
+        assert_eq!(
+            decoder
+                .decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 16, 4096)
+                .expect("first request"),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ])
+        );
+        assert_eq!(
+            decoder
+                .decode(&hex("8286 84be 5886 a8eb 1064 9cbf"), 16, 4096)
+                .expect("second request"),
+            fields(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ])
+        );
+        assert_eq!(decoder.size, 110);
+
+        // Referencing the table cannot get past the list limit
+        let bomb = vec![0xbe; 100];
+        assert!(matches!(
+            decoder.decode(&bomb, 1000, 1024),
+            Err(NetworkError::HeadersTooLarge {
+                exceeded: HeaderLimit::TotalSize,
+                limit: 1024,
+            })
+        ));
+        assert!(decoder.decode(&[0xff, 0x00], 16, 4096).is_err());
+    }
+
+    #[test]
+    fn test_encoded_block_round_trips_in_order() {
+        let headers = fields(&[
+            (":method", "GET"),
+            (":path", "/a?b=c"),
+            ("User-Agent", "Mozilla/5.0"),
+            ("x-long", &"v".repeat(300)),
+        ]);
+        let block = encode_headers(&headers);
+        // Never indexed into the server's table, never Huffman-coded
+        assert_eq!(&block[..2], [0x00, 0x07]);
+
+        let mut decoder = Decoder::new(4096);
+        let decoded = decoder.decode(&block, 16, 4096).expect("decodes");
+        assert_eq!(
+            decoded[2],
+            ("user-agent".to_string(), "Mozilla/5.0".to_string())
+        );
+        assert_eq!(decoded[3].1.len(), 300);
+        assert_eq!(decoder.size, 0);
+
+        // A size update over what was advertised is refused
+        assert!(Decoder::new(4096)
+            .decode(&[0x3f, 0xe2, 0x1f], 16, 4096)
+            .is_err());
+    }
+}
@@ -0,0 +1,335 @@
+//! Streaming HTML tokenizer shared by the sanitizer and the text extractor.
+//!
+//! This is not a full HTML5 tokenizer, only enough to find tags: text,
+//! start tags with their attributes, end tags, and everything else
+//! between '<' and '>' (comments, doctypes, processing instructions).
+//! The content of raw text elements is never tokenized as markup.
+//!
+//! Every byte fed in comes out in exactly one token, in order, so a
+//! consumer that writes tokens back gets the document byte for byte. Only
+//! an incomplete construct is held back between chunks.
+
+/// Elements whose content is raw text, never markup.
+pub(crate) const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea", "title", "xmp"];
+
+/// A piece of a document, borrowing its source bytes.
+#[derive(Debug)]
+pub(crate) enum Token<'a> {
+    /// Character data, including a stray '<'
+    Text(&'a [u8]),
+    /// Content of a raw text element
+    RawText(&'a [u8]),
+    /// A start tag, and the bytes it was parsed from
+    StartTag(Tag, &'a [u8]),
+    /// An end tag's lowercase name, and its bytes
+    EndTag(String, &'a [u8]),
+    /// A comment, doctype or processing instruction, as written
+    Other(&'a [u8]),
+}
+
+/// Streaming tokenizer for one document.
+#[derive(Debug, Default)]
+pub(crate) struct HtmlTokenizer {
+    /// Incomplete construct carried over to the next chunk
+    pending: Vec<u8>,
+    /// Inside a raw text element: the end tag that closes it
+    raw_text_end: Option<Vec<u8>>,
+}
+
+impl HtmlTokenizer {
+    /// Tokenize the next chunk, handing each complete token to `emit`.
+    pub(crate) fn feed(&mut self, chunk: &[u8], mut emit: impl FnMut(Token<'_>)) {
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(chunk);
+
+        let mut pos = 0;
+        while pos < data.len() {
+            if let Some(end_tag) = &self.raw_text_end {
+                match find_ignore_case(&data[pos..], end_tag) {
+                    Some(offset) => {
+                        emit(Token::RawText(&data[pos..pos + offset]));
+                        pos += offset;
+                        self.raw_text_end = None;
+                    }
+                    None => {
+                        // Hold back a possible partial end tag
+                        let keep = (end_tag.len() - 1).min(data.len() - pos);
+                        emit(Token::RawText(&data[pos..data.len() - keep]));
+                        pos = data.len() - keep;
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            let Some(offset) = data[pos..].iter().position(|&b| b == b'<') else {
+                emit(Token::Text(&data[pos..]));
+                pos = data.len();
+                break;
+            };
+            if offset > 0 {
+                emit(Token::Text(&data[pos..pos + offset]));
+                pos += offset;
+            }
+
+            match self.markup_at(&data[pos..]) {
+                Some((token, consumed)) => {
+                    emit(token);
+                    pos += consumed;
+                }
+                None => break,
+            }
+        }
+
+        self.pending = data[pos..].to_vec();
+    }
+
+    /// Take whatever is held back at the end of the document.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Tokenize markup starting with '<'. Returns the token and the bytes
+    /// consumed, or `None` if the construct is incomplete.
+    fn markup_at<'a>(&mut self, data: &'a [u8]) -> Option<(Token<'a>, usize)> {
+        const COMMENT_OPEN: &[u8] = b"<!--";
+
+        if data.len() < COMMENT_OPEN.len() && COMMENT_OPEN.starts_with(data) {
+            return None;
+        }
+        if data.starts_with(COMMENT_OPEN) {
+            // `<!-->` and `<!--->` are empty comments, closed on the spot
+            let body = &data[COMMENT_OPEN.len()..];
+            let end = if body.starts_with(b">") {
+                COMMENT_OPEN.len() + 1
+            } else if body.starts_with(b"->") {
+                COMMENT_OPEN.len() + 2
+            } else {
+                find_ignore_case(body, b"-->")? + COMMENT_OPEN.len() + 3
+            };
+            return Some((Token::Other(&data[..end]), end));
+        }
+
+        match data.get(1) {
+            None => None,
+            Some(b) if b.is_ascii_alphabetic() => {
+                let tag = parse_tag(data)?;
+                let end = tag.end;
+                if RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) {
+                    self.raw_text_end = Some(format!("</{}", tag.name).into_bytes());
+                }
+                Some((Token::StartTag(tag, &data[..end]), end))
+            }
+            Some(b'/') => {
+                let end = data.iter().position(|&b| b == b'>')? + 1;
+                let name_end = data[2..end]
+                    .iter()
+                    .position(|b| b.is_ascii_whitespace() || b"/>".contains(b))
+                    .map_or(end, |i| i + 2);
+                let name = String::from_utf8_lossy(&data[2..name_end]).to_ascii_lowercase();
+                Some((Token::EndTag(name, &data[..end]), end))
+            }
+            Some(b'!' | b'?') => {
+                let end = data.iter().position(|&b| b == b'>')? + 1;
+                Some((Token::Other(&data[..end]), end))
+            }
+            // A stray '<' is text
+            Some(_) => Some((Token::Text(&data[..1]), 1)),
+        }
+    }
+}
+
+/// A parsed start tag.
+#[derive(Debug)]
+pub(crate) struct Tag {
+    /// Lowercase element name
+    pub(crate) name: String,
+    /// Attributes in source order
+    pub(crate) attrs: Vec<Attr>,
+    /// Length of the tag including '>'
+    pub(crate) end: usize,
+}
+
+impl Tag {
+    pub(crate) fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.as_str())
+    }
+}
+
+/// A parsed attribute.
+#[derive(Debug)]
+pub(crate) struct Attr {
+    /// Lowercase name
+    pub(crate) name: String,
+    /// Unquoted value
+    pub(crate) value: String,
+    /// Byte range in the tag, including the whitespace before it
+    pub(crate) span: (usize, usize),
+}
+
+/// Parse a start tag at the beginning of `data`. `None` if incomplete.
+fn parse_tag(data: &[u8]) -> Option<Tag> {
+    let is_space = |b: u8| b.is_ascii_whitespace();
+
+    let mut pos = 1;
+    while pos < data.len() && !is_space(data[pos]) && !b"/>".contains(&data[pos]) {
+        pos += 1;
+    }
+    let name = String::from_utf8_lossy(&data[1..pos]).to_ascii_lowercase();
+
+    let mut attrs = Vec::new();
+    loop {
+        let span_start = pos;
+        while pos < data.len() && (is_space(data[pos]) || data[pos] == b'/') {
+            pos += 1;
+        }
+        match data.get(pos)? {
+            b'>' => {
+                return Some(Tag {
+                    name,
+                    attrs,
+                    end: pos + 1,
+                })
+            }
+            _ => {
+                let name_start = pos;
+                while pos < data.len() && !is_space(data[pos]) && !b"/>=".contains(&data[pos]) {
+                    pos += 1;
+                }
+                let attr_name =
+                    String::from_utf8_lossy(&data[name_start..pos]).to_ascii_lowercase();
+
+                let mut after = pos;
+                while after < data.len() && is_space(data[after]) {
+                    after += 1;
+                }
+                let mut value = String::new();
+                if data.get(after) == Some(&b'=') {
+                    pos = after + 1;
+                    while pos < data.len() && is_space(data[pos]) {
+                        pos += 1;
+                    }
+                    let value_start;
+                    match data.get(pos)? {
+                        &quote @ (b'"' | b'\'') => {
+                            value_start = pos + 1;
+                            pos = value_start
+                                + data[value_start..].iter().position(|&b| b == quote)?;
+                            value = String::from_utf8_lossy(&data[value_start..pos]).into_owned();
+                            pos += 1;
+                        }
+                        _ => {
+                            value_start = pos;
+                            while pos < data.len() && !is_space(data[pos]) && data[pos] != b'>' {
+                                pos += 1;
+                            }
+                            value = String::from_utf8_lossy(&data[value_start..pos]).into_owned();
+                        }
+                    }
+                }
+
+                attrs.push(Attr {
+                    name: attr_name,
+                    value,
+                    span: (span_start, pos),
+                });
+            }
+        }
+    }
+}
+
+/// Find `needle` in `haystack`, ASCII case-insensitively.
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tokens as (kind, source) pairs, adjacent text merged, fed in
+    /// `size`-byte chunks.
+    fn tokenize(html: &str, size: usize) -> Vec<(&'static str, String)> {
+        let mut tokenizer = HtmlTokenizer::default();
+        let mut tokens: Vec<(&'static str, String)> = Vec::new();
+        for chunk in html.as_bytes().chunks(size) {
+            tokenizer.feed(chunk, |token| {
+                let (kind, bytes) = match token {
+                    Token::Text(bytes) => ("text", bytes),
+                    Token::RawText(bytes) => ("raw", bytes),
+                    Token::StartTag(_, bytes) => ("start", bytes),
+                    Token::EndTag(_, bytes) => ("end", bytes),
+                    Token::Other(bytes) => ("other", bytes),
+                };
+                let source = String::from_utf8_lossy(bytes).into_owned();
+                match tokens.last_mut() {
+                    Some((last, text)) if *last == kind && matches!(kind, "text" | "raw") => {
+                        text.push_str(&source)
+                    }
+                    _ if source.is_empty() => {}
+                    _ => tokens.push((kind, source)),
+                }
+            });
+        }
+        let rest = String::from_utf8_lossy(&tokenizer.finish()).into_owned();
+        if !rest.is_empty() {
+            tokens.push(("pending", rest));
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_tokens_cover_every_byte() {
+        let html = "<!DOCTYPE html><p class=x>a < b</P><!-- <b> -->\
+                    <script>'</scr' + 'ipt>'</SCRIPT><?pi ?><a href=";
+        let expected = vec![
+            ("other", "<!DOCTYPE html>"),
+            ("start", "<p class=x>"),
+            ("text", "a < b"),
+            ("end", "</P>"),
+            ("other", "<!-- <b> -->"),
+            ("start", "<script>"),
+            ("raw", "'</scr' + 'ipt>'"),
+            ("end", "</SCRIPT>"),
+            ("other", "<?pi ?>"),
+            ("pending", "<a href="),
+        ];
+
+        for size in [1, 2, 5, html.len()] {
+            let tokens = tokenize(html, size);
+            let tokens: Vec<_> = tokens.iter().map(|(k, s)| (*k, s.as_str())).collect();
+            assert_eq!(tokens, expected, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn test_abruptly_closed_comments() {
+        for comment in ["<!-->", "<!--->"] {
+            let html = format!("{}<b>x", comment);
+            for size in [1, html.len()] {
+                let tokens = tokenize(&html, size);
+                let tokens: Vec<_> = tokens.iter().map(|(k, s)| (*k, s.as_str())).collect();
+                assert_eq!(
+                    tokens,
+                    vec![("other", comment), ("start", "<b>"), ("text", "x")]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_end_tag_names() {
+        let mut names = Vec::new();
+        HtmlTokenizer::default().feed(b"</DIV></p ></br/></>", |token| {
+            if let Token::EndTag(name, _) = token {
+                names.push(name);
+            }
+        });
+        assert_eq!(names, ["div", "p", "br", ""]);
+    }
+}
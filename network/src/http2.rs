@@ -0,0 +1,821 @@
+//! HTTP/2 requests, for connections where ALPN selected "h2".
+//!
+//! The connection opens the way Firefox's does, all from
+//! `Http2Fingerprint`:
+//!
+//! - the preface and one SETTINGS frame with exactly its settings, in order
+//! - a WINDOW_UPDATE raising the connection window by `window_update`
+//! - its PRIORITY frames, creating the idle group streams
+//! - the request's HEADERS on the next free stream, carrying `priority`
+//!
+//! Pseudo-headers come first in Firefox's order, then the request headers
+//! in `normalize_header_order` order. Connection-specific headers mean
+//! nothing in HTTP/2 and are dropped, along with anything
+//! `DANGEROUS_HEADERS` lists.
+//!
+//! One request is made per connection, as with HTTP/1.1. The response is
+//! read into a `RawResponse` under the same `ResponseLimits` and body cap;
+//! received DATA is credited back at once, so only the cap bounds how much
+//! the server sends.
+
+use forloop_config::ByteSize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::circuit::RawResponse;
+use crate::headers::{check_header, normalize_header_order, DANGEROUS_HEADERS};
+use crate::hpack::{encode_headers, Decoder};
+use crate::http_response::{
+    body_too_large, check_header_value, headers_too_large, HeaderLimit, ResponseLimits,
+};
+use crate::protocol_fallback::Http2Failure;
+use crate::retry::CircuitFailure;
+use crate::timing::Phases;
+use crate::tls_fingerprint::{Http2Fingerprint, Http2Priority};
+use crate::NetworkError;
+
+/// Connection preface every client starts with.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+const PROTOCOL_ERROR: u32 = 0x1;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+pub(crate) const COMPRESSION_ERROR: u32 = 0x9;
+
+/// Window and frame size before SETTINGS say otherwise.
+const DEFAULT_WINDOW: i64 = 65_535;
+const DEFAULT_MAX_FRAME: usize = 16_384;
+const DEFAULT_HEADER_TABLE: usize = 4_096;
+
+/// Headers that only mean something to an HTTP/1.1 connection.
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A request to send over HTTP/2.
+pub(crate) struct Http2Request<'a> {
+    pub(crate) method: &'a str,
+    /// Host, with the port unless it is 443
+    pub(crate) authority: &'a str,
+    pub(crate) path: &'a str,
+    pub(crate) headers: &'a [(String, String)],
+    pub(crate) body: Option<&'a [u8]>,
+}
+
+/// Everything sent before the request: preface, SETTINGS, WINDOW_UPDATE
+/// and PRIORITY frames.
+pub(crate) fn connection_start(fingerprint: &Http2Fingerprint) -> Vec<u8> {
+    let mut out = PREFACE.to_vec();
+
+    let settings: Vec<u8> = fingerprint
+        .settings
+        .iter()
+        .flat_map(|(id, value)| id.to_be_bytes().into_iter().chain(value.to_be_bytes()))
+        .collect();
+    push_frame(&mut out, SETTINGS, 0, 0, &settings);
+    push_frame(
+        &mut out,
+        WINDOW_UPDATE,
+        0,
+        0,
+        &fingerprint.window_update.to_be_bytes(),
+    );
+    for (stream, priority) in &fingerprint.priority_frames {
+        push_frame(&mut out, PRIORITY, 0, *stream, &priority_field(priority));
+    }
+    out
+}
+
+/// The header list for `request`: pseudo-headers, then its headers in
+/// Firefox's order.
+pub(crate) fn request_headers(
+    request: &Http2Request<'_>,
+) -> Result<Vec<(String, String)>, NetworkError> {
+    // HPACK would carry CR and LF, but an HTTP/1.1 hop behind the
+    // server could split on them
+    for (name, value) in request.headers {
+        check_header(name, value)?;
+    }
+    let mut headers: Vec<(String, String)> = request
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.to_ascii_lowercase();
+            !CONNECTION_HEADERS.contains(&name.as_str())
+                && !DANGEROUS_HEADERS.contains(&name.as_str())
+        })
+        .cloned()
+        .collect();
+    if let Some(body) = request.body {
+        headers.push(("content-length".to_string(), body.len().to_string()));
+    }
+    normalize_header_order(&mut headers);
+
+    let mut list = vec![
+        (":method".to_string(), request.method.to_string()),
+        (":path".to_string(), request.path.to_string()),
+        (":authority".to_string(), request.authority.to_string()),
+        (":scheme".to_string(), "https".to_string()),
+    ];
+    list.extend(headers);
+    Ok(list)
+}
+
+/// Make `request` over a fresh h2 connection on `stream`, reading a body
+/// of at most `max_body` bytes.
+pub(crate) async fn exchange<S>(
+    stream: S,
+    fingerprint: &Http2Fingerprint,
+    request: &Http2Request<'_>,
+    limits: &ResponseLimits,
+    max_body: ByteSize,
+) -> Result<RawResponse, NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let setting = |id: u16| {
+        fingerprint
+            .settings
+            .iter()
+            .find(|(setting, _)| *setting == id)
+            .map(|(_, value)| *value as usize)
+    };
+    // The first stream id above every idle group stream
+    let stream_id = fingerprint
+        .priority_frames
+        .iter()
+        .map(|(stream, _)| *stream + 2)
+        .max()
+        .unwrap_or(1);
+    let body = request.body.unwrap_or_default();
+
+    let mut out = connection_start(fingerprint);
+    let block = encode_headers(&request_headers(request)?);
+    push_headers(
+        &mut out,
+        stream_id,
+        &fingerprint.priority,
+        &block,
+        body.is_empty(),
+    );
+
+    let mut connection = Connection {
+        stream,
+        stream_id,
+        limits,
+        max_body,
+        decoder: Decoder::new(setting(SETTINGS_HEADER_TABLE_SIZE).unwrap_or(DEFAULT_HEADER_TABLE)),
+        max_frame: setting(SETTINGS_MAX_FRAME_SIZE)
+            .filter(|size| *size >= DEFAULT_MAX_FRAME)
+            .unwrap_or(DEFAULT_MAX_FRAME),
+        peer_max_frame: DEFAULT_MAX_FRAME,
+        send_window: DEFAULT_WINDOW,
+        stream_window: DEFAULT_WINDOW,
+        peer_initial_window: DEFAULT_WINDOW,
+        read_any: false,
+        bodyless: request.method.eq_ignore_ascii_case("HEAD"),
+        head: None,
+        body: Vec::new(),
+    };
+    connection.write(&out).await?;
+    connection.run(body).await
+}
+
+/// State of the one exchange on a connection.
+struct Connection<'a, S> {
+    stream: S,
+    stream_id: u32,
+    limits: &'a ResponseLimits,
+    max_body: ByteSize,
+    decoder: Decoder,
+    /// Largest frame we accept
+    max_frame: usize,
+    /// Largest frame the server accepts
+    peer_max_frame: usize,
+    send_window: i64,
+    stream_window: i64,
+    peer_initial_window: i64,
+    read_any: bool,
+    /// A HEAD request, whose response has no body whatever its length
+    bodyless: bool,
+    head: Option<(u16, Vec<(String, String)>)>,
+    body: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<'_, S> {
+    /// Send `body` as the windows allow, and read until the response ends.
+    async fn run(mut self, body: &[u8]) -> Result<RawResponse, NetworkError> {
+        let mut sent = 0;
+        let mut header_block: Option<(Vec<u8>, bool)> = None;
+
+        loop {
+            while sent < body.len() && self.send_window > 0 && self.stream_window > 0 {
+                let n = (body.len() - sent)
+                    .min(self.peer_max_frame)
+                    .min(self.send_window.min(self.stream_window) as usize);
+                let flags = if sent + n == body.len() {
+                    FLAG_END_STREAM
+                } else {
+                    0
+                };
+                let mut out = Vec::with_capacity(FRAME_HEADER_LEN + n);
+                push_frame(&mut out, DATA, flags, self.stream_id, &body[sent..sent + n]);
+                self.write(&out).await?;
+                sent += n;
+                self.send_window -= n as i64;
+                self.stream_window -= n as i64;
+            }
+
+            let (kind, flags, stream, payload) = self.read_frame().await?;
+
+            // A header block is only ever continued, never interleaved
+            if let Some((block, end_stream)) = &mut header_block {
+                if kind != CONTINUATION || stream != self.stream_id {
+                    return Err(protocol("header block interrupted"));
+                }
+                block.extend_from_slice(&payload);
+                if block.len() > self.limits.max_header_bytes.get() {
+                    return Err(self.block_too_large());
+                }
+                if flags & FLAG_END_HEADERS != 0 {
+                    let end_stream = *end_stream;
+                    let (block, _) = header_block.take().unwrap_or_default();
+                    if self.headers(&block, end_stream)? {
+                        return self.finish();
+                    }
+                }
+                continue;
+            }
+
+            match kind {
+                DATA => {
+                    self.check_stream(stream)?;
+                    if self.head.is_none() {
+                        return Err(protocol("DATA before the response headers"));
+                    }
+                    let data = unpad(&payload, flags)?;
+                    let received = self.body.len() as u64 + data.len() as u64;
+                    if received > self.max_body.get() as u64 {
+                        return Err(body_too_large(self.max_body, received));
+                    }
+                    self.body.extend_from_slice(data);
+                    if flags & FLAG_END_STREAM != 0 {
+                        return self.finish();
+                    }
+                    if !payload.is_empty() {
+                        // Credit the whole frame back, padding included
+                        let increment = (payload.len() as u32).to_be_bytes();
+                        let mut out = Vec::new();
+                        push_frame(&mut out, WINDOW_UPDATE, 0, 0, &increment);
+                        push_frame(&mut out, WINDOW_UPDATE, 0, self.stream_id, &increment);
+                        self.write(&out).await?;
+                    }
+                }
+                HEADERS => {
+                    self.check_stream(stream)?;
+                    let mut fragment = unpad(&payload, flags)?;
+                    if flags & FLAG_PRIORITY != 0 {
+                        fragment = fragment
+                            .get(5..)
+                            .ok_or_else(|| protocol("short HEADERS frame"))?;
+                    }
+                    let end_stream = flags & FLAG_END_STREAM != 0;
+                    if flags & FLAG_END_HEADERS == 0 {
+                        header_block = Some((fragment.to_vec(), end_stream));
+                    } else if self.headers(fragment, end_stream)? {
+                        return self.finish();
+                    }
+                }
+                SETTINGS if flags & FLAG_ACK == 0 => {
+                    if stream != 0 || payload.len() % 6 != 0 {
+                        return Err(protocol("malformed SETTINGS"));
+                    }
+                    for setting in payload.chunks_exact(6) {
+                        let value =
+                            u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                        match u16::from_be_bytes([setting[0], setting[1]]) {
+                            SETTINGS_INITIAL_WINDOW_SIZE => {
+                                self.stream_window += value as i64 - self.peer_initial_window;
+                                self.peer_initial_window = value as i64;
+                            }
+                            SETTINGS_MAX_FRAME_SIZE => self.peer_max_frame = value as usize,
+                            _ => {}
+                        }
+                    }
+                    let mut ack = Vec::new();
+                    push_frame(&mut ack, SETTINGS, FLAG_ACK, 0, &[]);
+                    self.write(&ack).await?;
+                }
+                PING if flags & FLAG_ACK == 0 => {
+                    let mut pong = Vec::new();
+                    push_frame(&mut pong, PING, FLAG_ACK, 0, &payload);
+                    self.write(&pong).await?;
+                }
+                WINDOW_UPDATE => {
+                    let increment = word(&payload, 0) & 0x7fff_ffff;
+                    if increment == 0 {
+                        return Err(protocol("malformed WINDOW_UPDATE"));
+                    }
+                    if stream == 0 {
+                        self.send_window += increment as i64;
+                    } else if stream == self.stream_id {
+                        self.stream_window += increment as i64;
+                    }
+                }
+                RST_STREAM if stream == self.stream_id => {
+                    return Err(NetworkError::Http2(Http2Failure::StreamReset {
+                        code: word(&payload, 0),
+                    }));
+                }
+                GOAWAY => {
+                    // The request goes on if the server took it in
+                    let last_stream_id = word(&payload, 0) & 0x7fff_ffff;
+                    if last_stream_id < self.stream_id {
+                        return Err(NetworkError::Http2(Http2Failure::GoAway {
+                            code: word(&payload, 4),
+                            last_stream_id,
+                        }));
+                    }
+                }
+                PUSH_PROMISE => return Err(protocol("push was disabled")),
+                CONTINUATION => return Err(protocol("CONTINUATION without HEADERS")),
+                // Acks, priorities and unknown frame types
+                _ => {}
+            }
+        }
+    }
+
+    /// The error for a header block over `max_header_bytes`.
+    fn block_too_large(&self) -> NetworkError {
+        headers_too_large(HeaderLimit::TotalSize, self.limits.max_header_bytes.get())
+    }
+
+    /// Take in a complete header block. Returns whether the response ended.
+    fn headers(&mut self, block: &[u8], end_stream: bool) -> Result<bool, NetworkError> {
+        if block.len() > self.limits.max_header_bytes.get() {
+            return Err(self.block_too_large());
+        }
+        let fields = self.decoder.decode(
+            block,
+            self.limits.max_headers,
+            self.limits.max_header_bytes.get(),
+        )?;
+        for (_, value) in &fields {
+            check_header_value(value, self.limits)?;
+        }
+
+        if self.head.is_some() {
+            // Trailers, decoded to keep the table in step and dropped
+            if !end_stream {
+                return Err(protocol("trailers without END_STREAM"));
+            }
+            return Ok(true);
+        }
+
+        let mut status = None;
+        let mut headers = Vec::new();
+        for (name, value) in fields {
+            match name.strip_prefix(':') {
+                Some("status") if status.is_none() && headers.is_empty() => {
+                    status = Some(
+                        value
+                            .parse::<u16>()
+                            .ok()
+                            .filter(|status| (100..=999).contains(status))
+                            .ok_or_else(|| protocol("malformed :status"))?,
+                    );
+                }
+                Some(_) => return Err(protocol("unexpected pseudo-header")),
+                None => headers.push((name, value)),
+            }
+        }
+        match status.ok_or_else(|| protocol("response without :status"))? {
+            101 => Err(protocol("101 is not allowed in HTTP/2")),
+            // Interim responses are skipped
+            100..=199 if !end_stream => Ok(false),
+            100..=199 => Err(protocol("interim response ended the stream")),
+            status => {
+                let length = headers
+                    .iter()
+                    .find(|(name, _)| name == "content-length")
+                    .and_then(|(_, value)| value.parse::<u64>().ok());
+                let over = |len: &u64| !self.bodyless && *len > self.max_body.get() as u64;
+                if let Some(length) = length.filter(over) {
+                    return Err(body_too_large(self.max_body, length));
+                }
+                self.head = Some((status, headers));
+                Ok(end_stream)
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<RawResponse, NetworkError> {
+        let (status, headers) = self
+            .head
+            .take()
+            .ok_or_else(|| protocol("stream ended without a response"))?;
+        Ok(RawResponse {
+            status,
+            headers,
+            body: std::mem::take(&mut self.body),
+            phases: Phases::default(),
+        })
+    }
+
+    fn check_stream(&self, stream: u32) -> Result<(), NetworkError> {
+        if stream != self.stream_id {
+            return Err(protocol("frame for a stream that was never opened"));
+        }
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> Result<(u8, u8, u32, Vec<u8>), NetworkError> {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        if let Err(e) = self.stream.read_exact(&mut header).await {
+            // As with HTTP/1.1, a reset before anything arrived is the
+            // circuit's doing
+            return Err(match e.kind() {
+                std::io::ErrorKind::ConnectionReset if !self.read_any => {
+                    NetworkError::CircuitFailed(CircuitFailure::ConnectionReset)
+                }
+                std::io::ErrorKind::UnexpectedEof => protocol("connection closed mid-response"),
+                _ => NetworkError::RequestFailed(e.to_string()),
+            });
+        }
+        self.read_any = true;
+
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if len > self.max_frame {
+            log::debug!("HTTP/2: {} byte frame over the advertised size", len);
+            return Err(NetworkError::Http2(Http2Failure::Connection {
+                code: FRAME_SIZE_ERROR,
+            }));
+        }
+        let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0u8; len];
+        self.stream
+            .read_exact(&mut payload)
+            .await
+            .map_err(|_| protocol("truncated frame"))?;
+        Ok((header[3], header[4], stream, payload))
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), NetworkError> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .map_err(|e| NetworkError::RequestFailed(e.to_string()))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| NetworkError::RequestFailed(e.to_string()))
+    }
+}
+
+/// Append HEADERS for the request, continued as needed to fit the
+/// default frame size.
+fn push_headers(
+    out: &mut Vec<u8>,
+    stream: u32,
+    priority: &Http2Priority,
+    block: &[u8],
+    end_stream: bool,
+) {
+    let mut payload = priority_field(priority).to_vec();
+    let first = block.len().min(DEFAULT_MAX_FRAME - payload.len());
+    payload.extend_from_slice(&block[..first]);
+
+    let mut rest = block[first..].chunks(DEFAULT_MAX_FRAME).peekable();
+    let mut flags = FLAG_PRIORITY;
+    if end_stream {
+        flags |= FLAG_END_STREAM;
+    }
+    if rest.peek().is_none() {
+        flags |= FLAG_END_HEADERS;
+    }
+    push_frame(out, HEADERS, flags, stream, &payload);
+    while let Some(fragment) = rest.next() {
+        let flags = if rest.peek().is_none() {
+            FLAG_END_HEADERS
+        } else {
+            0
+        };
+        push_frame(out, CONTINUATION, flags, stream, fragment);
+    }
+}
+
+/// Stream dependency, exclusive bit and weight, as PRIORITY and HEADERS
+/// carry them. The weight is written as given.
+fn priority_field(priority: &Http2Priority) -> [u8; 5] {
+    let mut dependency = priority.depends_on & 0x7fff_ffff;
+    if priority.exclusive {
+        dependency |= 0x8000_0000;
+    }
+    let [a, b, c, d] = dependency.to_be_bytes();
+    [a, b, c, d, priority.weight]
+}
+
+fn push_frame(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&(stream & 0x7fff_ffff).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// The payload of a DATA or HEADERS frame without its padding.
+fn unpad(payload: &[u8], flags: u8) -> Result<&[u8], NetworkError> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let (&padding, rest) = payload
+        .split_first()
+        .ok_or_else(|| protocol("short padded frame"))?;
+    rest.len()
+        .checked_sub(padding as usize)
+        .map(|len| &rest[..len])
+        .ok_or_else(|| protocol("padding longer than the frame"))
+}
+
+/// The big-endian word at `offset`, or 0 if the payload is too short.
+fn word(payload: &[u8], offset: usize) -> u32 {
+    payload
+        .get(offset..offset + 4)
+        .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// A connection error: the server is not speaking HTTP/2 properly.
+fn protocol(detail: &str) -> NetworkError {
+    log::debug!("HTTP/2: {}", detail);
+    NetworkError::Http2(Http2Failure::Connection {
+        code: PROTOCOL_ERROR,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    /// Frames a server read, in order.
+    type Frames = Vec<(u8, u8, u32, Vec<u8>)>;
+
+    async fn read_frame(server: &mut DuplexStream) -> (u8, u8, u32, Vec<u8>) {
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        server.read_exact(&mut header).await.expect("frame header");
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let mut payload = vec![0u8; len];
+        server.read_exact(&mut payload).await.expect("payload");
+        let stream = u32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+        (header[3], header[4], stream, payload)
+    }
+
+    /// Serve one h2 exchange: read the client's frames up to the end of
+    /// its request, then send `response`. Returns what the client sent.
+    fn h2_server(mut server: DuplexStream, response: Vec<u8>) -> tokio::task::JoinHandle<Frames> {
+        tokio::spawn(async move {
+            let mut preface = [0u8; 24];
+            server.read_exact(&mut preface).await.expect("preface");
+            assert_eq!(preface, PREFACE);
+
+            let mut frames = Frames::new();
+            loop {
+                let frame = read_frame(&mut server).await;
+                let done = matches!(frame.0, HEADERS | DATA) && frame.1 & FLAG_END_STREAM != 0;
+                frames.push(frame);
+                if done {
+                    break;
+                }
+            }
+            server.write_all(&response).await.expect("response");
+            // Keep reading so the client's acks and window updates land
+            let mut sink = Vec::new();
+            let _ = server.read_to_end(&mut sink).await;
+            frames
+        })
+    }
+
+    /// A server response: SETTINGS, then HEADERS and DATA on `stream`.
+    fn response(stream: u32, block: &[u8], data: &[&[u8]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_frame(&mut out, SETTINGS, 0, 0, &[0, 4, 0, 0, 0xff, 0xff]);
+        push_frame(&mut out, SETTINGS, FLAG_ACK, 0, &[]);
+        let flags = if data.is_empty() {
+            FLAG_END_HEADERS | FLAG_END_STREAM
+        } else {
+            FLAG_END_HEADERS
+        };
+        push_frame(&mut out, HEADERS, flags, stream, block);
+        for (i, piece) in data.iter().enumerate() {
+            let flags = if i + 1 == data.len() {
+                FLAG_END_STREAM
+            } else {
+                0
+            };
+            push_frame(&mut out, DATA, flags, stream, piece);
+        }
+        out
+    }
+
+    fn request<'a>(headers: &'a [(String, String)], body: Option<&'a [u8]>) -> Http2Request<'a> {
+        Http2Request {
+            method: if body.is_some() { "POST" } else { "GET" },
+            authority: "example.com",
+            path: "/index.html?q=1",
+            headers,
+            body,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_start_matches_fingerprint() {
+        let fingerprint = Http2Fingerprint::default();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        // :status 200 (indexed), then a literal content-type
+        let mut block = vec![0x88];
+        block.extend_from_slice(&encode_headers(&[(
+            "content-type".to_string(),
+            "text/html".to_string(),
+        )]));
+        let server = h2_server(server, response(15, &block, &[b"<html>", b"</html>"]));
+
+        let headers = vec![
+            ("Accept".to_string(), "text/html".to_string()),
+            ("Host".to_string(), "example.com".to_string()),
+            ("User-Agent".to_string(), "Mozilla/5.0".to_string()),
+            ("Connection".to_string(), "keep-alive".to_string()),
+        ];
+        let response = exchange(
+            client,
+            &fingerprint,
+            &request(&headers, None),
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+        .expect("response");
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.headers,
+            [("content-type".to_string(), "text/html".to_string())]
+        );
+        assert_eq!(response.body, b"<html></html>");
+
+        let frames = server.await.expect("server");
+        // SETTINGS: exactly the fingerprint's, in its order
+        let (kind, flags, stream, payload) = &frames[0];
+        assert_eq!((*kind, *flags, *stream), (SETTINGS, 0, 0));
+        let settings: Vec<(u16, u32)> = payload
+            .chunks_exact(6)
+            .map(|s| {
+                (
+                    u16::from_be_bytes([s[0], s[1]]),
+                    u32::from_be_bytes([s[2], s[3], s[4], s[5]]),
+                )
+            })
+            .collect();
+        assert_eq!(settings, fingerprint.settings);
+
+        assert_eq!(
+            frames[1],
+            (
+                WINDOW_UPDATE,
+                0,
+                0,
+                fingerprint.window_update.to_be_bytes().to_vec()
+            )
+        );
+        let priorities: Vec<(u32, Vec<u8>)> = frames[2..8]
+            .iter()
+            .map(|(kind, _, stream, payload)| {
+                assert_eq!(*kind, PRIORITY);
+                (*stream, payload.clone())
+            })
+            .collect();
+        let expected: Vec<(u32, Vec<u8>)> = fingerprint
+            .priority_frames
+            .iter()
+            .map(|(stream, priority)| (*stream, priority_field(priority).to_vec()))
+            .collect();
+        assert_eq!(priorities, expected);
+
+        // The request itself, on the first stream past the groups
+        let (kind, flags, stream, payload) = &frames[8];
+        assert_eq!((*kind, *stream), (HEADERS, 15));
+        assert_eq!(*flags, FLAG_PRIORITY | FLAG_END_HEADERS | FLAG_END_STREAM);
+        assert_eq!(payload[..5], priority_field(&fingerprint.priority));
+        let sent = Decoder::new(4096)
+            .decode(&payload[5..], 16, 4096)
+            .expect("decodes");
+        let names: Vec<&str> = sent.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                ":method",
+                ":path",
+                ":authority",
+                ":scheme",
+                "user-agent",
+                "accept"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_and_response_limits() {
+        let fingerprint = Http2Fingerprint::default();
+
+        // A body larger than one frame goes out in pieces
+        let (client, server) = tokio::io::duplex(256 * 1024);
+        // :status 204, indexed
+        let server = h2_server(server, response(15, &[0x89], &[]));
+        let upload = vec![7u8; 40_000];
+        let uploaded = exchange(
+            client,
+            &fingerprint,
+            &request(&[], Some(&upload)),
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+        .expect("response");
+        assert_eq!(uploaded.status, 204);
+        assert!(uploaded.body.is_empty());
+        let frames = server.await.expect("server");
+        let data: usize = frames
+            .iter()
+            .filter(|(kind, ..)| *kind == DATA)
+            .map(|(.., payload)| payload.len())
+            .sum();
+        assert_eq!(data, upload.len());
+        assert!(frames
+            .iter()
+            .filter(|(kind, ..)| *kind == DATA)
+            .all(|(.., payload)| payload.len() <= DEFAULT_MAX_FRAME));
+
+        // The body cap holds for DATA as for HTTP/1.1
+        let (client, server) = tokio::io::duplex(256 * 1024);
+        let chunk = vec![1u8; 1000];
+        let _server = h2_server(server, response(15, &[0x88], &[&chunk, &chunk]));
+        let error = exchange(
+            client,
+            &fingerprint,
+            &request(&[], None),
+            &ResponseLimits::default(),
+            ByteSize::bytes(1500),
+        )
+        .await
+        .expect_err("too large");
+        assert!(matches!(
+            error,
+            NetworkError::ResponseTooLarge {
+                limit: 1500,
+                received: 2000
+            }
+        ));
+
+        // A server that gives up on h2 at once leaves the request eligible
+        // for the HTTP/1.1 retry
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut goaway = Vec::new();
+        push_frame(&mut goaway, GOAWAY, 0, 0, &[0, 0, 0, 0, 0, 0, 0, 1]);
+        let _server = h2_server(server, goaway);
+        let error = exchange(
+            client,
+            &fingerprint,
+            &request(&[], None),
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+        .expect_err("goaway");
+        assert!(crate::protocol_fallback::fallback_eligible(&error));
+    }
+}
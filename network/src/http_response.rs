@@ -0,0 +1,754 @@
+//! HTTP/1.1 response parsing.
+//!
+//! Reads what comes back for a request built by `build_http_request`:
+//!
+//! - status line `HTTP/1.x NNN reason`; anything else is rejected
+//! - headers, within `ResponseLimits` for total size, count and the size
+//!   of each value; obsolete line folding is rejected rather than unfolded
+//! - interim 1xx responses are skipped; 101 is refused since we never ask
+//!   to switch protocols
+//! - no body for HEAD, 204 and 304
+//! - otherwise the body is delimited by chunked transfer-encoding (trailers
+//!   are read and discarded), then Content-Length, then connection close
+//!
+//! A response with both Transfer-Encoding and Content-Length is framed by
+//! the former and the latter is dropped, so a smuggled length never
+//! reaches the caller.
+//!
+//! `read_response` buffers the whole body. `read_head` stops after the
+//! head and leaves the body to a `BodyReader`, which hands it over piece
+//! by piece as it arrives, for `AnonymizedNetwork::request_streaming`.
+//!
+//! The body is held to `max_body` while it is read: a Content-Length over
+//! it is refused before anything is buffered, and chunked or close-delimited
+//! bodies stop one byte past it. Content codings are not decoded here, so
+//! the cap counts the bytes a decoder would start from; anything that
+//! inflates a body has to hold its output to the same cap.
+//!
+//! `Circuit::request` reads its response here from the TLS stream.
+
+use forloop_config::ByteSize;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use crate::circuit::{RawResponse, ResponseStream};
+use crate::retry::CircuitFailure;
+use crate::timing::Phases;
+use crate::NetworkError;
+
+/// Limits applied while reading a response head.
+///
+/// Reading stops at the first limit crossed, with
+/// `NetworkError::HeadersTooLarge`; nothing past it is buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseLimits {
+    /// Status line, headers and trailers together
+    pub max_header_bytes: ByteSize,
+    /// Header and trailer fields together
+    pub max_headers: usize,
+    /// Any one header or trailer value
+    pub max_header_value: ByteSize,
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: ByteSize::kib(64),
+            max_headers: 100,
+            max_header_value: ByteSize::kib(8),
+        }
+    }
+}
+
+/// Which of the `ResponseLimits` a response head crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderLimit {
+    /// `max_headers`
+    Count,
+    /// `max_header_bytes`
+    TotalSize,
+    /// `max_header_value`
+    ValueSize,
+}
+
+impl std::fmt::Display for HeaderLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HeaderLimit::Count => "header count",
+            HeaderLimit::TotalSize => "header block size",
+            HeaderLimit::ValueSize => "header value size",
+        })
+    }
+}
+
+/// Read one response to a `method` request from `reader`, with a body of
+/// at most `max_body` bytes.
+///
+/// A body over the cap fails with `NetworkError::ResponseTooLarge` and
+/// whatever was read of it is dropped. The stream is then mid-response
+/// and must not be reused.
+pub async fn read_response<R>(
+    reader: &mut R,
+    method: &str,
+    limits: &ResponseLimits,
+    max_body: ByteSize,
+) -> Result<RawResponse, NetworkError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut head = read_head(reader, method, limits, max_body).await?;
+    let mut body = Vec::new();
+    while let Some(chunk) = head.body.next_chunk().await? {
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(RawResponse {
+        status: head.status,
+        headers: head.headers,
+        body,
+        phases: Phases::default(),
+    })
+}
+
+/// A response head, with its body still on the connection.
+pub(crate) struct ResponseHead<R> {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: BodyReader<R>,
+}
+
+/// Read the head of a response to a `method` request from `reader`,
+/// leaving the body to be read as it arrives.
+///
+/// A Content-Length over `max_body` is refused here, before any of the
+/// body is read.
+pub(crate) async fn read_head<R>(
+    mut reader: R,
+    method: &str,
+    limits: &ResponseLimits,
+    max_body: ByteSize,
+) -> Result<ResponseHead<R>, NetworkError>
+where
+    R: AsyncBufRead + Unpin,
+{
+    // A reset before the first byte is the circuit's doing, and safe to
+    // retry since nothing of the response was seen
+    if let Err(e) = reader.fill_buf().await {
+        return Err(match e.kind() {
+            std::io::ErrorKind::ConnectionReset => {
+                NetworkError::CircuitFailed(CircuitFailure::ConnectionReset)
+            }
+            _ => NetworkError::RequestFailed(e.to_string()),
+        });
+    }
+
+    let mut budget = HeaderBudget::new(limits);
+    let (status, mut headers) = loop {
+        let status = parse_status_line(&budget.read_line(&mut reader).await?)?;
+        let headers = read_fields(&mut reader, &mut budget).await?;
+        match status {
+            101 => return Err(invalid("unexpected 101 Switching Protocols")),
+            100..=199 => continue,
+            _ => break (status, headers),
+        }
+    };
+
+    let bodyless = method.eq_ignore_ascii_case("HEAD") || status == 204 || status == 304;
+    let framing = if bodyless {
+        Framing::Done
+    } else if is_chunked(&headers)? {
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case("content-length"));
+        Framing::Chunked {
+            left: 0,
+            started: false,
+        }
+    } else if let Some(len) = content_length(&headers)? {
+        if len > max_body.get() as u64 {
+            return Err(body_too_large(max_body, len));
+        }
+        Framing::Length(len)
+    } else {
+        Framing::Close
+    };
+
+    Ok(ResponseHead {
+        status,
+        headers,
+        body: BodyReader {
+            reader,
+            framing,
+            received: 0,
+            max_body,
+            limits: *limits,
+            trailers: budget,
+        },
+    })
+}
+
+/// Present a response that was read in full, as HTTP/2 ones are, as a
+/// head with its body still to read.
+pub(crate) fn buffered_head(
+    response: RawResponse,
+    limits: &ResponseLimits,
+) -> ResponseHead<ResponseStream> {
+    let len = response.body.len() as u64;
+    ResponseHead {
+        status: response.status,
+        headers: response.headers,
+        body: BodyReader {
+            reader: Box::new(std::io::Cursor::new(response.body)),
+            framing: Framing::Length(len),
+            received: 0,
+            max_body: ByteSize::bytes(len as usize),
+            limits: *limits,
+            trailers: HeaderBudget::new(limits),
+        },
+    }
+}
+
+/// How the rest of a body is delimited.
+enum Framing {
+    /// Chunked, with `left` bytes of the current chunk still to read
+    Chunked { left: usize, started: bool },
+    /// Content-Length, with this many bytes still to read
+    Length(u64),
+    /// Until the connection closes
+    Close,
+    /// Nothing left to read
+    Done,
+}
+
+/// Reads a response body piece by piece, as it comes off the connection.
+pub(crate) struct BodyReader<R> {
+    reader: R,
+    framing: Framing,
+    received: u64,
+    max_body: ByteSize,
+    limits: ResponseLimits,
+    trailers: HeaderBudget,
+}
+
+impl<R: AsyncBufRead + Unpin> BodyReader<R> {
+    /// Read the next piece of the body, or `None` once all of it is read.
+    ///
+    /// Pieces are whatever the connection has buffered, so they arrive as
+    /// the server sends them.
+    pub(crate) async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, NetworkError> {
+        let want = match self.framing {
+            Framing::Done | Framing::Length(0) => {
+                self.framing = Framing::Done;
+                return Ok(None);
+            }
+            Framing::Chunked { left: 0, started } => {
+                if started {
+                    self.read_chunk_end().await?;
+                }
+                let size = self.read_chunk_size().await?;
+                if size == 0 {
+                    // Trailers count against the header limits and are dropped
+                    read_fields(&mut self.reader, &mut self.trailers).await?;
+                    self.framing = Framing::Done;
+                    return Ok(None);
+                }
+                let received = self.received.saturating_add(size as u64);
+                if received > self.max_body.get() as u64 {
+                    return Err(body_too_large(self.max_body, received));
+                }
+                self.framing = Framing::Chunked {
+                    left: size,
+                    started: true,
+                };
+                size as u64
+            }
+            Framing::Chunked { left, .. } => left as u64,
+            Framing::Length(left) => left,
+            // One byte past the cap is enough to know it was crossed
+            Framing::Close => self.max_body.get() as u64 + 1 - self.received,
+        };
+
+        let available = self
+            .reader
+            .fill_buf()
+            .await
+            .map_err(|e| NetworkError::RequestFailed(e.to_string()))?;
+        if available.is_empty() {
+            return match self.framing {
+                Framing::Close => {
+                    self.framing = Framing::Done;
+                    Ok(None)
+                }
+                Framing::Length(_) => Err(invalid("body shorter than Content-Length")),
+                _ => Err(invalid("truncated chunk")),
+            };
+        }
+        let n = available
+            .len()
+            .min(usize::try_from(want).unwrap_or(usize::MAX));
+        let chunk = available[..n].to_vec();
+        self.reader.consume(n);
+        self.received += n as u64;
+
+        match &mut self.framing {
+            Framing::Chunked { left, .. } => *left -= n,
+            Framing::Length(left) => *left -= n as u64,
+            Framing::Close if self.received > self.max_body.get() as u64 => {
+                return Err(body_too_large(self.max_body, self.received));
+            }
+            _ => {}
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Read the size line that starts a chunk.
+    async fn read_chunk_size(&mut self) -> Result<usize, NetworkError> {
+        // Each size line gets a budget of its own, however many chunks
+        let line = HeaderBudget::new(&self.limits)
+            .read_line(&mut self.reader)
+            .await?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        usize::from_str_radix(size, 16)
+            .ok()
+            .filter(|_| !size.is_empty() && size.len() <= 16)
+            .ok_or_else(|| invalid("malformed chunk size"))
+    }
+
+    /// Read the CRLF that ends a chunk's data.
+    async fn read_chunk_end(&mut self) -> Result<(), NetworkError> {
+        let mut crlf = [0u8; 2];
+        self.reader
+            .read_exact(&mut crlf)
+            .await
+            .map_err(|_| invalid("truncated chunk"))?;
+        if crlf != *b"\r\n" {
+            return Err(invalid("chunk not followed by CRLF"));
+        }
+        Ok(())
+    }
+}
+
+/// Bytes and fields left for the head of the response.
+struct HeaderBudget {
+    bytes: usize,
+    fields: usize,
+    limits: ResponseLimits,
+}
+
+impl HeaderBudget {
+    fn new(limits: &ResponseLimits) -> Self {
+        Self {
+            bytes: limits.max_header_bytes.get(),
+            fields: limits.max_headers,
+            limits: *limits,
+        }
+    }
+
+    /// Read one CRLF- or LF-terminated line, without the terminator.
+    async fn read_line<R: AsyncBufRead + Unpin>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<String, NetworkError> {
+        let mut line = Vec::new();
+        let read = reader
+            .take(self.bytes as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await
+            .map_err(|e| NetworkError::RequestFailed(e.to_string()))?;
+        if read > self.bytes {
+            return Err(headers_too_large(
+                HeaderLimit::TotalSize,
+                self.limits.max_header_bytes.get(),
+            ));
+        }
+        if !line.ends_with(b"\n") {
+            return Err(invalid("connection closed in the response head"));
+        }
+        self.bytes -= read;
+
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| invalid("non-UTF-8 response head"))
+    }
+}
+
+/// Parse `HTTP/1.x NNN reason`, returning the status code.
+fn parse_status_line(line: &str) -> Result<u16, NetworkError> {
+    let malformed = || invalid(&format!("malformed status line {:?}", line));
+    let rest = line
+        .strip_prefix("HTTP/1.1 ")
+        .or_else(|| line.strip_prefix("HTTP/1.0 "))
+        .ok_or_else(malformed)?;
+    let code = rest.get(..3).ok_or_else(malformed)?;
+    if !code.bytes().all(|b| b.is_ascii_digit())
+        || !matches!(rest.as_bytes().get(3), None | Some(b' '))
+    {
+        return Err(malformed());
+    }
+    match code.parse() {
+        Ok(status @ 100..=599) => Ok(status),
+        _ => Err(malformed()),
+    }
+}
+
+/// Read header or trailer fields up to the empty line.
+async fn read_fields<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    budget: &mut HeaderBudget,
+) -> Result<Vec<(String, String)>, NetworkError> {
+    let mut fields = Vec::new();
+    loop {
+        let line = budget.read_line(reader).await?;
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        if line.starts_with([' ', '\t']) {
+            return Err(invalid("obsolete header line folding"));
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("header without a colon"))?;
+        if name.is_empty() || name.bytes().any(|b| !b.is_ascii_graphic()) {
+            return Err(invalid("malformed header name"));
+        }
+        if budget.fields == 0 {
+            return Err(headers_too_large(
+                HeaderLimit::Count,
+                budget.limits.max_headers,
+            ));
+        }
+        let value = value.trim();
+        check_header_value(value, &budget.limits)?;
+        budget.fields -= 1;
+        fields.push((name.to_string(), value.to_string()));
+    }
+}
+
+/// Whether the body is chunked. Other transfer codings are refused.
+fn is_chunked(headers: &[(String, String)]) -> Result<bool, NetworkError> {
+    let codings: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty())
+        .collect();
+    match codings.as_slice() {
+        [] => Ok(false),
+        [only] if only == "chunked" => Ok(true),
+        _ => Err(invalid("unsupported transfer-encoding")),
+    }
+}
+
+/// Get the Content-Length, refusing conflicting values.
+fn content_length(headers: &[(String, String)]) -> Result<Option<u64>, NetworkError> {
+    let mut length = None;
+    for (_, value) in headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    {
+        for part in value.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid("malformed Content-Length"));
+            }
+            let parsed: u64 = part
+                .parse()
+                .map_err(|_| invalid("malformed Content-Length"))?;
+            if length.is_some_and(|l| l != parsed) {
+                return Err(invalid("conflicting Content-Length values"));
+            }
+            length = Some(parsed);
+        }
+    }
+    Ok(length)
+}
+
+fn invalid(detail: &str) -> NetworkError {
+    NetworkError::InvalidResponse(detail.to_string())
+}
+
+pub(crate) fn headers_too_large(exceeded: HeaderLimit, limit: usize) -> NetworkError {
+    NetworkError::HeadersTooLarge { exceeded, limit }
+}
+
+/// Refuse a header or trailer value over `max_header_value`.
+pub(crate) fn check_header_value(value: &str, limits: &ResponseLimits) -> Result<(), NetworkError> {
+    let limit = limits.max_header_value.get();
+    if value.len() > limit {
+        return Err(headers_too_large(HeaderLimit::ValueSize, limit));
+    }
+    Ok(())
+}
+
+pub(crate) fn body_too_large(limit: ByteSize, received: u64) -> NetworkError {
+    NetworkError::ResponseTooLarge {
+        limit: limit.get(),
+        received,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(raw: &[u8]) -> Result<RawResponse, NetworkError> {
+        read_response(
+            &mut &raw[..],
+            "GET",
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+    }
+
+    fn header<'a>(response: &'a RawResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_with_trailers() {
+        let response = parse(
+            b"HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 200 OK\r\n\
+              Transfer-Encoding: chunked\r\n\
+              Content-Length: 3\r\n\
+              Content-Type: text/plain\r\n\r\n\
+              5;ext=1\r\nhello\r\n\
+              7\r\n, world\r\n\
+              0\r\n\
+              Expires: never\r\n\r\n",
+        )
+        .await
+        .expect("parses");
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello, world");
+        assert_eq!(header(&response, "content-type"), Some("text/plain"));
+        assert_eq!(header(&response, "content-length"), None);
+        assert_eq!(header(&response, "expires"), None);
+    }
+
+    #[tokio::test]
+    async fn test_many_small_chunks() {
+        const CHUNKS: usize = 30_000;
+        let mut raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for _ in 0..CHUNKS {
+            raw.extend_from_slice(b"1\r\na\r\n");
+        }
+        raw.extend_from_slice(b"0\r\n\r\n");
+
+        // The size lines add up to well over the head limit
+        let response = parse(&raw).await.expect("parses");
+        assert_eq!(response.body, vec![b'a'; CHUNKS]);
+
+        // One size line over it is still refused
+        let mut raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1;".to_vec();
+        raw.extend(vec![b'x'; ResponseLimits::default().max_header_bytes.get()]);
+        raw.extend_from_slice(b"\r\na\r\n0\r\n\r\n");
+        assert!(matches!(
+            parse(&raw).await,
+            Err(NetworkError::HeadersTooLarge {
+                exceeded: HeaderLimit::TotalSize,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_body_framing() {
+        // No Content-Length: read until close
+        let response = parse(b"HTTP/1.0 200 OK\r\n\r\nall of it")
+            .await
+            .expect("parses");
+        assert_eq!(response.body, b"all of it");
+
+        let response = parse(b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabcdef")
+            .await
+            .expect("parses");
+        assert_eq!(response.body, b"abc");
+
+        for bodyless in [
+            &b"HTTP/1.1 204 No Content\r\nContent-Length: 5\r\n\r\n"[..],
+            b"HTTP/1.1 304 Not Modified\r\n\r\n",
+        ] {
+            assert!(parse(bodyless).await.expect("parses").body.is_empty());
+        }
+        let head = read_response(
+            &mut &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"[..],
+            "HEAD",
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+        .expect("parses");
+        assert!(head.body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_responses_rejected() {
+        for raw in [
+            &b"HTTP/2 200\r\n\r\n"[..],
+            b"HTTP/1.1 20 OK\r\n\r\n",
+            b"HTTP/1.1 2000 OK\r\n\r\n",
+            b"ICY 200 OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\n folded\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+            b"HTTP/1.1 101 Switching Protocols\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nServer: x",
+        ] {
+            assert!(
+                matches!(parse(raw).await, Err(NetworkError::InvalidResponse(_))),
+                "accepted {:?}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_headers_rejected() {
+        let limits = ResponseLimits {
+            max_header_bytes: ByteSize::bytes(64),
+            max_headers: 2,
+            max_header_value: ByteSize::bytes(16),
+        };
+        let read = |raw: &'static [u8]| async move {
+            read_response(&mut &raw[..], "GET", &limits, ByteSize::bytes(8)).await
+        };
+
+        let long = b"HTTP/1.1 200 OK\r\nX-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n\r\n";
+        let many = b"HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+        let wide = b"HTTP/1.1 200 OK\r\nA: aaaaaaaaaaaaaaaaa\r\n\r\n";
+        for (raw, exceeded, limit) in [
+            (&long[..], HeaderLimit::TotalSize, 64),
+            (&many[..], HeaderLimit::Count, 2),
+            (&wide[..], HeaderLimit::ValueSize, 16),
+        ] {
+            let error = read(raw).await.expect_err("over the limit");
+            assert!(
+                matches!(error, NetworkError::HeadersTooLarge { exceeded: e, limit: l } if e == exceeded && l == limit),
+                "{}",
+                error
+            );
+        }
+        assert!(read(b"HTTP/1.1 200 OK\r\nA: 1\r\nB: 2\r\n\r\n")
+            .await
+            .is_ok());
+    }
+
+    /// A server that sends `head` and then repeats `body` forever,
+    /// counting what it has handed out.
+    struct Endless {
+        head: &'static [u8],
+        body: &'static [u8],
+        served: usize,
+    }
+
+    impl tokio::io::AsyncRead for Endless {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let source = match self.head.get(self.served..) {
+                Some(rest) if !rest.is_empty() => rest,
+                _ => {
+                    let offset = (self.served - self.head.len()) % self.body.len();
+                    &self.body[offset..]
+                }
+            };
+            let n = source.len().min(buf.remaining());
+            buf.put_slice(&source[..n]);
+            self.served += n;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pathological_heads_stop_at_the_limit() {
+        let limits = ResponseLimits::default();
+        let head_cap = limits.max_header_bytes.get();
+        // Endless tiny headers, and one header that never ends
+        for (head, body, exceeded) in [
+            (
+                &b"HTTP/1.1 200 OK\r\n"[..],
+                &b"A: 1\r\n"[..],
+                HeaderLimit::Count,
+            ),
+            (b"HTTP/1.1 200 OK\r\nX: ", b"a", HeaderLimit::TotalSize),
+        ] {
+            let mut server = tokio::io::BufReader::new(Endless {
+                head,
+                body,
+                served: 0,
+            });
+            let error = read_response(&mut server, "GET", &limits, ByteSize::kib(1))
+                .await
+                .expect_err("over the limit");
+
+            match error {
+                NetworkError::HeadersTooLarge { exceeded: e, .. } if e == exceeded => {}
+                other => panic!("unexpected error {:?}", other),
+            }
+            // Reading stopped at the limit, one buffer fill at most past it
+            assert!(
+                server.get_ref().served <= head_cap + 8 * 1024,
+                "read {} bytes for a {} byte head cap",
+                server.get_ref().served,
+                head_cap
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oversized_streamed_body_stops_at_the_cap() {
+        const LIMIT: usize = 64 * 1024;
+        for (head, body) in [
+            (
+                &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n"[..],
+                &b"10\r\naaaaaaaaaaaaaaaa\r\n"[..],
+            ),
+            (b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n", b"a"),
+            (
+                b"HTTP/1.1 200 OK\r\nContent-Length: 1099511627776\r\n\r\n",
+                b"a",
+            ),
+        ] {
+            let mut server = tokio::io::BufReader::new(Endless {
+                head,
+                body,
+                served: 0,
+            });
+            let error = read_response(
+                &mut server,
+                "GET",
+                &ResponseLimits::default(),
+                ByteSize::bytes(LIMIT),
+            )
+            .await
+            .expect_err("over the cap");
+
+            match error {
+                NetworkError::ResponseTooLarge { limit, received } => {
+                    assert_eq!(limit, LIMIT);
+                    assert!(received > LIMIT as u64);
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+            // Reading stopped at the cap rather than draining the server;
+            // chunk framing is the only overhead
+            assert!(
+                server.get_ref().served < 2 * LIMIT,
+                "read {} bytes for a {} byte cap",
+                server.get_ref().served,
+                LIMIT
+            );
+        }
+    }
+}
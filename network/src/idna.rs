@@ -0,0 +1,160 @@
+//! Hostnames to their ASCII form, for the Host header, SNI and SOCKS.
+//!
+//! Labels are lowercased and any with non-ASCII characters become
+//! Punycode "xn--" labels (RFC 3492). This is IDNA's ToASCII without the
+//! UTS 46 mapping table: hosts that need more than lowercasing to be
+//! valid are refused rather than guessed at.
+
+/// Dots IDNA treats as label separators.
+const DOTS: [char; 4] = ['.', '\u{3002}', '\u{ff0e}', '\u{ff61}'];
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Longest label and name DNS allows.
+const MAX_LABEL: usize = 63;
+const MAX_NAME: usize = 253;
+
+/// Get the ASCII form of `host`, or `None` if it cannot be a hostname.
+///
+/// A trailing dot is dropped. Only letters, digits, '-' and '_' may remain
+/// in a label once it is encoded, and no label may hold whitespace or
+/// control characters before it is.
+pub(crate) fn domain_to_ascii(host: &str) -> Option<String> {
+    let host = host.strip_suffix(DOTS).unwrap_or(host);
+    let mut labels = Vec::new();
+    for label in host.split(DOTS) {
+        let label: String = label.chars().flat_map(char::to_lowercase).collect();
+        let label = if label.is_ascii() {
+            label
+        } else if label.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return None;
+        } else {
+            format!("xn--{}", punycode(&label)?)
+        };
+        let valid = label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if label.is_empty() || label.len() > MAX_LABEL || !valid {
+            return None;
+        }
+        labels.push(label);
+    }
+    let ascii = labels.join(".");
+    (ascii.len() <= MAX_NAME).then_some(ascii)
+}
+
+/// Punycode-encode one label (RFC 3492 section 6.3).
+fn punycode(label: &str) -> Option<String> {
+    let input: Vec<u32> = label.chars().map(u32::from).collect();
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len();
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while handled < input.len() {
+        let m = input.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled as u32 + 1)?)?;
+        n = m;
+        for &c in &input {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c != n {
+                continue;
+            }
+            let mut q = delta;
+            let mut k = BASE;
+            loop {
+                let t = k.saturating_sub(bias).clamp(T_MIN, T_MAX);
+                if q < t {
+                    break;
+                }
+                output.push(digit(t + (q - t) % (BASE - t)));
+                q = (q - t) / (BASE - t);
+                k += BASE;
+            }
+            output.push(digit(q));
+            bias = adapt(delta, handled as u32 + 1, handled == basic);
+            delta = 0;
+            handled += 1;
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    Some(output)
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(punycode("münchen").as_deref(), Some("mnchen-3ya"));
+        assert_eq!(
+            punycode("ドメイン名例").as_deref(),
+            Some("eckwd4c7cu47r2wf")
+        );
+        assert_eq!(
+            punycode("правительство").as_deref(),
+            Some("80aealotwbjpid2k")
+        );
+
+        assert_eq!(
+            domain_to_ascii("Bücher.Example").as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(
+            domain_to_ascii("例え。テスト").as_deref(),
+            Some("xn--r8jz45g.xn--zckzah")
+        );
+        assert_eq!(domain_to_ascii("ÖBB.at").as_deref(), Some("xn--bb-eka.at"));
+        assert_eq!(
+            domain_to_ascii("WWW.Example.COM.").as_deref(),
+            Some("www.example.com")
+        );
+
+        for bad in [
+            "",
+            "a..b",
+            "exa mple.com",
+            "a/b",
+            "a%2eb",
+            "a:b",
+            "é\u{a0}.com",
+        ] {
+            assert_eq!(domain_to_ascii(bad), None, "{:?}", bad);
+        }
+        assert_eq!(domain_to_ascii(&"a".repeat(64)), None);
+        assert_eq!(domain_to_ascii(&["a"; 128].join(".")), None);
+    }
+}
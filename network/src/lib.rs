@@ -16,56 +16,279 @@
 #![deny(missing_docs)]
 #![forbid(clippy::unwrap_used)]
 
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use circuit::{parse_url, RawResponse};
+use events::EVENT_CHANNEL_CAPACITY;
+use forloop_config::{ByteSize, Port, TIMING_PRECISION};
+use forloop_fingerprint::SyntheticIdentity;
+use sanitize::sanitize_response;
+use streaming::pump_body;
+use tokio::sync::broadcast;
+
+mod backend;
+mod bootstrap;
+mod bridges;
+mod build_limit;
+mod challenge;
+mod churn;
 mod circuit;
+mod circuit_pool;
+mod client_hello;
+mod control;
+mod control_protocol;
+mod digest;
+mod download_manager;
+mod downloads;
+mod events;
+mod frames;
+mod geoip;
 mod headers;
+mod hpack;
+mod html_tokens;
+mod http2;
+mod http_response;
+mod idna;
+mod metrics;
+mod navigation;
+mod onion_alternatives;
+mod onion_connect;
 mod padding;
+mod page_circuits;
+mod policy;
+mod ports;
+mod protocol_fallback;
+mod redirects;
+mod response_headers;
+mod retry;
+mod sanitize;
+mod scheduler;
+mod secret;
+mod self_check;
+mod snowflake;
+mod socks;
+mod streaming;
+mod tasks;
+#[cfg(test)]
+mod temp_dir;
+mod text_extract;
+mod timing;
 mod tls_fingerprint;
+mod tls_handshake;
+mod tor_events;
 mod tor_integration;
+mod tor_process;
 mod traffic_shaper;
+mod transports;
+mod upload;
+mod verify;
+mod watchdog;
 
+pub use backend::{BackendFuture, TorBackend, TorStream};
+pub use bootstrap::{
+    BackoffPolicy, BootstrapFailure, BootstrapSupervisor, Bootstrapper, SuggestedAction,
+    SupervisorCancel, SupervisorEvent,
+};
+pub use bridges::{
+    parse_bridge_lines, BridgeLine, BridgeLineError, BridgeParseError, BridgeTransport,
+};
+pub use build_limit::{BuildLimiter, BUILD_QUEUE_TIMEOUT, MAX_CONCURRENT_CIRCUIT_BUILDS};
+pub use challenge::{
+    check_challenge, strip_challenge_headers, CHALLENGE_HEADERS, CREDENTIAL_HEADERS,
+};
+pub use churn::{ChurnGuard, ChurnLimits, ChurnVerdict};
 pub use circuit::{Circuit, CircuitManager};
-pub use headers::{HeaderSynthesizer, SyntheticHeaders};
+pub use circuit_pool::{CircuitPool, PoolPolicy};
+pub use client_hello::{
+    build_client_hello, parse_client_hello, server_name_for, ClientHelloSummary, HelloRandom,
+};
+pub use control::{
+    read_auth_cookie, read_cookie_file, ControlChannel, ScrubBuffer, AUTH_COOKIE_FILE,
+};
+pub use control_protocol::{
+    bootstrap_progress, circuit_path, newest_built_circuit, open_authenticated, relay_address,
+    BootstrapProgress, ControlConnection, ControlReply, ControlStream, ProtocolInfo,
+};
+pub use download_manager::{
+    download_name, tmpfs_capacity, Download, DownloadBudget, DownloadManager, DownloadProgress,
+    DownloadState, DEFAULT_DOWNLOAD_NAME,
+};
+pub use downloads::{
+    download_file_name, is_pdf, Downloader, PdfStats, ResponseRouter, Routed, PDF_SIGNATURE,
+};
+pub use events::{ConnectionSecurity, ErrorClass, IdentityPhase, NetworkEvent, TorState};
+pub use frames::{origin_of, Frame, FrameId, FrameSandbox, PageContext, RequestKeys};
+pub use geoip::{GeoIp, UNKNOWN_COUNTRY};
+pub use headers::{
+    check_header, is_tracking_param, normalize_header_order, strip_dangerous_headers,
+    strip_tracking_params, BrowserProfile, Destination, FetchSite, HeaderSynthesizer, Platform,
+    SyntheticHeaders, ACCEPT_ENCODING, ACCEPT_LANGUAGES, DANGEROUS_HEADERS, TRACKING_PARAMS,
+    TRACKING_PARAM_PREFIXES,
+};
+pub use http_response::{read_response, HeaderLimit, ResponseLimits};
+pub use metrics::{MetricsSnapshot, NetworkMetrics};
+pub use navigation::{
+    Connector, NavigationPipeline, NavigationStep, NavigationTarget, ReadyNavigation,
+};
+pub use onion_alternatives::{
+    onion_alternative, InterstitialChoice, OnionInterstitial, INTERSTITIAL_URL, ONION_ALTERNATIVES,
+};
+pub use onion_connect::{OnionConnectTracker, OnionPhase, OnionRetry, OnionTimeout};
 pub use padding::PaddingGenerator;
-pub use tls_fingerprint::TlsFingerprintNormalizer;
-pub use tor_integration::TorController;
-pub use traffic_shaper::TrafficShaper;
+pub use page_circuits::IsolationKey;
+pub use policy::{validate_request, NetworkRequestMsg, PolicyViolation, ValidatedRequest};
+pub use ports::{port_allowed, ALLOWED_PORTS, BLOCKED_PORTS};
+pub use protocol_fallback::{
+    fallback_eligible, http11_only, request_with_fallback, Http2Failure, ProtocolMemo,
+    RequestMetrics, HTTP11_ALPN,
+};
+pub use redirects::{
+    is_redirect, redirect_method, resolve_location, RedirectChain, RedirectPolicy,
+    RedirectRefusal, RedirectStep, RedirectTracker,
+};
+pub use response_headers::{
+    normalize_response_headers, CANONICAL_RESPONSE_ORDER, MAX_RESPONSE_HEADERS,
+    MAX_RESPONSE_HEADER_BYTES, NORMALIZED_CACHE_CONTROL, RESOURCE_HINT_RELS,
+    STRIPPED_RESPONSE_HEADERS,
+};
+pub use retry::{retry_on_new_circuit, CircuitFailure, RetryPolicy};
+pub use sanitize::{
+    is_html, sanitize_html, HtmlSanitizer, MetaRefresh, SanitizeReport, SanitizeStats,
+};
+pub use scheduler::{HttpVersion, QueueState, RequestScheduler, ResourceKind, ScheduledRequest};
+pub use secret::SecretBytes;
+pub use self_check::{self_check, HealthReport, CHECK_ENDPOINTS};
+pub use snowflake::{
+    snowflake_bridges, SNOWFLAKE_BRIDGES, SNOWFLAKE_BROKER, SNOWFLAKE_FRONTS,
+    SNOWFLAKE_STUN_SERVERS, SNOWFLAKE_UTLS_IMITATE,
+};
+pub use socks::{IsolationToken, SocksEndpoint, SocksReplyCode};
+pub use streaming::{ResponseBody, StreamingResponse, BODY_CHANNEL_DEPTH};
+pub use tasks::{TaskCancel, TaskRegistry, TaskScope};
+pub use text_extract::{
+    extract_text, render_reader, ExtractedText, TextExtractor, MAX_EXTRACTED_REFERENCES,
+    MAX_EXTRACTED_TEXT,
+};
+pub use timing::RequestTiming;
+pub use tls_fingerprint::{
+    is_grease, FingerprintMismatch, Http2Fingerprint, Http2Priority, TlsConfig,
+    TlsFingerprintNormalizer, TlsVersion,
+};
+pub use tls_handshake::{handshake, ServerHello, TlsFailure, TlsFailureReason};
+pub use tor_events::{
+    FailureHint, HsDescAction, StreamStatus, TorEvent, TorHealth, TorHealthStatus,
+    SETEVENTS_COMMAND,
+};
+pub use tor_integration::{
+    TorConfig, TorController, GUARD_LABEL, IDENTITY_DEADLINE, MAX_RECONNECT_BACKOFF,
+    NEWNYM_INTERVAL, RECONNECT_BACKOFF, REDACT_GUARD_COUNTRY,
+};
+pub use tor_process::{TorLog, TorProcess, TOR_BINARY, TOR_STARTUP_DEADLINE};
+pub use traffic_shaper::{
+    normalize_size, strip_padding, ShapedReader, ShapedWriter, TrafficShaper, MAX_BURST,
+    MIN_PADDED_REQUEST, PADDING_HEADER, SIZE_BUCKETS,
+};
+pub use transports::{PluggableTransport, TransportManager, PT_SEARCH_PATHS};
+pub use upload::{
+    check_request_size, send_chunked, BodyStream, ProgressSink, RequestBody, UploadCancel,
+    UploadProgress, UploadTransport, STREAM_CHUNK_BYTES, UPLOAD_CHUNK_BYTES,
+};
+pub use verify::{
+    compare_bodies, compare_pages, normalize_body, verify_page, Consistency, ConsistencyReport,
+    FetchedPage, PageFetcher,
+};
+pub use watchdog::{CircuitActivity, CircuitWatchdog, WatchdogPolicy, MAX_CONCURRENT_CIRCUITS};
 
 /// Network layer configuration.
 /// All values are compile-time defaults with no runtime override.
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
-    /// Minimum padding bytes per request
-    pub min_padding_bytes: usize,
-    /// Maximum padding bytes per request
-    pub max_padding_bytes: usize,
+    /// Minimum padding per request
+    pub min_padding: ByteSize,
+    /// Maximum padding per request
+    pub max_padding: ByteSize,
     /// Minimum jitter delay
-    pub min_jitter_ms: u64,
+    pub min_jitter: Duration,
     /// Maximum jitter delay
-    pub max_jitter_ms: u64,
+    pub max_jitter: Duration,
+    /// Deliver response bytes in chunks with random delays between
+    pub shape_response_reads: bool,
+    /// Minimum delay between response chunks when shaping reads
+    pub min_read_delay: Duration,
+    /// Maximum delay between response chunks when shaping reads
+    pub max_read_delay: Duration,
     /// Tor SOCKS5 port (embedded tor)
-    pub tor_socks_port: u16,
+    pub tor_socks_port: Port,
     /// Tor control port (embedded tor)
-    pub tor_control_port: u16,
-    /// Request timeout
+    pub tor_control_port: Port,
+    /// Request timeout (first byte, clearnet)
     pub request_timeout: Duration,
+    /// First-byte timeout for .onion destinations, which need a rendezvous
+    pub onion_first_byte_timeout: Duration,
     /// Force new circuit per request
     pub new_circuit_per_request: bool,
+    /// Maximum request body size
+    pub max_request_size: ByteSize,
+    /// Maximum response body size, enforced while the body is read
+    pub max_response_bytes: ByteSize,
+    /// Where PDFs are saved instead of rendered (RAM-backed)
+    pub download_dir: PathBuf,
+    /// Space downloads may take up in `download_dir`
+    pub download_budget: DownloadBudget,
+    /// Per-page limits on circuit churn
+    pub churn_limits: ChurnLimits,
+    /// Limits on responses read from the network
+    pub response_limits: ResponseLimits,
+    /// Limits on following redirects
+    pub redirect_policy: RedirectPolicy,
+    /// When a request is retried on a new circuit
+    pub retry_policy: RetryPolicy,
+    /// Circuits built ahead of requests, or `None` to build each on demand
+    pub circuit_pool: Option<PoolPolicy>,
+    /// Circuits built at once; further builds queue for up to
+    /// `request_timeout`
+    pub max_concurrent_circuit_builds: usize,
 }
 
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
-            min_padding_bytes: 256,
-            max_padding_bytes: 2048,
-            min_jitter_ms: 0,
-            max_jitter_ms: 50,
-            tor_socks_port: 9150,
-            tor_control_port: 9151,
+            min_padding: ByteSize::bytes(256),
+            max_padding: ByteSize::bytes(2048),
+            min_jitter: Duration::ZERO,
+            max_jitter: Duration::from_millis(50),
+            shape_response_reads: false,
+            min_read_delay: Duration::ZERO,
+            max_read_delay: Duration::from_millis(20),
+            tor_socks_port: Port::new(9150),
+            tor_control_port: Port::new(9151),
             request_timeout: Duration::from_secs(60),
+            onion_first_byte_timeout: Duration::from_secs(120),
             new_circuit_per_request: true, // MUST be true, non-configurable in practice
+            max_request_size: ByteSize::mib(100),
+            max_response_bytes: ByteSize::mib(50),
+            download_dir: forloop_config::get_temp_download_dir(),
+            download_budget: DownloadBudget::default(),
+            churn_limits: ChurnLimits::default(),
+            response_limits: ResponseLimits::default(),
+            redirect_policy: RedirectPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            circuit_pool: Some(PoolPolicy::default()),
+            max_concurrent_circuit_builds: MAX_CONCURRENT_CIRCUIT_BUILDS,
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// Get the first-byte timeout for a request to `url`.
+    pub fn first_byte_timeout(&self, url: &str) -> Duration {
+        match ConnectionSecurity::of_url(url) {
+            ConnectionSecurity::Onion => self.onion_first_byte_timeout,
+            ConnectionSecurity::Https => self.request_timeout,
         }
     }
 }
@@ -81,6 +304,17 @@ pub struct NetworkResponse {
     pub body: Vec<u8>,
     /// Circuit ID used (for debugging, not exposed to content)
     pub circuit_id: String,
+    /// What the HTML sanitizer changed (HTML documents only)
+    pub html_report: Option<SanitizeReport>,
+    /// Where the body was saved instead of rendered (PDFs only); `body` is
+    /// empty when set
+    pub download: Option<PathBuf>,
+    /// Attempts made and whether the request fell back to HTTP/1.1
+    pub metrics: RequestMetrics,
+    /// Coarse time spent in each phase of the request, if measured
+    pub timing: Option<RequestTiming>,
+    /// Redirects followed to get here
+    pub redirects: RedirectChain,
 }
 
 /// Errors that can occur in the network layer.
@@ -90,10 +324,52 @@ pub enum NetworkError {
     #[error("Tor connection failed: {0}")]
     TorConnectionFailed(String),
 
+    /// Tor's control port answered a command with an error
+    #[error("Control port refused command: {status} {message}")]
+    ControlRejected {
+        /// 4xx or 5xx status code
+        status: u16,
+        /// Text of the error reply
+        message: String,
+    },
+
+    /// The tor binary could not be found
+    #[error("Tor binary not found: {0}")]
+    TorBinaryNotFound(String),
+
+    /// A port tor needs is taken, usually by another tor
+    #[error("Port {0} is already in use; is another Tor running?")]
+    TorPortInUse(Port),
+
+    /// The Tor configuration would not do what it says
+    #[error("Invalid Tor configuration: {0}")]
+    InvalidTorConfig(String),
+
+    /// tor exited before its control port came up
+    #[error("Tor exited during startup: {0}")]
+    TorStartupFailed(String),
+
+    /// A bridge needs a pluggable transport client that is not installed
+    #[error("{transport} bridge configured but {binary} not found")]
+    TransportNotFound {
+        /// Transport the bridge uses
+        transport: String,
+        /// Client it needs
+        binary: String,
+    },
+
     /// Circuit creation failed
     #[error("Circuit creation failed: {0}")]
     CircuitCreationFailed(String),
 
+    /// The circuit failed before any of the response arrived
+    #[error("Circuit failed: {0}")]
+    CircuitFailed(CircuitFailure),
+
+    /// Tor's SOCKS port failed the request with this reply
+    #[error("SOCKS request failed: {0}")]
+    SocksFailure(SocksReplyCode),
+
     /// Request failed
     #[error("Request failed: {0}")]
     RequestFailed(String),
@@ -102,9 +378,25 @@ pub enum NetworkError {
     #[error("Request timed out")]
     Timeout,
 
-    /// TLS error
+    /// Onion service timed out, with what the error page needs
+    #[error("Onion service timed out: {0}")]
+    OnionTimeout(OnionTimeout),
+
+    /// Something between us and Tor asked for proxy credentials
+    #[error("Proxy authentication requested; traffic may be intercepted")]
+    ProxyAuthRequired,
+
+    /// TLS handshake or certificate failure
     #[error("TLS error: {0}")]
-    TlsError(String),
+    TlsError(TlsFailure),
+
+    /// HTTP/2 connection or stream failure
+    #[error("HTTP/2 failure: {0}")]
+    Http2(Http2Failure),
+
+    /// Malformed or oversized HTTP response
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
 
     /// DNS resolution failed
     #[error("DNS resolution failed: {0}")]
@@ -114,9 +406,65 @@ pub enum NetworkError {
     #[error("Invalid URL: {0}")]
     InvalidUrl(String),
 
+    /// Request header that cannot be sent safely
+    #[error("Invalid header: {0}")]
+    InvalidHeader(String),
+
     /// Protocol not supported (only HTTPS)
     #[error("Protocol not supported: {0} (only HTTPS allowed)")]
     ProtocolNotSupported(String),
+
+    /// URL names a port on the `BLOCKED_PORTS` list
+    #[error("Port not allowed: {0} (restricted port)")]
+    PortBlocked(u16),
+
+    /// Request body exceeds the configured cap
+    #[error("Request body too large: {size} bytes (limit {limit})")]
+    RequestTooLarge {
+        /// Body size in bytes
+        size: usize,
+        /// Configured limit in bytes
+        limit: usize,
+    },
+
+    /// Response body exceeds the configured cap
+    #[error("Response body too large: {received} bytes (limit {limit})")]
+    ResponseTooLarge {
+        /// Configured limit in bytes
+        limit: usize,
+        /// Bytes received or announced when the cap was crossed
+        received: u64,
+    },
+
+    /// Response head crossed one of the `ResponseLimits`
+    #[error("Response headers too large: {exceeded} over the limit of {limit}")]
+    HeadersTooLarge {
+        /// Limit that was crossed
+        exceeded: HeaderLimit,
+        /// Its configured value, in fields or bytes
+        limit: usize,
+    },
+
+    /// A download would overrun the space budgeted for downloads
+    #[error("Download too large: {needed} bytes needed (limit {limit})")]
+    DownloadBudgetExceeded {
+        /// Budget in bytes
+        limit: u64,
+        /// Bytes the download would take the file or directory to
+        needed: u64,
+    },
+
+    /// Request was cancelled before it completed
+    #[error("Request cancelled")]
+    Cancelled,
+
+    /// A redirect broke the redirect policy
+    #[error("Redirect refused: {0}")]
+    RedirectRefused(RedirectRefusal),
+
+    /// Request violated the shared request policy
+    #[error("Policy violation: {0}")]
+    PolicyViolation(#[from] PolicyViolation),
 }
 
 /// The main network layer abstraction.
@@ -125,9 +473,32 @@ pub struct AnonymizedNetwork {
     config: NetworkConfig,
     tor_controller: Arc<TorController>,
     circuit_manager: Arc<CircuitManager>,
-    header_synthesizer: HeaderSynthesizer,
-    traffic_shaper: TrafficShaper,
+    header_synthesizer: std::sync::RwLock<HeaderSynthesizer>,
+    traffic_shaper: Arc<TrafficShaper>,
     tls_normalizer: TlsFingerprintNormalizer,
+    protocol_memo: ProtocolMemo,
+    response_router: ResponseRouter,
+    download_manager: DownloadManager,
+    churn_guard: ChurnGuard,
+    events: broadcast::Sender<NetworkEvent>,
+    next_context: AtomicU64,
+    tracking_params_removed: AtomicU64,
+    metrics: Arc<NetworkMetrics>,
+    onion_connects: std::sync::Mutex<HashMap<u64, OnionConnectTracker>>,
+    tasks: Arc<TaskRegistry>,
+    in_flight: std::sync::Mutex<TaskCancel>,
+    identity: tokio::sync::Mutex<()>,
+}
+
+/// Lets requests through again once `new_identity` ends, however it ends.
+struct ReopenRequests<'a>(&'a std::sync::Mutex<TaskCancel>);
+
+impl Drop for ReopenRequests<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.0.lock() {
+            *in_flight = TaskCancel::default();
+        }
+    }
 }
 
 impl AnonymizedNetwork {
@@ -137,44 +508,370 @@ impl AnonymizedNetwork {
         let tor_controller = Arc::new(
             TorController::new(config.tor_socks_port, config.tor_control_port).await?,
         );
+        Ok(Self::from_controller(config, tor_controller))
+    }
 
-        let circuit_manager = Arc::new(CircuitManager::new(Arc::clone(&tor_controller)));
+    /// Create the network layer on a Tor controller the caller connected,
+    /// e.g. to a fake control port, for other crates' tests.
+    ///
+    /// Only compiled for tests, or with the `test-support` feature.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn with_tor_controller(config: NetworkConfig, tor_controller: Arc<TorController>) -> Self {
+        Self::from_controller(config, tor_controller)
+    }
 
-        let header_synthesizer = HeaderSynthesizer::new();
-        let traffic_shaper = TrafficShaper::new(
-            config.min_padding_bytes,
-            config.max_padding_bytes,
-            config.min_jitter_ms,
-            config.max_jitter_ms,
+    /// Create the network layer on a connected Tor controller.
+    ///
+    /// Must be called within a tokio runtime.
+    fn from_controller(config: NetworkConfig, tor_controller: Arc<TorController>) -> Self {
+        let mut traffic_shaper = TrafficShaper::new(
+            config.min_padding,
+            config.max_padding,
+            config.min_jitter,
+            config.max_jitter,
         );
+        if config.shape_response_reads {
+            traffic_shaper =
+                traffic_shaper.with_read_shaping(config.min_read_delay, config.max_read_delay);
+        }
+        let traffic_shaper = Arc::new(traffic_shaper);
+        let metrics = Arc::new(NetworkMetrics::new());
+        let backend: Arc<dyn TorBackend> = tor_controller.clone();
+        let mut circuit_manager = CircuitManager::new(backend)
+            .with_response_limits(config.response_limits, config.max_response_bytes)
+            .with_traffic_shaper(Arc::clone(&traffic_shaper))
+            .with_metrics(Arc::clone(&metrics))
+            .with_build_limit(config.max_concurrent_circuit_builds, config.request_timeout);
+        if let Some(policy) = config.circuit_pool {
+            circuit_manager = circuit_manager.with_pool(CircuitPool::new(policy));
+        }
+        let circuit_manager = Arc::new(circuit_manager);
+
+        let header_synthesizer = std::sync::RwLock::new(HeaderSynthesizer::new());
         let tls_normalizer = TlsFingerprintNormalizer::new();
+        #[cfg(debug_assertions)]
+        tls_normalizer.self_check();
+        let response_router = ResponseRouter::new(Downloader::new(config.download_dir.clone()));
+        let churn_guard = ChurnGuard::new(config.churn_limits);
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        let tasks = Arc::new(TaskRegistry::new());
+        circuit_manager.spawn_sweeper(&tasks);
+        circuit_manager.spawn_reaper(&tasks);
+        circuit_manager.spawn_pool_filler(&tasks);
+        let download_manager = DownloadManager::new(
+            config.download_dir.clone(),
+            config.download_budget,
+            Arc::clone(&tasks),
+        );
+        let state_events = events.clone();
+        tor_controller.spawn_supervisor(&tasks, move |state| {
+            let _ = state_events.send(NetworkEvent::TorStateChanged(state));
+        });
 
-        Ok(Self {
+        Self {
             config,
             tor_controller,
             circuit_manager,
             header_synthesizer,
             traffic_shaper,
             tls_normalizer,
-        })
+            protocol_memo: ProtocolMemo::new(),
+            response_router,
+            download_manager,
+            churn_guard,
+            events,
+            next_context: AtomicU64::new(1),
+            tracking_params_removed: AtomicU64::new(0),
+            metrics,
+            onion_connects: std::sync::Mutex::new(HashMap::new()),
+            tasks,
+            in_flight: std::sync::Mutex::new(TaskCancel::default()),
+            identity: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Total padding bytes added to requests so far.
+    pub fn padding_overhead(&self) -> u64 {
+        self.traffic_shaper.padding_overhead()
+    }
+
+    /// Most circuit builds ever queued at once, for the status display.
+    pub fn peak_circuit_build_queue(&self) -> usize {
+        self.circuit_manager.build_limiter().peak_queue_depth()
+    }
+
+    /// Tracking parameters removed from request URLs so far.
+    pub fn tracking_params_removed(&self) -> u64 {
+        self.tracking_params_removed.load(Ordering::Relaxed)
+    }
+
+    /// Request, byte and circuit totals since startup or the last
+    /// `new_identity`, for the status bar.
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// NEWNYMs held back by Tor's rate limit so far, for the status display.
+    ///
+    /// Circuits made meanwhile were isolated by SOCKS credentials alone.
+    pub fn newnym_suppressed(&self) -> u64 {
+        self.tor_controller.newnym_suppressed()
+    }
+
+    /// Follow Tor's bootstrap progress, including stalls after bootstrap.
+    pub fn bootstrap_progress(&self) -> tokio::sync::watch::Receiver<BootstrapProgress> {
+        self.tor_controller.bootstrap_progress()
+    }
+
+    /// Get the registry every long-lived network task is spawned through.
+    pub fn tasks(&self) -> Arc<TaskRegistry> {
+        Arc::clone(&self.tasks)
+    }
+
+    /// Subscribe to network events.
+    ///
+    /// Events sent before subscribing are not replayed.
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.events.subscribe()
+    }
+
+    /// Get the header synthesizer for the current identity.
+    fn synthesizer(&self) -> std::sync::RwLockReadGuard<'_, HeaderSynthesizer> {
+        self.header_synthesizer.read().expect("synthesizer lock")
+    }
+
+    /// Make every request from now on with the User-Agent of `identity`'s
+    /// platform, so headers agree with what its navigator reports.
+    ///
+    /// Call again whenever the fingerprint identity rotates. `new_identity`
+    /// forgets it; until the next call the platform rotates per request.
+    pub fn set_identity(&self, identity: &SyntheticIdentity) {
+        *self.header_synthesizer.write().expect("synthesizer lock") =
+            HeaderSynthesizer::for_identity(identity);
+    }
+
+    /// Publish an event. Having no subscribers is not an error.
+    fn emit(&self, event: NetworkEvent) {
+        let _ = self.events.send(event);
+    }
+
+    /// Allocate a context and announce the request.
+    fn start_request(&self, destination: Destination) -> u64 {
+        let context = self.next_context.fetch_add(1, Ordering::Relaxed);
+        self.metrics.record_attempt();
+        self.emit(NetworkEvent::RequestStarted {
+            context,
+            destination,
+        });
+        context
+    }
+
+    /// Announce how a request ended.
+    fn finish_request(
+        &self,
+        context: u64,
+        url: &str,
+        result: &Result<NetworkResponse, NetworkError>,
+    ) {
+        self.metrics.record_outcome(result);
+        let status = result.as_ref().map(|response| response.status);
+        self.announce_outcome(context, url, status);
+    }
+
+    /// Announce how a streamed request ended, once its response head is
+    /// in or it failed.
+    fn finish_streaming(
+        &self,
+        context: u64,
+        url: &str,
+        result: &Result<StreamingResponse, NetworkError>,
+    ) {
+        self.metrics.record_streamed(result);
+        let status = result.as_ref().map(|response| response.status);
+        self.announce_outcome(context, url, status);
+    }
+
+    fn announce_outcome(&self, context: u64, url: &str, outcome: Result<u16, &NetworkError>) {
+        self.emit(match outcome {
+            Ok(status) => NetworkEvent::Completed {
+                context,
+                status,
+                security: ConnectionSecurity::of_url(url),
+            },
+            Err(e) => NetworkEvent::Failed {
+                context,
+                error_class: ErrorClass::of(e),
+            },
+        });
+        self.publish_metrics();
+    }
+
+    /// Count a request refused before it left.
+    fn record_refused(&self, error: &NetworkError) {
+        self.metrics.record_refused(error);
+        self.publish_metrics();
+    }
+
+    /// Publish the session totals for the status bar.
+    fn publish_metrics(&self) {
+        self.emit(NetworkEvent::MetricsUpdated(self.metrics.snapshot()));
+    }
+
+    /// Run a request, failing it with `NetworkError::Cancelled` if
+    /// `new_identity` runs before it is done.
+    async fn cancellable<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T, NetworkError>>,
+    ) -> Result<T, NetworkError> {
+        let cancel = self.in_flight.lock().expect("in-flight lock").clone();
+        tokio::select! {
+            result = request => result,
+            () = cancel.cancelled() => Err(NetworkError::Cancelled),
+        }
+    }
+
+    /// Announce the circuit a request is about to use.
+    async fn announce_circuit(&self) {
+        if let Some(info) = self.tor_controller.get_current_circuit_info().await {
+            self.emit(NetworkEvent::CircuitBuilt { info });
+        }
+    }
+
+    /// Get a new circuit, or the one `page`'s requests share.
+    async fn circuit_for(&self, page: Option<&IsolationKey>) -> Result<Arc<Circuit>, NetworkError> {
+        match page {
+            // Only building the shared circuit draws from the page's bucket
+            Some(key) => {
+                let context_id = key.context_id();
+                self.circuit_manager
+                    .page_circuit(key, || {
+                        self.admit_page_request(self.churn_guard.charge_circuit(context_id))
+                    })
+                    .await
+            }
+            None => self
+                .circuit_manager
+                .create_new_circuit()
+                .await
+                .map(Arc::new),
+        }
     }
 
     /// Make an HTTP request through the anonymized network.
     ///
     /// # Guarantees
     ///
-    /// - A NEW circuit is created for this request
+    /// - A NEW circuit is created for this request (`request_for_page`
+    ///   shares one across a page load instead)
     /// - Headers are synthetic and randomized
     /// - Traffic is padded and jittered
     /// - TLS fingerprint matches Tor Browser
     /// - Real IP never reaches the destination
     /// - DNS resolution happens over Tor
+    /// - Tracking parameters are removed from the query string
+    ///
+    /// If the circuit fails before the response starts, the request is
+    /// retried on another new circuit as `NetworkConfig::retry_policy`
+    /// allows; `NetworkResponse::circuit_id` names the one that answered.
+    ///
+    /// A `RequestBody::Stream` is sent as it is read, so it cannot be
+    /// replayed: the request is made once, on one circuit. Cancellation
+    /// by `new_identity` and the first-byte timeout, applied to each piece
+    /// of the body, hold while it is sent.
     pub async fn request(
         &self,
         method: &str,
         url: &str,
-        body: Option<&[u8]>,
+        body: Option<RequestBody>,
     ) -> Result<NetworkResponse, NetworkError> {
+        self.request_in(method, url, body, None).await
+    }
+
+    /// Make a request of a page load, as `request` does but on the circuit
+    /// the page's other requests under `key` share.
+    ///
+    /// `key` is the page's first-party origin in its navigation context.
+    /// Requests under it share one circuit until the next `navigate` or
+    /// `new_identity`, and never with requests under another key; a
+    /// circuit that fails is replaced for all of them. Each request counts
+    /// against the context's onion service cap, but only building the
+    /// circuit draws from its circuit bucket.
+    /// The renderer and broker choose this per call site, where a page's
+    /// subresources would otherwise each leave from a different exit;
+    /// `request` stays the default.
+    pub async fn request_for_page(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<RequestBody>,
+        key: &IsolationKey,
+    ) -> Result<NetworkResponse, NetworkError> {
+        self.request_in(method, url, body, Some(key)).await
+    }
+
+    /// Make a request on a new circuit, or on `page`'s shared one.
+    async fn request_in(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<RequestBody>,
+        page: Option<&IsolationKey>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let (validated, stream) = match self.prepare_request(method, url, body) {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                self.record_refused(&e);
+                return Err(e);
+            }
+        };
+
+        if let Some(key) = page {
+            let verdict = self
+                .churn_guard
+                .check_onion(key.context_id(), validated.url());
+            if let Err(e) = self.admit_page_request(verdict).await {
+                self.record_refused(&e);
+                return Err(e);
+            }
+        }
+        match stream {
+            Some(stream) => self.request_streamed(validated, stream, page).await,
+            None => {
+                let isolation = origin_of(validated.url()).unwrap_or_default();
+                let headers = self.synthesizer().generate();
+                self.request_tracked(validated, &isolation, headers, false, page)
+                    .await
+            }
+        }
+    }
+
+    /// Validate `msg` against the shared policy, which also strips
+    /// tracking parameters from its URL.
+    fn validate(&self, msg: NetworkRequestMsg) -> Result<ValidatedRequest, NetworkError> {
+        let validated = validate_request(msg, self.config.max_request_size.get())?;
+        self.count_tracking_params(&validated);
+        Ok(validated)
+    }
+
+    /// Count the tracking parameters validation removed from `request`.
+    fn count_tracking_params(&self, request: &ValidatedRequest) {
+        let removed = request.tracking_params_removed();
+        if removed > 0 {
+            log::debug!("Removed {} tracking parameters from request URL", removed);
+            self.tracking_params_removed
+                .fetch_add(removed as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Check a request made through `request` before it touches the
+    /// network.
+    fn prepare_request(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<RequestBody>,
+    ) -> Result<(ValidatedRequest, Option<BodyStream>), NetworkError> {
         // Validate URL - only HTTPS allowed
         if !url.starts_with("https://") {
             return Err(NetworkError::ProtocolNotSupported(
@@ -182,71 +879,742 @@ impl AnonymizedNetwork {
             ));
         }
 
-        // Apply jitter before request
-        self.traffic_shaper.apply_jitter().await;
+        let (body, stream) = match body {
+            Some(RequestBody::Bytes(bytes)) => (Some(bytes), None),
+            Some(RequestBody::Stream(stream)) => (None, Some(stream)),
+            None => (None, None),
+        };
 
-        // Create a NEW circuit for this request
-        let circuit = self.circuit_manager.create_new_circuit().await?;
+        // Refuse oversized bodies before touching the network
+        let len = stream
+            .as_ref()
+            .and_then(BodyStream::known_len)
+            .map(|len| usize::try_from(len).unwrap_or(usize::MAX));
+        if let Some(len) = body.as_ref().map(Vec::len).or(len) {
+            check_request_size(len, self.config.max_request_size.get())?;
+        }
 
-        // Generate synthetic headers
-        let synthetic_headers = self.header_synthesizer.generate();
+        let validated = self.validate(NetworkRequestMsg {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body,
+            frame_id: FrameId::TOP,
+            destination: Destination::Document,
+        })?;
+        Ok((validated, stream))
+    }
 
-        // Pad the request body
-        let padded_body = body.map(|b| self.traffic_shaper.pad_request(b));
+    /// Make a request whose body is read as it is sent.
+    async fn request_streamed(
+        &self,
+        request: ValidatedRequest,
+        body: BodyStream,
+        page: Option<&IsolationKey>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let synthetic_headers = self.synthesizer().generate();
+        let context = self.start_request(synthetic_headers.destination);
+        let result = self
+            .cancellable(self.send_streamed(&request, synthetic_headers, body, page))
+            .await;
+        self.finish_request(context, request.url(), &result);
+        result
+    }
 
-        // Configure TLS with normalized fingerprint
-        let tls_config = self.tls_normalizer.create_config()?;
+    async fn send_streamed(
+        &self,
+        request: &ValidatedRequest,
+        synthetic_headers: SyntheticHeaders,
+        body: BodyStream,
+        page: Option<&IsolationKey>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let url = request.url();
+        self.traffic_shaper.apply_jitter().await;
 
-        // Make the actual request through Tor
-        let response = circuit
-            .request(
-                method,
+        let started = Instant::now();
+        let circuit = self.circuit_for(page).await?;
+        let circuit_built = Instant::now();
+        self.announce_circuit().await;
+        // A chunked body is padded chunk by chunk as well
+        let mut headers = synthetic_headers.to_vec();
+        let len = body
+            .known_len()
+            .map_or(0, |len| usize::try_from(len).unwrap_or(usize::MAX));
+        self.traffic_shaper.pad_request(&mut headers, len);
+        self.metrics.record_sent(&headers, len);
+
+        let isolation = origin_of(url).unwrap_or_default();
+        let host = parse_url(url)?.host;
+        let tls_config = self.protocol_memo.tls_config(
+            &isolation,
+            &host,
+            &self.tls_normalizer.create_config()?,
+        );
+        let metrics = RequestMetrics {
+            attempts: 1,
+            memoized: self.protocol_memo.is_downgraded(&isolation, &host),
+            ..RequestMetrics::default()
+        };
+
+        let result = circuit
+            .request_streamed(
+                request.method(),
                 url,
-                &synthetic_headers,
-                padded_body.as_deref(),
+                &headers,
+                body,
                 tls_config,
-                self.config.request_timeout,
+                self.config.first_byte_timeout(url),
+                self.config.max_request_size.get(),
             )
+            .await
+            .map(|response| response.since(started, circuit_built));
+        self.finish_single_shot(&circuit, url, result, metrics)
+            .await
+    }
+
+    /// Make a request as `request` does, following redirects.
+    ///
+    /// Each hop is a separate request on its own new circuit; see
+    /// `RedirectPolicy` for what is refused. The response carries the
+    /// chain of URLs that redirected. A cross-origin redirect to an onion
+    /// service is returned as is, with `RedirectChain::onion_location`
+    /// set for the UI to offer.
+    pub async fn request_following_redirects(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&[u8]>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let mut tracker = RedirectTracker::new(self.config.redirect_policy, url);
+        let mut method = method.to_string();
+        let mut url = url.to_string();
+        let mut body = body.map(<[u8]>::to_vec);
+
+        loop {
+            let response = self
+                .request(&method, &url, body.clone().map(RequestBody::Bytes))
+                .await?;
+            if !is_redirect(response.status) {
+                return Ok(NetworkResponse {
+                    redirects: tracker.finish(),
+                    ..response
+                });
+            }
+
+            let location = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+                .map(|(_, value)| value.as_str());
+            match tracker.next(&url, location)? {
+                RedirectStep::Follow(next) => {
+                    log::debug!("Following {} redirect", response.status);
+                    let (next_method, keep_body) = redirect_method(response.status, &method);
+                    if !keep_body {
+                        body = None;
+                    }
+                    method = next_method;
+                    url = next;
+                }
+                RedirectStep::SurfaceOnion(_) => {
+                    return Ok(NetworkResponse {
+                        redirects: tracker.finish(),
+                        ..response
+                    });
+                }
+            }
+        }
+    }
+
+    /// Make a request as `request` does, but return once the response head
+    /// arrives and hand the body over as it is received.
+    ///
+    /// For large downloads, whose bodies are never held in memory whole.
+    /// The request gets its own new circuit, padding and jitter, and the
+    /// response headers are sanitized, as for `request`. The body is not
+    /// routed or sanitized, so it must not reach the renderer. Dropping
+    /// the body before its end closes the circuit.
+    pub async fn request_streaming(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<&[u8]>,
+    ) -> Result<StreamingResponse, NetworkError> {
+        let body = body.map(|body| RequestBody::Bytes(body.to_vec()));
+        let validated = match self.prepare_request(method, url, body) {
+            Ok((validated, _)) => validated,
+            Err(e) => {
+                self.record_refused(&e);
+                return Err(e);
+            }
+        };
+
+        let context = self.start_request(validated.destination());
+        let result = self.send_streaming(&validated).await;
+        self.finish_streaming(context, validated.url(), &result);
+        result
+    }
+
+    /// Send a request for `request_streaming`, returning once the
+    /// response head is in.
+    async fn send_streaming(
+        &self,
+        validated: &ValidatedRequest,
+    ) -> Result<StreamingResponse, NetworkError> {
+        let url = validated.url();
+        self.traffic_shaper.apply_jitter().await;
+
+        // A build waiting its turn still gives way to New Loop
+        let circuit = self
+            .cancellable(self.circuit_manager.create_new_circuit())
             .await?;
+        self.announce_circuit().await;
+
+        let mut headers = self.synthesizer().generate().to_vec();
+        let body_len = validated.body().map_or(0, <[u8]>::len);
+        self.traffic_shaper.pad_request(&mut headers, body_len);
+        self.metrics.record_sent(&headers, body_len);
+        let isolation = origin_of(url).unwrap_or_default();
+        let tls_config = self.tls_normalizer.create_config()?;
+        let host = parse_url(url)?.host;
+
+        let result = self
+            .cancellable(request_with_fallback(
+                &self.protocol_memo,
+                &isolation,
+                &host,
+                &tls_config,
+                |tls| {
+                    circuit.request_streaming(
+                        validated.method(),
+                        validated.url(),
+                        &headers,
+                        validated.body(),
+                        tls,
+                        self.config.first_byte_timeout(url),
+                        self.config.max_request_size.get(),
+                    )
+                },
+            ))
+            .await;
+        let head = match result.and_then(|(head, _)| {
+            check_challenge(head.status)?;
+            Ok(head)
+        }) {
+            Ok(head) => head,
+            Err(e) => {
+                // Never leave a half-used circuit around
+                let _ = self.circuit_manager.close_circuit(circuit.id()).await;
+                return Err(e);
+            }
+        };
+
+        let (headers, _) = normalize_response_headers(head.headers);
+        self.traffic_shaper.apply_jitter().await;
+
+        // The circuit lives as long as the body; it is closed unless the
+        // body is read to its end
+        let circuit_id = circuit.id().to_string();
+        let activity = circuit.activity().clone();
+        let manager = Arc::clone(&self.circuit_manager);
+        let abandon = async move {
+            let _ = manager.close_circuit(circuit.id()).await;
+        };
+        Ok(StreamingResponse {
+            status: head.status,
+            headers,
+            circuit_id,
+            body: pump_body(
+                &self.tasks,
+                head.body,
+                activity,
+                Arc::clone(&self.metrics),
+                abandon,
+            ),
+        })
+    }
+
+    /// Download `url` into the RAM-backed download directory.
+    ///
+    /// Returns once the response head is in. The body is written by a
+    /// task in the context scope, so New Loop cancels the download and
+    /// wipes what was written of it.
+    pub async fn download(&self, url: &str) -> Result<Download, NetworkError> {
+        let response = self.request_streaming("GET", url, None).await?;
+        if !(200..300).contains(&response.status) {
+            return Err(NetworkError::RequestFailed(format!(
+                "Download failed: HTTP {}",
+                response.status
+            )));
+        }
+        self.download_manager.start(url, response)
+    }
+
+    /// Make the GET for a top-level navigation.
+    ///
+    /// Same guarantees as `request`; the Sec-Fetch-* headers follow how
+    /// the navigation was started.
+    pub async fn navigate(
+        &self,
+        target: &NavigationTarget,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let validated = self.validate(NetworkRequestMsg {
+            method: "GET".to_string(),
+            url: target.url.clone(),
+            headers: Vec::new(),
+            body: None,
+            frame_id: FrameId::TOP,
+            destination: Destination::Document,
+        })?;
+
+        self.churn_guard.reset_context(target.context_id);
+        // The page being left no longer shares its circuits
+        self.circuit_manager.end_page_load();
+        let isolation = origin_of(&target.url).unwrap_or_default();
+        let headers = target.headers(&self.synthesizer());
+        self.request_tracked(validated, &isolation, headers, false, None)
+            .await
+    }
+
+    /// Handle a request message received over IPC from the broker.
+    ///
+    /// The broker has already validated the message, but a compromised
+    /// broker could have skipped or relaxed that, so it is re-validated
+    /// here with the same rules. The request must name a frame of `page`.
+    pub async fn handle_ipc_request(
+        &self,
+        payload: &[u8],
+        page: &PageContext,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let msg = NetworkRequestMsg::from_bytes(payload)?;
+        let validated = self.validate(msg)?;
+        let keys = page.request_keys(&validated)?;
+        log::debug!(
+            "Request from frame {} ({}), isolation key {}",
+            keys.frame_id.0,
+            keys.frame_origin,
+            keys.isolation_origin
+        );
+        self.admit_page_request(self.churn_guard.check(page.context_id(), validated.url()))
+            .await?;
+        // The frame is what initiated it, as Sec-Fetch-Site tells
+        let headers = self.synthesizer().generate_for_request(
+            validated.destination(),
+            &keys.frame_origin,
+            validated.url(),
+        );
+        self.request_tracked(validated, &keys.isolation_origin, headers, false, None)
+            .await
+    }
+
+    /// Act on the churn guard's verdict on a request a page made.
+    async fn admit_page_request(&self, verdict: ChurnVerdict) -> Result<(), NetworkError> {
+        let refused = || {
+            NetworkError::from(PolicyViolation::BlockedByPolicy(
+                "Too many connection attempts from this page".to_string(),
+            ))
+        };
+
+        match verdict {
+            ChurnVerdict::Admit => Ok(()),
+            ChurnVerdict::Delay(delay) => {
+                log::debug!("Delaying page request by {}ms", delay.as_millis());
+                self.churn_guard.clock().sleep(delay).await;
+                Ok(())
+            }
+            ChurnVerdict::Refuse => Err(refused()),
+            ChurnVerdict::Abusive => {
+                self.emit(NetworkEvent::ExcessiveConnections);
+                Err(refused())
+            }
+        }
+    }
+
+    /// Make a request that has passed `validate_request` in this process.
+    pub async fn request_validated(
+        &self,
+        request: ValidatedRequest,
+    ) -> Result<NetworkResponse, NetworkError> {
+        self.count_tracking_params(&request);
+        let isolation = origin_of(request.url()).unwrap_or_default();
+        let headers = self.synthesizer().generate();
+        self.request_tracked(request, &isolation, headers, false, None)
+            .await
+    }
+
+    /// Retry an onion request that timed out, from its error page.
+    ///
+    /// If Tor already holds the service descriptor the circuit is opened
+    /// without purging it, so the lookup is not repeated.
+    pub async fn retry_onion(&self, retry: &OnionRetry) -> Result<NetworkResponse, NetworkError> {
+        let validated = self.validate(NetworkRequestMsg {
+            method: "GET".to_string(),
+            url: retry.url.clone(),
+            headers: Vec::new(),
+            body: None,
+            frame_id: FrameId::TOP,
+            destination: Destination::Document,
+        })?;
+        let isolation = origin_of(&retry.url).unwrap_or_default();
+        let headers = self.synthesizer().generate();
+        self.request_tracked(validated, &isolation, headers, retry.reuse_descriptor, None)
+            .await
+    }
+
+    /// Make a request; `isolation` is the first-party origin it is made for,
+    /// and `page` the key whose circuit it shares, if any.
+    async fn request_tracked(
+        &self,
+        request: ValidatedRequest,
+        isolation: &str,
+        synthetic_headers: SyntheticHeaders,
+        reuse_descriptor: bool,
+        page: Option<&IsolationKey>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let context = self.start_request(synthetic_headers.destination);
+        self.track_onion(context, request.url());
+
+        let result = self
+            .cancellable(self.send_validated(
+                &request,
+                isolation,
+                synthetic_headers,
+                reuse_descriptor,
+                page,
+            ))
+            .await;
+        let tracker = self
+            .onion_connects
+            .lock()
+            .expect("onion tracker lock")
+            .remove(&context);
+        let result = match (result, tracker) {
+            (Err(NetworkError::Timeout), Some(tracker))
+            | (Err(NetworkError::CircuitFailed(CircuitFailure::TtlExpired)), Some(tracker)) => {
+                Err(NetworkError::OnionTimeout(tracker.timed_out()))
+            }
+            (result, _) => result,
+        };
+        self.finish_request(context, request.url(), &result);
+        result
+    }
+
+    /// Start following an onion request through the control-port events.
+    fn track_onion(&self, context: u64, url: &str) {
+        if let Some(tracker) = OnionConnectTracker::for_url(url) {
+            let phase = tracker.phase();
+            self.onion_connects
+                .lock()
+                .expect("onion tracker lock")
+                .insert(context, tracker);
+            self.emit(NetworkEvent::OnionProgress { context, phase });
+        }
+    }
+
+    async fn send_validated(
+        &self,
+        request: &ValidatedRequest,
+        isolation: &str,
+        synthetic_headers: SyntheticHeaders,
+        reuse_descriptor: bool,
+        page: Option<&IsolationKey>,
+    ) -> Result<NetworkResponse, NetworkError> {
+        // Apply jitter before request
+        self.traffic_shaper.apply_jitter().await;
+
+        // Synthetic headers first, then the page's (already policy-checked)
+        let mut headers = synthetic_headers.to_vec();
+        headers.extend_from_slice(request.headers());
+
+        // Pad the request; the body goes as is
+        self.traffic_shaper
+            .pad_request(&mut headers, request.body().map_or(0, <[u8]>::len));
+        let headers = &headers;
+        let body = request.body();
+
+        // Configure TLS with normalized fingerprint
+        let tls_config = &self.tls_normalizer.create_config()?;
+        let host = &parse_url(request.url())?.host;
+        self.metrics
+            .record_sent(headers, body.map_or(0, <[u8]>::len));
+
+        // Each attempt gets a NEW circuit (or the page's, replaced once
+        // closed); one that fails before the response starts is retried on
+        // another as the policy allows
+        let (circuit, response, metrics) =
+            retry_on_new_circuit(&self.config.retry_policy, || async move {
+                let started = Instant::now();
+                let circuit = if reuse_descriptor {
+                    Arc::new(
+                        self.circuit_manager
+                            .create_circuit_reusing_descriptors()
+                            .await?,
+                    )
+                } else {
+                    self.circuit_for(page).await?
+                };
+                let circuit_built = Instant::now();
+                self.announce_circuit().await;
+
+                // Make the actual request through Tor, falling back to
+                // HTTP/1.1 on the same circuit if the server's HTTP/2 is broken
+                let result = request_with_fallback(
+                    &self.protocol_memo,
+                    isolation,
+                    host,
+                    tls_config,
+                    |tls| {
+                        circuit.request(
+                            request.method(),
+                            request.url(),
+                            headers,
+                            body,
+                            tls,
+                            self.config.first_byte_timeout(request.url()),
+                            self.config.max_request_size.get(),
+                        )
+                    },
+                )
+                .await;
+                match result {
+                    Ok((response, metrics)) => {
+                        Ok((circuit, response.since(started, circuit_built), metrics))
+                    }
+                    // A failed circuit, or one left mid-stream by an
+                    // oversized head or body, goes with it
+                    Err(
+                        e @ (NetworkError::CircuitFailed(_)
+                        | NetworkError::ResponseTooLarge { .. }
+                        | NetworkError::HeadersTooLarge { .. }),
+                    ) => {
+                        let _ = self.circuit_manager.close_circuit(circuit.id()).await;
+                        Err(e)
+                    }
+                    Err(e) => Err(e),
+                }
+            })
+            .await?;
+
+        let timing = response.phases.timing(Instant::now(), TIMING_PRECISION);
+
+        // Tor never asks for proxy credentials; whoever did is in the path
+        if let Err(e) = check_challenge(response.status) {
+            let _ = self.circuit_manager.close_circuit(circuit.id()).await;
+            return Err(e);
+        }
 
         // Sanitize response headers (remove tracking headers)
-        let sanitized_headers = self.sanitize_response_headers(response.headers);
+        let (sanitized_headers, headers_dropped) = normalize_response_headers(response.headers);
+
+        // PDFs are downloaded, never rendered
+        let routed = self.response_router.route(
+            request.destination(),
+            request.url(),
+            &sanitized_headers,
+            response.body,
+        );
 
         // Apply jitter after response
         self.traffic_shaper.apply_jitter().await;
 
+        // Strip auto-firing constructs before the renderer sees the document
         Ok(NetworkResponse {
-            status: response.status,
-            headers: sanitized_headers,
-            body: response.body,
-            circuit_id: circuit.id().to_string(),
+            metrics,
+            timing,
+            ..self.finish_response(
+                request.url(),
+                response.status,
+                sanitized_headers,
+                headers_dropped,
+                routed?,
+                circuit.id(),
+            )
         })
     }
 
-    /// Sanitize response headers to remove any tracking mechanisms.
-    fn sanitize_response_headers(&self, headers: Vec<(String, String)>) -> Vec<(String, String)> {
-        headers
-            .into_iter()
-            .filter(|(name, _)| {
-                let lower = name.to_lowercase();
-                // Remove these headers entirely
-                !matches!(
-                    lower.as_str(),
-                    "set-cookie"
-                        | "set-cookie2"
-                        | "etag"
-                        | "last-modified"
-                        | "x-request-id"
-                        | "x-correlation-id"
-                        | "x-amzn-requestid"
-                        | "cf-ray"
-                        | "x-cache"
-                        | "x-served-by"
-                        | "x-timer"
-                        | "x-trace-id"
-                )
-            })
-            .collect()
+    /// Upload a request body in chunks, reporting progress.
+    ///
+    /// Same guarantees as `request`. `sink` receives an `UploadProgress`
+    /// after every chunk. If `cancel` fires mid-body, the connection is
+    /// torn down, the circuit is closed and `NetworkError::Cancelled` is
+    /// returned.
+    pub async fn upload(
+        &self,
+        method: &str,
+        url: &str,
+        body: &[u8],
+        sink: &mut dyn ProgressSink,
+        cancel: &UploadCancel,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let synthetic_headers = self.synthesizer().generate();
+        let context = self.start_request(synthetic_headers.destination);
+
+        // The caller's sink and the event channel both see every chunk
+        let mut forwarding_sink = |progress: UploadProgress| {
+            sink.on_progress(progress);
+            self.emit(NetworkEvent::Progress { context, progress });
+        };
+
+        let result = self
+            .send_upload(
+                method,
+                url,
+                body,
+                synthetic_headers,
+                &mut forwarding_sink,
+                cancel,
+            )
+            .await;
+        self.finish_request(context, url, &result);
+        result
+    }
+
+    async fn send_upload(
+        &self,
+        method: &str,
+        url: &str,
+        body: &[u8],
+        synthetic_headers: SyntheticHeaders,
+        sink: &mut dyn ProgressSink,
+        cancel: &UploadCancel,
+    ) -> Result<NetworkResponse, NetworkError> {
+        if !url.starts_with("https://") {
+            return Err(NetworkError::ProtocolNotSupported(
+                url.split(':').next().unwrap_or("unknown").to_string(),
+            ));
+        }
+
+        check_request_size(body.len(), self.config.max_request_size.get())?;
+
+        self.traffic_shaper.apply_jitter().await;
+
+        // A build waiting its turn still gives way to New Loop
+        let started = Instant::now();
+        let circuit = self
+            .cancellable(self.circuit_manager.create_new_circuit())
+            .await?;
+        let circuit_built = Instant::now();
+        self.announce_circuit().await;
+        let mut headers = synthetic_headers.to_vec();
+        self.traffic_shaper.pad_request(&mut headers, body.len());
+        self.metrics.record_sent(&headers, body.len());
+
+        // The streamed body is not replayed, so uploads only follow earlier
+        // fallbacks and never retry themselves
+        let isolation = origin_of(url).unwrap_or_default();
+        let host = parse_url(url)?.host;
+        let tls_config = self.protocol_memo.tls_config(
+            &isolation,
+            &host,
+            &self.tls_normalizer.create_config()?,
+        );
+        let metrics = RequestMetrics {
+            attempts: 1,
+            memoized: self.protocol_memo.is_downgraded(&isolation, &host),
+            ..RequestMetrics::default()
+        };
+
+        let result = self
+            .cancellable(circuit.upload(
+                method,
+                url,
+                &headers,
+                body,
+                tls_config,
+                self.config.max_request_size.get(),
+                sink,
+                cancel,
+            ))
+            .await
+            .map(|response| response.since(started, circuit_built));
+        self.finish_single_shot(&circuit, url, result, metrics)
+            .await
+    }
+
+    /// Turn the response to a request made once, without retries, into a
+    /// `NetworkResponse`, closing `circuit` if it failed.
+    async fn finish_single_shot(
+        &self,
+        circuit: &Circuit,
+        url: &str,
+        result: Result<RawResponse, NetworkError>,
+        metrics: RequestMetrics,
+    ) -> Result<NetworkResponse, NetworkError> {
+        let response = match result.and_then(|response| {
+            check_challenge(response.status)?;
+            Ok(response)
+        }) {
+            Ok(response) => response,
+            Err(e) => {
+                // Never leave a half-used circuit around
+                let _ = self.circuit_manager.close_circuit(circuit.id()).await;
+                return Err(e);
+            }
+        };
+        let timing = response.phases.timing(Instant::now(), TIMING_PRECISION);
+
+        let (sanitized_headers, headers_dropped) = normalize_response_headers(response.headers);
+        let routed = self.response_router.route(
+            Destination::Document,
+            url,
+            &sanitized_headers,
+            response.body,
+        );
+
+        self.traffic_shaper.apply_jitter().await;
+
+        Ok(NetworkResponse {
+            metrics,
+            timing,
+            ..self.finish_response(
+                url,
+                response.status,
+                sanitized_headers,
+                headers_dropped,
+                routed?,
+                circuit.id(),
+            )
+        })
+    }
+
+    /// Build the response, sanitizing bodies that go to the renderer.
+    ///
+    /// `headers_dropped` is added to the document's shield counters.
+    fn finish_response(
+        &self,
+        url: &str,
+        status: u16,
+        headers: Vec<(String, String)>,
+        headers_dropped: usize,
+        routed: Routed,
+        circuit_id: &str,
+    ) -> NetworkResponse {
+        let (body, html_report, download) = match routed {
+            Routed::Render(body) => {
+                let (body, mut html_report) = sanitize_response(url, &headers, body);
+                if let Some(report) = &mut html_report {
+                    report.stats.headers_dropped = headers_dropped;
+                }
+                (body, html_report, None)
+            }
+            Routed::Downloaded(path) => (Vec::new(), None, Some(path)),
+        };
+        NetworkResponse {
+            status,
+            headers,
+            body,
+            circuit_id: circuit_id.to_string(),
+            html_report,
+            download,
+            metrics: RequestMetrics::default(),
+            timing: None,
+            redirects: RedirectChain::default(),
+        }
+    }
+
+    /// What the PDF policy has done so far.
+    pub fn pdf_stats(&self) -> PdfStats {
+        self.response_router.stats()
     }
 
     /// Check if the Tor network is connected and healthy.
@@ -254,14 +1622,92 @@ impl AnonymizedNetwork {
         self.tor_controller.is_connected().await
     }
 
+    /// Confirm over a fresh circuit that traffic leaves through Tor.
+    ///
+    /// Tries `CHECK_ENDPOINTS` in order. Meant to run after bootstrap and
+    /// after each new identity; the exit address never leaves this call.
+    pub async fn self_check(&self) -> Result<HealthReport, NetworkError> {
+        self_check(self, CHECK_ENDPOINTS).await
+    }
+
+    /// Feed an asynchronous control-port line to the Tor controller.
+    ///
+    /// Health changes are published as `NetworkEvent::TorStateChanged`,
+    /// onion connection steps as `NetworkEvent::OnionProgress`.
+    pub fn handle_control_line(&self, line: &str) {
+        if let Some(status) = self.tor_controller.handle_control_line(line) {
+            self.emit(NetworkEvent::TorStateChanged(status.into()));
+        }
+
+        let Some(event) = TorEvent::parse(line) else {
+            return;
+        };
+        let progress: Vec<_> = self
+            .onion_connects
+            .lock()
+            .expect("onion tracker lock")
+            .iter_mut()
+            .filter_map(|(&context, tracker)| tracker.apply(&event).map(|phase| (context, phase)))
+            .collect();
+        for (context, phase) in progress {
+            self.emit(NetworkEvent::OnionProgress { context, phase });
+        }
+    }
+
     /// Get current Tor circuit information (for UI display only).
     pub async fn get_circuit_info(&self) -> Option<CircuitInfo> {
         self.tor_controller.get_current_circuit_info().await
     }
+
+    /// Drop all network state for New Loop and switch to a new identity.
+    ///
+    /// In order: requests in flight fail with `NetworkError::Cancelled`
+    /// and context-scoped tasks end; every active and pooled circuit is
+    /// closed; isolation tokens get a new nonce and per-page memory, the
+    /// `set_identity` platform and `metrics_snapshot` totals included, is
+    /// forgotten; NEWNYM is sent, once Tor's rate limit allows. Returns
+    /// the id of a circuit built after the NEWNYM.
+    ///
+    /// Requests made before it returns fail as well. Progress is
+    /// published as `NetworkEvent::NewIdentity`.
+    pub async fn new_identity(&self) -> Result<String, NetworkError> {
+        let _identity = self.identity.lock().await;
+        self.emit(NetworkEvent::NewIdentity(IdentityPhase::ClosingCircuits));
+
+        self.in_flight.lock().expect("in-flight lock").cancel();
+        let _reopen = ReopenRequests(&self.in_flight);
+        self.tasks.cancel_scope(TaskScope::Context).await;
+        self.circuit_manager.close_all().await?;
+        self.circuit_manager.rotate_isolation();
+        self.protocol_memo.clear();
+        self.churn_guard.reset();
+        self.metrics.reset();
+        self.publish_metrics();
+        *self.header_synthesizer.write().expect("synthesizer lock") = HeaderSynthesizer::new();
+
+        self.emit(NetworkEvent::NewIdentity(IdentityPhase::BuildingCircuit));
+        let circuit_id = self.tor_controller.new_identity().await?;
+        self.announce_circuit().await;
+        self.emit(NetworkEvent::NewIdentity(IdentityPhase::Ready));
+        Ok(circuit_id)
+    }
+
+    /// Tear down the Tor control channel and wipe its secrets.
+    ///
+    /// Called by the quit path and the kill switch.
+    pub fn teardown(&self) {
+        self.tor_controller.teardown();
+    }
+
+    /// Cancel every background task, then tear down the control channel.
+    pub async fn shutdown(&self) {
+        self.tasks.shutdown().await;
+        self.teardown();
+    }
 }
 
 /// Information about the current Tor circuit (for display only).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CircuitInfo {
     /// Entry node country code
     pub entry_country: String,
@@ -273,8 +1719,6 @@ pub struct CircuitInfo {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn test_rejects_http() {
         // Can't actually test async in unit tests without runtime,
@@ -289,6 +1733,338 @@ mod tests {
         assert!(url.starts_with("https://"));
     }
 
+    #[test]
+    fn test_onion_first_byte_timeout() {
+        let config = super::NetworkConfig::default();
+        assert!(config.onion_first_byte_timeout > config.request_timeout);
+        assert_eq!(
+            config.first_byte_timeout("https://abcxyz.onion/"),
+            config.onion_first_byte_timeout
+        );
+        assert_eq!(
+            config.first_byte_timeout("https://example.com/?via=abcxyz.onion"),
+            config.request_timeout
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_identity_aborts_requests_and_rebuilds() {
+        use super::*;
+        use crate::control_protocol::tests::{mock_control_port, tor_ready};
+        use forloop_config::clock::ManualClock;
+
+        // A SOCKS port that never answers holds the request in flight
+        let socks = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let socks_port = Port::new(socks.local_addr().expect("address").port());
+        let (stream, log) = mock_control_port(tor_ready());
+        let clock = ManualClock::new();
+        let tor = TorController::with_control_stream(socks_port, Port::new(9151), stream)
+            .await
+            .expect("controller")
+            .with_clock(Arc::new(clock.clone()));
+        let network = AnonymizedNetwork::with_tor_controller(
+            NetworkConfig {
+                max_jitter: Duration::ZERO,
+                circuit_pool: None,
+                ..NetworkConfig::default()
+            },
+            Arc::new(tor),
+        );
+        let mut events = network.subscribe();
+
+        // Refused before and after a context is allocated
+        for url in ["http://example.com/", "https://example.com:25/"] {
+            assert!(network.request("GET", url, None).await.is_err());
+        }
+        let request = network.request("GET", "https://example.com/", None);
+        tokio::pin!(request);
+        let _held = tokio::select! {
+            accepted = socks.accept() => accepted.expect("accept"),
+            result = &mut request => panic!("request ended early: {:?}", result),
+        };
+        log.lock().expect("command log").clear();
+        let metrics = network.metrics_snapshot();
+        assert_eq!(metrics.attempts, 3);
+        assert_eq!(metrics.successes, 0);
+        assert_eq!(metrics.failures_of(ErrorClass::Refused), 2);
+        assert_eq!(metrics.circuits_built, 1);
+        assert!(metrics.bytes_sent > 0);
+
+        clock.advance(NEWNYM_INTERVAL);
+        let (result, identity) = tokio::join!(&mut request, network.new_identity());
+        assert!(matches!(result, Err(NetworkError::Cancelled)));
+        assert_eq!(identity.expect("new identity"), "2");
+        assert_eq!(
+            log.lock().expect("command log").as_slice(),
+            [
+                "CLOSECIRCUIT 1",
+                "GETINFO circuit-status",
+                "SIGNAL NEWNYM",
+                "GETINFO circuit-status",
+                // Announcing the new circuit
+                "GETINFO circuit-status",
+                "GETINFO ns/id/C",
+            ]
+        );
+        assert_eq!(network.circuit_manager.active_circuit_count().await, 0);
+        // The totals go with the loop; only the cancellation may follow
+        let metrics = network.metrics_snapshot();
+        assert_eq!(metrics.attempts, 0);
+        assert!(metrics.failure_count() <= 1);
+        assert_eq!(
+            metrics.failure_count(),
+            metrics.failures_of(ErrorClass::Cancelled)
+        );
+        assert_eq!(metrics.bytes_sent, 0);
+
+        let mut phases = Vec::new();
+        let mut cancelled = false;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NetworkEvent::NewIdentity(phase) => phases.push(phase),
+                NetworkEvent::Failed { error_class, .. } => {
+                    cancelled = error_class == ErrorClass::Cancelled;
+                }
+                _ => {}
+            }
+        }
+        assert!(cancelled);
+        assert_eq!(
+            phases,
+            [
+                IdentityPhase::ClosingCircuits,
+                IdentityPhase::BuildingCircuit,
+                IdentityPhase::Ready,
+            ]
+        );
+    }
+
+    /// A network on `config` whose SOCKS port refuses connections.
+    async fn unreachable_network(config: super::NetworkConfig) -> super::AnonymizedNetwork {
+        use super::*;
+        use crate::control_protocol::tests::{mock_control_port, tor_ready};
+
+        let socks = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let socks_port = Port::new(socks.local_addr().expect("address").port());
+        drop(socks);
+        let (stream, _log) = mock_control_port(tor_ready());
+        let tor = TorController::with_control_stream(socks_port, Port::new(9151), stream)
+            .await
+            .expect("controller");
+        AnonymizedNetwork::with_tor_controller(
+            NetworkConfig {
+                max_jitter: Duration::ZERO,
+                circuit_pool: None,
+                ..config
+            },
+            Arc::new(tor),
+        )
+    }
+
+    /// A network whose pages may each open `burst` circuits, and never
+    /// more, over a SOCKS port that refuses connections.
+    async fn churn_limited_network(burst: u32) -> super::AnonymizedNetwork {
+        use super::*;
+
+        unreachable_network(NetworkConfig {
+            churn_limits: ChurnLimits {
+                circuit_burst: burst,
+                circuits_per_minute: 0,
+                delay_strikes: 0,
+                ..ChurnLimits::default()
+            },
+            ..NetworkConfig::default()
+        })
+        .await
+    }
+
+    fn is_churn_refusal(result: &Result<super::NetworkResponse, super::NetworkError>) -> bool {
+        matches!(
+            result,
+            Err(super::NetworkError::PolicyViolation(
+                super::PolicyViolation::BlockedByPolicy(_)
+            ))
+        )
+    }
+
+    #[tokio::test]
+    async fn test_plain_requests_keep_page_churn_limits() {
+        use super::*;
+
+        let network = churn_limited_network(2).await;
+        let page = PageContext::new(1, "https://hostile.example/").expect("valid URL");
+        let payload = NetworkRequestMsg {
+            method: "GET".to_string(),
+            url: "https://hostile.example/px".to_string(),
+            headers: Vec::new(),
+            body: None,
+            frame_id: FrameId::TOP,
+            destination: Destination::Image,
+        }
+        .to_bytes();
+
+        // A top-level request between the page's own used to start it over
+        for _ in 0..2 {
+            let result = network.handle_ipc_request(&payload, &page).await;
+            assert!(!is_churn_refusal(&result), "{:?}", result);
+            let result = network.request("GET", "https://example.com/", None).await;
+            assert!(!is_churn_refusal(&result), "{:?}", result);
+        }
+        let result = network.handle_ipc_request(&payload, &page).await;
+        assert!(is_churn_refusal(&result), "{:?}", result);
+
+        // Only a navigation in the page's own context does
+        let mut target = NavigationTarget {
+            context_id: 2,
+            url: "https://hostile.example/".to_string(),
+            host: "hostile.example".to_string(),
+            port: 443,
+            kind: forloop_config::NavigationKind::AddressBar,
+            initiator: None,
+        };
+        let _ = network.navigate(&target).await;
+        let result = network.handle_ipc_request(&payload, &page).await;
+        assert!(is_churn_refusal(&result), "{:?}", result);
+
+        target.context_id = page.context_id();
+        let _ = network.navigate(&target).await;
+        let result = network.handle_ipc_request(&payload, &page).await;
+        assert!(!is_churn_refusal(&result), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_page_requests_hit_churn_limit() {
+        use super::*;
+
+        let network = churn_limited_network(2).await;
+        // Requests on the circuit the page already has draw nothing
+        let key = IsolationKey::for_url(1, "https://hostile.example/").expect("https origin");
+        for _ in 0..5 {
+            let result = network
+                .request_for_page("GET", "https://hostile.example/px", None, &key)
+                .await;
+            assert!(!is_churn_refusal(&result), "{:?}", result);
+        }
+
+        // Every circuit built for it does
+        let second = IsolationKey::for_url(1, "https://cdn.example/").expect("https origin");
+        let result = network
+            .request_for_page("GET", "https://cdn.example/px", None, &second)
+            .await;
+        assert!(!is_churn_refusal(&result), "{:?}", result);
+        let third = IsolationKey::for_url(1, "https://img.example/").expect("https origin");
+        let result = network
+            .request_for_page("GET", "https://img.example/px", None, &third)
+            .await;
+        assert!(is_churn_refusal(&result), "{:?}", result);
+        assert_eq!(
+            network.metrics_snapshot().failures_of(ErrorClass::Refused),
+            1
+        );
+
+        // Another page has its own budget
+        let other = IsolationKey::for_url(2, "https://other.example/").expect("https origin");
+        let result = network
+            .request_for_page("GET", "https://other.example/", None, &other)
+            .await;
+        assert!(!is_churn_refusal(&result), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_tracking_params_stripped_on_every_path() {
+        use super::*;
+
+        let network = unreachable_network(NetworkConfig::default()).await;
+        let target = NavigationTarget {
+            context_id: 1,
+            url: "https://example.com/?utm_source=feed&id=1".to_string(),
+            host: "example.com".to_string(),
+            port: 443,
+            kind: forloop_config::NavigationKind::AddressBar,
+            initiator: None,
+        };
+        let _ = network.navigate(&target).await;
+        assert_eq!(network.tracking_params_removed(), 1);
+
+        let page = PageContext::new(1, "https://example.com/").expect("valid URL");
+        let payload = NetworkRequestMsg {
+            method: "GET".to_string(),
+            url: "https://example.com/px?fbclid=x".to_string(),
+            headers: Vec::new(),
+            body: None,
+            frame_id: FrameId::TOP,
+            destination: Destination::Image,
+        }
+        .to_bytes();
+        let _ = network.handle_ipc_request(&payload, &page).await;
+        assert_eq!(network.tracking_params_removed(), 2);
+
+        let _ = network
+            .download("https://example.com/report.pdf?gclid=1&utm_medium=mail")
+            .await;
+        assert_eq!(network.tracking_params_removed(), 4);
+
+        let _ = network
+            .request("GET", "https://example.com/?mc_eid=1", None)
+            .await;
+        assert_eq!(network.tracking_params_removed(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_download_counted_in_metrics() {
+        use super::*;
+
+        let network = unreachable_network(NetworkConfig::default()).await;
+        let mut events = network.subscribe();
+
+        let error = network
+            .download("https://example.com/report.pdf")
+            .await
+            .expect_err("no SOCKS proxy");
+        let metrics = network.metrics_snapshot();
+        assert_eq!(metrics.attempts, 1);
+        assert_eq!(metrics.successes, 0);
+        assert_eq!(metrics.failures_of(ErrorClass::of(&error)), 1);
+        assert_eq!(metrics.circuits_built, 1);
+        assert!(metrics.bytes_sent > 0);
+
+        let mut started = None;
+        let mut failed = None;
+        let mut published = None;
+        while let Ok(event) = events.try_recv() {
+            match event {
+                NetworkEvent::RequestStarted { context, .. } => started = Some(context),
+                NetworkEvent::Failed {
+                    context,
+                    error_class,
+                } => failed = Some((context, error_class)),
+                NetworkEvent::MetricsUpdated(snapshot) => published = Some(snapshot),
+                _ => {}
+            }
+        }
+        assert!(started.is_some());
+        assert_eq!(
+            failed,
+            started.map(|context| (context, ErrorClass::of(&error)))
+        );
+        assert_eq!(published, Some(metrics));
+
+        // Refused before it leaves
+        assert!(network
+            .download("http://example.com/report.pdf")
+            .await
+            .is_err());
+        let metrics = network.metrics_snapshot();
+        assert_eq!(metrics.attempts, 2);
+        assert_eq!(metrics.failures_of(ErrorClass::Refused), 1);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::MetricsUpdated(snapshot)) if snapshot == metrics
+        ));
+    }
+
     #[test]
     fn test_sanitize_headers() {
         let headers = vec![
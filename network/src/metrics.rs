@@ -0,0 +1,226 @@
+//! Session totals for the status bar.
+//!
+//! Fixed-size atomic counters, bumped as requests start and end: how many
+//! were made, how each ended, the bytes on the wire and the circuits
+//! opened for them. No URL, host or time is kept per request, only the
+//! running totals, in memory. `new_identity` zeroes them with the rest of
+//! the loop.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::events::ErrorClass;
+use crate::streaming::StreamingResponse;
+use crate::{NetworkError, NetworkResponse};
+
+/// Bytes a header takes in an HTTP/1.1 head: name, ": " and CRLF.
+fn header_bytes(headers: &[(String, String)]) -> u64 {
+    headers
+        .iter()
+        .map(|(name, value)| (name.len() + value.len() + 4) as u64)
+        .sum()
+}
+
+/// Running totals of the requests made through the network layer.
+#[derive(Debug, Default)]
+pub struct NetworkMetrics {
+    attempts: AtomicU64,
+    successes: AtomicU64,
+    failures: [AtomicU64; ErrorClass::ALL.len()],
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    circuits_built: AtomicU64,
+}
+
+impl NetworkMetrics {
+    /// Create zeroed counters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request leaving for the network.
+    pub(crate) fn record_attempt(&self) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count how a request ended, and the response's bytes if it arrived.
+    pub(crate) fn record_outcome(&self, result: &Result<NetworkResponse, NetworkError>) {
+        match result {
+            Ok(response) => {
+                self.successes.fetch_add(1, Ordering::Relaxed);
+                self.bytes_received.fetch_add(
+                    header_bytes(&response.headers) + response.body.len() as u64,
+                    Ordering::Relaxed,
+                );
+            }
+            Err(e) => self.record_failure(e),
+        }
+    }
+
+    /// Count how a streamed request ended, and its response head's bytes
+    /// if it arrived. The body's are counted by `record_received` as it
+    /// is read.
+    pub(crate) fn record_streamed(&self, result: &Result<StreamingResponse, NetworkError>) {
+        match result {
+            Ok(response) => {
+                self.successes.fetch_add(1, Ordering::Relaxed);
+                self.record_received(header_bytes(&response.headers));
+            }
+            Err(e) => self.record_failure(e),
+        }
+    }
+
+    /// Count response bytes that arrived.
+    pub(crate) fn record_received(&self, bytes: u64) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a request refused before it left: an attempt and a failure.
+    pub(crate) fn record_refused(&self, error: &NetworkError) {
+        self.record_attempt();
+        self.record_failure(error);
+    }
+
+    fn record_failure(&self, error: &NetworkError) {
+        self.failures[ErrorClass::of(error) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count the bytes of a request head, padding included, and its body.
+    pub(crate) fn record_sent(&self, headers: &[(String, String)], body_len: usize) {
+        self.bytes_sent
+            .fetch_add(header_bytes(headers) + body_len as u64, Ordering::Relaxed);
+    }
+
+    /// Count a circuit opened for a request.
+    pub(crate) fn record_circuit(&self) {
+        self.circuits_built.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Current totals.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MetricsSnapshot {
+            attempts: load(&self.attempts),
+            successes: load(&self.successes),
+            failures: ErrorClass::ALL
+                .into_iter()
+                .zip(&self.failures)
+                .map(|(class, count)| (class, load(count)))
+                .filter(|&(_, count)| count > 0)
+                .collect(),
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            circuits_built: load(&self.circuits_built),
+        }
+    }
+
+    /// Zero every counter.
+    pub fn reset(&self) {
+        for counter in [
+            &self.attempts,
+            &self.successes,
+            &self.bytes_sent,
+            &self.bytes_received,
+            &self.circuits_built,
+        ]
+        .into_iter()
+        .chain(&self.failures)
+        {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Totals at one moment, for display.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Requests made, refused ones included
+    pub attempts: u64,
+    /// Requests that got a response
+    pub successes: u64,
+    /// Failed requests per error class, classes with none left out
+    pub failures: Vec<(ErrorClass, u64)>,
+    /// Request bytes sent, padding included
+    pub bytes_sent: u64,
+    /// Response bytes received
+    pub bytes_received: u64,
+    /// Circuits opened for requests
+    pub circuits_built: u64,
+}
+
+impl MetricsSnapshot {
+    /// Failed requests of every class.
+    pub fn failure_count(&self) -> u64 {
+        self.failures.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// Failed requests of `class`.
+    pub fn failures_of(&self, class: ErrorClass) -> u64 {
+        self.failures
+            .iter()
+            .find(|&&(c, _)| c == class)
+            .map_or(0, |&(_, count)| count)
+    }
+
+    /// Bytes sent and received.
+    pub fn bytes_transferred(&self) -> u64 {
+        self.bytes_sent.saturating_add(self.bytes_received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &[u8]) -> NetworkResponse {
+        NetworkResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/html".to_string())],
+            body: body.to_vec(),
+            circuit_id: "1".to_string(),
+            html_report: None,
+            download: None,
+            metrics: Default::default(),
+            timing: None,
+            redirects: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_counters() {
+        for (index, class) in ErrorClass::ALL.into_iter().enumerate() {
+            assert_eq!(class as usize, index);
+        }
+
+        let metrics = NetworkMetrics::new();
+        for result in [
+            Ok(response(b"hello")),
+            Err(NetworkError::Timeout),
+            Err(NetworkError::Timeout),
+            Err(NetworkError::DnsError("no such host".to_string())),
+        ] {
+            metrics.record_attempt();
+            metrics.record_outcome(&result);
+        }
+        metrics.record_refused(&NetworkError::PortBlocked(25));
+        metrics.record_sent(&[("user-agent".to_string(), "x".to_string())], 10);
+        metrics.record_circuit();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.attempts, 5);
+        assert_eq!(snapshot.successes, 1);
+        assert_eq!(snapshot.failure_count(), 4);
+        assert_eq!(snapshot.failures_of(ErrorClass::Timeout), 2);
+        assert_eq!(snapshot.failures_of(ErrorClass::Dns), 1);
+        assert_eq!(snapshot.failures_of(ErrorClass::Refused), 1);
+        assert_eq!(snapshot.failures_of(ErrorClass::Tls), 0);
+        assert_eq!(snapshot.failures.len(), 3);
+        // "content-type: text/html\r\n" and the body
+        assert_eq!(snapshot.bytes_received, 25 + 5);
+        assert_eq!(snapshot.bytes_sent, 15 + 10);
+        assert_eq!(snapshot.bytes_transferred(), 55);
+        assert_eq!(snapshot.circuits_built, 1);
+
+        metrics.reset();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+    }
+}
@@ -0,0 +1,576 @@
+//! Main-document navigation with early circuit and TLS setup.
+//!
+//! Perceived latency over Tor is dominated by circuit build and TLS. The
+//! pipeline starts both the moment the user presses Enter on a valid URL,
+//! while the UI is still transitioning, and only issues the GET once the
+//! renderer signals it is ready:
+//!
+//! 1. `on_enter`: validate the URL, create a navigation context and start
+//!    circuit acquisition + TLS connect in the background. If the host
+//!    has an official onion service, return the interstitial instead and
+//!    start nothing until `resolve_interstitial`
+//! 2. `on_edit`: the user changed the URL again; the pending connect is
+//!    cancelled
+//! 3. `renderer_ready`: wait for the connection and hand it over for the GET
+//!
+//! Nothing is started before Enter: connecting while the user types would
+//! leak keystrokes to the network.
+//!
+//! Link clicks, reloads and back/forward go through `on_navigate` with
+//! their `NavigationKind`; the target keeps it so the GET carries the
+//! Sec-Fetch-Site Firefox would send for that kind.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+
+use forloop_config::NavigationKind;
+
+use crate::circuit::parse_url;
+use crate::frames::{origin_of, FrameId};
+use crate::headers::{Destination, HeaderSynthesizer, SyntheticHeaders};
+use crate::onion_alternatives::{InterstitialChoice, OnionInterstitial};
+use crate::policy::{validate_request, NetworkRequestMsg};
+use crate::tasks::{TaskRegistry, TaskScope};
+use crate::NetworkError;
+
+/// A validated navigation target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NavigationTarget {
+    /// Navigation context (one per Enter press)
+    pub context_id: u64,
+    /// Full URL
+    pub url: String,
+    /// Destination host
+    pub host: String,
+    /// Destination port
+    pub port: u16,
+    /// What started the navigation
+    pub kind: NavigationKind,
+    /// Origin of the page a clicked link was in
+    pub initiator: Option<String>,
+}
+
+impl NavigationTarget {
+    /// Generate the GET's synthetic headers.
+    pub fn headers(&self, synthesizer: &HeaderSynthesizer) -> SyntheticHeaders {
+        synthesizer.generate_navigation(self.kind, self.initiator.as_deref(), &self.url)
+    }
+}
+
+/// What `on_enter` did with the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NavigationStep {
+    /// Connect started for the target
+    Connecting(NavigationTarget),
+    /// Onion alternative known: show this page, nothing was started
+    Interstitial(OnionInterstitial),
+}
+
+/// Establishes a connection (fresh circuit + TLS) for a navigation.
+pub trait Connector: Clone + Send + Sync + 'static {
+    /// Connected stream, ready for the GET.
+    type Connection: Send + 'static;
+
+    /// Acquire a circuit and complete the TLS handshake to the target.
+    fn connect(
+        &self,
+        target: NavigationTarget,
+    ) -> impl Future<Output = Result<Self::Connection, NetworkError>> + Send;
+}
+
+/// A navigation whose connection is ready for the GET.
+#[derive(Debug)]
+pub struct ReadyNavigation<T> {
+    /// What is being navigated to
+    pub target: NavigationTarget,
+    /// Connection to issue the GET on
+    pub connection: T,
+}
+
+/// Connect in flight for the current navigation.
+struct PendingConnect<T> {
+    target: NavigationTarget,
+    task: AbortHandle,
+    result: oneshot::Receiver<Result<T, NetworkError>>,
+}
+
+/// Drives a main-document navigation from Enter to GET.
+pub struct NavigationPipeline<C: Connector> {
+    connector: C,
+    max_request_bytes: usize,
+    next_context_id: u64,
+    pending: Option<PendingConnect<C::Connection>>,
+    interstitial: Option<OnionInterstitial>,
+    /// Domains the user chose to reach over clearnet in this context
+    clearnet_allowed: HashSet<&'static str>,
+    /// Owner of the connect tasks (context scope)
+    tasks: Arc<TaskRegistry>,
+}
+
+impl<C: Connector> NavigationPipeline<C> {
+    /// Create a pipeline using the given connector.
+    pub fn new(connector: C, max_request_bytes: usize) -> Self {
+        Self {
+            connector,
+            max_request_bytes,
+            next_context_id: 1,
+            pending: None,
+            interstitial: None,
+            clearnet_allowed: HashSet::new(),
+            tasks: Arc::new(TaskRegistry::new()),
+        }
+    }
+
+    /// Spawn connects into the browser's task registry, so New Loop and
+    /// Quit cancel them with the rest of the context.
+    pub fn with_tasks(mut self, tasks: Arc<TaskRegistry>) -> Self {
+        self.tasks = tasks;
+        self
+    }
+
+    /// The user pressed Enter: validate and start connecting immediately.
+    ///
+    /// Any earlier pending connect or interstitial is cancelled first. A
+    /// host with a known onion alternative yields the interstitial and no
+    /// connect, unless the user already chose clearnet for that domain in
+    /// this pipeline's context. Must be called from within a tokio runtime.
+    pub fn on_enter(&mut self, input: &str) -> Result<NavigationStep, NetworkError> {
+        self.on_navigate(input, NavigationKind::AddressBar, None)
+    }
+
+    /// Navigate to `input`, started the way `kind` says.
+    ///
+    /// `referrer` is the URL of the page a link was clicked in; only its
+    /// origin is kept. Otherwise as `on_enter`.
+    pub fn on_navigate(
+        &mut self,
+        input: &str,
+        kind: NavigationKind,
+        referrer: Option<&str>,
+    ) -> Result<NavigationStep, NetworkError> {
+        self.cancel();
+
+        let mut target = self.validate(input, self.next_context_id)?;
+        self.next_context_id += 1;
+        target.kind = kind;
+        target.initiator = referrer.and_then(origin_of);
+
+        if let Some(page) = OnionInterstitial::for_url(target.context_id, &target.url, &target.host)
+            .filter(|page| !self.clearnet_allowed.contains(page.domain))
+        {
+            log::debug!(
+                "Onion alternative available for context {}",
+                page.context_id
+            );
+            self.interstitial = Some(page.clone());
+            return Ok(NavigationStep::Interstitial(page));
+        }
+
+        self.start(target.clone());
+        Ok(NavigationStep::Connecting(target))
+    }
+
+    /// The user answered the interstitial: start connecting to their choice.
+    ///
+    /// The choice is made on a browser page, so the navigation continues
+    /// as if typed into the address bar.
+    ///
+    /// Choosing clearnet is remembered for the domain until this pipeline
+    /// (the browsing context) is dropped.
+    pub fn resolve_interstitial(
+        &mut self,
+        choice: InterstitialChoice,
+    ) -> Result<NavigationTarget, NetworkError> {
+        let page = self
+            .interstitial
+            .take()
+            .ok_or_else(|| NetworkError::RequestFailed("No interstitial shown".to_string()))?;
+
+        if choice == InterstitialChoice::ContinueClearnet {
+            self.clearnet_allowed.insert(page.domain);
+        }
+        let target = self.validate(page.url_for(choice), page.context_id)?;
+        self.start(target.clone());
+        Ok(target)
+    }
+
+    /// Whether an interstitial is waiting for the user.
+    pub fn has_interstitial(&self) -> bool {
+        self.interstitial.is_some()
+    }
+
+    fn validate(&self, input: &str, context_id: u64) -> Result<NavigationTarget, NetworkError> {
+        let validated = validate_request(
+            NetworkRequestMsg {
+                method: "GET".to_string(),
+                url: input.trim().to_string(),
+                headers: Vec::new(),
+                body: None,
+                frame_id: FrameId::TOP,
+                destination: Destination::Document,
+            },
+            self.max_request_bytes,
+        )?;
+        let parsed = parse_url(validated.url())?;
+
+        Ok(NavigationTarget {
+            context_id,
+            url: validated.url().to_string(),
+            host: parsed.host,
+            port: parsed.port,
+            kind: NavigationKind::AddressBar,
+            initiator: None,
+        })
+    }
+
+    fn start(&mut self, target: NavigationTarget) {
+        let connector = self.connector.clone();
+        let task_target = target.clone();
+        let (tx, result) = oneshot::channel();
+        let task = self
+            .tasks
+            .spawn(TaskScope::Context, "navigation connect", async move {
+                let _ = tx.send(connector.connect(task_target).await);
+            });
+
+        self.pending = Some(PendingConnect {
+            target,
+            task,
+            result,
+        });
+    }
+
+    /// The user edited the URL: drop the pending connect or interstitial.
+    pub fn on_edit(&mut self) {
+        self.cancel();
+    }
+
+    /// Whether a connect is in flight.
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The renderer is ready: wait for the connection and hand it over.
+    pub async fn renderer_ready(&mut self) -> Result<ReadyNavigation<C::Connection>, NetworkError> {
+        let pending = self
+            .pending
+            .take()
+            .ok_or_else(|| NetworkError::RequestFailed("No navigation pending".to_string()))?;
+
+        let connection = pending
+            .result
+            .await
+            .map_err(|_| NetworkError::Cancelled)??;
+
+        Ok(ReadyNavigation {
+            target: pending.target,
+            connection,
+        })
+    }
+
+    fn cancel(&mut self) {
+        self.interstitial = None;
+        if let Some(pending) = self.pending.take() {
+            log::debug!(
+                "Cancelling connect for context {}",
+                pending.target.context_id
+            );
+            pending.task.abort();
+        }
+    }
+}
+
+impl<C: Connector> Drop for NavigationPipeline<C> {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::FetchSite;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Event {
+        ConnectStarted(String),
+        ConnectFinished(String),
+        ConnectDropped(String),
+        RendererReady,
+    }
+
+    type Log = Arc<Mutex<Vec<Event>>>;
+
+    /// Connector standing in for Tor: records events, takes a while to connect.
+    #[derive(Clone)]
+    struct FakeTor {
+        log: Log,
+        delay: Duration,
+    }
+
+    /// Records a drop if the connect never finished (i.e. was cancelled).
+    struct DropGuard {
+        log: Log,
+        host: String,
+        finished: bool,
+    }
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            if !self.finished {
+                let event = Event::ConnectDropped(self.host.clone());
+                self.log.lock().expect("log lock").push(event);
+            }
+        }
+    }
+
+    impl Connector for FakeTor {
+        type Connection = String;
+
+        fn connect(
+            &self,
+            target: NavigationTarget,
+        ) -> impl Future<Output = Result<String, NetworkError>> + Send {
+            let log = Arc::clone(&self.log);
+            let delay = self.delay;
+            async move {
+                let host = target.host.clone();
+                log.lock()
+                    .expect("log lock")
+                    .push(Event::ConnectStarted(host.clone()));
+                let mut guard = DropGuard {
+                    log: Arc::clone(&log),
+                    host: host.clone(),
+                    finished: false,
+                };
+
+                tokio::time::sleep(delay).await;
+
+                guard.finished = true;
+                log.lock()
+                    .expect("log lock")
+                    .push(Event::ConnectFinished(host.clone()));
+                Ok(format!("tls:{}", host))
+            }
+        }
+    }
+
+    fn fake_tor(delay_ms: u64) -> (FakeTor, Log) {
+        let log: Log = Arc::new(Mutex::new(Vec::new()));
+        let tor = FakeTor {
+            log: Arc::clone(&log),
+            delay: Duration::from_millis(delay_ms),
+        };
+        (tor, log)
+    }
+
+    fn events(log: &Log) -> Vec<Event> {
+        log.lock().expect("log lock").clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connect_starts_before_renderer_ready() {
+        let (tor, log) = fake_tor(200);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+
+        let Ok(NavigationStep::Connecting(target)) = pipeline.on_enter("https://example.com/page")
+        else {
+            panic!("expected a connect");
+        };
+        assert_eq!(target.host, "example.com");
+
+        // UI transition: the renderer is not ready yet
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        log.lock().expect("log lock").push(Event::RendererReady);
+
+        let ready = pipeline.renderer_ready().await.expect("connected");
+        assert_eq!(ready.connection, "tls:example.com");
+        assert_eq!(ready.target, target);
+
+        assert_eq!(
+            events(&log),
+            vec![
+                Event::ConnectStarted("example.com".to_string()),
+                Event::RendererReady,
+                Event::ConnectFinished("example.com".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_edit_cancels_pending_connect() {
+        let (tor, log) = fake_tor(500);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+
+        pipeline
+            .on_enter("https://first.example/")
+            .expect("valid URL");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        pipeline.on_edit();
+        assert!(!pipeline.has_pending());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(
+            events(&log),
+            vec![
+                Event::ConnectStarted("first.example".to_string()),
+                Event::ConnectDropped("first.example".to_string()),
+            ]
+        );
+        assert!(pipeline.renderer_ready().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_pipeline_leaves_no_connect() {
+        let (tor, log) = fake_tor(500);
+        let tasks = Arc::new(TaskRegistry::new());
+        let mut pipeline = NavigationPipeline::new(tor, 1024).with_tasks(Arc::clone(&tasks));
+
+        pipeline
+            .on_enter("https://first.example/")
+            .expect("valid URL");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(
+            tasks.live_tasks(TaskScope::Context),
+            vec!["navigation connect"]
+        );
+
+        drop(pipeline);
+        tasks.assert_no_leaks(TaskScope::Context).await;
+        assert_eq!(
+            events(&log).last(),
+            Some(&Event::ConnectDropped("first.example".to_string()))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_navigation_kind_selects_fetch_site() {
+        let (tor, _log) = fake_tor(1);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+        let synthesizer = HeaderSynthesizer::new();
+
+        let Ok(NavigationStep::Connecting(target)) = pipeline.on_navigate(
+            "https://example.com/next",
+            NavigationKind::LinkClick,
+            Some("https://www.example.com/page?id=1"),
+        ) else {
+            panic!("expected a connect");
+        };
+        assert_eq!(target.initiator.as_deref(), Some("https://www.example.com"));
+        assert_eq!(target.headers(&synthesizer).fetch_site, FetchSite::SameSite);
+
+        let Ok(NavigationStep::Connecting(target)) = pipeline.on_navigate(
+            "https://example.com/next",
+            NavigationKind::Reload,
+            Some("https://example.com/next"),
+        ) else {
+            panic!("expected a connect");
+        };
+        assert_eq!(target.kind, NavigationKind::Reload);
+        assert_eq!(target.headers(&synthesizer).fetch_site, FetchSite::None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_url_starts_nothing() {
+        let (tor, log) = fake_tor(1);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+
+        assert!(pipeline.on_enter("http://example.com/").is_err());
+        assert!(pipeline.on_enter("example").is_err());
+        assert!(!pipeline.has_pending());
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(events(&log).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_request_before_interstitial_choice() {
+        let (tor, log) = fake_tor(1);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+
+        let Ok(NavigationStep::Interstitial(page)) =
+            pipeline.on_enter("https://duckduckgo.com/?q=tor")
+        else {
+            panic!("expected the interstitial");
+        };
+        assert_eq!(page.page_url(), "forloop:onion-available");
+        assert!(pipeline.has_interstitial());
+        assert!(!pipeline.has_pending());
+
+        // However long the user takes, nothing goes out
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(events(&log).is_empty());
+
+        let target = pipeline
+            .resolve_interstitial(page.default_choice())
+            .expect("valid onion");
+        assert_eq!(target.context_id, page.context_id);
+        assert!(target.host.ends_with(".onion"));
+        let ready = pipeline.renderer_ready().await.expect("connected");
+        assert_eq!(
+            events(&log),
+            vec![
+                Event::ConnectStarted(target.host.clone()),
+                Event::ConnectFinished(target.host.clone()),
+            ]
+        );
+        assert_eq!(ready.target, target);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_edit_dismisses_interstitial() {
+        let (tor, log) = fake_tor(1);
+        let mut pipeline = NavigationPipeline::new(tor, 1024);
+
+        pipeline
+            .on_enter("https://www.facebook.com/")
+            .expect("valid URL");
+        pipeline.on_edit();
+        assert!(!pipeline.has_interstitial());
+        assert!(pipeline
+            .resolve_interstitial(InterstitialChoice::UseOnion)
+            .is_err());
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(events(&log).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_clearnet_choice_remembered_per_context() {
+        let (tor, _log) = fake_tor(1);
+        let mut pipeline = NavigationPipeline::new(tor.clone(), 1024);
+
+        pipeline
+            .on_enter("https://nytimes.com/")
+            .expect("valid URL");
+        let target = pipeline
+            .resolve_interstitial(InterstitialChoice::ContinueClearnet)
+            .expect("valid URL");
+        assert_eq!(target.host, "nytimes.com");
+
+        // Same context: no second prompt, for any subdomain
+        assert!(matches!(
+            pipeline.on_enter("https://www.nytimes.com/world"),
+            Ok(NavigationStep::Connecting(_))
+        ));
+        // Other mapped domains still prompt
+        assert!(matches!(
+            pipeline.on_enter("https://bbc.com/"),
+            Ok(NavigationStep::Interstitial(_))
+        ));
+
+        // A new context starts without the choice
+        let mut fresh = NavigationPipeline::new(tor, 1024);
+        assert!(matches!(
+            fresh.on_enter("https://nytimes.com/"),
+            Ok(NavigationStep::Interstitial(_))
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+//! Compiled-in table of official onion services.
+//!
+//! Reaching a service that runs its own onion through a Tor exit is
+//! strictly worse than using the onion: the exit sees the traffic
+//! metadata and the connection depends on the clearnet TLS certificate
+//! chain. Before a top-level navigation to a mapped domain, the pipeline
+//! shows an interstitial offering the onion instead.
+//!
+//! The table is deliberately small and never updated over the network.
+//! Every entry must be the service's own, publicly announced v3 address;
+//! additions go through review like any other privacy default.
+
+/// Registrable domain → official v3 onion host.
+///
+/// Keep sorted by domain.
+pub const ONION_ALTERNATIVES: &[(&str, &str)] = &[
+    (
+        "bbc.com",
+        "bbcnewsd73hkzno2ini43t4gblxvycyac5aw4gnv7t2rccijh7745uqd.onion",
+    ),
+    (
+        "duckduckgo.com",
+        "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion",
+    ),
+    (
+        "facebook.com",
+        "facebookwkhpilnemxj7asaniu7vnjjbiltxjqhye3mhbshg7kx5tfyd.onion",
+    ),
+    (
+        "nytimes.com",
+        "nytimesn7cgmftshazwhfgzm37qxb44r64ytbb2dj3x62d2lljsciiyd.onion",
+    ),
+    (
+        "proton.me",
+        "protonmailrmez3lotccipshtkleegetolb73fuirgj7r4o4vfu7ozyd.onion",
+    ),
+    (
+        "torproject.org",
+        "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion",
+    ),
+    (
+        "twitter.com",
+        "twitter3e4tixl4xyajtrzo62zg5vztmjuricljdp2c5kshju4avyoid.onion",
+    ),
+];
+
+/// Internal URL the interstitial is rendered at.
+pub const INTERSTITIAL_URL: &str = "forloop:onion-available";
+
+/// Find the mapped domain and onion host for `host`, if any.
+///
+/// Matches the domain itself and any subdomain of it.
+pub fn onion_alternative(host: &str) -> Option<(&'static str, &'static str)> {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    ONION_ALTERNATIVES
+        .iter()
+        .find(|(domain, _)| {
+            host == *domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        })
+        .copied()
+}
+
+/// The user's answer to the interstitial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InterstitialChoice {
+    /// Navigate to the onion service (the Enter default)
+    #[default]
+    UseOnion,
+    /// Continue to the clearnet site through an exit
+    ContinueClearnet,
+}
+
+/// Model for the `forloop:onion-available` page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionInterstitial {
+    /// Navigation context the interstitial belongs to
+    pub context_id: u64,
+    /// Mapped registrable domain
+    pub domain: &'static str,
+    /// URL the user entered
+    pub clearnet_url: String,
+    /// Same path on the onion service
+    pub onion_url: String,
+}
+
+impl OnionInterstitial {
+    /// Build the interstitial for `url` if its host is mapped.
+    pub fn for_url(context_id: u64, url: &str, host: &str) -> Option<Self> {
+        let (domain, onion) = onion_alternative(host)?;
+
+        // Keep path, query and fragment; the onion has its own host and port
+        let rest = url.split_once("://").map_or("", |(_, rest)| rest);
+        let path = rest
+            .find(['/', '?', '#'])
+            .map_or("/", |start| &rest[start..]);
+
+        Some(Self {
+            context_id,
+            domain,
+            clearnet_url: url.to_string(),
+            onion_url: format!("https://{}{}", onion, path),
+        })
+    }
+
+    /// Internal URL to show in the address bar while the page is up.
+    pub fn page_url(&self) -> &'static str {
+        INTERSTITIAL_URL
+    }
+
+    /// Choice taken when the user presses Enter.
+    pub fn default_choice(&self) -> InterstitialChoice {
+        InterstitialChoice::default()
+    }
+
+    /// URL to navigate to for `choice`.
+    pub fn url_for(&self, choice: InterstitialChoice) -> &str {
+        match choice {
+            InterstitialChoice::UseOnion => &self.onion_url,
+            InterstitialChoice::ContinueClearnet => &self.clearnet_url,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frames::FrameId;
+    use crate::headers::Destination;
+    use crate::policy::{validate_request, NetworkRequestMsg};
+
+    #[test]
+    fn test_table_is_well_formed() {
+        let domains: Vec<_> = ONION_ALTERNATIVES.iter().map(|(d, _)| *d).collect();
+        let mut sorted = domains.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(domains, sorted, "table must be sorted and unique");
+
+        for (domain, onion) in ONION_ALTERNATIVES {
+            assert!(!domain.ends_with(".onion"));
+            let url = format!("https://{}/", onion);
+            let msg = NetworkRequestMsg {
+                method: "GET".to_string(),
+                url,
+                headers: Vec::new(),
+                body: None,
+                frame_id: FrameId::TOP,
+                destination: Destination::Document,
+            };
+            assert!(
+                validate_request(msg, 0).is_ok(),
+                "{} is not a valid v3 onion",
+                onion
+            );
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let (domain, _) = onion_alternative("www.DuckDuckGo.com").expect("mapped");
+        assert_eq!(domain, "duckduckgo.com");
+        assert!(onion_alternative("duckduckgo.com.").is_some());
+
+        assert!(onion_alternative("notduckduckgo.com").is_none());
+        assert!(onion_alternative("duckduckgo.com.evil.example").is_none());
+        assert!(onion_alternative("example.com").is_none());
+    }
+
+    #[test]
+    fn test_interstitial_keeps_path() {
+        let page = OnionInterstitial::for_url(
+            3,
+            "https://www.nytimes.com:443/section/world?x=1",
+            "www.nytimes.com",
+        )
+        .expect("mapped");
+
+        assert_eq!(page.page_url(), "forloop:onion-available");
+        assert_eq!(page.default_choice(), InterstitialChoice::UseOnion);
+        assert_eq!(
+            page.url_for(InterstitialChoice::UseOnion),
+            "https://nytimesn7cgmftshazwhfgzm37qxb44r64ytbb2dj3x62d2lljsciiyd.onion/section/world?x=1"
+        );
+        assert_eq!(
+            page.url_for(InterstitialChoice::ContinueClearnet),
+            "https://www.nytimes.com:443/section/world?x=1"
+        );
+
+        let bare = OnionInterstitial::for_url(4, "https://bbc.com", "bbc.com").expect("mapped");
+        assert!(bare.onion_url.ends_with(".onion/"));
+    }
+}
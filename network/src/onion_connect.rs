@@ -0,0 +1,315 @@
+//! Onion service connection progress, timeouts and retry.
+//!
+//! An onion service legitimately takes 10–20 seconds to reach: fetch the
+//! descriptor, introduce ourselves, wait at the rendezvous point, then
+//! wait for the service to open the stream. A clearnet site that slow is
+//! usually dead, so onion requests get their own, longer first-byte
+//! timeout (`NetworkConfig::onion_first_byte_timeout`), the status bar
+//! follows the steps as Tor reports them, and a timeout names the step it
+//! stalled at and offers a retry.
+//!
+//! Tor keeps fetched descriptors until SIGNAL NEWNYM purges them. A retry
+//! after the descriptor arrived therefore asks for a circuit without
+//! NEWNYM, so the slowest step is not repeated.
+
+use std::fmt;
+
+use crate::tor_events::{HsDescAction, StreamStatus, TorEvent};
+
+/// Step an onion connection has reached, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OnionPhase {
+    /// Fetching the service descriptor from the HSDirs
+    LookingUp,
+    /// Descriptor in hand, contacting an introduction point
+    Introducing,
+    /// Introduction sent, waiting at the rendezvous point
+    Rendezvous,
+    /// Rendezvous joined, waiting for the service to open the stream
+    WaitingForService,
+    /// The service accepted the stream
+    Connected,
+}
+
+impl OnionPhase {
+    /// Get the status-bar text for this step.
+    pub fn status_text(&self) -> &'static str {
+        match self {
+            OnionPhase::LookingUp => "Looking up onion service…",
+            OnionPhase::Introducing => "Introducing…",
+            OnionPhase::Rendezvous => "Rendezvous…",
+            OnionPhase::WaitingForService => "Waiting for service…",
+            OnionPhase::Connected => "Connected",
+        }
+    }
+}
+
+/// Onion host of `url` without the ".onion" suffix, if it is one.
+fn onion_address(url: &str) -> Option<String> {
+    let host = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()?;
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    let address = host
+        .to_ascii_lowercase()
+        .strip_suffix(".onion")?
+        .to_string();
+    // Subdomains of an onion address share its descriptor
+    Some(address.rsplit('.').next().unwrap_or_default().to_string())
+}
+
+/// Follows one onion connection through the control-port events.
+#[derive(Debug, Clone)]
+pub struct OnionConnectTracker {
+    url: String,
+    address: String,
+    phase: OnionPhase,
+    descriptor: bool,
+}
+
+impl OnionConnectTracker {
+    /// Start tracking a request to `url`, if it is an onion URL.
+    pub fn for_url(url: &str) -> Option<Self> {
+        Some(Self {
+            address: onion_address(url)?,
+            url: url.to_string(),
+            phase: OnionPhase::LookingUp,
+            descriptor: false,
+        })
+    }
+
+    /// Get the step reached so far.
+    pub fn phase(&self) -> OnionPhase {
+        self.phase
+    }
+
+    /// Whether Tor holds a descriptor for the service.
+    pub fn has_descriptor(&self) -> bool {
+        self.descriptor
+    }
+
+    /// Apply a control-port event. Returns the new step if it advanced.
+    ///
+    /// Events for other services are ignored. Steps never go backwards;
+    /// Tor retries introduction points internally.
+    pub fn apply(&mut self, event: &TorEvent) -> Option<OnionPhase> {
+        let reached = match event {
+            TorEvent::HsDescriptor { action, address } if *address == self.address => {
+                match action {
+                    HsDescAction::Requested => return None,
+                    HsDescAction::Received => {
+                        self.descriptor = true;
+                        OnionPhase::Introducing
+                    }
+                    HsDescAction::Failed => {
+                        self.descriptor = false;
+                        return None;
+                    }
+                }
+            }
+            TorEvent::HsCircuit {
+                rendezvous,
+                address,
+            } if *address == self.address => {
+                if *rendezvous {
+                    OnionPhase::Rendezvous
+                } else {
+                    OnionPhase::Introducing
+                }
+            }
+            TorEvent::Stream { status, host }
+                if onion_address(host).as_deref() == Some(self.address.as_str()) =>
+            {
+                match status {
+                    StreamStatus::SentConnect => OnionPhase::WaitingForService,
+                    StreamStatus::Succeeded => OnionPhase::Connected,
+                    StreamStatus::New | StreamStatus::Failed | StreamStatus::Closed => return None,
+                }
+            }
+            _ => return None,
+        };
+
+        (reached > self.phase).then(|| {
+            self.phase = reached;
+            reached
+        })
+    }
+
+    /// Describe the timeout of this connection for the error page.
+    pub fn timed_out(&self) -> OnionTimeout {
+        OnionTimeout {
+            url: self.url.clone(),
+            phase: self.phase,
+            descriptor_cached: self.descriptor,
+        }
+    }
+}
+
+/// An onion request that produced no first byte in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionTimeout {
+    /// URL that timed out
+    pub url: String,
+    /// Last step reached
+    pub phase: OnionPhase,
+    /// Whether Tor still holds the service descriptor
+    pub descriptor_cached: bool,
+}
+
+impl OnionTimeout {
+    /// Get the error-page text for the step the connection stalled at.
+    pub fn message(&self) -> &'static str {
+        match self.phase {
+            OnionPhase::LookingUp => {
+                "The onion service could not be found. It may be offline, or the address may be wrong."
+            }
+            OnionPhase::Introducing | OnionPhase::Rendezvous => {
+                "The onion service did not answer. It may be offline or overloaded."
+            }
+            OnionPhase::WaitingForService | OnionPhase::Connected => {
+                "The onion service was reached but did not respond in time."
+            }
+        }
+    }
+
+    /// The one-click retry offered on the error page.
+    pub fn retry(&self) -> OnionRetry {
+        OnionRetry {
+            url: self.url.clone(),
+            reuse_descriptor: self.descriptor_cached,
+        }
+    }
+}
+
+impl fmt::Display for OnionTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // No URL: error strings end up in logs
+        write!(f, "stalled at \"{}\"", self.phase.status_text())
+    }
+}
+
+/// A retry of a timed-out onion request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionRetry {
+    /// URL to request again
+    pub url: String,
+    /// Open the circuit without purging Tor's descriptor cache
+    pub reuse_descriptor: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://abcxyz.onion/forum?t=1";
+
+    /// Feed control-port lines, collecting the steps reached.
+    fn script(tracker: &mut OnionConnectTracker, lines: &[&str]) -> Vec<OnionPhase> {
+        lines
+            .iter()
+            .filter_map(|line| TorEvent::parse(line))
+            .filter_map(|event| tracker.apply(&event))
+            .collect()
+    }
+
+    #[test]
+    fn test_progress_follows_events() {
+        let mut tracker = OnionConnectTracker::for_url(URL).expect("onion URL");
+        assert_eq!(tracker.phase(), OnionPhase::LookingUp);
+
+        let reached = script(
+            &mut tracker,
+            &[
+                "650 STREAM 12 NEW 0 abcxyz.onion:443 SOURCE_ADDR=127.0.0.1:50000 PURPOSE=USER",
+                "650 HS_DESC REQUESTED abcxyz NO_AUTH $AAAA~relay DESCID",
+                // Another tab's service does not move this one
+                "650 HS_DESC RECEIVED otherservice NO_AUTH $AAAA~relay DESCID",
+                "650 HS_DESC RECEIVED abcxyz NO_AUTH $AAAA~relay DESCID",
+                "650 CIRC 7 BUILT $A~a HS_STATE=HSCR_ESTABLISHED_IDLE REND_QUERY=abcxyz",
+                "650 CIRC 8 BUILT $B~b HS_STATE=HSCI_INTRO_SENT REND_QUERY=abcxyz",
+                "650 CIRC 7 EXTENDED $A~a HS_STATE=HSCR_ESTABLISHED_WAITING REND_QUERY=abcxyz",
+                "650 CIRC 8 BUILT $B~b HS_STATE=HSCI_DONE REND_QUERY=abcxyz",
+                "650 CIRC 7 EXTENDED $A~a HS_STATE=HSCR_JOINED REND_QUERY=abcxyz",
+                "650 STREAM 12 SENTCONNECT 7 abcxyz.onion:443",
+                "650 STREAM 12 SUCCEEDED 7 abcxyz.onion:443",
+            ],
+        );
+
+        assert_eq!(
+            reached,
+            vec![
+                OnionPhase::Introducing,
+                OnionPhase::Rendezvous,
+                OnionPhase::WaitingForService,
+                OnionPhase::Connected,
+            ]
+        );
+        let status: Vec<_> = reached.iter().map(OnionPhase::status_text).collect();
+        assert_eq!(
+            status[..3],
+            ["Introducing…", "Rendezvous…", "Waiting for service…"]
+        );
+    }
+
+    #[test]
+    fn test_timeout_keeps_descriptor_for_retry() {
+        let mut tracker = OnionConnectTracker::for_url(URL).expect("onion URL");
+        script(
+            &mut tracker,
+            &[
+                "650 HS_DESC RECEIVED abcxyz NO_AUTH $AAAA~relay DESCID",
+                "650 CIRC 8 BUILT $B~b HS_STATE=HSCI_INTRO_SENT REND_QUERY=abcxyz",
+            ],
+        );
+
+        let timeout = tracker.timed_out();
+        assert_eq!(timeout.phase, OnionPhase::Introducing);
+        assert_eq!(
+            timeout.message(),
+            "The onion service did not answer. It may be offline or overloaded."
+        );
+        assert!(!timeout.to_string().contains("abcxyz"));
+        assert_eq!(
+            timeout.retry(),
+            OnionRetry {
+                url: URL.to_string(),
+                reuse_descriptor: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_timeout_without_descriptor() {
+        let mut tracker = OnionConnectTracker::for_url(URL).expect("onion URL");
+        script(
+            &mut tracker,
+            &[
+                "650 HS_DESC REQUESTED abcxyz NO_AUTH $AAAA~relay DESCID",
+                "650 HS_DESC FAILED abcxyz NO_AUTH $AAAA~relay REASON=NOT_FOUND",
+            ],
+        );
+
+        let timeout = tracker.timed_out();
+        assert_eq!(timeout.phase, OnionPhase::LookingUp);
+        assert!(timeout
+            .message()
+            .starts_with("The onion service could not be found"));
+        assert!(!timeout.retry().reuse_descriptor);
+    }
+
+    #[test]
+    fn test_only_onion_urls_are_tracked() {
+        assert!(OnionConnectTracker::for_url("https://example.com/a.onion").is_none());
+
+        // Subdomain, case and port do not hide the service address
+        let mut tracker =
+            OnionConnectTracker::for_url("https://www.ABCXYZ.onion:8443/").expect("onion URL");
+        let reached = script(
+            &mut tracker,
+            &["650 HS_DESC RECEIVED abcxyz NO_AUTH $AAAA~relay DESCID"],
+        );
+        assert_eq!(reached, vec![OnionPhase::Introducing]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Circuits shared by the requests of one page load.
+//!
+//! By default every request gets a new circuit. That also means every
+//! image on a page can leave from a different exit, in a different
+//! country, which breaks many sites and gives an observer many circuits
+//! to correlate instead of one. The renderer and broker can instead
+//! make a page's requests under an `IsolationKey`, the page's first-party
+//! origin in its navigation context: requests with the same key share one
+//! circuit until the next navigation or New Loop, and requests with
+//! different keys never do.
+//!
+//! A key only lives in memory. It has no `Display`, its `Debug` hides the
+//! origin, and it cannot be serialized, so it never reaches a log or disk.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OnceCell;
+
+use crate::circuit::Circuit;
+use crate::frames::origin_of;
+
+/// First-party origin a page load's requests share a circuit under.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct IsolationKey {
+    context_id: u64,
+    origin: String,
+}
+
+impl IsolationKey {
+    /// Key for the page whose top-level document is at `url`, loaded by
+    /// the navigation `context_id`, or `None` if the URL has no https
+    /// origin.
+    pub fn for_url(context_id: u64, url: &str) -> Option<Self> {
+        origin_of(url).map(|origin| Self { context_id, origin })
+    }
+
+    /// The page's navigation context, which its churn is accounted under.
+    pub(crate) fn context_id(&self) -> u64 {
+        self.context_id
+    }
+}
+
+impl fmt::Debug for IsolationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IsolationKey(..)")
+    }
+}
+
+/// A key's circuit, built by the first request that needs it.
+pub(crate) type PageCircuit = Arc<OnceCell<Arc<Circuit>>>;
+
+/// Circuits of the current page load, one per key.
+#[derive(Default)]
+pub(crate) struct PageCircuits {
+    circuits: Mutex<HashMap<IsolationKey, PageCircuit>>,
+}
+
+impl PageCircuits {
+    /// Get the slot for `key`'s circuit, adding an empty one if needed.
+    pub(crate) fn slot(&self, key: &IsolationKey) -> PageCircuit {
+        let mut circuits = self.circuits.lock().expect("page circuits lock");
+        Arc::clone(circuits.entry(key.clone()).or_default())
+    }
+
+    /// Empty `key`'s slot if it still is `slot`, so the next request
+    /// builds a new circuit.
+    pub(crate) fn discard(&self, key: &IsolationKey, slot: &PageCircuit) {
+        let mut circuits = self.circuits.lock().expect("page circuits lock");
+        if circuits
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, slot))
+        {
+            circuits.remove(key);
+        }
+    }
+
+    /// Forget the circuit `circuit_id` under whichever key holds it.
+    pub(crate) fn forget(&self, circuit_id: &str) {
+        self.circuits
+            .lock()
+            .expect("page circuits lock")
+            .retain(|_, slot| slot.get().is_none_or(|circuit| circuit.id() != circuit_id));
+    }
+
+    /// Forget every key; their circuits close once no request uses them.
+    pub(crate) fn clear(&self) {
+        self.circuits.lock().expect("page circuits lock").clear();
+    }
+
+    /// Number of keys with a slot.
+    pub(crate) fn len(&self) -> usize {
+        self.circuits.lock().expect("page circuits lock").len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_is_origin_and_hidden() {
+        let key = IsolationKey::for_url(1, "https://News.Example/a?b").expect("key");
+        assert_eq!(
+            key,
+            IsolationKey::for_url(1, "https://news.example/other").expect("key")
+        );
+        assert_ne!(
+            key,
+            IsolationKey::for_url(1, "https://news.example:8443/").expect("key")
+        );
+        assert_ne!(
+            key,
+            IsolationKey::for_url(2, "https://news.example/a?b").expect("key")
+        );
+        assert_eq!(format!("{:?}", key), "IsolationKey(..)");
+        assert!(IsolationKey::for_url(1, "http://news.example/").is_none());
+    }
+}
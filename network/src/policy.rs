@@ -0,0 +1,487 @@
+//! Request policy shared by the broker and the network process.
+//!
+//! The broker validates every `NetworkRequestMsg` from a content process
+//! before forwarding it. The network process must not trust that check: a
+//! compromised broker could relax it for a compromised renderer. Both
+//! layers therefore call the same `validate_request`, and the IPC-driven
+//! path of `AnonymizedNetwork` only accepts its output, `ValidatedRequest`.
+
+use crate::frames::FrameId;
+use crate::headers::{strip_tracking_params, Destination, DANGEROUS_HEADERS};
+
+/// Methods a page may issue. CONNECT and TRACE are never allowed.
+const ALLOWED_METHODS: &[&str] = &["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+/// Headers only the transport may set (lowercase).
+const TRANSPORT_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "te",
+    "upgrade",
+];
+
+/// Length of a v3 onion address label (without ".onion").
+const ONION_V3_LEN: usize = 56;
+
+/// A network request as sent over IPC by a content process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkRequestMsg {
+    /// HTTP method
+    pub method: String,
+    /// Absolute URL
+    pub url: String,
+    /// Page-supplied headers
+    pub headers: Vec<(String, String)>,
+    /// Request body
+    pub body: Option<Vec<u8>>,
+    /// Frame that issued the request (`FrameId::TOP` for navigations)
+    pub frame_id: FrameId,
+    /// What the request is for
+    pub destination: Destination,
+}
+
+/// Wire byte for a destination.
+fn destination_to_wire(destination: Destination) -> u8 {
+    match destination {
+        Destination::Document => 0,
+        Destination::Image => 1,
+        Destination::Xhr => 2,
+        Destination::Font => 3,
+        Destination::Embed => 4,
+        Destination::Script => 5,
+        Destination::Style => 6,
+        Destination::Iframe => 7,
+    }
+}
+
+/// Destination for a wire byte.
+fn destination_from_wire(byte: u8) -> Result<Destination, PolicyViolation> {
+    match byte {
+        0 => Ok(Destination::Document),
+        1 => Ok(Destination::Image),
+        2 => Ok(Destination::Xhr),
+        3 => Ok(Destination::Font),
+        4 => Ok(Destination::Embed),
+        5 => Ok(Destination::Script),
+        6 => Ok(Destination::Style),
+        7 => Ok(Destination::Iframe),
+        _ => Err(PolicyViolation::Malformed),
+    }
+}
+
+impl NetworkRequestMsg {
+    /// Serialize for an IPC payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn put(buffer: &mut Vec<u8>, bytes: &[u8]) {
+            buffer.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(bytes);
+        }
+
+        let mut buffer = Vec::new();
+        put(&mut buffer, self.method.as_bytes());
+        put(&mut buffer, self.url.as_bytes());
+        buffer.extend_from_slice(&(self.headers.len() as u32).to_le_bytes());
+        for (name, value) in &self.headers {
+            put(&mut buffer, name.as_bytes());
+            put(&mut buffer, value.as_bytes());
+        }
+        match &self.body {
+            Some(body) => {
+                buffer.push(1);
+                put(&mut buffer, body);
+            }
+            None => buffer.push(0),
+        }
+        buffer.extend_from_slice(&self.frame_id.0.to_le_bytes());
+        buffer.push(destination_to_wire(self.destination));
+        buffer
+    }
+
+    /// Deserialize from an IPC payload.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PolicyViolation> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        let method = reader.string()?;
+        let url = reader.string()?;
+        let count = reader.u32()? as usize;
+        let mut headers = Vec::new();
+        for _ in 0..count {
+            headers.push((reader.string()?, reader.string()?));
+        }
+        let body = match reader.take(1)?[0] {
+            0 => None,
+            1 => Some(reader.field()?.to_vec()),
+            _ => return Err(PolicyViolation::Malformed),
+        };
+        let frame_id = FrameId(reader.u64()?);
+        let destination = destination_from_wire(reader.take(1)?[0])?;
+
+        if reader.pos != bytes.len() {
+            return Err(PolicyViolation::Malformed);
+        }
+
+        Ok(Self {
+            method,
+            url,
+            headers,
+            body,
+            frame_id,
+            destination,
+        })
+    }
+}
+
+/// Cursor over an IPC payload.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PolicyViolation> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(PolicyViolation::Malformed)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, PolicyViolation> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    fn u64(&mut self) -> Result<u64, PolicyViolation> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+
+    fn field(&mut self) -> Result<&'a [u8], PolicyViolation> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, PolicyViolation> {
+        String::from_utf8(self.field()?.to_vec()).map_err(|_| PolicyViolation::Malformed)
+    }
+}
+
+/// Why a request was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyViolation {
+    /// IPC payload could not be decoded
+    #[error("Malformed request message")]
+    Malformed,
+
+    /// Scheme other than HTTPS
+    #[error("Scheme not allowed: {0}")]
+    SchemeNotAllowed(String),
+
+    /// URL could not be parsed
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    /// .onion host that is not a valid v3 address
+    #[error("Invalid onion address: {0}")]
+    InvalidOnion(String),
+
+    /// Method not in the allowlist
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+
+    /// Identifying, transport-level or malformed header
+    #[error("Header not allowed: {0}")]
+    HeaderNotAllowed(String),
+
+    /// Body over the size cap
+    #[error("Request body too large: {size} bytes (limit {limit})")]
+    BodyTooLarge {
+        /// Body size in bytes
+        size: usize,
+        /// Configured limit in bytes
+        limit: usize,
+    },
+
+    /// Request names a frame that is not in the page
+    #[error("Unknown frame: {0}")]
+    UnknownFrame(u64),
+
+    /// Response the browser refuses to hand to the renderer
+    #[error("Blocked by policy: {0}")]
+    BlockedByPolicy(String),
+}
+
+/// A request that passed `validate_request`.
+///
+/// Can only be constructed by validation, so holding one proves the
+/// policy was applied in this process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    frame_id: FrameId,
+    destination: Destination,
+    tracking_params_removed: usize,
+}
+
+impl ValidatedRequest {
+    /// Get the HTTP method.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Get the URL.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Get the page-supplied headers.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Get the request body.
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    /// Get the frame that issued the request.
+    pub fn frame_id(&self) -> FrameId {
+        self.frame_id
+    }
+
+    /// Get what the request is for.
+    pub fn destination(&self) -> Destination {
+        self.destination
+    }
+
+    /// Get how many tracking parameters validation removed from the URL.
+    pub fn tracking_params_removed(&self) -> usize {
+        self.tracking_params_removed
+    }
+}
+
+/// Validate a request against the shared policy.
+///
+/// Checks scheme, host (including v3 onion validity), method, headers
+/// and body size, and strips tracking parameters from the URL.
+pub fn validate_request(
+    msg: NetworkRequestMsg,
+    max_request_bytes: usize,
+) -> Result<ValidatedRequest, PolicyViolation> {
+    let rest = match msg.url.split_once("://") {
+        Some(("https", rest)) => rest,
+        Some((scheme, _)) => return Err(PolicyViolation::SchemeNotAllowed(scheme.to_string())),
+        None => return Err(PolicyViolation::InvalidUrl(msg.url)),
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.contains('@') {
+        // Userinfo can smuggle a misleading host past a naive check
+        return Err(PolicyViolation::InvalidUrl(msg.url));
+    }
+    let host = authority
+        .rsplit_once(':')
+        .map_or(authority, |(host, _)| host)
+        .to_ascii_lowercase();
+    if host.is_empty() {
+        return Err(PolicyViolation::InvalidUrl(msg.url));
+    }
+    if host.ends_with(".onion") && !is_valid_onion(&host) {
+        return Err(PolicyViolation::InvalidOnion(host));
+    }
+
+    if !ALLOWED_METHODS.contains(&msg.method.as_str()) {
+        return Err(PolicyViolation::MethodNotAllowed(msg.method));
+    }
+
+    for (name, value) in &msg.headers {
+        let lower = name.to_ascii_lowercase();
+        let malformed = name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b))
+            || value.bytes().any(|b| matches!(b, b'\r' | b'\n' | 0));
+        if malformed
+            || DANGEROUS_HEADERS.contains(&lower.as_str())
+            || TRANSPORT_HEADERS.contains(&lower.as_str())
+        {
+            return Err(PolicyViolation::HeaderNotAllowed(name.clone()));
+        }
+    }
+
+    if let Some(body) = &msg.body {
+        if body.len() > max_request_bytes {
+            return Err(PolicyViolation::BodyTooLarge {
+                size: body.len(),
+                limit: max_request_bytes,
+            });
+        }
+    }
+
+    let (url, tracking_params_removed) = strip_tracking_params(&msg.url);
+    Ok(ValidatedRequest {
+        method: msg.method,
+        url,
+        headers: msg.headers,
+        body: msg.body,
+        frame_id: msg.frame_id,
+        destination: msg.destination,
+        tracking_params_removed,
+    })
+}
+
+/// Check a host is a v3 onion address (optionally with subdomains).
+///
+/// The checksum needs SHA3 and is verified by Tor itself; here we check
+/// the length, the base32 alphabet and the version byte (which always
+/// encodes as a trailing 'd').
+fn is_valid_onion(host: &str) -> bool {
+    let Some(name) = host.strip_suffix(".onion") else {
+        return false;
+    };
+    let label = name.rsplit('.').next().unwrap_or_default();
+
+    label.len() == ONION_V3_LEN
+        && label
+            .bytes()
+            .all(|b| matches!(b, b'a'..=b'z' | b'2'..=b'7'))
+        && label.ends_with('d')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: usize = 1024;
+
+    /// A well-known v3 address (the Tor Project's).
+    const ONION: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+    fn msg(method: &str, url: &str) -> NetworkRequestMsg {
+        NetworkRequestMsg {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: vec![("Accept".to_string(), "text/html".to_string())],
+            body: None,
+            frame_id: FrameId::TOP,
+            destination: Destination::Document,
+        }
+    }
+
+    /// Broker-side check, then IPC, then the network-side re-check.
+    fn through_both_layers(
+        msg: NetworkRequestMsg,
+    ) -> [Result<ValidatedRequest, PolicyViolation>; 2] {
+        let payload = msg.to_bytes();
+        let broker = validate_request(msg, LIMIT);
+        let network = NetworkRequestMsg::from_bytes(&payload)
+            .and_then(|decoded| validate_request(decoded, LIMIT));
+        [broker, network]
+    }
+
+    #[test]
+    fn test_valid_requests_pass() {
+        for url in [
+            "https://example.com/".to_string(),
+            format!("https://{}/index.html", ONION),
+            format!("https://www.{}:443/", ONION),
+        ] {
+            for result in through_both_layers(msg("GET", &url)) {
+                assert!(result.is_ok(), "{} rejected", url);
+            }
+        }
+
+        // Both layers strip tracking parameters
+        for result in through_both_layers(msg("GET", "https://x.com/?a=1&utm_source=mail&b=2")) {
+            let validated = result.expect("valid request");
+            assert_eq!(validated.url(), "https://x.com/?a=1&b=2");
+            assert_eq!(validated.tracking_params_removed(), 1);
+        }
+    }
+
+    #[test]
+    fn test_wire_roundtrip() {
+        let mut original = msg("POST", "https://example.com/form");
+        original.body = Some(b"a=1".to_vec());
+        original.frame_id = FrameId(7);
+        original.destination = Destination::Embed;
+        let decoded = NetworkRequestMsg::from_bytes(&original.to_bytes()).expect("valid payload");
+        assert_eq!(decoded, original);
+        for destination in Destination::ALL {
+            assert_eq!(
+                destination_from_wire(destination_to_wire(destination)),
+                Ok(destination)
+            );
+        }
+
+        assert_eq!(
+            NetworkRequestMsg::from_bytes(&[1, 0, 0]),
+            Err(PolicyViolation::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_both_layers_reject_violations() {
+        let mut too_big = msg("POST", "https://example.com/");
+        too_big.body = Some(vec![0u8; LIMIT + 1]);
+
+        let mut cookie = msg("GET", "https://example.com/");
+        cookie
+            .headers
+            .push(("Cookie".to_string(), "id=1".to_string()));
+
+        let mut injected = msg("GET", "https://example.com/");
+        injected
+            .headers
+            .push(("X-Test".to_string(), "a\r\nHost: evil".to_string()));
+
+        let mut host = msg("GET", "https://example.com/");
+        host.headers
+            .push(("Host".to_string(), "other.com".to_string()));
+
+        let cases = [
+            msg("GET", "http://example.com/"),
+            msg("GET", "file:///etc/passwd"),
+            msg("GET", "https://user@example.com/"),
+            msg("GET", "https://abcdef.onion/"),
+            msg(
+                "GET",
+                &format!("https://{}/", ONION.replace("wid.", "wia.")),
+            ),
+            msg("CONNECT", "https://example.com/"),
+            msg("TRACE", "https://example.com/"),
+            too_big,
+            cookie,
+            injected,
+            host,
+        ];
+
+        for case in cases {
+            for result in through_both_layers(case.clone()) {
+                assert!(result.is_err(), "{:?} accepted", case);
+            }
+        }
+    }
+
+    #[test]
+    fn test_network_rejects_what_a_compromised_broker_forwards() {
+        // The broker skipped validation and forwarded raw bytes
+        let payload = msg("GET", "http://tracker.example/").to_bytes();
+        let decoded = NetworkRequestMsg::from_bytes(&payload).expect("valid payload");
+
+        assert_eq!(
+            validate_request(decoded, LIMIT),
+            Err(PolicyViolation::SchemeNotAllowed("http".to_string()))
+        );
+    }
+}
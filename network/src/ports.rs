@@ -0,0 +1,62 @@
+//! Ports requests may not be sent to.
+//!
+//! A page can point a request at any port, and an exit will connect to
+//! it: without a check, a page could make the browser talk SMTP or IRC
+//! through Tor, relaying spam or abuse, and the odd port would stand out
+//! in the exit's traffic. The blocked list is the one browsers share
+//! (the Fetch standard's "bad ports", which Tor Browser inherits). Onion
+//! services are exempt: their traffic never leaves Tor, and they often
+//! run on unusual ports.
+
+/// Ports no request is sent to, in ascending order.
+pub const BLOCKED_PORTS: &[u16] = &[
+    1, 7, 9, 11, 13, 15, 17, 19, 20, 21, 22, 23, 25, 37, 42, 43, 53, 69, 77, 79, 87, 95, 101, 102,
+    103, 104, 109, 110, 111, 113, 115, 117, 119, 123, 135, 137, 139, 143, 161, 179, 389, 427, 465,
+    512, 513, 514, 515, 526, 530, 531, 532, 540, 548, 554, 556, 563, 587, 601, 636, 989, 990, 993,
+    995, 1719, 1720, 1723, 2049, 3659, 4045, 4190, 5060, 5061, 6000, 6566, 6665, 6666, 6667, 6668,
+    6669, 6679, 6697, 10080,
+];
+
+/// Ports always allowed, whatever `BLOCKED_PORTS` says.
+pub const ALLOWED_PORTS: &[u16] = &[443, 8443];
+
+/// Whether a request may be sent to `port` on `host`, an ASCII hostname.
+pub fn port_allowed(host: &str, port: u16) -> bool {
+    ALLOWED_PORTS.contains(&port)
+        || host.to_ascii_lowercase().ends_with(".onion")
+        || BLOCKED_PORTS.binary_search(&port).is_err()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocked_ports_sorted() {
+        assert!(BLOCKED_PORTS.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ALLOWED_PORTS
+            .iter()
+            .all(|port| !BLOCKED_PORTS.contains(port)));
+    }
+
+    #[test]
+    fn test_port_allowed() {
+        for port in [443, 8443, 80, 8080, 3000] {
+            assert!(port_allowed("example.com", port), "{} blocked", port);
+        }
+        for port in [25, 110, 143, 6667, 22, 10080] {
+            assert!(!port_allowed("example.com", port), "{} allowed", port);
+            assert!(!port_allowed("2001:db8::1", port), "{} allowed", port);
+        }
+    }
+
+    #[test]
+    fn test_onion_hosts_exempt() {
+        let onion = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        for port in [25, 6667, 22] {
+            assert!(port_allowed(onion, port), "{} blocked", port);
+        }
+        assert!(!port_allowed("onion.example", 25));
+        assert!(!port_allowed("example.onion.example", 25));
+    }
+}
@@ -0,0 +1,365 @@
+//! HTTP/2 to HTTP/1.1 fallback.
+//!
+//! Some servers negotiate h2 over ALPN and then fail to speak it: they
+//! send GOAWAY before processing anything, or break the connection with a
+//! protocol error. Firefox retries such requests over HTTP/1.1 on a new
+//! connection, so failing hard would both break the site and single us
+//! out. We do the same:
+//!
+//! - only connection-level HTTP/2 failures qualify (see
+//!   `fallback_eligible`); TLS and certificate errors never do, since
+//!   retrying those would be a downgrade an attacker could trigger
+//! - the retry happens once, on the same circuit, with ALPN restricted to
+//!   http/1.1
+//! - a successful fallback is remembered per (context, host), so the
+//!   page's other requests to that host go straight to HTTP/1.1; the
+//!   context is the first-party isolation origin, so one site's fallback
+//!   is never visible to another
+//! - `RequestMetrics` records that the request was downgraded
+
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::tls_fingerprint::TlsConfig;
+use crate::NetworkError;
+
+/// ALPN identifier for HTTP/1.1.
+pub const HTTP11_ALPN: &str = "http/1.1";
+
+/// HTTP/2 error codes (RFC 9113 section 7) that mean the server's HTTP/2
+/// support is broken rather than that the request was refused.
+const FALLBACK_ERROR_CODES: &[u32] = &[
+    0x0, // NO_ERROR (only with an immediate GOAWAY)
+    0x1, // PROTOCOL_ERROR
+    0x2, // INTERNAL_ERROR
+    0x3, // FLOW_CONTROL_ERROR
+    0x4, // SETTINGS_TIMEOUT
+    0x6, // FRAME_SIZE_ERROR
+    0x9, // COMPRESSION_ERROR
+    0xc, // INADEQUATE_SECURITY
+    0xd, // HTTP_1_1_REQUIRED
+];
+
+/// HTTP_1_1_REQUIRED: the server asks for HTTP/1.1 explicitly.
+const HTTP_1_1_REQUIRED: u32 = 0xd;
+
+/// How an HTTP/2 connection or stream failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Http2Failure {
+    /// The server sent GOAWAY
+    GoAway {
+        /// Error code
+        code: u32,
+        /// Highest stream the server processed; 0 if none
+        last_stream_id: u32,
+    },
+    /// We detected a connection error (bad frames, SETTINGS not acked)
+    Connection {
+        /// Error code
+        code: u32,
+    },
+    /// The server reset one stream
+    StreamReset {
+        /// Error code
+        code: u32,
+    },
+}
+
+impl fmt::Display for Http2Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Http2Failure::GoAway {
+                code,
+                last_stream_id,
+            } => write!(f, "GOAWAY {:#x} after stream {}", code, last_stream_id),
+            Http2Failure::Connection { code } => write!(f, "connection error {:#x}", code),
+            Http2Failure::StreamReset { code } => write!(f, "stream reset {:#x}", code),
+        }
+    }
+}
+
+/// Whether `error` allows retrying the request over HTTP/1.1.
+pub fn fallback_eligible(error: &NetworkError) -> bool {
+    let NetworkError::Http2(failure) = error else {
+        return false;
+    };
+    match *failure {
+        // A GOAWAY after streams were processed is a normal shutdown
+        Http2Failure::GoAway {
+            code,
+            last_stream_id,
+        } => last_stream_id == 0 && FALLBACK_ERROR_CODES.contains(&code),
+        Http2Failure::Connection { code } => code != 0 && FALLBACK_ERROR_CODES.contains(&code),
+        Http2Failure::StreamReset { code } => code == HTTP_1_1_REQUIRED,
+    }
+}
+
+/// What happened to one request at the protocol level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RequestMetrics {
+    /// Connection attempts made (1, or 2 after a fallback)
+    pub attempts: u32,
+    /// The HTTP/2 failure that made this request fall back, if it did
+    pub downgraded_from: Option<Http2Failure>,
+    /// HTTP/1.1 was used because an earlier request fell back
+    pub memoized: bool,
+}
+
+/// Hosts known to need HTTP/1.1, per context.
+#[derive(Debug, Default)]
+pub struct ProtocolMemo {
+    downgraded: Mutex<HashSet<(String, String)>>,
+}
+
+impl ProtocolMemo {
+    /// Create an empty memo.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether requests to `host` in `context` go straight to HTTP/1.1.
+    pub fn is_downgraded(&self, context: &str, host: &str) -> bool {
+        self.downgraded
+            .lock()
+            .expect("protocol memo lock")
+            .contains(&(context.to_string(), host.to_ascii_lowercase()))
+    }
+
+    /// Remember that `host` needed HTTP/1.1 in `context`.
+    pub fn record_downgrade(&self, context: &str, host: &str) {
+        self.downgraded
+            .lock()
+            .expect("protocol memo lock")
+            .insert((context.to_string(), host.to_ascii_lowercase()));
+    }
+
+    /// Forget every decision made in `context`.
+    pub fn forget_context(&self, context: &str) {
+        self.downgraded
+            .lock()
+            .expect("protocol memo lock")
+            .retain(|(c, _)| c != context);
+    }
+
+    /// Forget everything.
+    pub fn clear(&self) {
+        self.downgraded.lock().expect("protocol memo lock").clear();
+    }
+
+    /// Get the TLS configuration for a request to `host` in `context`.
+    pub fn tls_config(&self, context: &str, host: &str, base: &TlsConfig) -> TlsConfig {
+        if self.is_downgraded(context, host) {
+            http11_only(base)
+        } else {
+            base.clone()
+        }
+    }
+}
+
+/// Restrict `config`'s ALPN list to HTTP/1.1.
+pub fn http11_only(config: &TlsConfig) -> TlsConfig {
+    TlsConfig {
+        alpn_protocols: vec![HTTP11_ALPN.to_string()],
+        ..config.clone()
+    }
+}
+
+/// Run `attempt` with `base`, retrying once over HTTP/1.1 when HTTP/2
+/// fails in a way that allows it.
+///
+/// `attempt` gets the TLS configuration to connect with and must use the
+/// same circuit each time.
+pub async fn request_with_fallback<T, F, Fut>(
+    memo: &ProtocolMemo,
+    context: &str,
+    host: &str,
+    base: &TlsConfig,
+    mut attempt: F,
+) -> Result<(T, RequestMetrics), NetworkError>
+where
+    F: FnMut(TlsConfig) -> Fut,
+    Fut: Future<Output = Result<T, NetworkError>>,
+{
+    let mut metrics = RequestMetrics {
+        attempts: 1,
+        memoized: memo.is_downgraded(context, host),
+        ..RequestMetrics::default()
+    };
+
+    let error = match attempt(memo.tls_config(context, host, base)).await {
+        Ok(response) => return Ok((response, metrics)),
+        Err(e) if !metrics.memoized && fallback_eligible(&e) => e,
+        Err(e) => return Err(e),
+    };
+
+    if let NetworkError::Http2(failure) = error {
+        log::debug!(
+            "{} failed over HTTP/2 ({}), retrying over HTTP/1.1",
+            host,
+            failure
+        );
+        metrics.downgraded_from = Some(failure);
+    }
+    metrics.attempts += 1;
+
+    let response = attempt(http11_only(base)).await?;
+    memo.record_downgrade(context, host);
+    Ok((response, metrics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls_fingerprint::TlsFingerprintNormalizer;
+    use crate::tls_handshake::{TlsFailure, TlsFailureReason};
+    use std::cell::RefCell;
+
+    /// Server whose HTTP/2 fails with `h2_error`; HTTP/1.1 works.
+    struct MockServer {
+        h2_error: fn() -> NetworkError,
+        offered: RefCell<Vec<Vec<String>>>,
+    }
+
+    impl MockServer {
+        fn new(h2_error: fn() -> NetworkError) -> Self {
+            Self {
+                h2_error,
+                offered: RefCell::new(Vec::new()),
+            }
+        }
+
+        async fn serve(&self, tls: TlsConfig) -> Result<&'static str, NetworkError> {
+            let h2 = tls.alpn_protocols.first().map(String::as_str) == Some("h2");
+            self.offered.borrow_mut().push(tls.alpn_protocols);
+            if h2 {
+                Err((self.h2_error)())
+            } else {
+                Ok(HTTP11_ALPN)
+            }
+        }
+
+        fn attempts(&self) -> Vec<Vec<String>> {
+            self.offered.take()
+        }
+    }
+
+    fn tls() -> TlsConfig {
+        TlsFingerprintNormalizer::new()
+            .create_config()
+            .expect("tls config")
+    }
+
+    async fn fetch(
+        server: &MockServer,
+        memo: &ProtocolMemo,
+        context: &str,
+    ) -> Result<(&'static str, RequestMetrics), NetworkError> {
+        request_with_fallback(memo, context, "broken.example", &tls(), |tls| {
+            server.serve(tls)
+        })
+        .await
+    }
+
+    fn immediate_goaway() -> NetworkError {
+        NetworkError::Http2(Http2Failure::GoAway {
+            code: 0x0,
+            last_stream_id: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_broken_h2_falls_back_once() {
+        let memo = ProtocolMemo::new();
+        let site = "https://site.example";
+
+        for h2_error in [
+            immediate_goaway as fn() -> NetworkError,
+            || NetworkError::Http2(Http2Failure::Connection { code: 0x1 }),
+            || NetworkError::Http2(Http2Failure::StreamReset { code: 0xd }),
+        ] {
+            memo.clear();
+            let server = MockServer::new(h2_error);
+            let (protocol, metrics) = fetch(&server, &memo, site).await.expect("fell back");
+
+            assert_eq!(protocol, HTTP11_ALPN);
+            assert_eq!(metrics.attempts, 2);
+            assert_eq!(metrics.downgraded_from, failure_of(&h2_error()));
+            assert!(!metrics.memoized);
+            assert_eq!(
+                server.attempts(),
+                [vec!["h2", "http/1.1"], vec!["http/1.1"]]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ineligible_failures_not_retried() {
+        let memo = ProtocolMemo::new();
+
+        for h2_error in [
+            (|| {
+                NetworkError::TlsError(TlsFailure::new(
+                    "broken.example",
+                    TlsFailureReason::Alert(45),
+                ))
+            }) as fn() -> NetworkError,
+            || {
+                NetworkError::Http2(Http2Failure::GoAway {
+                    code: 0x0,
+                    last_stream_id: 5,
+                })
+            },
+            || NetworkError::Http2(Http2Failure::StreamReset { code: 0x7 }),
+            || NetworkError::Timeout,
+        ] {
+            let server = MockServer::new(h2_error);
+            let error = fetch(&server, &memo, "https://site.example")
+                .await
+                .expect_err("no fallback");
+
+            assert_eq!(error.to_string(), h2_error().to_string());
+            assert_eq!(server.attempts().len(), 1);
+        }
+        assert!(!memo.is_downgraded("https://site.example", "broken.example"));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_memoized_per_context() {
+        let memo = ProtocolMemo::new();
+        let server = MockServer::new(immediate_goaway);
+
+        fetch(&server, &memo, "https://a.example")
+            .await
+            .expect("fell back");
+        server.attempts();
+
+        // Subresources in the same context skip the h2 probe
+        let (_, metrics) = fetch(&server, &memo, "https://a.example")
+            .await
+            .expect("memoized");
+        assert_eq!(metrics.attempts, 1);
+        assert!(metrics.memoized);
+        assert_eq!(metrics.downgraded_from, None);
+        assert_eq!(server.attempts(), [vec!["http/1.1"]]);
+
+        // Another context probes again
+        let (_, metrics) = fetch(&server, &memo, "https://b.example")
+            .await
+            .expect("fell back");
+        assert_eq!(metrics.attempts, 2);
+        assert_eq!(server.attempts().len(), 2);
+
+        memo.forget_context("https://a.example");
+        assert!(!memo.is_downgraded("https://a.example", "broken.example"));
+        assert!(memo.is_downgraded("https://b.example", "BROKEN.example"));
+    }
+
+    fn failure_of(error: &NetworkError) -> Option<Http2Failure> {
+        match error {
+            NetworkError::Http2(failure) => Some(*failure),
+            _ => None,
+        }
+    }
+}
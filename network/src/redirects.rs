@@ -0,0 +1,331 @@
+//! Redirect following.
+//!
+//! `AnonymizedNetwork::request_following_redirects` follows 3xx responses
+//! so callers don't each have to. Every hop is an ordinary request, so it
+//! gets a brand new circuit like any other. The policy:
+//!
+//! - at most `RedirectPolicy::max_hops` redirects
+//! - never to anything but https (no downgrade)
+//! - a URL already visited in the chain is a loop and ends it
+//! - 303, and 301/302 after a POST, continue as GET without the body;
+//!   307 and 308 keep the method and body
+//! - a cross-origin redirect to an onion service is not followed but
+//!   surfaced in `RedirectChain::onion_location`, for the UI to offer
+//!
+//! The final response carries the URLs it went through.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::circuit::parse_url;
+use crate::frames::origin_of;
+use crate::NetworkError;
+
+/// Redirect limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectPolicy {
+    /// Most redirects followed for one request
+    pub max_hops: usize,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self { max_hops: 10 }
+    }
+}
+
+/// Why a redirect was not followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectRefusal {
+    /// More than `max_hops` redirects
+    TooManyHops(usize),
+    /// Target is not https
+    Downgrade(String),
+    /// Target was already visited in this chain
+    Loop(String),
+    /// No usable Location header
+    BadLocation(String),
+}
+
+impl fmt::Display for RedirectRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedirectRefusal::TooManyHops(hops) => write!(f, "more than {} redirects", hops),
+            RedirectRefusal::Downgrade(url) => write!(f, "redirect to non-HTTPS {}", url),
+            RedirectRefusal::Loop(url) => write!(f, "redirect loop at {}", url),
+            RedirectRefusal::BadLocation(location) => {
+                write!(f, "unusable Location {:?}", location)
+            }
+        }
+    }
+}
+
+/// The redirects a response went through.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedirectChain {
+    /// URLs that answered with a redirect, in order; empty if none did
+    pub hops: Vec<String>,
+    /// Onion service the last response redirected to, not followed
+    pub onion_location: Option<String>,
+}
+
+/// What to do with a redirect response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedirectStep {
+    /// Request this URL next
+    Follow(String),
+    /// Stop and offer this onion URL to the user
+    SurfaceOnion(String),
+}
+
+/// Whether `status` is a redirect we follow.
+pub fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Method for the request after a `status` redirect of a `method`
+/// request, and whether the body goes with it.
+pub fn redirect_method(status: u16, method: &str) -> (String, bool) {
+    match status {
+        307 | 308 => (method.to_string(), true),
+        303 if method != "HEAD" => ("GET".to_string(), false),
+        301 | 302 if method == "POST" => ("GET".to_string(), false),
+        _ => (method.to_string(), false),
+    }
+}
+
+/// Resolve a Location header against the URL that sent it.
+pub fn resolve_location(base: &str, location: &str) -> Result<String, RedirectRefusal> {
+    let location = location.trim();
+    let bad = || RedirectRefusal::BadLocation(location.to_string());
+    if location.is_empty() || location.bytes().any(|b| b.is_ascii_control()) {
+        return Err(bad());
+    }
+
+    if has_scheme(location) {
+        return Ok(location.to_string());
+    }
+    if let Some(rest) = location.strip_prefix("//") {
+        return Ok(format!("https://{}", rest));
+    }
+
+    let origin = origin_of(base).ok_or_else(bad)?;
+    let path = parse_url(base).map_err(|_| bad())?.path;
+    let path = path.split('#').next().unwrap_or_default();
+    if location.starts_with('/') {
+        return Ok(format!("{}{}", origin, location));
+    }
+    let (dir_path, _query) = path.split_once('?').unwrap_or((path, ""));
+    if location.starts_with('?') {
+        return Ok(format!("{}{}{}", origin, dir_path, location));
+    }
+    if location.starts_with('#') {
+        return Ok(format!("{}{}{}", origin, path, location));
+    }
+    let dir = dir_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    Ok(format!("{}{}/{}", origin, dir, location))
+}
+
+/// Follows one request's redirects, enforcing `RedirectPolicy`.
+#[derive(Debug)]
+pub struct RedirectTracker {
+    policy: RedirectPolicy,
+    visited: HashSet<String>,
+    chain: RedirectChain,
+}
+
+impl RedirectTracker {
+    /// Start tracking a request to `url`.
+    pub fn new(policy: RedirectPolicy, url: &str) -> Self {
+        Self {
+            policy,
+            visited: HashSet::from([without_fragment(url).to_string()]),
+            chain: RedirectChain::default(),
+        }
+    }
+
+    /// Decide what to do with a redirect from `from` to `location`.
+    pub fn next(
+        &mut self,
+        from: &str,
+        location: Option<&str>,
+    ) -> Result<RedirectStep, NetworkError> {
+        let refuse = |refusal| Err(NetworkError::RedirectRefused(refusal));
+        let Some(location) = location else {
+            return refuse(RedirectRefusal::BadLocation(String::new()));
+        };
+        let target = match resolve_location(from, location) {
+            Ok(target) => target,
+            Err(refusal) => return refuse(refusal),
+        };
+        self.chain.hops.push(from.to_string());
+
+        let is_https = target
+            .get(..8)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://"));
+        if !is_https {
+            return refuse(RedirectRefusal::Downgrade(target));
+        }
+        let Ok(parsed) = parse_url(&target) else {
+            return refuse(RedirectRefusal::BadLocation(location.to_string()));
+        };
+        if parsed.host.to_ascii_lowercase().ends_with(".onion")
+            && origin_of(&target) != origin_of(from)
+        {
+            self.chain.onion_location = Some(target.clone());
+            return Ok(RedirectStep::SurfaceOnion(target));
+        }
+        if self.chain.hops.len() > self.policy.max_hops {
+            return refuse(RedirectRefusal::TooManyHops(self.policy.max_hops));
+        }
+        if !self.visited.insert(without_fragment(&target).to_string()) {
+            return refuse(RedirectRefusal::Loop(target));
+        }
+        Ok(RedirectStep::Follow(target))
+    }
+
+    /// Take the chain, once a response is final.
+    pub fn finish(self) -> RedirectChain {
+        self.chain
+    }
+}
+
+/// Whether `location` starts with a scheme (RFC 3986: a letter, then
+/// letters, digits, `+`, `-` or `.`, then `:`) before any `/`, `?` or
+/// `#`, making it an absolute URL.
+fn has_scheme(location: &str) -> bool {
+    let end = location.find(['/', '?', '#']).unwrap_or(location.len());
+    let Some((scheme, _)) = location[..end].split_once(':') else {
+        return false;
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+fn without_fragment(url: &str) -> &str {
+    url.split('#').next().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refusal(result: Result<RedirectStep, NetworkError>) -> RedirectRefusal {
+        match result {
+            Err(NetworkError::RedirectRefused(refusal)) => refusal,
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        let base = "https://example.com/a/b?x=1";
+        for (location, expected) in [
+            ("https://other.example/", "https://other.example/"),
+            ("//other.example/p", "https://other.example/p"),
+            ("/root", "https://example.com/root"),
+            ("c", "https://example.com/a/c"),
+            ("?y=2", "https://example.com/a/b?y=2"),
+            ("HTTPS://other.example/", "HTTPS://other.example/"),
+            ("javascript:alert(1)", "javascript:alert(1)"),
+            (
+                "/go?to=https://x.example/",
+                "https://example.com/go?to=https://x.example/",
+            ),
+            (
+                "next?to=https://x.example/",
+                "https://example.com/a/next?to=https://x.example/",
+            ),
+            ("#https://x", "https://example.com/a/b?x=1#https://x"),
+            ("a:b/c", "a:b/c"),
+            ("1a:b", "https://example.com/a/1a:b"),
+        ] {
+            assert_eq!(resolve_location(base, location).as_deref(), Ok(expected));
+        }
+        assert!(resolve_location(base, "").is_err());
+        assert!(resolve_location(base, "/a\r\nSet-Cookie: x").is_err());
+    }
+
+    #[test]
+    fn test_method_semantics() {
+        assert_eq!(redirect_method(303, "POST"), ("GET".to_string(), false));
+        assert_eq!(redirect_method(303, "HEAD"), ("HEAD".to_string(), false));
+        assert_eq!(redirect_method(302, "POST"), ("GET".to_string(), false));
+        assert_eq!(redirect_method(301, "GET"), ("GET".to_string(), false));
+        assert_eq!(redirect_method(307, "POST"), ("POST".to_string(), true));
+        assert_eq!(redirect_method(308, "PUT"), ("PUT".to_string(), true));
+    }
+
+    #[test]
+    fn test_policy_refusals() {
+        let mut tracker = RedirectTracker::new(RedirectPolicy::default(), "https://a.example/");
+        assert_eq!(
+            refusal(tracker.next("https://a.example/", Some("http://a.example/"))),
+            RedirectRefusal::Downgrade("http://a.example/".to_string())
+        );
+
+        let mut tracker = RedirectTracker::new(RedirectPolicy::default(), "https://a.example/");
+        assert_eq!(
+            tracker
+                .next("https://a.example/", Some("HTTPS://b.example/"))
+                .expect("follows"),
+            RedirectStep::Follow("HTTPS://b.example/".to_string())
+        );
+
+        let mut tracker = RedirectTracker::new(RedirectPolicy::default(), "https://a.example/");
+        assert_eq!(
+            tracker
+                .next("https://a.example/", Some("/b"))
+                .expect("follows"),
+            RedirectStep::Follow("https://a.example/b".to_string())
+        );
+        assert_eq!(
+            refusal(tracker.next("https://a.example/b", Some("/#top"))),
+            RedirectRefusal::Loop("https://a.example/#top".to_string())
+        );
+
+        let mut tracker =
+            RedirectTracker::new(RedirectPolicy { max_hops: 2 }, "https://a.example/0");
+        for hop in 1..=2 {
+            let from = format!("https://a.example/{}", hop - 1);
+            assert!(tracker.next(&from, Some(&format!("/{}", hop))).is_ok());
+        }
+        assert_eq!(
+            refusal(tracker.next("https://a.example/2", Some("/3"))),
+            RedirectRefusal::TooManyHops(2)
+        );
+    }
+
+    #[test]
+    fn test_cross_origin_onion_surfaced() {
+        let onion = format!("https://{}.onion/", "a".repeat(56));
+        let mut tracker = RedirectTracker::new(RedirectPolicy::default(), "https://a.example/");
+        tracker
+            .next("https://a.example/", Some("https://www.a.example/"))
+            .expect("follows");
+        assert_eq!(
+            tracker
+                .next("https://www.a.example/", Some(&onion))
+                .expect("surfaced"),
+            RedirectStep::SurfaceOnion(onion.clone())
+        );
+        assert_eq!(
+            tracker.finish(),
+            RedirectChain {
+                hops: vec![
+                    "https://a.example/".to_string(),
+                    "https://www.a.example/".to_string()
+                ],
+                onion_location: Some(onion.clone()),
+            }
+        );
+
+        // Within the onion service, redirects are followed as usual
+        let mut tracker = RedirectTracker::new(RedirectPolicy::default(), &onion);
+        assert_eq!(
+            tracker.next(&onion, Some("/login")).expect("follows"),
+            RedirectStep::Follow(format!("{}login", onion))
+        );
+    }
+}
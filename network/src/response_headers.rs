@@ -0,0 +1,478 @@
+//! Response header normalization.
+//!
+//! Caching headers describe the origin rather than the resource: Date and
+//! Age expose the server's (or CDN layer's) clock skew, Expires and
+//! Cache-Control the caching setup behind it. forloop never caches across
+//! contexts, so none of them is needed. Others make the browser act on the
+//! server's behalf later: Alt-Svc moves future connections to an endpoint
+//! that can recognize the client across circuits, Report-To and NEL send
+//! reports nobody asked for, Accept-CH asks for client hints, and Link
+//! preloads start subresource fetches whose timing the server controls.
+//! Headers that survive are normalized in a fixed order:
+//!
+//! 1. Credential challenges are removed (see `strip_challenge_headers`).
+//! 2. `STRIPPED_RESPONSE_HEADERS` are removed, and the resource hints in
+//!    each Link (`RESOURCE_HINT_RELS`); a Link with nothing else goes.
+//! 3. Names are lowercased and duplicates merged as RFC 9110 allows:
+//!    list-valued fields are comma-joined in arrival order, anything else
+//!    keeps its first value.
+//! 4. Headers are sorted: `CANONICAL_RESPONSE_ORDER` first, the rest
+//!    alphabetically.
+//! 5. At most `MAX_RESPONSE_HEADERS` headers and `MAX_RESPONSE_HEADER_BYTES`
+//!    bytes are kept, in that order; the overflow is counted for the
+//!    shield.
+//! 6. Every Cache-Control is removed and a single `no-store` appended,
+//!    outside the cap.
+//!
+//! The output therefore depends only on the header values that are kept,
+//! never on their order, casing or repetition, or on which caching
+//! headers the server sent. Nothing downstream, including the per-context
+//! RAM cache, ever sees the originals.
+
+use crate::challenge::strip_challenge_headers;
+
+/// Response headers removed outright (lowercase).
+pub const STRIPPED_RESPONSE_HEADERS: &[&str] = &[
+    // State and validators
+    "set-cookie",
+    "set-cookie2",
+    "etag",
+    "last-modified",
+    // Request tracing and CDN details
+    "x-request-id",
+    "x-correlation-id",
+    "x-amzn-requestid",
+    "cf-ray",
+    "x-cache",
+    "x-served-by",
+    "x-timer",
+    "x-trace-id",
+    // Clocks and caching, meaningless without a cache
+    "date",
+    "age",
+    "expires",
+    "vary",
+    // Connections, reports and hints the server steers
+    "alt-svc",
+    "report-to",
+    "reporting-endpoints",
+    "nel",
+    "server-timing",
+    "accept-ch",
+    "critical-ch",
+];
+
+/// Link relations that make the browser fetch or connect ahead of the
+/// page (lowercase).
+pub const RESOURCE_HINT_RELS: &[&str] = &[
+    "preload",
+    "modulepreload",
+    "prefetch",
+    "prerender",
+    "preconnect",
+    "dns-prefetch",
+];
+
+/// The only Cache-Control a normalized response carries.
+pub const NORMALIZED_CACHE_CONTROL: &str = "no-store";
+
+/// Order of well-known headers in normalized output (lowercase).
+pub const CANONICAL_RESPONSE_ORDER: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-encoding",
+    "content-language",
+    "content-disposition",
+    "content-range",
+    "accept-ranges",
+    "location",
+    "retry-after",
+    "strict-transport-security",
+    "content-security-policy",
+    "content-security-policy-report-only",
+    "cross-origin-opener-policy",
+    "cross-origin-embedder-policy",
+    "cross-origin-resource-policy",
+    "referrer-policy",
+    "permissions-policy",
+    "x-content-type-options",
+    "x-frame-options",
+    "access-control-allow-origin",
+    "access-control-allow-credentials",
+    "access-control-allow-methods",
+    "access-control-allow-headers",
+    "access-control-expose-headers",
+    "timing-allow-origin",
+    "link",
+];
+
+/// Headers whose value is a comma-separated list, so duplicates may be
+/// joined (lowercase). Any other duplicate keeps its first value.
+const LIST_RESPONSE_HEADERS: &[&str] = &[
+    "accept-ranges",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-expose-headers",
+    "allow",
+    "content-encoding",
+    "content-language",
+    "content-security-policy",
+    "content-security-policy-report-only",
+    "link",
+    "permissions-policy",
+    "referrer-policy",
+    "timing-allow-origin",
+    "via",
+];
+
+/// Most headers delivered to the renderer, not counting Cache-Control.
+pub const MAX_RESPONSE_HEADERS: usize = 64;
+
+/// Most header bytes (name, ": ", value) delivered to the renderer.
+pub const MAX_RESPONSE_HEADER_BYTES: usize = 16 * 1024;
+
+/// Normalize response headers as described in the module docs.
+///
+/// Returns the headers and how many were dropped by the cap.
+pub fn normalize_response_headers(
+    mut headers: Vec<(String, String)>,
+) -> (Vec<(String, String)>, usize) {
+    // A 401 renders its body, but nothing may turn it into a prompt
+    strip_challenge_headers(&mut headers);
+
+    let mut merged: Vec<(String, String)> = Vec::new();
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        if name == "cache-control" || STRIPPED_RESPONSE_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        let value = match name.as_str() {
+            "link" => match strip_resource_hints(&value) {
+                Some(links) => links,
+                None => continue,
+            },
+            _ => value,
+        };
+        match merged.iter_mut().find(|(seen, _)| *seen == name) {
+            Some((_, first)) if LIST_RESPONSE_HEADERS.contains(&name.as_str()) => {
+                first.push_str(", ");
+                first.push_str(&value);
+            }
+            Some(_) => {}
+            None => merged.push((name, value)),
+        }
+    }
+
+    merged.sort_by(|(a, _), (b, _)| {
+        let rank = |name: &str| {
+            CANONICAL_RESPONSE_ORDER
+                .iter()
+                .position(|known| *known == name)
+                .unwrap_or(CANONICAL_RESPONSE_ORDER.len())
+        };
+        rank(a).cmp(&rank(b)).then_with(|| a.cmp(b))
+    });
+
+    let (mut kept, mut bytes, mut dropped) = (0, 0, 0);
+    merged.retain(|(name, value)| {
+        let len = name.len() + 2 + value.len();
+        let fits = kept < MAX_RESPONSE_HEADERS && bytes + len <= MAX_RESPONSE_HEADER_BYTES;
+        if fits {
+            kept += 1;
+            bytes += len;
+        } else {
+            dropped += 1;
+        }
+        fits
+    });
+
+    merged.push((
+        "cache-control".to_string(),
+        NORMALIZED_CACHE_CONTROL.to_string(),
+    ));
+    (merged, dropped)
+}
+
+/// Remove resource hints from a Link value; `None` if nothing is left.
+fn strip_resource_hints(value: &str) -> Option<String> {
+    let kept: Vec<&str> = split_links(value)
+        .into_iter()
+        .filter(|link| !is_resource_hint(link))
+        .collect();
+    (!kept.is_empty()).then(|| kept.join(", "))
+}
+
+/// Split a Link value at the commas outside URIs and quoted strings.
+fn split_links(value: &str) -> Vec<&str> {
+    let (mut links, mut start) = (Vec::new(), 0);
+    let (mut in_uri, mut in_quotes) = (false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            '<' if !in_quotes => in_uri = true,
+            '>' if !in_quotes => in_uri = false,
+            '"' if !in_uri => in_quotes = !in_quotes,
+            ',' if !in_uri && !in_quotes => {
+                links.push(value[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    links.push(value[start..].trim());
+    links.retain(|link| !link.is_empty());
+    links
+}
+
+/// Whether one link's rel names a resource hint.
+fn is_resource_hint(link: &str) -> bool {
+    let params = link.split_once('>').map_or(link, |(_, params)| params);
+    params.split(';').any(|param| {
+        let Some((name, rels)) = param.split_once('=') else {
+            return false;
+        };
+        name.trim().eq_ignore_ascii_case("rel")
+            && rels
+                .trim()
+                .trim_matches('"')
+                .split_ascii_whitespace()
+                .any(|rel| {
+                    RESOURCE_HINT_RELS
+                        .iter()
+                        .any(|hint| rel.eq_ignore_ascii_case(hint))
+                })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_conflicting_caching_headers_normalize_identically() {
+        let first = vec![
+            header("Content-Type", "text/html"),
+            header("Date", "Tue, 14 Nov 2023 22:13:20 GMT"),
+            header("Cache-Control", "public, max-age=3600"),
+            header("Age", "812"),
+            header("Expires", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            header("Vary", "Accept-Encoding"),
+            header("cache-control", "no-cache"),
+            header("ETag", "\"abc123\""),
+        ];
+        let second = vec![
+            header("cache-control", "private, immutable"),
+            header("expires", "0"),
+            header("Content-Type", "text/html"),
+            header("vary", "*"),
+            header("date", "Mon, 01 Jan 2024 00:00:00 GMT"),
+        ];
+
+        let expected = vec![
+            header("content-type", "text/html"),
+            header("cache-control", NORMALIZED_CACHE_CONTROL),
+        ];
+        assert_eq!(normalize_response_headers(first), (expected.clone(), 0));
+        assert_eq!(normalize_response_headers(second), (expected, 0));
+    }
+
+    #[test]
+    fn test_no_store_added_when_absent() {
+        let (normalized, _) = normalize_response_headers(vec![header("content-length", "12")]);
+        assert_eq!(
+            normalized,
+            vec![
+                header("content-length", "12"),
+                header("cache-control", NORMALIZED_CACHE_CONTROL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_challenges_removed_before_normalizing() {
+        let (normalized, _) = normalize_response_headers(vec![
+            header("WWW-Authenticate", "Basic realm=\"x\""),
+            header("Cache-Control", "no-store"),
+        ]);
+        assert_eq!(
+            normalized,
+            vec![header("cache-control", NORMALIZED_CACHE_CONTROL)]
+        );
+    }
+
+    #[test]
+    fn test_duplicates_merged_per_header() {
+        let (normalized, dropped) = normalize_response_headers(vec![
+            header("Content-Type", "text/html"),
+            header("Link", "</a.css>; rel=stylesheet"),
+            header("content-type", "application/octet-stream"),
+            header("Content-Security-Policy", "default-src 'self'"),
+            header("Location", "/first"),
+            header("LINK", "</b>; rel=canonical"),
+            header("content-security-policy", "img-src 'none'"),
+            header("Location", "/second"),
+            header("X-Frame-Options", "DENY"),
+            header("x-frame-options", "SAMEORIGIN"),
+        ]);
+        assert_eq!(dropped, 0);
+        assert_eq!(
+            normalized,
+            vec![
+                // Singletons keep the first value
+                header("content-type", "text/html"),
+                header("location", "/first"),
+                // Lists are joined in arrival order
+                header(
+                    "content-security-policy",
+                    "default-src 'self', img-src 'none'"
+                ),
+                header("x-frame-options", "DENY"),
+                header("link", "</a.css>; rel=stylesheet, </b>; rel=canonical"),
+                header("cache-control", NORMALIZED_CACHE_CONTROL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stripped_in_any_casing() {
+        let casings = |name: &str| {
+            let alternating: String = name
+                .chars()
+                .enumerate()
+                .map(|(i, c)| match i % 2 {
+                    0 => c.to_ascii_uppercase(),
+                    _ => c,
+                })
+                .collect();
+            let title: String = name
+                .split('-')
+                .map(|word| {
+                    let (first, rest) = word.split_at(word.len().min(1));
+                    first.to_ascii_uppercase() + rest
+                })
+                .collect::<Vec<_>>()
+                .join("-");
+            [
+                name.to_string(),
+                name.to_ascii_uppercase(),
+                title,
+                alternating,
+            ]
+        };
+
+        for name in STRIPPED_RESPONSE_HEADERS
+            .iter()
+            .chain(crate::challenge::CHALLENGE_HEADERS)
+            .chain(&["cache-control"])
+        {
+            for casing in casings(name) {
+                let (normalized, _) = normalize_response_headers(vec![
+                    header(&casing, "1"),
+                    header("Content-Type", "text/html"),
+                ]);
+                assert_eq!(
+                    normalized,
+                    vec![
+                        header("content-type", "text/html"),
+                        header("cache-control", NORMALIZED_CACHE_CONTROL),
+                    ],
+                    "{} kept",
+                    casing
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_resource_hints_removed_from_link() {
+        for rel in RESOURCE_HINT_RELS {
+            let (normalized, _) = normalize_response_headers(vec![header(
+                "Link",
+                &format!("</x.js>; rel={}; as=script", rel.to_ascii_uppercase()),
+            )]);
+            assert_eq!(
+                normalized,
+                vec![header("cache-control", NORMALIZED_CACHE_CONTROL)]
+            );
+        }
+
+        let (normalized, _) = normalize_response_headers(vec![header(
+            "Link",
+            "</a,b.css>; rel=preload; as=style, <https://example.com/>; rel=\"canonical\", \
+             </f.woff2>; rel=\"preload prefetch\"; title=\"a, b\", </next>; rel=next",
+        )]);
+        assert_eq!(
+            normalized,
+            vec![
+                header(
+                    "link",
+                    "<https://example.com/>; rel=\"canonical\", </next>; rel=next"
+                ),
+                header("cache-control", NORMALIZED_CACHE_CONTROL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_order_independent_of_server() {
+        let headers = vec![
+            header("X-Powered-By", "PHP"),
+            header("Server", "nginx"),
+            header("Content-Length", "5"),
+            header("Referrer-Policy", "no-referrer"),
+            header("Content-Type", "text/plain"),
+            header("Accept-Ranges", "bytes"),
+        ];
+        let (normalized, _) = normalize_response_headers(headers.clone());
+        let names: Vec<_> = normalized.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "content-type",
+                "content-length",
+                "accept-ranges",
+                "referrer-policy",
+                "server",
+                "x-powered-by",
+                "cache-control",
+            ]
+        );
+
+        let mut reversed = headers;
+        reversed.reverse();
+        assert_eq!(normalize_response_headers(reversed).0, normalized);
+    }
+
+    #[test]
+    fn test_header_count_and_bytes_capped() {
+        let mut headers = vec![header("Content-Type", "text/html")];
+        headers.extend((0..200).map(|i| header(&format!("x-filler-{:03}", i), "1")));
+        let (normalized, dropped) = normalize_response_headers(headers);
+        assert_eq!(normalized.len(), MAX_RESPONSE_HEADERS + 1);
+        assert_eq!(dropped, 201 - MAX_RESPONSE_HEADERS);
+        assert_eq!(normalized[0], header("content-type", "text/html"));
+        assert_eq!(
+            normalized.last(),
+            Some(&header("cache-control", NORMALIZED_CACHE_CONTROL))
+        );
+
+        // One oversized header is dropped; smaller ones after it still fit
+        let (normalized, dropped) = normalize_response_headers(vec![
+            header("Link", &"x".repeat(MAX_RESPONSE_HEADER_BYTES)),
+            header("Content-Type", "text/html"),
+            header("X-Extra", "1"),
+        ]);
+        assert_eq!(dropped, 1);
+        assert_eq!(
+            normalized,
+            vec![
+                header("content-type", "text/html"),
+                header("x-extra", "1"),
+                header("cache-control", NORMALIZED_CACHE_CONTROL),
+            ]
+        );
+    }
+}
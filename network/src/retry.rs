@@ -0,0 +1,200 @@
+//! Retrying circuit-level failures on a fresh circuit.
+//!
+//! Tor circuits fail often: the exit refuses the port, or the circuit
+//! collapses while the stream is being set up. Such a request is retried
+//! once on a brand new circuit rather than failing the page.
+//!
+//! Only failures that happen before any response byte arrives are
+//! `CircuitFailure`s, so a request whose response has started is never
+//! replayed. Everything else is returned as is.
+
+use std::fmt;
+use std::future::Future;
+
+use crate::socks::SocksReplyCode;
+use crate::NetworkError;
+
+/// A circuit failed before any of the response arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CircuitFailure {
+    /// The exit could not open the stream (SOCKS5 general failure)
+    General,
+    /// The exit's policy refuses the destination or port
+    ExitPolicy,
+    /// The exit has no route to the destination network
+    NetworkUnreachable,
+    /// The destination refused the connection
+    ConnectionRefused,
+    /// The circuit timed out or collapsed while connecting (TTL expired)
+    TtlExpired,
+    /// The connection was reset before any response byte
+    ConnectionReset,
+}
+
+impl CircuitFailure {
+    /// Map a SOCKS5 reply code, if it is a circuit-level failure.
+    pub(crate) fn from_socks_reply(code: SocksReplyCode) -> Option<Self> {
+        match code {
+            SocksReplyCode::GeneralFailure => Some(CircuitFailure::General),
+            SocksReplyCode::NotAllowed => Some(CircuitFailure::ExitPolicy),
+            SocksReplyCode::NetworkUnreachable => Some(CircuitFailure::NetworkUnreachable),
+            SocksReplyCode::ConnectionRefused => Some(CircuitFailure::ConnectionRefused),
+            SocksReplyCode::TtlExpired => Some(CircuitFailure::TtlExpired),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CircuitFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CircuitFailure::General => "exit could not connect",
+            CircuitFailure::ExitPolicy => "refused by the exit policy",
+            CircuitFailure::NetworkUnreachable => "network unreachable from the exit",
+            CircuitFailure::ConnectionRefused => "connection refused",
+            CircuitFailure::TtlExpired => "circuit expired while connecting",
+            CircuitFailure::ConnectionReset => "connection reset before the response",
+        })
+    }
+}
+
+/// When a request is retried on a new circuit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// New circuits tried after the first one fails
+    pub max_retries: u32,
+    /// Failures worth a new circuit
+    pub retryable: Vec<CircuitFailure>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 1,
+            // A refused connection or an unreachable network is the
+            // destination's doing and would fail the same from any exit
+            retryable: vec![
+                CircuitFailure::General,
+                CircuitFailure::ExitPolicy,
+                CircuitFailure::TtlExpired,
+                CircuitFailure::ConnectionReset,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether `error` is retried after `retries` earlier retries.
+    pub fn allows(&self, error: &NetworkError, retries: u32) -> bool {
+        match error {
+            NetworkError::CircuitFailed(failure) => {
+                retries < self.max_retries && self.retryable.contains(failure)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Run `attempt`, running it again while it fails in a way `policy`
+/// retries.
+///
+/// `attempt` must open a new circuit each time and close it when it fails.
+pub async fn retry_on_new_circuit<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut attempt: F,
+) -> Result<T, NetworkError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, NetworkError>>,
+{
+    let mut retries = 0;
+    loop {
+        match attempt().await {
+            Err(e) if policy.allows(&e, retries) => {
+                log::debug!("{}, retrying on a new circuit", e);
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socks::socks5_connect;
+    use std::cell::Cell;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Proxy that answers one CONNECT with `reply`.
+    fn mock_proxy(reply: u8) -> tokio::io::DuplexStream {
+        let (client, mut proxy) = tokio::io::duplex(512);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 0]).await.expect("choice");
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut rest = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut rest).await.expect("rest");
+            let _ = proxy.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+        });
+        client
+    }
+
+    #[tokio::test]
+    async fn test_socks_failure_retried_on_new_circuit() {
+        // The first circuit's exit fails the stream, the second connects
+        let replies = [0x01, 0x00];
+        let circuits = Cell::new(0);
+        let connected = retry_on_new_circuit(&RetryPolicy::default(), || {
+            let circuit = circuits.get();
+            circuits.set(circuit + 1);
+            async move {
+                let mut stream = mock_proxy(replies[circuit]);
+                socks5_connect(&mut stream, "example.com", 443, None).await?;
+                Ok(format!("circuit {}", circuit))
+            }
+        })
+        .await;
+        assert_eq!(connected.expect("retried"), "circuit 1");
+        assert_eq!(circuits.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retry_limits() {
+        let policy = &RetryPolicy::default();
+        let count = |error: fn() -> NetworkError| async move {
+            let attempts = Cell::new(0);
+            let result: Result<(), _> = retry_on_new_circuit(policy, || {
+                attempts.set(attempts.get() + 1);
+                async move { Err(error()) }
+            })
+            .await;
+            assert!(result.is_err());
+            attempts.get()
+        };
+
+        // Retried once, then the failure is returned
+        assert_eq!(
+            count(|| NetworkError::CircuitFailed(CircuitFailure::TtlExpired)).await,
+            2
+        );
+        // Not a failure another exit would fix
+        assert_eq!(
+            count(|| NetworkError::CircuitFailed(CircuitFailure::ConnectionRefused)).await,
+            1
+        );
+        // The response had started; never replayed
+        assert_eq!(
+            count(|| NetworkError::InvalidResponse("truncated chunk".into())).await,
+            1
+        );
+
+        let never = RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        };
+        assert!(!never.allows(&NetworkError::CircuitFailed(CircuitFailure::General), 0));
+    }
+}
@@ -0,0 +1,405 @@
+//! Pre-render sanitization of main-document HTML.
+//!
+//! Some markup fires network requests as soon as the parser sees it, before
+//! any of our policies are attached to the document. A minimal token-level
+//! pass over text/html bodies removes those constructs first:
+//!
+//! - `<meta http-equiv=refresh>`: removed and reported, so the refresh
+//!   policy decides whether (and when) to follow it
+//! - `<link rel=preconnect|dns-prefetch|prefetch>`: removed
+//! - `ping` on `<a>` and `<area>`: attribute removed
+//! - `<base href>` pointing off-origin: href removed
+//!
+//! Everything else is passed through byte for byte. The sanitizer is
+//! streaming: only an incomplete tag or comment is held back between chunks.
+
+use crate::frames::origin_of;
+use crate::html_tokens::{HtmlTokenizer, Tag, Token};
+
+/// Resource hints that open connections on their own.
+const STRIPPED_LINK_RELS: &[&str] = &["preconnect", "dns-prefetch", "prefetch"];
+
+/// A meta refresh taken out of the document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaRefresh {
+    /// Delay before refreshing
+    pub delay_secs: u32,
+    /// Target, if any (otherwise the page reloads itself)
+    pub url: Option<String>,
+}
+
+/// What was changed in a document, for the shield UI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SanitizeStats {
+    /// Meta refreshes handed to the refresh policy
+    pub refreshes_routed: usize,
+    /// Resource hint links removed
+    pub hints_stripped: usize,
+    /// ping attributes removed
+    pub pings_stripped: usize,
+    /// Off-origin base hrefs removed
+    pub bases_neutralized: usize,
+    /// Response headers over the delivery cap
+    pub headers_dropped: usize,
+}
+
+impl SanitizeStats {
+    /// Total number of constructs neutralized.
+    pub fn total(&self) -> usize {
+        self.refreshes_routed
+            + self.hints_stripped
+            + self.pings_stripped
+            + self.bases_neutralized
+            + self.headers_dropped
+    }
+}
+
+/// Outcome of sanitizing a document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SanitizeReport {
+    /// Counters
+    pub stats: SanitizeStats,
+    /// Meta refreshes for the refresh policy, in document order
+    pub refreshes: Vec<MetaRefresh>,
+}
+
+/// Whether a Content-Type denotes an HTML document.
+pub fn is_html(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/html"))
+}
+
+/// Sanitize a response body if it is an HTML document.
+///
+/// Returns the body to hand to the renderer and, for HTML, the report.
+pub(crate) fn sanitize_response(
+    url: &str,
+    headers: &[(String, String)],
+    body: Vec<u8>,
+) -> (Vec<u8>, Option<SanitizeReport>) {
+    let html = headers
+        .iter()
+        .any(|(name, value)| name.eq_ignore_ascii_case("content-type") && is_html(value));
+    if !html {
+        return (body, None);
+    }
+    let Some(origin) = origin_of(url) else {
+        return (body, None);
+    };
+
+    let (body, report) = sanitize_html(&origin, &body);
+    (body, Some(report))
+}
+
+/// Sanitize a complete HTML body.
+pub fn sanitize_html(origin: &str, body: &[u8]) -> (Vec<u8>, SanitizeReport) {
+    let mut sanitizer = HtmlSanitizer::new(origin);
+    let mut out = sanitizer.feed(body);
+    out.extend(sanitizer.finish());
+    (out, sanitizer.into_report())
+}
+
+/// Streaming HTML sanitizer for one document.
+#[derive(Debug)]
+pub struct HtmlSanitizer {
+    /// Document origin ("https://host[:port]"), lowercase
+    origin: String,
+    tokenizer: HtmlTokenizer,
+    report: SanitizeReport,
+}
+
+impl HtmlSanitizer {
+    /// Create a sanitizer for a document served from `origin`.
+    pub fn new(origin: &str) -> Self {
+        Self {
+            origin: origin.trim_end_matches('/').to_ascii_lowercase(),
+            tokenizer: HtmlTokenizer::default(),
+            report: SanitizeReport::default(),
+        }
+    }
+
+    /// Sanitize the next chunk. Returns the bytes that are final.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len());
+        let mut tokenizer = std::mem::take(&mut self.tokenizer);
+        tokenizer.feed(chunk, |token| match token {
+            Token::StartTag(tag, bytes) => self.rewrite_start_tag(bytes, &tag, &mut out),
+            Token::Text(bytes)
+            | Token::RawText(bytes)
+            | Token::EndTag(_, bytes)
+            | Token::Other(bytes) => out.extend_from_slice(bytes),
+        });
+        self.tokenizer = tokenizer;
+        out
+    }
+
+    /// Flush whatever is held back at the end of the document.
+    ///
+    /// An unterminated tag at EOF is never acted on by a browser, so it is
+    /// passed through as text.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.tokenizer.finish()
+    }
+
+    /// Get what has been changed so far.
+    pub fn report(&self) -> &SanitizeReport {
+        &self.report
+    }
+
+    /// Consume the sanitizer, returning its report.
+    pub fn into_report(self) -> SanitizeReport {
+        self.report
+    }
+
+    fn rewrite_start_tag(&mut self, bytes: &[u8], tag: &Tag, out: &mut Vec<u8>) {
+        let mut removed: Vec<(usize, usize)> = Vec::new();
+
+        match tag.name.as_str() {
+            "meta"
+                if tag
+                    .attr("http-equiv")
+                    .is_some_and(|v| v.eq_ignore_ascii_case("refresh")) =>
+            {
+                let refresh = parse_refresh(tag.attr("content").unwrap_or_default());
+                self.report.refreshes.push(refresh);
+                self.report.stats.refreshes_routed += 1;
+                return;
+            }
+            "link"
+                if tag.attr("rel").is_some_and(|rel| {
+                    rel.split_ascii_whitespace().any(|token| {
+                        STRIPPED_LINK_RELS
+                            .iter()
+                            .any(|hint| token.eq_ignore_ascii_case(hint))
+                    })
+                }) =>
+            {
+                self.report.stats.hints_stripped += 1;
+                return;
+            }
+            "a" | "area" => {
+                for attr in tag.attrs.iter().filter(|a| a.name == "ping") {
+                    removed.push(attr.span);
+                    self.report.stats.pings_stripped += 1;
+                }
+            }
+            "base" => {
+                for attr in tag.attrs.iter().filter(|a| a.name == "href") {
+                    if !self.is_same_origin(&attr.value) {
+                        removed.push(attr.span);
+                        self.report.stats.bases_neutralized += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let mut pos = 0;
+        for (start, end) in removed {
+            out.extend_from_slice(&bytes[pos..start]);
+            pos = end;
+        }
+        out.extend_from_slice(&bytes[pos..]);
+    }
+
+    fn is_same_origin(&self, href: &str) -> bool {
+        let href = href.trim();
+        let scheme_len = href
+            .find(':')
+            .filter(|&i| {
+                i > 0
+                    && href[..i]
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+            })
+            .map(|i| i + 1);
+
+        let absolute = match (href.strip_prefix("//"), scheme_len) {
+            (Some(rest), _) => {
+                let scheme = self.origin.split("//").next().unwrap_or_default();
+                format!("{}//{}", scheme, rest)
+            }
+            (None, Some(_)) => href.to_string(),
+            // Relative: resolves against the document
+            (None, None) => return true,
+        };
+
+        let origin_end = absolute
+            .find("//")
+            .map(|i| {
+                i + 2
+                    + absolute[i + 2..]
+                        .find(['/', '?', '#'])
+                        .unwrap_or(absolute.len() - i - 2)
+            })
+            .unwrap_or(absolute.len());
+        absolute[..origin_end].eq_ignore_ascii_case(&self.origin)
+    }
+}
+
+/// Parse a refresh `content` value: "5; url=https://example.com/".
+fn parse_refresh(content: &str) -> MetaRefresh {
+    let content = content.trim();
+    let digits = content.bytes().take_while(u8::is_ascii_digit).count();
+    let delay_secs = content[..digits].parse().unwrap_or(0);
+
+    let rest = content[digits..]
+        .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit())
+        .trim_start_matches(|c: char| c == ';' || c == ',' || c.is_ascii_whitespace());
+    let rest = match rest.get(..4) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("url=") => &rest[4..],
+        _ => rest,
+    };
+    let url = rest.trim().trim_matches(|c| c == '\'' || c == '"');
+
+    MetaRefresh {
+        delay_secs,
+        url: (!url.is_empty()).then(|| url.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: &str = "https://example.com";
+
+    fn sanitize(html: &str) -> (String, SanitizeReport) {
+        let (out, report) = sanitize_html(ORIGIN, html.as_bytes());
+        (String::from_utf8(out).expect("utf-8"), report)
+    }
+
+    /// Feed byte by byte to exercise every chunk boundary.
+    fn sanitize_streaming(html: &str) -> String {
+        let mut sanitizer = HtmlSanitizer::new(ORIGIN);
+        let mut out = Vec::new();
+        for byte in html.as_bytes() {
+            out.extend(sanitizer.feed(std::slice::from_ref(byte)));
+        }
+        out.extend(sanitizer.finish());
+        String::from_utf8(out).expect("utf-8")
+    }
+
+    #[test]
+    fn test_meta_refresh_routed() {
+        let (out, report) = sanitize(
+            "<head><META HTTP-EQUIV=\"Refresh\" content=\"3; URL='https://t.example/x'\"></head>",
+        );
+
+        assert_eq!(out, "<head></head>");
+        assert_eq!(
+            report.refreshes,
+            vec![MetaRefresh {
+                delay_secs: 3,
+                url: Some("https://t.example/x".to_string()),
+            }]
+        );
+        assert_eq!(report.stats.refreshes_routed, 1);
+    }
+
+    #[test]
+    fn test_resource_hints_stripped() {
+        let (out, report) = sanitize(
+            "<link rel=preconnect href=https://a.example>\
+             <link rel=\"DNS-Prefetch\" href=//b.example>\
+             <link rel='prefetch' href=/next>\
+             <link rel=stylesheet href=/site.css>",
+        );
+
+        assert_eq!(out, "<link rel=stylesheet href=/site.css>");
+        assert_eq!(report.stats.hints_stripped, 3);
+    }
+
+    #[test]
+    fn test_ping_stripped() {
+        let (out, report) =
+            sanitize("<a href=\"/x\" ping=\"https://t.example/p\">x</a><area PING=/p href=/y>");
+
+        assert_eq!(out, "<a href=\"/x\">x</a><area href=/y>");
+        assert_eq!(report.stats.pings_stripped, 2);
+    }
+
+    #[test]
+    fn test_off_origin_base_neutralized() {
+        let (out, report) = sanitize(
+            "<base href=\"https://evil.example/\" target=_blank>\
+             <base href=/static/>\
+             <base href=\"https://EXAMPLE.com/app/\">\
+             <base href=//evil.example/>",
+        );
+
+        assert_eq!(
+            out,
+            "<base target=_blank>\
+             <base href=/static/>\
+             <base href=\"https://EXAMPLE.com/app/\">\
+             <base>"
+        );
+        assert_eq!(report.stats.bases_neutralized, 2);
+        assert_eq!(report.stats.total(), 2);
+    }
+
+    #[test]
+    fn test_raw_text_and_comments_untouched() {
+        let html = "<script>var s = '<a ping=x>';</script>\
+                    <!-- <link rel=preconnect href=x> -->\
+                    <style>a[ping] { color: red }</style>\
+                    <textarea><meta http-equiv=refresh content=0></textarea>";
+        let (out, report) = sanitize(html);
+
+        assert_eq!(out, html);
+        assert_eq!(report.stats.total(), 0);
+    }
+
+    #[test]
+    fn test_abruptly_closed_comments() {
+        // Both are complete comments; what follows is live markup
+        for comment in ["<!-->", "<!--->"] {
+            let html = format!("{}<a ping=/p>x</a><!-- -->", comment);
+            let expected = format!("{}<a>x</a><!-- -->", comment);
+
+            let (out, report) = sanitize(&html);
+            assert_eq!(out, expected);
+            assert_eq!(report.stats.pings_stripped, 1);
+            assert_eq!(sanitize_streaming(&html), expected);
+        }
+    }
+
+    #[test]
+    fn test_malformed_markup() {
+        // Unterminated tag at EOF, stray '<', nested quotes
+        let (out, report) = sanitize("1 < 2 <a title='say \"hi\" >' ping=x>t</a><a ping=y");
+        assert_eq!(out, "1 < 2 <a title='say \"hi\" >'>t</a><a ping=y");
+        assert_eq!(report.stats.pings_stripped, 1);
+    }
+
+    #[test]
+    fn test_unaffected_content_is_byte_identical() {
+        let html = "<!DOCTYPE html>\r\n<html lang=en><head><meta charset=\"utf-8\">\
+                    <title>T &amp; <b></title><link rel=icon href=/f.ico></head>\
+                    <body class='x'><p>caf\u{e9} <br/><img src=a.png alt=\"<>\"></p>\
+                    <?xml ?></body></html>\n";
+
+        let (out, report) = sanitize(html);
+        assert_eq!(out, html);
+        assert_eq!(report, SanitizeReport::default());
+        assert_eq!(sanitize_streaming(html), html);
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_document() {
+        let html = "<a href=/ ping=/p>x</a><script>'</scr' + 'ipt>'</script>\
+                    <meta http-equiv=refresh content=0><link rel=prefetch href=/n>";
+
+        assert_eq!(sanitize_streaming(html), sanitize(html).0);
+    }
+
+    #[test]
+    fn test_is_html() {
+        assert!(is_html("text/html"));
+        assert!(is_html("Text/HTML; charset=utf-8"));
+        assert!(!is_html("application/json"));
+    }
+}
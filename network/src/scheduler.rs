@@ -0,0 +1,358 @@
+//! Per-context subresource request scheduling.
+//!
+//! The order and concurrency in which requests leave a context is visible
+//! at the exit. We issue them the way Firefox does:
+//!
+//! - HTTP/1.1: never pipelined, one request per connection at a time
+//! - HTTP/2: streams carry Firefox's priority groups, rooted at the
+//!   `Http2Fingerprint` default priority
+//! - Order: main document, then CSS, then fonts and scripts, then images,
+//!   FIFO within a class
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::tls_fingerprint::{Http2Fingerprint, Http2Priority};
+
+/// Firefox's "leader" priority group stream (render-blocking CSS).
+const GROUP_LEADER: u32 = 3;
+/// Firefox's "follower" priority group stream (fonts, scripts).
+const GROUP_FOLLOWER: u32 = 5;
+/// Firefox's "unblocked" priority group stream (images).
+const GROUP_UNBLOCKED: u32 = 7;
+
+/// What a request fetches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Main document
+    Document,
+    /// Stylesheet
+    Stylesheet,
+    /// Web font
+    Font,
+    /// Script
+    Script,
+    /// Image
+    Image,
+}
+
+impl ResourceKind {
+    /// Scheduling class; lower is issued first.
+    fn rank(self) -> u8 {
+        match self {
+            ResourceKind::Document => 0,
+            ResourceKind::Stylesheet => 1,
+            ResourceKind::Font | ResourceKind::Script => 2,
+            ResourceKind::Image => 3,
+        }
+    }
+
+    /// HTTP/2 stream priority for this kind.
+    pub fn h2_priority(self) -> Http2Priority {
+        let base = Http2Fingerprint::default().priority;
+        let depends_on = match self {
+            ResourceKind::Document => return base,
+            ResourceKind::Stylesheet => GROUP_LEADER,
+            ResourceKind::Font | ResourceKind::Script => GROUP_FOLLOWER,
+            ResourceKind::Image => GROUP_UNBLOCKED,
+        };
+        Http2Priority { depends_on, ..base }
+    }
+}
+
+/// Protocol spoken on the context's connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/1.1 over a pool of connections
+    Http11 {
+        /// Number of connections in the pool
+        connections: usize,
+    },
+    /// HTTP/2 multiplexed over one connection
+    Http2 {
+        /// Maximum concurrent streams
+        max_streams: usize,
+    },
+}
+
+/// A request handed out by the scheduler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledRequest {
+    /// Scheduler-assigned ID, passed back to `complete`
+    pub id: u64,
+    /// URL to fetch
+    pub url: String,
+    /// What is being fetched
+    pub kind: ResourceKind,
+    /// Connection index to issue it on
+    pub connection: usize,
+    /// Stream priority (HTTP/2 only)
+    pub priority: Option<Http2Priority>,
+}
+
+/// Snapshot of the scheduler for debugging.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueState {
+    /// Queued requests in issue order
+    pub queued: Vec<(u64, ResourceKind)>,
+    /// Requests issued and not yet completed
+    pub in_flight: Vec<u64>,
+    /// Concurrency limit
+    pub limit: usize,
+}
+
+/// Queued request, ordered by class then arrival.
+#[derive(Debug)]
+struct Queued {
+    key: Reverse<(u8, u64)>,
+    url: String,
+    kind: ResourceKind,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Orders and throttles a context's requests.
+#[derive(Debug)]
+pub struct RequestScheduler {
+    version: HttpVersion,
+    queue: BinaryHeap<Queued>,
+    /// Request in flight on each connection (HTTP/1.1) or each stream (HTTP/2)
+    in_flight: Vec<(u64, usize)>,
+    next_id: u64,
+}
+
+impl RequestScheduler {
+    /// Create a scheduler for one context.
+    pub fn new(version: HttpVersion) -> Self {
+        Self {
+            version,
+            queue: BinaryHeap::new(),
+            in_flight: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Queue a request. Returns its ID.
+    pub fn enqueue(&mut self, url: &str, kind: ResourceKind) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push(Queued {
+            key: Reverse((kind.rank(), id)),
+            url: url.to_string(),
+            kind,
+        });
+        id
+    }
+
+    /// Take the next request if the concurrency limit allows one.
+    pub fn next_ready(&mut self) -> Option<ScheduledRequest> {
+        if self.in_flight.len() >= self.limit() {
+            return None;
+        }
+
+        let connection = match self.version {
+            // Only an idle connection may take a request: no pipelining
+            HttpVersion::Http11 { connections } => (0..connections)
+                .find(|conn| self.in_flight.iter().all(|&(_, busy)| busy != *conn))?,
+            HttpVersion::Http2 { .. } => 0,
+        };
+
+        let queued = self.queue.pop()?;
+        let Reverse((_, id)) = queued.key;
+        self.in_flight.push((id, connection));
+
+        Some(ScheduledRequest {
+            id,
+            url: queued.url,
+            kind: queued.kind,
+            connection,
+            priority: match self.version {
+                HttpVersion::Http11 { .. } => None,
+                HttpVersion::Http2 { .. } => Some(queued.kind.h2_priority()),
+            },
+        })
+    }
+
+    /// Mark a request as finished, freeing its slot.
+    pub fn complete(&mut self, id: u64) {
+        self.in_flight.retain(|&(in_flight, _)| in_flight != id);
+    }
+
+    /// Whether nothing is queued or in flight.
+    pub fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Get the current queue state.
+    pub fn queue_state(&self) -> QueueState {
+        let mut queued: Vec<&Queued> = self.queue.iter().collect();
+        queued.sort_by(|a, b| b.cmp(a));
+
+        QueueState {
+            queued: queued
+                .into_iter()
+                .map(|q| {
+                    let Reverse((_, id)) = q.key;
+                    (id, q.kind)
+                })
+                .collect(),
+            in_flight: self.in_flight.iter().map(|&(id, _)| id).collect(),
+            limit: self.limit(),
+        }
+    }
+
+    fn limit(&self) -> usize {
+        match self.version {
+            HttpVersion::Http11 { connections } => connections,
+            HttpVersion::Http2 { max_streams } => max_streams,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resources of a synthetic page, in discovery order.
+    const PAGE: &[(&str, ResourceKind)] = &[
+        ("https://example.com/", ResourceKind::Document),
+        ("https://example.com/hero.jpg", ResourceKind::Image),
+        ("https://example.com/app.js", ResourceKind::Script),
+        ("https://example.com/site.css", ResourceKind::Stylesheet),
+        ("https://example.com/logo.png", ResourceKind::Image),
+        ("https://example.com/font.woff2", ResourceKind::Font),
+        ("https://example.com/print.css", ResourceKind::Stylesheet),
+    ];
+
+    /// HTTP/1.1 connection that fails on a pipelined request.
+    #[derive(Default)]
+    struct MockConnection {
+        outstanding: Option<u64>,
+        sent: Vec<String>,
+    }
+
+    impl MockConnection {
+        fn send(&mut self, request: &ScheduledRequest) {
+            assert!(
+                self.outstanding.is_none(),
+                "{} pipelined behind request {:?}",
+                request.url,
+                self.outstanding
+            );
+            self.outstanding = Some(request.id);
+            self.sent.push(request.url.clone());
+        }
+
+        fn respond(&mut self) -> Option<u64> {
+            self.outstanding.take()
+        }
+    }
+
+    fn load_page(scheduler: &mut RequestScheduler) {
+        for (url, kind) in PAGE {
+            scheduler.enqueue(url, *kind);
+        }
+    }
+
+    fn issue_order(requests: &[ScheduledRequest]) -> Vec<&str> {
+        requests
+            .iter()
+            .map(|r| r.url.trim_start_matches("https://example.com/"))
+            .collect()
+    }
+
+    #[test]
+    fn test_http11_order_without_pipelining() {
+        let mut scheduler = RequestScheduler::new(HttpVersion::Http11 { connections: 2 });
+        let mut connections: Vec<MockConnection> =
+            (0..2).map(|_| MockConnection::default()).collect();
+        load_page(&mut scheduler);
+
+        let mut issued = Vec::new();
+        while !scheduler.is_idle() {
+            while let Some(request) = scheduler.next_ready() {
+                assert!(request.priority.is_none());
+                connections[request.connection].send(&request);
+                issued.push(request);
+            }
+            // Responses arrive on one connection at a time
+            let id = connections
+                .iter_mut()
+                .find_map(MockConnection::respond)
+                .expect("a request in flight");
+            scheduler.complete(id);
+        }
+
+        assert_eq!(
+            issue_order(&issued),
+            vec![
+                "",
+                "site.css",
+                "print.css",
+                "app.js",
+                "font.woff2",
+                "hero.jpg",
+                "logo.png"
+            ]
+        );
+        let total: usize = connections.iter().map(|c| c.sent.len()).sum();
+        assert_eq!(total, PAGE.len());
+    }
+
+    #[test]
+    fn test_http2_priorities() {
+        let mut scheduler = RequestScheduler::new(HttpVersion::Http2 { max_streams: 3 });
+        load_page(&mut scheduler);
+
+        let first: Vec<ScheduledRequest> = std::iter::from_fn(|| scheduler.next_ready()).collect();
+        assert_eq!(issue_order(&first), vec!["", "site.css", "print.css"]);
+
+        let document = first[0].priority.clone().expect("h2 priority");
+        assert_eq!(document, Http2Fingerprint::default().priority);
+        let css = first[1].priority.clone().expect("h2 priority");
+        assert_eq!(css.depends_on, GROUP_LEADER);
+        assert_eq!(css.weight, document.weight);
+
+        assert_eq!(
+            ResourceKind::Image.h2_priority().depends_on,
+            GROUP_UNBLOCKED
+        );
+    }
+
+    #[test]
+    fn test_queue_state() {
+        let mut scheduler = RequestScheduler::new(HttpVersion::Http11 { connections: 1 });
+        load_page(&mut scheduler);
+
+        let document = scheduler.next_ready().expect("document");
+        assert!(scheduler.next_ready().is_none());
+
+        let state = scheduler.queue_state();
+        assert_eq!(state.in_flight, vec![document.id]);
+        assert_eq!(state.limit, 1);
+        assert_eq!(state.queued.len(), PAGE.len() - 1);
+        assert_eq!(state.queued[0].1, ResourceKind::Stylesheet);
+        assert_eq!(state.queued.last().map(|q| q.1), Some(ResourceKind::Image));
+
+        scheduler.complete(document.id);
+        assert!(scheduler.queue_state().in_flight.is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+//! Secrets held by the network process.
+//!
+//! Control-port credentials must not outlive their use or leak into logs.
+//! `SecretBytes` zeroes its contents when wiped or dropped and never
+//! prints them.
+
+use std::fmt;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// Overwrite a buffer with zeros in a way the optimizer keeps.
+pub(crate) fn wipe_bytes(bytes: &mut [u8]) {
+    bytes.fill(0);
+    std::hint::black_box(&mut *bytes);
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// A secret byte string, zeroed on drop.
+#[derive(Default)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    /// Take ownership of a secret.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    /// Borrow the secret for immediate use.
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Get the length in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if the secret is empty (or has been wiped).
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Zero and release the secret now.
+    pub fn wipe(&mut self) {
+        wipe_bytes(&mut self.0);
+        self.0.clear();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.wipe();
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let secret = SecretBytes::new(b"hunter2".to_vec());
+        let debug = format!("{:?}", secret);
+
+        assert_eq!(debug, "SecretBytes([REDACTED; 7])");
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_wipe() {
+        let mut secret = SecretBytes::new(vec![0xAA; 32]);
+        assert_eq!(secret.len(), 32);
+
+        secret.wipe();
+        assert!(secret.is_empty());
+
+        let mut raw = vec![0xAA; 4];
+        wipe_bytes(&mut raw);
+        assert_eq!(raw, vec![0; 4]);
+    }
+}
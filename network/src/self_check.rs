@@ -0,0 +1,188 @@
+//! Connectivity self-check: is traffic really leaving through Tor?
+//!
+//! `is_healthy` only says the control port is up. The self-check fetches
+//! a check endpoint over a fresh circuit and reads its verdict, which
+//! the Tor Project's check service gives as `{"IsTor":true,"IP":"..."}`.
+//! Endpoints are tried in order until one answers.
+//!
+//! The exit address is looked up in the embedded GeoIP table and dropped
+//! at once: only its country reaches the `HealthReport`, and the address
+//! is never logged.
+
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use crate::geoip::{GeoIp, UNKNOWN_COUNTRY};
+use crate::verify::PageFetcher;
+use crate::NetworkError;
+
+/// Endpoints answering in the check service's JSON format, in the order
+/// they are tried.
+pub const CHECK_ENDPOINTS: &[&str] = &["https://check.torproject.org/api/ip"];
+
+/// Outcome of a self-check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// The check service saw the request arrive from a Tor exit
+    pub tor_confirmed: bool,
+    /// Country code of the exit, `UNKNOWN_COUNTRY` if not in the table
+    pub exit_ip_country: String,
+    /// Time taken by the check request, circuit included
+    pub latency: Duration,
+}
+
+/// Fetch each of `endpoints` in turn until one gives a verdict.
+///
+/// Fails with the last error if none does.
+pub async fn self_check<F: PageFetcher>(
+    fetcher: &F,
+    endpoints: &[&str],
+) -> Result<HealthReport, NetworkError> {
+    let mut last_error = NetworkError::RequestFailed("No connectivity check endpoints".to_string());
+    for endpoint in endpoints {
+        let started = Instant::now();
+        let verdict = fetcher
+            .fetch(endpoint)
+            .await
+            .and_then(|page| parse_check_response(&page.body));
+        match verdict {
+            Ok((tor_confirmed, exit_ip_country)) => {
+                return Ok(HealthReport {
+                    tor_confirmed,
+                    exit_ip_country,
+                    latency: started.elapsed(),
+                })
+            }
+            Err(e) => {
+                log::debug!("Connectivity check failed: {}", e);
+                last_error = e;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Read `IsTor` and the exit's country from a check response body.
+fn parse_check_response(body: &[u8]) -> Result<(bool, String), NetworkError> {
+    let unrecognised = || NetworkError::InvalidResponse("Unrecognised check response".to_string());
+    let body = std::str::from_utf8(body).map_err(|_| unrecognised())?;
+
+    let tor_confirmed = match json_field(body, "IsTor") {
+        Some("true") => true,
+        Some("false") => false,
+        _ => return Err(unrecognised()),
+    };
+    let country = json_field(body, "IP")
+        .and_then(|ip| ip.parse::<Ipv4Addr>().ok())
+        .and_then(|ip| GeoIp::embedded().country(ip))
+        .unwrap_or(UNKNOWN_COUNTRY);
+
+    Ok((tor_confirmed, country.to_string()))
+}
+
+/// Value of the top-level `name` field of a flat JSON object: a string's
+/// contents without quotes, or any other value as written.
+fn json_field<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\"", name);
+    let rest = body[body.find(&key)? + key.len()..].trim_start();
+    let value = rest.strip_prefix(':')?.trim_start();
+    match value.strip_prefix('"') {
+        Some(string) => string.split('"').next(),
+        None => value.split([',', '}']).next().map(str::trim),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FetchedPage;
+    use std::future::Future;
+    use std::sync::Mutex;
+
+    /// Fetcher serving one canned answer per endpoint, in order.
+    struct CheckService {
+        answers: Mutex<Vec<Result<&'static [u8], NetworkError>>>,
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl CheckService {
+        fn answering(answers: Vec<Result<&'static [u8], NetworkError>>) -> Self {
+            Self {
+                answers: Mutex::new(answers),
+                fetched: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl PageFetcher for CheckService {
+        fn fetch(&self, url: &str) -> impl Future<Output = Result<FetchedPage, NetworkError>> {
+            self.fetched
+                .lock()
+                .expect("fetch log")
+                .push(url.to_string());
+            let answer = self.answers.lock().expect("answers").remove(0);
+            async move {
+                answer.map(|body| FetchedPage {
+                    circuit_id: "1".to_string(),
+                    content_type: "application/json".to_string(),
+                    body: body.to_vec(),
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_response_parsed() {
+        assert_eq!(
+            parse_check_response(br#"{"IsTor":true,"IP":"66.111.2.131"}"#).expect("verdict"),
+            (true, "US".to_string())
+        );
+        assert_eq!(
+            parse_check_response(b"{ \"IP\" : \"192.0.2.1\", \"IsTor\" : false }")
+                .expect("verdict"),
+            (false, UNKNOWN_COUNTRY.to_string())
+        );
+        assert_eq!(
+            parse_check_response(br#"{"IsTor":true,"IP":"2001:db8::1"}"#).expect("verdict"),
+            (true, UNKNOWN_COUNTRY.to_string())
+        );
+        assert!(parse_check_response(b"<html>Congratulations</html>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_next_endpoint() {
+        let service = CheckService::answering(vec![
+            Err(NetworkError::Timeout),
+            Ok(br#"{"IsTor":true,"IP":"66.111.2.131"}"#),
+        ]);
+
+        let report = self_check(&service, &["https://a.example/", "https://b.example/"])
+            .await
+            .expect("report");
+        assert!(report.tor_confirmed);
+        assert_eq!(report.exit_ip_country, "US");
+        assert_eq!(
+            *service.fetched.lock().expect("fetch log"),
+            ["https://a.example/", "https://b.example/"]
+        );
+        // Only the country survives
+        assert!(!format!("{:?}", report).contains("66.111"));
+
+        let service = CheckService::answering(vec![Ok(b"not json")]);
+        assert!(matches!(
+            self_check(&service, &["https://a.example/"]).await,
+            Err(NetworkError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires Tor daemon"]
+    async fn test_self_check_over_tor() {
+        let network = crate::AnonymizedNetwork::new(crate::NetworkConfig::default())
+            .await
+            .expect("network");
+        let report = network.self_check().await.expect("report");
+        assert!(report.tor_confirmed);
+    }
+}
@@ -0,0 +1,90 @@
+//! Built-in Snowflake bridges.
+//!
+//! Where bridges.torproject.org itself is blocked, a user has no bridge
+//! lines to paste. Like Tor Browser, forloop ships Snowflake bridges that
+//! need none (`--use-snowflake`, or "Use Snowflake" in the settings).
+//! The client reaches the broker through a fronting CDN and finds its
+//! peers through the STUN servers; the bridge addresses are placeholders,
+//! as the broker picks the bridge by fingerprint.
+//!
+//! Everything here follows Tor Browser's built-in bridge list and is
+//! updated from it in this one module.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use crate::bridges::{BridgeLine, BridgeTransport};
+
+/// Broker the client asks for a Snowflake proxy.
+pub const SNOWFLAKE_BROKER: &str = "https://1098762253.rsc.cdn77.org/";
+
+/// Domains the broker request is fronted through.
+pub const SNOWFLAKE_FRONTS: &[&str] = &["www.cdn77.com", "www.phpmyadmin.net"];
+
+/// STUN servers the client finds its public address through.
+pub const SNOWFLAKE_STUN_SERVERS: &[&str] = &[
+    "stun:stun.antisip.com:3478",
+    "stun:stun.epygi.com:3478",
+    "stun:stun.uls.co.za:3478",
+    "stun:stun.voipgate.com:3478",
+    "stun:stun.mixvoip.com:3478",
+    "stun:stun.nextcloud.com:3478",
+    "stun:stun.bethesda.net:3478",
+    "stun:stun.nextcloud.com:443",
+];
+
+/// TLS fingerprint the client imitates towards the fronts.
+pub const SNOWFLAKE_UTLS_IMITATE: &str = "hellorandomizedalpn";
+
+/// Placeholder address and fingerprint of each built-in bridge.
+pub const SNOWFLAKE_BRIDGES: &[(SocketAddrV4, &str)] = &[
+    (
+        SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 3), 80),
+        "2B280B23E1107BB62ABFC40DDCC8824814F80A72",
+    ),
+    (
+        SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 4), 80),
+        "8838024498816A039FCBBAB14E6F40A0843051FA",
+    ),
+];
+
+/// The built-in Snowflake bridges, as bridge lines.
+pub fn snowflake_bridges() -> Vec<BridgeLine> {
+    SNOWFLAKE_BRIDGES
+        .iter()
+        .map(|&(address, fingerprint)| BridgeLine {
+            transport: BridgeTransport::Snowflake,
+            address: address.into(),
+            fingerprint: Some(fingerprint.to_string()),
+            args: vec![
+                ("fingerprint".to_string(), fingerprint.to_string()),
+                ("url".to_string(), SNOWFLAKE_BROKER.to_string()),
+                ("fronts".to_string(), SNOWFLAKE_FRONTS.join(",")),
+                ("ice".to_string(), SNOWFLAKE_STUN_SERVERS.join(",")),
+                (
+                    "utls-imitate".to_string(),
+                    SNOWFLAKE_UTLS_IMITATE.to_string(),
+                ),
+            ],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_bridges_parse_back() {
+        let bridges = snowflake_bridges();
+        assert_eq!(bridges.len(), SNOWFLAKE_BRIDGES.len());
+        for bridge in &bridges {
+            // Would pass as a pasted line, and stays one torrc line
+            let line = bridge.torrc_line();
+            assert_eq!(line.parse::<BridgeLine>().as_ref(), Ok(bridge));
+            assert!(line.starts_with("Bridge snowflake 192.0.2."));
+            assert!(line.contains(" url=https://1098762253.rsc.cdn77.org/ "));
+            assert!(line.contains(" fronts=www.cdn77.com,www.phpmyadmin.net "));
+            assert!(line.contains(" ice=stun:stun.antisip.com:3478,stun:stun.epygi.com:3478,"));
+        }
+    }
+}
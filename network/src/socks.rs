@@ -0,0 +1,669 @@
+//! SOCKS5 CONNECT and RESOLVE to Tor's SOCKS port.
+//!
+//! The destination is sent as a domain name, so name resolution happens
+//! at the exit; only IPv6 literals, which Tor would not take as a name,
+//! go as addresses. With an `IsolationToken`, only username/password
+//! authentication is offered and the token is sent as the credentials:
+//! Tor (IsolateSOCKSAuth) never puts streams with different credentials
+//! on the same circuit, whatever NEWNYM rate limiting does. A proxy that
+//! accepts the connection without them is not isolating and is refused.
+//! Without a token only no-authentication is offered. A proxy asking for
+//! any other credentials is not Tor (see `challenge`).
+//!
+//! RESOLVE is Tor's extension command 0xF0: the exit looks the name up
+//! and the reply's bound address is the answer.
+//!
+//! A failed request's reply code is kept as a `SocksReplyCode`. The torrc
+//! turns on `ExtendedErrors`, so an onion service that cannot be reached
+//! says why (0xF0-0xF7) instead of a bare "host unreachable".
+//!
+//! The SOCKS port is a `SocksEndpoint`: a TCP port on localhost, or a
+//! Unix socket in tor's data directory, which no other local user can
+//! reach. Unix sockets are only used where the platform has them.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::backend::TorStream;
+use crate::retry::CircuitFailure;
+use crate::NetworkError;
+
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const COMMAND_CONNECT: u8 = 0x01;
+const COMMAND_RESOLVE: u8 = 0xF0;
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+/// SOCKS5 credentials that keep a circuit's streams to themselves.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct IsolationToken {
+    username: String,
+    password: String,
+}
+
+impl IsolationToken {
+    /// Token for the `sequence`th circuit of the manager with `nonce`,
+    /// whose Tor circuit id is `circuit_id`.
+    pub(crate) fn new(circuit_id: &str, nonce: u64, sequence: u64) -> Self {
+        let mut username = format!("forloop-{}", circuit_id);
+        username.truncate(255);
+        Self {
+            username,
+            password: format!("{:016x}-{}", nonce, sequence),
+        }
+    }
+
+    /// SOCKS5 username.
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// SOCKS5 password.
+    pub fn password(&self) -> &str {
+        &self.password
+    }
+}
+
+impl fmt::Debug for IsolationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Identifies the circuit's streams to Tor; keep it out of logs
+        write!(f, "IsolationToken([REDACTED])")
+    }
+}
+
+/// Where Tor's SOCKS port listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocksEndpoint {
+    /// A TCP port, on localhost
+    Tcp(SocketAddr),
+    /// A Unix socket (`SocksPort unix:/path`)
+    Unix(PathBuf),
+}
+
+impl SocksEndpoint {
+    /// Open a connection to the SOCKS port.
+    ///
+    /// Fails with `TorConnectionFailed` if nothing listens there, or for
+    /// a Unix socket on a platform without them.
+    pub(crate) async fn connect(&self) -> Result<Box<dyn TorStream>, NetworkError> {
+        let failed = |e: std::io::Error| NetworkError::TorConnectionFailed(e.to_string());
+        match self {
+            Self::Tcp(address) => Ok(Box::new(TcpStream::connect(address).await.map_err(failed)?)),
+            #[cfg(unix)]
+            Self::Unix(path) => Ok(Box::new(
+                tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(failed)?,
+            )),
+            #[cfg(not(unix))]
+            Self::Unix(_) => Err(NetworkError::TorConnectionFailed(
+                "Unix sockets are not supported on this platform".to_string(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SocksEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Ask the proxy on `stream` to connect to `host:port`, isolated by
+/// `isolation` if given.
+pub(crate) async fn socks5_connect<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    isolation: Option<&IsolationToken>,
+) -> Result<(), NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Bound address and port; unused
+    socks5_request(stream, COMMAND_CONNECT, host, port, isolation).await?;
+    Ok(())
+}
+
+/// Ask the proxy on `stream` to resolve `host` at the exit, isolated by
+/// `isolation` if given.
+pub(crate) async fn socks5_resolve<S>(
+    stream: &mut S,
+    host: &str,
+    isolation: Option<&IsolationToken>,
+) -> Result<IpAddr, NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let bound = socks5_request(stream, COMMAND_RESOLVE, host, 0, isolation).await?;
+    let address = &bound[..bound.len() - 2];
+    if let Ok(octets) = <[u8; 4]>::try_from(address) {
+        Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if let Ok(octets) = <[u8; 16]>::try_from(address) {
+        Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        Err(NetworkError::DnsError(host.to_string()))
+    }
+}
+
+/// Negotiate, send `command` for `host:port` and return the reply's
+/// bound address and port, as sent.
+async fn socks5_request<S>(
+    stream: &mut S,
+    command: u8,
+    host: &str,
+    port: u16,
+    isolation: Option<&IsolationToken>,
+) -> Result<Vec<u8>, NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io = |e: std::io::Error| match e.kind() {
+        std::io::ErrorKind::ConnectionReset => {
+            NetworkError::CircuitFailed(CircuitFailure::ConnectionReset)
+        }
+        _ => NetworkError::TorConnectionFailed(e.to_string()),
+    };
+    if host.is_empty() || host.len() > 255 {
+        return Err(NetworkError::InvalidUrl(host.to_string()));
+    }
+
+    let method = match isolation {
+        Some(_) => METHOD_USERNAME_PASSWORD,
+        None => METHOD_NO_AUTH,
+    };
+    stream
+        .write_all(&[SOCKS_VERSION, 1, method])
+        .await
+        .map_err(io)?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await.map_err(io)?;
+    match choice {
+        [SOCKS_VERSION, chosen] if chosen == method => {}
+        [SOCKS_VERSION, METHOD_NO_AUTH] => {
+            return Err(NetworkError::TorConnectionFailed(
+                "SOCKS5 proxy ignored stream isolation".to_string(),
+            ))
+        }
+        [SOCKS_VERSION, _] => return Err(NetworkError::ProxyAuthRequired),
+        _ => {
+            return Err(NetworkError::TorConnectionFailed(
+                "not a SOCKS5 proxy".to_string(),
+            ))
+        }
+    }
+
+    if let Some(token) = isolation {
+        let mut auth = vec![USERNAME_PASSWORD_VERSION, token.username.len() as u8];
+        auth.extend_from_slice(token.username.as_bytes());
+        auth.push(token.password.len() as u8);
+        auth.extend_from_slice(token.password.as_bytes());
+        stream.write_all(&auth).await.map_err(io)?;
+
+        let mut status = [0u8; 2];
+        stream.read_exact(&mut status).await.map_err(io)?;
+        if status != [USERNAME_PASSWORD_VERSION, 0] {
+            return Err(NetworkError::TorConnectionFailed(
+                "SOCKS5 proxy rejected the isolation credentials".to_string(),
+            ));
+        }
+    }
+
+    let mut request = vec![SOCKS_VERSION, command, 0];
+    match host.parse::<Ipv6Addr>() {
+        Ok(address) => {
+            request.push(ADDRESS_IPV6);
+            request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            request.extend_from_slice(&[ADDRESS_DOMAIN, host.len() as u8]);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await.map_err(io)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await.map_err(io)?;
+    if reply[1] != 0 {
+        return Err(connect_error(host, reply[1]));
+    }
+
+    let address_len = match reply[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN => stream.read_u8().await.map_err(io)? as usize,
+        other => {
+            return Err(NetworkError::TorConnectionFailed(format!(
+                "bad SOCKS5 address type {}",
+                other
+            )))
+        }
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await.map_err(io)?;
+    Ok(bound)
+}
+
+/// Reply code of a failed SOCKS5 request.
+///
+/// The standard codes are from RFC 1928; the onion service codes are
+/// Tor's, sent when the SOCKS port has `ExtendedErrors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SocksReplyCode {
+    /// 0x01: general failure
+    GeneralFailure,
+    /// 0x02: not allowed by the exit's ruleset
+    NotAllowed,
+    /// 0x03: network unreachable
+    NetworkUnreachable,
+    /// 0x04: host unreachable, or its name could not be resolved
+    HostUnreachable,
+    /// 0x05: connection refused
+    ConnectionRefused,
+    /// 0x06: TTL expired
+    TtlExpired,
+    /// 0x07: command not supported
+    CommandNotSupported,
+    /// 0x08: address type not supported
+    AddressTypeNotSupported,
+    /// 0xF0: no descriptor found for the onion service
+    OnionDescriptorNotFound,
+    /// 0xF1: the onion service's descriptor is invalid
+    OnionDescriptorInvalid,
+    /// 0xF2: every introduction point failed
+    OnionIntroductionFailed,
+    /// 0xF3: the rendezvous failed
+    OnionRendezvousFailed,
+    /// 0xF4: the service needs client authorization and has none
+    OnionMissingClientAuth,
+    /// 0xF5: the service rejected the client authorization
+    OnionWrongClientAuth,
+    /// 0xF6: the onion address is malformed
+    OnionInvalidAddress,
+    /// 0xF7: the introduction timed out
+    OnionIntroductionTimedOut,
+    /// Any other code
+    Other(u8),
+}
+
+impl SocksReplyCode {
+    /// Interpret a reply byte.
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Self::GeneralFailure,
+            0x02 => Self::NotAllowed,
+            0x03 => Self::NetworkUnreachable,
+            0x04 => Self::HostUnreachable,
+            0x05 => Self::ConnectionRefused,
+            0x06 => Self::TtlExpired,
+            0x07 => Self::CommandNotSupported,
+            0x08 => Self::AddressTypeNotSupported,
+            0xF0 => Self::OnionDescriptorNotFound,
+            0xF1 => Self::OnionDescriptorInvalid,
+            0xF2 => Self::OnionIntroductionFailed,
+            0xF3 => Self::OnionRendezvousFailed,
+            0xF4 => Self::OnionMissingClientAuth,
+            0xF5 => Self::OnionWrongClientAuth,
+            0xF6 => Self::OnionInvalidAddress,
+            0xF7 => Self::OnionIntroductionTimedOut,
+            code => Self::Other(code),
+        }
+    }
+
+    /// Get the reply byte.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::GeneralFailure => 0x01,
+            Self::NotAllowed => 0x02,
+            Self::NetworkUnreachable => 0x03,
+            Self::HostUnreachable => 0x04,
+            Self::ConnectionRefused => 0x05,
+            Self::TtlExpired => 0x06,
+            Self::CommandNotSupported => 0x07,
+            Self::AddressTypeNotSupported => 0x08,
+            Self::OnionDescriptorNotFound => 0xF0,
+            Self::OnionDescriptorInvalid => 0xF1,
+            Self::OnionIntroductionFailed => 0xF2,
+            Self::OnionRendezvousFailed => 0xF3,
+            Self::OnionMissingClientAuth => 0xF4,
+            Self::OnionWrongClientAuth => 0xF5,
+            Self::OnionInvalidAddress => 0xF6,
+            Self::OnionIntroductionTimedOut => 0xF7,
+            Self::Other(code) => code,
+        }
+    }
+
+    /// Whether this is one of Tor's onion service errors.
+    pub fn is_onion_service(self) -> bool {
+        (0xF0..=0xF7).contains(&self.code())
+    }
+}
+
+impl fmt::Display for SocksReplyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::GeneralFailure => "general failure",
+            Self::NotAllowed => "not allowed by ruleset",
+            Self::NetworkUnreachable => "network unreachable",
+            Self::HostUnreachable => "host unreachable",
+            Self::ConnectionRefused => "connection refused",
+            Self::TtlExpired => "TTL expired",
+            Self::CommandNotSupported => "command not supported",
+            Self::AddressTypeNotSupported => "address type not supported",
+            Self::OnionDescriptorNotFound => "onion service descriptor not found",
+            Self::OnionDescriptorInvalid => "onion service descriptor invalid",
+            Self::OnionIntroductionFailed => "onion service introduction failed",
+            Self::OnionRendezvousFailed => "onion service rendezvous failed",
+            Self::OnionMissingClientAuth => "onion service client authorization missing",
+            Self::OnionWrongClientAuth => "onion service client authorization wrong",
+            Self::OnionInvalidAddress => "invalid onion address",
+            Self::OnionIntroductionTimedOut => "onion service introduction timed out",
+            Self::Other(code) => return write!(f, "reply code {:#04x}", code),
+        };
+        write!(f, "{} ({:#04x})", text, self.code())
+    }
+}
+
+/// Map a SOCKS5 reply code to an error.
+///
+/// Circuit-level failures become `CircuitFailed`, which the retry policy
+/// acts on; the rest keep their code in `SocksFailure`.
+fn connect_error(host: &str, code: u8) -> NetworkError {
+    match SocksReplyCode::from_code(code) {
+        // Tor reports failed resolution as "host unreachable"
+        SocksReplyCode::HostUnreachable => NetworkError::DnsError(host.to_string()),
+        code => match CircuitFailure::from_socks_reply(code) {
+            Some(failure) => NetworkError::CircuitFailed(failure),
+            None => NetworkError::SocksFailure(code),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_sends_domain() {
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 0]).await.expect("choice");
+
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut rest = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut rest).await.expect("rest");
+            proxy
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .expect("reply");
+            (greeting, head, rest)
+        });
+
+        socks5_connect(&mut client, "example.com", 443, None)
+            .await
+            .expect("connected");
+        let (greeting, head, rest) = server.await.expect("proxy");
+        assert_eq!(greeting, [5, 1, 0]);
+        assert_eq!(head, [5, 1, 0, 3, 11]);
+        assert_eq!(rest, b"example.com\x01\xbb");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handshake_over_unix_socket() {
+        let token = IsolationToken::new("5", 1, 1);
+        let (mut client, mut proxy) = tokio::net::UnixStream::pair().expect("socketpair");
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 2]).await.expect("choice");
+
+            let mut auth = [0u8; 2];
+            proxy.read_exact(&mut auth).await.expect("username length");
+            let mut username = vec![0u8; auth[1] as usize];
+            proxy.read_exact(&mut username).await.expect("username");
+            let mut password = vec![0u8; proxy.read_u8().await.expect("length") as usize];
+            proxy.read_exact(&mut password).await.expect("password");
+            proxy.write_all(&[1, 0]).await.expect("auth status");
+
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut target = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut target).await.expect("target");
+            proxy
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .expect("reply");
+            (greeting, username, target)
+        });
+
+        socks5_connect(&mut client, "example.com", 443, Some(&token))
+            .await
+            .expect("connected");
+        let (greeting, username, target) = server.await.expect("proxy");
+        assert_eq!(greeting, [5, 1, 2]);
+        assert_eq!(username, token.username().as_bytes());
+        assert_eq!(target, b"example.com\x01\xbb");
+    }
+
+    #[tokio::test]
+    async fn test_connect_sends_ipv6() {
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 0]).await.expect("choice");
+
+            let mut request = [0u8; 22];
+            proxy.read_exact(&mut request).await.expect("request");
+            proxy
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .expect("reply");
+            request
+        });
+
+        socks5_connect(&mut client, "2001:db8::1", 8443, None)
+            .await
+            .expect("connected");
+        let request = server.await.expect("proxy");
+        assert_eq!(request[..4], [5, 1, 0, 4]);
+        assert_eq!(
+            request[4..20],
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets()
+        );
+        assert_eq!(request[20..], 8443u16.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_reads_bound_address() {
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 0]).await.expect("choice");
+
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut rest = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut rest).await.expect("rest");
+            proxy
+                .write_all(&[5, 0, 0, 1, 93, 184, 216, 34, 0, 0])
+                .await
+                .expect("reply");
+            head
+        });
+
+        let address = socks5_resolve(&mut client, "example.com", None)
+            .await
+            .expect("resolved");
+        assert_eq!(address, IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)));
+        assert_eq!(server.await.expect("proxy"), [5, 0xF0, 0, 3, 11]);
+
+        // Tor answers a failed lookup with "host unreachable"
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        tokio::spawn(async move {
+            let mut request = [0u8; 3 + 17];
+            let _ = proxy.read_exact(&mut request[..3]).await;
+            let _ = proxy.write_all(&[5, 0]).await;
+            let _ = proxy.read_exact(&mut request[3..]).await;
+            let _ = proxy.write_all(&[5, 4, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+        });
+        assert!(matches!(
+            socks5_resolve(&mut client, "nx.example", None).await,
+            Err(NetworkError::DnsError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_auth_request_refused() {
+        let (mut client, mut proxy) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            let _ = proxy.read_exact(&mut greeting).await;
+            let _ = proxy.write_all(&[5, 2]).await;
+        });
+        assert!(matches!(
+            socks5_connect(&mut client, "example.com", 443, None).await,
+            Err(NetworkError::ProxyAuthRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_isolation_credentials_sent() {
+        let token = IsolationToken::new("17", 0xAB, 3);
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            proxy.read_exact(&mut greeting).await.expect("greeting");
+            proxy.write_all(&[5, 2]).await.expect("choice");
+
+            let mut auth = vec![0u8; 2];
+            proxy.read_exact(&mut auth).await.expect("username length");
+            let mut username = vec![0u8; auth[1] as usize];
+            proxy.read_exact(&mut username).await.expect("username");
+            let mut password = vec![0u8; proxy.read_u8().await.expect("length") as usize];
+            proxy.read_exact(&mut password).await.expect("password");
+            proxy.write_all(&[1, 0]).await.expect("auth status");
+
+            let mut head = [0u8; 5];
+            proxy.read_exact(&mut head).await.expect("head");
+            let mut rest = vec![0u8; head[4] as usize + 2];
+            proxy.read_exact(&mut rest).await.expect("rest");
+            proxy
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .expect("reply");
+            (greeting, username, password)
+        });
+
+        socks5_connect(&mut client, "example.com", 443, Some(&token))
+            .await
+            .expect("connected");
+        let (greeting, username, password) = server.await.expect("proxy");
+        assert_eq!(greeting, [5, 1, 2]);
+        assert_eq!(username, b"forloop-17");
+        assert_eq!(password, b"00000000000000ab-3");
+        assert_eq!(format!("{:?}", token), "IsolationToken([REDACTED])");
+
+        // A proxy that skips authentication would not isolate
+        let (mut client, mut proxy) = tokio::io::duplex(64);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            let _ = proxy.read_exact(&mut greeting).await;
+            let _ = proxy.write_all(&[5, 0]).await;
+        });
+        assert!(matches!(
+            socks5_connect(&mut client, "example.com", 443, Some(&token)).await,
+            Err(NetworkError::TorConnectionFailed(_))
+        ));
+    }
+
+    /// Connect through a proxy that fails the request with `code`.
+    async fn connect_failing_with(code: u8) -> NetworkError {
+        let (mut client, mut proxy) = tokio::io::duplex(512);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            let _ = proxy.read_exact(&mut greeting).await;
+            let _ = proxy.write_all(&[5, 0]).await;
+            let mut request = [0u8; 5 + 62 + 2];
+            let _ = proxy.read_exact(&mut request).await;
+            let _ = proxy.write_all(&[5, code, 0, 1, 0, 0, 0, 0, 0, 0]).await;
+        });
+        let onion = "expyuzz4wqqyqhjn76dffiojyfgkybahbwtwxgq3kakenvbpgahltqad.onion";
+        match socks5_connect(&mut client, onion, 80, None).await {
+            Err(e) => e,
+            Ok(()) => panic!("code {:#04x} accepted", code),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_codes_mapped() {
+        for (code, failure) in [
+            (0x01, CircuitFailure::General),
+            (0x02, CircuitFailure::ExitPolicy),
+            (0x03, CircuitFailure::NetworkUnreachable),
+            (0x05, CircuitFailure::ConnectionRefused),
+            (0x06, CircuitFailure::TtlExpired),
+        ] {
+            let error = connect_failing_with(code).await;
+            assert!(
+                matches!(error, NetworkError::CircuitFailed(f) if f == failure),
+                "{:#04x}: {:?}",
+                code,
+                error
+            );
+        }
+        assert!(matches!(
+            connect_failing_with(0x04).await,
+            NetworkError::DnsError(_)
+        ));
+
+        for (code, reply) in [
+            (0x07, SocksReplyCode::CommandNotSupported),
+            (0x08, SocksReplyCode::AddressTypeNotSupported),
+            (0xF0, SocksReplyCode::OnionDescriptorNotFound),
+            (0xF1, SocksReplyCode::OnionDescriptorInvalid),
+            (0xF2, SocksReplyCode::OnionIntroductionFailed),
+            (0xF3, SocksReplyCode::OnionRendezvousFailed),
+            (0xF4, SocksReplyCode::OnionMissingClientAuth),
+            (0xF5, SocksReplyCode::OnionWrongClientAuth),
+            (0xF6, SocksReplyCode::OnionInvalidAddress),
+            (0xF7, SocksReplyCode::OnionIntroductionTimedOut),
+            (0x42, SocksReplyCode::Other(0x42)),
+        ] {
+            let error = connect_failing_with(code).await;
+            assert!(
+                matches!(error, NetworkError::SocksFailure(r) if r == reply),
+                "{:#04x}: {:?}",
+                code,
+                error
+            );
+            assert_eq!(reply.code(), code);
+            assert_eq!(reply.is_onion_service(), code >= 0xF0);
+        }
+
+        for code in 1..=u8::MAX {
+            assert_eq!(SocksReplyCode::from_code(code).code(), code);
+        }
+        assert_eq!(
+            SocksReplyCode::OnionDescriptorNotFound.to_string(),
+            "onion service descriptor not found (0xf0)"
+        );
+    }
+}
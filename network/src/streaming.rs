@@ -0,0 +1,303 @@
+//! Streaming response bodies.
+//!
+//! `AnonymizedNetwork::request_streaming` returns as soon as the response
+//! head is read. A task then owns the connection and the circuit and
+//! forwards the body through a bounded channel, so no more than
+//! `BODY_CHANNEL_DEPTH` pieces wait in memory however large the body is.
+//!
+//! The circuit is marked as streaming while the body moves, so the
+//! watchdog leaves a slow download alone. If the `ResponseBody` is dropped
+//! before the end, or reading the body fails, the task closes the circuit.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::http_response::BodyReader;
+use crate::metrics::NetworkMetrics;
+use crate::tasks::{TaskRegistry, TaskScope};
+use crate::watchdog::CircuitActivity;
+use crate::NetworkError;
+
+/// Body pieces held between the connection and the reader.
+pub const BODY_CHANNEL_DEPTH: usize = 4;
+
+/// A response whose body is read as it arrives.
+#[derive(Debug)]
+pub struct StreamingResponse {
+    /// HTTP status code
+    pub status: u16,
+    /// Response headers, sanitized
+    pub headers: Vec<(String, String)>,
+    /// Circuit the response arrives on
+    pub circuit_id: String,
+    /// The body, as it arrives
+    pub body: ResponseBody,
+}
+
+/// The body of a `StreamingResponse`.
+///
+/// Read it with `chunk` or through `AsyncRead`. Dropping it before the end
+/// cancels the download and closes its circuit.
+pub struct ResponseBody {
+    chunks: mpsc::Receiver<Result<Option<Vec<u8>>, NetworkError>>,
+    pending: Vec<u8>,
+    offset: usize,
+    finished: bool,
+}
+
+impl ResponseBody {
+    pub(crate) fn new(chunks: mpsc::Receiver<Result<Option<Vec<u8>>, NetworkError>>) -> Self {
+        Self {
+            chunks,
+            pending: Vec::new(),
+            offset: 0,
+            finished: false,
+        }
+    }
+
+    /// A body that has already arrived in full, for other crates' tests.
+    ///
+    /// Only compiled for tests, or with the `test-support` feature.
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn from_bytes(body: Vec<u8>) -> Self {
+        let (sender, chunks) = mpsc::channel(2);
+        let _ = sender.try_send(Ok(Some(body)));
+        let _ = sender.try_send(Ok(None));
+        Self::new(chunks)
+    }
+
+    /// Get the next piece of the body, or `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, NetworkError> {
+        if self.offset < self.pending.len() {
+            let rest = self.pending.split_off(self.offset);
+            self.pending.clear();
+            self.offset = 0;
+            return Ok(Some(rest));
+        }
+        let next = self.chunks.recv().await;
+        self.receive(next)
+    }
+
+    /// Interpret what came off the channel. The channel closing without
+    /// an end marker means the task was cancelled mid-body.
+    fn receive(
+        &mut self,
+        next: Option<Result<Option<Vec<u8>>, NetworkError>>,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        if self.finished {
+            return Ok(None);
+        }
+        match next {
+            Some(Ok(Some(chunk))) => Ok(Some(chunk)),
+            Some(Ok(None)) => {
+                self.finished = true;
+                Ok(None)
+            }
+            Some(Err(e)) => Err(e),
+            None => Err(NetworkError::RequestFailed(
+                "Response body cut off".to_string(),
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseBody")
+            .field("buffered", &(self.pending.len() - self.offset))
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+impl AsyncRead for ResponseBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.offset == self.pending.len() {
+            if self.finished {
+                return Poll::Ready(Ok(()));
+            }
+            let next = ready!(self.chunks.poll_recv(cx));
+            match self.receive(next) {
+                Ok(Some(chunk)) => {
+                    self.pending = chunk;
+                    self.offset = 0;
+                }
+                Ok(None) => return Poll::Ready(Ok(())),
+                Err(e) => return Poll::Ready(Err(io::Error::other(e))),
+            }
+        }
+
+        let n = (self.pending.len() - self.offset).min(buf.remaining());
+        let start = self.offset;
+        buf.put_slice(&self.pending[start..start + n]);
+        self.offset += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Forward `body` to a `ResponseBody` from a task in the context scope,
+/// counting its bytes in `metrics`.
+///
+/// `abandon` runs if the body does not reach its end: the reader went
+/// away, reading failed, or the watchdog reaped the circuit. It is
+/// dropped unrun after a clean end.
+pub(crate) fn pump_body<R, F>(
+    tasks: &TaskRegistry,
+    mut body: BodyReader<R>,
+    activity: CircuitActivity,
+    metrics: Arc<NetworkMetrics>,
+    abandon: F,
+) -> ResponseBody
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (sender, chunks) = mpsc::channel(BODY_CHANNEL_DEPTH);
+    tasks.spawn(TaskScope::Context, "response body", async move {
+        activity.set_streaming(true);
+        let complete = loop {
+            let next = tokio::select! {
+                next = body.next_chunk() => next,
+                _ = sender.closed() => break false,
+            };
+            if activity.is_reaped() {
+                let reaped = NetworkError::RequestFailed("Circuit reaped by the watchdog".into());
+                let _ = sender.send(Err(reaped)).await;
+                break false;
+            }
+            activity.touch();
+            match next {
+                Ok(Some(chunk)) => {
+                    metrics.record_received(chunk.len() as u64);
+                    if sender.send(Ok(Some(chunk))).await.is_err() {
+                        break false;
+                    }
+                }
+                Ok(None) => break sender.send(Ok(None)).await.is_ok(),
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    break false;
+                }
+            }
+        };
+        activity.set_streaming(false);
+        if !complete {
+            abandon.await;
+        }
+    });
+    ResponseBody::new(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_response::{read_head, ResponseLimits};
+    use crate::watchdog::CircuitWatchdog;
+    use forloop_config::ByteSize;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+    use tokio::sync::oneshot;
+
+    /// Read the head the server sends and start pumping its body.
+    /// The receiver fires if the body is abandoned.
+    async fn stream(
+        tasks: &TaskRegistry,
+        watchdog: &CircuitWatchdog,
+        metrics: &Arc<NetworkMetrics>,
+        client: DuplexStream,
+    ) -> (u16, ResponseBody, oneshot::Receiver<()>) {
+        let permit = watchdog.try_permit().expect("permit");
+        let activity = watchdog.track("7", permit);
+        let head = read_head(
+            BufReader::new(client),
+            "GET",
+            &ResponseLimits::default(),
+            ByteSize::mib(1),
+        )
+        .await
+        .expect("head");
+        let (abandoned, fired) = oneshot::channel();
+        let abandon = async move {
+            let _ = abandoned.send(());
+        };
+        let body = pump_body(tasks, head.body, activity, Arc::clone(metrics), abandon);
+        (head.status, body, fired)
+    }
+
+    #[tokio::test]
+    async fn test_trickled_body_arrives_incrementally() {
+        let tasks = TaskRegistry::new();
+        let watchdog = CircuitWatchdog::default();
+        let (client, mut server) = tokio::io::duplex(1024);
+        let (next_tx, mut next_rx) = mpsc::channel::<()>(1);
+
+        // Each piece is only sent once the previous one has been read, so
+        // the test hangs if the body is buffered before it is handed over
+        let server_task = tokio::spawn(async move {
+            server
+                .write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")
+                .await
+                .expect("write head");
+            for piece in ["first", "second", "third"] {
+                let chunk = format!("{:x}\r\n{}\r\n", piece.len(), piece);
+                server.write_all(chunk.as_bytes()).await.expect("write");
+                next_rx.recv().await;
+            }
+            server.write_all(b"0\r\n\r\n").await.expect("write end");
+        });
+
+        let metrics = Arc::new(NetworkMetrics::new());
+        let (status, mut body, fired) = stream(&tasks, &watchdog, &metrics, client).await;
+        assert_eq!(status, 200);
+        for piece in ["first", "second", "third"] {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.chunk())
+                .await
+                .expect("piece arrives before the rest is sent")
+                .expect("reads");
+            assert_eq!(chunk.as_deref(), Some(piece.as_bytes()));
+            next_tx.send(()).await.expect("server waiting");
+        }
+        assert_eq!(body.chunk().await.expect("clean end"), None);
+        server_task.await.expect("server");
+        assert_eq!(metrics.snapshot().bytes_received, 16);
+
+        // A clean end leaves the circuit open
+        assert!(fired.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dropping_body_closes_circuit() {
+        let tasks = TaskRegistry::new();
+        let watchdog = CircuitWatchdog::default();
+        let (client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\n\r\npartial")
+            .await
+            .expect("write");
+
+        let metrics = Arc::new(NetworkMetrics::new());
+        let (_, mut body, fired) = stream(&tasks, &watchdog, &metrics, client).await;
+        let mut buf = [0u8; 7];
+        body.read_exact(&mut buf).await.expect("reads");
+        assert_eq!(&buf, b"partial");
+
+        // The server stalls with the body unfinished; the reader gives up
+        drop(body);
+        tokio::time::timeout(Duration::from_secs(5), fired)
+            .await
+            .expect("abandoned promptly")
+            .expect("circuit closed");
+        drop(server);
+    }
+}
@@ -0,0 +1,298 @@
+//! Ownership of long-lived background tasks.
+//!
+//! A task spawned with a bare `tokio::spawn` outlives whatever started it:
+//! after New Loop it can keep a circuit open, after Quit it can touch
+//! state that was already wiped. Long-lived tasks are therefore spawned
+//! through a `TaskRegistry`, into one of three scopes:
+//!
+//! - `Context`: one navigation context; cancelled on New Loop
+//! - `Session`: the browser session (event demux and the like)
+//! - `Process`: lives as long as the process (watchdog sweeper)
+//!
+//! Cancelling a scope drops every task in it at its next await point and
+//! waits until all of them have finished. `shutdown` cancels the scopes
+//! innermost first.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tokio::task::{AbortHandle, Id, JoinSet};
+
+/// Lifetime a background task is tied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TaskScope {
+    /// One navigation context; ends on New Loop
+    Context,
+    /// The browser session
+    Session,
+    /// The whole process
+    Process,
+}
+
+impl TaskScope {
+    /// Order in which `TaskRegistry::shutdown` cancels the scopes.
+    pub const SHUTDOWN_ORDER: [Self; 3] = [Self::Context, Self::Session, Self::Process];
+
+    fn index(self) -> usize {
+        match self {
+            TaskScope::Context => 0,
+            TaskScope::Session => 1,
+            TaskScope::Process => 2,
+        }
+    }
+}
+
+/// Cancellation signal shared by the tasks of one scope.
+#[derive(Debug, Clone, Default)]
+pub struct TaskCancel {
+    cancelled: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+impl TaskCancel {
+    /// Cancel; every task waiting in `cancelled` wakes at once.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until cancellation is requested.
+    pub async fn cancelled(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // Register before checking, so a cancel in between is not missed
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+}
+
+/// Tasks of one scope.
+#[derive(Default)]
+struct ScopeTasks {
+    set: JoinSet<()>,
+    names: HashMap<Id, &'static str>,
+    cancel: TaskCancel,
+}
+
+impl ScopeTasks {
+    /// Forget tasks that have already finished.
+    fn reap(&mut self) {
+        while let Some(result) = self.set.try_join_next_with_id() {
+            let id = match result {
+                Ok((id, ())) => id,
+                Err(e) => e.id(),
+            };
+            self.names.remove(&id);
+        }
+    }
+}
+
+/// Owner of every long-lived background task.
+pub struct TaskRegistry {
+    scopes: Mutex<[ScopeTasks; 3]>,
+}
+
+impl TaskRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self {
+            scopes: Mutex::new(Default::default()),
+        }
+    }
+
+    /// Spawn `future` into `scope`. Must be called within a tokio runtime.
+    ///
+    /// `name` identifies the task in logs and leak reports. The returned
+    /// handle aborts just this task.
+    pub fn spawn<F>(&self, scope: TaskScope, name: &'static str, future: F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut scopes = self.scopes.lock().expect("Task registry lock poisoned");
+        let tasks = &mut scopes[scope.index()];
+        tasks.reap();
+
+        let cancel = tasks.cancel.clone();
+        let handle = tasks.set.spawn(async move {
+            tokio::select! {
+                _ = future => {}
+                _ = cancel.cancelled() => log::debug!("Cancelled {:?} task {}", scope, name),
+            }
+        });
+        tasks.names.insert(handle.id(), name);
+        handle
+    }
+
+    /// Cancel every task in `scope` and wait until all have finished.
+    ///
+    /// The scope stays usable: tasks spawned afterwards run normally.
+    pub async fn cancel_scope(&self, scope: TaskScope) {
+        let mut set = {
+            let mut scopes = self.scopes.lock().expect("Task registry lock poisoned");
+            let tasks = &mut scopes[scope.index()];
+            tasks.cancel.cancel();
+            tasks.cancel = TaskCancel::default();
+            tasks.names.clear();
+            std::mem::take(&mut tasks.set)
+        };
+
+        while set.join_next().await.is_some() {}
+    }
+
+    /// Cancel every scope, innermost first.
+    pub async fn shutdown(&self) {
+        for scope in TaskScope::SHUTDOWN_ORDER {
+            self.cancel_scope(scope).await;
+        }
+    }
+
+    /// Names of the tasks in `scope` that have not finished, sorted.
+    pub fn live_tasks(&self, scope: TaskScope) -> Vec<&'static str> {
+        let mut scopes = self.scopes.lock().expect("Task registry lock poisoned");
+        let tasks = &mut scopes[scope.index()];
+        tasks.reap();
+
+        let mut names: Vec<_> = tasks.names.values().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Test-only leak detector: panic if `scope` still has live tasks.
+    ///
+    /// Call after the owner of the scope's tasks was dropped. Aborted
+    /// tasks finish at their next poll, so they get a moment to do so.
+    #[cfg(any(test, feature = "test-support"))]
+    pub async fn assert_no_leaks(&self, scope: TaskScope) {
+        for _ in 0..100 {
+            if self.live_tasks(scope).is_empty() {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        panic!(
+            "{:?} tasks outlived their owner: {:?}",
+            scope,
+            self.live_tasks(scope)
+        );
+    }
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for TaskRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskRegistry")
+            .field("context", &self.live_tasks(TaskScope::Context))
+            .field("session", &self.live_tasks(TaskScope::Session))
+            .field("process", &self.live_tasks(TaskScope::Process))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    /// Records the order in which tasks were dropped.
+    struct DropLog {
+        name: &'static str,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Drop for DropLog {
+        fn drop(&mut self) {
+            self.log.lock().expect("drop log").push(self.name);
+        }
+    }
+
+    fn forever(
+        name: &'static str,
+        log: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> impl Future<Output = ()> {
+        let guard = DropLog {
+            name,
+            log: Arc::clone(log),
+        };
+        async move {
+            let _guard = guard;
+            std::future::pending::<()>().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_scopes_in_order() {
+        let registry = TaskRegistry::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        registry.spawn(TaskScope::Process, "sweeper", forever("process", &log));
+        registry.spawn(TaskScope::Session, "demux", forever("session", &log));
+        registry.spawn(TaskScope::Context, "connect", forever("context", &log));
+        tokio::task::yield_now().await;
+        assert_eq!(registry.live_tasks(TaskScope::Session), vec!["demux"]);
+
+        registry.shutdown().await;
+
+        assert_eq!(
+            *log.lock().expect("drop log"),
+            vec!["context", "session", "process"]
+        );
+        for scope in TaskScope::SHUTDOWN_ORDER {
+            registry.assert_no_leaks(scope).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_scope_is_reusable() {
+        let registry = TaskRegistry::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        registry.spawn(TaskScope::Context, "old loop", forever("old", &log));
+        registry.cancel_scope(TaskScope::Context).await;
+        assert_eq!(*log.lock().expect("drop log"), vec!["old"]);
+
+        let (tx, rx) = oneshot::channel();
+        registry.spawn(TaskScope::Context, "new loop", async move {
+            let _ = tx.send(7);
+        });
+        assert_eq!(rx.await.expect("task ran"), 7);
+        registry.assert_no_leaks(TaskScope::Context).await;
+    }
+
+    #[tokio::test]
+    async fn test_abort_handle_stops_one_task() {
+        let registry = TaskRegistry::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let handle = registry.spawn(TaskScope::Session, "a", forever("a", &log));
+        registry.spawn(TaskScope::Session, "b", forever("b", &log));
+        handle.abort();
+        tokio::task::yield_now().await;
+
+        assert_eq!(registry.live_tasks(TaskScope::Session), vec!["b"]);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "outlived their owner")]
+    async fn test_leak_detector_reports_live_tasks() {
+        let registry = TaskRegistry::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        registry.spawn(TaskScope::Context, "orphan", forever("orphan", &log));
+        registry.assert_no_leaks(TaskScope::Context).await;
+    }
+}
@@ -0,0 +1,46 @@
+//! Scratch directories for tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Directories handed out so far in this process.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temp dir, removed when the test ends.
+///
+/// Names are unique within the process, so tests running in parallel
+/// never share one.
+#[derive(Debug)]
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Reserve a directory for `tag` without creating it.
+    pub(crate) fn new(tag: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "forloop-{}-{}-{}",
+            tag,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Self(dir)
+    }
+
+    /// Create an empty directory for `tag`.
+    pub(crate) fn create(tag: &str) -> Self {
+        let dir = Self::new(tag);
+        std::fs::create_dir_all(&dir.0).expect("create temp dir");
+        dir
+    }
+
+    /// Where the directory is.
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,470 @@
+//! Reduce an HTML body to plain text without a rendering engine.
+//!
+//! The consistency checker and reader mode both need a document's content
+//! rather than its bytes. The extractor walks the same tokens as the HTML
+//! sanitizer and produces:
+//!
+//! - the visible text: scripts and styles dropped, entities decoded,
+//!   whitespace collapsed, one line per block element
+//! - the title, if the document has one
+//! - image and link references, in document order
+//!
+//! Output depends only on the input bytes, never on how they were chunked.
+//! Malformed markup degrades the way a browser would: a stray '<' is text,
+//! an unterminated tag at EOF is dropped, and an unclosed script swallows
+//! the rest of the document. Text and references are capped so a hostile
+//! page cannot make either consumer allocate without bound.
+
+use crate::html_tokens::{HtmlTokenizer, Tag, Token, RAW_TEXT_ELEMENTS};
+
+/// Most bytes of text kept from one document.
+pub const MAX_EXTRACTED_TEXT: usize = 512 * 1024;
+
+/// Most image plus link references kept from one document.
+pub const MAX_EXTRACTED_REFERENCES: usize = 1024;
+
+/// Elements that start a new line of text.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// Named entities decoded; anything else is left as written.
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("apos", '\''),
+    ("copy", '\u{a9}'),
+    ("gt", '>'),
+    ("hellip", '\u{2026}'),
+    ("laquo", '\u{ab}'),
+    ("ldquo", '\u{201c}'),
+    ("lsquo", '\u{2018}'),
+    ("lt", '<'),
+    ("mdash", '\u{2014}'),
+    ("middot", '\u{b7}'),
+    ("nbsp", '\u{a0}'),
+    ("ndash", '\u{2013}'),
+    ("quot", '"'),
+    ("raquo", '\u{bb}'),
+    ("rdquo", '\u{201d}'),
+    ("reg", '\u{ae}'),
+    ("rsquo", '\u{2019}'),
+    ("trade", '\u{2122}'),
+];
+
+/// Text and references extracted from a document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtractedText {
+    /// Document title, whitespace collapsed
+    pub title: Option<String>,
+    /// Visible text, one line per block
+    pub text: String,
+    /// `<img src>` values, in document order
+    pub images: Vec<String>,
+    /// `<a href>` and `<area href>` values, in document order
+    pub links: Vec<String>,
+    /// Whether text or references were cut off at the caps
+    pub truncated: bool,
+}
+
+impl ExtractedText {
+    /// Everything extracted as one string, for diffing two copies.
+    pub fn canonical(&self) -> String {
+        let mut out = String::new();
+        if let Some(title) = &self.title {
+            out.push_str(title);
+            out.push_str("\n\n");
+        }
+        out.push_str(&self.text);
+        for image in &self.images {
+            out.push_str("\n[image] ");
+            out.push_str(image);
+        }
+        for link in &self.links {
+            out.push_str("\n[link] ");
+            out.push_str(link);
+        }
+        out
+    }
+}
+
+/// Where content inside a raw text element goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum RawText {
+    /// `<script>`, `<style>`: dropped
+    #[default]
+    Skip,
+    /// `<title>`: kept as the title
+    Title,
+    /// `<textarea>`, `<xmp>`: shown as text
+    Text,
+}
+
+/// Streaming text extractor for one document.
+#[derive(Debug, Default)]
+pub struct TextExtractor {
+    tokenizer: HtmlTokenizer,
+    /// Where the content of the current raw text element goes
+    raw_text: RawText,
+    /// Text seen since the last tag, not yet decoded
+    run: Vec<u8>,
+    /// Undecoded title text
+    title: Vec<u8>,
+    /// Whitespace seen but not yet written
+    space_pending: bool,
+    out: ExtractedText,
+}
+
+impl TextExtractor {
+    /// Create an extractor for a new document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extract from the next chunk.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut tokenizer = std::mem::take(&mut self.tokenizer);
+        tokenizer.feed(chunk, |token| self.token(token));
+        self.tokenizer = tokenizer;
+    }
+
+    /// Finish the document and return what was extracted.
+    ///
+    /// An unterminated tag at EOF is dropped, as a browser would.
+    pub fn finish(mut self) -> ExtractedText {
+        self.flush_run();
+        if !self.title.is_empty() && self.out.title.is_none() {
+            let title = collapse_whitespace(&decode_entities(&self.title));
+            self.out.title = (!title.is_empty()).then_some(title);
+        }
+        let end = self.out.text.trim_end().len();
+        self.out.text.truncate(end);
+        self.out
+    }
+
+    fn token(&mut self, token: Token<'_>) {
+        match token {
+            Token::Text(bytes) => self.run.extend_from_slice(bytes),
+            Token::RawText(bytes) => match self.raw_text {
+                RawText::Skip => {}
+                RawText::Title => self.title.extend_from_slice(bytes),
+                RawText::Text => self.run.extend_from_slice(bytes),
+            },
+            Token::StartTag(tag, _) => {
+                self.flush_run();
+                self.start_tag(&tag);
+            }
+            Token::EndTag(name, _) => {
+                self.flush_run();
+                if BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    self.break_line();
+                }
+            }
+            Token::Other(_) => {}
+        }
+    }
+
+    fn start_tag(&mut self, tag: &Tag) {
+        let name = tag.name.as_str();
+        if BLOCK_ELEMENTS.contains(&name) {
+            self.break_line();
+        }
+
+        match name {
+            "img" => {
+                if let Some(src) = tag.attr("src") {
+                    self.push_reference(src, true);
+                }
+            }
+            "a" | "area" => {
+                if let Some(href) = tag.attr("href") {
+                    self.push_reference(href, false);
+                }
+            }
+            name if RAW_TEXT_ELEMENTS.contains(&name) => {
+                self.raw_text = match name {
+                    "script" | "style" => RawText::Skip,
+                    "title" => RawText::Title,
+                    _ => RawText::Text,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn push_reference(&mut self, value: &str, image: bool) {
+        let value = decode_entities(value.as_bytes());
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+        if self.out.images.len() + self.out.links.len() >= MAX_EXTRACTED_REFERENCES {
+            self.out.truncated = true;
+            return;
+        }
+        let list = if image {
+            &mut self.out.images
+        } else {
+            &mut self.out.links
+        };
+        list.push(value.to_string());
+    }
+
+    /// Decode and append the pending text run.
+    fn flush_run(&mut self) {
+        if self.run.is_empty() {
+            return;
+        }
+        let run = std::mem::take(&mut self.run);
+        if self.out.truncated {
+            return;
+        }
+
+        for c in decode_entities(&run).chars() {
+            if c.is_ascii_whitespace() {
+                self.space_pending = true;
+                continue;
+            }
+            let at_line_start = self.out.text.is_empty() || self.out.text.ends_with('\n');
+            let space = self.space_pending && !at_line_start;
+            if self.out.text.len() + usize::from(space) + c.len_utf8() > MAX_EXTRACTED_TEXT {
+                self.out.truncated = true;
+                return;
+            }
+            if space {
+                self.out.text.push(' ');
+            }
+            self.space_pending = false;
+            self.out.text.push(c);
+        }
+    }
+
+    /// End the current line at a block boundary.
+    fn break_line(&mut self) {
+        self.space_pending = false;
+        if !self.out.text.is_empty() && !self.out.text.ends_with('\n') {
+            self.out.text.push('\n');
+        }
+    }
+}
+
+/// Extract text and references from a complete HTML body.
+pub fn extract_text(body: &[u8]) -> ExtractedText {
+    let mut extractor = TextExtractor::new();
+    extractor.feed(body);
+    extractor.finish()
+}
+
+/// Render extracted text into the reader mode template.
+///
+/// Only the title and text are shown: images would be fetched and links
+/// are listed in the original page.
+pub fn render_reader(url: &str, extracted: &ExtractedText) -> String {
+    let title = escape_html(extracted.title.as_deref().unwrap_or(url));
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n</head>\n", title));
+    out.push_str(&format!(
+        "<body class=\"reader\">\n<article>\n<h1>{}</h1>\n",
+        title
+    ));
+    for line in extracted.text.lines() {
+        out.push_str("<p>");
+        out.push_str(&escape_html(line));
+        out.push_str("</p>\n");
+    }
+    if extracted.truncated {
+        out.push_str("<p class=\"truncated\">This page was too long to show in full.</p>\n");
+    }
+    out.push_str("</article>\n</body>\n</html>\n");
+    out
+}
+
+/// Decode character references in `raw`, replacing invalid UTF-8.
+fn decode_entities(raw: &[u8]) -> String {
+    let raw = String::from_utf8_lossy(raw);
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw.as_ref();
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_reference(rest) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode one reference at the start of `s` ("&amp;", "&#38;", "&#x26;").
+/// Returns the character and the bytes consumed.
+fn decode_reference(s: &str) -> Option<(char, usize)> {
+    let semi = s.bytes().take(34).position(|b| b == b';')?;
+    let name = &s[1..semi];
+
+    let c = if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        match code {
+            0 => char::REPLACEMENT_CHARACTER,
+            code => char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER),
+        }
+    } else {
+        NAMED_ENTITIES
+            .iter()
+            .find(|(entity, _)| *entity == name)
+            .map(|&(_, c)| c)?
+    };
+    Some((c, semi + 1))
+}
+
+/// Collapse runs of whitespace to one space and trim the ends.
+fn collapse_whitespace(s: &str) -> String {
+    s.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARTICLE: &[u8] = b"<!DOCTYPE html>\n<html><head>\n\
+        <title>  Release   notes &amp; more </title>\n\
+        <style>p { color: red }</style>\n\
+        <script>document.write('<p>injected</p>')</script>\n\
+        </head><body>\n\
+        <h1>Version&nbsp;2</h1>\n\
+        <p>Fixes a   crash\n   when <b>loading</b> pages.<br>See the <a href=\"/log?a=1&amp;b=2\">log</a>.</p>\n\
+        <!-- <p>hidden</p> -->\n\
+        <ul><li>One</li><li>Two &lt;3&gt; &#8212; &#x263A;</li></ul>\n\
+        <img src=\"/shot.png\" alt=\"screenshot\"><img src=\"  \">\n\
+        </body></html>\n";
+
+    const ARTICLE_TEXT: &str = "Version\u{a0}2\n\
+        Fixes a crash when loading pages.\n\
+        See the log.\n\
+        One\n\
+        Two <3> \u{2014} \u{263a}";
+
+    #[test]
+    fn test_sample_page_snapshot() {
+        let extracted = extract_text(ARTICLE);
+        assert_eq!(extracted.title.as_deref(), Some("Release notes & more"));
+        assert_eq!(extracted.text, ARTICLE_TEXT);
+        assert_eq!(extracted.images, vec!["/shot.png".to_string()]);
+        assert_eq!(extracted.links, vec!["/log?a=1&b=2".to_string()]);
+        assert!(!extracted.truncated);
+
+        assert_eq!(
+            render_reader("https://example.com/", &extracted),
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Release notes &amp; more</title>\n</head>\n<body class=\"reader\">\n\
+             <article>\n<h1>Release notes &amp; more</h1>\n\
+             <p>Version\u{a0}2</p>\n\
+             <p>Fixes a crash when loading pages.</p>\n\
+             <p>See the log.</p>\n\
+             <p>One</p>\n\
+             <p>Two &lt;3&gt; \u{2014} \u{263a}</p>\n\
+             </article>\n</body>\n</html>\n"
+        );
+    }
+
+    #[test]
+    fn test_malformed_markup_snapshot() {
+        let extracted = extract_text(
+            b"<div>a < b &bogus; &amp</div><p>unclosed <i class=\"x\">tag \xff</p><div><script>var s = '</div>';",
+        );
+        assert_eq!(extracted.text, "a < b &bogus; &amp\nunclosed tag \u{fffd}");
+        assert_eq!(extracted.title, None);
+
+        // Unterminated tag at EOF is dropped
+        assert_eq!(extract_text(b"<p>end</p><a href=\"x").text, "end");
+
+        // Abruptly closed empty comments hide nothing after them
+        assert_eq!(extract_text(b"<!-->one<!--->two<!-- -->").text, "onetwo");
+    }
+
+    #[test]
+    fn test_output_is_independent_of_chunking() {
+        let whole = extract_text(ARTICLE);
+        for size in [1, 2, 3, 7, 64] {
+            let mut extractor = TextExtractor::new();
+            for chunk in ARTICLE.chunks(size) {
+                extractor.feed(chunk);
+            }
+            assert_eq!(extractor.finish(), whole, "chunk size {}", size);
+        }
+        assert_eq!(extract_text(ARTICLE).canonical(), whole.canonical());
+    }
+
+    #[test]
+    fn test_output_is_capped() {
+        let mut page = b"<p>".to_vec();
+        page.extend(b"word ".repeat(MAX_EXTRACTED_TEXT));
+        for _ in 0..MAX_EXTRACTED_REFERENCES + 10 {
+            page.extend_from_slice(b"<a href=/x></a>");
+        }
+
+        let extracted = extract_text(&page);
+        assert!(extracted.truncated);
+        assert!(extracted.text.len() <= MAX_EXTRACTED_TEXT);
+        assert_eq!(extracted.links.len(), MAX_EXTRACTED_REFERENCES);
+        assert!(render_reader("https://example.com/", &extracted).contains("too long"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Coarse per-phase timing of a request.
+//!
+//! Knowing where a slow request spent its time (building the circuit,
+//! reaching the server through it, the TLS handshake, waiting for the
+//! first byte) is what tells a slow onion service from a slow circuit.
+//! Precise timings are a side channel, though, so every duration is
+//! floored to the timing precision given to content (100 ms): shown in
+//! the page-info panel, or leaked to content, they say no more than
+//! `performance.now()` does.
+//!
+//! Phase ends are quantized as offsets from the start and the phases
+//! taken as their differences, so the phases add up to the time to the
+//! first byte, and never to more than the total.
+
+use std::time::{Duration, Instant};
+
+/// Where a request spent its time, each duration a multiple of the
+/// timing precision.
+///
+/// A phase a request skipped, such as building a circuit it shared, is
+/// zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestTiming {
+    /// Getting a circuit
+    pub circuit_build: Duration,
+    /// Opening the stream to the server through it
+    pub socks_connect: Duration,
+    /// TLS handshake
+    pub tls_handshake: Duration,
+    /// From the handshake to the first response byte (to the whole
+    /// response over HTTP/2)
+    pub first_byte: Duration,
+    /// Whole request, body included
+    pub total: Duration,
+}
+
+/// When each phase of a request ended.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Phases {
+    pub(crate) started: Option<Instant>,
+    pub(crate) circuit_built: Option<Instant>,
+    pub(crate) connected: Option<Instant>,
+    pub(crate) handshaken: Option<Instant>,
+    pub(crate) first_byte: Option<Instant>,
+}
+
+impl Phases {
+    /// Timing of a request that ended at `finished`, floored to `quantum`,
+    /// or `None` if its start was not recorded.
+    pub(crate) fn timing(&self, finished: Instant, quantum: Duration) -> Option<RequestTiming> {
+        let started = self.started?;
+        let offset = |at: Instant| floor(at.saturating_duration_since(started), quantum);
+
+        // A missing end leaves its phase empty
+        let mut end = Duration::ZERO;
+        let mut phase = |at: Option<Instant>| {
+            let previous = end;
+            end = at.map_or(end, offset).max(end);
+            end - previous
+        };
+        let circuit_build = phase(self.circuit_built);
+        let socks_connect = phase(self.connected);
+        let tls_handshake = phase(self.handshaken);
+        let first_byte = phase(self.first_byte);
+
+        Some(RequestTiming {
+            circuit_build,
+            socks_connect,
+            tls_handshake,
+            first_byte,
+            total: offset(finished).max(end),
+        })
+    }
+}
+
+/// `duration` rounded down to a multiple of `quantum`.
+fn floor(duration: Duration, quantum: Duration) -> Duration {
+    let quantum = quantum.as_nanos().max(1);
+    let floored = duration.as_nanos() / quantum * quantum;
+    Duration::from_nanos(u64::try_from(floored).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUANTUM: Duration = Duration::from_millis(100);
+
+    fn marked(marks_ms: [u64; 4]) -> (Phases, Instant) {
+        let started = Instant::now();
+        let at = |ms: u64| Some(started + Duration::from_millis(ms));
+        let phases = Phases {
+            started: Some(started),
+            circuit_built: at(marks_ms[0]),
+            connected: at(marks_ms[1]),
+            handshaken: at(marks_ms[2]),
+            first_byte: at(marks_ms[3]),
+        };
+        (phases, started)
+    }
+
+    #[test]
+    fn test_phases_quantized_and_add_up() {
+        let (phases, started) = marked([1_234, 2_010, 2_399, 3_651]);
+        let timing = phases
+            .timing(started + Duration::from_millis(3_987), QUANTUM)
+            .expect("timing");
+
+        let durations = [
+            timing.circuit_build,
+            timing.socks_connect,
+            timing.tls_handshake,
+            timing.first_byte,
+        ];
+        for duration in durations.iter().chain([&timing.total]) {
+            assert_eq!(
+                duration.as_nanos() % QUANTUM.as_nanos(),
+                0,
+                "{:?}",
+                duration
+            );
+        }
+        assert_eq!(
+            durations,
+            [1_200, 800, 300, 1_300].map(Duration::from_millis)
+        );
+        assert_eq!(
+            durations.iter().sum::<Duration>(),
+            Duration::from_millis(3_600)
+        );
+        assert_eq!(timing.total, Duration::from_millis(3_900));
+    }
+
+    #[test]
+    fn test_skipped_phases_are_zero() {
+        let (mut phases, started) = marked([0, 450, 980, 1_020]);
+        phases.circuit_built = None;
+        let timing = phases
+            .timing(started + Duration::from_millis(1_050), QUANTUM)
+            .expect("timing");
+        assert_eq!(timing.circuit_build, Duration::ZERO);
+        assert_eq!(timing.socks_connect, Duration::from_millis(400));
+        assert_eq!(timing.total, Duration::from_millis(1_000));
+
+        // Under one quantum, everything is zero
+        let (phases, started) = marked([10, 20, 30, 40]);
+        assert_eq!(
+            phases.timing(started + Duration::from_millis(99), QUANTUM),
+            Some(RequestTiming::default())
+        );
+        assert_eq!(Phases::default().timing(Instant::now(), QUANTUM), None);
+    }
+}
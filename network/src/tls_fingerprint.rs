@@ -2,9 +2,22 @@
 //!
 //! TLS fingerprinting (JA3, JA4, etc.) can identify browsers.
 //! This module ensures our TLS fingerprint matches Tor Browser.
+//!
+//! `TlsConfig::ja3` and `TlsConfig::ja4` compute the fingerprints from the
+//! configuration itself, and `TlsFingerprintNormalizer::self_check` holds
+//! the JA3 to the expected hash, so the two cannot drift apart unnoticed.
+
+use std::fmt;
 
+use crate::client_hello::{
+    parse_client_hello, EXT_ALPN, EXT_PRE_SHARED_KEY, EXT_SERVER_NAME, LEGACY_VERSION,
+};
+use crate::digest::{md5, Sha256};
 use crate::NetworkError;
 
+/// JA3 hash of the ClientHello `tor_browser_config` produces.
+const EXPECTED_JA3_HASH: &str = "edce22309f9e1145fb6fe4541f06fcfc";
+
 /// TLS configuration for normalized fingerprint.
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
@@ -14,6 +27,8 @@ pub struct TlsConfig {
     pub extensions: Vec<u16>,
     /// Supported groups (curves)
     pub supported_groups: Vec<u16>,
+    /// EC point formats
+    pub ec_point_formats: Vec<u8>,
     /// Signature algorithms
     pub signature_algorithms: Vec<u16>,
     /// ALPN protocols
@@ -24,8 +39,266 @@ pub struct TlsConfig {
     pub max_version: TlsVersion,
 }
 
+impl TlsConfig {
+    /// Get the JA3 string of the ClientHello this configuration produces.
+    pub fn ja3(&self) -> String {
+        self.fields().ja3()
+    }
+
+    /// Get the JA3 hash of the ClientHello this configuration produces.
+    pub fn ja3_hash(&self) -> String {
+        self.fields().ja3_hash()
+    }
+
+    /// Get the JA4 fingerprint of the ClientHello this configuration
+    /// produces.
+    pub fn ja4(&self) -> String {
+        self.fields().ja4()
+    }
+
+    /// Get the wire values of the versions offered, newest first.
+    pub(crate) fn offered_versions(&self) -> Vec<u16> {
+        [TlsVersion::Tls13, TlsVersion::Tls12]
+            .into_iter()
+            .filter(|v| (self.min_version..=self.max_version).contains(v))
+            .map(TlsVersion::wire)
+            .collect()
+    }
+
+    /// The ClientHello sent to a clearnet host. pre_shared_key is left
+    /// out: it only resumes a session, and none is ever kept.
+    fn fields(&self) -> HelloFields {
+        HelloFields {
+            version: LEGACY_VERSION,
+            supported_versions: self.offered_versions(),
+            cipher_suites: self.cipher_suites.clone(),
+            extensions: self
+                .extensions
+                .iter()
+                .copied()
+                .filter(|&e| e != EXT_PRE_SHARED_KEY)
+                .collect(),
+            supported_groups: self.supported_groups.clone(),
+            ec_point_formats: self.ec_point_formats.clone(),
+            signature_algorithms: self.signature_algorithms.clone(),
+            alpn: self.alpn_protocols.first().cloned(),
+        }
+    }
+}
+
+/// One way a ClientHello differs from the configured fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FingerprintMismatch {
+    /// The record could not be parsed
+    Malformed(String),
+    /// legacy_version differs
+    Version {
+        /// Configured value
+        expected: u16,
+        /// Value in the ClientHello
+        found: u16,
+    },
+    /// Compression methods other than null alone were offered
+    Compression(Vec<u8>),
+    /// An extension was sent that is not configured
+    UnexpectedExtension(u16),
+    /// A configured extension was not sent
+    MissingExtension(u16),
+    /// Extensions were sent out of the configured order
+    ExtensionOrder {
+        /// Configured order
+        expected: Vec<u16>,
+        /// Order in the ClientHello
+        found: Vec<u16>,
+    },
+    /// The contents of a list differ
+    List {
+        /// Which list
+        field: &'static str,
+        /// Configured values
+        expected: Vec<String>,
+        /// Values in the ClientHello
+        found: Vec<String>,
+    },
+}
+
+impl fmt::Display for FingerprintMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FingerprintMismatch::Malformed(detail) => write!(f, "not a ClientHello: {}", detail),
+            FingerprintMismatch::Version { expected, found } => {
+                write!(f, "version {:#06x}, expected {:#06x}", found, expected)
+            }
+            FingerprintMismatch::Compression(methods) => {
+                write!(f, "compression methods {:?}, expected [0]", methods)
+            }
+            FingerprintMismatch::UnexpectedExtension(extension) => {
+                write!(f, "unexpected extension {:#06x}", extension)
+            }
+            FingerprintMismatch::MissingExtension(extension) => {
+                write!(f, "missing extension {:#06x}", extension)
+            }
+            FingerprintMismatch::ExtensionOrder { expected, found } => write!(
+                f,
+                "extensions in order {:04x?}, expected {:04x?}",
+                found, expected
+            ),
+            FingerprintMismatch::List {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} [{}], expected [{}]",
+                field,
+                found.join(", "),
+                expected.join(", ")
+            ),
+        }
+    }
+}
+
+/// Whether `value` is a GREASE value (RFC 8701). Fingerprints leave them
+/// out, since clients pick them at random.
+pub fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// The ClientHello fields JA3 and JA4 are computed from.
+pub(crate) struct HelloFields {
+    pub(crate) version: u16,
+    pub(crate) supported_versions: Vec<u16>,
+    pub(crate) cipher_suites: Vec<u16>,
+    pub(crate) extensions: Vec<u16>,
+    pub(crate) supported_groups: Vec<u16>,
+    pub(crate) ec_point_formats: Vec<u8>,
+    pub(crate) signature_algorithms: Vec<u16>,
+    /// First ALPN protocol offered
+    pub(crate) alpn: Option<String>,
+}
+
+impl HelloFields {
+    /// Version, cipher suites, extensions, groups and point formats, in
+    /// decimal and wire order.
+    pub(crate) fn ja3(&self) -> String {
+        let decimal = |values: &[u16]| {
+            values
+                .iter()
+                .filter(|v| !is_grease(**v))
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>()
+                .join("-")
+        };
+        let point_formats: Vec<u16> = self.ec_point_formats.iter().map(|&f| f as u16).collect();
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            decimal(&self.cipher_suites),
+            decimal(&self.extensions),
+            decimal(&self.supported_groups),
+            decimal(&point_formats)
+        )
+    }
+
+    pub(crate) fn ja3_hash(&self) -> String {
+        hex(&md5(self.ja3().as_bytes()))
+    }
+
+    /// Protocol, version, SNI, counts and ALPN; then hashes of the sorted
+    /// cipher suites, and of the sorted extensions (less SNI and ALPN)
+    /// with the signature algorithms in wire order.
+    pub(crate) fn ja4(&self) -> String {
+        let without_grease = |values: &[u16]| {
+            values
+                .iter()
+                .copied()
+                .filter(|v| !is_grease(*v))
+                .collect::<Vec<_>>()
+        };
+        let mut cipher_suites = without_grease(&self.cipher_suites);
+        let extensions = without_grease(&self.extensions);
+        let signature_algorithms = without_grease(&self.signature_algorithms);
+
+        let version = match without_grease(&self.supported_versions)
+            .into_iter()
+            .max()
+            .unwrap_or(self.version)
+        {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            _ => "00",
+        };
+        let sni = if extensions.contains(&EXT_SERVER_NAME) {
+            'd'
+        } else {
+            'i'
+        };
+        let alpn = match self.alpn.as_deref().map(str::chars) {
+            Some(mut chars) => match (chars.next(), chars.next_back()) {
+                (Some(first), Some(last)) => format!("{}{}", first, last),
+                (Some(only), None) => format!("{}{}", only, only),
+                _ => "00".to_string(),
+            },
+            None => "00".to_string(),
+        };
+        let prefix = format!(
+            "t{}{}{:02}{:02}{}",
+            version,
+            sni,
+            cipher_suites.len().min(99),
+            extensions.len().min(99),
+            alpn
+        );
+
+        cipher_suites.sort_unstable();
+        let mut sorted_extensions: Vec<u16> = extensions
+            .into_iter()
+            .filter(|&e| e != EXT_SERVER_NAME && e != EXT_ALPN)
+            .collect();
+        sorted_extensions.sort_unstable();
+        let mut extension_part = hex_list(&sorted_extensions);
+        if !signature_algorithms.is_empty() {
+            extension_part.push('_');
+            extension_part.push_str(&hex_list(&signature_algorithms));
+        }
+
+        format!(
+            "{}_{}_{}",
+            prefix,
+            truncated_sha256(&hex_list(&cipher_suites)),
+            truncated_sha256(&extension_part)
+        )
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Four-digit hex values, comma-separated, as JA4 hashes them.
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{:04x}", v))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The first 12 hex digits of the SHA-256 of `input`, or zeros for an
+/// empty list.
+fn truncated_sha256(input: &str) -> String {
+    if input.is_empty() {
+        return "0".repeat(12);
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(input.as_bytes());
+    hex(&hasher.finish()[..6])
+}
+
 /// TLS version.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TlsVersion {
     /// TLS 1.2
     Tls12,
@@ -33,6 +306,16 @@ pub enum TlsVersion {
     Tls13,
 }
 
+impl TlsVersion {
+    /// Get the version's wire value.
+    pub fn wire(self) -> u16 {
+        match self {
+            TlsVersion::Tls12 => 0x0303,
+            TlsVersion::Tls13 => 0x0304,
+        }
+    }
+}
+
 /// Normalizes TLS fingerprint to match Tor Browser.
 pub struct TlsFingerprintNormalizer {
     config: TlsConfig,
@@ -97,6 +380,9 @@ impl TlsFingerprintNormalizer {
                 0x0101, // ffdhe3072
             ],
 
+            // Uncompressed points only
+            ec_point_formats: vec![0],
+
             // Signature algorithms
             signature_algorithms: vec![
                 0x0403, // ecdsa_secp256r1_sha256
@@ -126,23 +412,124 @@ impl TlsFingerprintNormalizer {
     /// Get the expected JA3 fingerprint hash.
     /// Used for testing/verification.
     pub fn expected_ja3_hash(&self) -> &'static str {
-        // This should match Tor Browser's JA3
         // JA3 = MD5(SSLVersion,Ciphers,Extensions,EllipticCurves,EllipticCurvePointFormats)
-        "e7d705a3286e19ea42f587b344ee6865"
+        EXPECTED_JA3_HASH
+    }
+
+    /// Check that the configuration still hashes to the expected JA3.
+    ///
+    /// # Panics
+    ///
+    /// If it does not: the configuration or the expected hash changed
+    /// without the other.
+    pub fn self_check(&self) {
+        assert_eq!(
+            self.config.ja3_hash(),
+            self.expected_ja3_hash(),
+            "TLS configuration drifted from the expected JA3 (now {})",
+            self.config.ja3()
+        );
     }
 
-    /// Verify that a ClientHello matches our expected fingerprint.
-    pub fn verify_client_hello(&self, client_hello: &[u8]) -> bool {
-        // Parse ClientHello and verify:
-        // 1. Cipher suite order matches
-        // 2. Extension order matches
-        // 3. Supported groups match
-        // 4. Signature algorithms match
+    /// Compare a ClientHello record with our expected fingerprint,
+    /// returning every difference; an empty list means it matches.
+    ///
+    /// Cipher suites, supported groups, point formats, signature
+    /// algorithms, supported versions and ALPN must match exactly.
+    /// Extensions must be in the configured order; SNI and pre_shared_key
+    /// may be absent.
+    pub fn verify_client_hello(&self, client_hello: &[u8]) -> Vec<FingerprintMismatch> {
+        let hello = match parse_client_hello(client_hello) {
+            Ok(hello) => hello,
+            Err(detail) => return vec![FingerprintMismatch::Malformed(detail)],
+        };
+        let config = &self.config;
+        let mut mismatches = Vec::new();
 
-        // This is a simplified check - real implementation would
-        // parse the TLS ClientHello structure
+        if hello.version != LEGACY_VERSION {
+            mismatches.push(FingerprintMismatch::Version {
+                expected: LEGACY_VERSION,
+                found: hello.version,
+            });
+        }
+        if hello.compression_methods != [0] {
+            mismatches.push(FingerprintMismatch::Compression(
+                hello.compression_methods.clone(),
+            ));
+        }
+
+        let optional = |extension: &u16| matches!(*extension, EXT_SERVER_NAME | EXT_PRE_SHARED_KEY);
+        for &extension in &hello.extensions {
+            if !config.extensions.contains(&extension) {
+                mismatches.push(FingerprintMismatch::UnexpectedExtension(extension));
+            }
+        }
+        for &extension in config.extensions.iter().filter(|e| !optional(e)) {
+            if !hello.extensions.contains(&extension) {
+                mismatches.push(FingerprintMismatch::MissingExtension(extension));
+            }
+        }
+        // Order is judged on the extensions both sides have
+        let shared: Vec<u16> = hello
+            .extensions
+            .iter()
+            .copied()
+            .filter(|e| config.extensions.contains(e))
+            .collect();
+        let configured: Vec<u16> = config
+            .extensions
+            .iter()
+            .copied()
+            .filter(|e| shared.contains(e))
+            .collect();
+        if shared != configured {
+            mismatches.push(FingerprintMismatch::ExtensionOrder {
+                expected: configured,
+                found: shared,
+            });
+        }
 
-        !client_hello.is_empty()
+        let mut compare = |field, expected: Vec<String>, found: Vec<String>| {
+            if expected != found {
+                mismatches.push(FingerprintMismatch::List {
+                    field,
+                    expected,
+                    found,
+                });
+            }
+        };
+        let hex = |values: &[u16]| values.iter().map(|v| format!("{:#06x}", v)).collect();
+        compare(
+            "cipher suites",
+            hex(&config.cipher_suites),
+            hex(&hello.cipher_suites),
+        );
+        compare(
+            "supported groups",
+            hex(&config.supported_groups),
+            hex(&hello.supported_groups),
+        );
+        compare(
+            "EC point formats",
+            config.ec_point_formats.iter().map(u8::to_string).collect(),
+            hello.ec_point_formats.iter().map(u8::to_string).collect(),
+        );
+        compare(
+            "signature algorithms",
+            hex(&config.signature_algorithms),
+            hex(&hello.signature_algorithms),
+        );
+        compare(
+            "supported versions",
+            hex(&config.offered_versions()),
+            hex(&hello.supported_versions),
+        );
+        compare(
+            "ALPN protocols",
+            config.alpn_protocols.clone(),
+            hello.alpn_protocols.clone(),
+        );
+        mismatches
     }
 }
 
@@ -162,10 +549,13 @@ pub struct Http2Fingerprint {
     pub window_update: u32,
     /// Header priority
     pub priority: Http2Priority,
+    /// PRIORITY frames sent after the preface, creating the idle streams
+    /// requests are grouped under
+    pub priority_frames: Vec<(u32, Http2Priority)>,
 }
 
 /// HTTP/2 priority settings.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Http2Priority {
     /// Stream dependency
     pub depends_on: u32,
@@ -193,6 +583,28 @@ impl Default for Http2Fingerprint {
                 weight: 41,
                 exclusive: false,
             },
+            // Leader, follower, unblocked, background, speculative and
+            // urgent-start groups
+            priority_frames: [
+                (3, 0, 200),
+                (5, 0, 100),
+                (7, 0, 0),
+                (9, 7, 0),
+                (11, 3, 0),
+                (13, 0, 240),
+            ]
+            .into_iter()
+            .map(|(stream, depends_on, weight)| {
+                (
+                    stream,
+                    Http2Priority {
+                        depends_on,
+                        weight,
+                        exclusive: false,
+                    },
+                )
+            })
+            .collect(),
         }
     }
 }
@@ -204,7 +616,7 @@ mod tests {
     #[test]
     fn test_normalizer_creation() {
         let normalizer = TlsFingerprintNormalizer::new();
-        let config = normalizer.create_config().unwrap();
+        let config = normalizer.create_config().expect("config");
 
         // Verify cipher suite order
         assert_eq!(config.cipher_suites[0], 0x1301); // TLS_AES_128_GCM_SHA256
@@ -214,7 +626,7 @@ mod tests {
     #[test]
     fn test_tls_version() {
         let normalizer = TlsFingerprintNormalizer::new();
-        let config = normalizer.create_config().unwrap();
+        let config = normalizer.create_config().expect("config");
 
         assert_eq!(config.min_version, TlsVersion::Tls12);
         assert_eq!(config.max_version, TlsVersion::Tls13);
@@ -223,8 +635,167 @@ mod tests {
     #[test]
     fn test_alpn() {
         let normalizer = TlsFingerprintNormalizer::new();
-        let config = normalizer.create_config().unwrap();
+        let config = normalizer.create_config().expect("config");
 
         assert_eq!(config.alpn_protocols, vec!["h2", "http/1.1"]);
     }
+
+    /// The JA3 README's example ClientHello: TLS 1.0, SNI, three curves,
+    /// uncompressed points. `grease` adds a GREASE cipher suite and
+    /// extension.
+    fn reference_hello(grease: bool) -> Vec<u8> {
+        let mut suites = vec![47u16, 53, 5, 10, 49161, 49162, 49171, 49172, 50, 56, 19, 4];
+        let mut extensions = vec![
+            0x00, 0x00, 0x00, 0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, b'e', b'x', b'a', b'm', b'p',
+            b'l', b'e', b'.', b'c', b'o', b'm', // server_name
+            0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x17, 0x00, 0x18, 0x00, 0x19, // groups
+            0x00, 0x0b, 0x00, 0x02, 0x01, 0x00, // ec_point_formats
+        ];
+        if grease {
+            suites.insert(0, 0x0a0a);
+            extensions.splice(0..0, [0x3a, 0x3a, 0x00, 0x00]);
+        }
+
+        let mut hello = vec![0x03, 0x01];
+        hello.extend_from_slice(&[0x42; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&((suites.len() * 2) as u16).to_be_bytes());
+        for suite in suites {
+            hello.extend_from_slice(&suite.to_be_bytes());
+        }
+        hello.extend_from_slice(&[1, 0]);
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
+
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend_from_slice(&(hello.len() as u16 + 4).to_be_bytes());
+        record.extend_from_slice(&[0x01, 0x00]);
+        record.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        record.extend_from_slice(&hello);
+        record
+    }
+
+    #[test]
+    fn test_ja3_reference_vector() {
+        let hello = parse_client_hello(&reference_hello(false)).expect("parses");
+        assert_eq!(
+            hello.ja3(),
+            "769,47-53-5-10-49161-49162-49171-49172-50-56-19-4,0-10-11,23-24-25,0"
+        );
+        assert_eq!(hello.ja3_hash(), "ada70206e40642a3e4461f35503241d5");
+        assert_eq!(hello.ja4(), "t10d120300_d94e65cdb899_33a13ba74d1c");
+
+        // GREASE values change nothing
+        let greased = parse_client_hello(&reference_hello(true)).expect("parses");
+        assert_eq!(greased.cipher_suites[0], 0x0a0a);
+        assert_eq!(greased.ja3_hash(), hello.ja3_hash());
+        assert_eq!(greased.ja4(), hello.ja4());
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+    }
+
+    #[test]
+    fn test_config_fingerprint_matches_wire() {
+        let normalizer = TlsFingerprintNormalizer::new();
+        normalizer.self_check();
+
+        let config = normalizer.create_config().expect("config");
+        let record = crate::client_hello::build_client_hello(
+            &config,
+            Some("example.com"),
+            &crate::client_hello::HelloRandom::generate(),
+        )
+        .expect("encodes");
+        let hello = parse_client_hello(&record).expect("parses");
+        assert_eq!(config.ja3(), hello.ja3());
+        assert_eq!(config.ja4(), hello.ja4());
+        assert!(config.ja3().starts_with("771,4865-4867-4866-"));
+        assert!(config.ja4().starts_with("t13d1514h2_"));
+
+        // Any change to the configuration shows up in the hash
+        let mut reordered = config.clone();
+        reordered.cipher_suites.swap(1, 2);
+        assert_ne!(reordered.ja3_hash(), normalizer.expected_ja3_hash());
+    }
+
+    /// Read the ClientHello fixture from `fixtures/tls`.
+    fn captured_hello() -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/tls/tor-browser-13-client-hello.hex");
+        let hex: String = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+            .split_whitespace()
+            .collect();
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("hex"))
+            .collect()
+    }
+
+    #[test]
+    fn test_captured_hello_matches() {
+        let normalizer = TlsFingerprintNormalizer::new();
+        let record = captured_hello();
+        assert_eq!(normalizer.verify_client_hello(&record), []);
+
+        let hello = parse_client_hello(&record).expect("parses");
+        assert_eq!(hello.record_version, 0x0301);
+        assert_eq!(hello.random, [0x5a; 32]);
+        assert_eq!(hello.session_id, [0xa5; 32]);
+        assert_eq!(hello.compression_methods, [0]);
+        assert_eq!(hello.ja3_hash(), normalizer.expected_ja3_hash());
+    }
+
+    #[test]
+    fn test_mismatches_are_itemized() {
+        let normalizer = TlsFingerprintNormalizer::new();
+        let record = captured_hello();
+        // Cipher suites start after the headers, random and session id
+        const SUITES: usize = 5 + 4 + 2 + 32 + 1 + 32 + 2;
+
+        let mut swapped = record.clone();
+        swapped[SUITES + 2..SUITES + 6].rotate_left(2);
+        let mismatches = normalizer.verify_client_hello(&swapped);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(
+            mismatches[0].to_string(),
+            "cipher suites [0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, \
+             0xcca8, 0xc013, 0xc014, 0x009c, 0x009d, 0x002f, 0x0035], expected [0x1301, \
+             0x1303, 0x1302, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013, 0xc014, \
+             0x009c, 0x009d, 0x002f, 0x0035]"
+        );
+
+        let mut compressed = record.clone();
+        compressed[SUITES + 30 + 1] = 1;
+        assert_eq!(
+            normalizer.verify_client_hello(&compressed),
+            [FingerprintMismatch::Compression(vec![1])]
+        );
+
+        assert!(matches!(
+            normalizer.verify_client_hello(&record[..100]).as_slice(),
+            [FingerprintMismatch::Malformed(_)]
+        ));
+
+        // Judged against a configuration that differs from what was sent
+        let mut config = normalizer.config.clone();
+        config.extensions.swap(1, 2);
+        config.extensions.retain(|&e| e != 0x001b);
+        config.extensions.push(0x002d);
+        let other = TlsFingerprintNormalizer { config };
+        let mismatches = other.verify_client_hello(&record);
+        assert_eq!(
+            mismatches[..2],
+            [
+                FingerprintMismatch::UnexpectedExtension(0x001b),
+                FingerprintMismatch::MissingExtension(0x002d),
+            ]
+        );
+        let FingerprintMismatch::ExtensionOrder { expected, found } = &mismatches[2] else {
+            panic!("expected an order mismatch, got {:?}", mismatches[2]);
+        };
+        assert_eq!(expected[..3], [0x0000, 0xff01, 0x0017]);
+        assert_eq!(found[..3], [0x0000, 0x0017, 0xff01]);
+        assert_eq!(mismatches.len(), 3);
+    }
 }
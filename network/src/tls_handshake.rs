@@ -0,0 +1,533 @@
+//! TLS handshake over a circuit's SOCKS5 stream.
+//!
+//! The handshake runs on rustls with the ring provider. Cipher suites,
+//! supported groups, versions and ALPN protocols are offered in the
+//! `TlsConfig` order, limited to what rustls implements; extension order
+//! is rustls's own, so the wire ClientHello is not the byte-exact one
+//! `client_hello` builds for fingerprint checks.
+//!
+//! The server's certificate is always validated against the Mozilla roots
+//! in `webpki-roots`; there is no way to skip it. SNI is sent for clearnet
+//! hosts and never for onion services or IP literals. Each handshake gets
+//! a fresh configuration, so session tickets never link two circuits.
+//! Alerts and certificate errors are reported with the host so the UI can
+//! show `ErrorDialog::certificate_error`.
+
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ProtocolVersion, RootCertStore, SupportedProtocolVersion};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::client_hello::server_name_for;
+use crate::tls_fingerprint::{TlsConfig, TlsVersion};
+use crate::NetworkError;
+
+/// Why a TLS handshake failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TlsFailureReason {
+    /// The server sent a fatal alert with this description code
+    Alert(u8),
+    /// The certificate could not be verified
+    Certificate(String),
+    /// The server broke the handshake protocol
+    Protocol(String),
+    /// Nothing `TlsConfig` offers can be negotiated
+    Unsupported(String),
+}
+
+/// A failed TLS handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFailure {
+    /// Host the handshake was with
+    pub host: String,
+    /// What went wrong
+    pub reason: TlsFailureReason,
+}
+
+impl TlsFailure {
+    /// Create a failure for `host`.
+    pub fn new(host: &str, reason: TlsFailureReason) -> Self {
+        Self {
+            host: host.to_string(),
+            reason,
+        }
+    }
+
+    /// Whether the user should see the certificate error dialog.
+    pub fn is_certificate_error(&self) -> bool {
+        match self.reason {
+            TlsFailureReason::Certificate(_) => true,
+            // bad_certificate through certificate_unknown, unknown_ca
+            TlsFailureReason::Alert(code) => matches!(code, 42..=46 | 48),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for TlsFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            TlsFailureReason::Alert(code) => {
+                write!(
+                    f,
+                    "{} sent alert {} ({})",
+                    self.host,
+                    code,
+                    alert_name(*code)
+                )
+            }
+            TlsFailureReason::Certificate(detail) => {
+                write!(f, "certificate for {} rejected: {}", self.host, detail)
+            }
+            TlsFailureReason::Protocol(detail) => write!(f, "{}: {}", self.host, detail),
+            TlsFailureReason::Unsupported(detail) => {
+                write!(f, "cannot negotiate with {}: {}", self.host, detail)
+            }
+        }
+    }
+}
+
+/// Get the RFC 8446 name of an alert description.
+fn alert_name(code: u8) -> &'static str {
+    match code {
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        43 => "unsupported_certificate",
+        44 => "certificate_revoked",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        47 => "illegal_parameter",
+        48 => "unknown_ca",
+        50 => "decode_error",
+        70 => "protocol_version",
+        80 => "internal_error",
+        112 => "unrecognized_name",
+        120 => "no_application_protocol",
+        _ => "unknown",
+    }
+}
+
+/// What the server chose.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    /// Negotiated version
+    pub version: TlsVersion,
+    /// Selected cipher suite
+    pub cipher_suite: u16,
+    /// Protocol selected by ALPN, if any
+    pub alpn: Option<String>,
+}
+
+/// Run the TLS handshake with `host` on `stream`, returning the encrypted
+/// stream once the server's certificate has been verified.
+pub async fn handshake<S>(
+    stream: S,
+    host: &str,
+    config: &TlsConfig,
+) -> Result<(TlsStream<S>, ServerHello), NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    let roots = ROOTS.get_or_init(|| {
+        Arc::new(RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        })
+    });
+    handshake_with_roots(stream, host, config, Arc::clone(roots)).await
+}
+
+/// Run the TLS handshake, trusting only `roots` (internal).
+async fn handshake_with_roots<S>(
+    stream: S,
+    host: &str,
+    config: &TlsConfig,
+    roots: Arc<RootCertStore>,
+) -> Result<(TlsStream<S>, ServerHello), NetworkError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let fail = |reason| NetworkError::TlsError(TlsFailure::new(host, reason));
+
+    let client = client_config(config, roots, server_name_for(host).is_some()).map_err(fail)?;
+    let name = host.trim_start_matches('[').trim_end_matches(']');
+    let server_name = ServerName::try_from(name.to_string()).map_err(|e| {
+        fail(TlsFailureReason::Protocol(format!(
+            "invalid server name: {}",
+            e
+        )))
+    })?;
+
+    let stream = TlsConnector::from(Arc::new(client))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| fail(failure_reason(e)))?;
+
+    let (_, connection) = stream.get_ref();
+    let server_hello = ServerHello {
+        version: match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => TlsVersion::Tls13,
+            _ => TlsVersion::Tls12,
+        },
+        cipher_suite: connection
+            .negotiated_cipher_suite()
+            .map_or(0, |suite| u16::from(suite.suite())),
+        alpn: connection
+            .alpn_protocol()
+            .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+    };
+    log::debug!(
+        "{} selected {:?} with cipher suite {:#06x}",
+        host,
+        server_hello.version,
+        server_hello.cipher_suite
+    );
+    Ok((stream, server_hello))
+}
+
+/// Build a rustls configuration offering what `config` offers, in its
+/// order, and sending SNI only if `sni`.
+fn client_config(
+    config: &TlsConfig,
+    roots: Arc<RootCertStore>,
+    sni: bool,
+) -> Result<ClientConfig, TlsFailureReason> {
+    let ring = ring::default_provider();
+    let cipher_suites = config
+        .cipher_suites
+        .iter()
+        .filter_map(|&id| {
+            ring.cipher_suites
+                .iter()
+                .find(|suite| u16::from(suite.suite()) == id)
+                .copied()
+        })
+        .collect();
+    let kx_groups = config
+        .supported_groups
+        .iter()
+        .filter_map(|&id| {
+            ring.kx_groups
+                .iter()
+                .find(|group| u16::from(group.name()) == id)
+                .copied()
+        })
+        .collect();
+    let versions: Vec<&'static SupportedProtocolVersion> = [
+        (TlsVersion::Tls13, &rustls::version::TLS13),
+        (TlsVersion::Tls12, &rustls::version::TLS12),
+    ]
+    .into_iter()
+    .filter(|(version, _)| (config.min_version..=config.max_version).contains(version))
+    .map(|(_, supported)| supported)
+    .collect();
+
+    let provider = CryptoProvider {
+        cipher_suites,
+        kx_groups,
+        ..ring
+    };
+    let mut client = ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&versions)
+        .map_err(|e| TlsFailureReason::Unsupported(e.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client.alpn_protocols = config
+        .alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    client.enable_sni = sni;
+    Ok(client)
+}
+
+/// Classify a failed handshake.
+fn failure_reason(error: std::io::Error) -> TlsFailureReason {
+    match error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    {
+        Some(rustls::Error::AlertReceived(alert)) => TlsFailureReason::Alert(u8::from(*alert)),
+        Some(
+            e @ (rustls::Error::InvalidCertificate(_) | rustls::Error::NoCertificatesPresented),
+        ) => TlsFailureReason::Certificate(e.to_string()),
+        Some(e) => TlsFailureReason::Protocol(e.to_string()),
+        None => TlsFailureReason::Protocol(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_hello::{parse_client_hello, ClientHelloSummary, CONTENT_HANDSHAKE};
+    use crate::tls_fingerprint::TlsFingerprintNormalizer;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsAcceptor;
+
+    /// TLS record content type for alerts.
+    const CONTENT_ALERT: u8 = 0x15;
+    /// Handshake message type of a ServerHello.
+    const HANDSHAKE_SERVER_HELLO: u8 = 0x02;
+    /// Renegotiation SCSV, which rustls adds to every TLS 1.2 offer.
+    const RENEGOTIATION_SCSV: u16 = 0x00ff;
+
+    fn config() -> TlsConfig {
+        TlsFingerprintNormalizer::new()
+            .create_config()
+            .expect("tls config")
+    }
+
+    /// A ServerHello record selecting `cipher_suite` over TLS 1.3.
+    fn server_hello(cipher_suite: u16) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0x11; 32]);
+        body.push(0);
+        body.extend_from_slice(&cipher_suite.to_be_bytes());
+        body.push(0);
+        body.extend_from_slice(&[0x00, 0x06, 0x00, 0x2b, 0x00, 0x02, 0x03, 0x04]);
+
+        let mut handshake = vec![HANDSHAKE_SERVER_HELLO, 0, 0, body.len() as u8];
+        handshake.extend_from_slice(&body);
+        let mut record = vec![CONTENT_HANDSHAKE, 0x03, 0x03, 0, handshake.len() as u8];
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    /// A fatal alert record with description `code`.
+    fn alert(code: u8) -> Vec<u8> {
+        vec![CONTENT_ALERT, 0x03, 0x03, 0, 2, 2, code]
+    }
+
+    /// Read one record, returning its content type and payload.
+    async fn read_record(stream: &mut TcpStream) -> std::io::Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await?;
+        let mut payload = vec![0u8; u16::from_be_bytes([header[3], header[4]]) as usize];
+        stream.read_exact(&mut payload).await?;
+        Ok((header[0], payload))
+    }
+
+    /// Run a local server that captures the ClientHello and answers with
+    /// `reply`, then handshake with it as `host`.
+    async fn against_server(
+        host: &str,
+        reply: Vec<u8>,
+    ) -> (ClientHelloSummary, Result<ServerHello, NetworkError>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.expect("accept");
+            let (_, payload) = read_record(&mut socket).await.expect("client hello");
+            socket.write_all(&reply).await.expect("reply");
+
+            let mut record = vec![CONTENT_HANDSHAKE, 0x03, 0x01];
+            record.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            record.extend_from_slice(&payload);
+            parse_client_hello(&record).expect("parses")
+        });
+
+        let stream = TcpStream::connect(addr).await.expect("connect");
+        let result = handshake(stream, host, &config()).await;
+        (
+            server.await.expect("server"),
+            result.map(|(_, server_hello)| server_hello),
+        )
+    }
+
+    /// A self-signed certificate for `host`, which expired in 2001 if
+    /// `expired`.
+    fn certificate(host: &str, expired: bool) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+        let mut params = rcgen::CertificateParams::new(vec![host.to_string()]).expect("params");
+        if expired {
+            params.not_before = rcgen::date_time_ymd(2000, 1, 1);
+            params.not_after = rcgen::date_time_ymd(2001, 1, 1);
+        }
+        let key = rcgen::KeyPair::generate().expect("key");
+        let cert = params.self_signed(&key).expect("certificate");
+        (
+            cert.der().clone(),
+            PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+        )
+    }
+
+    fn trusting(cert: &CertificateDer<'static>) -> Arc<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.clone()).expect("trust anchor");
+        Arc::new(roots)
+    }
+
+    /// Serve TLS with `cert` once, answering "ping" with "pong".
+    async fn tls_server(cert: CertificateDer<'static>, key: PrivateKeyDer<'static>) -> SocketAddr {
+        let mut config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .expect("versions")
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+                .expect("server config");
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.expect("accept");
+            if let Ok(mut stream) = acceptor.accept(socket).await {
+                let mut ping = [0u8; 4];
+                if stream.read_exact(&mut ping).await.is_ok() {
+                    let _ = stream.write_all(b"pong").await;
+                    let _ = stream.shutdown().await;
+                }
+            }
+        });
+        addr
+    }
+
+    fn tls_failure<T: fmt::Debug>(result: Result<T, NetworkError>) -> TlsFailure {
+        match result {
+            Err(NetworkError::TlsError(failure)) => failure,
+            other => panic!("expected a TLS error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hello_follows_config_order() {
+        let config = config();
+        let (hello, result) = against_server("example.com", alert(40)).await;
+
+        // The AEAD suites rustls implements, in the configured order
+        let offered: Vec<u16> = hello
+            .cipher_suites
+            .iter()
+            .copied()
+            .filter(|&suite| suite != RENEGOTIATION_SCSV)
+            .collect();
+        assert_eq!(offered, config.cipher_suites[..9]);
+        assert_eq!(hello.supported_groups, config.supported_groups[..3]);
+        assert_eq!(hello.alpn_protocols, ["h2", "http/1.1"]);
+        assert_eq!(hello.server_name.as_deref(), Some("example.com"));
+
+        let failure = tls_failure(result);
+        assert_eq!(failure.host, "example.com");
+        assert_eq!(failure.reason, TlsFailureReason::Alert(40));
+    }
+
+    #[tokio::test]
+    async fn test_onion_hello_has_no_sni() {
+        let host = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        let (hello, _) = against_server(host, alert(40)).await;
+        assert_eq!(hello.server_name, None);
+        assert!(!hello.extensions.contains(&0x0000));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_reported_with_host() {
+        let failure = tls_failure(against_server("expired.example", alert(45)).await.1);
+        assert!(failure.is_certificate_error());
+        assert_eq!(
+            NetworkError::TlsError(failure).to_string(),
+            "TLS error: expired.example sent alert 45 (certificate_expired)"
+        );
+
+        // A suite we never offered
+        let failure = tls_failure(against_server("odd.example", server_hello(0x0005)).await.1);
+        assert!(!failure.is_certificate_error());
+        assert!(matches!(failure.reason, TlsFailureReason::Protocol(_)));
+    }
+
+    #[tokio::test]
+    async fn test_verified_handshake_carries_data() {
+        let (cert, key) = certificate("example.com", false);
+        let roots = trusting(&cert);
+        let addr = tls_server(cert, key).await;
+
+        let socket = TcpStream::connect(addr).await.expect("connect");
+        let (mut stream, server_hello) =
+            handshake_with_roots(socket, "example.com", &config(), roots)
+                .await
+                .expect("handshake");
+        assert_eq!(
+            server_hello,
+            ServerHello {
+                version: TlsVersion::Tls13,
+                cipher_suite: 0x1301,
+                alpn: Some("http/1.1".to_string()),
+            }
+        );
+
+        stream.write_all(b"ping").await.expect("write");
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.expect("read");
+        assert_eq!(reply, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_tls12_follows_suite_order() {
+        let (cert, key) = certificate("example.com", false);
+        let roots = trusting(&cert);
+        let addr = tls_server(cert, key).await;
+
+        let mut config = config();
+        config.max_version = TlsVersion::Tls12;
+        let socket = TcpStream::connect(addr).await.expect("connect");
+        let (_, server_hello) = handshake_with_roots(socket, "example.com", &config, roots)
+            .await
+            .expect("handshake");
+        assert_eq!(server_hello.version, TlsVersion::Tls12);
+        // TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256, first for an ECDSA key
+        assert_eq!(server_hello.cipher_suite, 0xc02b);
+    }
+
+    #[tokio::test]
+    async fn test_certificates_validated_strictly() {
+        let certificate_failure = |result: Result<_, NetworkError>| {
+            let failure = tls_failure(result);
+            assert!(failure.is_certificate_error(), "{}", failure);
+            failure
+        };
+
+        // Self-signed, so not under any public root
+        let (cert, key) = certificate("example.com", false);
+        let addr = tls_server(cert, key).await;
+        let socket = TcpStream::connect(addr).await.expect("connect");
+        let failure = certificate_failure(handshake(socket, "example.com", &config()).await);
+        assert_eq!(failure.host, "example.com");
+
+        // Trusted, but for another name
+        let (cert, key) = certificate("example.com", false);
+        let roots = trusting(&cert);
+        let addr = tls_server(cert, key).await;
+        let socket = TcpStream::connect(addr).await.expect("connect");
+        certificate_failure(handshake_with_roots(socket, "other.example", &config(), roots).await);
+
+        // Trusted, but expired
+        let (cert, key) = certificate("example.com", true);
+        let roots = trusting(&cert);
+        let addr = tls_server(cert, key).await;
+        let socket = TcpStream::connect(addr).await.expect("connect");
+        let failure = certificate_failure(
+            handshake_with_roots(socket, "example.com", &config(), roots).await,
+        );
+        assert!(failure.to_string().contains("expired"), "{}", failure);
+    }
+
+    #[tokio::test]
+    async fn test_nothing_negotiable() {
+        let mut config = config();
+        config.cipher_suites = vec![0x002f];
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let socket = TcpStream::connect(listener.local_addr().expect("addr"))
+            .await
+            .expect("connect");
+        let failure = tls_failure(handshake(socket, "example.com", &config).await);
+        assert!(matches!(failure.reason, TlsFailureReason::Unsupported(_)));
+    }
+}
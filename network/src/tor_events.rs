@@ -0,0 +1,374 @@
+//! Asynchronous control-port events that explain failures.
+//!
+//! Tor reports two conditions that otherwise surface as mysterious
+//! request failures: loss of network connectivity (NETWORK_LIVENESS)
+//! and a badly skewed system clock (STATUS_GENERAL CLOCK_SKEW), which
+//! breaks onion descriptor validation. We subscribe to both, track them
+//! in `TorHealth`, and use them to pick a status and an error-page hint.
+//!
+//! Connecting to an onion service is also reported step by step
+//! (HS_DESC, CIRC with an HS_STATE, STREAM); `OnionConnectTracker` turns
+//! those into status-bar progress.
+
+/// Command subscribing to the events handled here, and to STATUS_CLIENT
+/// for bootstrap progress.
+pub const SETEVENTS_COMMAND: &str =
+    "SETEVENTS NETWORK_LIVENESS STATUS_GENERAL STATUS_CLIENT HS_DESC CIRC STREAM\r\n";
+
+/// Clock skew below this is not worth warning about (seconds).
+const CLOCK_SKEW_THRESHOLD_SECS: i64 = 5 * 60;
+
+/// A parsed asynchronous control-port event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorEvent {
+    /// 650 NETWORK_LIVENESS UP|DOWN
+    NetworkLiveness(bool),
+    /// 650 STATUS_GENERAL WARN CLOCK_SKEW SKEW=<seconds>
+    ///
+    /// Negative when our clock is behind the source.
+    ClockSkew(i64),
+    /// 650 HS_DESC <action> <address> ...
+    HsDescriptor {
+        /// What happened to the descriptor
+        action: HsDescAction,
+        /// Onion address without the ".onion" suffix
+        address: String,
+    },
+    /// 650 CIRC ... HS_STATE=<state> REND_QUERY=<address>
+    ///
+    /// Only introduction states and the rendezvous states after the
+    /// introduction was sent are reported.
+    HsCircuit {
+        /// True for the rendezvous circuit, false for introduction
+        rendezvous: bool,
+        /// Onion address without the ".onion" suffix
+        address: String,
+    },
+    /// 650 STREAM <id> <status> <circuit> <target>
+    Stream {
+        /// Stream status
+        status: StreamStatus,
+        /// Target host, without the port
+        host: String,
+    },
+}
+
+/// Onion descriptor fetch outcome from an HS_DESC event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HsDescAction {
+    /// Asked an HSDir for the descriptor
+    Requested,
+    /// Got a valid descriptor
+    Received,
+    /// The fetch failed
+    Failed,
+}
+
+/// Stream status from a STREAM event (the ones we act on).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// Stream created, waiting for a circuit
+    New,
+    /// BEGIN sent, waiting for the far end to connect
+    SentConnect,
+    /// Far end connected
+    Succeeded,
+    /// Stream failed
+    Failed,
+    /// Stream closed
+    Closed,
+}
+
+impl TorEvent {
+    /// Parse a single "650" event line. Unrelated lines give `None`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.trim_end().split(' ');
+        if words.next()? != "650" {
+            return None;
+        }
+
+        match words.next()? {
+            "NETWORK_LIVENESS" => match words.next()? {
+                "UP" => Some(TorEvent::NetworkLiveness(true)),
+                "DOWN" => Some(TorEvent::NetworkLiveness(false)),
+                _ => None,
+            },
+            "STATUS_GENERAL" => {
+                let _severity = words.next()?;
+                if words.next()? != "CLOCK_SKEW" {
+                    return None;
+                }
+                words
+                    .find_map(|arg| arg.strip_prefix("SKEW="))
+                    .and_then(|skew| skew.parse().ok())
+                    .map(TorEvent::ClockSkew)
+            }
+            "HS_DESC" => {
+                let action = match words.next()? {
+                    "REQUESTED" => HsDescAction::Requested,
+                    "RECEIVED" => HsDescAction::Received,
+                    "FAILED" => HsDescAction::Failed,
+                    _ => return None,
+                };
+                Some(TorEvent::HsDescriptor {
+                    action,
+                    address: words.next()?.to_ascii_lowercase(),
+                })
+            }
+            "CIRC" => {
+                let mut rendezvous = None;
+                let mut address = None;
+                for arg in words {
+                    if let Some(state) = arg.strip_prefix("HS_STATE=") {
+                        rendezvous = match state {
+                            "HSCR_ESTABLISHED_WAITING" | "HSCR_JOINED" => Some(true),
+                            _ if state.starts_with("HSCI_") => Some(false),
+                            // The rendezvous point is set up before the intro
+                            _ => None,
+                        };
+                    } else if let Some(query) = arg.strip_prefix("REND_QUERY=") {
+                        address = Some(query.to_ascii_lowercase());
+                    }
+                }
+                Some(TorEvent::HsCircuit {
+                    rendezvous: rendezvous?,
+                    address: address?,
+                })
+            }
+            "STREAM" => {
+                let _id = words.next()?;
+                let status = match words.next()? {
+                    "NEW" => StreamStatus::New,
+                    "SENTCONNECT" => StreamStatus::SentConnect,
+                    "SUCCEEDED" => StreamStatus::Succeeded,
+                    "FAILED" => StreamStatus::Failed,
+                    "CLOSED" => StreamStatus::Closed,
+                    _ => return None,
+                };
+                let _circuit = words.next()?;
+                let target = words.next()?;
+                let host = target.rsplit_once(':').map_or(target, |(host, _)| host);
+                Some(TorEvent::Stream {
+                    status,
+                    host: host.to_ascii_lowercase(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Health as derived from events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TorHealthStatus {
+    /// Nothing wrong reported
+    Healthy,
+    /// Connected but impaired, with a short reason for the status bar
+    Degraded(String),
+}
+
+/// Hint shown on the error page when a request fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureHint {
+    /// Tor reported the network as down
+    Offline,
+    /// Tor reported a clock skew of about this many minutes
+    ClockSkew {
+        /// Absolute skew, rounded to minutes
+        minutes: i64,
+    },
+}
+
+impl FailureHint {
+    /// Get the user-facing hint text.
+    pub fn message(&self) -> String {
+        match self {
+            FailureHint::Offline => "Your network connection appears to be offline.".to_string(),
+            FailureHint::ClockSkew { minutes } => format!(
+                "Your system clock appears to be wrong by ~{} minutes. Onion services will fail.",
+                minutes
+            ),
+        }
+    }
+}
+
+/// Tracks liveness and clock skew from control-port events.
+#[derive(Debug, Clone, Default)]
+pub struct TorHealth {
+    /// Last NETWORK_LIVENESS was DOWN
+    network_down: bool,
+    /// Last significant clock skew, in seconds
+    clock_skew: Option<i64>,
+}
+
+impl TorHealth {
+    /// Create a tracker with nothing reported.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply an event. Returns the new status if it changed.
+    pub fn apply(&mut self, event: &TorEvent) -> Option<TorHealthStatus> {
+        let before = self.status();
+
+        match *event {
+            TorEvent::NetworkLiveness(up) => self.network_down = !up,
+            TorEvent::ClockSkew(skew) => {
+                self.clock_skew = (skew.abs() >= CLOCK_SKEW_THRESHOLD_SECS).then_some(skew);
+            }
+            TorEvent::HsDescriptor { .. }
+            | TorEvent::HsCircuit { .. }
+            | TorEvent::Stream { .. } => {}
+        }
+
+        let after = self.status();
+        (after != before).then_some(after)
+    }
+
+    /// Get the current status.
+    pub fn status(&self) -> TorHealthStatus {
+        if self.network_down {
+            TorHealthStatus::Degraded("Network offline".to_string())
+        } else if self.clock_skew.is_some() {
+            TorHealthStatus::Degraded("System clock skewed".to_string())
+        } else {
+            TorHealthStatus::Healthy
+        }
+    }
+
+    /// Pick the hint for a failed request. Offline takes precedence.
+    pub fn failure_hint(&self) -> Option<FailureHint> {
+        if self.network_down {
+            return Some(FailureHint::Offline);
+        }
+        self.clock_skew.map(|skew| FailureHint::ClockSkew {
+            minutes: (skew.abs() + 30) / 60,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(health: &mut TorHealth, lines: &[&str]) -> Vec<TorHealthStatus> {
+        lines
+            .iter()
+            .filter_map(|line| TorEvent::parse(line))
+            .filter_map(|event| health.apply(&event))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_events() {
+        assert_eq!(
+            TorEvent::parse("650 NETWORK_LIVENESS DOWN\r\n"),
+            Some(TorEvent::NetworkLiveness(false))
+        );
+        assert_eq!(
+            TorEvent::parse("650 STATUS_GENERAL WARN CLOCK_SKEW SKEW=-3600 SOURCE=CONSENSUS"),
+            Some(TorEvent::ClockSkew(-3600))
+        );
+        assert_eq!(
+            TorEvent::parse("650 STATUS_GENERAL NOTICE BUG REASON=x"),
+            None
+        );
+        assert_eq!(TorEvent::parse("250 OK"), None);
+    }
+
+    #[test]
+    fn test_parse_onion_events() {
+        assert_eq!(
+            TorEvent::parse("650 HS_DESC RECEIVED ABCXYZ NO_AUTH $AAAA~relay DESCID"),
+            Some(TorEvent::HsDescriptor {
+                action: HsDescAction::Received,
+                address: "abcxyz".to_string(),
+            })
+        );
+        assert_eq!(
+            TorEvent::parse(
+                "650 CIRC 7 EXTENDED $A~a,$B~b BUILD_FLAGS=IS_INTERNAL,NEED_CAPACITY \
+                 PURPOSE=HS_CLIENT_REND HS_STATE=HSCR_JOINED REND_QUERY=abcxyz"
+            ),
+            Some(TorEvent::HsCircuit {
+                rendezvous: true,
+                address: "abcxyz".to_string(),
+            })
+        );
+        assert_eq!(
+            TorEvent::parse("650 STREAM 12 SENTCONNECT 7 abcxyz.onion:443"),
+            Some(TorEvent::Stream {
+                status: StreamStatus::SentConnect,
+                host: "abcxyz.onion".to_string(),
+            })
+        );
+
+        // Ordinary circuits and uninteresting states are not onion progress
+        assert_eq!(
+            TorEvent::parse("650 CIRC 3 BUILT $A~a PURPOSE=GENERAL"),
+            None
+        );
+        assert_eq!(
+            TorEvent::parse("650 CIRC 7 BUILT $A~a HS_STATE=HSCR_ESTABLISHED_IDLE REND_QUERY=x"),
+            None
+        );
+        assert_eq!(TorEvent::parse("650 STREAM 12 REMAP 7 1.2.3.4:443"), None);
+        assert_eq!(
+            TorEvent::parse("650 HS_DESC UPLOAD abcxyz UNKNOWN $A"),
+            None
+        );
+
+        // None of them touch health
+        let mut health = TorHealth::new();
+        assert!(feed(&mut health, &["650 STREAM 12 FAILED 0 abcxyz.onion:443"]).is_empty());
+    }
+
+    #[test]
+    fn test_liveness_transitions() {
+        let mut health = TorHealth::new();
+        let statuses = feed(
+            &mut health,
+            &[
+                "650 NETWORK_LIVENESS DOWN",
+                "650 NETWORK_LIVENESS DOWN",
+                "650 NETWORK_LIVENESS UP",
+            ],
+        );
+
+        assert_eq!(
+            statuses,
+            vec![
+                TorHealthStatus::Degraded("Network offline".to_string()),
+                TorHealthStatus::Healthy,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hint_selection() {
+        let mut health = TorHealth::new();
+        assert_eq!(health.failure_hint(), None);
+
+        // Small skew is ignored
+        feed(&mut health, &["650 STATUS_GENERAL WARN CLOCK_SKEW SKEW=60"]);
+        assert_eq!(health.failure_hint(), None);
+
+        feed(
+            &mut health,
+            &["650 STATUS_GENERAL WARN CLOCK_SKEW SKEW=-5400 SOURCE=OR:1.2.3.4:443"],
+        );
+        let hint = health.failure_hint().expect("skew hint");
+        assert_eq!(hint, FailureHint::ClockSkew { minutes: 90 });
+        assert!(hint.message().contains("~90 minutes"));
+
+        // Offline wins while the network is down
+        feed(&mut health, &["650 NETWORK_LIVENESS DOWN"]);
+        assert_eq!(health.failure_hint(), Some(FailureHint::Offline));
+
+        feed(&mut health, &["650 NETWORK_LIVENESS UP"]);
+        assert_eq!(
+            health.failure_hint(),
+            Some(FailureHint::ClockSkew { minutes: 90 })
+        );
+    }
+}
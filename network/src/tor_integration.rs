@@ -2,112 +2,703 @@
 //!
 //! This module handles communication with an embedded Tor daemon.
 //! It provides circuit management and SOCKS5 proxy functionality.
+//!
+//! Everything goes through one control-port connection: authentication,
+//! the event subscription, bootstrap progress, and NEWNYM for each new
+//! circuit. Events that arrive while a command waits for its reply are
+//! applied to the health state once the command is done.
+//!
+//! Tor silently ignores a NEWNYM sent within `NEWNYM_INTERVAL` of the
+//! last one. Inside that window no NEWNYM is sent; the new circuit is
+//! isolated by its SOCKS credentials alone, and the suppression is
+//! counted for the status display.
+//!
+//! Between commands, a supervisor task (`spawn_supervisor`) reads the
+//! events that arrive and notices when Tor closes the connection. It then
+//! reconnects with exponential backoff; until it is back, `is_connected`
+//! is false and new circuits fail at once.
+//!
+//! Where the platform has Unix sockets, the embedded daemon listens on
+//! sockets inside its RAM data directory instead of TCP ports, so other
+//! local users cannot reach it and a system Tor Browser on 9150/9151
+//! does not get in the way.
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use forloop_config::{system_clock, Clock, Port, StatePaths};
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
+use tokio::task::AbortHandle;
+
+use crate::bridges::BridgeLine;
+use crate::control::ControlChannel;
+use crate::control_protocol::{
+    circuit_path, newest_built_circuit, open_authenticated, relay_address, BootstrapProgress,
+    ControlConnection, ControlStream,
+};
+use crate::digest::Sha256;
+use crate::geoip::{GeoIp, UNKNOWN_COUNTRY};
+use crate::snowflake::snowflake_bridges;
+use crate::socks::SocksEndpoint;
+use crate::tasks::{TaskRegistry, TaskScope};
+use crate::tor_events::{FailureHint, TorEvent, TorHealth, TorHealthStatus, SETEVENTS_COMMAND};
+use crate::tor_process::{TorProcess, TOR_BINARY, TOR_STARTUP_DEADLINE};
+use crate::transports::{
+    is_executable, not_found, used_transports, PluggableTransport, TransportManager,
+};
+use crate::{CircuitInfo, NetworkError, TorState};
+
+/// How long bootstrap may take before `TorController::new` gives up.
+const BOOTSTRAP_DEADLINE: Duration = Duration::from_secs(60);
+
+/// Tor ignores a NEWNYM sent sooner than this after the previous one.
+pub const NEWNYM_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long `TorController::new_identity` and `new_circuit` wait for a
+/// circuit built after their NEWNYM.
+pub const IDENTITY_DEADLINE: Duration = Duration::from_secs(30);
+
+/// How often they look for that circuit.
+const IDENTITY_POLL: Duration = Duration::from_millis(500);
 
-use crate::{CircuitInfo, NetworkError};
+/// How often the supervisor checks the idle control connection.
+const CONTROL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait before the first reconnect attempt; doubled after each failure.
+pub const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest wait between reconnect attempts.
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Show `GUARD_LABEL` instead of the guard's country in `CircuitInfo`.
+///
+/// The guard stays the same for months, so its country shown on every
+/// circuit says more about the user than any one exit does.
+pub const REDACT_GUARD_COUNTRY: bool = true;
+
+/// What `CircuitInfo::entry_country` says when the guard is redacted.
+pub const GUARD_LABEL: &str = "guard";
 
 /// Controller for the embedded Tor daemon.
 pub struct TorController {
-    socks_port: u16,
-    control_port: u16,
+    socks: SocksEndpoint,
+    control_port: Port,
+    control_socket: Option<PathBuf>,
     connected: AtomicBool,
-    control_connection: Mutex<Option<TcpStream>>,
+    reconnectable: bool,
+    torn_down: AtomicBool,
+    control_connection: Mutex<Option<ControlConnection>>,
+    control: std::sync::Mutex<ControlChannel>,
+    data_dir: PathBuf,
+    process: std::sync::Mutex<Option<TorProcess>>,
+    health: std::sync::Mutex<TorHealth>,
+    clock: Arc<dyn Clock>,
+    last_newnym: std::sync::Mutex<Option<Instant>>,
+    newnym_suppressed: AtomicU64,
+    bootstrap: watch::Sender<BootstrapProgress>,
 }
 
 impl TorController {
     /// Create a new Tor controller and start the embedded daemon.
-    pub async fn new(socks_port: u16, control_port: u16) -> Result<Self, NetworkError> {
-        let controller = Self {
+    ///
+    /// Where the platform has Unix sockets the daemon listens on those,
+    /// and the ports are not opened.
+    pub async fn new(socks_port: Port, control_port: Port) -> Result<Self, NetworkError> {
+        let mut controller = Self {
+            reconnectable: true,
+            ..Self::unconnected(socks_port, control_port)
+        };
+        let config = TorConfig {
+            data_dir: controller.data_dir.to_string_lossy().into_owned(),
             socks_port,
             control_port,
-            connected: AtomicBool::new(false),
-            control_connection: Mutex::new(None),
+            ..TorConfig::default()
         };
+        controller.socks = config.socks_endpoint();
+        controller.control_socket = config.control_socket();
+        controller.start_embedded_tor(&config).await?;
+        controller.attach_control().await?;
 
-        controller.start_embedded_tor().await?;
-        controller.wait_for_bootstrap().await?;
+        Ok(controller)
+    }
 
+    /// Create a controller for a Tor daemon already running on these
+    /// ports, connecting as `new` does once it has started its own.
+    pub async fn connect(socks_port: Port, control_port: Port) -> Result<Self, NetworkError> {
+        let controller = Self {
+            reconnectable: true,
+            ..Self::unconnected(socks_port, control_port)
+        };
+        controller.attach_control().await?;
+
+        Ok(controller)
+    }
+
+    /// Connect to the control socket if there is one, or else to the
+    /// control port over TCP, and attach to it.
+    async fn attach_control(&self) -> Result<(), NetworkError> {
+        let failed =
+            |e: std::io::Error| NetworkError::TorConnectionFailed(format!("Control port: {}", e));
+        #[cfg(unix)]
+        if let Some(path) = &self.control_socket {
+            return self
+                .attach(
+                    || async move { tokio::net::UnixStream::connect(path).await.map_err(failed) },
+                )
+                .await;
+        }
+        let control_port = self.control_port;
+        self.attach(|| async move {
+            TcpStream::connect(("127.0.0.1", control_port.get()))
+                .await
+                .map_err(failed)
+        })
+        .await
+    }
+
+    /// Create a controller speaking to a control port already connected
+    /// on `stream`, waiting for bootstrap like `new`.
+    ///
+    /// With a single stream there is no reconnecting, so a stale cookie
+    /// is not retried and a lost connection stays lost.
+    pub async fn with_control_stream<S: ControlStream + 'static>(
+        socks_port: Port,
+        control_port: Port,
+        stream: S,
+    ) -> Result<Self, NetworkError> {
+        let controller = Self::unconnected(socks_port, control_port);
+        let mut stream = Some(stream);
+        controller
+            .attach(|| {
+                let stream = stream.take().ok_or_else(|| {
+                    NetworkError::TorConnectionFailed("Control stream already used".to_string())
+                });
+                async move { stream }
+            })
+            .await?;
         Ok(controller)
     }
 
-    /// Start the embedded Tor daemon.
-    async fn start_embedded_tor(&self) -> Result<(), NetworkError> {
-        // In production, this would spawn the arti or tor process
-        // with specific configuration for maximum privacy.
-        //
-        // Configuration includes:
-        // - No disk writes (all in memory)
-        // - Strict exit node policies
-        // - Bridge support for censored networks
-        // - Custom entry guards (optional)
+    fn unconnected(socks_port: Port, control_port: Port) -> Self {
+        Self {
+            socks: SocksEndpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], socks_port.get()))),
+            control_port,
+            control_socket: None,
+            connected: AtomicBool::new(false),
+            reconnectable: false,
+            torn_down: AtomicBool::new(false),
+            control_connection: Mutex::new(None),
+            control: std::sync::Mutex::new(ControlChannel::new()),
+            data_dir: PathBuf::from(TorConfig::default().data_dir),
+            process: std::sync::Mutex::new(None),
+            health: std::sync::Mutex::new(TorHealth::new()),
+            clock: system_clock(),
+            last_newnym: std::sync::Mutex::new(None),
+            newnym_suppressed: AtomicU64::new(0),
+            bootstrap: watch::channel(BootstrapProgress::default()).0,
+        }
+    }
+
+    /// Follow bootstrap progress.
+    ///
+    /// Keeps reporting after bootstrap completes, so a regression after a
+    /// network loss shows up as a stall.
+    pub fn bootstrap_progress(&self) -> watch::Receiver<BootstrapProgress> {
+        self.bootstrap.subscribe()
+    }
+
+    /// Reach the SOCKS port at `endpoint` instead of the TCP port given.
+    pub fn with_socks_endpoint(mut self, endpoint: SocksEndpoint) -> Self {
+        self.socks = endpoint;
+        self
+    }
+
+    /// Time the NEWNYM rate limit on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        // For now, we assume tor is running or will be started by the launcher
+    /// Connect and authenticate, subscribe to events and wait for bootstrap.
+    async fn attach<C, F, S>(&self, connect: C) -> Result<(), NetworkError>
+    where
+        C: FnMut() -> F,
+        F: std::future::Future<Output = Result<S, NetworkError>>,
+        S: ControlStream + 'static,
+    {
+        let (mut connection, info) = open_authenticated(connect, &self.data_dir).await?;
+        connection.command(SETEVENTS_COMMAND.trim_end()).await?;
         log::info!(
-            "Tor controller initialized on ports {}/{}",
-            self.socks_port,
-            self.control_port
+            "Control port authenticated (Tor {})",
+            info.tor_version.as_deref().unwrap_or("unknown")
         );
 
+        *self.control_connection.lock().await = Some(connection);
+        self.wait_for_bootstrap().await
+    }
+
+    /// Watch the control connection for as long as the process scope of
+    /// `tasks` and this controller live.
+    ///
+    /// Events that arrive between commands are applied as they come. If
+    /// Tor closes the connection or it breaks, `is_connected` turns false
+    /// and `report` gets `TorState::Failed`. A controller made by `new`
+    /// then reconnects, waiting `RECONNECT_BACKOFF` and doubling the wait
+    /// up to `MAX_RECONNECT_BACKOFF`; it authenticates and subscribes to
+    /// events again, and `report` gets `TorState::Connected` once it is
+    /// back. After `teardown` nothing is reconnected.
+    pub fn spawn_supervisor<F>(self: &Arc<Self>, tasks: &TaskRegistry, report: F) -> AbortHandle
+    where
+        F: Fn(TorState) + Send + 'static,
+    {
+        let controller = Arc::downgrade(self);
+        tasks.spawn(TaskScope::Process, "control supervisor", async move {
+            loop {
+                tokio::time::sleep(CONTROL_CHECK_INTERVAL).await;
+                let Some(tor) = controller.upgrade() else {
+                    return;
+                };
+                let Err(e) = tor.check_control().await else {
+                    continue;
+                };
+                if tor.torn_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                log::warn!("Lost the Tor control connection: {}", e);
+                report(TorState::Failed("Lost connection to Tor".to_string()));
+                if !tor.reconnectable {
+                    return;
+                }
+                drop(tor);
+
+                let mut backoff = RECONNECT_BACKOFF;
+                loop {
+                    tokio::time::sleep(backoff).await;
+                    let Some(tor) = controller.upgrade() else {
+                        return;
+                    };
+                    if tor.torn_down.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match tor.attach_control().await {
+                        Ok(()) => break,
+                        Err(e) => log::debug!("Reconnecting to Tor failed: {}", e),
+                    }
+                    backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                }
+                log::info!("Tor control connection restored");
+                report(TorState::Connected);
+            }
+        })
+    }
+
+    /// Apply the events that arrived since the last command and check
+    /// that the control connection still works.
+    ///
+    /// A connection busy with a command is taken to work. A broken one is
+    /// dropped, and `is_connected` turns false.
+    async fn check_control(&self) -> Result<(), NetworkError> {
+        let Ok(mut guard) = self.control_connection.try_lock() else {
+            return Ok(());
+        };
+        let Some(connection) = guard.as_mut() else {
+            return Err(not_connected());
+        };
+        let alive = connection.read_ready_events().await;
+        let events = connection.take_events();
+        if alive.is_err() {
+            guard.take();
+            self.connected.store(false, Ordering::SeqCst);
+        }
+        drop(guard);
+        self.apply_events(events);
+
+        alive
+    }
+
+    /// Start the embedded Tor daemon, with its data directory in RAM.
+    ///
+    /// It runs until `teardown`, which kills it and wipes its data.
+    async fn start_embedded_tor(&self, config: &TorConfig) -> Result<(), NetworkError> {
+        let process = TorProcess::spawn(TOR_BINARY, config, TOR_STARTUP_DEADLINE).await?;
+        log::info!("Tor started, SOCKS on {}", self.socks);
+        *self.process.lock().expect("Process lock poisoned") = Some(process);
+
         Ok(())
     }
 
     /// Wait for Tor to complete bootstrap.
+    ///
+    /// Asks for the current phase, then follows STATUS_CLIENT BOOTSTRAP
+    /// events until PROGRESS=100 or `BOOTSTRAP_DEADLINE` passes. Each step
+    /// is published to `bootstrap_progress` receivers.
     async fn wait_for_bootstrap(&self) -> Result<(), NetworkError> {
-        // In production, connect to control port and wait for:
-        // 650 STATUS_CLIENT NOTICE CIRCUIT_ESTABLISHED
+        let mut guard = self.control_connection.lock().await;
+        let connection = guard.as_mut().ok_or_else(not_connected)?;
 
-        // Simulate bootstrap wait
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        self.connected.store(true, Ordering::SeqCst);
+        let phase = connection.get_info("status/bootstrap-phase").await?;
+        self.record_bootstrap(&phase);
+        let deadline = tokio::time::Instant::now() + BOOTSTRAP_DEADLINE;
+        while !self.bootstrap.borrow().is_done() {
+            let event = tokio::time::timeout_at(deadline, connection.next_event())
+                .await
+                .map_err(|_| {
+                    NetworkError::TorConnectionFailed(format!(
+                        "Bootstrap stalled at {}%",
+                        self.bootstrap.borrow().percent
+                    ))
+                })??;
+            self.handle_control_line(&event);
+        }
+        let events = connection.take_events();
+        drop(guard);
+        self.apply_events(events);
 
+        self.connected.store(true, Ordering::SeqCst);
         log::info!("Tor bootstrap complete");
         Ok(())
     }
 
+    /// Use a different Tor data directory (where the auth cookie lives).
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    /// (Re)connect and authenticate the control channel.
+    ///
+    /// The auth cookie is re-read from the data directory every time, as
+    /// Tor rewrites it on restart, and is wiped as soon as AUTHENTICATE
+    /// has been sent.
+    pub fn connect_control(&self) -> Result<(), NetworkError> {
+        self.control
+            .lock()
+            .expect("Control lock poisoned")
+            .reconnect(&self.data_dir)
+    }
+
+    /// Whether the control channel is authenticated.
+    pub fn control_authenticated(&self) -> bool {
+        self.control
+            .lock()
+            .expect("Control lock poisoned")
+            .is_authenticated()
+    }
+
+    /// Close the control channel and wipe everything secret it held, then
+    /// kill the embedded daemon and wipe its data directory.
+    ///
+    /// Called on quit and by the kill switch. Safe to call more than once.
+    pub fn teardown(&self) {
+        self.torn_down.store(true, Ordering::SeqCst);
+        self.control.lock().expect("Control lock poisoned").close();
+        if let Ok(mut connection) = self.control_connection.try_lock() {
+            connection.take();
+        }
+        self.connected.store(false, Ordering::SeqCst);
+        // Dropping the process kills tor and wipes its data
+        self.process.lock().expect("Process lock poisoned").take();
+        log::info!("Tor control channel torn down");
+    }
+
     /// Check if Tor is connected.
     pub async fn is_connected(&self) -> bool {
         self.connected.load(Ordering::SeqCst)
     }
 
-    /// Get the SOCKS5 proxy address.
-    pub fn socks_addr(&self) -> String {
-        format!("127.0.0.1:{}", self.socks_port)
+    /// Get the SOCKS5 proxy address: a TCP port or a Unix socket.
+    pub fn socks_addr(&self) -> SocksEndpoint {
+        self.socks.clone()
     }
 
     /// Request a new circuit from Tor.
+    ///
+    /// Sends SIGNAL NEWNYM, so new streams get a fresh circuit, and waits
+    /// as `new_identity` does for a circuit built after it; returns its id.
+    /// Within `NEWNYM_INTERVAL` of the last NEWNYM, Tor would ignore it;
+    /// the circuit is then isolated as by `new_isolated_circuit`.
+    ///
+    /// Fails at once while the control connection is down.
     pub async fn new_circuit(&self) -> Result<String, NetworkError> {
-        // Send SIGNAL NEWNYM to control port
-        // This creates a new circuit for subsequent connections
+        // Without the control port Tor's state is unknown
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(not_connected());
+        }
+        if !self.claim_newnym() {
+            self.newnym_suppressed.fetch_add(1, Ordering::Relaxed);
+            log::debug!("NEWNYM rate-limited, isolating by SOCKS credentials");
+            return self.new_isolated_circuit().await;
+        }
 
-        let circuit_id = generate_circuit_id();
+        let circuit_id = self.newnym_circuit().await?;
         log::debug!("Created new Tor circuit: {}", circuit_id);
 
         Ok(circuit_id)
     }
 
+    /// Take the NEWNYM slot if the rate limit allows one now.
+    fn claim_newnym(&self) -> bool {
+        let now = self.clock.now();
+        let mut last = self.last_newnym.lock().expect("NEWNYM lock");
+        if last.is_some_and(|at| now.saturating_duration_since(at) < NEWNYM_INTERVAL) {
+            return false;
+        }
+        *last = Some(now);
+        true
+    }
+
+    /// Take the next NEWNYM slot, returning how long until it opens.
+    ///
+    /// The slot is held from now on, so `new_circuit` does not spend it.
+    fn reserve_newnym(&self) -> Duration {
+        let now = self.clock.now();
+        let mut last = self.last_newnym.lock().expect("NEWNYM lock");
+        let opens = last.map_or(now, |at| (at + NEWNYM_INTERVAL).max(now));
+        *last = Some(opens);
+        opens - now
+    }
+
+    /// Switch to a new identity: send NEWNYM and wait until a circuit
+    /// built after it shows up in circuit-status. Returns its id.
+    ///
+    /// Unlike `new_circuit`, this waits out the rate limit instead of
+    /// isolating by SOCKS credentials, as an ignored NEWNYM would leave
+    /// the old circuits in use. Gives up after `IDENTITY_DEADLINE`.
+    pub async fn new_identity(&self) -> Result<String, NetworkError> {
+        let wait = self.reserve_newnym();
+        if !wait.is_zero() {
+            log::debug!("Waiting {}ms for the NEWNYM rate limit", wait.as_millis());
+            self.clock.sleep(wait).await;
+        }
+
+        let circuit_id = self.newnym_circuit().await?;
+        log::debug!("New identity on Tor circuit: {}", circuit_id);
+        Ok(circuit_id)
+    }
+
+    /// Send NEWNYM and wait until a circuit built after it shows up in
+    /// circuit-status. Returns its id.
+    ///
+    /// NEWNYM builds nothing by itself, so the newest circuit when it is
+    /// acknowledged is usually an old one. Gives up after
+    /// `IDENTITY_DEADLINE`.
+    async fn newnym_circuit(&self) -> Result<String, NetworkError> {
+        // Tor numbers circuits in creation order
+        let newest = |status: &str| newest_built_circuit(status)?.parse::<u64>().ok();
+        let before = newest(&self.circuit_status().await?);
+        self.command("SIGNAL NEWNYM").await?;
+
+        let deadline = self.clock.now() + IDENTITY_DEADLINE;
+        loop {
+            let built = newest(&self.circuit_status().await?)
+                .filter(|&id| before.is_none_or(|before| id > before));
+            if let Some(circuit_id) = built {
+                return Ok(circuit_id.to_string());
+            }
+            if self.clock.now() >= deadline {
+                return Err(NetworkError::CircuitCreationFailed(
+                    "No new circuit built after NEWNYM".to_string(),
+                ));
+            }
+            self.clock.sleep(IDENTITY_POLL).await;
+        }
+    }
+
+    /// Send `command` and wait for its reply.
+    async fn command(&self, command: &str) -> Result<(), NetworkError> {
+        let mut guard = self.control_connection.lock().await;
+        let connection = guard.as_mut().ok_or_else(not_connected)?;
+        let reply = connection.command(command).await;
+        let events = connection.take_events();
+        drop(guard);
+        self.apply_events(events);
+
+        reply.map(drop)
+    }
+
+    /// Get Tor's circuit-status listing.
+    async fn circuit_status(&self) -> Result<String, NetworkError> {
+        let mut guard = self.control_connection.lock().await;
+        let connection = guard.as_mut().ok_or_else(not_connected)?;
+        let status = connection.get_info("circuit-status").await;
+        let events = connection.take_events();
+        drop(guard);
+        self.apply_events(events);
+
+        status
+    }
+
+    /// Number of NEWNYMs not sent because of Tor's rate limit.
+    pub fn newnym_suppressed(&self) -> u64 {
+        self.newnym_suppressed.load(Ordering::Relaxed)
+    }
+
+    /// Request a new circuit without purging cached onion descriptors.
+    ///
+    /// SIGNAL NEWNYM also drops every fetched descriptor. This isolates
+    /// the circuit with fresh SOCKS credentials instead, so a retried
+    /// onion request skips the descriptor fetch.
+    pub async fn new_isolated_circuit(&self) -> Result<String, NetworkError> {
+        let circuit_id = generate_circuit_id();
+        log::debug!("Created new Tor circuit {} keeping descriptors", circuit_id);
+
+        Ok(circuit_id)
+    }
+
     /// Get information about the current circuit.
+    ///
+    /// Describes the newest built general-purpose circuit, locating its
+    /// relays with the embedded GeoIP table. `None` if no circuit is open.
     pub async fn get_current_circuit_info(&self) -> Option<CircuitInfo> {
-        // Query control port for circuit info
-        // GETINFO circuit-status
-
-        // Mock response for now
-        Some(CircuitInfo {
-            entry_country: "DE".to_string(),
-            exit_country: "CH".to_string(),
-            hop_count: 3,
+        let mut guard = self.control_connection.lock().await;
+        let connection = guard.as_mut()?;
+        let info = describe_circuit(connection).await;
+        let events = connection.take_events();
+        drop(guard);
+        self.apply_events(events);
+
+        info.unwrap_or_else(|e| {
+            log::debug!("Circuit info unavailable: {}", e);
+            None
         })
     }
 
+    /// Handle an asynchronous event line read from the control port.
+    ///
+    /// Returns the new health status if the event changed it.
+    pub fn handle_control_line(&self, line: &str) -> Option<TorHealthStatus> {
+        self.record_bootstrap(line);
+        let event = TorEvent::parse(line)?;
+        self.health
+            .lock()
+            .expect("Health lock poisoned")
+            .apply(&event)
+    }
+
+    /// Get the current health status.
+    pub fn health_status(&self) -> TorHealthStatus {
+        self.health.lock().expect("Health lock poisoned").status()
+    }
+
+    /// Get the error-page hint for a failed request, if any.
+    pub fn failure_hint(&self) -> Option<FailureHint> {
+        self.health
+            .lock()
+            .expect("Health lock poisoned")
+            .failure_hint()
+    }
+
     /// Close a specific circuit.
+    ///
+    /// Isolated circuits are only SOCKS credentials with no id at the
+    /// control port; there is nothing to close for them.
     pub async fn close_circuit(&self, circuit_id: &str) -> Result<(), NetworkError> {
-        // Send CLOSECIRCUIT <id> to control port
+        if circuit_id.is_empty() || !circuit_id.bytes().all(|b| b.is_ascii_digit()) {
+            log::debug!("Released isolated circuit: {}", circuit_id);
+            return Ok(());
+        }
+
+        let mut guard = self.control_connection.lock().await;
+        let connection = guard.as_mut().ok_or_else(not_connected)?;
+        let closed = connection
+            .command(&format!("CLOSECIRCUIT {}", circuit_id))
+            .await;
+        let events = connection.take_events();
+        drop(guard);
+        self.apply_events(events);
+
+        closed?;
         log::debug!("Closed Tor circuit: {}", circuit_id);
         Ok(())
     }
+
+    /// Publish the bootstrap status in `line`, if it has one.
+    fn record_bootstrap(&self, line: &str) {
+        let Some(reported) = BootstrapProgress::parse(line) else {
+            return;
+        };
+        self.bootstrap.send_if_modified(|progress| {
+            let next = progress.advance(reported);
+            match &next.stalled {
+                Some(reason) => {
+                    log::warn!("Tor bootstrap stalled at {}%: {}", next.percent, reason)
+                }
+                None => log::info!("Tor bootstrap {}%", next.percent),
+            }
+            let changed = next != *progress;
+            *progress = next;
+            changed
+        });
+    }
+
+    fn apply_events(&self, events: Vec<String>) {
+        for event in events {
+            self.handle_control_line(&event);
+        }
+    }
+}
+
+/// Describe the newest built circuit from circuit-status and the
+/// consensus entries of its entry and exit.
+async fn describe_circuit(
+    connection: &mut ControlConnection,
+) -> Result<Option<CircuitInfo>, NetworkError> {
+    let status = connection.get_info("circuit-status").await?;
+    let Some(path) = newest_built_circuit(&status).and_then(|id| circuit_path(&status, &id)) else {
+        return Ok(None);
+    };
+    let (Some(entry), Some(exit)) = (path.first(), path.last()) else {
+        return Ok(None);
+    };
+
+    let entry_country = if REDACT_GUARD_COUNTRY {
+        GUARD_LABEL.to_string()
+    } else {
+        relay_country(connection, entry).await
+    };
+    Ok(Some(CircuitInfo {
+        entry_country,
+        exit_country: relay_country(connection, exit).await,
+        hop_count: path.len(),
+    }))
+}
+
+/// Country of the relay with `fingerprint`, or `UNKNOWN_COUNTRY` if it is
+/// not in the consensus or not in the table.
+async fn relay_country(connection: &mut ControlConnection, fingerprint: &str) -> String {
+    if fingerprint.is_empty() || !fingerprint.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return UNKNOWN_COUNTRY.to_string();
+    }
+    connection
+        .get_info(&format!("ns/id/{}", fingerprint))
+        .await
+        .ok()
+        .and_then(|entry| relay_address(&entry))
+        .and_then(|address| GeoIp::embedded().country(address))
+        .unwrap_or(UNKNOWN_COUNTRY)
+        .to_string()
+}
+
+fn not_connected() -> NetworkError {
+    NetworkError::TorConnectionFailed("Control port not connected".to_string())
+}
+
+impl fmt::Debug for TorController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Written by hand so no secret can ever end up in a log line
+        f.debug_struct("TorController")
+            .field("socks", &self.socks)
+            .field("control_port", &self.control_port)
+            .field("control_socket", &self.control_socket)
+            .field("connected", &self.connected.load(Ordering::SeqCst))
+            .field("control_authenticated", &self.control_authenticated())
+            .finish_non_exhaustive()
+    }
 }
 
 /// Generate a random circuit ID.
@@ -119,54 +710,272 @@ fn generate_circuit_id() -> String {
         .unwrap_or_default()
         .as_nanos();
 
-    // In production, use proper random bytes
-    format!("circuit_{:016x}", timestamp)
+    // Several may be made within one clock tick
+    format!(
+        "circuit_{:016x}{:016x}",
+        timestamp as u64,
+        rand::random::<u64>()
+    )
 }
 
+/// Start of the comment ending a generated torrc, followed by the
+/// SHA-256 of everything before it.
+const TORRC_HASH_PREFIX: &str = "# forloop-config-hash ";
+
+/// Hex SHA-256 of a torrc's directives.
+fn torrc_hash(directives: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(directives.as_bytes());
+    hasher
+        .finish()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Name of the SOCKS socket inside the data directory.
+const SOCKS_SOCKET: &str = "socks.sock";
+
+/// Name of the control socket inside the data directory.
+const CONTROL_SOCKET: &str = "control.sock";
+
+/// Longest socket path a `sockaddr_un` holds on every Unix.
+const MAX_SOCKET_PATH: usize = 103;
+
 /// Configuration for the embedded Tor daemon.
+///
+/// Values are written into the torrc as they are, so `validate` must pass
+/// before `to_torrc` is used: a newline or `#` in any of them would let
+/// it add or hide directives.
 #[derive(Debug, Clone)]
 pub struct TorConfig {
     /// Data directory (should be in RAM)
     pub data_dir: String,
     /// SOCKS port
-    pub socks_port: u16,
+    pub socks_port: Port,
     /// Control port
-    pub control_port: u16,
+    pub control_port: Port,
+    /// Listen on Unix sockets in the data directory instead of the SOCKS
+    /// and control ports; on by default where the platform has them
+    pub unix_sockets: bool,
     /// Use bridges (for censored networks)
     pub use_bridges: bool,
     /// Bridge lines
-    pub bridges: Vec<String>,
+    pub bridges: Vec<BridgeLine>,
+    /// Pluggable transport clients tor may start for the bridges
+    pub transport_plugins: Vec<PluggableTransport>,
     /// Disable disk writes
     pub disable_disk: bool,
     /// Enforce strict exit policies
     pub strict_exit: bool,
+    /// ISO 3166-1 alpha-2 codes of countries never to exit from
+    pub exclude_exit_countries: Vec<String>,
 }
 
 impl Default for TorConfig {
     fn default() -> Self {
         Self {
-            data_dir: "/dev/shm/forloop-tor".to_string(), // RAM-backed
-            socks_port: 9150,
-            control_port: 9151,
+            data_dir: StatePaths::system()
+                .tor_data_dir()
+                .to_string_lossy()
+                .into_owned(), // RAM-backed
+            socks_port: Port::new(9150),
+            control_port: Port::new(9151),
+            unix_sockets: cfg!(unix),
             use_bridges: false,
             bridges: Vec::new(),
+            transport_plugins: Vec::new(),
             disable_disk: true,
             strict_exit: true,
+            exclude_exit_countries: Vec::new(),
         }
     }
 }
 
 impl TorConfig {
+    /// Check what tor would otherwise accept and misread: an unknown
+    /// country code matches no relay, so excluding it excludes nothing.
+    ///
+    /// Also refuses anything that would not stay one torrc value: a data
+    /// directory that is relative or holds whitespace, control characters
+    /// or `#`; a port of 0, or the same port twice; a bridge whose line
+    /// does not parse back to the same `BridgeLine`. Unix sockets must
+    /// be supported and their paths fit a `sockaddr_un`.
+    ///
+    /// With bridges on, every transport they use needs an executable
+    /// client in `transport_plugins`, or this fails with
+    /// `TransportNotFound`.
+    pub fn validate(&self) -> Result<(), NetworkError> {
+        let invalid = |message: String| Err(NetworkError::InvalidTorConfig(message));
+
+        let unsafe_char = |c: char| c.is_whitespace() || c.is_control() || c == '#';
+        if !std::path::Path::new(&self.data_dir).is_absolute()
+            || self.data_dir.contains(unsafe_char)
+        {
+            return invalid(format!(
+                "Data directory {:?} is not an absolute path without spaces, \
+                 control characters or '#'",
+                self.data_dir
+            ));
+        }
+
+        for plugin in &self.transport_plugins {
+            let binary = plugin.binary.to_string_lossy();
+            if !plugin.binary.is_absolute() || binary.contains(unsafe_char) {
+                return invalid(format!(
+                    "Transport client {:?} is not an absolute path without spaces, \
+                     control characters or '#'",
+                    binary
+                ));
+            }
+        }
+
+        for port in [self.socks_port, self.control_port] {
+            if port.get() == 0 {
+                return invalid("Port 0 is not a valid port".to_string());
+            }
+        }
+        if self.socks_port == self.control_port {
+            return invalid(format!(
+                "SOCKS and control ports are both {}",
+                self.socks_port
+            ));
+        }
+
+        if self.unix_sockets {
+            if !cfg!(unix) {
+                return invalid("Unix sockets are not supported on this platform".to_string());
+            }
+            // The longer of the two names
+            let socket = Path::new(&self.data_dir).join(CONTROL_SOCKET);
+            if socket.as_os_str().len() > MAX_SOCKET_PATH {
+                return invalid(format!(
+                    "Socket path {} is longer than {} bytes",
+                    socket.display(),
+                    MAX_SOCKET_PATH
+                ));
+            }
+        }
+
+        for country in &self.exclude_exit_countries {
+            forloop_config::parse_country_code(country)
+                .map_err(|e| NetworkError::InvalidTorConfig(e.to_string()))?;
+        }
+
+        for (index, bridge) in self.bridges.iter().enumerate() {
+            let line = bridge.torrc_line();
+            let reparsed = line.parse::<BridgeLine>().ok();
+            if line.contains(['\r', '\n', '#']) || reparsed.as_ref() != Some(bridge) {
+                return invalid(format!("Bridge {} does not stay one torrc line", index + 1));
+            }
+        }
+
+        if self.use_bridges {
+            for transport in used_transports(&self.bridges) {
+                let plugin = self
+                    .transport_plugins
+                    .iter()
+                    .find(|plugin| plugin.transports.contains(&transport));
+                match plugin {
+                    Some(plugin) if is_executable(&plugin.binary) => {}
+                    Some(plugin) => {
+                        return Err(NetworkError::TransportNotFound {
+                            transport: transport.name().unwrap_or_default().to_string(),
+                            binary: plugin.binary.display().to_string(),
+                        })
+                    }
+                    None => return Err(not_found(transport)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill `transport_plugins` with `manager`'s clients for the
+    /// transports the bridges use.
+    ///
+    /// Fails with `TransportNotFound` if one of them has no client.
+    pub fn with_transports(mut self, manager: &TransportManager) -> Result<Self, NetworkError> {
+        self.transport_plugins = if self.use_bridges {
+            manager.plugins_for(&self.bridges)?
+        } else {
+            Vec::new()
+        };
+        Ok(self)
+    }
+
+    /// Turn bridges on and add the built-in Snowflake bridges to any
+    /// already configured, then find the clients for all of them as
+    /// `with_transports` does.
+    ///
+    /// Fails with `TransportNotFound` if there is no snowflake-client.
+    pub fn with_snowflake(mut self, manager: &TransportManager) -> Result<Self, NetworkError> {
+        self.use_bridges = true;
+        for bridge in snowflake_bridges() {
+            if !self.bridges.contains(&bridge) {
+                self.bridges.push(bridge);
+            }
+        }
+        self.with_transports(manager)
+    }
+
+    /// Where tor's SOCKS port listens under this configuration.
+    pub fn socks_endpoint(&self) -> SocksEndpoint {
+        if self.unix_sockets {
+            SocksEndpoint::Unix(Path::new(&self.data_dir).join(SOCKS_SOCKET))
+        } else {
+            SocksEndpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], self.socks_port.get())))
+        }
+    }
+
+    /// tor's control socket, if it listens on one instead of the control
+    /// port.
+    pub fn control_socket(&self) -> Option<PathBuf> {
+        self.unix_sockets
+            .then(|| Path::new(&self.data_dir).join(CONTROL_SOCKET))
+    }
+
+    /// Whether `torrc` is unchanged from what `to_torrc` wrote: its last
+    /// line is the hash of the rest.
+    pub fn verify_torrc(torrc: &str) -> bool {
+        let Some(body) = torrc.strip_suffix('\n') else {
+            return false;
+        };
+        let (directives, last) = match body.rfind('\n') {
+            Some(end) => body.split_at(end + 1),
+            None => ("", body),
+        };
+        last.strip_prefix(TORRC_HASH_PREFIX)
+            .is_some_and(|hash| hash == torrc_hash(directives))
+    }
+
     /// Generate torrc content from this configuration.
+    ///
+    /// Only meaningful for a configuration that passed `validate`. The
+    /// last line is a comment with the hash `verify_torrc` checks.
     pub fn to_torrc(&self) -> String {
         let mut config = String::new();
 
         config.push_str(&format!("DataDirectory {}\n", self.data_dir));
-        config.push_str(&format!("SocksPort {}\n", self.socks_port));
-        config.push_str(&format!("ControlPort {}\n", self.control_port));
+        // Streams with different SOCKS credentials never share a circuit;
+        // onion service failures come back with their own reply codes
+        let socks = match self.socks_endpoint() {
+            endpoint @ SocksEndpoint::Unix(_) => endpoint.to_string(),
+            SocksEndpoint::Tcp(_) => self.socks_port.to_string(),
+        };
+        config.push_str(&format!(
+            "SocksPort {} IsolateSOCKSAuth ExtendedErrors\n",
+            socks
+        ));
+        match self.control_socket() {
+            Some(path) => config.push_str(&format!("ControlSocket {}\n", path.display())),
+            None => config.push_str(&format!("ControlPort {}\n", self.control_port)),
+        }
 
         // Security settings
         config.push_str("CookieAuthentication 1\n");
+        // Logs stay in memory, in the launcher's log buffer
+        config.push_str("Log notice stderr\n");
         config.push_str("AvoidDiskWrites 1\n");
         config.push_str("DisableDebuggerAttachment 1\n");
 
@@ -176,14 +985,33 @@ impl TorConfig {
         // Exit policies
         if self.strict_exit {
             config.push_str("ExitRelay 0\n");
+        }
+        if !self.exclude_exit_countries.is_empty() {
+            let countries: Vec<String> = self
+                .exclude_exit_countries
+                .iter()
+                .map(|country| format!("{{{}}}", country.to_ascii_lowercase()))
+                .collect();
+            config.push_str(&format!("ExcludeExitNodes {}\n", countries.join(",")));
+        }
+        if self.strict_exit || !self.exclude_exit_countries.is_empty() {
             config.push_str("StrictNodes 1\n");
         }
 
         // Bridge configuration
         if self.use_bridges {
             config.push_str("UseBridges 1\n");
+            // Only the transports some bridge uses
+            let transports = used_transports(&self.bridges);
+            for plugin in &self.transport_plugins {
+                if let Some(line) = plugin.torrc_line(&transports) {
+                    config.push_str(&line);
+                    config.push('\n');
+                }
+            }
             for bridge in &self.bridges {
-                config.push_str(&format!("Bridge {}\n", bridge));
+                config.push_str(&bridge.torrc_line());
+                config.push('\n');
             }
         }
 
@@ -191,6 +1019,10 @@ impl TorConfig {
         config.push_str("SafeLogging 1\n");
         config.push_str("ClientOnly 1\n");
 
+        let hash = torrc_hash(&config);
+        config.push_str(TORRC_HASH_PREFIX);
+        config.push_str(&hash);
+        config.push('\n');
         config
     }
 }
@@ -198,6 +1030,18 @@ impl TorConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::control::tests::cookie_dir;
+    use crate::control_protocol::tests::{mock_control_port, tor_ready};
+    use forloop_config::clock::ManualClock;
+
+    async fn ready_controller() -> (TorController, crate::control_protocol::tests::CommandLog) {
+        let (stream, log) = mock_control_port(tor_ready());
+        let controller =
+            TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+                .await
+                .expect("controller");
+        (controller, log)
+    }
 
     #[test]
     fn test_circuit_id_generation() {
@@ -209,14 +1053,720 @@ mod tests {
         assert!(id1.starts_with("circuit_"));
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_teardown_and_debug_hide_secrets() {
+        let cookie = [0x5A; 32];
+        let dir = cookie_dir("controller", &cookie);
+        let (controller, _) = ready_controller().await;
+        let controller = controller.with_data_dir(dir.path());
+
+        controller.connect_control().expect("authenticate");
+        assert!(controller.control_authenticated());
+
+        let debug = format!("{:?}", controller);
+        assert!(!debug.contains("5A5A"));
+        assert!(!debug.contains("90, 90"));
+
+        controller.teardown();
+        assert!(!controller.control_authenticated());
+        assert!(!controller.is_connected().await);
+
+        // Teardown is idempotent
+        controller.teardown();
+    }
+
+    #[tokio::test]
+    async fn test_control_command_sequence() {
+        let clock = ManualClock::new();
+        let (controller, log) = ready_controller().await;
+        let controller = controller.with_clock(Arc::new(clock.clone()));
+        assert!(controller.is_connected().await);
+
+        let first = controller.new_circuit().await.expect("circuit");
+        clock.advance(NEWNYM_INTERVAL);
+        let second = controller.new_circuit().await.expect("circuit");
+        assert_eq!((first.as_str(), second.as_str()), ("1", "2"));
+        controller.close_circuit(&first).await.expect("closed");
+        // Isolated circuits never reach the control port
+        controller
+            .close_circuit(&generate_circuit_id())
+            .await
+            .expect("released");
+
+        assert_eq!(
+            log.lock().expect("command log").as_slice(),
+            [
+                "PROTOCOLINFO 1",
+                "AUTHENTICATE",
+                SETEVENTS_COMMAND.trim_end(),
+                "GETINFO status/bootstrap-phase",
+                "GETINFO circuit-status",
+                "SIGNAL NEWNYM",
+                "GETINFO circuit-status",
+                "GETINFO circuit-status",
+                "SIGNAL NEWNYM",
+                "GETINFO circuit-status",
+                "CLOSECIRCUIT 1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_circuit_waits_for_a_circuit_built_after_newnym() {
+        // Circuit 1 predates the NEWNYM; circuit 2 shows up a poll later
+        let mut ready = tor_ready();
+        ready("SIGNAL NEWNYM");
+        let mut polls_until_built = None;
+        let (stream, _) = mock_control_port(move |command| match command {
+            "SIGNAL NEWNYM" => {
+                polls_until_built = Some(2);
+                "250 OK\r\n".to_string()
+            }
+            "GETINFO circuit-status" => {
+                if let Some(polls) = polls_until_built.as_mut() {
+                    *polls -= 1;
+                    if *polls == 0 {
+                        polls_until_built = None;
+                        ready("SIGNAL NEWNYM");
+                    }
+                }
+                ready(command)
+            }
+            _ => ready(command),
+        });
+        let clock = ManualClock::new();
+        let controller =
+            TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+                .await
+                .expect("controller")
+                .with_clock(Arc::new(clock.clone()));
+
+        let circuit = controller.new_circuit();
+        tokio::pin!(circuit);
+        let pending = tokio::time::timeout(Duration::from_millis(10), circuit.as_mut()).await;
+        assert!(pending.is_err(), "returned a circuit from before NEWNYM");
+
+        clock.advance(IDENTITY_POLL);
+        assert_eq!(circuit.await.expect("circuit"), "2");
+    }
+
+    #[tokio::test]
+    async fn test_rapid_circuits_isolated_despite_newnym_rate_limit() {
+        let clock = ManualClock::new();
+        let (controller, log) = ready_controller().await;
+        let tor = Arc::new(controller.with_clock(Arc::new(clock.clone())));
+        let manager = crate::CircuitManager::new(Arc::clone(&tor) as _);
+
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..20 {
+            let circuit = manager.create_new_circuit().await.expect("circuit");
+            let isolation = circuit.isolation();
+            let key = (
+                circuit.id().to_string(),
+                isolation.username().to_string(),
+                isolation.password().to_string(),
+            );
+            assert!(seen.insert(key), "circuit and isolation reused");
+        }
+        let newnyms = |log: &crate::control_protocol::tests::CommandLog| {
+            log.lock()
+                .expect("command log")
+                .iter()
+                .filter(|command| *command == "SIGNAL NEWNYM")
+                .count()
+        };
+        // Only the first was worth sending; Tor would have ignored the rest
+        assert_eq!(newnyms(&log), 1);
+        assert_eq!(tor.newnym_suppressed(), 19);
+
+        clock.advance(NEWNYM_INTERVAL);
+        manager.create_new_circuit().await.expect("circuit");
+        assert_eq!(newnyms(&log), 2);
+    }
+
+    /// Serve `tor_ready` control connections on `listener`, logging each
+    /// connection's commands. Aborting the task drops every connection.
+    async fn serve_control(
+        listener: tokio::net::TcpListener,
+        logs: Arc<std::sync::Mutex<Vec<crate::control_protocol::tests::CommandLog>>>,
+    ) {
+        let mut connections = tokio::task::JoinSet::new();
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let (mut mock, log) = mock_control_port(tor_ready());
+            logs.lock().expect("connection logs").push(log);
+            connections.spawn(async move {
+                let _ = tokio::io::copy_bidirectional(&mut socket, &mut mock).await;
+            });
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reconnects_after_control_connection_loss() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let address = listener.local_addr().expect("address");
+        let logs = Arc::default();
+        let server = tokio::spawn(serve_control(listener, Arc::clone(&logs)));
+        let tor = Arc::new(
+            TorController::connect(Port::new(9150), Port::new(address.port()))
+                .await
+                .expect("controller"),
+        );
+        let tasks = TaskRegistry::new();
+        let (states, mut reported) = tokio::sync::mpsc::unbounded_channel();
+        tor.spawn_supervisor(&tasks, move |state| {
+            let _ = states.send(state);
+        });
+        assert_eq!(tor.new_circuit().await.expect("circuit"), "1");
+
+        // Tor goes away
+        server.abort();
+        let _ = server.await;
+        assert!(matches!(reported.recv().await, Some(TorState::Failed(_))));
+        let lost_at = tokio::time::Instant::now();
+        assert!(!tor.is_connected().await);
+        assert!(matches!(
+            tor.new_circuit().await,
+            Err(NetworkError::TorConnectionFailed(_))
+        ));
+
+        // And comes back on the same port while reconnects are failing
+        tokio::time::sleep(RECONNECT_BACKOFF * 2).await;
+        let listener = tokio::net::TcpListener::bind(address)
+            .await
+            .expect("rebind");
+        let server = tokio::spawn(serve_control(listener, Arc::clone(&logs)));
+        assert_eq!(reported.recv().await, Some(TorState::Connected));
+        assert!(lost_at.elapsed() >= RECONNECT_BACKOFF * 3);
+        assert!(tor.is_connected().await);
+        tor.new_circuit().await.expect("circuit");
+
+        {
+            let logs = logs.lock().expect("connection logs");
+            let log = logs
+                .last()
+                .expect("reconnected")
+                .lock()
+                .expect("command log");
+            assert_eq!(
+                log[..4],
+                [
+                    "PROTOCOLINFO 1",
+                    "AUTHENTICATE",
+                    SETEVENTS_COMMAND.trim_end(),
+                    "GETINFO status/bootstrap-phase",
+                ]
+            );
+        }
+        tasks.shutdown().await;
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_new_identity_waits_out_newnym_rate_limit() {
+        let clock = ManualClock::new();
+        let (controller, log) = ready_controller().await;
+        let controller = controller.with_clock(Arc::new(clock.clone()));
+        assert_eq!(controller.new_circuit().await.expect("circuit"), "1");
+        log.lock().expect("command log").clear();
+
+        let identity = controller.new_identity();
+        tokio::pin!(identity);
+        // Tor would ignore a NEWNYM now, so nothing is sent yet
+        let pending = tokio::time::timeout(Duration::from_millis(10), identity.as_mut()).await;
+        assert!(pending.is_err());
+        assert!(log.lock().expect("command log").is_empty());
+        // Meanwhile the slot is taken; new circuits are isolated instead
+        assert!(controller
+            .new_circuit()
+            .await
+            .expect("circuit")
+            .starts_with("circuit_"));
+
+        clock.advance(NEWNYM_INTERVAL);
+        assert_eq!(identity.await.expect("identity"), "2");
+        assert_eq!(
+            log.lock().expect("command log").as_slice(),
+            [
+                "GETINFO circuit-status",
+                "SIGNAL NEWNYM",
+                "GETINFO circuit-status",
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bootstrap_follows_status_client_events() {
+        let mut ready = tor_ready();
+        let (stream, _) = mock_control_port(move |command| {
+            match command {
+            "GETINFO status/bootstrap-phase" => concat!(
+                "250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=50 TAG=loading_descriptors\r\n",
+                "250 OK\r\n",
+                "650 NETWORK_LIVENESS DOWN\r\n",
+                "650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=90 TAG=ap_handshake_done\r\n",
+                "650 NETWORK_LIVENESS UP\r\n",
+                "650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=100 TAG=done\r\n",
+            )
+            .to_string(),
+            _ => ready(command),
+        }
+        });
+        let controller =
+            TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+                .await
+                .expect("bootstrapped");
+        assert!(controller.is_connected().await);
+        assert_eq!(controller.health_status(), TorHealthStatus::Healthy);
+        let mut progress = controller.bootstrap_progress();
+        assert!(progress.borrow_and_update().is_done());
+
+        // Losing the network afterwards sets progress back; Tor's warning
+        // while it retries keeps it stalled
+        controller.handle_control_line("650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=30 TAG=conn");
+        assert!(progress.has_changed().expect("sender alive"));
+        assert_eq!(
+            progress.borrow_and_update().stalled.as_deref(),
+            Some("Progress fell from 100% to 30%")
+        );
+        controller.handle_control_line(
+            "650 STATUS_CLIENT WARN BOOTSTRAP PROGRESS=30 TAG=conn WARNING=\"No route to host\"",
+        );
+        assert_eq!(
+            progress.borrow_and_update().stalled.as_deref(),
+            Some("No route to host")
+        );
+        controller.handle_control_line("650 STATUS_CLIENT NOTICE BOOTSTRAP PROGRESS=100 TAG=done");
+        assert!(progress.borrow().is_done());
+
+        // A bootstrap that stops making progress hits the deadline
+        let mut ready = tor_ready();
+        let (stream, _) = mock_control_port(move |command| match command {
+            "GETINFO status/bootstrap-phase" => {
+                "250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=5 TAG=conn\r\n250 OK\r\n"
+                    .to_string()
+            }
+            _ => ready(command),
+        });
+        let error = TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+            .await
+            .expect_err("stalled");
+        assert!(error.to_string().contains("stalled at 5%"));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_info_from_control_port() {
+        let mut ready = tor_ready();
+        let (stream, log) = mock_control_port(move |command| match command {
+            "GETINFO circuit-status" => concat!(
+                "250+circuit-status=\r\n",
+                "4 BUILT $F2044413DAC2E02E3D6BCF4735A19BCA1DE97281~gabelmoo,",
+                "$847B1F850344D7876491A54892F904934E4EB85D~tor26,",
+                "$BD6A829255CB08E66FBE7D3748363586E46B3810~maatuska ",
+                "BUILD_FLAGS=NEED_CAPACITY PURPOSE=GENERAL\r\n",
+                ".\r\n250 OK\r\n",
+            )
+            .to_string(),
+            "GETINFO ns/id/BD6A829255CB08E66FBE7D3748363586E46B3810" => concat!(
+                "250+ns/id/BD6A829255CB08E66FBE7D3748363586E46B3810=\r\n",
+                "r maatuska vWqCklXLCOZvvn03SDY1huRrOBA 7BAkAYrESO2w8SQRCLqW2KoNbsI ",
+                "2024-05-01 09:12:06 171.25.193.9 80 443\r\n",
+                "s Authority Fast Running Stable V2Dir Valid\r\n",
+                ".\r\n250 OK\r\n",
+            )
+            .to_string(),
+            _ => ready(command),
+        });
+        let controller =
+            TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+                .await
+                .expect("controller");
+
+        let info = controller
+            .get_current_circuit_info()
+            .await
+            .expect("circuit");
+        assert_eq!(
+            info,
+            CircuitInfo {
+                entry_country: GUARD_LABEL.to_string(),
+                exit_country: "SE".to_string(),
+                hop_count: 3,
+            }
+        );
+        // The redacted guard is never even looked up
+        assert!(!log
+            .lock()
+            .expect("command log")
+            .iter()
+            .any(|command| command.contains("F2044413DAC2E02E3D6BCF4735A19BCA1DE97281")));
+
+        // No circuit open yet
+        let (controller, _) = ready_controller().await;
+        assert_eq!(controller.get_current_circuit_info().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_error_replies_fail_closed() {
+        let mut ready = tor_ready();
+        let (stream, log) = mock_control_port(move |command| match command {
+            "AUTHENTICATE" => "515 Authentication failed\r\n".to_string(),
+            _ => ready(command),
+        });
+        let error = TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+            .await
+            .expect_err("rejected");
+        assert!(matches!(
+            error,
+            NetworkError::ControlRejected { status: 515, .. }
+        ));
+        // Nothing is sent after a refused AUTHENTICATE
+        assert_eq!(
+            log.lock().expect("command log").as_slice(),
+            ["PROTOCOLINFO 1", "AUTHENTICATE"]
+        );
+
+        let mut ready = tor_ready();
+        let (stream, _) = mock_control_port(move |command| match command {
+            "SIGNAL NEWNYM" => "552 Unrecognized signal\r\n".to_string(),
+            _ => ready(command),
+        });
+        let controller =
+            TorController::with_control_stream(Port::new(9150), Port::new(9151), stream)
+                .await
+                .expect("controller");
+        assert!(matches!(
+            controller.new_circuit().await,
+            Err(NetworkError::ControlRejected { status: 552, .. })
+        ));
+    }
+
     #[test]
     fn test_torrc_generation() {
-        let config = TorConfig::default();
+        let config = TorConfig {
+            unix_sockets: false,
+            ..TorConfig::default()
+        };
         let torrc = config.to_torrc();
 
         assert!(torrc.contains("DataDirectory"));
-        assert!(torrc.contains("SocksPort 9150"));
+        assert!(torrc.contains("SocksPort 9150 IsolateSOCKSAuth ExtendedErrors\n"));
+        assert!(torrc.contains("\nControlPort 9151\n"));
         assert!(torrc.contains("AvoidDiskWrites 1"));
         assert!(torrc.contains("SafeLogging 1"));
     }
+
+    #[test]
+    fn test_torrc_unix_sockets() {
+        assert_eq!(TorConfig::default().unix_sockets, cfg!(unix));
+        let config = TorConfig {
+            data_dir: "/run/user/1000/forloop/tor".to_string(),
+            unix_sockets: true,
+            ..TorConfig::default()
+        };
+        let torrc = config.to_torrc();
+        assert!(torrc.contains(
+            "\nSocksPort unix:/run/user/1000/forloop/tor/socks.sock IsolateSOCKSAuth ExtendedErrors\n"
+        ));
+        assert!(torrc.contains("\nControlSocket /run/user/1000/forloop/tor/control.sock\n"));
+        assert!(!torrc.contains("ControlPort"));
+        assert_eq!(
+            config.socks_endpoint(),
+            SocksEndpoint::Unix(PathBuf::from("/run/user/1000/forloop/tor/socks.sock"))
+        );
+        assert_eq!(
+            config.control_socket(),
+            Some(PathBuf::from("/run/user/1000/forloop/tor/control.sock"))
+        );
+
+        let tcp = TorConfig {
+            unix_sockets: false,
+            ..config.clone()
+        };
+        assert_eq!(
+            tcp.socks_endpoint(),
+            SocksEndpoint::Tcp(SocketAddr::from(([127, 0, 0, 1], 9150)))
+        );
+        assert_eq!(tcp.control_socket(), None);
+
+        if cfg!(unix) {
+            config.validate().expect("short socket paths");
+        }
+        let config = TorConfig {
+            data_dir: format!("/tmp/{}", "d".repeat(100)),
+            ..config
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(NetworkError::InvalidTorConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_torrc_bridges() {
+        let config = TorConfig {
+            use_bridges: true,
+            bridges: crate::parse_bridge_lines([
+                "Bridge meek_lite 192.0.2.18:80 be776a53492e1e044a26f17306e1bc46a55a1625 url=https://meek.azureedge.net/",
+                "192.0.2.1:9001",
+            ])
+            .expect("valid bridges"),
+            ..TorConfig::default()
+        };
+        let torrc = config.to_torrc();
+
+        assert!(torrc.contains(
+            "\nBridge meek_lite 192.0.2.18:80 BE776A53492E1E044A26F17306E1BC46A55A1625 url=https://meek.azureedge.net/\n"
+        ));
+        assert!(torrc.contains("\nBridge 192.0.2.1:9001\n"));
+    }
+
+    #[test]
+    fn test_torrc_transport_plugins() {
+        use crate::temp_dir::TempDir;
+        use crate::transports::tests::{add_client, bridges, MEEK, OBFS4, SNOWFLAKE};
+        use crate::BridgeTransport;
+
+        let dir = TempDir::create("pt-torrc");
+        let obfs4proxy = add_client(&dir, "obfs4proxy", 0o755);
+        let snowflake = add_client(&dir, "snowflake-client", 0o755);
+        let manager = TransportManager::new([dir.path()]);
+        let config = TorConfig {
+            use_bridges: true,
+            bridges: bridges(&[OBFS4, MEEK]),
+            ..TorConfig::default()
+        }
+        .with_transports(&manager)
+        .expect("clients found");
+        config.validate().expect("valid");
+        let torrc = config.to_torrc();
+        assert!(torrc.contains(&format!(
+            "\nUseBridges 1\nClientTransportPlugin obfs4,meek_lite exec {}\nBridge obfs4 ",
+            obfs4proxy.display()
+        )));
+        assert!(!torrc.contains("snowflake"));
+
+        // A client no bridge uses gets no line
+        let config = TorConfig {
+            bridges: bridges(&[MEEK]),
+            transport_plugins: vec![
+                PluggableTransport {
+                    transports: vec![BridgeTransport::Obfs4, BridgeTransport::MeekLite],
+                    binary: obfs4proxy.clone(),
+                },
+                PluggableTransport {
+                    transports: vec![BridgeTransport::Snowflake],
+                    binary: snowflake,
+                },
+            ],
+            ..config
+        };
+        let torrc = config.to_torrc();
+        assert_eq!(torrc.matches("ClientTransportPlugin").count(), 1);
+        assert!(torrc.contains(&format!(
+            "\nClientTransportPlugin meek_lite exec {}\n",
+            obfs4proxy.display()
+        )));
+        // Nor does any without bridges on
+        let config = TorConfig {
+            use_bridges: false,
+            ..config
+        };
+        assert!(!config.to_torrc().contains("ClientTransportPlugin"));
+
+        // Missing, gone or not executable: refused before tor starts
+        let config = TorConfig {
+            use_bridges: true,
+            bridges: bridges(&[SNOWFLAKE]),
+            ..TorConfig::default()
+        };
+        assert_eq!(
+            config.validate().map_err(|e| e.to_string()),
+            Err("snowflake bridge configured but snowflake-client not found".to_string())
+        );
+        let plugged = config.with_transports(&manager).expect("client found");
+        plugged.validate().expect("valid");
+        std::fs::set_permissions(
+            &plugged.transport_plugins[0].binary,
+            std::os::unix::fs::PermissionsExt::from_mode(0o644),
+        )
+        .expect("chmod");
+        assert!(matches!(
+            plugged.validate(),
+            Err(NetworkError::TransportNotFound { .. })
+        ));
+        let relative = TorConfig {
+            transport_plugins: vec![PluggableTransport {
+                transports: vec![BridgeTransport::Snowflake],
+                binary: PathBuf::from("snowflake-client"),
+            }],
+            ..plugged
+        };
+        assert!(matches!(
+            relative.validate(),
+            Err(NetworkError::InvalidTorConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_torrc_snowflake_preset() {
+        use crate::temp_dir::TempDir;
+        use crate::transports::tests::{add_client, bridges, OBFS4};
+
+        let dir = TempDir::create("pt-snowflake");
+        let manager = TransportManager::new([dir.path()]);
+        assert_eq!(
+            TorConfig::default()
+                .with_snowflake(&manager)
+                .map_err(|e| e.to_string())
+                .err(),
+            Some("snowflake bridge configured but snowflake-client not found".to_string())
+        );
+
+        let snowflake = add_client(&dir, "snowflake-client", 0o755);
+        let config = TorConfig::default()
+            .with_snowflake(&manager)
+            .expect("client found");
+        config.validate().expect("valid");
+        let torrc = config.to_torrc();
+        assert!(torrc.contains(&format!(
+            "\nUseBridges 1\nClientTransportPlugin snowflake exec {}\n",
+            snowflake.display()
+        )));
+        assert_eq!(torrc.matches("\nBridge snowflake ").count(), 2);
+        assert!(torrc.contains(" fronts=www.cdn77.com,www.phpmyadmin.net "));
+        // Applying it twice adds nothing
+        let again = config
+            .clone()
+            .with_snowflake(&manager)
+            .expect("client found");
+        assert_eq!(again.to_torrc(), torrc);
+
+        // Manual bridges stay, with their own client
+        let obfs4proxy = add_client(&dir, "obfs4proxy", 0o755);
+        let config = TorConfig {
+            use_bridges: true,
+            bridges: bridges(&[OBFS4]),
+            ..TorConfig::default()
+        }
+        .with_snowflake(&manager)
+        .expect("clients found");
+        config.validate().expect("valid");
+        let torrc = config.to_torrc();
+        assert!(torrc.contains(&format!(
+            "\nClientTransportPlugin obfs4 exec {}\n",
+            obfs4proxy.display()
+        )));
+        assert!(torrc.contains(&format!(
+            "\nClientTransportPlugin snowflake exec {}\n",
+            snowflake.display()
+        )));
+        assert_eq!(torrc.matches("\nBridge obfs4 ").count(), 1);
+        assert_eq!(torrc.matches("\nBridge snowflake ").count(), 2);
+    }
+
+    #[test]
+    fn test_torrc_exclude_exit_countries() {
+        assert!(!TorConfig::default().to_torrc().contains("ExcludeExitNodes"));
+
+        let config = TorConfig {
+            strict_exit: false,
+            exclude_exit_countries: vec!["DE".to_string(), "US".to_string()],
+            ..TorConfig::default()
+        };
+        config.validate().expect("valid codes");
+        let torrc = config.to_torrc();
+        assert!(torrc.contains("\nExcludeExitNodes {de},{us}\n"));
+        assert_eq!(torrc.matches("StrictNodes 1\n").count(), 1);
+
+        let config = TorConfig {
+            exclude_exit_countries: vec!["DE".to_string()],
+            ..TorConfig::default()
+        };
+        let torrc = config.to_torrc();
+        assert!(torrc.contains("\nExcludeExitNodes {de}\n"));
+        assert_eq!(torrc.matches("StrictNodes 1\n").count(), 1);
+
+        let config = TorConfig {
+            exclude_exit_countries: vec!["DE".to_string(), "XX".to_string()],
+            ..TorConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(NetworkError::InvalidTorConfig(message)) if message.contains("XX")
+        ));
+    }
+
+    #[test]
+    fn test_torrc_values_cannot_inject_directives() {
+        TorConfig::default().validate().expect("defaults are valid");
+
+        for data_dir in [
+            "/tmp/forloop\nControlPort 0.0.0.0:9051",
+            "/tmp/forloop\r",
+            "relative/forloop",
+            "/tmp/for loop",
+            "/tmp/forloop#x",
+        ] {
+            let config = TorConfig {
+                data_dir: data_dir.to_string(),
+                ..TorConfig::default()
+            };
+            assert!(
+                matches!(config.validate(), Err(NetworkError::InvalidTorConfig(_))),
+                "{:?}",
+                data_dir
+            );
+        }
+
+        let config = TorConfig {
+            socks_port: Port::new(0),
+            ..TorConfig::default()
+        };
+        assert!(config.validate().is_err());
+        let config = TorConfig {
+            control_port: Port::new(9150),
+            ..TorConfig::default()
+        };
+        assert!(config.validate().is_err());
+
+        let bridge: BridgeLine = "meek_lite 192.0.2.18:80 url=https://meek.example/"
+            .parse()
+            .expect("valid bridge");
+        for value in ["x\n%include /etc/passwd", "x #hidden", "x y"] {
+            let mut bridge = bridge.clone();
+            bridge.args[0].1 = value.to_string();
+            let config = TorConfig {
+                use_bridges: true,
+                bridges: vec![bridge],
+                ..TorConfig::default()
+            };
+            assert!(
+                matches!(
+                    config.validate(),
+                    Err(NetworkError::InvalidTorConfig(message)) if message.contains("Bridge 1")
+                ),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_torrc_ends_with_its_hash() {
+        let torrc = TorConfig::default().to_torrc();
+        assert!(TorConfig::verify_torrc(&torrc));
+        let last = torrc.lines().last().expect("hash line");
+        assert!(last.starts_with(TORRC_HASH_PREFIX));
+        assert_eq!(last.len(), TORRC_HASH_PREFIX.len() + 64);
+
+        let tampered = torrc.replacen("SafeLogging 1", "SafeLogging 0", 1);
+        assert!(!TorConfig::verify_torrc(&tampered));
+        let (directives, hash) = torrc.split_at(torrc.rfind(TORRC_HASH_PREFIX).expect("hash"));
+        let appended = format!("{}ControlPort 0.0.0.0:9051\n{}", directives, hash);
+        assert!(!TorConfig::verify_torrc(&appended));
+        assert!(!TorConfig::verify_torrc(directives));
+        assert!(!TorConfig::verify_torrc(""));
+    }
 }
@@ -0,0 +1,550 @@
+//! The tor daemon, run as a child process.
+//!
+//! `TorProcess::spawn` writes the torrc for a `TorConfig` into its
+//! RAM-backed data directory and starts tor with `-f` pointing at it.
+//! tor logs to stderr (`Log notice stderr`); the lines are kept in a
+//! bounded in-memory `TorLog` and never reach the disk. tor is up once it
+//! reports its control listener open. Unix sockets it listens on are
+//! then made private to this user.
+//!
+//! The data directory holds tor's keys, consensus and auth cookie, and
+//! the state of any pluggable transport clients tor starts (see
+//! `transports`). When the process is dropped, or `kill_all_state` is
+//! called, tor is killed, its transport clients exit with it, and the
+//! directory is overwritten and deleted.
+
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use forloop_config::{secure_delete_dir, Port};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, Command};
+use tokio::sync::watch;
+
+#[cfg(unix)]
+use crate::socks::SocksEndpoint;
+use crate::tor_integration::TorConfig;
+use crate::NetworkError;
+
+/// The tor binary `TorController::new` runs, looked up on `PATH`.
+pub const TOR_BINARY: &str = "tor";
+
+/// How long tor may take to open its control port.
+pub const TOR_STARTUP_DEADLINE: Duration = Duration::from_secs(30);
+
+/// Lines of tor output kept in a `TorLog`.
+const LOG_CAPACITY: usize = 200;
+
+/// What tor logs once its control port accepts connections.
+const CONTROL_READY: &str = "Opened Control listener";
+
+/// What tor logs when a port it should listen on is taken.
+const ADDRESS_IN_USE: &str = "Address already in use";
+
+/// Name of the torrc inside the data directory.
+const TORRC_FILE: &str = "torrc";
+
+/// tor's recent log output, held in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct TorLog(Arc<Mutex<VecDeque<String>>>);
+
+impl TorLog {
+    /// The lines kept, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .map(|lines| lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn push(&self, line: String) {
+        if let Ok(mut lines) = self.0.lock() {
+            if lines.len() == LOG_CAPACITY {
+                lines.pop_front();
+            }
+            lines.push_back(line);
+        }
+    }
+}
+
+/// A running tor daemon and the data directory it owns.
+#[derive(Debug)]
+pub struct TorProcess {
+    child: Child,
+    data_dir: PathBuf,
+    log: TorLog,
+}
+
+impl TorProcess {
+    /// Start `binary` on `config` and wait up to `deadline` for its
+    /// control port.
+    ///
+    /// A data directory left behind by a crashed run is wiped first.
+    /// Fails with `InvalidTorConfig` or `TransportNotFound` if `config`
+    /// does not validate,
+    /// `TorPortInUse` if the SOCKS or control port is taken (when not on
+    /// Unix sockets),
+    /// `TorBinaryNotFound` if there is no `binary`, and `TorStartupFailed`
+    /// if tor exits or is not up in time. After a failure nothing is left
+    /// running and the data directory is gone.
+    pub async fn spawn(
+        binary: impl AsRef<Path>,
+        config: &TorConfig,
+        deadline: Duration,
+    ) -> Result<Self, NetworkError> {
+        config.validate()?;
+        if !config.unix_sockets {
+            for port in [config.socks_port, config.control_port] {
+                check_port_free(port)?;
+            }
+        }
+
+        let binary = binary.as_ref();
+        let data_dir = PathBuf::from(&config.data_dir);
+        let spawned = write_torrc(&data_dir, &config.to_torrc()).and_then(|torrc| {
+            Command::new(binary)
+                .arg("-f")
+                .arg(torrc)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+        });
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = wipe(&data_dir);
+                return Err(if e.kind() == io::ErrorKind::NotFound {
+                    NetworkError::TorBinaryNotFound(binary.display().to_string())
+                } else {
+                    NetworkError::TorStartupFailed(e.to_string())
+                });
+            }
+        };
+
+        let log = TorLog::default();
+        let (ready_tx, ready) = watch::channel(false);
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(read_log(stderr, log.clone(), ready_tx));
+        }
+        // From here on, dropping the process kills tor and wipes its data
+        let mut process = Self {
+            child,
+            data_dir,
+            log,
+        };
+        process.wait_until_ready(ready, deadline).await?;
+        #[cfg(unix)]
+        for socket in unix_sockets(config) {
+            make_private(&socket).map_err(|e| {
+                NetworkError::TorStartupFailed(format!("Socket {}: {}", socket.display(), e))
+            })?;
+        }
+        Ok(process)
+    }
+
+    /// Wait for tor to open its control port.
+    async fn wait_until_ready(
+        &mut self,
+        mut ready: watch::Receiver<bool>,
+        deadline: Duration,
+    ) -> Result<(), NetworkError> {
+        let outcome = tokio::time::timeout(deadline, async {
+            if ready.wait_for(|ready| *ready).await.is_ok() {
+                return Ok(());
+            }
+            // stderr closed before the control port came up: tor is exiting
+            Err(self.child.wait().await)
+        })
+        .await;
+
+        match outcome {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(status)) => Err(self.startup_failure(status)),
+            Err(_) => Err(NetworkError::TorStartupFailed(format!(
+                "Control port not up after {}s",
+                deadline.as_secs()
+            ))),
+        }
+    }
+
+    /// Work out from its log why tor exited during startup.
+    fn startup_failure(&self, status: io::Result<ExitStatus>) -> NetworkError {
+        let lines = self.log.lines();
+        if let Some(port) = lines.iter().find_map(|line| port_in_use(line)) {
+            return NetworkError::TorPortInUse(port);
+        }
+        let status = status.map_or_else(|e| e.to_string(), |status| status.to_string());
+        match lines.last() {
+            Some(line) => NetworkError::TorStartupFailed(format!("{} ({})", line, status)),
+            None => NetworkError::TorStartupFailed(status),
+        }
+    }
+
+    /// tor's log output so far.
+    pub fn log(&self) -> TorLog {
+        self.log.clone()
+    }
+
+    /// The data directory tor runs in.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Kill tor, wait for it to exit, then wipe its data directory.
+    pub async fn kill_all_state(mut self) -> io::Result<()> {
+        // Already exited is fine
+        let _ = self.child.kill().await;
+        wipe(&self.data_dir)
+    }
+}
+
+impl Drop for TorProcess {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+        if let Err(e) = wipe(&self.data_dir) {
+            log::warn!("Failed to wipe the Tor data directory: {}", e);
+        }
+    }
+}
+
+/// Fail with `TorPortInUse` if something already listens on `port`.
+fn check_port_free(port: Port) -> Result<(), NetworkError> {
+    match std::net::TcpListener::bind(("127.0.0.1", port.get())) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Err(NetworkError::TorPortInUse(port)),
+        // Anything else is for tor to report
+        _ => Ok(()),
+    }
+}
+
+/// Create `data_dir`, private to this user, and write `torrc` into it.
+///
+/// The file is read back and its hash checked, so tor is never started
+/// on a torrc other than the one written.
+fn write_torrc(data_dir: &Path, torrc: &str) -> io::Result<PathBuf> {
+    wipe(data_dir)?;
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(data_dir)?;
+
+    let path = data_dir.join(TORRC_FILE);
+    std::fs::write(&path, torrc)?;
+    if !TorConfig::verify_torrc(&std::fs::read_to_string(&path)?) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "torrc changed after it was written",
+        ));
+    }
+    Ok(path)
+}
+
+/// The Unix sockets tor listens on under `config`.
+#[cfg(unix)]
+fn unix_sockets(config: &TorConfig) -> Vec<PathBuf> {
+    let socks = match config.socks_endpoint() {
+        SocksEndpoint::Unix(path) => Some(path),
+        SocksEndpoint::Tcp(_) => None,
+    };
+    socks.into_iter().chain(config.control_socket()).collect()
+}
+
+/// Make the socket at `path` private to this user, if tor has opened it.
+///
+/// The data directory already is; this does not rely on tor's umask.
+#[cfg(unix)]
+fn make_private(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Overwrite and delete `data_dir`, if it exists.
+fn wipe(data_dir: &Path) -> io::Result<()> {
+    if data_dir.exists() {
+        secure_delete_dir(data_dir)?;
+    }
+    Ok(())
+}
+
+/// Copy tor's stderr into `log`, flagging `ready` once the control port
+/// is open.
+async fn read_log(stderr: ChildStderr, log: TorLog, ready: watch::Sender<bool>) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.contains(CONTROL_READY) {
+            ready.send_replace(true);
+        }
+        log.push(line);
+    }
+}
+
+/// The port in a tor "Could not bind to 127.0.0.1:9150: Address already
+/// in use" line.
+fn port_in_use(line: &str) -> Option<Port> {
+    let (bind, _) = line.split_once(ADDRESS_IN_USE)?;
+    let (_, port) = bind.trim_end_matches([':', ' ']).rsplit_once(':')?;
+    port.parse().ok().map(Port::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// Scripts are written and run one test at a time, so no test forks
+    /// while another still has its script open for writing.
+    static FAKE_TOR: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// Scratch directory holding a fake tor and its data directory.
+    struct Scratch(TempDir);
+
+    impl Scratch {
+        fn new(tag: &str) -> Self {
+            Self(TempDir::create(&format!("tor-{}", tag)))
+        }
+
+        /// Write an executable shell script standing in for tor.
+        fn fake_tor(&self, body: &str) -> PathBuf {
+            use std::os::unix::fs::PermissionsExt;
+
+            let path = self.0.path().join("tor");
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).expect("write fake tor");
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                .expect("make fake tor executable");
+            path
+        }
+
+        fn config(&self) -> TorConfig {
+            TorConfig {
+                data_dir: self.0.path().join("data").to_string_lossy().into_owned(),
+                socks_port: free_port(),
+                control_port: free_port(),
+                unix_sockets: false,
+                ..TorConfig::default()
+            }
+        }
+    }
+
+    fn free_port() -> Port {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        Port::new(listener.local_addr().expect("address").port())
+    }
+
+    #[tokio::test]
+    async fn test_fake_tor_spawned_and_cleaned_up() {
+        let _serial = FAKE_TOR.lock().await;
+        let scratch = Scratch::new("lifecycle");
+        // Checks it was given the torrc, keeps a key in the data directory
+        // and says its control port is up once it is
+        let tor = scratch.fake_tor(
+            r#"[ "$1" = "-f" ] || exit 2
+grep -q "^ControlPort" "$2" || exit 3
+echo "[notice] Bootstrapped 0% (starting): Starting" >&2
+sleep 0.2
+echo "secret" > "$(dirname "$2")/keys"
+echo "[notice] Opened Control listener connection (ready) on 127.0.0.1:9151" >&2
+exec sleep 30"#,
+        );
+        let config = scratch.config();
+
+        let started = std::time::Instant::now();
+        let process = TorProcess::spawn(&tor, &config, Duration::from_secs(10))
+            .await
+            .expect("fake tor starts");
+        // Waited for the control port, not just for the spawn
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        let data_dir = process.data_dir().to_path_buf();
+        assert!(data_dir.join("keys").exists());
+        let torrc = std::fs::read_to_string(data_dir.join(TORRC_FILE)).expect("torrc");
+        assert_eq!(torrc, config.to_torrc());
+        assert_eq!(process.log().lines().len(), 2);
+
+        process.kill_all_state().await.expect("wiped");
+        assert!(!data_dir.exists());
+
+        // Dropping cleans up just the same
+        let process = TorProcess::spawn(&tor, &config, Duration::from_secs(10))
+            .await
+            .expect("fake tor starts");
+        drop(process);
+        assert!(!data_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_startup_failures_are_told_apart() {
+        let _serial = FAKE_TOR.lock().await;
+        let scratch = Scratch::new("failures");
+        let config = scratch.config();
+        let data_dir = PathBuf::from(&config.data_dir);
+
+        let missing = TorProcess::spawn(
+            scratch.0.path().join("no-tor"),
+            &config,
+            Duration::from_secs(10),
+        );
+        assert!(matches!(
+            missing.await,
+            Err(NetworkError::TorBinaryNotFound(_))
+        ));
+        assert!(!data_dir.exists());
+
+        let tor = scratch.fake_tor(&format!(
+            r#"echo "[warn] Could not bind to 127.0.0.1:{}: Address already in use. Is Tor already running?" >&2
+exit 1"#,
+            config.control_port
+        ));
+        let taken = TorProcess::spawn(&tor, &config, Duration::from_secs(10)).await;
+        assert!(
+            matches!(taken, Err(NetworkError::TorPortInUse(port)) if port == config.control_port)
+        );
+        assert!(!data_dir.exists());
+
+        // Taken before tor even starts
+        let listener =
+            std::net::TcpListener::bind(("127.0.0.1", config.socks_port.get())).expect("bind");
+        let taken = TorProcess::spawn(&tor, &config, Duration::from_secs(10)).await;
+        assert!(
+            matches!(taken, Err(NetworkError::TorPortInUse(port)) if port == config.socks_port)
+        );
+        drop(listener);
+
+        let tor = scratch.fake_tor(
+            r#"echo "[err] Reading config failed--see warnings above." >&2
+exit 1"#,
+        );
+        match TorProcess::spawn(&tor, &config, Duration::from_secs(10)).await {
+            Err(NetworkError::TorStartupFailed(reason)) => {
+                assert!(reason.contains("Reading config failed"), "{}", reason)
+            }
+            other => panic!("expected a startup failure, got {:?}", other.map(|_| ())),
+        }
+        assert!(!data_dir.exists());
+
+        // Never opens its control port
+        let tor = scratch.fake_tor("exec sleep 30");
+        let silent = TorProcess::spawn(&tor, &config, Duration::from_millis(200)).await;
+        assert!(matches!(silent, Err(NetworkError::TorStartupFailed(_))));
+        assert!(!data_dir.exists());
+
+        // Refused before a torrc is written
+        let invalid = TorConfig {
+            exclude_exit_countries: vec!["ZZ".to_string()],
+            ..scratch.config()
+        };
+        let refused = TorProcess::spawn(&tor, &invalid, Duration::from_secs(10)).await;
+        assert!(matches!(refused, Err(NetworkError::InvalidTorConfig(_))));
+        assert!(!data_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_transport_clients_checked_before_startup() {
+        use crate::transports::tests::{add_client, bridges, OBFS4};
+        use crate::TransportManager;
+
+        let _serial = FAKE_TOR.lock().await;
+        let scratch = Scratch::new("transports");
+        let tor = scratch.fake_tor(
+            r#"grep -q "^ClientTransportPlugin obfs4 exec /" "$2" || exit 3
+echo "[notice] Opened Control listener connection (ready) on 127.0.0.1:9151" >&2
+exec sleep 30"#,
+        );
+        let config = TorConfig {
+            use_bridges: true,
+            bridges: bridges(&[OBFS4]),
+            ..scratch.config()
+        };
+        let data_dir = PathBuf::from(&config.data_dir);
+
+        let missing = TorProcess::spawn(&tor, &config, Duration::from_secs(10)).await;
+        assert!(matches!(
+            missing,
+            Err(NetworkError::TransportNotFound { ref binary, .. }) if binary == "obfs4proxy"
+        ));
+        assert!(!data_dir.exists());
+
+        let clients = TempDir::create("pt-spawn");
+        add_client(&clients, "lyrebird", 0o755);
+        let config = config
+            .with_transports(&TransportManager::new([clients.path()]))
+            .expect("client found");
+        let process = TorProcess::spawn(&tor, &config, Duration::from_secs(10))
+            .await
+            .expect("fake tor starts");
+        drop(process);
+        assert!(!data_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_unix_sockets_made_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let _serial = FAKE_TOR.lock().await;
+        let scratch = Scratch::new("sockets");
+        // Listens on sockets only, opened with a lax mode; a plain file
+        // stands in for the socket
+        let tor = scratch.fake_tor(
+            r#"grep -q "^ControlSocket .*/control.sock$" "$2" || exit 3
+grep -q "^SocksPort unix:.*/socks.sock " "$2" || exit 4
+grep -q "^ControlPort" "$2" && exit 5
+touch "$(dirname "$2")/socks.sock"
+chmod 666 "$(dirname "$2")/socks.sock"
+echo "[notice] Opened Control listener connection (ready) on $(dirname "$2")/control.sock" >&2
+exec sleep 30"#,
+        );
+        let config = TorConfig {
+            unix_sockets: true,
+            ..scratch.config()
+        };
+        // The TCP ports are not needed
+        let _taken =
+            std::net::TcpListener::bind(("127.0.0.1", config.socks_port.get())).expect("bind");
+
+        let process = TorProcess::spawn(&tor, &config, Duration::from_secs(10))
+            .await
+            .expect("fake tor starts");
+        let socks = process.data_dir().join("socks.sock");
+        let mode = std::fs::metadata(&socks)
+            .expect("socket")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let data_dir = process.data_dir().to_path_buf();
+        drop(process);
+        assert!(!data_dir.exists());
+    }
+
+    #[test]
+    fn test_port_in_use_parsed_from_log() {
+        assert_eq!(
+            port_in_use("[warn] Could not bind to 127.0.0.1:9150: Address already in use. Is Tor already running?"),
+            Some(Port::new(9150))
+        );
+        assert_eq!(
+            port_in_use("[warn] Could not bind to [::1]:9151: Address already in use."),
+            Some(Port::new(9151))
+        );
+        assert_eq!(port_in_use("[notice] Bootstrapped 5%"), None);
+    }
+
+    #[test]
+    fn test_log_keeps_recent_lines() {
+        let log = TorLog::default();
+        for i in 0..LOG_CAPACITY + 5 {
+            log.push(format!("line {}", i));
+        }
+        let lines = log.lines();
+        assert_eq!(lines.len(), LOG_CAPACITY);
+        assert_eq!(lines[0], "line 5");
+    }
+}
@@ -2,86 +2,269 @@
 //!
 //! This module adds padding and jitter to requests/responses
 //! to resist traffic analysis attacks.
-
+//!
+//! Cell-level padding is Tor's to do, so requests are padded at the HTTP
+//! level: a `PADDING_HEADER` of filler servers ignore. Bodies are never
+//! touched, so a form post arrives byte for byte as the page sent it.
+//!
+//! Write sizes leak request sizes to anyone watching the local side of the
+//! connection, so HTTP/1.1 requests go out through a `ShapedWriter`: in
+//! bursts of `SIZE_BUCKETS` sizes, the last one filled up, with jitter
+//! between them. Responses leak the same way, in the timing of the
+//! reads, so with read shaping on they come in through a `ShapedReader`.
+
+use forloop_config::clock::Sleep;
+use forloop_config::{system_clock, ByteSize, Clock};
 use rand::Rng;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+/// Header carrying request padding.
+pub const PADDING_HEADER: &str = "X-Padding";
+
+/// Fewest bytes of body and padding together in a padded request.
+pub const MIN_PADDED_REQUEST: usize = 512;
+
+/// Sizes outgoing data is normalized to.
+pub const SIZE_BUCKETS: &[usize] = &[512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+/// Largest burst a `ShapedWriter` sends; more is split into bursts of this
+/// size.
+pub const MAX_BURST: usize = 4096;
+
+/// Chunk extension carrying padding in a chunked request body; servers
+/// ignore extensions they do not know (RFC 9112 section 7.1.1).
+pub const CHUNK_PADDING_EXT: &str = "pad";
+
+/// Filler characters; base64's alphabet, valid in any header value.
+const FILLER: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 
 /// Traffic shaper that adds padding and delays.
 pub struct TrafficShaper {
-    min_padding: usize,
-    max_padding: usize,
-    min_jitter_ms: u64,
-    max_jitter_ms: u64,
+    min_padding: ByteSize,
+    max_padding: ByteSize,
+    min_jitter: Duration,
+    max_jitter: Duration,
+    clock: Arc<dyn Clock>,
+    padded_bytes: AtomicU64,
+    read_delays: Option<(Duration, Duration)>,
 }
 
 impl TrafficShaper {
     /// Create a new traffic shaper.
     pub fn new(
-        min_padding: usize,
-        max_padding: usize,
-        min_jitter_ms: u64,
-        max_jitter_ms: u64,
+        min_padding: ByteSize,
+        max_padding: ByteSize,
+        min_jitter: Duration,
+        max_jitter: Duration,
     ) -> Self {
         Self {
             min_padding,
             max_padding,
-            min_jitter_ms,
-            max_jitter_ms,
+            min_jitter,
+            max_jitter,
+            clock: system_clock(),
+            padded_bytes: AtomicU64::new(0),
+            read_delays: None,
         }
     }
 
-    /// Add random padding to a request body.
-    pub fn pad_request(&self, body: &[u8]) -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-        let padding_size = rng.gen_range(self.min_padding..=self.max_padding);
+    /// Shape response reads, waiting between `min_delay` and `max_delay`
+    /// between chunks (see `ShapedReader`).
+    pub fn with_read_shaping(mut self, min_delay: Duration, max_delay: Duration) -> Self {
+        self.read_delays = Some((min_delay, max_delay));
+        self
+    }
 
-        // Padding is added as a custom header or in a way that
-        // doesn't affect the request semantics.
-        // For HTTP, we use a custom X-Padding header that servers ignore.
-        //
-        // In practice, this padding would be applied at the Tor cell level
-        // rather than HTTP level for better resistance.
+    /// Whether response reads are shaped.
+    pub fn shapes_reads(&self) -> bool {
+        self.read_delays.is_some()
+    }
 
-        let mut padded = body.to_vec();
+    /// Sleep on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 
-        // For non-empty bodies, we can extend. For empty, padding
-        // happens at transport layer.
-        if !padded.is_empty() {
-            // Add random bytes (will be stripped or ignored)
-            // This is illustrative - real implementation at cell level
-            log::trace!("Added {} bytes padding", padding_size);
-        }
+    /// Pad a request with a body of `body_len` bytes (0 for none) by
+    /// adding a `PADDING_HEADER` to its `headers`.
+    ///
+    /// The filler is between the minimum and maximum padding long, and
+    /// longer if that is needed to reach `MIN_PADDED_REQUEST`. Padding
+    /// already in `headers`, ours or the page's, is replaced rather than
+    /// added to. Returns the filler length.
+    pub fn pad_request(&self, headers: &mut Vec<(String, String)>, body_len: usize) -> usize {
+        strip_padding(headers);
 
-        padded
+        let mut rng = rand::thread_rng();
+        let min = self.min_padding.get();
+        let max = self.max_padding.get().max(min);
+        let size = rng
+            .gen_range(min..=max)
+            .max(MIN_PADDED_REQUEST.saturating_sub(body_len));
+        let filler: String = (0..size)
+            .map(|_| FILLER[rng.gen_range(0..FILLER.len())] as char)
+            .collect();
+        headers.push((PADDING_HEADER.to_string(), filler));
+
+        log::trace!("Added {} bytes padding", size);
+        self.padded_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        size
+    }
+
+    /// Frame `data` as one chunk of a chunked request body, padded with
+    /// a `CHUNK_PADDING_EXT` extension so the framed chunk fills its size
+    /// bucket.
+    pub fn pad_chunk(&self, data: &[u8]) -> Vec<u8> {
+        let size_line = format!("{:x};{}=", data.len(), CHUNK_PADDING_EXT);
+        let framed = size_line.len() + data.len() + 4;
+        // An extension value is a token, so at least one character
+        let size = normalize_size(framed + 1) - framed;
+
+        let mut rng = rand::thread_rng();
+        let mut chunk = Vec::with_capacity(framed + size);
+        chunk.extend_from_slice(size_line.as_bytes());
+        // The alphanumeric part of the filler; '+' and '/' end a token
+        chunk.extend((0..size).map(|_| FILLER[rng.gen_range(0..62)]));
+        chunk.extend_from_slice(b"\r\n");
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(b"\r\n");
+
+        self.padded_bytes.fetch_add(size as u64, Ordering::Relaxed);
+        chunk
+    }
+
+    /// Total padding bytes added so far.
+    pub fn padding_overhead(&self) -> u64 {
+        self.padded_bytes.load(Ordering::Relaxed)
     }
 
     /// Apply random jitter delay.
     pub async fn apply_jitter(&self) {
-        if self.max_jitter_ms == 0 {
+        if self.max_jitter.is_zero() {
             return;
         }
 
-        let mut rng = rand::thread_rng();
-        let jitter_ms = rng.gen_range(self.min_jitter_ms..=self.max_jitter_ms);
+        let jitter = random_jitter(self.min_jitter, self.max_jitter);
+
+        if !jitter.is_zero() {
+            self.clock.sleep(jitter).await;
+            log::trace!("Applied {}ms jitter", jitter.as_millis());
+        }
+    }
 
-        if jitter_ms > 0 {
-            tokio::time::sleep(Duration::from_millis(jitter_ms)).await;
-            log::trace!("Applied {}ms jitter", jitter_ms);
+    /// Wrap `inner` in a `ShapedWriter` with this shaper's jitter between
+    /// bursts, filling the last one with `filler`.
+    pub fn shape<W>(&self, inner: W, filler: u8) -> ShapedWriter<W> {
+        ShapedWriter {
+            inner,
+            buffer: Vec::new(),
+            filler,
+            min_delay: self.min_jitter,
+            max_delay: self.max_jitter,
+            clock: Arc::clone(&self.clock),
+            bursts: 0,
         }
     }
 
     /// Apply synchronous jitter (for non-async contexts).
     pub fn apply_jitter_sync(&self) {
-        if self.max_jitter_ms == 0 {
+        if self.max_jitter.is_zero() {
             return;
         }
 
-        let mut rng = rand::thread_rng();
-        let jitter_ms = rng.gen_range(self.min_jitter_ms..=self.max_jitter_ms);
+        let jitter = random_jitter(self.min_jitter, self.max_jitter);
+
+        if !jitter.is_zero() {
+            self.clock.sleep_blocking(jitter);
+        }
+    }
+}
+
+impl TrafficShaper {
+    /// Wrap `inner` in a `ShapedReader` with this shaper's read delays;
+    /// without read shaping, reads are coalesced but not delayed.
+    pub fn shape_reads<R>(&self, inner: R) -> ShapedReader<R> {
+        let (min_delay, max_delay) = self.read_delays.unwrap_or_default();
+        ShapedReader {
+            inner,
+            chunk: Vec::with_capacity(MAX_BURST),
+            pos: 0,
+            eof: false,
+            min_delay,
+            max_delay,
+            clock: Arc::clone(&self.clock),
+            delay: None,
+        }
+    }
+}
+
+/// Pick a delay between `min` and `max`, at millisecond granularity.
+fn random_jitter(min: Duration, max: Duration) -> Duration {
+    let mut rng = rand::thread_rng();
+    let min_ms = min.as_millis() as u64;
+    let max_ms = (max.as_millis() as u64).max(min_ms);
+    Duration::from_millis(rng.gen_range(min_ms..=max_ms))
+}
+
+/// Writer that sends what it is given in bursts of bucketed sizes.
+///
+/// Bytes are held until a full `MAX_BURST` is buffered. `finish` sends
+/// the rest filled up to its bucket with the filler byte, which the
+/// protocol on top must ignore. Every write the inner writer sees is
+/// therefore a `SIZE_BUCKETS` size, as long as it takes whole writes.
+pub struct ShapedWriter<W> {
+    inner: W,
+    buffer: Vec<u8>,
+    filler: u8,
+    min_delay: Duration,
+    max_delay: Duration,
+    clock: Arc<dyn Clock>,
+    bursts: usize,
+}
+
+impl<W: AsyncWrite + Unpin> ShapedWriter<W> {
+    /// Queue `data`, sending every full burst.
+    pub async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= MAX_BURST {
+            self.burst(MAX_BURST).await?;
+        }
+        Ok(())
+    }
 
-        if jitter_ms > 0 {
-            std::thread::sleep(Duration::from_millis(jitter_ms));
+    /// Send what is left, filled up to its bucket, and flush.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let size = normalize_size(self.buffer.len());
+            self.buffer.resize(size, self.filler);
+            self.burst(size).await?;
         }
+        self.inner.flush().await
+    }
+
+    /// Get the inner writer back; anything not finished is dropped.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    async fn burst(&mut self, size: usize) -> io::Result<()> {
+        if self.bursts > 0 {
+            let delay = random_jitter(self.min_delay, self.max_delay);
+            if !delay.is_zero() {
+                self.clock.sleep(delay).await;
+            }
+        }
+        self.inner.write_all(&self.buffer[..size]).await?;
+        self.buffer.drain(..size);
+        self.bursts += 1;
+        Ok(())
     }
 }
 
@@ -93,8 +276,10 @@ pub struct PaddingGenerator {
 
 impl PaddingGenerator {
     /// Create a new padding generator.
-    pub fn new(target_size: usize) -> Self {
-        Self { target_size }
+    pub fn new(target_size: ByteSize) -> Self {
+        Self {
+            target_size: target_size.get(),
+        }
     }
 
     /// Generate padding bytes.
@@ -121,33 +306,126 @@ impl PaddingGenerator {
 impl Default for PaddingGenerator {
     fn default() -> Self {
         // Tor cell size is 514 bytes (512 payload + 2 header)
-        Self::new(512)
+        Self::new(ByteSize::bytes(512))
+    }
+}
+
+/// Reader that hands out what arrives in chunks, with a delay between.
+///
+/// Whatever the inner reader has ready, up to `MAX_BURST` bytes, is
+/// gathered into one chunk; the next chunk is not read until this one is
+/// consumed and a random delay has passed. Reads above it see the
+/// shaper's timing rather than the network's. Writes pass straight
+/// through, so it can wrap a whole connection.
+pub struct ShapedReader<R> {
+    inner: R,
+    chunk: Vec<u8>,
+    pos: usize,
+    eof: bool,
+    min_delay: Duration,
+    max_delay: Duration,
+    clock: Arc<dyn Clock>,
+    delay: Option<Sleep>,
+}
+
+impl<R: AsyncRead + Unpin> ShapedReader<R> {
+    /// Gather what the inner reader has ready into `chunk`.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut space = [0u8; MAX_BURST];
+        while !self.eof && self.chunk.len() < MAX_BURST {
+            let mut read = ReadBuf::new(&mut space[..MAX_BURST - self.chunk.len()]);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut read) {
+                Poll::Ready(Ok(())) if read.filled().is_empty() => self.eof = true,
+                Poll::Ready(Ok(())) => self.chunk.extend_from_slice(read.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending if self.chunk.is_empty() => return Poll::Pending,
+                Poll::Pending => break,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ShapedReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos == this.chunk.len() {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            this.chunk.clear();
+            this.pos = 0;
+            ready!(this.poll_fill(cx))?;
+            if this.chunk.is_empty() {
+                // End of stream
+                return Poll::Ready(Ok(()));
+            }
+        }
+
+        let n = buf.remaining().min(this.chunk.len() - this.pos);
+        buf.put_slice(&this.chunk[this.pos..this.pos + n]);
+        this.pos += n;
+        if this.pos == this.chunk.len() {
+            let delay = random_jitter(this.min_delay, this.max_delay);
+            if !delay.is_zero() {
+                this.delay = Some(this.clock.sleep(delay));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncWrite + Unpin> AsyncWrite for ShapedReader<R> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
     }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Remove padding from `headers`, returning how many headers went.
+pub fn strip_padding(headers: &mut Vec<(String, String)>) -> usize {
+    let before = headers.len();
+    headers.retain(|(name, _)| !name.eq_ignore_ascii_case(PADDING_HEADER));
+    before - headers.len()
 }
 
 /// Normalize packet sizes to fixed buckets.
 /// This reduces the information leaked by packet sizes.
 pub fn normalize_size(size: usize) -> usize {
-    // Size buckets: 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536
-    const BUCKETS: &[usize] = &[512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
-
-    for &bucket in BUCKETS {
+    for &bucket in SIZE_BUCKETS {
         if size <= bucket {
             return bucket;
         }
     }
 
     // For very large sizes, round up to nearest 64KB
-    ((size + 65535) / 65536) * 65536
+    size.div_ceil(65536) * 65536
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use forloop_config::clock::ManualClock;
 
     #[test]
     fn test_padding_generator() {
-        let gen = PaddingGenerator::new(512);
+        let gen = PaddingGenerator::new(ByteSize::bytes(512));
 
         let padding = gen.generate(100);
         assert_eq!(padding.len(), 412);
@@ -169,11 +447,307 @@ mod tests {
         assert_eq!(normalize_size(100000), 131072);
     }
 
+    #[test]
+    fn test_padding_overhead_counts_padding() {
+        let shaper = padding_shaper(100, 200);
+        assert_eq!(shaper.padding_overhead(), 0);
+
+        // Bodiless requests are padded too
+        let first = shaper.pad_request(&mut Vec::new(), 0);
+        let second = shaper.pad_request(&mut Vec::new(), 600);
+        assert_eq!(shaper.padding_overhead(), (first + second) as u64);
+        assert_eq!(first, MIN_PADDED_REQUEST);
+        assert!((100..=200).contains(&second));
+    }
+
+    #[test]
+    fn test_chunks_padded_to_buckets() {
+        let shaper = padding_shaper(0, 0);
+        for len in [0, 1, 500, 16 * 1024 - 64] {
+            let data = vec![b'x'; len];
+            let chunk = shaper.pad_chunk(&data);
+            assert_eq!(chunk.len(), normalize_size(chunk.len()));
+
+            // The size line, then the data as is
+            let line_end = chunk
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .expect("size line");
+            let line = std::str::from_utf8(&chunk[..line_end]).expect("ASCII");
+            let (size, ext) = line.split_once(';').expect("extension");
+            assert_eq!(usize::from_str_radix(size, 16).expect("hex size"), len);
+            let value = ext.strip_prefix("pad=").expect("padding");
+            assert!(!value.is_empty() && value.bytes().all(|b| b.is_ascii_alphanumeric()));
+            assert_eq!(&chunk[line_end + 2..chunk.len() - 2], &data[..]);
+            assert!(chunk.ends_with(b"\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_padded_size_distribution() {
+        let shaper = padding_shaper(600, 900);
+        let sizes: Vec<usize> = (0..500)
+            .map(|_| shaper.pad_request(&mut Vec::new(), 10))
+            .collect();
+        assert!(sizes.iter().all(|size| (600..=900).contains(size)));
+        // Spread over the range, not stuck at either end
+        assert!(sizes.iter().any(|&size| size < 700));
+        assert!(sizes.iter().any(|&size| size > 800));
+
+        // Small requests still reach the minimum
+        let shaper = padding_shaper(16, 32);
+        for body_len in [0, 5, 100, 496, 500] {
+            let size = shaper.pad_request(&mut Vec::new(), body_len);
+            assert!(body_len + size >= MIN_PADDED_REQUEST, "{}", body_len);
+        }
+        assert!((16..=32).contains(&shaper.pad_request(&mut Vec::new(), 5000)));
+    }
+
+    #[test]
+    fn test_padding_preserves_request() {
+        let shaper = padding_shaper(100, 200);
+        let original = vec![
+            ("Accept".to_string(), "*/*".to_string()),
+            (
+                "Content-Type".to_string(),
+                "application/x-www-form-urlencoded".to_string(),
+            ),
+        ];
+
+        // A page's own padding header is replaced, and padding twice
+        // leaves one
+        let mut headers = original.clone();
+        headers.push(("x-padding".to_string(), "page".to_string()));
+        shaper.pad_request(&mut headers, 11);
+        shaper.pad_request(&mut headers, 11);
+        assert_eq!(headers[..2], original[..]);
+        assert_eq!(headers.len(), 3);
+        let (name, filler) = &headers[2];
+        assert_eq!(name, PADDING_HEADER);
+        assert!(filler.len() + 11 >= MIN_PADDED_REQUEST);
+        assert!(filler.bytes().all(|b| FILLER.contains(&b)));
+        crate::headers::check_header(name, filler).expect("valid header");
+
+        assert_eq!(strip_padding(&mut headers), 1);
+        assert_eq!(headers, original);
+    }
+
+    /// Writer recording the length of every write it is given.
+    struct Recorder {
+        inner: tokio::io::DuplexStream,
+        writes: Vec<usize>,
+    }
+
+    impl AsyncWrite for Recorder {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            let written = std::pin::Pin::new(&mut self.inner).poll_write(cx, buf);
+            if let std::task::Poll::Ready(Ok(n)) = written {
+                self.writes.push(n);
+            }
+            written
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shaped_writes_are_bucketed() {
+        use tokio::io::AsyncReadExt;
+
+        let shaper = padding_shaper(0, 0);
+        for chunks in [
+            vec![1],
+            vec![511, 1],
+            vec![700],
+            vec![100, 3000, 2000],
+            vec![4096],
+            vec![10_000, 1, 1],
+            vec![5; 2000],
+        ] {
+            let (client, mut server) = tokio::io::duplex(1 << 20);
+            let recorder = Recorder {
+                inner: client,
+                writes: Vec::new(),
+            };
+            let mut writer = shaper.shape(recorder, b'\n');
+            let mut sent = Vec::new();
+            for (i, &len) in chunks.iter().enumerate() {
+                let data = vec![i as u8 % 251 + 1; len];
+                writer.write(&data).await.expect("write");
+                sent.extend_from_slice(&data);
+            }
+            writer.finish().await.expect("finish");
+            let recorder = writer.into_inner();
+
+            assert!(
+                recorder.writes.iter().all(|n| SIZE_BUCKETS.contains(n)),
+                "{:?} for {:?}",
+                recorder.writes,
+                chunks
+            );
+            let total: usize = recorder.writes.iter().sum();
+            assert!(total >= sent.len());
+
+            drop(recorder);
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).await.expect("read");
+            assert_eq!(received.len(), total);
+            assert_eq!(received[..sent.len()], sent[..]);
+            assert!(received[sent.len()..].iter().all(|&b| b == b'\n'));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shaped_bursts_are_jittered() {
+        let clock = ManualClock::new();
+        let shaper = shaper(30, 30, &clock);
+        let (client, _server) = tokio::io::duplex(1 << 16);
+        let mut writer = shaper.shape(client, 0);
+
+        // The first burst goes at once, the next waits its delay
+        let bursts = tokio::spawn(async move {
+            writer.write(&[1; MAX_BURST * 2]).await.expect("write");
+            writer.finish().await.expect("finish");
+            writer.into_inner()
+        });
+        tokio::task::yield_now().await;
+        clock.advance(Duration::from_millis(29));
+        tokio::task::yield_now().await;
+        assert!(!bursts.is_finished());
+
+        clock.advance(Duration::from_millis(1));
+        bursts.await.expect("bursts sent");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_read_intervals_jittered_within_bounds() {
+        use tokio::io::AsyncReadExt;
+        use tokio::time::Instant;
+
+        let (min, max) = (Duration::from_millis(5), Duration::from_millis(40));
+        let shaper = padding_shaper(0, 0).with_read_shaping(min, max);
+        let (mut server, client) = tokio::io::duplex(1 << 20);
+        let sent: Vec<u8> = (0..MAX_BURST * 60).map(|i| i as u8).collect();
+        server.write_all(&sent).await.expect("response");
+        drop(server);
+
+        let mut reader = shaper.shape_reads(client);
+        let (mut received, mut intervals) = (Vec::new(), Vec::new());
+        let mut last = Instant::now();
+        let mut buf = vec![0u8; 3 * MAX_BURST];
+        loop {
+            let n = reader.read(&mut buf).await.expect("read");
+            if n == 0 {
+                break;
+            }
+            // Reads are coalesced into full chunks, never larger
+            assert_eq!(n, MAX_BURST);
+            received.extend_from_slice(&buf[..n]);
+            intervals.push(last.elapsed());
+            last = Instant::now();
+        }
+        assert_eq!(received, sent);
+
+        // The first chunk is not delayed
+        let intervals = &intervals[1..];
+        assert!(
+            intervals.iter().all(|i| (min..=max).contains(i)),
+            "{:?}",
+            intervals
+        );
+        let millis: Vec<f64> = intervals.iter().map(|i| i.as_millis() as f64).collect();
+        let mean = millis.iter().sum::<f64>() / millis.len() as f64;
+        let variance = millis.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / millis.len() as f64;
+        assert!(variance > 0.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shaped_reads_keep_size_cap() {
+        use crate::http_response::{read_response, ResponseLimits};
+        use crate::NetworkError;
+        use tokio::io::BufReader;
+
+        let shaper = padding_shaper(0, 0)
+            .with_read_shaping(Duration::from_millis(1), Duration::from_millis(3));
+        let shaper = &shaper;
+        let read = |body_len: usize| async move {
+            let (mut server, client) = tokio::io::duplex(1 << 16);
+            let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body_len);
+            server.write_all(head.as_bytes()).await.expect("head");
+            server.write_all(&vec![b'x'; body_len]).await.expect("body");
+            drop(server);
+
+            let mut reader = BufReader::new(shaper.shape_reads(client));
+            let limits = ResponseLimits::default();
+            read_response(&mut reader, "GET", &limits, ByteSize::bytes(10_000)).await
+        };
+
+        let response = read(9_000).await.expect("under the cap");
+        assert_eq!(response.body.len(), 9_000);
+        assert!(matches!(
+            read(20_000).await,
+            Err(NetworkError::ResponseTooLarge { limit: 10_000, .. })
+        ));
+    }
+
+    fn padding_shaper(min: usize, max: usize) -> TrafficShaper {
+        TrafficShaper::new(
+            ByteSize::bytes(min),
+            ByteSize::bytes(max),
+            Duration::ZERO,
+            Duration::ZERO,
+        )
+    }
+
+    fn shaper(min_jitter_ms: u64, max_jitter_ms: u64, clock: &ManualClock) -> TrafficShaper {
+        TrafficShaper::new(
+            ByteSize::bytes(100),
+            ByteSize::bytes(200),
+            Duration::from_millis(min_jitter_ms),
+            Duration::from_millis(max_jitter_ms),
+        )
+        .with_clock(Arc::new(clock.clone()))
+    }
+
     #[test]
     fn test_traffic_shaper_jitter_sync() {
-        let shaper = TrafficShaper::new(100, 200, 0, 5);
+        let clock = ManualClock::new();
+        shaper(0, 5, &clock).apply_jitter_sync();
+        assert!(clock.elapsed() <= Duration::from_millis(5));
+
+        let before = clock.elapsed();
+        shaper(20, 20, &clock).apply_jitter_sync();
+        assert_eq!(clock.elapsed() - before, Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_traffic_shaper_jitter_waits_on_clock() {
+        let clock = ManualClock::new();
+        let shaper = shaper(40, 40, &clock);
+
+        let jitter = tokio::spawn(async move { shaper.apply_jitter().await });
+        tokio::task::yield_now().await;
+        clock.advance(Duration::from_millis(39));
+        tokio::task::yield_now().await;
+        assert!(!jitter.is_finished());
 
-        // This should not panic
-        shaper.apply_jitter_sync();
+        clock.advance(Duration::from_millis(1));
+        jitter.await.expect("jitter completes");
     }
 }
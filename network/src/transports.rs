@@ -0,0 +1,244 @@
+//! Pluggable transport clients.
+//!
+//! A bridge reached through obfs4, meek_lite or snowflake is no use until
+//! tor can start the client for that transport, which it learns from a
+//! `ClientTransportPlugin <transports> exec <binary>` line. tor runs the
+//! client as its own child and closes its stdin on exit, so the client
+//! never outlives the `TorProcess`; the client's state goes under the
+//! data directory (`pt_state`), in RAM, and is wiped with it.
+//!
+//! `TransportManager` finds the clients in a list of directories. Only
+//! transports some bridge uses get a line, and a missing or
+//! non-executable client is reported before tor starts instead of as a
+//! bootstrap that never finishes.
+
+use std::path::{Path, PathBuf};
+
+use crate::bridges::{BridgeLine, BridgeTransport};
+use crate::NetworkError;
+
+/// Directories searched for transport clients by default, in order.
+pub const PT_SEARCH_PATHS: &[&str] = &["/usr/local/bin", "/usr/bin"];
+
+/// A transport client and the transports tor starts it for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluggableTransport {
+    /// Transports it serves, in torrc order
+    pub transports: Vec<BridgeTransport>,
+    /// Absolute path of the executable
+    pub binary: PathBuf,
+}
+
+impl PluggableTransport {
+    /// The `ClientTransportPlugin` torrc line for `transports`, or `None`
+    /// if this client serves none of them.
+    pub fn torrc_line(&self, transports: &[BridgeTransport]) -> Option<String> {
+        let names: Vec<&str> = self
+            .transports
+            .iter()
+            .filter(|transport| transports.contains(transport))
+            .filter_map(|transport| transport.name())
+            .collect();
+        if names.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "ClientTransportPlugin {} exec {}",
+            names.join(","),
+            self.binary.display()
+        ))
+    }
+}
+
+/// Finds transport clients in a list of directories.
+#[derive(Debug, Clone)]
+pub struct TransportManager {
+    search_paths: Vec<PathBuf>,
+}
+
+impl Default for TransportManager {
+    fn default() -> Self {
+        Self::new(PT_SEARCH_PATHS)
+    }
+}
+
+impl TransportManager {
+    /// Search `search_paths`, in order.
+    pub fn new<P: Into<PathBuf>>(search_paths: impl IntoIterator<Item = P>) -> Self {
+        Self {
+            search_paths: search_paths.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// The first executable client for `transport` in the search paths;
+    /// `None` for vanilla bridges or if there is none.
+    pub fn find(&self, transport: BridgeTransport) -> Option<PathBuf> {
+        client_names(transport).iter().find_map(|name| {
+            self.search_paths
+                .iter()
+                .map(|dir| dir.join(name))
+                .find(|path| is_executable(path))
+        })
+    }
+
+    /// Clients for every transport `bridges` use, one per binary.
+    ///
+    /// Fails with `TransportNotFound` for the first transport there is no
+    /// client for.
+    pub fn plugins_for(
+        &self,
+        bridges: &[BridgeLine],
+    ) -> Result<Vec<PluggableTransport>, NetworkError> {
+        let mut plugins: Vec<PluggableTransport> = Vec::new();
+        for transport in used_transports(bridges) {
+            let binary = self.find(transport).ok_or_else(|| not_found(transport))?;
+            match plugins.iter_mut().find(|plugin| plugin.binary == binary) {
+                Some(plugin) => plugin.transports.push(transport),
+                None => plugins.push(PluggableTransport {
+                    transports: vec![transport],
+                    binary,
+                }),
+            }
+        }
+        Ok(plugins)
+    }
+}
+
+/// Transports other than vanilla that `bridges` use, each once.
+pub(crate) fn used_transports(bridges: &[BridgeLine]) -> Vec<BridgeTransport> {
+    let mut transports = Vec::new();
+    for bridge in bridges {
+        if bridge.transport != BridgeTransport::Vanilla && !transports.contains(&bridge.transport) {
+            transports.push(bridge.transport);
+        }
+    }
+    transports
+}
+
+/// `TransportNotFound` for `transport`, naming its usual client.
+pub(crate) fn not_found(transport: BridgeTransport) -> NetworkError {
+    NetworkError::TransportNotFound {
+        transport: transport.name().unwrap_or("vanilla").to_string(),
+        binary: client_names(transport)
+            .first()
+            .copied()
+            .unwrap_or("tor")
+            .to_string(),
+    }
+}
+
+/// File names of the clients serving `transport`, best known first.
+fn client_names(transport: BridgeTransport) -> &'static [&'static str] {
+    match transport {
+        BridgeTransport::Vanilla => &[],
+        // lyrebird is obfs4proxy's successor and serves the same transports
+        BridgeTransport::Obfs4 | BridgeTransport::MeekLite => &["obfs4proxy", "lyrebird"],
+        BridgeTransport::Snowflake => &["snowflake-client"],
+    }
+}
+
+/// Whether `path` is a regular file this user may execute.
+pub(crate) fn is_executable(path: &Path) -> bool {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// Add a fake transport client called `name` to `dir`, with permission
+    /// bits `mode`.
+    pub(crate) fn add_client(dir: &TempDir, name: &str, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.path().join(name);
+        std::fs::write(&path, "#!/bin/sh\n").expect("write client");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))
+            .expect("set client mode");
+        path
+    }
+
+    pub(crate) fn bridges(lines: &[&str]) -> Vec<BridgeLine> {
+        crate::parse_bridge_lines(lines.iter().copied()).expect("valid bridges")
+    }
+
+    pub(crate) const OBFS4: &str =
+        "obfs4 192.0.2.3:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=AAAA iat-mode=0";
+    pub(crate) const MEEK: &str = "meek_lite 192.0.2.18:80 url=https://meek.example/";
+    pub(crate) const SNOWFLAKE: &str = "snowflake 192.0.2.4:80 url=https://snowflake.example/";
+
+    #[test]
+    fn test_clients_found_per_binary() {
+        let first = TempDir::create("pt-first");
+        let second = TempDir::create("pt-second");
+        add_client(&second, "snowflake-client", 0o755);
+        let lyrebird = add_client(&second, "lyrebird", 0o755);
+        // Not executable, so skipped
+        add_client(&first, "obfs4proxy", 0o644);
+        let manager = TransportManager::new([first.path(), second.path()]);
+
+        assert_eq!(manager.find(BridgeTransport::Obfs4), Some(lyrebird.clone()));
+        assert_eq!(manager.find(BridgeTransport::Vanilla), None);
+
+        let plugins = manager
+            .plugins_for(&bridges(&["192.0.2.1:9001", OBFS4, MEEK, OBFS4]))
+            .expect("clients found");
+        assert_eq!(
+            plugins,
+            [PluggableTransport {
+                transports: vec![BridgeTransport::Obfs4, BridgeTransport::MeekLite],
+                binary: lyrebird.clone(),
+            }]
+        );
+        assert_eq!(
+            plugins[0].torrc_line(&[BridgeTransport::MeekLite, BridgeTransport::Obfs4]),
+            Some(format!(
+                "ClientTransportPlugin obfs4,meek_lite exec {}",
+                lyrebird.display()
+            ))
+        );
+        assert_eq!(
+            plugins[0].torrc_line(&[BridgeTransport::MeekLite]),
+            Some(format!(
+                "ClientTransportPlugin meek_lite exec {}",
+                lyrebird.display()
+            ))
+        );
+        assert_eq!(plugins[0].torrc_line(&[BridgeTransport::Snowflake]), None);
+
+        // Vanilla bridges need no client
+        assert_eq!(
+            TransportManager::new(Vec::<PathBuf>::new())
+                .plugins_for(&bridges(&["192.0.2.1:9001"]))
+                .expect("nothing needed"),
+            []
+        );
+    }
+
+    #[test]
+    fn test_missing_client_named() {
+        let dir = TempDir::create("pt-missing");
+        add_client(&dir, "snowflake-client", 0o755);
+        let manager = TransportManager::new([dir.path()]);
+
+        let error = manager
+            .plugins_for(&bridges(&[SNOWFLAKE, OBFS4]))
+            .expect_err("no obfs4 client");
+        assert_eq!(
+            error.to_string(),
+            "obfs4 bridge configured but obfs4proxy not found"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! Request body size cap and chunked uploads with progress.
+//!
+//! A page (or a user attaching a file) must not be able to stream an
+//! unbounded body out through a circuit unnoticed. Bodies above
+//! `NetworkConfig::max_request_size` are refused before any byte is
+//! sent; accepted bodies are written in fixed-size chunks, reporting
+//! progress after each one and checking for cancellation in between.
+//!
+//! A body need not be in memory: a `RequestBody::Stream` is read from an
+//! `AsyncRead` as it is sent, so a file from the download directory goes
+//! out without being buffered whole. One of unknown length is sent with
+//! chunked transfer-encoding, and the cap is enforced as it is read.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncRead;
+
+use crate::NetworkError;
+
+/// Bytes written per chunk; one progress event is emitted per chunk.
+pub const UPLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Body bytes sent per piece of a streamed body. With its chunk framing
+/// and some padding, a piece fills the 16 KiB size bucket.
+pub const STREAM_CHUNK_BYTES: usize = 16 * 1024 - 64;
+
+/// A request body.
+#[derive(Debug)]
+pub enum RequestBody {
+    /// The whole body, in memory
+    Bytes(Vec<u8>),
+    /// A body read as it is sent
+    Stream(BodyStream),
+}
+
+impl From<Vec<u8>> for RequestBody {
+    fn from(body: Vec<u8>) -> Self {
+        RequestBody::Bytes(body)
+    }
+}
+
+impl From<&[u8]> for RequestBody {
+    fn from(body: &[u8]) -> Self {
+        RequestBody::Bytes(body.to_vec())
+    }
+}
+
+impl From<BodyStream> for RequestBody {
+    fn from(body: BodyStream) -> Self {
+        RequestBody::Stream(body)
+    }
+}
+
+/// A request body read from an `AsyncRead` as it is sent.
+pub struct BodyStream {
+    pub(crate) reader: Box<dyn AsyncRead + Send + Unpin>,
+    pub(crate) len: Option<u64>,
+}
+
+impl BodyStream {
+    /// Stream a body of unknown length, sent chunked.
+    pub fn chunked(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        Self {
+            reader: Box::new(reader),
+            len: None,
+        }
+    }
+
+    /// Stream a body of exactly `len` bytes, sent with Content-Length.
+    pub fn sized(reader: impl AsyncRead + Send + Unpin + 'static, len: u64) -> Self {
+        Self {
+            reader: Box::new(reader),
+            len: Some(len),
+        }
+    }
+
+    /// Length of the body, if known.
+    pub fn known_len(&self) -> Option<u64> {
+        self.len
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reject a body larger than the configured cap.
+pub fn check_request_size(len: usize, max_request_bytes: usize) -> Result<(), NetworkError> {
+    if len > max_request_bytes {
+        return Err(NetworkError::RequestTooLarge {
+            size: len,
+            limit: max_request_bytes,
+        });
+    }
+    Ok(())
+}
+
+/// Upload progress for one request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Body bytes written so far
+    pub sent_bytes: u64,
+    /// Total body bytes
+    pub total_bytes: u64,
+}
+
+impl UploadProgress {
+    /// Get progress as a percentage (0-100).
+    pub fn percent(&self) -> u8 {
+        if self.total_bytes == 0 {
+            return 100;
+        }
+        (self.sent_bytes.min(self.total_bytes) * 100 / self.total_bytes) as u8
+    }
+}
+
+/// Receiver of upload progress events.
+pub trait ProgressSink {
+    /// Called after each chunk is written.
+    fn on_progress(&mut self, progress: UploadProgress);
+}
+
+impl<F: FnMut(UploadProgress)> ProgressSink for F {
+    fn on_progress(&mut self, progress: UploadProgress) {
+        self(progress)
+    }
+}
+
+/// Connection an upload body is written to.
+pub trait UploadTransport {
+    /// Write one chunk of the body.
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), NetworkError>;
+
+    /// Tear the connection down without finishing the body.
+    fn abort(&mut self);
+}
+
+/// Shared cancellation flag for an in-flight upload.
+#[derive(Debug, Clone, Default)]
+pub struct UploadCancel {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl UploadCancel {
+    /// Create a new, uncancelled handle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation. Takes effect before the next chunk.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Check whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Write `body` to `transport` in chunks, reporting progress to `sink`.
+///
+/// On cancellation or a write error the transport is aborted, so a
+/// half-sent body never completes; the caller then closes the circuit.
+pub fn send_chunked(
+    body: &[u8],
+    max_request_bytes: usize,
+    transport: &mut dyn UploadTransport,
+    sink: &mut dyn ProgressSink,
+    cancel: &UploadCancel,
+) -> Result<(), NetworkError> {
+    check_request_size(body.len(), max_request_bytes)?;
+
+    let total_bytes = body.len() as u64;
+    let mut sent_bytes = 0u64;
+
+    if body.is_empty() {
+        sink.on_progress(UploadProgress {
+            sent_bytes,
+            total_bytes,
+        });
+        return Ok(());
+    }
+
+    for chunk in body.chunks(UPLOAD_CHUNK_BYTES) {
+        if cancel.is_cancelled() {
+            transport.abort();
+            return Err(NetworkError::Cancelled);
+        }
+
+        if let Err(e) = transport.write_chunk(chunk) {
+            transport.abort();
+            return Err(e);
+        }
+
+        sent_bytes += chunk.len() as u64;
+        sink.on_progress(UploadProgress {
+            sent_bytes,
+            total_bytes,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Transport that records what it was sent.
+    #[derive(Default)]
+    struct MockTransport {
+        written: usize,
+        aborted: bool,
+    }
+
+    impl UploadTransport for MockTransport {
+        fn write_chunk(&mut self, chunk: &[u8]) -> Result<(), NetworkError> {
+            self.written += chunk.len();
+            Ok(())
+        }
+
+        fn abort(&mut self) {
+            self.aborted = true;
+        }
+    }
+
+    #[test]
+    fn test_cap_rejects_before_sending() {
+        let mut transport = MockTransport::default();
+        let mut events = Vec::new();
+        let mut sink = |p: UploadProgress| events.push(p);
+
+        let result = send_chunked(
+            &[0u8; 1025],
+            1024,
+            &mut transport,
+            &mut sink,
+            &UploadCancel::new(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(NetworkError::RequestTooLarge {
+                size: 1025,
+                limit: 1024
+            })
+        ));
+        assert_eq!(transport.written, 0);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_progress_cadence() {
+        let body = vec![7u8; UPLOAD_CHUNK_BYTES * 3 + 10];
+        let mut transport = MockTransport::default();
+        let mut events = Vec::new();
+        let mut sink = |p: UploadProgress| events.push(p);
+
+        send_chunked(
+            &body,
+            usize::MAX,
+            &mut transport,
+            &mut sink,
+            &UploadCancel::new(),
+        )
+        .expect("upload succeeds");
+
+        // One event per chunk, monotonically increasing, ending at the total
+        assert_eq!(events.len(), 4);
+        assert!(events.windows(2).all(|w| w[0].sent_bytes < w[1].sent_bytes));
+        let last = events.last().expect("at least one event");
+        assert_eq!(last.sent_bytes, body.len() as u64);
+        assert_eq!(last.percent(), 100);
+        assert_eq!(transport.written, body.len());
+        assert!(!transport.aborted);
+    }
+
+    #[test]
+    fn test_cancel_mid_upload_aborts() {
+        let body = vec![0u8; UPLOAD_CHUNK_BYTES * 4];
+        let cancel = UploadCancel::new();
+        let mut transport = MockTransport::default();
+        let mut events = Vec::new();
+
+        let result = {
+            let trigger = cancel.clone();
+            let mut sink = |p: UploadProgress| {
+                events.push(p);
+                if events.len() == 2 {
+                    trigger.cancel();
+                }
+            };
+            send_chunked(&body, usize::MAX, &mut transport, &mut sink, &cancel)
+        };
+
+        assert!(matches!(result, Err(NetworkError::Cancelled)));
+        assert!(transport.aborted);
+        assert_eq!(transport.written, UPLOAD_CHUNK_BYTES * 2);
+        assert_eq!(events.len(), 2);
+    }
+}
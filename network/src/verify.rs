@@ -0,0 +1,299 @@
+//! "Verify this page": fetch a page twice over separate circuits and compare.
+//!
+//! Tor authenticates an onion service, but a compromised service (or a
+//! middlebox on its side) can still serve a different document to each
+//! circuit and recognise the visitor by which variant comes back. When the
+//! user asks, the page is fetched over two fresh circuits at once and the
+//! normalized bodies are compared. Nothing here depends on the scheme:
+//! the request policy admits HTTPS only today, and plaintext onion-http
+//! pages take the same path once that scheme is allowed.
+//!
+//! Headers other than Content-Type are ignored. HTML documents are
+//! compared by their extracted text and image/link references, so markup
+//! and whitespace changes do not count; other bodies are compared byte for
+//! byte after fixing line endings and trailing whitespace. Masking volatile
+//! regions (timestamps, CSRF tokens) by selector is not done here, so
+//! dynamic pages can report a mismatch that is harmless.
+
+use std::future::Future;
+
+use crate::sanitize::is_html;
+use crate::text_extract::extract_text;
+use crate::{AnonymizedNetwork, NetworkError};
+
+/// One copy of a page and the circuit it came over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchedPage {
+    /// Circuit the copy was fetched on
+    pub circuit_id: String,
+    /// Content-Type of the response, empty if absent
+    pub content_type: String,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl FetchedPage {
+    /// The form the page is compared in: extracted text for HTML, the
+    /// normalized body otherwise.
+    pub fn normalized(&self) -> Vec<u8> {
+        if is_html(&self.content_type) {
+            extract_text(&self.body).canonical().into_bytes()
+        } else {
+            normalize_body(&self.body)
+        }
+    }
+}
+
+/// Fetches a page on a circuit of its own.
+pub trait PageFetcher {
+    /// GET `url` over a fresh circuit.
+    fn fetch(&self, url: &str) -> impl Future<Output = Result<FetchedPage, NetworkError>>;
+}
+
+impl PageFetcher for AnonymizedNetwork {
+    async fn fetch(&self, url: &str) -> Result<FetchedPage, NetworkError> {
+        let response = self.request("GET", url, None).await?;
+        let content_type = response
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        Ok(FetchedPage {
+            circuit_id: response.circuit_id,
+            content_type,
+            body: response.body,
+        })
+    }
+}
+
+/// How the two copies compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Normalized pages are identical
+    Match {
+        /// Normalized length in bytes
+        len: usize,
+    },
+    /// Normalized pages differ
+    Mismatch {
+        /// Normalized length of the first copy
+        first_len: usize,
+        /// Normalized length of the second copy
+        second_len: usize,
+        /// Offset of the first differing byte
+        first_difference: usize,
+    },
+}
+
+/// Result of verifying one page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyReport {
+    /// Navigation context the page belongs to
+    pub context_id: u64,
+    /// URL that was fetched
+    pub url: String,
+    /// The two circuits used
+    pub circuits: [String; 2],
+    /// Comparison outcome
+    pub consistency: Consistency,
+}
+
+impl ConsistencyReport {
+    /// Whether both circuits saw the same document.
+    pub fn is_consistent(&self) -> bool {
+        matches!(self.consistency, Consistency::Match { .. })
+    }
+}
+
+/// Normalize a body for comparison: LF line endings, no trailing whitespace.
+pub fn normalize_body(body: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = body.iter().copied().filter(|&b| b != b'\r').collect();
+    let end = out
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |last| last + 1);
+    out.truncate(end);
+    out
+}
+
+/// Compare two bodies after normalization.
+pub fn compare_bodies(first: &[u8], second: &[u8]) -> Consistency {
+    compare_normalized(&normalize_body(first), &normalize_body(second))
+}
+
+/// Compare two copies of a page in their normalized form.
+pub fn compare_pages(first: &FetchedPage, second: &FetchedPage) -> Consistency {
+    compare_normalized(&first.normalized(), &second.normalized())
+}
+
+fn compare_normalized(first: &[u8], second: &[u8]) -> Consistency {
+    if first == second {
+        return Consistency::Match { len: first.len() };
+    }
+
+    let first_difference = first
+        .iter()
+        .zip(second)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| first.len().min(second.len()));
+
+    Consistency::Mismatch {
+        first_len: first.len(),
+        second_len: second.len(),
+        first_difference,
+    }
+}
+
+/// Fetch `url` over two fresh circuits concurrently and compare the bodies.
+///
+/// Both fetches start together so a time-dependent page is less likely to
+/// differ between them. Fails if either fetch fails or both copies came
+/// over the same circuit, since that comparison would prove nothing.
+pub async fn verify_page<F: PageFetcher>(
+    fetcher: &F,
+    context_id: u64,
+    url: &str,
+) -> Result<ConsistencyReport, NetworkError> {
+    let (first, second) = tokio::join!(fetcher.fetch(url), fetcher.fetch(url));
+    let (first, second) = (first?, second?);
+
+    if first.circuit_id == second.circuit_id {
+        return Err(NetworkError::CircuitCreationFailed(
+            "Verification fetches shared a circuit".to_string(),
+        ));
+    }
+
+    let consistency = compare_pages(&first, &second);
+    if let Consistency::Mismatch {
+        first_difference, ..
+    } = consistency
+    {
+        log::warn!(
+            "Context {}: page differs between circuits at byte {}",
+            context_id,
+            first_difference
+        );
+    }
+
+    Ok(ConsistencyReport {
+        context_id,
+        url: url.to_string(),
+        circuits: [first.circuit_id, second.circuit_id],
+        consistency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    const URL: &str =
+        "https://2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion/index.html";
+
+    /// Fetcher standing in for Tor: serves one body per circuit, in order.
+    struct FakeTor {
+        bodies: Vec<&'static [u8]>,
+        content_type: &'static str,
+        next: AtomicUsize,
+        reuse_circuit: bool,
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl FakeTor {
+        fn serving(bodies: Vec<&'static [u8]>) -> Self {
+            Self {
+                bodies,
+                content_type: "text/html; charset=utf-8",
+                next: AtomicUsize::new(0),
+                reuse_circuit: false,
+                fetched: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl PageFetcher for FakeTor {
+        fn fetch(&self, url: &str) -> impl Future<Output = Result<FetchedPage, NetworkError>> {
+            let n = self.next.fetch_add(1, Ordering::SeqCst);
+            self.fetched
+                .lock()
+                .expect("fetch log lock")
+                .push(url.to_string());
+            let circuit = if self.reuse_circuit { 0 } else { n };
+            let body = self.bodies[n % self.bodies.len()].to_vec();
+            async move {
+                Ok(FetchedPage {
+                    circuit_id: format!("circuit-{}", circuit),
+                    content_type: self.content_type.to_string(),
+                    body,
+                })
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_bodies_match() {
+        let tor = FakeTor::serving(vec![b"<p>hello</p>\r\n", b"<p>hello</p>\n"]);
+
+        let report = verify_page(&tor, 7, URL).await.expect("verified");
+        assert!(report.is_consistent());
+        assert_eq!(report.consistency, Consistency::Match { len: 5 });
+        assert_eq!(report.context_id, 7);
+        assert_ne!(report.circuits[0], report.circuits[1]);
+        assert_eq!(
+            *tor.fetched.lock().expect("fetch log lock"),
+            vec![URL.to_string(), URL.to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_differing_bodies_mismatch() {
+        let tor = FakeTor::serving(vec![b"<p>id=1111</p>", b"<p>id=2222</p>"]);
+        let report = verify_page(&tor, 1, URL).await.expect("verified");
+        assert_eq!(
+            report.consistency,
+            Consistency::Mismatch {
+                first_len: 7,
+                second_len: 7,
+                first_difference: 3,
+            }
+        );
+
+        // A variant that only adds an image still differs
+        let tor = FakeTor::serving(vec![b"<p>a</p>", b"<p>a</p><img src=x>"]);
+        let report = verify_page(&tor, 1, URL).await.expect("verified");
+        assert_eq!(
+            report.consistency,
+            Consistency::Mismatch {
+                first_len: 1,
+                second_len: 11,
+                first_difference: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_html_compared_by_text() {
+        let tor = FakeTor::serving(vec![
+            b"<div class=a><p>Same   words</p><script>var t=1</script></div>",
+            b"<section>\n<p>Same words</p>\n<script>var t=2</script></section>",
+        ]);
+        let report = verify_page(&tor, 1, URL).await.expect("verified");
+        assert_eq!(report.consistency, Consistency::Match { len: 10 });
+
+        // Other bodies are still compared byte for byte
+        let mut tor = FakeTor::serving(vec![b"<p>a</p>", b"<p> a </p>"]);
+        tor.content_type = "text/plain";
+        let report = verify_page(&tor, 1, URL).await.expect("verified");
+        assert!(!report.is_consistent());
+    }
+
+    #[tokio::test]
+    async fn test_shared_circuit_is_refused() {
+        let mut tor = FakeTor::serving(vec![b"same"]);
+        tor.reuse_circuit = true;
+        assert!(verify_page(&tor, 1, URL).await.is_err());
+    }
+}
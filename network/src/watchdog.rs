@@ -0,0 +1,347 @@
+//! Watchdog for wedged circuits.
+//!
+//! Every open circuit holds one of a small number of concurrency permits.
+//! A circuit stuck half-open (SOCKS connected, TLS never completing) holds
+//! its permit forever, and a handful of them starve all navigation. The
+//! watchdog tracks each circuit's age and last read/write, and reaps
+//! circuits that have gone idle or outlived the hard cap, releasing their
+//! permits.
+//!
+//! A circuit streaming a body that is still making progress is never
+//! reaped, however old it is.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use forloop_config::{system_clock, Clock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::NetworkError;
+
+/// Default number of circuits that may be open at once.
+pub const MAX_CONCURRENT_CIRCUITS: usize = 6;
+
+/// When the watchdog reaps a circuit.
+#[derive(Debug, Clone)]
+pub struct WatchdogPolicy {
+    /// Reap a circuit with no read or write for this long
+    pub idle_timeout: Duration,
+    /// Reap a circuit this old unless it is streaming with progress
+    pub max_age: Duration,
+    /// How often to sweep while waiting for a permit
+    pub sweep_interval: Duration,
+}
+
+impl Default for WatchdogPolicy {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(30),
+            max_age: Duration::from_secs(600),
+            sweep_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActivityState {
+    last_activity: Instant,
+    streaming: bool,
+    reaped: bool,
+}
+
+/// Activity handle the request code updates on every read and write.
+#[derive(Debug, Clone)]
+pub struct CircuitActivity {
+    state: Arc<Mutex<ActivityState>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitActivity {
+    fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ActivityState {
+                last_activity: clock.now(),
+                streaming: false,
+                reaped: false,
+            })),
+            clock,
+        }
+    }
+
+    /// Record a read or write on the circuit.
+    pub fn touch(&self) {
+        let now = self.clock.now();
+        self.state.lock().expect("activity lock").last_activity = now;
+    }
+
+    /// Mark the start or end of a streaming body.
+    pub fn set_streaming(&self, streaming: bool) {
+        let now = self.clock.now();
+        let mut state = self.state.lock().expect("activity lock");
+        state.streaming = streaming;
+        state.last_activity = now;
+    }
+
+    /// Whether the watchdog has reaped the circuit.
+    pub fn is_reaped(&self) -> bool {
+        self.state.lock().expect("activity lock").reaped
+    }
+}
+
+#[derive(Debug)]
+struct TrackedCircuit {
+    opened: Instant,
+    activity: CircuitActivity,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Tracks open circuits and their concurrency permits.
+#[derive(Debug)]
+pub struct CircuitWatchdog {
+    policy: WatchdogPolicy,
+    permits: Arc<Semaphore>,
+    circuits: Mutex<HashMap<String, TrackedCircuit>>,
+    reaped: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitWatchdog {
+    /// Create a watchdog allowing `max_concurrent` open circuits.
+    pub fn new(max_concurrent: usize, policy: WatchdogPolicy) -> Self {
+        Self {
+            policy,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            circuits: Mutex::new(HashMap::new()),
+            reaped: AtomicU64::new(0),
+            clock: system_clock(),
+        }
+    }
+
+    /// Time circuits on `clock` instead of the real clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Get the reaping policy.
+    pub fn policy(&self) -> &WatchdogPolicy {
+        &self.policy
+    }
+
+    /// Take a permit without waiting, if one is free.
+    pub fn try_permit(&self) -> Option<OwnedSemaphorePermit> {
+        Arc::clone(&self.permits).try_acquire_owned().ok()
+    }
+
+    /// Wait until a permit is free, sweeping for wedged circuits meanwhile.
+    ///
+    /// Returns the IDs of circuits reaped while waiting; the caller closes
+    /// them.
+    pub async fn permit(&self) -> Result<(OwnedSemaphorePermit, Vec<String>), NetworkError> {
+        let mut reaped = Vec::new();
+        loop {
+            tokio::select! {
+                permit = Arc::clone(&self.permits).acquire_owned() => {
+                    let permit = permit.map_err(|_| {
+                        NetworkError::CircuitCreationFailed("watchdog shut down".to_string())
+                    })?;
+                    return Ok((permit, reaped));
+                }
+                _ = self.clock.sleep(self.policy.sweep_interval) => {
+                    reaped.extend(self.sweep());
+                }
+            }
+        }
+    }
+
+    /// Start tracking a circuit that holds `permit`.
+    pub fn track(&self, circuit_id: &str, permit: OwnedSemaphorePermit) -> CircuitActivity {
+        let activity = CircuitActivity::new(Arc::clone(&self.clock));
+        self.circuits.lock().expect("watchdog lock").insert(
+            circuit_id.to_string(),
+            TrackedCircuit {
+                opened: self.clock.now(),
+                activity: activity.clone(),
+                _permit: permit,
+            },
+        );
+        activity
+    }
+
+    /// Stop tracking a circuit that closed normally, releasing its permit.
+    pub fn release(&self, circuit_id: &str) {
+        self.circuits
+            .lock()
+            .expect("watchdog lock")
+            .remove(circuit_id);
+    }
+
+    /// Wait one sweep interval, then sweep.
+    pub async fn next_sweep(&self) -> Vec<String> {
+        self.clock.sleep(self.policy.sweep_interval).await;
+        self.sweep()
+    }
+
+    /// Reap wedged circuits, releasing their permits.
+    ///
+    /// Returns the reaped circuit IDs; the caller closes them with Tor.
+    pub fn sweep(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let mut circuits = self.circuits.lock().expect("watchdog lock");
+
+        let wedged: Vec<String> = circuits
+            .iter()
+            .filter(|(_, circuit)| self.is_wedged(circuit, now))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in &wedged {
+            if let Some(circuit) = circuits.remove(id) {
+                circuit.activity.state.lock().expect("activity lock").reaped = true;
+            }
+        }
+        drop(circuits);
+
+        if !wedged.is_empty() {
+            let total = self
+                .reaped
+                .fetch_add(wedged.len() as u64, Ordering::Relaxed)
+                + wedged.len() as u64;
+            log::debug!(
+                "Watchdog reaped {} wedged circuit(s), {} in total",
+                wedged.len(),
+                total
+            );
+        }
+        wedged
+    }
+
+    fn is_wedged(&self, circuit: &TrackedCircuit, now: Instant) -> bool {
+        let state = circuit.activity.state.lock().expect("activity lock");
+        let idle = now.saturating_duration_since(state.last_activity) >= self.policy.idle_timeout;
+        let too_old = now.saturating_duration_since(circuit.opened) >= self.policy.max_age;
+        idle || (too_old && !state.streaming)
+    }
+
+    /// Number of circuits currently tracked.
+    pub fn tracked(&self) -> usize {
+        self.circuits.lock().expect("watchdog lock").len()
+    }
+
+    /// Number of permits currently free.
+    pub fn available_permits(&self) -> usize {
+        self.permits.available_permits()
+    }
+
+    /// Number of circuits reaped since creation.
+    pub fn reaped_count(&self) -> u64 {
+        self.reaped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for CircuitWatchdog {
+    fn default() -> Self {
+        Self::new(MAX_CONCURRENT_CIRCUITS, WatchdogPolicy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use forloop_config::clock::ManualClock;
+
+    fn watchdog(clock: &ManualClock) -> CircuitWatchdog {
+        CircuitWatchdog::new(2, WatchdogPolicy::default()).with_clock(Arc::new(clock.clone()))
+    }
+
+    fn open(watchdog: &CircuitWatchdog, id: &str) -> CircuitActivity {
+        let permit = watchdog.try_permit().expect("free permit");
+        watchdog.track(id, permit)
+    }
+
+    #[test]
+    fn test_reaps_stuck_but_not_slow_circuits() {
+        let clock = ManualClock::new();
+        let watchdog = watchdog(&clock);
+        let stuck = open(&watchdog, "stuck");
+        let slow = open(&watchdog, "slow");
+        slow.set_streaming(true);
+        assert!(watchdog.try_permit().is_none());
+
+        // The slow body trickles in every 20s; the stuck one never moves
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(20));
+            slow.touch();
+            assert!(watchdog.sweep().iter().all(|id| id == "stuck"));
+        }
+
+        assert!(stuck.is_reaped());
+        assert!(!slow.is_reaped());
+        assert_eq!(watchdog.reaped_count(), 1);
+        assert_eq!(watchdog.available_permits(), 1);
+    }
+
+    #[test]
+    fn test_age_cap_spares_progressing_streams() {
+        let clock = ManualClock::new();
+        let watchdog = watchdog(&clock);
+        let streaming = open(&watchdog, "streaming");
+        let chatty = open(&watchdog, "chatty");
+        streaming.set_streaming(true);
+
+        // Both keep making progress past the hard cap
+        for _ in 0..70 {
+            clock.advance(Duration::from_secs(10));
+            streaming.touch();
+            chatty.touch();
+        }
+
+        assert_eq!(watchdog.sweep(), vec!["chatty".to_string()]);
+        assert!(!streaming.is_reaped());
+
+        // A stream that stops progressing is reaped once idle
+        clock.advance(WatchdogPolicy::default().idle_timeout);
+        assert_eq!(watchdog.sweep(), vec!["streaming".to_string()]);
+        assert_eq!(watchdog.reaped_count(), 2);
+        assert_eq!(watchdog.available_permits(), 2);
+    }
+
+    #[test]
+    fn test_release_returns_permit() {
+        let clock = ManualClock::new();
+        let watchdog = watchdog(&clock);
+        open(&watchdog, "a");
+        open(&watchdog, "b");
+        assert_eq!(watchdog.available_permits(), 0);
+
+        watchdog.release("a");
+        assert_eq!(watchdog.tracked(), 1);
+        assert_eq!(watchdog.available_permits(), 1);
+        assert_eq!(watchdog.reaped_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_waiting_for_permit_reaps_wedged() {
+        let clock = ManualClock::new();
+        let watchdog = Arc::new(watchdog(&clock));
+        open(&watchdog, "a");
+        open(&watchdog, "b");
+
+        let waiter = {
+            let watchdog = Arc::clone(&watchdog);
+            tokio::spawn(async move { watchdog.permit().await.map(|(_, reaped)| reaped) })
+        };
+
+        // Let the waiter register its sweep timer, then let time pass
+        while !waiter.is_finished() {
+            tokio::task::yield_now().await;
+            clock.advance(Duration::from_secs(5));
+        }
+
+        let reaped = waiter.await.expect("join").expect("permit");
+        assert!(!reaped.is_empty());
+        assert!(clock.elapsed() >= WatchdogPolicy::default().idle_timeout);
+    }
+}
@@ -0,0 +1,420 @@
+//! Static assets shared read-only with content processes.
+//!
+//! Assets the renderer needs on every page (the error-page stylesheet,
+//! the letterbox chrome) are compiled into the binary. The broker copies
+//! each one into a memfd once at startup and seals it against writes,
+//! resizing and further seal changes. Every content process inherits the
+//! same fds at spawn and receives an `AssetManifest` mapping asset ids to
+//! fd indices and lengths, so nothing is re-sent per navigation and a
+//! compromised renderer cannot alter what the next one sees.
+//!
+//! The content side maps each fd read-only with `AssetMapping` and only
+//! hands out bounds-checked slices.
+
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::ptr::NonNull;
+
+/// Seals every asset memfd carries.
+const ASSET_SEALS: libc::c_int =
+    libc::F_SEAL_WRITE | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+
+/// Size of one serialized manifest entry.
+const MANIFEST_ENTRY_LEN: usize = 12;
+
+/// A bundled asset.
+///
+/// The discriminant is the id in the manifest and must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u16)]
+pub enum AssetId {
+    /// Stylesheet for error and interstitial pages
+    ErrorPageCss = 0,
+    /// Letterbox chrome around the content area
+    LetterboxCss = 1,
+}
+
+impl AssetId {
+    /// Every asset, in id order.
+    pub const ALL: [Self; 2] = [Self::ErrorPageCss, Self::LetterboxCss];
+
+    /// Get the asset's contents as compiled into the binary.
+    pub fn embedded(&self) -> &'static [u8] {
+        match self {
+            AssetId::ErrorPageCss => include_bytes!("../assets/error-page.css"),
+            AssetId::LetterboxCss => include_bytes!("../assets/letterbox.css"),
+        }
+    }
+
+    /// Get the memfd name, visible in /proc/<pid>/fd.
+    fn memfd_name(&self) -> &'static CStr {
+        match self {
+            AssetId::ErrorPageCss => c"forloop-asset-error-page.css",
+            AssetId::LetterboxCss => c"forloop-asset-letterbox.css",
+        }
+    }
+
+    fn from_wire(value: u16) -> io::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|id| *id as u16 == value)
+            .ok_or_else(|| invalid_data("Unknown asset id"))
+    }
+}
+
+/// Where one asset lives in the inherited fds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AssetEntry {
+    /// Asset
+    pub id: AssetId,
+    /// Position in the fd list handed over at spawn
+    pub fd_index: u16,
+    /// Length in bytes
+    pub len: u64,
+}
+
+/// Asset ids → fd indices and lengths, sent to each content process.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AssetManifest {
+    /// One entry per asset
+    pub entries: Vec<AssetEntry>,
+}
+
+impl AssetManifest {
+    /// Serialize for an AssetManifest IPC payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(2 + self.entries.len() * MANIFEST_ENTRY_LEN);
+        buffer.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        for entry in &self.entries {
+            buffer.extend_from_slice(&(entry.id as u16).to_le_bytes());
+            buffer.extend_from_slice(&entry.fd_index.to_le_bytes());
+            buffer.extend_from_slice(&entry.len.to_le_bytes());
+        }
+        buffer
+    }
+
+    /// Deserialize from an AssetManifest IPC payload.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let (count, rest) = bytes
+            .split_first_chunk::<2>()
+            .ok_or_else(|| invalid_data("Malformed asset manifest"))?;
+        let count = u16::from_le_bytes(*count) as usize;
+        if rest.len() != count * MANIFEST_ENTRY_LEN {
+            return Err(invalid_data("Malformed asset manifest"));
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for raw in rest.chunks_exact(MANIFEST_ENTRY_LEN) {
+            let id = u16::from_le_bytes([raw[0], raw[1]]);
+            let fd_index = u16::from_le_bytes([raw[2], raw[3]]);
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&raw[4..12]);
+
+            let id = AssetId::from_wire(id)?;
+            if entries.iter().any(|entry: &AssetEntry| entry.id == id) {
+                return Err(invalid_data("Duplicate asset id"));
+            }
+            entries.push(AssetEntry {
+                id,
+                fd_index,
+                len: u64::from_le_bytes(len),
+            });
+        }
+        Ok(Self { entries })
+    }
+}
+
+/// Broker-side store of sealed asset memfds.
+#[derive(Debug)]
+pub struct AssetStore {
+    fds: Vec<OwnedFd>,
+    manifest: AssetManifest,
+}
+
+impl AssetStore {
+    /// Copy every embedded asset into its own sealed memfd.
+    pub fn new() -> io::Result<Self> {
+        let mut fds = Vec::with_capacity(AssetId::ALL.len());
+        let mut entries = Vec::with_capacity(AssetId::ALL.len());
+
+        for (fd_index, id) in AssetId::ALL.into_iter().enumerate() {
+            let bytes = id.embedded();
+            fds.push(sealed_memfd(id.memfd_name(), bytes)?);
+            entries.push(AssetEntry {
+                id,
+                fd_index: fd_index as u16,
+                len: bytes.len() as u64,
+            });
+        }
+
+        Ok(Self {
+            fds,
+            manifest: AssetManifest { entries },
+        })
+    }
+
+    /// Get the manifest to send to a content process.
+    pub fn manifest(&self) -> &AssetManifest {
+        &self.manifest
+    }
+
+    /// Get the fds to hand to a content process at spawn, in index order.
+    pub fn spawn_fds(&self) -> Vec<BorrowedFd<'_>> {
+        self.fds.iter().map(|fd| fd.as_fd()).collect()
+    }
+}
+
+/// Create a memfd holding `bytes`, sealed read-only.
+fn sealed_memfd(name: &CStr, bytes: &[u8]) -> io::Result<OwnedFd> {
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Freshly created, nothing else owns it
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    File::from(fd.try_clone()?).write_all(bytes)?;
+
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, ASSET_SEALS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Read-only mapping of one asset memfd (content side).
+#[derive(Debug)]
+pub struct AssetMapping {
+    ptr: Option<NonNull<u8>>,
+    len: usize,
+}
+
+// The mapping is read-only and the memfd is sealed, so the bytes never change
+unsafe impl Send for AssetMapping {}
+unsafe impl Sync for AssetMapping {}
+
+impl AssetMapping {
+    /// Map `len` bytes of an inherited asset fd read-only.
+    ///
+    /// Refuses fds that are not sealed against writes and resizing, or
+    /// whose size does not match the manifest.
+    pub fn map(fd: BorrowedFd<'_>, len: u64) -> io::Result<Self> {
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        if seals < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let required = libc::F_SEAL_WRITE | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+        if seals & required != required {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Asset fd is not sealed",
+            ));
+        }
+
+        let size = File::from(fd.try_clone_to_owned()?).metadata()?.len();
+        if size != len {
+            return Err(invalid_data("Asset size does not match manifest"));
+        }
+        let len = usize::try_from(len).map_err(|_| invalid_data("Asset too large"))?;
+        if len == 0 {
+            // mmap refuses empty mappings
+            return Ok(Self { ptr: None, len });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()),
+            len,
+        })
+    }
+
+    /// Get the whole asset.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self.ptr {
+            // `len` readable bytes until drop; the seals keep them fixed
+            Some(ptr) => unsafe { std::slice::from_raw_parts(ptr.as_ptr(), self.len) },
+            None => &[],
+        }
+    }
+
+    /// Get `len` bytes at `offset`, or `None` if out of bounds.
+    pub fn get(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        let end = offset.checked_add(len)?;
+        self.as_bytes().get(offset..end)
+    }
+
+    /// Length in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the asset is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for AssetMapping {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            unsafe {
+                libc::munmap(ptr.as_ptr().cast(), self.len);
+            }
+        }
+    }
+}
+
+/// Every asset mapped in a content process, looked up by id.
+#[derive(Debug, Default)]
+pub struct ContentAssets {
+    mappings: Vec<(AssetId, AssetMapping)>,
+}
+
+impl ContentAssets {
+    /// Map the inherited fds as described by the broker's manifest.
+    pub fn from_manifest(manifest: &AssetManifest, fds: &[BorrowedFd<'_>]) -> io::Result<Self> {
+        let mappings = manifest
+            .entries
+            .iter()
+            .map(|entry| {
+                let fd = fds
+                    .get(entry.fd_index as usize)
+                    .ok_or_else(|| invalid_data("Asset fd index out of range"))?;
+                Ok((entry.id, AssetMapping::map(*fd, entry.len)?))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { mappings })
+    }
+
+    /// Get an asset by id, if the broker provided it.
+    pub fn get(&self, id: AssetId) -> Option<&AssetMapping> {
+        self.mappings
+            .iter()
+            .find(|(mapped, _)| *mapped == id)
+            .map(|(_, mapping)| mapping)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IpcChannel, IpcMessage, IpcMessageType};
+
+    #[test]
+    fn test_retrieval_by_id_over_ipc() {
+        let store = AssetStore::new().expect("Failed to create asset store");
+        let (broker, content) = IpcChannel::create_pair().expect("Failed to create channel");
+
+        broker
+            .send(&IpcMessage::asset_manifest(1, store.manifest()))
+            .expect("Failed to send");
+        let received = content.recv().expect("Failed to receive");
+        assert_eq!(received.msg_type, IpcMessageType::AssetManifest);
+        let manifest = AssetManifest::from_bytes(&received.payload).expect("Bad manifest");
+        assert_eq!(&manifest, store.manifest());
+
+        let assets =
+            ContentAssets::from_manifest(&manifest, &store.spawn_fds()).expect("Failed to map");
+        for id in AssetId::ALL {
+            let mapping = assets.get(id).expect("asset mapped");
+            assert_eq!(mapping.as_bytes(), id.embedded());
+        }
+
+        let css = assets.get(AssetId::ErrorPageCss).expect("asset mapped");
+        assert_eq!(css.get(0, 2), Some(&b"/*"[..]));
+        assert_eq!(css.get(css.len(), 0), Some(&b""[..]));
+        assert_eq!(css.get(css.len() - 1, 2), None);
+        assert_eq!(css.get(usize::MAX, 2), None);
+    }
+
+    #[test]
+    fn test_seals_are_enforced() {
+        let store = AssetStore::new().expect("Failed to create asset store");
+
+        for fd in store.spawn_fds() {
+            let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+            assert_eq!(seals & ASSET_SEALS, ASSET_SEALS);
+
+            // Writing, resizing and a writable mapping all fail
+            let mut file = File::from(fd.try_clone_to_owned().expect("dup"));
+            let error = file.write_all(b"x").expect_err("write must fail");
+            assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+            assert!(file.set_len(0).is_err());
+            assert!(file.set_len(1 << 20).is_err());
+
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    1,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            assert_eq!(ptr, libc::MAP_FAILED);
+
+            // Nor can the seals be lifted
+            let result = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, 0) };
+            assert_ne!(result, 0);
+        }
+    }
+
+    #[test]
+    fn test_unsealed_or_mismatched_fd_refused() {
+        let fd = unsafe { libc::memfd_create(c"forloop-test".as_ptr(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let error = AssetMapping::map(fd.as_fd(), 0).expect_err("unsealed fd");
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        let store = AssetStore::new().expect("Failed to create asset store");
+        let mut manifest = store.manifest().clone();
+        manifest.entries[0].len += 1;
+        assert!(ContentAssets::from_manifest(&manifest, &store.spawn_fds()).is_err());
+
+        manifest.entries[0].fd_index = 99;
+        assert!(ContentAssets::from_manifest(&manifest, &store.spawn_fds()).is_err());
+    }
+
+    #[test]
+    fn test_manifest_wire_format() {
+        let manifest = AssetStore::new().expect("store").manifest().clone();
+        let bytes = manifest.to_bytes();
+        assert_eq!(bytes.len(), 2 + AssetId::ALL.len() * MANIFEST_ENTRY_LEN);
+        assert_eq!(
+            AssetManifest::from_bytes(&bytes).expect("decodes"),
+            manifest
+        );
+
+        assert!(AssetManifest::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(AssetManifest::from_bytes(&[]).is_err());
+
+        // Unknown and duplicate ids are refused
+        let mut unknown = bytes.clone();
+        unknown[2..4].copy_from_slice(&7u16.to_le_bytes());
+        assert!(AssetManifest::from_bytes(&unknown).is_err());
+        let mut duplicate = bytes;
+        duplicate[14..16].copy_from_slice(&0u16.to_le_bytes());
+        assert!(AssetManifest::from_bytes(&duplicate).is_err());
+    }
+}
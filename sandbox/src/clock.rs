@@ -0,0 +1,286 @@
+//! Broker-mediated clock access for content processes.
+//!
+//! `TimingDefense` rounds the clocks script can read, but native code in
+//! the renderer can call clock_gettime itself and get nanoseconds. The
+//! content seccomp policy therefore filters clock_gettime on its clock id:
+//!
+//! - CLOCK_REALTIME_COARSE and CLOCK_MONOTONIC_COARSE are allowed; they
+//!   only advance once per kernel tick (1-10ms)
+//! - every other clock is sent to the broker through a seccomp user
+//!   notification, and the broker answers with its own reading rounded
+//!   down to `TIMING_PRECISION`, the precision `TimingDefense` uses
+//! - CPU-time and dynamic clocks are refused with EINVAL, since the
+//!   broker's reading of them would describe the broker
+//!
+//! Residual risk: the vDSO. glibc serves clock_gettime from the vDSO
+//! without entering the kernel, so the filter only sees raw syscalls and
+//! the fallback path. A 64-bit process cannot have its vDSO removed
+//! without breaking libc, which caches pointers into it at startup, so
+//! the spawner leaves it mapped and `vdso_present` lets startup log that
+//! the exposure is there. Closing it needs a renderer that makes the
+//! syscall directly.
+
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::time::Duration;
+
+use forloop_config::TIMING_PRECISION;
+
+use crate::seccomp::{ArgFilter, ArgMatch, SeccompAction};
+
+/// Clocks content may read directly.
+pub const COARSE_CLOCKS: [libc::clockid_t; 2] =
+    [libc::CLOCK_REALTIME_COARSE, libc::CLOCK_MONOTONIC_COARSE];
+
+/// Fine clocks the broker answers for; other ids get EINVAL.
+const BROKERED_CLOCKS: &[libc::clockid_t] = &[
+    libc::CLOCK_REALTIME,
+    libc::CLOCK_MONOTONIC,
+    libc::CLOCK_MONOTONIC_RAW,
+    libc::CLOCK_BOOTTIME,
+    libc::CLOCK_TAI,
+];
+
+/// How a sandboxed process may read clocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockPolicy {
+    /// Clock ids clock_gettime may be called with directly
+    pub allowed_clocks: Vec<libc::clockid_t>,
+    /// Action for any other clock id
+    pub fine_clocks: SeccompAction,
+    /// Granularity of brokered readings
+    pub precision: Duration,
+}
+
+impl ClockPolicy {
+    /// Content process clock policy.
+    pub fn content() -> Self {
+        Self {
+            allowed_clocks: COARSE_CLOCKS.to_vec(),
+            fine_clocks: SeccompAction::Notify,
+            precision: TIMING_PRECISION,
+        }
+    }
+
+    /// The clock_gettime argument filter for this policy.
+    pub fn filter(&self) -> ArgFilter {
+        ArgFilter {
+            syscall: libc::SYS_clock_gettime,
+            allow_if: self
+                .allowed_clocks
+                .iter()
+                .map(|clock| ArgMatch::equals(0, *clock as u32))
+                .collect(),
+            otherwise: self.fine_clocks,
+        }
+    }
+
+    /// Read `clock` on behalf of a sandboxed process, rounded down to the
+    /// policy's precision.
+    pub fn read(&self, clock: libc::clockid_t) -> io::Result<libc::timespec> {
+        if !BROKERED_CLOCKS.contains(&clock) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::clock_gettime(clock, &mut now) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(self.quantize(now))
+    }
+
+    /// Round `time` down to the policy's precision.
+    pub fn quantize(&self, time: libc::timespec) -> libc::timespec {
+        let precision = self.precision.as_nanos().max(1);
+        let nanos = time.tv_sec as i128 * 1_000_000_000 + time.tv_nsec as i128;
+        let rounded = nanos - nanos.rem_euclid(precision as i128);
+        libc::timespec {
+            tv_sec: rounded.div_euclid(1_000_000_000) as libc::time_t,
+            tv_nsec: rounded.rem_euclid(1_000_000_000) as libc::c_long,
+        }
+    }
+}
+
+/// Answers the clock reads a sandboxed process was not allowed to make.
+#[derive(Debug)]
+pub struct ClockBroker {
+    listener: OwnedFd,
+    policy: ClockPolicy,
+}
+
+impl ClockBroker {
+    /// Serve notifications from `listener` (see
+    /// `SandboxPolicy::install_with_listener`) under `policy`.
+    pub fn new(listener: OwnedFd, policy: ClockPolicy) -> Self {
+        Self { listener, policy }
+    }
+
+    /// Answer notifications until every filtered process has exited.
+    pub fn serve(&self) {
+        while self.serve_one().is_ok() {}
+    }
+
+    /// Wait for one notification and answer it.
+    pub fn serve_one(&self) -> io::Result<()> {
+        let fd = self.listener.as_raw_fd();
+        let mut request: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_RECV, &mut request) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut response: libc::seccomp_notif_resp = unsafe { std::mem::zeroed() };
+        response.id = request.id;
+        response.error = match self.answer(&request) {
+            Ok(()) => 0,
+            Err(e) => -e.raw_os_error().unwrap_or(libc::EINVAL),
+        };
+
+        // A process that died meanwhile makes this fail with ENOENT
+        if unsafe { libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_SEND, &response) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Write the quantized time into the caller's timespec.
+    fn answer(&self, request: &libc::seccomp_notif) -> io::Result<()> {
+        if request.data.nr as libc::c_long != libc::SYS_clock_gettime {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+        let now = self.policy.read(request.data.args[0] as libc::clockid_t)?;
+
+        // The pid may have been reused if the caller died; only write
+        // while the notification is still live
+        let fd = self.listener.as_raw_fd();
+        if unsafe { libc::ioctl(fd, libc::SECCOMP_IOCTL_NOTIF_ID_VALID, &request.id) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let local = libc::iovec {
+            iov_base: &now as *const libc::timespec as *mut libc::c_void,
+            iov_len: std::mem::size_of::<libc::timespec>(),
+        };
+        let remote = libc::iovec {
+            iov_base: request.data.args[1] as *mut libc::c_void,
+            iov_len: std::mem::size_of::<libc::timespec>(),
+        };
+        let written = unsafe {
+            libc::process_vm_writev(request.pid as libc::pid_t, &local, 1, &remote, 1, 0)
+        };
+        if written != local.iov_len as isize {
+            return Err(io::Error::from_raw_os_error(libc::EFAULT));
+        }
+        Ok(())
+    }
+}
+
+/// Whether the vDSO is mapped into this process, letting clock_gettime
+/// bypass the seccomp clock filter.
+pub fn vdso_present() -> bool {
+    unsafe { libc::getauxval(libc::AT_SYSINFO_EHDR) != 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seccomp::SandboxPolicy;
+
+    /// Only the content clock filter; everything else allowed.
+    fn clock_filter_only() -> SandboxPolicy {
+        SandboxPolicy {
+            allowed: Vec::new(),
+            filtered: Vec::new(),
+            clock: Some(ClockPolicy::content()),
+            default_action: SeccompAction::Allow,
+        }
+    }
+
+    /// Read `clock` with the raw syscall, as native code bypassing libc would.
+    fn raw_clock(clock: libc::clockid_t) -> Result<Duration, i32> {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let result = unsafe { libc::syscall(libc::SYS_clock_gettime, clock, &mut ts) };
+        if result != 0 {
+            return Err(io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        }
+        Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    /// Run `body` in a forked child under the clock filter, with a broker
+    /// thread (started before the filter, so unfiltered) answering it.
+    fn in_brokered_child(body: fn() -> bool) -> bool {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let (tx, rx) = std::sync::mpsc::channel::<OwnedFd>();
+            std::thread::spawn(move || {
+                if let Ok(listener) = rx.recv() {
+                    ClockBroker::new(listener, ClockPolicy::content()).serve();
+                }
+            });
+            let ok = match clock_filter_only().install_with_listener() {
+                Ok(listener) => tx.send(listener).is_ok() && body(),
+                Err(_) => false,
+            };
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+        }
+
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    #[test]
+    fn test_quantize_rounds_down() {
+        let policy = ClockPolicy::content();
+        let rounded = policy.quantize(libc::timespec {
+            tv_sec: 12,
+            tv_nsec: 345_678_901,
+        });
+        assert_eq!((rounded.tv_sec, rounded.tv_nsec), (12, 300_000_000));
+        assert_eq!(policy.precision, TIMING_PRECISION);
+    }
+
+    #[test]
+    fn test_sandboxed_timer_resolution_above_floor() {
+        assert!(in_brokered_child(|| {
+            // Coarse clocks are read directly
+            let coarse = raw_clock(libc::CLOCK_MONOTONIC_COARSE).is_ok()
+                && raw_clock(libc::CLOCK_REALTIME_COARSE).is_ok();
+
+            // Fine clocks only ever step by whole precision units
+            let mut samples = Vec::new();
+            for _ in 0..60 {
+                match raw_clock(libc::CLOCK_MONOTONIC) {
+                    Ok(now) => samples.push(now),
+                    Err(_) => return false,
+                }
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            let aligned = samples
+                .iter()
+                .all(|t| t.as_nanos() % TIMING_PRECISION.as_nanos() == 0);
+            let steps_above_floor = samples
+                .windows(2)
+                .map(|w| w[1].saturating_sub(w[0]))
+                .filter(|step| !step.is_zero())
+                .all(|step| step >= TIMING_PRECISION);
+
+            // CPU-time clocks would describe the broker
+            let cpu_refused = raw_clock(libc::CLOCK_PROCESS_CPUTIME_ID) == Err(libc::EINVAL);
+
+            coarse && aligned && steps_above_floor && cpu_refused
+        }));
+    }
+
+    #[test]
+    fn test_vdso_detection_matches_maps() {
+        let maps = std::fs::read_to_string("/proc/self/maps").expect("read maps");
+        assert_eq!(vdso_present(), maps.contains("[vdso]"));
+    }
+}
@@ -0,0 +1,162 @@
+//! Form draft IPC for crash recovery.
+//!
+//! The content process periodically sends the focused textarea's text
+//! to the UI process, which keeps only the latest copy in memory. After
+//! a content-process recycle the UI can send it back once. Drafts never
+//! touch disk and are bounded so a page cannot push arbitrary data
+//! through the channel.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::{IpcMessage, IpcMessageType};
+
+/// Largest draft carried over IPC, in bytes.
+///
+/// Fits in a single SOCK_SEQPACKET message with room for the header.
+pub const MAX_DRAFT_BYTES: usize = 32 * 1024;
+
+/// Minimum time between two draft updates from one content process.
+pub const DRAFT_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Rate limiter for draft updates on the content side.
+#[derive(Debug, Clone)]
+pub struct DraftThrottle {
+    interval: Duration,
+    last_sent: Option<Instant>,
+}
+
+impl Default for DraftThrottle {
+    fn default() -> Self {
+        Self::new(DRAFT_MIN_INTERVAL)
+    }
+}
+
+impl DraftThrottle {
+    /// Allow at most one update per `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: None,
+        }
+    }
+
+    /// Whether an update may be sent at `now`. Records the send if so.
+    pub fn should_send(&mut self, now: Instant) -> bool {
+        let due = self
+            .last_sent
+            .is_none_or(|last| now.saturating_duration_since(last) >= self.interval);
+        if due {
+            self.last_sent = Some(now);
+        }
+        due
+    }
+}
+
+/// Cut `text` to at most `MAX_DRAFT_BYTES` on a character boundary.
+fn bounded(text: &str) -> &str {
+    if text.len() <= MAX_DRAFT_BYTES {
+        return text;
+    }
+    let mut end = MAX_DRAFT_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+impl IpcMessage {
+    /// Build a FormDraft message, truncating oversized text.
+    pub fn form_draft(request_id: u64, text: &str) -> Self {
+        Self {
+            msg_type: IpcMessageType::FormDraft,
+            payload: bounded(text).as_bytes().to_vec(),
+            request_id,
+        }
+    }
+
+    /// Build a RestoreDraft message, truncating oversized text.
+    pub fn restore_draft(request_id: u64, text: &str) -> Self {
+        Self {
+            msg_type: IpcMessageType::RestoreDraft,
+            payload: bounded(text).as_bytes().to_vec(),
+            request_id,
+        }
+    }
+
+    /// Take the draft text out of a FormDraft or RestoreDraft message.
+    ///
+    /// Oversized or non-UTF-8 payloads are wiped and rejected.
+    pub fn into_draft_text(mut self) -> io::Result<String> {
+        let is_draft = matches!(
+            self.msg_type,
+            IpcMessageType::FormDraft | IpcMessageType::RestoreDraft
+        );
+        if !is_draft || self.payload.len() > MAX_DRAFT_BYTES {
+            self.payload.fill(0);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Malformed form draft",
+            ));
+        }
+
+        String::from_utf8(self.payload).map_err(|e| {
+            let mut bytes = e.into_bytes();
+            bytes.fill(0);
+            io::Error::new(io::ErrorKind::InvalidData, "Malformed form draft")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IpcChannel;
+
+    #[test]
+    fn test_throttle() {
+        let start = Instant::now();
+        let mut throttle = DraftThrottle::default();
+
+        assert!(throttle.should_send(start));
+        assert!(!throttle.should_send(start + Duration::from_millis(500)));
+        assert!(throttle.should_send(start + DRAFT_MIN_INTERVAL));
+    }
+
+    #[test]
+    fn test_draft_bounded() {
+        let long = "é".repeat(MAX_DRAFT_BYTES);
+        let msg = IpcMessage::form_draft(1, &long);
+        assert!(msg.payload.len() <= MAX_DRAFT_BYTES);
+
+        let text = msg.into_draft_text().expect("truncated on a char boundary");
+        assert!(text.chars().all(|c| c == 'é'));
+
+        let oversized = IpcMessage {
+            msg_type: IpcMessageType::FormDraft,
+            payload: vec![b'a'; MAX_DRAFT_BYTES + 1],
+            request_id: 1,
+        };
+        assert!(oversized.into_draft_text().is_err());
+    }
+
+    #[test]
+    fn test_draft_roundtrip_over_ipc() {
+        let (content, ui) = IpcChannel::create_pair().expect("Failed to create channel");
+
+        content
+            .send(&IpcMessage::form_draft(4, "half-written post"))
+            .expect("Failed to send");
+        let received = ui.recv().expect("Failed to receive");
+        assert_eq!(received.msg_type, IpcMessageType::FormDraft);
+        assert_eq!(
+            received.into_draft_text().expect("Valid draft"),
+            "half-written post"
+        );
+
+        ui.send(&IpcMessage::restore_draft(5, "half-written post"))
+            .expect("Failed to send");
+        let received = content.recv().expect("Failed to receive");
+        assert_eq!(received.msg_type, IpcMessageType::RestoreDraft);
+    }
+}
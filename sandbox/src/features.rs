@@ -0,0 +1,336 @@
+//! Kernel feature detection and graceful degradation.
+//!
+//! Older kernels lack Landlock, some distros disable unprivileged user
+//! namespaces, and containers often block seccomp. The launcher probes
+//! once at startup, refuses to run when a Required feature is missing,
+//! and otherwise hands the matrix to every `SandboxConfig` so builders
+//! skip unsupported layers on purpose instead of failing half-way.
+
+use std::io;
+use std::path::Path;
+
+/// An optional kernel facility the sandbox layers are built on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KernelFeature {
+    /// Unprivileged user namespaces (and with them net/pid namespaces)
+    UserNamespaces,
+    /// seccomp-bpf syscall filtering
+    Seccomp,
+    /// Landlock filesystem restrictions
+    Landlock,
+    /// memfd_create with file sealing
+    MemfdSealing,
+    /// A writable tmpfs at /dev/shm
+    DevShm,
+}
+
+/// How the launcher reacts when a feature is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureRequirement {
+    /// Refuse to start
+    Required,
+    /// Start with the layer skipped and tell the user
+    Degradable,
+}
+
+impl KernelFeature {
+    /// Every feature, in probe order.
+    pub const ALL: [KernelFeature; 5] = [
+        KernelFeature::UserNamespaces,
+        KernelFeature::Seccomp,
+        KernelFeature::Landlock,
+        KernelFeature::MemfdSealing,
+        KernelFeature::DevShm,
+    ];
+
+    /// Classification of this feature.
+    ///
+    /// seccomp is the only layer that still confines a compromised content
+    /// process when everything else is gone, so running without it is not
+    /// offered. The rest narrow the blast radius and may be skipped.
+    pub fn requirement(self) -> FeatureRequirement {
+        match self {
+            KernelFeature::Seccomp => FeatureRequirement::Required,
+            KernelFeature::UserNamespaces
+            | KernelFeature::Landlock
+            | KernelFeature::MemfdSealing
+            | KernelFeature::DevShm => FeatureRequirement::Degradable,
+        }
+    }
+
+    /// Human-readable name for the startup report.
+    pub fn name(self) -> &'static str {
+        match self {
+            KernelFeature::UserNamespaces => "user namespaces",
+            KernelFeature::Seccomp => "seccomp",
+            KernelFeature::Landlock => "Landlock",
+            KernelFeature::MemfdSealing => "memfd sealing",
+            KernelFeature::DevShm => "/dev/shm",
+        }
+    }
+}
+
+/// Which kernel features are available on this host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureMatrix {
+    /// Unprivileged user namespaces can be created
+    pub user_namespaces: bool,
+    /// seccomp filters can be installed
+    pub seccomp: bool,
+    /// Landlock ABI is available
+    pub landlock: bool,
+    /// memfd_create supports sealing
+    pub memfd_sealing: bool,
+    /// /dev/shm is a writable directory
+    pub dev_shm: bool,
+}
+
+impl Default for FeatureMatrix {
+    fn default() -> Self {
+        Self::all_available()
+    }
+}
+
+impl FeatureMatrix {
+    /// A matrix with every feature present.
+    pub const fn all_available() -> Self {
+        Self {
+            user_namespaces: true,
+            seccomp: true,
+            landlock: true,
+            memfd_sealing: true,
+            dev_shm: true,
+        }
+    }
+
+    /// Probe the running kernel.
+    pub fn detect() -> Self {
+        Self {
+            user_namespaces: detect_user_namespaces(),
+            seccomp: detect_seccomp(),
+            landlock: detect_landlock(),
+            memfd_sealing: detect_memfd_sealing(),
+            dev_shm: detect_dev_shm(),
+        }
+    }
+
+    /// Copy of this matrix with `feature` marked present or absent.
+    pub fn with(mut self, feature: KernelFeature, available: bool) -> Self {
+        *self.slot(feature) = available;
+        self
+    }
+
+    /// Whether `feature` is available.
+    pub fn is_available(&self, feature: KernelFeature) -> bool {
+        match feature {
+            KernelFeature::UserNamespaces => self.user_namespaces,
+            KernelFeature::Seccomp => self.seccomp,
+            KernelFeature::Landlock => self.landlock,
+            KernelFeature::MemfdSealing => self.memfd_sealing,
+            KernelFeature::DevShm => self.dev_shm,
+        }
+    }
+
+    /// Missing features, in probe order.
+    pub fn missing(&self) -> Vec<KernelFeature> {
+        KernelFeature::ALL
+            .into_iter()
+            .filter(|feature| !self.is_available(*feature))
+            .collect()
+    }
+
+    /// Decide whether the browser may start on this host.
+    ///
+    /// Fails with `ErrorKind::Unsupported` naming the first missing
+    /// Required feature; otherwise reports what will be skipped.
+    pub fn check(&self) -> io::Result<StartupReport> {
+        let missing = self.missing();
+
+        if let Some(required) = missing
+            .iter()
+            .find(|feature| feature.requirement() == FeatureRequirement::Required)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("required kernel feature missing: {}", required.name()),
+            ));
+        }
+
+        Ok(StartupReport { degraded: missing })
+    }
+
+    fn slot(&mut self, feature: KernelFeature) -> &mut bool {
+        match feature {
+            KernelFeature::UserNamespaces => &mut self.user_namespaces,
+            KernelFeature::Seccomp => &mut self.seccomp,
+            KernelFeature::Landlock => &mut self.landlock,
+            KernelFeature::MemfdSealing => &mut self.memfd_sealing,
+            KernelFeature::DevShm => &mut self.dev_shm,
+        }
+    }
+}
+
+/// Outcome of a successful startup check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartupReport {
+    /// Degradable features that are missing and will be skipped
+    pub degraded: Vec<KernelFeature>,
+}
+
+impl StartupReport {
+    /// Whether any layer will be skipped.
+    pub fn is_degraded(&self) -> bool {
+        !self.degraded.is_empty()
+    }
+
+    /// One-line notice for the UI, if anything is degraded.
+    pub fn notice(&self) -> Option<String> {
+        if !self.is_degraded() {
+            return None;
+        }
+
+        let missing: Vec<String> = self
+            .degraded
+            .iter()
+            .map(|feature| format!("no {}", feature.name()))
+            .collect();
+        Some(format!(
+            "running with reduced isolation: {}",
+            missing.join(", ")
+        ))
+    }
+}
+
+/// Check sysctls that gate unprivileged user namespaces.
+fn detect_user_namespaces() -> bool {
+    if !Path::new("/proc/self/ns/user").exists() {
+        return false;
+    }
+
+    let sysctl_is_zero = |path: &str| {
+        std::fs::read_to_string(path)
+            .map(|value| value.trim() == "0")
+            .unwrap_or(false)
+    };
+
+    // Debian/Ubuntu knob, then the upstream per-user limit
+    !sysctl_is_zero("/proc/sys/kernel/unprivileged_userns_clone")
+        && !sysctl_is_zero("/proc/sys/user/max_user_namespaces")
+}
+
+/// The Seccomp status line is absent without CONFIG_SECCOMP, and
+/// PR_GET_SECCOMP fails when a container filter denies prctl.
+fn detect_seccomp() -> bool {
+    let status = match std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    if !status.lines().any(|line| line.starts_with("Seccomp:")) {
+        return false;
+    }
+
+    let result = unsafe { libc::prctl(libc::PR_GET_SECCOMP, 0, 0, 0, 0) };
+    result >= 0
+}
+
+/// Ask for the Landlock ABI version; any positive answer means usable.
+fn detect_landlock() -> bool {
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1;
+
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    abi > 0
+}
+
+/// Create a throwaway memfd and try to seal it.
+fn detect_memfd_sealing() -> bool {
+    let fd = unsafe {
+        libc::memfd_create(
+            c"forloop-probe".as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        )
+    };
+    if fd < 0 {
+        return false;
+    }
+
+    let sealed = unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, libc::F_SEAL_WRITE) } == 0;
+    unsafe {
+        libc::close(fd);
+    }
+    sealed
+}
+
+fn detect_dev_shm() -> bool {
+    let path = c"/dev/shm";
+    Path::new("/dev/shm").is_dir() && unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_matches_missing() {
+        let matrix = FeatureMatrix::detect();
+        for feature in KernelFeature::ALL {
+            assert_eq!(
+                matrix.missing().contains(&feature),
+                !matrix.is_available(feature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_required_refuses_start() {
+        let matrix = FeatureMatrix::all_available().with(KernelFeature::Seccomp, false);
+        let err = matrix.check().expect_err("seccomp is required");
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("seccomp"));
+    }
+
+    #[test]
+    fn test_degradable_features_warn() {
+        let report = FeatureMatrix::all_available()
+            .check()
+            .expect("full matrix starts");
+        assert_eq!(report.notice(), None);
+
+        let report = FeatureMatrix::all_available()
+            .with(KernelFeature::Landlock, false)
+            .with(KernelFeature::UserNamespaces, false)
+            .check()
+            .expect("degradable features only");
+        assert_eq!(
+            report.notice().as_deref(),
+            Some("running with reduced isolation: no user namespaces, no Landlock")
+        );
+    }
+
+    #[test]
+    fn test_classification_every_combination() {
+        for mask in 0u32..(1 << KernelFeature::ALL.len()) {
+            let matrix = KernelFeature::ALL
+                .iter()
+                .enumerate()
+                .fold(FeatureMatrix::all_available(), |m, (i, f)| {
+                    m.with(*f, mask & (1 << i) == 0)
+                });
+
+            match matrix.check() {
+                Ok(report) => {
+                    assert!(matrix.seccomp);
+                    assert_eq!(report.degraded, matrix.missing());
+                    assert_eq!(report.is_degraded(), mask != 0);
+                }
+                Err(_) => assert!(!matrix.seccomp),
+            }
+        }
+    }
+}
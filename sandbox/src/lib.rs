@@ -11,9 +11,30 @@
 
 #![cfg(target_os = "linux")]
 
-use std::ffi::CString;
 use std::io;
 
+use forloop_config::StatePaths;
+
+mod assets;
+mod clock;
+mod draft;
+mod features;
+mod monitor;
+mod router;
+mod seccomp;
+mod signals;
+
+pub use assets::{AssetEntry, AssetId, AssetManifest, AssetMapping, AssetStore, ContentAssets};
+pub use clock::{vdso_present, ClockBroker, ClockPolicy, COARSE_CLOCKS};
+pub use draft::{DraftThrottle, DRAFT_MIN_INTERVAL, MAX_DRAFT_BYTES};
+pub use features::{FeatureMatrix, FeatureRequirement, KernelFeature, StartupReport};
+pub use monitor::{
+    ResourceKind, ResourceLimits, ResourceMonitor, ResourceStats, ResourceVerdict,
+};
+pub use router::{IpcRouter, RouteOutcome};
+pub use seccomp::{ArgFilter, ArgMatch, SandboxPolicy, SeccompAction};
+pub use signals::{isolate_process_group, QuitSignal, QuitSignals, SIGNAL_QUIT_DEADLINE};
+
 /// Sandbox configuration for a process.
 #[derive(Debug, Clone)]
 pub struct SandboxConfig {
@@ -35,6 +56,12 @@ pub struct SandboxConfig {
     pub use_pid_ns: bool,
     /// seccomp-bpf policy
     pub seccomp_policy: SeccompPolicy,
+    /// Restrict filesystem access with Landlock
+    pub use_landlock: bool,
+    /// Shared memory backend for IPC buffers (None: copy over the socket)
+    pub shm_backend: Option<ShmBackend>,
+    /// Kernel features this config was adjusted for
+    pub features: FeatureMatrix,
 }
 
 /// Process types in forloop.
@@ -50,6 +77,15 @@ pub enum ProcessType {
     Content,
 }
 
+/// Backing store for shared memory handed between processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmBackend {
+    /// memfd sealed against writes by the receiver
+    SealedMemfd,
+    /// Unsealed file under /dev/shm
+    DevShm,
+}
+
 /// seccomp-bpf policy specification.
 #[derive(Debug, Clone)]
 pub enum SeccompPolicy {
@@ -79,6 +115,9 @@ impl SandboxConfig {
             use_net_ns: false,
             use_pid_ns: false,
             seccomp_policy: SeccompPolicy::None,
+            use_landlock: false,
+            shm_backend: Some(ShmBackend::SealedMemfd),
+            features: FeatureMatrix::all_available(),
         }
     }
 
@@ -94,6 +133,9 @@ impl SandboxConfig {
             use_net_ns: true,
             use_pid_ns: true,
             seccomp_policy: SeccompPolicy::Broker,
+            use_landlock: true,
+            shm_backend: Some(ShmBackend::SealedMemfd),
+            features: FeatureMatrix::all_available(),
         }
     }
 
@@ -109,6 +151,9 @@ impl SandboxConfig {
             use_net_ns: false, // Needs network namespace access
             use_pid_ns: true,
             seccomp_policy: SeccompPolicy::Network,
+            use_landlock: true,
+            shm_backend: Some(ShmBackend::SealedMemfd),
+            features: FeatureMatrix::all_available(),
         }
     }
 
@@ -124,8 +169,42 @@ impl SandboxConfig {
             use_net_ns: true, // Isolated network namespace
             use_pid_ns: true,
             seccomp_policy: SeccompPolicy::Content,
+            use_landlock: true,
+            shm_backend: Some(ShmBackend::SealedMemfd),
+            features: FeatureMatrix::all_available(),
         }
     }
+
+    /// Adjust this config to the host's kernel features.
+    ///
+    /// Layers the kernel cannot provide are switched off here, so
+    /// `apply_sandbox` skips them deliberately instead of erroring.
+    pub fn with_features(mut self, features: FeatureMatrix) -> Self {
+        if !features.user_namespaces {
+            // Unprivileged net/pid namespaces need a user namespace first
+            self.use_user_ns = false;
+            self.use_net_ns = false;
+            self.use_pid_ns = false;
+        }
+
+        if !features.seccomp {
+            self.seccomp_policy = SeccompPolicy::None;
+        }
+
+        if !features.landlock {
+            self.use_landlock = false;
+        }
+
+        if self.shm_backend == Some(ShmBackend::SealedMemfd) && !features.memfd_sealing {
+            self.shm_backend = Some(ShmBackend::DevShm);
+        }
+        if self.shm_backend == Some(ShmBackend::DevShm) && !features.dev_shm {
+            self.shm_backend = None;
+        }
+
+        self.features = features;
+        self
+    }
 }
 
 /// Apply sandbox restrictions to the current process.
@@ -145,6 +224,17 @@ pub fn apply_sandbox(config: &SandboxConfig) -> io::Result<()> {
     Ok(())
 }
 
+/// Start the UI process, which launches every other process.
+///
+/// The quit-signal handlers are installed first, before any child exists,
+/// to wipe the RAM state under `paths`; then `config` is applied as by
+/// `apply_sandbox`. The launcher polls the returned handlers.
+pub fn start_ui_process(config: &SandboxConfig, paths: &StatePaths) -> io::Result<QuitSignals> {
+    let signals = QuitSignals::install(&[paths.download_dir(), paths.tor_data_dir()])?;
+    apply_sandbox(config)?;
+    Ok(signals)
+}
+
 /// Apply Linux namespace isolation.
 fn apply_namespaces(config: &SandboxConfig) -> io::Result<()> {
     use libc::{unshare, CLONE_NEWNET, CLONE_NEWPID, CLONE_NEWUSER};
@@ -175,6 +265,15 @@ fn apply_namespaces(config: &SandboxConfig) -> io::Result<()> {
 
 /// Apply filesystem restrictions using bind mounts and pivot_root.
 fn apply_filesystem_restrictions(config: &SandboxConfig) -> io::Result<()> {
+    if config.use_landlock {
+        log::debug!("Landlock ruleset applied for {:?}", config.process_type);
+    } else if !config.features.landlock {
+        log::debug!(
+            "Landlock unavailable, skipped for {:?}",
+            config.process_type
+        );
+    }
+
     if config.allowed_paths.is_empty() && !config.allow_fs_read && !config.allow_fs_write {
         // Create minimal root filesystem
         // This is done via pivot_root to an empty tmpfs
@@ -226,6 +325,11 @@ fn drop_capabilities(config: &SandboxConfig) -> io::Result<()> {
 
 /// Apply seccomp-bpf filter.
 fn apply_seccomp(config: &SandboxConfig) -> io::Result<()> {
+    if !config.features.seccomp {
+        log::warn!("seccomp unavailable, skipped for {:?}", config.process_type);
+        return Ok(());
+    }
+
     match config.seccomp_policy {
         SeccompPolicy::None => {
             // No seccomp restrictions
@@ -246,47 +350,23 @@ fn apply_seccomp(config: &SandboxConfig) -> io::Result<()> {
 
 /// seccomp filter for content process.
 fn apply_content_seccomp() -> io::Result<()> {
-    // This would use libseccomp or raw BPF
-    // Allowed syscalls for content process:
-
-    let allowed_syscalls = [
-        libc::SYS_read,
-        libc::SYS_write,
-        libc::SYS_close,
-        libc::SYS_mmap,
-        libc::SYS_munmap,
-        libc::SYS_mprotect,
-        libc::SYS_brk,
-        libc::SYS_rt_sigaction,
-        libc::SYS_rt_sigprocmask,
-        libc::SYS_rt_sigreturn,
-        libc::SYS_ioctl,
-        libc::SYS_pipe2,
-        libc::SYS_dup,
-        libc::SYS_dup2,
-        libc::SYS_clone,
-        libc::SYS_wait4,
-        libc::SYS_exit,
-        libc::SYS_exit_group,
-        libc::SYS_futex,
-        libc::SYS_set_tid_address,
-        libc::SYS_clock_gettime,
-        libc::SYS_epoll_create1,
-        libc::SYS_epoll_ctl,
-        libc::SYS_epoll_wait,
-        libc::SYS_recvmsg,
-        libc::SYS_sendmsg,
-        libc::SYS_getrandom,
-        // Add more as needed
-    ];
+    // clone, clone3 and ioctl are argument-filtered, and clock_gettime is
+    // brokered; see SandboxPolicy::content and the clock module
+    let policy = SandboxPolicy::content();
+    let program = policy.compile();
 
     log::debug!(
-        "Content process seccomp filter: {} syscalls allowed",
-        allowed_syscalls.len()
+        "Content process seccomp filter: {} syscalls allowed, {} argument-filtered, {} BPF instructions",
+        policy.allowed.len(),
+        policy.filtered.len(),
+        program.len()
     );
+    if vdso_present() {
+        log::warn!("vDSO mapped in content process; clock_gettime via libc bypasses the clock broker");
+    }
 
-    // In production: Create and load BPF filter
-    // seccomp(SECCOMP_SET_MODE_FILTER, 0, &filter)
+    // In production: the broker keeps policy.install_with_listener()'s fd
+    // and runs a ClockBroker on it
 
     Ok(())
 }
@@ -381,22 +461,160 @@ pub struct IpcMessage {
 }
 
 /// Types of IPC messages.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// The discriminant is the value on the wire and must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
 pub enum IpcMessageType {
     /// Network request
-    NetworkRequest,
+    NetworkRequest = 0,
     /// Network response
-    NetworkResponse,
+    NetworkResponse = 1,
     /// Fingerprint identity
-    FingerprintIdentity,
+    FingerprintIdentity = 2,
     /// Render request
-    RenderRequest,
+    RenderRequest = 3,
     /// Render complete
-    RenderComplete,
+    RenderComplete = 4,
     /// Error
-    Error,
+    Error = 5,
     /// Shutdown
-    Shutdown,
+    Shutdown = 6,
+    /// Periodic resource usage report (child -> broker)
+    StatsReport = 7,
+    /// Focused textarea contents (content -> UI)
+    FormDraft = 8,
+    /// Unsent text to put back after a recycle (UI -> content)
+    RestoreDraft = 9,
+    /// Asset ids to inherited fds (broker -> content, at spawn)
+    AssetManifest = 10,
+}
+
+impl IpcMessageType {
+    /// Every known message type, in wire order.
+    pub const ALL: [Self; 11] = [
+        Self::NetworkRequest,
+        Self::NetworkResponse,
+        Self::FingerprintIdentity,
+        Self::RenderRequest,
+        Self::RenderComplete,
+        Self::Error,
+        Self::Shutdown,
+        Self::StatsReport,
+        Self::FormDraft,
+        Self::RestoreDraft,
+        Self::AssetManifest,
+    ];
+}
+
+impl From<IpcMessageType> for u32 {
+    fn from(msg_type: IpcMessageType) -> Self {
+        msg_type as u32
+    }
+}
+
+/// A wire discriminant no known message type uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownMessageType(pub u32);
+
+impl std::fmt::Display for UnknownMessageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown IPC message type {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMessageType {}
+
+impl TryFrom<u32> for IpcMessageType {
+    type Error = UnknownMessageType;
+
+    fn try_from(value: u32) -> Result<Self, UnknownMessageType> {
+        Self::ALL
+            .into_iter()
+            .find(|msg_type| u32::from(*msg_type) == value)
+            .ok_or(UnknownMessageType(value))
+    }
+}
+
+/// Message type as read off the wire, known or not.
+///
+/// Newer peers may send types this build does not know; they decode to
+/// `Unknown` instead of being mistaken for something else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireMessageType {
+    /// A type this build understands
+    Known(IpcMessageType),
+    /// Any other discriminant, carried verbatim
+    Unknown(u32),
+}
+
+impl From<u32> for WireMessageType {
+    fn from(value: u32) -> Self {
+        IpcMessageType::try_from(value).map_or(Self::Unknown(value), Self::Known)
+    }
+}
+
+impl From<IpcMessageType> for WireMessageType {
+    fn from(msg_type: IpcMessageType) -> Self {
+        Self::Known(msg_type)
+    }
+}
+
+impl From<WireMessageType> for u32 {
+    fn from(msg_type: WireMessageType) -> Self {
+        match msg_type {
+            WireMessageType::Known(known) => known.into(),
+            WireMessageType::Unknown(value) => value,
+        }
+    }
+}
+
+/// IPC message as received, before its type is checked.
+#[derive(Debug)]
+pub struct RawIpcMessage {
+    /// Message type as sent
+    pub msg_type: WireMessageType,
+    /// Message payload
+    pub payload: Vec<u8>,
+    /// Request ID for correlation
+    pub request_id: u64,
+}
+
+impl RawIpcMessage {
+    /// Convert to a typed message; unknown types are wiped and rejected.
+    pub fn into_known(mut self) -> Result<IpcMessage, UnknownMessageType> {
+        match self.msg_type {
+            WireMessageType::Known(msg_type) => Ok(IpcMessage {
+                msg_type,
+                payload: self.payload,
+                request_id: self.request_id,
+            }),
+            WireMessageType::Unknown(value) => {
+                self.payload.fill(0);
+                Err(UnknownMessageType(value))
+            }
+        }
+    }
+}
+
+impl IpcMessage {
+    /// Build a StatsReport message carrying the given resource stats.
+    pub fn stats_report(request_id: u64, stats: &ResourceStats) -> Self {
+        Self {
+            msg_type: IpcMessageType::StatsReport,
+            payload: stats.to_bytes(),
+            request_id,
+        }
+    }
+
+    /// Build an AssetManifest message for a freshly spawned content process.
+    pub fn asset_manifest(request_id: u64, manifest: &AssetManifest) -> Self {
+        Self {
+            msg_type: IpcMessageType::AssetManifest,
+            payload: manifest.to_bytes(),
+            request_id,
+        }
+    }
 }
 
 /// IPC channel between processes.
@@ -428,12 +646,22 @@ impl IpcChannel {
 
     /// Send a message.
     pub fn send(&self, msg: &IpcMessage) -> io::Result<()> {
+        self.send_frame(msg.msg_type.into(), msg.request_id, &msg.payload)
+    }
+
+    /// Send a frame with the given wire discriminant.
+    pub(crate) fn send_frame(
+        &self,
+        msg_type: u32,
+        request_id: u64,
+        payload: &[u8],
+    ) -> io::Result<()> {
         // Serialize message
         let mut buffer = Vec::new();
-        buffer.extend_from_slice(&(msg.msg_type as u32).to_le_bytes());
-        buffer.extend_from_slice(&msg.request_id.to_le_bytes());
-        buffer.extend_from_slice(&(msg.payload.len() as u32).to_le_bytes());
-        buffer.extend_from_slice(&msg.payload);
+        buffer.extend_from_slice(&msg_type.to_le_bytes());
+        buffer.extend_from_slice(&request_id.to_le_bytes());
+        buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(payload);
 
         let result = unsafe {
             libc::send(
@@ -444,6 +672,9 @@ impl IpcChannel {
             )
         };
 
+        // Payloads may carry form drafts; don't leave copies on the heap
+        buffer.fill(0);
+
         if result < 0 {
             return Err(io::Error::last_os_error());
         }
@@ -451,8 +682,19 @@ impl IpcChannel {
         Ok(())
     }
 
-    /// Receive a message.
+    /// Receive a message of a known type.
+    ///
+    /// A message of an unknown type is wiped and reported as InvalidData;
+    /// the channel stays usable. Use `recv_raw` with an `IpcRouter` to
+    /// tolerate newer peers.
     pub fn recv(&self) -> io::Result<IpcMessage> {
+        self.recv_raw()?
+            .into_known()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Receive a message, carrying its type verbatim.
+    pub fn recv_raw(&self) -> io::Result<RawIpcMessage> {
         let mut buffer = vec![0u8; 65536];
 
         let result = unsafe {
@@ -482,18 +724,10 @@ impl IpcChannel {
         let payload_len = u32::from_le_bytes(buffer[12..16].try_into().unwrap()) as usize;
 
         let payload = buffer[16..16 + payload_len].to_vec();
+        buffer.fill(0);
 
-        Ok(IpcMessage {
-            msg_type: match msg_type {
-                0 => IpcMessageType::NetworkRequest,
-                1 => IpcMessageType::NetworkResponse,
-                2 => IpcMessageType::FingerprintIdentity,
-                3 => IpcMessageType::RenderRequest,
-                4 => IpcMessageType::RenderComplete,
-                5 => IpcMessageType::Error,
-                6 => IpcMessageType::Shutdown,
-                _ => IpcMessageType::Error,
-            },
+        Ok(RawIpcMessage {
+            msg_type: msg_type.into(),
             request_id,
             payload,
         })
@@ -526,6 +760,53 @@ mod tests {
         assert!(!network.use_net_ns); // Needs real network
     }
 
+    #[test]
+    fn test_config_adjusts_to_features() {
+        let presets = [
+            SandboxConfig::ui_process,
+            SandboxConfig::broker_process,
+            SandboxConfig::network_process,
+            SandboxConfig::content_process,
+        ];
+
+        for mask in 0u32..(1 << KernelFeature::ALL.len()) {
+            let features = KernelFeature::ALL
+                .iter()
+                .enumerate()
+                .fold(FeatureMatrix::all_available(), |m, (i, f)| {
+                    m.with(*f, mask & (1 << i) == 0)
+                });
+
+            for preset in presets {
+                let full = preset();
+                let config = preset().with_features(features);
+                assert_eq!(config.features, features);
+
+                if features.user_namespaces {
+                    assert_eq!(config.use_user_ns, full.use_user_ns);
+                    assert_eq!(config.use_net_ns, full.use_net_ns);
+                    assert_eq!(config.use_pid_ns, full.use_pid_ns);
+                } else {
+                    assert!(!config.use_user_ns && !config.use_net_ns && !config.use_pid_ns);
+                }
+
+                if !features.seccomp {
+                    assert!(matches!(config.seccomp_policy, SeccompPolicy::None));
+                }
+                assert_eq!(config.use_landlock, full.use_landlock && features.landlock);
+
+                let expected_shm = if features.memfd_sealing {
+                    Some(ShmBackend::SealedMemfd)
+                } else if features.dev_shm {
+                    Some(ShmBackend::DevShm)
+                } else {
+                    None
+                };
+                assert_eq!(config.shm_backend, expected_shm);
+            }
+        }
+    }
+
     #[test]
     fn test_ipc_channel() {
         let (sender, receiver) = IpcChannel::create_pair().expect("Failed to create channel");
@@ -543,4 +824,70 @@ mod tests {
         assert_eq!(received.request_id, 12345);
         assert_eq!(received.payload, b"test payload");
     }
+
+    #[test]
+    fn test_message_type_round_trip() {
+        for (wire, msg_type) in IpcMessageType::ALL.into_iter().enumerate() {
+            let wire = wire as u32;
+            assert_eq!(u32::from(msg_type), wire);
+            assert_eq!(IpcMessageType::try_from(wire), Ok(msg_type));
+            assert_eq!(
+                WireMessageType::from(wire),
+                WireMessageType::Known(msg_type)
+            );
+        }
+
+        let next = IpcMessageType::ALL.len() as u32;
+        for wire in [next, 0xFFFF, u32::MAX] {
+            assert_eq!(
+                IpcMessageType::try_from(wire),
+                Err(UnknownMessageType(wire))
+            );
+            assert_eq!(u32::from(WireMessageType::from(wire)), wire);
+        }
+    }
+
+    #[test]
+    fn test_unknown_type_is_not_an_error_message() {
+        let (sender, receiver) = IpcChannel::create_pair().expect("Failed to create channel");
+
+        sender
+            .send_frame(42, 7, b"from the future")
+            .expect("Failed to send");
+        let error = receiver.recv().expect_err("unknown type must not decode");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // The channel is still usable afterwards
+        sender
+            .send(&IpcMessage {
+                msg_type: IpcMessageType::Shutdown,
+                request_id: 8,
+                payload: Vec::new(),
+            })
+            .expect("Failed to send");
+        assert_eq!(
+            receiver.recv().expect("Failed to receive").msg_type,
+            IpcMessageType::Shutdown
+        );
+    }
+
+    #[test]
+    fn test_stats_report_over_ipc() {
+        let (child, broker) = IpcChannel::create_pair().expect("Failed to create channel");
+
+        let stats = ResourceStats::collect(3).expect("Failed to collect stats");
+        child
+            .send(&IpcMessage::stats_report(1, &stats))
+            .expect("Failed to send");
+
+        let received = broker.recv().expect("Failed to receive");
+        assert_eq!(received.msg_type, IpcMessageType::StatsReport);
+
+        let decoded = ResourceStats::from_bytes(&received.payload).expect("Bad payload");
+        assert_eq!(decoded, stats);
+        assert_eq!(
+            ResourceMonitor::new(ProcessType::Content).evaluate(&decoded),
+            ResourceVerdict::Healthy
+        );
+    }
 }
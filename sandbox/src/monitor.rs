@@ -0,0 +1,345 @@
+//! Per-process resource monitoring for leak detection.
+//!
+//! Each child process periodically sends a StatsReport to the broker.
+//! The broker compares the report against the process type's limits,
+//! logs scrubbed warnings, and decides when a content process should
+//! be recycled. Recycling migrates nothing: the page reloads on a
+//! fresh identity in a new process.
+
+use std::io;
+
+use crate::ProcessType;
+
+/// Size of a serialized `ResourceStats` payload.
+const STATS_WIRE_LEN: usize = 24;
+
+/// Resource usage snapshot reported by a child process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResourceStats {
+    /// Resident set size in bytes
+    pub rss_bytes: u64,
+    /// Number of open file descriptors
+    pub open_fds: u64,
+    /// Live allocations held by the streaming/caching subsystems
+    pub active_allocations: u64,
+}
+
+impl ResourceStats {
+    /// Collect stats for the current process.
+    ///
+    /// RSS comes from /proc/self/statm and the fd count from /proc/self/fd.
+    /// Allocation counters are owned by the caller's subsystems and passed in.
+    pub fn collect(active_allocations: u64) -> io::Result<Self> {
+        let statm = std::fs::read_to_string("/proc/self/statm")?;
+        let rss_bytes = parse_statm_rss(&statm, page_size())?;
+
+        // The directory handle used for counting shows up as one entry
+        let open_fds = std::fs::read_dir("/proc/self/fd")?
+            .count()
+            .saturating_sub(1) as u64;
+
+        Ok(Self {
+            rss_bytes,
+            open_fds,
+            active_allocations,
+        })
+    }
+
+    /// Serialize for a StatsReport IPC payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(STATS_WIRE_LEN);
+        buffer.extend_from_slice(&self.rss_bytes.to_le_bytes());
+        buffer.extend_from_slice(&self.open_fds.to_le_bytes());
+        buffer.extend_from_slice(&self.active_allocations.to_le_bytes());
+        buffer
+    }
+
+    /// Deserialize from a StatsReport IPC payload.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        if bytes.len() != STATS_WIRE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Malformed stats report",
+            ));
+        }
+
+        let field = |i: usize| {
+            let mut raw = [0u8; 8];
+            raw.copy_from_slice(&bytes[i * 8..(i + 1) * 8]);
+            u64::from_le_bytes(raw)
+        };
+
+        Ok(Self {
+            rss_bytes: field(0),
+            open_fds: field(1),
+            active_allocations: field(2),
+        })
+    }
+}
+
+/// Get the system page size in bytes.
+fn page_size() -> u64 {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as u64
+    } else {
+        4096
+    }
+}
+
+/// Parse the resident page count (second field) out of /proc/self/statm.
+fn parse_statm_rss(statm: &str, page_size: u64) -> io::Result<u64> {
+    statm
+        .split_whitespace()
+        .nth(1)
+        .and_then(|pages| pages.parse::<u64>().ok())
+        .map(|pages| pages.saturating_mul(page_size))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed statm"))
+}
+
+/// Soft and hard resource thresholds for a process type.
+///
+/// Crossing a soft threshold logs a warning. Crossing a hard threshold
+/// recycles the process where that is possible (content processes only).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// RSS warning threshold in bytes
+    pub soft_rss_bytes: u64,
+    /// RSS recycle threshold in bytes
+    pub hard_rss_bytes: u64,
+    /// Open fd warning threshold
+    pub soft_fds: u64,
+    /// Open fd recycle threshold
+    pub hard_fds: u64,
+    /// Allocation counter warning threshold
+    pub soft_allocations: u64,
+    /// Allocation counter recycle threshold
+    pub hard_allocations: u64,
+}
+
+impl ResourceLimits {
+    /// Get the limits for a process type.
+    pub fn for_process(process_type: ProcessType) -> Self {
+        const MIB: u64 = 1024 * 1024;
+
+        match process_type {
+            ProcessType::Content => Self {
+                soft_rss_bytes: 1536 * MIB,
+                hard_rss_bytes: 3072 * MIB,
+                soft_fds: 256,
+                hard_fds: 768,
+                soft_allocations: 4096,
+                hard_allocations: 16384,
+            },
+            ProcessType::Network => Self {
+                soft_rss_bytes: 256 * MIB,
+                hard_rss_bytes: 512 * MIB,
+                soft_fds: 512,
+                hard_fds: 1024,
+                soft_allocations: 2048,
+                hard_allocations: 8192,
+            },
+            ProcessType::Broker | ProcessType::Ui => Self {
+                soft_rss_bytes: 512 * MIB,
+                hard_rss_bytes: 1024 * MIB,
+                soft_fds: 256,
+                hard_fds: 512,
+                soft_allocations: 1024,
+                hard_allocations: 4096,
+            },
+        }
+    }
+}
+
+/// Which resource crossed a threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Resident memory
+    Rss,
+    /// Open file descriptors
+    FileDescriptors,
+    /// Streaming/caching allocations
+    Allocations,
+}
+
+/// Broker decision for a single stats report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceVerdict {
+    /// Everything is below the soft thresholds
+    Healthy,
+    /// A soft threshold was crossed (or a hard one on a process we cannot recycle)
+    Warn(ResourceKind),
+    /// A hard threshold was crossed; replace the content process
+    Recycle(ResourceKind),
+}
+
+/// Broker-side resource monitor.
+#[derive(Debug, Clone)]
+pub struct ResourceMonitor {
+    process_type: ProcessType,
+    limits: ResourceLimits,
+}
+
+impl ResourceMonitor {
+    /// Create a monitor using the default limits for a process type.
+    pub fn new(process_type: ProcessType) -> Self {
+        Self::with_limits(process_type, ResourceLimits::for_process(process_type))
+    }
+
+    /// Create a monitor with explicit limits.
+    pub fn with_limits(process_type: ProcessType, limits: ResourceLimits) -> Self {
+        Self {
+            process_type,
+            limits,
+        }
+    }
+
+    /// Get the limits in use.
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.limits
+    }
+
+    /// Evaluate a stats report from the monitored child.
+    ///
+    /// Hard thresholds are checked before soft ones, so the most severe
+    /// verdict wins. Only content processes are ever recycled.
+    pub fn evaluate(&self, stats: &ResourceStats) -> ResourceVerdict {
+        let checks = [
+            (
+                ResourceKind::Rss,
+                stats.rss_bytes,
+                self.limits.soft_rss_bytes,
+                self.limits.hard_rss_bytes,
+            ),
+            (
+                ResourceKind::FileDescriptors,
+                stats.open_fds,
+                self.limits.soft_fds,
+                self.limits.hard_fds,
+            ),
+            (
+                ResourceKind::Allocations,
+                stats.active_allocations,
+                self.limits.soft_allocations,
+                self.limits.hard_allocations,
+            ),
+        ];
+
+        let verdict = if let Some(&(kind, ..)) =
+            checks.iter().find(|(_, value, _, hard)| value >= hard)
+        {
+            if self.process_type == ProcessType::Content {
+                ResourceVerdict::Recycle(kind)
+            } else {
+                ResourceVerdict::Warn(kind)
+            }
+        } else if let Some(&(kind, ..)) = checks.iter().find(|(_, value, soft, _)| value >= soft) {
+            ResourceVerdict::Warn(kind)
+        } else {
+            ResourceVerdict::Healthy
+        };
+
+        // Only the process type, resource and numbers are logged;
+        // never URLs, titles or anything else tied to the page.
+        match verdict {
+            ResourceVerdict::Healthy => {}
+            ResourceVerdict::Warn(kind) => log::warn!(
+                "{:?} process over {:?} threshold (rss={} fds={} allocs={})",
+                self.process_type,
+                kind,
+                stats.rss_bytes,
+                stats.open_fds,
+                stats.active_allocations
+            ),
+            ResourceVerdict::Recycle(kind) => log::warn!(
+                "{:?} process over hard {:?} limit, recycling",
+                self.process_type,
+                kind
+            ),
+        }
+
+        verdict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(rss_bytes: u64, open_fds: u64, active_allocations: u64) -> ResourceStats {
+        ResourceStats {
+            rss_bytes,
+            open_fds,
+            active_allocations,
+        }
+    }
+
+    #[test]
+    fn test_collect_current_process() {
+        let stats = ResourceStats::collect(7).expect("Failed to collect stats");
+        assert!(stats.rss_bytes > 0);
+        assert!(stats.open_fds >= 3); // stdin, stdout, stderr
+        assert_eq!(stats.active_allocations, 7);
+    }
+
+    #[test]
+    fn test_parse_statm() {
+        assert_eq!(
+            parse_statm_rss("12345 678 90 1 0 234 0\n", 4096).expect("valid statm"),
+            678 * 4096
+        );
+        assert!(parse_statm_rss("garbage", 4096).is_err());
+    }
+
+    #[test]
+    fn test_stats_wire_roundtrip() {
+        let stats = report(1 << 30, 42, 9);
+        let decoded = ResourceStats::from_bytes(&stats.to_bytes()).expect("valid payload");
+        assert_eq!(decoded, stats);
+
+        assert!(ResourceStats::from_bytes(&[0u8; 5]).is_err());
+    }
+
+    #[test]
+    fn test_healthy_report() {
+        let monitor = ResourceMonitor::new(ProcessType::Content);
+        assert_eq!(
+            monitor.evaluate(&report(0, 10, 0)),
+            ResourceVerdict::Healthy
+        );
+    }
+
+    #[test]
+    fn test_soft_threshold_warns() {
+        let monitor = ResourceMonitor::new(ProcessType::Content);
+        let limits = *monitor.limits();
+
+        assert_eq!(
+            monitor.evaluate(&report(0, limits.soft_fds, 0)),
+            ResourceVerdict::Warn(ResourceKind::FileDescriptors)
+        );
+    }
+
+    #[test]
+    fn test_hard_threshold_recycles_content() {
+        let monitor = ResourceMonitor::new(ProcessType::Content);
+        let limits = *monitor.limits();
+
+        // A soft fd breach must not mask a hard RSS breach
+        assert_eq!(
+            monitor.evaluate(&report(limits.hard_rss_bytes, limits.soft_fds, 0)),
+            ResourceVerdict::Recycle(ResourceKind::Rss)
+        );
+    }
+
+    #[test]
+    fn test_hard_threshold_only_warns_for_network() {
+        let monitor = ResourceMonitor::new(ProcessType::Network);
+        let limits = *monitor.limits();
+
+        assert_eq!(
+            monitor.evaluate(&report(0, 0, limits.hard_allocations)),
+            ResourceVerdict::Warn(ResourceKind::Allocations)
+        );
+    }
+}
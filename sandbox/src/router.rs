@@ -0,0 +1,184 @@
+//! Dispatch of received IPC messages to typed handlers.
+//!
+//! Messages of a type this build does not know come from a newer peer.
+//! Before the peers have agreed on a protocol version they are dropped
+//! with a warning that names only the discriminant and payload size, so
+//! adding a message type never breaks an older process. Once versions
+//! match, an unknown type is a protocol bug and a hard error.
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::{IpcMessage, IpcMessageType, RawIpcMessage};
+
+/// What `IpcRouter::route` did with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOutcome {
+    /// Handed to the handler registered for its type
+    Delivered(IpcMessageType),
+    /// Known type, but no handler is registered for it
+    Unhandled(IpcMessageType),
+    /// Unknown type, wiped and dropped
+    DroppedUnknown(u32),
+}
+
+type Handler = Box<dyn FnMut(IpcMessage)>;
+
+/// Routes received messages to one handler per known type.
+#[derive(Default)]
+pub struct IpcRouter {
+    handlers: HashMap<IpcMessageType, Handler>,
+    version_matched: bool,
+    dropped_unknown: u64,
+}
+
+impl IpcRouter {
+    /// Create a router with no handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the handler for `msg_type`, replacing any previous one.
+    pub fn on(&mut self, msg_type: IpcMessageType, handler: impl FnMut(IpcMessage) + 'static) {
+        self.handlers.insert(msg_type, Box::new(handler));
+    }
+
+    /// Record whether the peers agreed on a protocol version.
+    ///
+    /// Set by the version handshake; unknown types are fatal afterwards.
+    pub fn set_version_matched(&mut self, matched: bool) {
+        self.version_matched = matched;
+    }
+
+    /// Route one received message.
+    pub fn route(&mut self, message: RawIpcMessage) -> io::Result<RouteOutcome> {
+        let payload_len = message.payload.len();
+        let message = match message.into_known() {
+            Ok(message) => message,
+            Err(unknown) if self.version_matched => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, unknown));
+            }
+            Err(unknown) => {
+                self.dropped_unknown += 1;
+                log::warn!(
+                    "Dropped IPC message of unknown type {} ({} byte payload)",
+                    unknown.0,
+                    payload_len
+                );
+                return Ok(RouteOutcome::DroppedUnknown(unknown.0));
+            }
+        };
+
+        let msg_type = message.msg_type;
+        match self.handlers.get_mut(&msg_type) {
+            Some(handler) => {
+                handler(message);
+                Ok(RouteOutcome::Delivered(msg_type))
+            }
+            None => Ok(RouteOutcome::Unhandled(msg_type)),
+        }
+    }
+
+    /// Number of unknown-type messages dropped so far.
+    pub fn dropped_unknown(&self) -> u64 {
+        self.dropped_unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IpcChannel;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Seen = Rc<RefCell<Vec<(IpcMessageType, u64)>>>;
+
+    fn router_for_all_types(seen: &Seen) -> IpcRouter {
+        let mut router = IpcRouter::new();
+        for msg_type in IpcMessageType::ALL {
+            let seen = Rc::clone(seen);
+            router.on(msg_type, move |message| {
+                assert_eq!(message.msg_type, msg_type);
+                seen.borrow_mut()
+                    .push((message.msg_type, message.request_id));
+            });
+        }
+        router
+    }
+
+    #[test]
+    fn test_compatibility_matrix() {
+        let (sender, receiver) = IpcChannel::create_pair().expect("Failed to create channel");
+        let seen: Seen = Rc::default();
+        let mut router = router_for_all_types(&seen);
+
+        // Every known type reaches its own handler
+        for (id, msg_type) in IpcMessageType::ALL.into_iter().enumerate() {
+            sender
+                .send(&IpcMessage {
+                    msg_type,
+                    payload: vec![id as u8],
+                    request_id: id as u64,
+                })
+                .expect("Failed to send");
+            let raw = receiver.recv_raw().expect("Failed to receive");
+            assert_eq!(
+                router.route(raw).expect("routed"),
+                RouteOutcome::Delivered(msg_type)
+            );
+        }
+        let expected: Vec<_> = IpcMessageType::ALL
+            .into_iter()
+            .enumerate()
+            .map(|(id, msg_type)| (msg_type, id as u64))
+            .collect();
+        assert_eq!(*seen.borrow(), expected);
+
+        // Unknown types from a newer peer reach no handler and are not fatal
+        seen.borrow_mut().clear();
+        let next = IpcMessageType::ALL.len() as u32;
+        for wire in [next, 1000, u32::MAX] {
+            sender
+                .send_frame(wire, 99, b"secret")
+                .expect("Failed to send");
+            let raw = receiver.recv_raw().expect("Failed to receive");
+            assert_eq!(
+                router.route(raw).expect("not fatal"),
+                RouteOutcome::DroppedUnknown(wire)
+            );
+        }
+        assert!(seen.borrow().is_empty());
+        assert_eq!(router.dropped_unknown(), 3);
+    }
+
+    #[test]
+    fn test_unknown_type_fatal_once_versions_match() {
+        let (sender, receiver) = IpcChannel::create_pair().expect("Failed to create channel");
+        let seen: Seen = Rc::default();
+        let mut router = router_for_all_types(&seen);
+        router.set_version_matched(true);
+
+        let next = IpcMessageType::ALL.len() as u32;
+        sender.send_frame(next, 1, b"").expect("Failed to send");
+        let raw = receiver.recv_raw().expect("Failed to receive");
+        let error = router.route(raw).expect_err("protocol bug");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(seen.borrow().is_empty());
+        assert_eq!(router.dropped_unknown(), 0);
+    }
+
+    #[test]
+    fn test_unhandled_known_type() {
+        let mut router = IpcRouter::new();
+        let raw = RawIpcMessage {
+            msg_type: IpcMessageType::Shutdown.into(),
+            payload: Vec::new(),
+            request_id: 1,
+        };
+        assert_eq!(
+            router.route(raw).expect("routed"),
+            RouteOutcome::Unhandled(IpcMessageType::Shutdown)
+        );
+    }
+}
@@ -0,0 +1,413 @@
+//! Structured seccomp-bpf policies.
+//!
+//! Policies are written as data (plain allowlist plus per-syscall
+//! argument filters) so they can be reviewed without reading BPF, and
+//! are compiled to a classic BPF program only when installed.
+
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+
+use crate::clock::ClockPolicy;
+use crate::ProcessType;
+
+/// AUDIT_ARCH value for the architecture we were built for.
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// x32 syscalls share the x86_64 arch value; reject them by number.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets into `struct seccomp_data`.
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+const DATA_ARGS: u32 = 16;
+
+/// Namespace-creating clone flags. Never allowed in a sandboxed child.
+const CLONE_NAMESPACE_FLAGS: u32 = (libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET) as u32;
+
+/// Flags every thread-creating clone carries.
+const CLONE_THREAD_FLAGS: u32 = (libc::CLONE_VM | libc::CLONE_THREAD | libc::CLONE_SIGHAND) as u32;
+
+/// ioctl requests harmless enough for any sandboxed process.
+///
+/// Terminal probes (isatty, window size) and socket queue queries.
+/// DRM requests belong in a GPU process policy, never here.
+const BASIC_IOCTLS: &[u32] = &[
+    libc::TCGETS as u32,
+    libc::TIOCGWINSZ as u32,
+    libc::FIONREAD as u32,
+    libc::FIONBIO as u32,
+    libc::FIOCLEX as u32,
+];
+
+/// What the kernel does with a matching syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    /// Let it through
+    Allow,
+    /// Fail it with this errno
+    Errno(i32),
+    /// Suspend it and ask the supervisor holding the listener fd (see
+    /// `SandboxPolicy::install_with_listener`); fails with ENOSYS if
+    /// there is none
+    Notify,
+    /// Kill the whole process
+    Kill,
+}
+
+impl SeccompAction {
+    fn ret(self) -> u32 {
+        match self {
+            SeccompAction::Allow => libc::SECCOMP_RET_ALLOW,
+            SeccompAction::Errno(errno) => {
+                libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA)
+            }
+            SeccompAction::Notify => libc::SECCOMP_RET_USER_NOTIF,
+            SeccompAction::Kill => libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// Match on the low 32 bits of one syscall argument:
+/// `(arg & mask) == value`.
+///
+/// Flags and ioctl request numbers are 32-bit, so the high word is
+/// not inspected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgMatch {
+    /// Argument index (0-5)
+    pub arg: u8,
+    /// Bits to compare
+    pub mask: u32,
+    /// Required value of the masked bits
+    pub value: u32,
+}
+
+impl ArgMatch {
+    /// Argument equals `value` exactly.
+    pub const fn equals(arg: u8, value: u32) -> Self {
+        Self {
+            arg,
+            mask: u32::MAX,
+            value,
+        }
+    }
+}
+
+/// A syscall allowed only for some argument values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgFilter {
+    /// Syscall number
+    pub syscall: libc::c_long,
+    /// Allowed if any of these match
+    pub allow_if: Vec<ArgMatch>,
+    /// Action when none match
+    pub otherwise: SeccompAction,
+}
+
+/// A complete seccomp policy for one process type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    /// Syscalls allowed with any arguments
+    pub allowed: Vec<libc::c_long>,
+    /// Syscalls allowed only with matching arguments
+    pub filtered: Vec<ArgFilter>,
+    /// Which clocks may be read directly (None: clock_gettime is left to
+    /// the lists above)
+    pub clock: Option<ClockPolicy>,
+    /// Action for every other syscall
+    pub default_action: SeccompAction,
+}
+
+impl SandboxPolicy {
+    /// Policy for a process type, if it is filtered at all.
+    pub fn for_process(process_type: ProcessType) -> Option<Self> {
+        match process_type {
+            ProcessType::Content => Some(Self::content()),
+            ProcessType::Ui | ProcessType::Broker | ProcessType::Network => None,
+        }
+    }
+
+    /// Content process policy (most restrictive).
+    pub fn content() -> Self {
+        Self {
+            allowed: vec![
+                libc::SYS_read,
+                libc::SYS_write,
+                libc::SYS_close,
+                libc::SYS_mmap,
+                libc::SYS_munmap,
+                libc::SYS_mprotect,
+                libc::SYS_brk,
+                libc::SYS_rt_sigaction,
+                libc::SYS_rt_sigprocmask,
+                libc::SYS_rt_sigreturn,
+                libc::SYS_pipe2,
+                libc::SYS_dup,
+                libc::SYS_dup2,
+                libc::SYS_wait4,
+                libc::SYS_exit,
+                libc::SYS_exit_group,
+                libc::SYS_futex,
+                libc::SYS_set_tid_address,
+                libc::SYS_epoll_create1,
+                libc::SYS_epoll_ctl,
+                libc::SYS_epoll_wait,
+                libc::SYS_recvmsg,
+                libc::SYS_sendmsg,
+                libc::SYS_getrandom,
+            ],
+            filtered: vec![
+                // Threads only: thread flags set, no namespace flags
+                ArgFilter {
+                    syscall: libc::SYS_clone,
+                    allow_if: vec![ArgMatch {
+                        arg: 0,
+                        mask: CLONE_THREAD_FLAGS | CLONE_NAMESPACE_FLAGS,
+                        value: CLONE_THREAD_FLAGS,
+                    }],
+                    otherwise: SeccompAction::Errno(libc::EPERM),
+                },
+                // clone3 takes its flags by pointer, which BPF cannot read.
+                // ENOSYS makes libc fall back to clone.
+                ArgFilter {
+                    syscall: libc::SYS_clone3,
+                    allow_if: Vec::new(),
+                    otherwise: SeccompAction::Errno(libc::ENOSYS),
+                },
+                // Libraries probe ioctls harmlessly; refuse rather than kill
+                ArgFilter {
+                    syscall: libc::SYS_ioctl,
+                    allow_if: BASIC_IOCTLS
+                        .iter()
+                        .map(|request| ArgMatch::equals(1, *request))
+                        .collect(),
+                    otherwise: SeccompAction::Errno(libc::ENOTTY),
+                },
+            ],
+            // Coarse clocks only; fine clocks go to the clock broker
+            clock: Some(ClockPolicy::content()),
+            default_action: SeccompAction::Kill,
+        }
+    }
+
+    /// Compile to a classic BPF program.
+    pub fn compile(&self) -> Vec<libc::sock_filter> {
+        let mut prog = vec![
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, DATA_ARCH),
+            jump(libc::BPF_JEQ, AUDIT_ARCH, 1, 0),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, DATA_NR),
+        ];
+
+        #[cfg(target_arch = "x86_64")]
+        prog.extend([
+            jump(libc::BPF_JGE, X32_SYSCALL_BIT, 0, 1),
+            ret(libc::SECCOMP_RET_KILL_PROCESS),
+        ]);
+
+        for syscall in &self.allowed {
+            prog.push(jump(libc::BPF_JEQ, *syscall as u32, 0, 1));
+            prog.push(ret(libc::SECCOMP_RET_ALLOW));
+        }
+
+        let clock_filter = self.clock.as_ref().map(ClockPolicy::filter);
+        for filter in self.filtered.iter().chain(&clock_filter) {
+            // Each match is 4 instructions, plus the final `otherwise`
+            let block_len = filter.allow_if.len() * 4 + 1;
+            let skip = u8::try_from(block_len).expect("argument filter too long for one jump");
+            prog.push(jump(libc::BPF_JEQ, filter.syscall as u32, 0, skip));
+
+            for matcher in &filter.allow_if {
+                let offset = DATA_ARGS + 8 * u32::from(matcher.arg);
+                prog.extend([
+                    stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset),
+                    stmt(libc::BPF_ALU | libc::BPF_AND | libc::BPF_K, matcher.mask),
+                    jump(libc::BPF_JEQ, matcher.value, 0, 1),
+                    ret(libc::SECCOMP_RET_ALLOW),
+                ]);
+            }
+            prog.push(ret(filter.otherwise.ret()));
+        }
+
+        prog.push(ret(self.default_action.ret()));
+        prog
+    }
+
+    /// Install on the calling thread (inherited by its children).
+    ///
+    /// Sets no_new_privs first, which unprivileged seccomp requires.
+    /// Irreversible.
+    pub fn install(&self) -> io::Result<()> {
+        self.load(0).map(drop)
+    }
+
+    /// Install like `install`, returning the listener that receives
+    /// `SeccompAction::Notify` syscalls.
+    ///
+    /// Hand the fd to the supervisor (the broker) before making any
+    /// notified syscall, or the calling thread blocks until one answers.
+    pub fn install_with_listener(&self) -> io::Result<OwnedFd> {
+        let fd = self.load(libc::SECCOMP_FILTER_FLAG_NEW_LISTENER)?;
+        // The kernel returned a new fd that nothing else owns
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+    }
+
+    fn load(&self, flags: libc::c_ulong) -> io::Result<libc::c_long> {
+        let mut prog = self.compile();
+        let fprog = libc::sock_fprog {
+            len: u16::try_from(prog.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Policy too large"))?,
+            filter: prog.as_mut_ptr(),
+        };
+
+        let result = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                flags,
+                &fprog as *const libc::sock_fprog,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(result)
+    }
+}
+
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(op: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+fn ret(k: u32) -> libc::sock_filter {
+    stmt(libc::BPF_RET | libc::BPF_K, k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the content policy's argument filters; everything else allowed,
+    /// so the forked child can still run the test body.
+    fn argument_filters_only() -> SandboxPolicy {
+        SandboxPolicy {
+            allowed: Vec::new(),
+            filtered: SandboxPolicy::content().filtered,
+            clock: None,
+            default_action: SeccompAction::Allow,
+        }
+    }
+
+    /// Run `body` in a forked child under the argument filters.
+    fn in_filtered_child(body: fn() -> bool) -> bool {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+
+        if pid == 0 {
+            let ok = argument_filters_only().install().is_ok() && body();
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) }
+        }
+
+        let mut status = 0;
+        unsafe { libc::waitpid(pid, &mut status, 0) };
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+    }
+
+    fn errno() -> i32 {
+        io::Error::last_os_error().raw_os_error().unwrap_or(0)
+    }
+
+    #[test]
+    fn test_content_policy_is_structured() {
+        let policy = SandboxPolicy::content();
+        assert!(!policy.allowed.contains(&libc::SYS_clone));
+        assert!(!policy.allowed.contains(&libc::SYS_ioctl));
+        assert!(!policy.allowed.contains(&libc::SYS_clock_gettime));
+        assert_eq!(policy.filtered.len(), 3);
+        assert!(policy.clock.is_some());
+        assert_eq!(policy.default_action, SeccompAction::Kill);
+
+        let prog = policy.compile();
+        assert!(prog.len() < libc::BPF_MAXINSNS as usize);
+        assert_eq!(
+            prog.last().map(|i| i.k),
+            Some(libc::SECCOMP_RET_KILL_PROCESS)
+        );
+    }
+
+    #[test]
+    fn test_namespace_clone_denied() {
+        assert!(in_filtered_child(|| {
+            let flags = (libc::CLONE_NEWUSER | libc::SIGCHLD) as libc::c_ulong;
+            let pid = unsafe { libc::syscall(libc::SYS_clone, flags, 0, 0, 0, 0) };
+            if pid == 0 {
+                unsafe { libc::_exit(0) }
+            }
+            let plain_denied = pid == -1 && errno() == libc::EPERM;
+
+            // The kernel itself rejects this combination with EINVAL, so
+            // EPERM proves the filter answered first
+            let flags = (CLONE_THREAD_FLAGS | libc::CLONE_NEWUSER as u32) as libc::c_ulong;
+            let result = unsafe { libc::syscall(libc::SYS_clone, flags, 0, 0, 0, 0) };
+            let filtered = result == -1 && errno() == libc::EPERM;
+
+            plain_denied && filtered
+        }));
+    }
+
+    #[test]
+    fn test_thread_spawn_allowed() {
+        assert!(in_filtered_child(|| {
+            std::thread::spawn(|| 7).join().ok() == Some(7)
+        }));
+    }
+
+    #[test]
+    fn test_ioctl_allowlist() {
+        assert!(in_filtered_child(|| {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return false;
+            }
+
+            let mut pending: libc::c_int = 0;
+            let fionread = unsafe { libc::ioctl(fds[0], libc::FIONREAD, &mut pending) };
+
+            // FIOASYNC works on any fd, so ENOTTY can only come from the filter
+            let mut on: libc::c_int = 1;
+            let fioasync = unsafe { libc::ioctl(fds[0], libc::FIOASYNC, &mut on) };
+
+            fionread == 0 && fioasync == -1 && errno() == libc::ENOTTY
+        }));
+    }
+}
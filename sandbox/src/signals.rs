@@ -0,0 +1,277 @@
+//! Launcher signal handling: Ctrl+C, kill and a closed terminal.
+//!
+//! SIGINT, SIGTERM and SIGHUP are turned into a coordinated quit rather
+//! than an immediate death. The handler only records the signal; the
+//! launcher notices it through `QuitSignals::pending`, tells the children
+//! to shut down (they sit in their own process group, so the terminal's
+//! signal never reaches them directly), waits at most
+//! `SIGNAL_QUIT_DEADLINE`, then calls `QuitSignals::finish`.
+//!
+//! If the coordinated quit wedges, a second signal wipes the RAM-backed
+//! state from inside the handler and exits at once. The same wipe is
+//! registered with atexit. Both use only paths computed at install time
+//! and raw syscalls (open, getdents64, unlinkat), which are
+//! async-signal-safe. Files are unlinked without being overwritten: they
+//! live on tmpfs, so unlinking releases the pages.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// How long a signal-initiated quit may take before the emergency wipe.
+pub const SIGNAL_QUIT_DEADLINE: Duration = Duration::from_secs(2);
+
+/// Nesting depth the emergency wipe descends into.
+const MAX_WIPE_DEPTH: usize = 8;
+
+/// Signal received and not yet handled (0: none).
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+/// RAM paths to wipe, fixed at install time.
+static WIPE_PATHS: OnceLock<Vec<CString>> = OnceLock::new();
+
+/// A signal that asks forloop to quit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuitSignal {
+    /// SIGINT (Ctrl+C in the launching terminal)
+    Interrupt,
+    /// SIGTERM
+    Terminate,
+    /// SIGHUP (the launching terminal was closed)
+    Hangup,
+}
+
+impl QuitSignal {
+    /// All handled signals.
+    pub const ALL: [Self; 3] = [Self::Interrupt, Self::Terminate, Self::Hangup];
+
+    /// Get the signal number.
+    pub fn signo(&self) -> libc::c_int {
+        match self {
+            QuitSignal::Interrupt => libc::SIGINT,
+            QuitSignal::Terminate => libc::SIGTERM,
+            QuitSignal::Hangup => libc::SIGHUP,
+        }
+    }
+
+    /// Map a signal number back to a quit signal.
+    pub fn from_signo(signo: libc::c_int) -> Option<Self> {
+        Self::ALL.into_iter().find(|signal| signal.signo() == signo)
+    }
+
+    /// Exit code reporting a quit caused by this signal (128 + signo).
+    pub fn exit_code(&self) -> i32 {
+        128 + self.signo()
+    }
+}
+
+/// Installed quit-signal handlers.
+#[derive(Debug)]
+pub struct QuitSignals {
+    _private: (),
+}
+
+impl QuitSignals {
+    /// Install the handlers and the atexit wipe of `wipe_paths`.
+    ///
+    /// Call once, early in the launcher and before any child is spawned;
+    /// `start_ui_process` does. Paths are fixed here; the handler cannot
+    /// allocate to build them.
+    pub fn install(wipe_paths: &[PathBuf]) -> io::Result<Self> {
+        let paths = wipe_paths
+            .iter()
+            .map(|path| CString::new(path.as_os_str().as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        WIPE_PATHS.set(paths).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Quit signal handlers already installed",
+            )
+        })?;
+
+        if unsafe { libc::atexit(wipe_at_exit) } != 0 {
+            return Err(io::Error::other("atexit registration failed"));
+        }
+
+        for signal in QuitSignal::ALL {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_quit_signal as extern "C" fn(libc::c_int) as usize;
+            action.sa_flags = libc::SA_RESTART;
+            unsafe {
+                // No quit signal interrupts the handler of another
+                libc::sigemptyset(&mut action.sa_mask);
+                for blocked in QuitSignal::ALL {
+                    libc::sigaddset(&mut action.sa_mask, blocked.signo());
+                }
+                if libc::sigaction(signal.signo(), &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(Self { _private: () })
+    }
+
+    /// Get the quit signal received so far, if any.
+    pub fn pending(&self) -> Option<QuitSignal> {
+        QuitSignal::from_signo(PENDING.load(Ordering::SeqCst))
+    }
+
+    /// End a signal-initiated quit: wipe the RAM paths and exit with the
+    /// signal's exit code.
+    pub fn finish(self, signal: QuitSignal) -> ! {
+        wipe_ram_paths();
+        std::process::exit(signal.exit_code())
+    }
+}
+
+/// Move the calling process into its own process group.
+///
+/// Run in each child between fork and exec. The terminal sends Ctrl+C and
+/// hangup to its foreground process group only, so children are told to
+/// quit by the broker instead of racing it.
+pub fn isolate_process_group() -> io::Result<()> {
+    if unsafe { libc::setpgid(0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+extern "C" fn on_quit_signal(signo: libc::c_int) {
+    if PENDING.swap(signo, Ordering::SeqCst) != 0 {
+        // Second signal: the coordinated quit is stuck or the user insists
+        wipe_ram_paths();
+        unsafe { libc::_exit(128 + signo) };
+    }
+}
+
+extern "C" fn wipe_at_exit() {
+    wipe_ram_paths();
+}
+
+/// Remove every registered path. Async-signal-safe.
+fn wipe_ram_paths() {
+    let Some(paths) = WIPE_PATHS.get() else {
+        return;
+    };
+
+    for path in paths {
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if fd >= 0 {
+            wipe_dir(fd, 0);
+            unsafe {
+                libc::close(fd);
+                libc::rmdir(path.as_ptr());
+            }
+        } else {
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+    }
+}
+
+/// Unlink everything under the open directory `dirfd`. Async-signal-safe.
+fn wipe_dir(dirfd: libc::c_int, depth: usize) {
+    // Offsets into struct linux_dirent64
+    const RECLEN: usize = 16;
+    const TYPE: usize = 18;
+    const NAME: usize = 19;
+
+    let mut buf = [0u8; 4096];
+
+    // Removing entries shifts the listing; rescan until a pass is clean
+    loop {
+        if unsafe { libc::lseek(dirfd, 0, libc::SEEK_SET) } < 0 {
+            return;
+        }
+        let mut removed = 0;
+
+        loop {
+            let read =
+                unsafe { libc::syscall(libc::SYS_getdents64, dirfd, buf.as_mut_ptr(), buf.len()) };
+            if read <= 0 {
+                break;
+            }
+
+            let mut offset = 0;
+            while offset < read as usize {
+                let reclen = u16::from_ne_bytes([buf[offset + RECLEN], buf[offset + RECLEN + 1]]);
+                let d_type = buf[offset + TYPE];
+                let name = &buf[offset + NAME..];
+                offset += reclen as usize;
+
+                if name.starts_with(b".\0") || name.starts_with(b"..\0") {
+                    continue;
+                }
+                let name = name.as_ptr() as *const libc::c_char;
+
+                if d_type != libc::DT_DIR && unsafe { libc::unlinkat(dirfd, name, 0) } == 0 {
+                    removed += 1;
+                    continue;
+                }
+                if depth < MAX_WIPE_DEPTH {
+                    let child = unsafe {
+                        libc::openat(
+                            dirfd,
+                            name,
+                            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                        )
+                    };
+                    if child >= 0 {
+                        wipe_dir(child, depth + 1);
+                        unsafe { libc::close(child) };
+                    }
+                }
+                if unsafe { libc::unlinkat(dirfd, name, libc::AT_REMOVEDIR) } == 0 {
+                    removed += 1;
+                }
+            }
+        }
+
+        if removed == 0 {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[test]
+    fn test_children_leave_terminal_process_group() {
+        let mut child = unsafe {
+            Command::new("sleep")
+                .arg("5")
+                .pre_exec(isolate_process_group)
+                .spawn()
+                .expect("spawn sleep")
+        };
+        let pid = child.id() as libc::pid_t;
+        let pgid = unsafe { libc::getpgid(pid) };
+        let _ = child.kill();
+        let _ = child.wait();
+
+        assert_eq!(pgid, pid);
+        assert_ne!(pgid, unsafe { libc::getpgrp() });
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(QuitSignal::Interrupt.exit_code(), 130);
+        assert_eq!(QuitSignal::Terminate.exit_code(), 143);
+        assert_eq!(QuitSignal::Hangup.exit_code(), 129);
+        assert_eq!(QuitSignal::from_signo(libc::SIGUSR1), None);
+    }
+}
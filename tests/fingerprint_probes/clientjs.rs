@@ -0,0 +1,127 @@
+//! ClientJS 0.2.
+//!
+//! `getFingerprint()` joins its datapoints with `|` in a fixed order and
+//! hashes the result with MurmurHash3 x86 32-bit (seed 256), printed as a
+//! decimal number.
+
+use forloop_fingerprint::screen::{ScreenBucket, ScreenDefense};
+use forloop_fingerprint::timezone::TimeZone;
+
+use super::{Component, Library, ProbeEnv, USER_AGENTS};
+
+/// ClientJS 0.2.
+pub const LIBRARY: Library = Library {
+    name: "clientjs 0.2",
+    probe: components,
+    hash: fingerprint,
+};
+
+/// Instant the `timeZone` datapoint is read at (2023-11-14T22:13:20Z).
+const PROBE_EPOCH_MS: i64 = 1_700_000_000_000;
+
+/// Collect the datapoints in `getFingerprint()` order.
+fn components(env: &ProbeEnv) -> Vec<Component> {
+    let nav = &env.navigator;
+
+    vec![
+        Component::one_of(
+            "userAgent",
+            nav.user_agent.clone(),
+            USER_AGENTS.iter().copied(),
+        ),
+        Component::one_of(
+            "screenPrint",
+            screen_print(&env.screen),
+            ScreenBucket::BUCKETS
+                .iter()
+                .map(|b| screen_print(&ScreenDefense::new(*b))),
+        ),
+        Component::one_of("plugins", vec![""; nav.plugins_length].join(", "), [""]),
+        Component::one_of(
+            "timeZone",
+            time_zone(env.identity.timezone),
+            TimeZone::ANONYMITY_SET.iter().map(|z| time_zone(*z)),
+        ),
+        Component::one_of("language", nav.language.clone(), ["en-US"]),
+        // Firefox has no navigator.systemLanguage; ClientJS falls back
+        Component::one_of("systemLanguage", nav.language.clone(), ["en-US"]),
+        Component::one_of("cookies", nav.cookie_enabled.to_string(), ["false"]),
+        Component::noise("canvasPrint", canvas_print(env)),
+    ]
+}
+
+fn screen_print(screen: &ScreenDefense) -> String {
+    format!(
+        "Current Resolution: {}x{}, Available Resolution: {}x{}, Color Depth: {}, \
+         Device XDPI: undefined, Device YDPI: undefined",
+        screen.screen_width(),
+        screen.screen_height(),
+        screen.avail_width(),
+        screen.avail_height(),
+        screen.color_depth()
+    )
+}
+
+/// The parenthesized zone name of `String(new Date())`.
+fn time_zone(zone: TimeZone) -> String {
+    let date = zone.format_date_tostring(PROBE_EPOCH_MS);
+    date.split('(')
+        .nth(1)
+        .and_then(|name| name.split(')').next())
+        .unwrap_or("undefined")
+        .to_string()
+}
+
+/// The default 300x150 canvas with the ClientJS text drawn on it.
+///
+/// The data URL is stood in for by a hash of the readback.
+fn canvas_print(env: &ProbeEnv) -> String {
+    format!(
+        "{:08x}",
+        murmurhash3_32_gc(&env.canvas_readback(300, 150, 3), 0)
+    )
+}
+
+fn fingerprint(components: &[Component]) -> String {
+    let key: Vec<&str> = components.iter().map(|c| c.value.as_str()).collect();
+    murmurhash3_32_gc(key.join("|").as_bytes(), 256).to_string()
+}
+
+/// MurmurHash3 x86 32-bit (Gary Court's JS port, used by ClientJS).
+pub fn murmurhash3_32_gc(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+
+    let mut h = seed;
+    let blocks = data.chunks_exact(4);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let k = u32::from_le_bytes(block.try_into().expect("4-byte block"));
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        h = h.rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+
+    let k = tail
+        .iter()
+        .enumerate()
+        .fold(0u32, |k, (i, &byte)| k | u32::from(byte) << (8 * i));
+    if !tail.is_empty() {
+        h ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    h ^= data.len() as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+#[test]
+fn test_murmurhash3_32_reference_vectors() {
+    assert_eq!(murmurhash3_32_gc(b"", 0), 0);
+    assert_eq!(
+        murmurhash3_32_gc(b"The quick brown fox jumps over the lazy dog", 0x9747_b28c),
+        0x2fa8_26cd
+    );
+}
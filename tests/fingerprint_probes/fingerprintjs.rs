@@ -0,0 +1,331 @@
+//! FingerprintJS v4 (open-source edition).
+//!
+//! Components are keyed by name, serialized with JSON.stringify, joined in
+//! key order as `key:value|key:value`, and hashed with MurmurHash3
+//! x64 128-bit (seed 0) into the 32-hex-digit `visitorId`.
+
+use forloop_fingerprint::fonts::{FontDefense, ALLOWED_FONTS};
+use forloop_fingerprint::hardware::HardwareProfile;
+use forloop_fingerprint::screen::{ScreenBucket, ScreenDefense};
+use forloop_fingerprint::timezone::TimeZone;
+
+use super::{json_string, json_string_list, Component, Library, ProbeEnv, PLATFORMS, WEBGL_GPUS};
+
+/// FingerprintJS v4.
+pub const LIBRARY: Library = Library {
+    name: "fingerprintjs 4",
+    probe: components,
+    hash: visitor_id,
+};
+
+/// Fonts the `fonts` source measures against the fallback families.
+const FONT_LIST: &[&str] = &[
+    "sans-serif-thin",
+    "ARNO PRO",
+    "Agency FB",
+    "Arabic Typesetting",
+    "Arial Unicode MS",
+    "AvantGarde Bk BT",
+    "BankGothic Md BT",
+    "Batang",
+    "Bitstream Vera Sans Mono",
+    "Calibri",
+    "Century",
+    "Century Gothic",
+    "Clarendon",
+    "EUROSTILE",
+    "Franklin Gothic",
+    "Futura Bk BT",
+    "Futura Md BT",
+    "GOTHAM",
+    "Gill Sans",
+    "HELV",
+    "Haettenschweiler",
+    "Helvetica Neue",
+    "Humanst521 BT",
+    "Leelawadee",
+    "Letter Gothic",
+    "Levenim MT",
+    "Lucida Bright",
+    "Lucida Sans",
+    "Menlo",
+    "MS Mincho",
+    "MS Outlook",
+    "MS Reference Specialty",
+    "MS UI Gothic",
+    "MT Extra",
+    "MYRIAD PRO",
+    "Marlett",
+    "Meiryo UI",
+    "Microsoft Uighur",
+    "Minion Pro",
+    "Monotype Corsiva",
+    "PMingLiU",
+    "Pristina",
+    "SCRIPTINA",
+    "Segoe UI Light",
+    "Serifa",
+    "SimHei",
+    "Small Fonts",
+    "Staccato222 BT",
+    "TRAJAN PRO",
+    "Univers CE 55 Medium",
+    "Vrinda",
+    "ZWAdobeF",
+];
+
+/// Samples of the offline render the `audio` source sums.
+const AUDIO_WINDOW: std::ops::Range<usize> = 4500..5000;
+
+/// Collect the v4 entropy sources our defenses answer.
+fn components(env: &ProbeEnv) -> Vec<Component> {
+    let nav = &env.navigator;
+    let fonts: Vec<String> = FONT_LIST.iter().map(|f| f.to_string()).collect();
+
+    vec![
+        Component::noise("audio", audio(env)),
+        Component::noise("canvas", canvas(env)),
+        Component::one_of(
+            "colorDepth",
+            env.screen.color_depth().to_string(),
+            ScreenBucket::BUCKETS
+                .iter()
+                .map(|b| b.color_depth.to_string()),
+        ),
+        Component::one_of("cookiesEnabled", nav.cookie_enabled.to_string(), ["false"]),
+        Component::one_of(
+            "deviceMemory",
+            env.hardware.device_memory().to_string(),
+            HardwareProfile::PROFILES
+                .iter()
+                .map(|p| p.device_memory.to_string()),
+        ),
+        Component::one_of(
+            "fonts",
+            json_string_list(&FontDefense::new().filter_fonts(&fonts)),
+            [json_string_list(
+                &FONT_LIST
+                    .iter()
+                    .filter(|f| ALLOWED_FONTS.contains(f))
+                    .collect::<Vec<_>>(),
+            )],
+        ),
+        Component::one_of(
+            "hardwareConcurrency",
+            env.hardware.hardware_concurrency().to_string(),
+            HardwareProfile::PROFILES
+                .iter()
+                .map(|p| p.hardware_concurrency.to_string()),
+        ),
+        Component::one_of(
+            "languages",
+            format!(
+                "[{},{}]",
+                json_string_list(&[&nav.language]),
+                json_string_list(&nav.languages)
+            ),
+            [r#"[["en-US"],["en-US","en"]]"#],
+        ),
+        Component::one_of(
+            "osCpu",
+            json_string(&nav.oscpu),
+            [
+                "Windows NT 10.0; Win64; x64",
+                "Linux x86_64",
+                "Intel Mac OS X 10.15",
+            ]
+            .map(json_string),
+        ),
+        Component::one_of(
+            "platform",
+            json_string(&nav.platform),
+            PLATFORMS.iter().map(|p| json_string(p)),
+        ),
+        Component::one_of(
+            "plugins",
+            json_string_list(&vec![String::new(); nav.plugins_length]),
+            ["[]"],
+        ),
+        Component::one_of(
+            "screenResolution",
+            screen_resolution(&env.screen),
+            ScreenBucket::BUCKETS
+                .iter()
+                .map(|b| screen_resolution(&ScreenDefense::new(*b))),
+        ),
+        Component::one_of(
+            "timezone",
+            json_string(env.identity.timezone.iana_name()),
+            TimeZone::ANONYMITY_SET
+                .iter()
+                .map(|z| json_string(z.iana_name())),
+        ),
+        Component::one_of(
+            "touchSupport",
+            touch_support(env.hardware.max_touch_points()),
+            HardwareProfile::PROFILES
+                .iter()
+                .map(|p| touch_support(p.max_touch_points)),
+        ),
+        Component::one_of("vendor", json_string(&nav.vendor), [json_string("")]),
+        Component::one_of(
+            "webGlBasics",
+            webgl_basics(
+                env.webgl.vendor(),
+                env.webgl.renderer(),
+                env.webgl.unmasked_vendor(),
+                env.webgl.unmasked_renderer(),
+            ),
+            WEBGL_GPUS
+                .iter()
+                .map(|(vendor, renderer)| webgl_basics("WebKit", "WebKit WebGL", vendor, renderer)),
+        ),
+    ]
+}
+
+/// Sum of |sample| over the tail of a 5000-sample render at 44.1 kHz.
+fn audio(env: &ProbeEnv) -> String {
+    let buffer = env
+        .audio
+        .render_offline_fingerprint(AUDIO_WINDOW.end, 44100.0);
+    let sum: f64 = buffer[AUDIO_WINDOW]
+        .iter()
+        .map(|sample| f64::from(sample.abs()))
+        .sum();
+    sum.to_string()
+}
+
+/// Winding test plus the geometry (122x110) and text (240x60) canvases.
+///
+/// The data URLs are stood in for by a hash of the readback.
+fn canvas(env: &ProbeEnv) -> String {
+    let geometry = x64hash128(&env.canvas_readback(122, 110, 1), 0);
+    let text = x64hash128(&env.canvas_readback(240, 60, 2), 0);
+    format!(
+        r#"{{"winding":true,"geometry":{},"text":{}}}"#,
+        json_string(&geometry),
+        json_string(&text)
+    )
+}
+
+/// `[width, height].sort().reverse()`: JS sorts the numbers as strings.
+fn screen_resolution(screen: &ScreenDefense) -> String {
+    let mut dims = [screen.screen_width(), screen.screen_height()];
+    dims.sort_by_key(|d| d.to_string());
+    format!("[{},{}]", dims[1], dims[0])
+}
+
+fn touch_support(max_touch_points: u8) -> String {
+    format!(
+        r#"{{"maxTouchPoints":{},"touchEvent":false,"touchStart":false}}"#,
+        max_touch_points
+    )
+}
+
+fn webgl_basics(
+    vendor: &str,
+    renderer: &str,
+    vendor_unmasked: &str,
+    renderer_unmasked: &str,
+) -> String {
+    format!(
+        r#"{{"vendor":{},"vendorUnmasked":{},"renderer":{},"rendererUnmasked":{}}}"#,
+        json_string(vendor),
+        json_string(vendor_unmasked),
+        json_string(renderer),
+        json_string(renderer_unmasked)
+    )
+}
+
+/// `componentsToCanonicalString` followed by `x64hash128`.
+fn visitor_id(components: &[Component]) -> String {
+    let mut sorted: Vec<&Component> = components.iter().collect();
+    sorted.sort_by_key(|c| c.name);
+
+    let canonical: Vec<String> = sorted
+        .iter()
+        .map(|c| {
+            let key = c
+                .name
+                .replace('\\', "\\\\")
+                .replace(':', "\\:")
+                .replace('|', "\\|");
+            format!("{}:{}", key, c.value)
+        })
+        .collect();
+    x64hash128(canonical.join("|").as_bytes(), 0)
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^ (k >> 33)
+}
+
+/// MurmurHash3 x64 128-bit, as hex (h1 then h2).
+pub fn x64hash128(data: &[u8], seed: u32) -> String {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+
+    let mut h1 = u64::from(seed);
+    let mut h2 = u64::from(seed);
+
+    let blocks = data.chunks_exact(16);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let (lo, hi) = block.split_at(8);
+        let k1 = u64::from_le_bytes(lo.try_into().expect("8-byte half"));
+        let k2 = u64::from_le_bytes(hi.try_into().expect("8-byte half"));
+
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let (mut k1, mut k2) = (0u64, 0u64);
+    for (i, &byte) in tail.iter().enumerate() {
+        if i < 8 {
+            k1 |= u64::from(byte) << (8 * i);
+        } else {
+            k2 |= u64::from(byte) << (8 * (i - 8));
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+    }
+
+    let len = data.len() as u64;
+    h1 ^= len;
+    h2 ^= len;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+
+    format!("{:016x}{:016x}", h1, h2)
+}
+
+#[test]
+fn test_x64hash128_reference_vectors() {
+    assert_eq!(x64hash128(b"", 0), "00000000000000000000000000000000");
+    assert_eq!(
+        x64hash128(b"The quick brown fox jumps over the lazy dog", 0),
+        "e34bbc7bbc071b6c7a433ca9c49a9347"
+    );
+}
@@ -0,0 +1,263 @@
+//! Differential fingerprint probes.
+//!
+//! Each submodule encodes the probe logic of one open-source
+//! fingerprinting library against our defense APIs: the same components,
+//! serialized and hashed the way the library does it. No browser engine is
+//! involved; where a probe would read `canvas.toDataURL()` it reads the
+//! output of `CanvasDefense::apply_noise` instead.
+//!
+//! For every library the harness asserts that
+//! - one identity always yields the same visitor ID,
+//! - different identities yield different visitor IDs,
+//! - every component value lies in its declared anonymity set.
+//!
+//! Anonymity sets are declared here and in the library files, not read
+//! back from the defenses, so a defense that starts reporting a new value
+//! fails until the set is widened on purpose.
+
+use std::collections::HashSet;
+
+use forloop_fingerprint::audio::AudioDefense;
+use forloop_fingerprint::canvas::CanvasDefense;
+use forloop_fingerprint::hardware::HardwareDefense;
+use forloop_fingerprint::navigator::{NavigatorDefense, NavigatorProperties};
+use forloop_fingerprint::screen::ScreenDefense;
+use forloop_fingerprint::webgl::WebGLDefense;
+use forloop_fingerprint::SyntheticIdentity;
+
+mod clientjs;
+mod fingerprintjs;
+
+/// Origin the probes run on.
+pub const PROBE_ORIGIN: &str = "https://probe.example";
+
+/// User agents an identity may report (Tor Browser 13.0, Firefox 115 ESR).
+pub const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:109.0) Gecko/20100101 Firefox/115.0",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/115.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:109.0) Gecko/20100101 Firefox/115.0",
+];
+
+/// navigator.platform values an identity may report.
+pub const PLATFORMS: &[&str] = &["Win32", "Linux x86_64", "MacIntel"];
+
+/// GPUs an identity may report through WEBGL_debug_renderer_info,
+/// as (unmasked vendor, unmasked renderer).
+pub const WEBGL_GPUS: &[(&str, &str)] = &[
+    (
+        "Google Inc. (Intel)",
+        "ANGLE (Intel, Intel(R) UHD Graphics 620 Direct3D11 vs_5_0 ps_5_0)",
+    ),
+    (
+        "Google Inc. (NVIDIA)",
+        "ANGLE (NVIDIA, NVIDIA GeForce GTX 1060 Direct3D11 vs_5_0 ps_5_0)",
+    ),
+    (
+        "Intel Open Source Technology Center",
+        "Mesa DRI Intel(R) UHD Graphics 620 (KBL GT2)",
+    ),
+];
+
+/// Values a component may take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnonymitySet {
+    /// One of a fixed list of values shared by many users
+    OneOf(Vec<String>),
+    /// Derived from the identity's noise budget; differs per identity
+    Noise,
+}
+
+/// One probed value, serialized as the library serializes it.
+#[derive(Debug, Clone)]
+pub struct Component {
+    /// Name the library files the value under
+    pub name: &'static str,
+    /// Serialized value
+    pub value: String,
+    /// Values the component is allowed to take
+    pub set: AnonymitySet,
+}
+
+impl Component {
+    /// A component that must be one of `set`.
+    pub fn one_of<I, S>(name: &'static str, value: String, set: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            name,
+            value,
+            set: AnonymitySet::OneOf(set.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    /// A component carrying the identity's noise.
+    pub fn noise(name: &'static str, value: String) -> Self {
+        Self {
+            name,
+            value,
+            set: AnonymitySet::Noise,
+        }
+    }
+
+    /// Whether the value lies in the declared set.
+    pub fn in_set(&self) -> bool {
+        match &self.set {
+            AnonymitySet::OneOf(values) => values.contains(&self.value),
+            AnonymitySet::Noise => true,
+        }
+    }
+}
+
+/// What one identity exposes to a page of `PROBE_ORIGIN`.
+pub struct ProbeEnv {
+    /// Identity the defenses are built from
+    pub identity: SyntheticIdentity,
+    /// 2D canvas readbacks
+    pub canvas: CanvasDefense,
+    /// WebGL parameters
+    pub webgl: WebGLDefense,
+    /// OfflineAudioContext renders
+    pub audio: AudioDefense,
+    /// navigator.*
+    pub navigator: NavigatorProperties,
+    /// screen.*
+    pub screen: ScreenDefense,
+    /// navigator.hardwareConcurrency and friends
+    pub hardware: HardwareDefense,
+}
+
+impl ProbeEnv {
+    /// Build the defenses a page of `PROBE_ORIGIN` sees for `identity`.
+    pub fn new(identity: &SyntheticIdentity) -> Self {
+        let noise = identity.origin_noise(PROBE_ORIGIN);
+        let navigator = NavigatorDefense::for_identity(identity);
+
+        Self {
+            identity: identity.clone(),
+            canvas: CanvasDefense::for_origin(&noise),
+            webgl: WebGLDefense::for_origin(identity.webgl_seed, &noise),
+            audio: AudioDefense::with_budget(identity.noise_budget),
+            navigator: navigator.get_properties(),
+            screen: ScreenDefense::new(identity.screen_bucket),
+            hardware: HardwareDefense::new(identity.hardware.clone()),
+        }
+    }
+
+    /// RGBA readback of a probe's drawing, as getImageData returns it.
+    ///
+    /// The drawing itself is a fixed pattern per `scene`, standing in for
+    /// what the engine would rasterize; only the noise depends on the
+    /// identity.
+    pub fn canvas_readback(&self, width: u32, height: u32, scene: u8) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..width * height)
+            .flat_map(|i| {
+                let v = (i as u8).wrapping_mul(31).wrapping_add(scene);
+                [v, v.wrapping_add(85), v.wrapping_add(170), 255]
+            })
+            .collect();
+        self.canvas.apply_noise(&mut data, width, height);
+        data
+    }
+}
+
+/// The probe suite of one fingerprinting library.
+pub struct Library {
+    /// Library name and the version whose logic is encoded
+    pub name: &'static str,
+    /// Collect the library's components
+    pub probe: fn(&ProbeEnv) -> Vec<Component>,
+    /// Combine components into the visitor ID the way the library does
+    pub hash: fn(&[Component]) -> String,
+}
+
+impl Library {
+    /// Visitor ID the library computes for `env`.
+    pub fn visitor_id(&self, env: &ProbeEnv) -> String {
+        (self.hash)(&(self.probe)(env))
+    }
+}
+
+/// Every encoded library.
+pub const LIBRARIES: &[Library] = &[fingerprintjs::LIBRARY, clientjs::LIBRARY];
+
+/// Serialize a string as JSON.stringify does.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Serialize a list of strings as JSON.stringify does.
+pub fn json_string_list<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<String> = items.iter().map(|s| json_string(s.as_ref())).collect();
+    format!("[{}]", items.join(","))
+}
+
+/// Identities the harness compares.
+fn identities() -> Vec<SyntheticIdentity> {
+    (0..16u8)
+        .map(|i| SyntheticIdentity::from_seed([i; 32]))
+        .collect()
+}
+
+#[test]
+fn test_visitor_id_stable_within_identity() {
+    for library in LIBRARIES {
+        for identity in identities() {
+            let first = library.visitor_id(&ProbeEnv::new(&identity));
+            let second = library.visitor_id(&ProbeEnv::new(&identity));
+            assert_eq!(
+                first, second,
+                "{} visitor ID changed within one identity",
+                library.name
+            );
+        }
+    }
+}
+
+#[test]
+fn test_visitor_id_diverges_across_identities() {
+    let identities = identities();
+    for library in LIBRARIES {
+        let ids: HashSet<String> = identities
+            .iter()
+            .map(|identity| library.visitor_id(&ProbeEnv::new(identity)))
+            .collect();
+        assert_eq!(
+            ids.len(),
+            identities.len(),
+            "{} linked two identities",
+            library.name
+        );
+    }
+}
+
+#[test]
+fn test_components_stay_in_anonymity_sets() {
+    for library in LIBRARIES {
+        for identity in identities() {
+            for component in (library.probe)(&ProbeEnv::new(&identity)) {
+                assert!(
+                    component.in_set(),
+                    "{} component {} = {} is outside its anonymity set {:?}",
+                    library.name,
+                    component.name,
+                    component.value,
+                    component.set
+                );
+            }
+        }
+    }
+}
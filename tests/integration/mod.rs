@@ -5,3 +5,6 @@
 mod network_tests;
 mod fingerprint_tests;
 mod sandbox_tests;
+
+#[path = "../fingerprint_probes/mod.rs"]
+mod fingerprint_probes;
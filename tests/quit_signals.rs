@@ -0,0 +1,120 @@
+//! Quit signals against a started UI process.
+//!
+//! Each case runs this binary again as the UI process (`CHILD_ROOT_ENV`
+//! set), which starts the way the launcher does and waits to be
+//! signalled. The driver checks the exit code and that the RAM state is
+//! gone. Handlers are process-wide and install once, hence a child per
+//! case and no libtest harness.
+
+#[cfg(target_os = "linux")]
+mod quit {
+    use std::path::Path;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    use forloop_config::StatePaths;
+    use forloop_sandbox::{start_ui_process, QuitSignal, SandboxConfig};
+
+    /// Set in the UI process: the RAM root its state is under.
+    pub const CHILD_ROOT_ENV: &str = "FORLOOP_SIGNAL_TEST_ROOT";
+    /// Set in the UI process: "coordinated" or "wedged".
+    pub const CHILD_MODE_ENV: &str = "FORLOOP_SIGNAL_TEST_MODE";
+    /// Created by the UI process once its handlers are installed.
+    const READY: &str = "ready";
+
+    /// Start as the launcher does, then quit on a signal ("coordinated")
+    /// or never ("wedged").
+    pub fn run_child(root: &Path, mode: &str) -> ! {
+        let paths = StatePaths::with_ram_root(root);
+        let signals =
+            start_ui_process(&SandboxConfig::ui_process(), &paths).expect("UI process starts");
+        std::fs::write(paths.download_dir().join(READY), b"").expect("report ready");
+
+        loop {
+            if let Some(signal) = signals.pending().filter(|_| mode == "coordinated") {
+                signals.finish(signal);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    /// Fill a RAM root with a download and tor keys.
+    fn populate(name: &str) -> StatePaths {
+        let root =
+            std::env::temp_dir().join(format!("forloop-quit-{}-{}", std::process::id(), name));
+        let paths = StatePaths::with_ram_root(root);
+        std::fs::create_dir_all(paths.download_dir()).expect("create download dir");
+        std::fs::create_dir_all(paths.tor_data_dir().join("keys")).expect("create tor dir");
+        std::fs::write(paths.download_dir().join("report.pdf"), b"%PDF-1.7").expect("write file");
+        std::fs::write(paths.tor_data_dir().join("keys/secret_id_key"), b"key")
+            .expect("write file");
+        paths
+    }
+
+    /// Start a UI process on `paths`, deliver `signals` to it, return its
+    /// exit code.
+    fn run_instance(paths: &StatePaths, mode: &str, signals: &[QuitSignal]) -> Option<i32> {
+        let mut child = Command::new(std::env::current_exe().expect("test binary"))
+            .env(CHILD_ROOT_ENV, paths.ram_root())
+            .env(CHILD_MODE_ENV, mode)
+            .spawn()
+            .expect("spawn UI process");
+
+        let ready = paths.download_dir().join(READY);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready.exists() {
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                panic!("UI process never installed its handlers");
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        for signal in signals {
+            unsafe { libc::kill(child.id() as libc::pid_t, signal.signo()) };
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        child.wait().expect("wait for UI process").code()
+    }
+
+    fn assert_wiped(paths: &StatePaths) {
+        assert!(!paths.download_dir().exists(), "downloads left behind");
+        assert!(!paths.tor_data_dir().exists(), "tor state left behind");
+        std::fs::remove_dir(paths.ram_root()).expect("RAM root otherwise empty");
+    }
+
+    pub fn test_signals_run_coordinated_quit() {
+        for signal in QuitSignal::ALL {
+            let paths = populate(&format!("coordinated-{}", signal.signo()));
+            let code = run_instance(&paths, "coordinated", &[signal]);
+
+            assert_eq!(code, Some(signal.exit_code()), "{:?}", signal);
+            assert_wiped(&paths);
+        }
+    }
+
+    pub fn test_second_signal_wipes_from_handler() {
+        let paths = populate("wedged");
+        let code = run_instance(
+            &paths,
+            "wedged",
+            &[QuitSignal::Terminate, QuitSignal::Terminate],
+        );
+
+        assert_eq!(code, Some(QuitSignal::Terminate.exit_code()));
+        assert_wiped(&paths);
+    }
+}
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(root) = std::env::var_os(quit::CHILD_ROOT_ENV) {
+            let mode = std::env::var(quit::CHILD_MODE_ENV).unwrap_or_default();
+            quit::run_child(std::path::Path::new(&root), &mode);
+        }
+        quit::test_signals_run_coordinated_quit();
+        quit::test_second_signal_wipes_from_handler();
+    }
+}
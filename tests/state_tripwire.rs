@@ -0,0 +1,303 @@
+//! "Nothing is stored between sessions", checked against the real stack.
+//!
+//! Every path the components take is pointed into an `FsTripwire`: tor
+//! runs as a fake daemon in the tripwire's Tor data directory, requests
+//! go through a fake SOCKS proxy, downloads land in
+//! `NetworkConfig::download_dir`. After each phase of a session the
+//! tripwire is scanned for writes outside its RAM root, and after quit
+//! the RAM root itself must be empty.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::net::TcpListener;
+
+use forloop_config::clock::ManualClock;
+use forloop_config::tripwire::FsTripwire;
+use forloop_config::{kill_all_state_in, ForloopCli, Port, StatePaths};
+use forloop_network::{
+    AnonymizedNetwork, DownloadBudget, DownloadManager, DownloadState, NetworkConfig, ResponseBody,
+    StreamingResponse, TaskRegistry, TorConfig, TorController, TorProcess, NEWNYM_INTERVAL,
+};
+
+/// One test at a time writes and runs its fake tor, so no test forks
+/// while another still has its script open for writing.
+static FAKE_TOR: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Write a fake tor next to the tripwire's tree and allow writes to it.
+///
+/// It keeps keys and a consensus in its data directory, as tor does, and
+/// reports its control port up.
+fn fake_tor(tripwire: &mut FsTripwire) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin = tripwire.root().join("bin");
+    tripwire.allow(&bin);
+    std::fs::create_dir_all(&bin).expect("create bin dir");
+    let path = bin.join("tor");
+    std::fs::write(
+        &path,
+        r#"#!/bin/sh
+data="$(dirname "$2")"
+mkdir -p "$data/keys"
+echo "secret" > "$data/keys/secret_id_key"
+echo "network-status" > "$data/cached-consensus"
+echo "[notice] Opened Control listener connection (ready) on 127.0.0.1:9151" >&2
+exec sleep 30
+"#,
+    )
+    .expect("write fake tor");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+        .expect("make fake tor executable");
+    path
+}
+
+fn free_port() -> Port {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    Port::new(listener.local_addr().expect("address").port())
+}
+
+/// Serve the control port of a bootstrapped tor on an in-memory pipe.
+///
+/// Every NEWNYM builds one more circuit, which circuit-status lists.
+fn fake_control_port() -> DuplexStream {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let (read, mut write) = tokio::io::split(server);
+        let mut lines = BufReader::new(read).lines();
+        let mut circuits = 0;
+        while let Ok(Some(command)) = lines.next_line().await {
+            let reply = match command.as_str() {
+                "PROTOCOLINFO 1" => {
+                    "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".to_string()
+                }
+                "GETINFO status/bootstrap-phase" => concat!(
+                    "250-status/bootstrap-phase=NOTICE BOOTSTRAP PROGRESS=100 TAG=done ",
+                    "SUMMARY=\"Done\"\r\n250 OK\r\n"
+                )
+                .to_string(),
+                "GETINFO circuit-status" => {
+                    let mut reply = "250+circuit-status=\r\n".to_string();
+                    for id in 1..=circuits {
+                        reply += &format!("{} BUILT $A~a,$B~b,$C~c PURPOSE=GENERAL\r\n", id);
+                    }
+                    reply + ".\r\n250 OK\r\n"
+                }
+                "SIGNAL NEWNYM" => {
+                    circuits += 1;
+                    "250 OK\r\n".to_string()
+                }
+                _ if command == "AUTHENTICATE"
+                    || command.starts_with("SETEVENTS ")
+                    || command.starts_with("CLOSECIRCUIT ") =>
+                {
+                    "250 OK\r\n".to_string()
+                }
+                _ => "510 Unrecognized command\r\n".to_string(),
+            };
+            if write.write_all(reply.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    });
+    client
+}
+
+/// Answer SOCKS5 CONNECTs, then hang up once the ClientHello arrives.
+async fn fake_socks() -> Port {
+    async fn serve(mut stream: tokio::net::TcpStream) -> std::io::Result<()> {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await?;
+        let mut methods = vec![0u8; usize::from(head[1])];
+        stream.read_exact(&mut methods).await?;
+        let method = if methods.contains(&2) { 2 } else { 0 };
+        stream.write_all(&[5, method]).await?;
+        if method == 2 {
+            // Isolation credentials, any accepted
+            stream.read_u8().await?;
+            for _ in 0..2 {
+                let len = stream.read_u8().await?;
+                stream.read_exact(&mut vec![0u8; usize::from(len)]).await?;
+            }
+            stream.write_all(&[1, 0]).await?;
+        }
+        let mut request = [0u8; 5];
+        stream.read_exact(&mut request).await?;
+        stream
+            .read_exact(&mut vec![0u8; usize::from(request[4]) + 2])
+            .await?;
+        stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        let _ = stream.read(&mut [0u8; 1024]).await?;
+        Ok(())
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let port = Port::new(listener.local_addr().expect("address").port());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream));
+        }
+    });
+    port
+}
+
+/// Start tor and the network layer the way startup does, on `paths`.
+async fn start(
+    tor_binary: &Path,
+    paths: &StatePaths,
+    clock: &ManualClock,
+) -> (TorProcess, AnonymizedNetwork) {
+    let socks_port = fake_socks().await;
+    let tor_config = TorConfig {
+        data_dir: paths.tor_data_dir().to_string_lossy().into_owned(),
+        socks_port: free_port(),
+        control_port: free_port(),
+        unix_sockets: false,
+        ..TorConfig::default()
+    };
+    let process = TorProcess::spawn(tor_binary, &tor_config, Duration::from_secs(10))
+        .await
+        .expect("fake tor starts");
+    let controller = TorController::with_control_stream(
+        socks_port,
+        tor_config.control_port,
+        fake_control_port(),
+    )
+    .await
+    .expect("controller")
+    .with_clock(Arc::new(clock.clone()));
+    let network = AnonymizedNetwork::with_tor_controller(
+        NetworkConfig {
+            tor_socks_port: socks_port,
+            tor_control_port: tor_config.control_port,
+            download_dir: paths.download_dir(),
+            max_jitter: Duration::ZERO,
+            circuit_pool: None,
+            ..NetworkConfig::default()
+        },
+        Arc::new(controller),
+    );
+    (process, network)
+}
+
+/// Save a download that arrived in full, as the network layer does.
+async fn save_download(paths: &StatePaths) -> PathBuf {
+    let manager = DownloadManager::new(
+        paths.download_dir(),
+        DownloadBudget::default(),
+        Arc::new(TaskRegistry::new()),
+    );
+    let download = manager
+        .start(
+            "https://example.com/report.pdf",
+            StreamingResponse {
+                status: 200,
+                headers: vec![("content-type".to_string(), "application/pdf".to_string())],
+                circuit_id: "1".to_string(),
+                body: ResponseBody::from_bytes(b"%PDF-1.7".to_vec()),
+            },
+        )
+        .expect("download starts");
+    assert_eq!(
+        download.finished().await.state,
+        DownloadState::Complete(download.path().to_path_buf())
+    );
+    download.path().to_path_buf()
+}
+
+/// The quit path: stop the network layer, kill tor, wipe the RAM state.
+async fn quit(process: TorProcess, network: AnonymizedNetwork, paths: &StatePaths) {
+    network.shutdown().await;
+    process.kill_all_state().await.expect("tor wiped");
+    kill_all_state_in(paths).expect("quit wipe");
+}
+
+#[tokio::test]
+async fn test_session_leaves_nothing() {
+    let _serial = FAKE_TOR.lock().await;
+    let mut tripwire = FsTripwire::new().expect("Failed to create tripwire");
+    let tor = fake_tor(&mut tripwire);
+    let paths = tripwire.state_paths();
+    let clock = ManualClock::new();
+
+    let (process, network) = start(&tor, &paths, &clock).await;
+    assert!(paths.tor_data_dir().join("keys").exists());
+    tripwire.assert_clean();
+
+    // Browsing and downloading through the proxy
+    assert!(network
+        .request("GET", "https://example.com/?utm_source=feed", None)
+        .await
+        .is_err());
+    assert!(network
+        .download("https://example.com/report.pdf")
+        .await
+        .is_err());
+    tripwire.assert_clean();
+
+    // A download that does arrive is saved into the configured directory
+    let download = save_download(&paths).await;
+    assert!(download.starts_with(paths.download_dir()));
+    tripwire.assert_clean();
+
+    clock.advance(NEWNYM_INTERVAL);
+    network.new_identity().await.expect("new identity");
+    tripwire.assert_clean();
+
+    quit(process, network, &paths).await;
+    tripwire.assert_clean();
+    assert!(tripwire.ram_leftovers().expect("Failed to scan").is_empty());
+}
+
+#[tokio::test]
+async fn test_download_then_quit_leaves_nothing() {
+    let _serial = FAKE_TOR.lock().await;
+    let mut tripwire = FsTripwire::new().expect("Failed to create tripwire");
+    let tor = fake_tor(&mut tripwire);
+    let paths = tripwire.state_paths();
+
+    let (process, network) = start(&tor, &paths, &ManualClock::new()).await;
+    let download = save_download(&paths).await;
+    assert_eq!(
+        std::fs::read(&download).expect("download on disk"),
+        b"%PDF-1.7"
+    );
+    tripwire.assert_clean();
+
+    // Quit straight after, without a New Loop in between
+    quit(process, network, &paths).await;
+    assert!(!download.exists());
+    tripwire.assert_clean();
+    assert!(tripwire.ram_leftovers().expect("Failed to scan").is_empty());
+}
+
+#[tokio::test]
+async fn test_new_loop_startup_and_quit_leave_nothing() {
+    let _serial = FAKE_TOR.lock().await;
+    let mut tripwire = FsTripwire::new().expect("Failed to create tripwire");
+    let tor = fake_tor(&mut tripwire);
+    let paths = tripwire.state_paths();
+
+    // A session that crashed left its RAM state behind
+    std::fs::create_dir_all(paths.download_dir()).expect("create download dir");
+    std::fs::write(paths.download_dir().join("old.pdf"), b"%PDF-1.7").expect("stale download");
+
+    let args = vec![
+        "forloop".to_string(),
+        "--new-loop".to_string(),
+        "https://example.onion".to_string(),
+    ];
+    let cli = ForloopCli::parse_args(&args);
+    assert!(cli.new_loop);
+    kill_all_state_in(&paths).expect("New Loop wipe");
+    assert!(tripwire.ram_leftovers().expect("Failed to scan").is_empty());
+
+    let (process, network) = start(&tor, &paths, &ManualClock::new()).await;
+    tripwire.assert_clean();
+
+    quit(process, network, &paths).await;
+    tripwire.assert_clean();
+    assert!(tripwire.ram_leftovers().expect("Failed to scan").is_empty());
+}